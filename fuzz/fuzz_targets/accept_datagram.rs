@@ -0,0 +1,17 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use tuic_fuzz::RUNTIME;
+use tuic_quinn::{side, Connection, Task};
+
+// the input is a sequence of datagrams received by the server, see `tuic_fuzz::chunks()`
+fuzz_target!(|data: &[u8]| {
+    let conn = Connection::<side::Server>::new(tuic_fuzz::connection());
+
+    for dg in tuic_fuzz::chunks(data) {
+        if let Ok(Task::Packet(pkt)) = conn.accept_datagram(Bytes::copy_from_slice(dg)) {
+            let _ = RUNTIME.block_on(pkt.accept());
+        }
+    }
+});
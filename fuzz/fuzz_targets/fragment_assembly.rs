@@ -0,0 +1,53 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use std::{net::SocketAddr, time::Duration};
+use tuic::{model::Connection, Address, Dissociate, Packet};
+
+// Each 6 bytes of the input is an operation on the receiving side:
+//
+// - `[0]` - association ID, in 0..4
+// - `[1]` - packet ID, in 0..8
+// - `[2]` - total number of fragments
+// - `[3]` - fragment ID
+// - `[4]` - fragment size
+// - `[5]` - flags. `0x01` - with the address, `0x02` - dissociate the association instead, `0x04` - drop all pending fragments before the operation
+//
+// The narrow ID ranges make fragments of different packets and associations interleave.
+fuzz_target!(|data: &[u8]| {
+    let conn = Connection::<Bytes>::new();
+
+    for op in data.chunks_exact(6) {
+        let assoc_id = u16::from(op[0] % 4);
+        let pkt_id = u16::from(op[1] % 8);
+        let (frag_total, frag_id, size, flags) = (op[2], op[3], op[4], op[5]);
+
+        if flags & 0x04 != 0 {
+            conn.collect_garbage(Duration::ZERO);
+        }
+
+        if flags & 0x02 != 0 {
+            conn.recv_dissociate(Dissociate::new(assoc_id));
+            continue;
+        }
+
+        let addr = if flags & 0x01 != 0 {
+            Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 53)))
+        } else {
+            Address::None
+        };
+
+        let header = Packet::new(assoc_id, pkt_id, frag_total, frag_id, u16::from(size), addr);
+        let pkt = conn.recv_packet_unrestricted(header);
+        let frag = Bytes::from(vec![frag_id; usize::from(size)]);
+
+        if let Ok(Some(pkt)) = pkt.assemble(frag) {
+            let mut buf = Vec::new();
+            let (addr, id) = pkt.assemble(&mut buf);
+
+            assert_eq!(id, assoc_id);
+            assert!(!addr.is_none());
+        }
+    }
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use futures_util::FutureExt;
+use libfuzzer_sys::fuzz_target;
+use tuic::Header;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = data;
+    let res = Header::unmarshal(&mut input);
+
+    let mut async_input = data;
+    let async_res = Header::async_unmarshal(&mut async_input)
+        .now_or_never()
+        .expect("reading from a slice never pends");
+
+    let (header, async_header) = match (res, async_res) {
+        (Ok(header), Ok(async_header)) => (header, async_header),
+        (Err(_), Err(_)) => return,
+        (res, async_res) => panic!("sync and async unmarshalling disagree: {res:?}, {async_res:?}"),
+    };
+
+    // the encoding is canonical, so marshalling gives back exactly the consumed bytes
+    let consumed = &data[..data.len() - input.len()];
+    assert_eq!(header.len(), consumed.len());
+
+    let mut buf = Vec::new();
+    header.marshal(&mut buf).unwrap();
+    assert_eq!(buf, consumed);
+
+    let mut async_buf = Vec::new();
+    async_header.marshal(&mut async_buf).unwrap();
+    assert_eq!(async_buf, consumed);
+});
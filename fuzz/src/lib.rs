@@ -0,0 +1,62 @@
+//! Shared setup of the fuzzing targets
+
+use once_cell::sync::Lazy;
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use rustls::{Certificate, PrivateKey, RootCertStore};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::runtime::{Builder, Runtime};
+
+pub static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+/// The endpoints and the two sides of a QUIC connection over loopback, kept alive for the whole fuzzing process
+static PEERS: Lazy<(Endpoint, Endpoint, Connection, Connection)> =
+    Lazy::new(|| RUNTIME.block_on(connect()));
+
+/// Returns the server side of the QUIC connection, for the TUIC connection models
+pub fn connection() -> Connection {
+    PEERS.3.clone()
+}
+
+async fn connect() -> (Endpoint, Endpoint, Connection, Connection) {
+    let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let cert_der = Certificate(cert.serialize_der().unwrap());
+    let key_der = PrivateKey(cert.serialize_private_key_der());
+
+    let server_cfg = ServerConfig::with_single_cert(vec![cert_der.clone()], key_der).unwrap();
+    let server = Endpoint::server(server_cfg, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(&cert_der).unwrap();
+
+    let mut client = Endpoint::client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    client.set_default_client_config(ClientConfig::with_root_certificates(roots));
+
+    let connecting = client
+        .connect(server.local_addr().unwrap(), "localhost")
+        .unwrap();
+
+    let (client_conn, server_conn) =
+        tokio::join!(connecting, async { server.accept().await.unwrap().await });
+
+    (client, server, client_conn.unwrap(), server_conn.unwrap())
+}
+
+/// Splits the input into length-prefixed chunks, each with a big-endian `u16` length
+pub fn chunks(mut data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        if data.len() < 2 {
+            return None;
+        }
+
+        let len = (u16::from_be_bytes([data[0], data[1]]) as usize).min(data.len() - 2);
+        let (chunk, rest) = data[2..].split_at(len);
+        data = rest;
+        Some(chunk)
+    })
+}
@@ -0,0 +1,239 @@
+//! The benchmarks, each measuring one path through the relay
+
+use crate::{
+    socks5::{self, UdpAssociation},
+    target::{MODE_DOWNLOAD, MODE_ECHO, MODE_UPLOAD},
+};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{self, Error, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr},
+    time::{Duration, Instant},
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const UDP_WINDOW: usize = 64;
+const UDP_TIMEOUT: Duration = Duration::from_millis(200);
+
+pub enum Outcome {
+    Throughput {
+        bytes: u64,
+        elapsed: Duration,
+        lost: u64,
+    },
+    Latency(Vec<Duration>),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Throughput {
+                bytes,
+                elapsed,
+                lost,
+            } => {
+                let mbps = *bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0;
+                write!(f, "{mbps:>10.2} Mbit/s  ({bytes} bytes in {elapsed:.2?}")?;
+
+                if *lost > 0 {
+                    write!(f, ", {lost} packets lost")?;
+                }
+
+                write!(f, ")")
+            }
+            Self::Latency(samples) => {
+                if samples.is_empty() {
+                    return write!(f, "no samples");
+                }
+
+                let mut sorted = samples.clone();
+                sorted.sort_unstable();
+
+                let avg = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+                let pct = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+
+                write!(
+                    f,
+                    "avg {avg:>10.2?}  p50 {:>10.2?}  p99 {:>10.2?}  ({} samples)",
+                    pct(50),
+                    pct(99),
+                    sorted.len(),
+                )
+            }
+        }
+    }
+}
+
+/// Sends `size` bytes to the target through the proxy
+pub fn tcp_upload(proxy: SocketAddr, target: SocketAddr, size: u64) -> io::Result<Outcome> {
+    let mut stream = socks5::connect(proxy, target)?;
+    let buf = vec![0; CHUNK_SIZE];
+
+    let start = Instant::now();
+    stream.write_all(&[MODE_UPLOAD])?;
+
+    let mut remaining = size;
+
+    while remaining > 0 {
+        let n = remaining.min(buf.len() as u64) as usize;
+        stream.write_all(&buf[..n])?;
+        remaining -= n as u64;
+    }
+
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut received = [0; 8];
+    stream.read_exact(&mut received)?;
+    let elapsed = start.elapsed();
+
+    let received = u64::from_be_bytes(received);
+
+    if received != size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("sent {size} bytes, but the target received {received}"),
+        ));
+    }
+
+    Ok(Outcome::Throughput {
+        bytes: size,
+        elapsed,
+        lost: 0,
+    })
+}
+
+/// Receives `size` bytes from the target through the proxy
+pub fn tcp_download(proxy: SocketAddr, target: SocketAddr, size: u64) -> io::Result<Outcome> {
+    let mut stream = socks5::connect(proxy, target)?;
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    let start = Instant::now();
+    stream.write_all(&[MODE_DOWNLOAD])?;
+    stream.write_all(&size.to_be_bytes())?;
+
+    let mut received = 0;
+
+    while received < size {
+        match stream.read(&mut buf)? {
+            0 => break,
+            n => received += n as u64,
+        }
+    }
+
+    let elapsed = start.elapsed();
+
+    if received != size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("expected {size} bytes, but received {received}"),
+        ));
+    }
+
+    Ok(Outcome::Throughput {
+        bytes: size,
+        elapsed,
+        lost: 0,
+    })
+}
+
+/// Measures the round-trip time of `size`-byte messages echoed by the target through the proxy
+pub fn tcp_latency(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    size: usize,
+    count: usize,
+) -> io::Result<Outcome> {
+    let mut stream = socks5::connect(proxy, target)?;
+    stream.write_all(&[MODE_ECHO])?;
+
+    let msg = vec![0; size];
+    let mut buf = vec![0; size];
+    let mut samples = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let start = Instant::now();
+        stream.write_all(&msg)?;
+        stream.read_exact(&mut buf)?;
+        samples.push(start.elapsed());
+    }
+
+    Ok(Outcome::Latency(samples))
+}
+
+/// Sends `count` packets of `size` bytes to the UDP echo target through the proxy, keeping at most a window of them in flight
+///
+/// Packets not echoed back in time are counted as lost, and only the echoed bytes are counted into the throughput.
+pub fn udp_throughput(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    size: usize,
+    count: usize,
+) -> io::Result<Outcome> {
+    let mut assoc = UdpAssociation::new(proxy)?;
+    assoc.set_read_timeout(Some(UDP_TIMEOUT))?;
+
+    let msg = vec![0; size];
+    let mut sent = 0;
+    let mut in_flight = 0;
+    let mut bytes = 0;
+    let mut lost = 0;
+
+    let start = Instant::now();
+
+    while sent < count || in_flight > 0 {
+        while sent < count && in_flight < UDP_WINDOW {
+            assoc.send_to(&msg, target)?;
+            sent += 1;
+            in_flight += 1;
+        }
+
+        match assoc.recv() {
+            Ok(pkt) => {
+                bytes += pkt.len() as u64;
+                in_flight -= 1;
+            }
+            Err(err) if is_timeout(&err) => {
+                lost += in_flight as u64;
+                in_flight = 0;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(Outcome::Throughput {
+        bytes,
+        elapsed: start.elapsed(),
+        lost,
+    })
+}
+
+/// Measures the round-trip time of `size`-byte packets echoed by the UDP target through the proxy
+pub fn udp_latency(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    size: usize,
+    count: usize,
+) -> io::Result<Outcome> {
+    let mut assoc = UdpAssociation::new(proxy)?;
+    assoc.set_read_timeout(Some(UDP_TIMEOUT))?;
+
+    let msg = vec![0; size];
+    let mut samples = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let start = Instant::now();
+        assoc.send_to(&msg, target)?;
+
+        match assoc.recv() {
+            Ok(_) => samples.push(start.elapsed()),
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(Outcome::Latency(samples))
+}
+
+fn is_timeout(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
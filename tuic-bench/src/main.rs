@@ -0,0 +1,167 @@
+use crate::{bench::Outcome, rig::Rig};
+use lexopt::{Arg, Error as ArgumentError, Parser, ValueExt};
+use std::{env, io, net::SocketAddr, path::PathBuf, process};
+
+mod bench;
+mod rig;
+mod socks5;
+mod target;
+
+const HELP_MSG: &str = r#"
+Usage tuic-bench [arguments]
+
+Starts tuic-server and tuic-client on loopback, then measures the throughput and latency of relaying TCP, UDP in the `native` mode and UDP in the `quic` mode through them
+
+Build the binaries with `--release` for meaningful numbers
+
+Arguments:
+    --server <path>         Path of the tuic-server binary, defaults to the one next to tuic-bench
+    --client <path>         Path of the tuic-client binary, defaults to the one next to tuic-bench
+    -s, --size <bytes>      Bytes transferred in each TCP throughput test, defaults to 268435456
+    -n, --count <count>     Packets or messages sent in each UDP and latency test, defaults to 10000
+    --udp-size <bytes>      Size of the UDP payloads, defaults to 1400, which is fragmented in the `native` mode
+    -t, --test <name>       Run only the tests whose name contains the string, can be specified multiple times
+    --verbose               Print the logs of tuic-server and tuic-client
+    -v, --version           Print the version
+    -h, --help              Print this help message
+"#;
+
+struct Args {
+    server: PathBuf,
+    client: PathBuf,
+    size: u64,
+    count: usize,
+    udp_size: usize,
+    tests: Vec<String>,
+    verbose: bool,
+}
+
+type Test = Box<dyn Fn() -> io::Result<Outcome>>;
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+
+    let (tcp_target, udp_target) = match target::start() {
+        Ok(addrs) => addrs,
+        Err(err) => {
+            eprintln!("failed to start the targets: {err}");
+            process::exit(1);
+        }
+    };
+
+    let rig = match Rig::start(&args.server, &args.client, args.verbose) {
+        Ok(rig) => rig,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+
+    let mut failed = false;
+
+    for (name, test) in tests(&args, &rig, tcp_target, udp_target) {
+        if !args.tests.is_empty() && !args.tests.iter().any(|t| name.contains(t.as_str())) {
+            continue;
+        }
+
+        match test() {
+            Ok(outcome) => println!("{name:<20} {outcome}"),
+            Err(err) => {
+                println!("{name:<20} failed: {err}");
+                failed = true;
+            }
+        }
+    }
+
+    drop(rig);
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn tests(
+    args: &Args,
+    rig: &Rig,
+    tcp_target: SocketAddr,
+    udp_target: SocketAddr,
+) -> Vec<(String, Test)> {
+    let (size, count, udp_size) = (args.size, args.count, args.udp_size);
+    let proxy = rig.socks_native;
+
+    let mut tests: Vec<(String, Test)> = vec![
+        (
+            String::from("tcp-upload"),
+            Box::new(move || bench::tcp_upload(proxy, tcp_target, size)),
+        ),
+        (
+            String::from("tcp-download"),
+            Box::new(move || bench::tcp_download(proxy, tcp_target, size)),
+        ),
+        (
+            String::from("tcp-latency"),
+            Box::new(move || bench::tcp_latency(proxy, tcp_target, 64, count)),
+        ),
+    ];
+
+    for (mode, proxy) in [("native", rig.socks_native), ("quic", rig.socks_quic)] {
+        tests.push((
+            format!("udp-{mode}"),
+            Box::new(move || bench::udp_throughput(proxy, udp_target, udp_size, count)),
+        ));
+        tests.push((
+            format!("udp-{mode}-latency"),
+            Box::new(move || bench::udp_latency(proxy, udp_target, udp_size, count)),
+        ));
+    }
+
+    tests
+}
+
+fn parse_args() -> Result<Args, ArgumentError> {
+    let dir = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(PathBuf::from))
+        .unwrap_or_default();
+
+    let mut args = Args {
+        server: dir.join(format!("tuic-server{}", env::consts::EXE_SUFFIX)),
+        client: dir.join(format!("tuic-client{}", env::consts::EXE_SUFFIX)),
+        size: 256 * 1024 * 1024,
+        count: 10000,
+        udp_size: 1400,
+        tests: Vec::new(),
+        verbose: false,
+    };
+
+    let mut parser = Parser::from_env();
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Arg::Long("server") => args.server = parser.value()?.into(),
+            Arg::Long("client") => args.client = parser.value()?.into(),
+            Arg::Short('s') | Arg::Long("size") => args.size = parser.value()?.parse()?,
+            Arg::Short('n') | Arg::Long("count") => args.count = parser.value()?.parse()?,
+            Arg::Long("udp-size") => args.udp_size = parser.value()?.parse()?,
+            Arg::Short('t') | Arg::Long("test") => args.tests.push(parser.value()?.string()?),
+            Arg::Long("verbose") => args.verbose = true,
+            Arg::Short('v') | Arg::Long("version") => {
+                println!("{}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            Arg::Short('h') | Arg::Long("help") => {
+                println!("{HELP_MSG}");
+                process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    Ok(args)
+}
@@ -0,0 +1,153 @@
+//! Running tuic-server and tuic-client on loopback
+
+use serde_json::json;
+use std::{
+    env, fs,
+    io::{self, Error, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const UUID: &str = "00000000-0000-0000-0000-000000000000";
+const PASSWORD: &str = "tuic-bench";
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The server and the clients, killed on drop
+pub struct Rig {
+    dir: PathBuf,
+    children: Vec<Child>,
+    /// The SOCKS5 server of the client in UDP relay mode `native`
+    pub socks_native: SocketAddr,
+    /// The SOCKS5 server of the client in UDP relay mode `quic`
+    pub socks_quic: SocketAddr,
+}
+
+impl Rig {
+    pub fn start(server_bin: &Path, client_bin: &Path, verbose: bool) -> io::Result<Self> {
+        let dir = env::temp_dir().join(format!("tuic-bench-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+
+        let mut rig = Self {
+            dir,
+            children: Vec::new(),
+            socks_native: free_tcp_port()?,
+            socks_quic: free_tcp_port()?,
+        };
+
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")])
+            .map_err(|err| Error::new(ErrorKind::Other, err))?;
+
+        let cert_path = rig.dir.join("cert.pem");
+        let key_path = rig.dir.join("key.pem");
+
+        fs::write(
+            &cert_path,
+            cert.serialize_pem()
+                .map_err(|err| Error::new(ErrorKind::Other, err))?,
+        )?;
+        fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+        let server_addr = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+
+        let server_cfg = json!({
+            "server": server_addr,
+            "users": { UUID: PASSWORD },
+            "certificate": cert_path,
+            "private_key": key_path,
+            "log_level": if verbose { "info" } else { "warn" },
+        });
+
+        rig.spawn(server_bin, "server", &server_cfg, verbose)?;
+
+        for (mode, socks) in [("native", rig.socks_native), ("quic", rig.socks_quic)] {
+            let client_cfg = json!({
+                "relay": {
+                    "server": format!("localhost:{}", server_addr.port()),
+                    "ip": server_addr.ip(),
+                    "uuid": UUID,
+                    "password": PASSWORD,
+                    "certificates": [cert_path],
+                    "disable_native_certs": true,
+                    "udp_relay_mode": mode,
+                },
+                "local": { "server": socks },
+                "log_level": if verbose { "info" } else { "warn" },
+            });
+
+            rig.spawn(client_bin, &format!("client-{mode}"), &client_cfg, verbose)?;
+        }
+
+        for socks in [rig.socks_native, rig.socks_quic] {
+            wait_ready(socks)?;
+        }
+
+        Ok(rig)
+    }
+
+    fn spawn(
+        &mut self,
+        bin: &Path,
+        name: &str,
+        cfg: &serde_json::Value,
+        verbose: bool,
+    ) -> io::Result<()> {
+        let cfg_path = self.dir.join(format!("{name}.json"));
+        fs::write(&cfg_path, cfg.to_string())?;
+
+        let child = Command::new(bin)
+            .arg("-c")
+            .arg(&cfg_path)
+            .stdout(Stdio::null())
+            .stderr(if verbose {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            })
+            .spawn()
+            .map_err(|err| {
+                Error::new(
+                    err.kind(),
+                    format!("failed to start {}: {err}", bin.display()),
+                )
+            })?;
+
+        self.children.push(child);
+        Ok(())
+    }
+}
+
+impl Drop for Rig {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_tcp_port() -> io::Result<SocketAddr> {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()
+}
+
+/// Waits for the SOCKS5 server of the client to listen
+fn wait_ready(addr: SocketAddr) -> io::Result<()> {
+    let start = Instant::now();
+
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(_) => return Ok(()),
+            Err(_) if start.elapsed() < READY_TIMEOUT => thread::sleep(Duration::from_millis(50)),
+            Err(err) => {
+                return Err(Error::new(
+                    err.kind(),
+                    format!("tuic-client is not listening on {addr}: {err}"),
+                ))
+            }
+        }
+    }
+}
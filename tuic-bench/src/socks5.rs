@@ -0,0 +1,141 @@
+//! A minimal SOCKS5 client without authentication, for reaching the destinations through tuic-client
+
+use std::{
+    io::{self, Error, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket},
+    time::Duration,
+};
+
+const VERSION: u8 = 0x05;
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Establishes a TCP connection to the target through the proxy
+pub fn connect(proxy: SocketAddr, target: SocketAddr) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy)?;
+    stream.set_nodelay(true)?;
+    request(&mut stream, CMD_CONNECT, target)?;
+    Ok(stream)
+}
+
+/// A UDP association through the proxy, kept as long as the control connection is open
+pub struct UdpAssociation {
+    _ctrl: TcpStream,
+    socket: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl UdpAssociation {
+    pub fn new(proxy: SocketAddr) -> io::Result<Self> {
+        let mut ctrl = TcpStream::connect(proxy)?;
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+
+        let relay = request(&mut ctrl, CMD_UDP_ASSOCIATE, socket.local_addr()?)?;
+
+        // the relay may be bound to the unspecified address
+        let relay = if relay.ip().is_unspecified() {
+            SocketAddr::from((proxy.ip(), relay.port()))
+        } else {
+            relay
+        };
+
+        socket.connect(relay)?;
+
+        Ok(Self {
+            _ctrl: ctrl,
+            socket,
+            buf: vec![0; 64 * 1024],
+        })
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    pub fn send_to(&mut self, data: &[u8], target: SocketAddr) -> io::Result<()> {
+        let mut pkt = vec![0, 0, 0];
+        write_addr(&mut pkt, target);
+        pkt.extend_from_slice(data);
+        self.socket.send(&pkt)?;
+        Ok(())
+    }
+
+    /// Receives a packet, returning its payload
+    pub fn recv(&mut self) -> io::Result<&[u8]> {
+        let n = self.socket.recv(&mut self.buf)?;
+
+        let header_len = match self.buf.get(3) {
+            Some(&ATYP_IPV4) => 3 + 1 + 4 + 2,
+            Some(&ATYP_IPV6) => 3 + 1 + 16 + 2,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "invalid UDP packet")),
+        };
+
+        self.buf
+            .get(header_len..n)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "truncated UDP packet"))
+    }
+}
+
+fn request(stream: &mut TcpStream, cmd: u8, addr: SocketAddr) -> io::Result<SocketAddr> {
+    stream.write_all(&[VERSION, 1, 0x00])?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+
+    if reply != [VERSION, 0x00] {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "the proxy requires authentication",
+        ));
+    }
+
+    let mut req = vec![VERSION, cmd, 0x00];
+    write_addr(&mut req, addr);
+    stream.write_all(&req)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+
+    if reply[1] != 0x00 {
+        return Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("the proxy replied with error {:#04x}", reply[1]),
+        ));
+    }
+
+    let ip = match reply[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0; 4];
+            stream.read_exact(&mut ip)?;
+            IpAddr::from(ip)
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0; 16];
+            stream.read_exact(&mut ip)?;
+            IpAddr::from(ip)
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, "invalid address type")),
+    };
+
+    let mut port = [0; 2];
+    stream.read_exact(&mut port)?;
+
+    Ok(SocketAddr::from((ip, u16::from_be_bytes(port))))
+}
+
+fn write_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
@@ -0,0 +1,90 @@
+//! The local destinations of the relayed traffic
+
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    thread,
+};
+
+pub const MODE_UPLOAD: u8 = b'u';
+pub const MODE_DOWNLOAD: u8 = b'd';
+pub const MODE_ECHO: u8 = b'e';
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Starts the TCP and UDP servers on loopback, returning their addresses
+///
+/// A TCP connection starts with a byte of the mode:
+///
+/// - `MODE_UPLOAD` - reads until EOF, then replies the number of bytes read as a big-endian `u64`
+/// - `MODE_DOWNLOAD` - reads a big-endian `u64`, then sends that many bytes and closes
+/// - `MODE_ECHO` - echoes everything back
+///
+/// UDP packets are echoed back.
+pub fn start() -> io::Result<(SocketAddr, SocketAddr)> {
+    let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let addrs = (tcp.local_addr()?, udp.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in tcp.incoming().flatten() {
+            thread::spawn(move || {
+                let _ = handle_tcp(stream);
+            });
+        }
+    });
+
+    thread::spawn(move || {
+        let mut buf = vec![0; BUF_SIZE];
+
+        while let Ok((n, addr)) = udp.recv_from(&mut buf) {
+            let _ = udp.send_to(&buf[..n], addr);
+        }
+    });
+
+    Ok(addrs)
+}
+
+fn handle_tcp(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+
+    let mut mode = [0; 1];
+    stream.read_exact(&mut mode)?;
+
+    let mut buf = vec![0; BUF_SIZE];
+
+    match mode[0] {
+        MODE_UPLOAD => {
+            let mut total = 0u64;
+
+            loop {
+                match stream.read(&mut buf)? {
+                    0 => break,
+                    n => total += n as u64,
+                }
+            }
+
+            stream.write_all(&total.to_be_bytes())
+        }
+        MODE_DOWNLOAD => {
+            let mut size = [0; 8];
+            stream.read_exact(&mut size)?;
+            let mut remaining = u64::from_be_bytes(size);
+
+            while remaining > 0 {
+                let n = remaining.min(buf.len() as u64) as usize;
+                stream.write_all(&buf[..n])?;
+                remaining -= n as u64;
+            }
+
+            Ok(())
+        }
+        MODE_ECHO => loop {
+            match stream.read(&mut buf)? {
+                0 => break Ok(()),
+                n => stream.write_all(&buf[..n])?,
+            }
+        },
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,47 @@
+//! Running the client in an Android `VpnService`
+//!
+//! The service protects the outbound sockets of the client with `VpnService.protect()` through JNI, and the TUN device it established is read by the client, so apps only pass the service and the file descriptor.
+
+use crate::{error::Error, protect, tun::Tun};
+use jni::{
+    objects::{GlobalRef, JObject, JValue},
+    JNIEnv, JavaVM,
+};
+use std::{os::fd::RawFd, sync::Arc};
+
+/// Sets up the client to run in the `VpnService`, reading IP packets from its TUN device with the MTU
+///
+/// Call it before [`set_config()`](crate::set_config), which creates the sockets of the relay server endpoints. Must be called within a Tokio runtime. The file descriptor is duplicated, so the caller keeps the ownership of it.
+pub fn set_vpn_service(
+    env: &mut JNIEnv,
+    service: &JObject,
+    tun_fd: RawFd,
+    mtu: u16,
+) -> Result<(), Error> {
+    let vm = env.get_java_vm()?;
+    let service = env.new_global_ref(service)?;
+
+    protect::set_protector(Some(Arc::new(move |fd| protect_socket(&vm, &service, fd))));
+
+    Tun::set_fd(Some((tun_fd, mtu)))
+}
+
+/// Calls `VpnService.protect()` with the socket, from any thread
+fn protect_socket(vm: &JavaVM, service: &GlobalRef, fd: RawFd) -> bool {
+    let res = vm.attach_current_thread_as_daemon().and_then(|mut env| {
+        env.call_method(service, "protect", "(I)Z", &[JValue::Int(fd)])?
+            .z()
+    });
+
+    match res {
+        Ok(true) => true,
+        Ok(false) => {
+            log::warn!("[android] failed to protect socket {fd}");
+            false
+        }
+        Err(err) => {
+            log::warn!("[android] failed to protect socket {fd}: {err}");
+            false
+        }
+    }
+}
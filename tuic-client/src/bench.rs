@@ -0,0 +1,383 @@
+//! Measuring the throughput and latency to the relay servers, with `tuic-client bench`
+//!
+//! Except for the echo latency, the servers must have `allow_bench` enabled, serving the TCP relays and UDP packets to `DOMAIN` themselves: a TCP relay starts with a byte of the mode, `MODE_UPLOAD` being replied the number of bytes read until EOF as a big-endian `u64`, `MODE_DOWNLOAD` being sent the number of bytes in the big-endian `u64` following it, and `MODE_ECHO` being echoed back. UDP packets are echoed back.
+//!
+//! Each relay server in the config is measured in turn, through a pool of its own, for:
+//!
+//! - the handshake, as the time to establish the first connection
+//! - TCP latency, as the round-trip time of small messages echoed on a TCP relay
+//! - TCP upload and download throughput
+//! - UDP latency, as the round-trip time of packets echoed one at a time
+//! - UDP throughput, with a window of packets in flight, counting the packets not echoed in time as lost
+//! - echo latency, as the round-trip time of `Echo` commands reflected by the server itself, without relaying to any target
+
+use crate::{
+    config::{Bench as BenchConfig, Config},
+    connection::{self, Connection},
+    error::Error,
+    forward::UDP_SESSIONS,
+};
+use bytes::Bytes;
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    future::Future,
+    io::{Error as IoError, ErrorKind},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc::{self, Receiver},
+    time::{self, Instant},
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address;
+
+pub const DOMAIN: &str = "bench.tuic.invalid";
+
+const MODE_UPLOAD: u8 = b'u';
+const MODE_DOWNLOAD: u8 = b'd';
+const MODE_ECHO: u8 = b'e';
+
+const MESSAGE_SIZE: usize = 64;
+const CHUNK_SIZE: usize = 64 * 1024;
+const UDP_WINDOW: usize = 64;
+const UDP_TIMEOUT: Duration = Duration::from_secs(1);
+const TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+enum Outcome {
+    Duration(Duration),
+    Throughput {
+        bytes: u64,
+        elapsed: Duration,
+        lost: u64,
+    },
+    Latency {
+        samples: Vec<Duration>,
+        lost: usize,
+    },
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Duration(duration) => write!(f, "{duration:>10.2?}"),
+            Self::Throughput {
+                bytes,
+                elapsed,
+                lost,
+            } => {
+                let mbps = *bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0;
+                write!(f, "{mbps:>10.2} Mbit/s  ({bytes} bytes in {elapsed:.2?}")?;
+
+                if *lost > 0 {
+                    write!(f, ", {lost} packets lost")?;
+                }
+
+                write!(f, ")")
+            }
+            Self::Latency { samples, lost } => {
+                if samples.is_empty() {
+                    return write!(f, "no samples");
+                }
+
+                let mut sorted = samples.clone();
+                sorted.sort_unstable();
+
+                let avg = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+                let pct = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+
+                write!(
+                    f,
+                    "avg {avg:>10.2?}  p50 {:>10.2?}  p99 {:>10.2?}  ({} samples",
+                    pct(50),
+                    pct(99),
+                    sorted.len(),
+                )?;
+
+                if *lost > 0 {
+                    write!(f, ", {lost} lost")?;
+                }
+
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Measures the relay servers in the config one by one, printing the results
+pub async fn run(cfg: Config) -> Result<(), Error> {
+    let Some(bench) = cfg.bench else {
+        return Ok(());
+    };
+
+    let relays = cfg
+        .relay
+        .into_iter()
+        .filter(|relay| {
+            bench.server.as_ref().map_or(true, |name| {
+                *name == relay.server.0 || *name == format!("{}:{}", relay.server.0, relay.server.1)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if relays.is_empty() {
+        return Err(Error::Bench("no relay server with the name"));
+    }
+
+    for relay in relays {
+        let server = format!("{}:{}", relay.server.0, relay.server.1);
+        println!("{server}");
+
+        Connection::set_config(
+            vec![relay],
+            cfg.health_check.clone(),
+            cfg.balance,
+            cfg.reconnect.clone(),
+        )?;
+
+        let res = measure(&bench).await;
+        Connection::stop();
+
+        if let Err(err) = res {
+            println!("  failed: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn measure(bench: &BenchConfig) -> Result<(), Error> {
+    let target = Address::DomainAddress(String::from(DOMAIN), 0);
+
+    let start = Instant::now();
+    let conn = Connection::get_for_connect(&target).await?;
+    report("handshake", Ok(Outcome::Duration(start.elapsed())));
+
+    report(
+        "tcp latency",
+        test(tcp_latency(&conn, &target, bench.count)).await,
+    );
+    report(
+        "tcp upload",
+        test(tcp_upload(&conn, &target, bench.size)).await,
+    );
+    report(
+        "tcp download",
+        test(tcp_download(&conn, &target, bench.size)).await,
+    );
+
+    let assoc_id = connection::next_assoc_id();
+    let (tx, mut rx) = mpsc::channel(UDP_WINDOW * 2);
+
+    // the echoed packets come back as to the UDP sessions of local forwards, which have a fixed target too
+    UDP_SESSIONS.lock().insert(assoc_id, tx);
+
+    report(
+        "udp latency",
+        test(udp_latency(&target, assoc_id, &mut rx, bench)).await,
+    );
+    report(
+        "udp throughput",
+        test(udp_throughput(&target, assoc_id, &mut rx, bench)).await,
+    );
+
+    UDP_SESSIONS.lock().remove(&assoc_id);
+
+    if let Ok(Some(conn)) = Connection::get_for_dissociate(assoc_id).await {
+        let _ = conn.dissociate(assoc_id).await;
+    }
+
+    // measured last, as servers not supporting `Echo` close the connection
+    report("echo latency", test(echo_latency(&conn, bench.count)).await);
+
+    Ok(())
+}
+
+fn report(name: &str, res: Result<Outcome, Error>) {
+    match res {
+        Ok(outcome) => println!("  {name:<16}{outcome}"),
+        Err(err) => println!("  {name:<16}failed: {err}"),
+    }
+}
+
+async fn test(test: impl Future<Output = Result<Outcome, Error>>) -> Result<Outcome, Error> {
+    time::timeout(TEST_TIMEOUT, test)
+        .await
+        .map_err(|_| Error::Timeout)?
+}
+
+/// The error of a TCP relay closed by a server not serving the bench, as it fails resolving `DOMAIN`
+fn closed(err: IoError) -> Error {
+    if err.kind() == ErrorKind::UnexpectedEof {
+        Error::Bench("the relay is closed, check that the server has `allow_bench` enabled")
+    } else {
+        Error::Io(err)
+    }
+}
+
+async fn tcp_latency(conn: &Connection, target: &Address, count: usize) -> Result<Outcome, Error> {
+    let mut stream = conn.connect(target.clone(), None).await?.compat();
+    stream.write_u8(MODE_ECHO).await?;
+
+    let msg = [0; MESSAGE_SIZE];
+    let mut buf = [0; MESSAGE_SIZE];
+    let mut samples = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let start = Instant::now();
+        stream.write_all(&msg).await?;
+        stream.read_exact(&mut buf).await.map_err(closed)?;
+        samples.push(start.elapsed());
+    }
+
+    let _ = stream.shutdown().await;
+    Ok(Outcome::Latency { samples, lost: 0 })
+}
+
+async fn tcp_upload(conn: &Connection, target: &Address, size: u64) -> Result<Outcome, Error> {
+    let mut stream = conn.connect(target.clone(), None).await?.compat();
+    let buf = vec![0; CHUNK_SIZE];
+
+    let start = Instant::now();
+    stream.write_u8(MODE_UPLOAD).await?;
+
+    let mut remaining = size;
+
+    while remaining > 0 {
+        let n = remaining.min(buf.len() as u64) as usize;
+        stream.write_all(&buf[..n]).await?;
+        remaining -= n as u64;
+    }
+
+    stream.shutdown().await?;
+
+    let received = stream.read_u64().await.map_err(closed)?;
+    let elapsed = start.elapsed();
+
+    if received != size {
+        return Err(Error::Bench("the server received fewer bytes than sent"));
+    }
+
+    Ok(Outcome::Throughput {
+        bytes: size,
+        elapsed,
+        lost: 0,
+    })
+}
+
+async fn tcp_download(conn: &Connection, target: &Address, size: u64) -> Result<Outcome, Error> {
+    let mut stream = conn.connect(target.clone(), None).await?.compat();
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    let start = Instant::now();
+    stream.write_u8(MODE_DOWNLOAD).await?;
+    stream.write_u64(size).await?;
+
+    let mut received = 0;
+
+    while received < size {
+        match stream.read(&mut buf).await? {
+            0 => break,
+            n => received += n as u64,
+        }
+    }
+
+    let elapsed = start.elapsed();
+
+    if received != size {
+        return Err(closed(IoError::from(ErrorKind::UnexpectedEof)));
+    }
+
+    Ok(Outcome::Throughput {
+        bytes: size,
+        elapsed,
+        lost: 0,
+    })
+}
+
+async fn echo_latency(conn: &Connection, count: usize) -> Result<Outcome, Error> {
+    let mut samples = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let start = Instant::now();
+        conn.echo(vec![0; MESSAGE_SIZE]).await?;
+        samples.push(start.elapsed());
+    }
+
+    Ok(Outcome::Latency { samples, lost: 0 })
+}
+
+async fn send_packet(target: &Address, assoc_id: u16, pkt: Bytes) -> Result<(), Error> {
+    let conn = Connection::get_for_packet(assoc_id).await?;
+    conn.packet(pkt, target.clone(), assoc_id, None).await
+}
+
+async fn udp_latency(
+    target: &Address,
+    assoc_id: u16,
+    rx: &mut Receiver<Bytes>,
+    bench: &BenchConfig,
+) -> Result<Outcome, Error> {
+    let msg = Bytes::from(vec![0; bench.udp_size]);
+    let mut samples = Vec::with_capacity(bench.count);
+    let mut lost = 0;
+
+    for _ in 0..bench.count {
+        let start = Instant::now();
+        send_packet(target, assoc_id, msg.clone()).await?;
+
+        match time::timeout(UDP_TIMEOUT, rx.recv()).await {
+            Ok(Some(_)) => samples.push(start.elapsed()),
+            Ok(None) | Err(_) => lost += 1,
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(Error::Bench(
+            "no packet is echoed, check that the server has `allow_bench` enabled",
+        ));
+    }
+
+    Ok(Outcome::Latency { samples, lost })
+}
+
+/// Sends packets keeping at most a window of them in flight, counting only the echoed bytes into the throughput
+async fn udp_throughput(
+    target: &Address,
+    assoc_id: u16,
+    rx: &mut Receiver<Bytes>,
+    bench: &BenchConfig,
+) -> Result<Outcome, Error> {
+    let msg = Bytes::from(vec![0; bench.udp_size]);
+    let mut sent = 0;
+    let mut in_flight = 0;
+    let mut bytes = 0;
+    let mut lost = 0;
+
+    let start = Instant::now();
+
+    while sent < bench.count || in_flight > 0 {
+        while sent < bench.count && in_flight < UDP_WINDOW {
+            send_packet(target, assoc_id, msg.clone()).await?;
+            sent += 1;
+            in_flight += 1;
+        }
+
+        match time::timeout(UDP_TIMEOUT, rx.recv()).await {
+            Ok(Some(pkt)) => {
+                bytes += pkt.len() as u64;
+                in_flight -= 1;
+            }
+            Ok(None) | Err(_) => {
+                lost += in_flight as u64;
+                in_flight = 0;
+            }
+        }
+    }
+
+    Ok(Outcome::Throughput {
+        bytes,
+        elapsed: start.elapsed(),
+        lost,
+    })
+}
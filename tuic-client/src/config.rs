@@ -1,45 +1,149 @@
-use crate::utils::{CongestionControl, UdpRelayMode};
-use humantime::Duration as HumanDuration;
-use lexopt::{Arg, Error as ArgumentError, Parser};
+use crate::{
+    dns::Rule as DnsRule,
+    router::{Outbound, Rule},
+    utils::{
+        Balance, CongestionControl, InboundProtocol, IpCidr, Ipv4Cidr, KeepWarmStrategy,
+        TelemetryFormat, UdpRelayMode, UpstreamProxy, WebSocketBridge,
+    },
+};
+use lexopt::{Arg, Error as ArgumentError, Parser, ValueExt};
 use log::LevelFilter;
+use quinn::VarInt;
 use serde::{de::Error as DeError, Deserialize, Deserializer};
-use serde_json::Error as SerdeError;
+use serde_json::{Error as SerdeError, Value};
 use std::{
+    collections::HashMap,
     env::ArgsOs,
     fmt::Display,
-    fs::File,
+    fs::{self, File},
     io::Error as IoError,
     net::{IpAddr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
     time::Duration,
 };
 use thiserror::Error;
+use tuic::Address;
+use tuic_config::Override;
 use uuid::Uuid;
 
+mod import;
+mod sip003;
+
+pub use tuic_config::ShareLink;
+
+const MIN_DATAGRAM_SIZE: usize = 512;
+
 const HELP_MSG: &str = r#"
-Usage tuic-client [arguments]
+Usage tuic-client [check-config | bench] [arguments]
+
+Commands:
+    check-config            Check the config and exit, with status 0 if it is valid, 1 if it is invalid or 2 on wrong arguments
+    bench                   Measure the throughput and latency of TCP and UDP to each relay server, which must have `allow_bench` enabled, then exit
 
 Arguments:
-    -c, --config <path>     Path to the config file (required)
+    -c, --config <path>     Path to the config file
+    --set <path=value>      Override a field of the config, e.g. `--set relay.bandwidth.up=100mbps`, can be specified multiple times
+    -s, --share-link        Print the share links of the relay servers in the config file
+    -i, --import <path>     Convert a sing-box or v2ray config with TUIC outbounds into a config of this client and print it
     -v, --version           Print the version
     -h, --help              Print this help message
+
+Arguments of bench:
+    --server <name>         Only measure the relay server with the name, as `host` or `host:port`
+    --size <bytes>          Bytes transferred in each TCP throughput test, defaults to 16777216
+    -n, --count <count>     Messages or packets sent in each latency and UDP test, defaults to 200
+    --udp-size <bytes>      Size of the UDP payloads, defaults to 1200
+
+When started as a Shadowsocks SIP003 plugin without arguments, the config is read from the SS_* environment variables
+
+Fields can also be overridden by TUIC_* environment variables, e.g. `TUIC_RELAY__BANDWIDTH__UP=100mbps`, which `--set` takes precedence over
 "#;
 
 #[derive(Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub relay: Relay,
+    #[serde(deserialize_with = "deserialize_relays")]
+    pub relay: Vec<Relay>,
+
+    #[serde(default = "default::health_check")]
+    pub health_check: HealthCheck,
+
+    #[serde(
+        default = "default::balance",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub balance: Balance,
+
+    #[serde(default = "default::reconnect")]
+    pub reconnect: Reconnect,
+
+    #[serde(deserialize_with = "deserialize_locals")]
+    pub local: Vec<Local>,
+
+    #[serde(default)]
+    pub dns: Option<Dns>,
 
-    pub local: Local,
+    #[serde(default)]
+    pub controller: Option<Controller>,
+
+    #[serde(default)]
+    pub healthz: Option<SocketAddr>,
+
+    #[serde(default)]
+    pub telemetry: Option<Telemetry>,
+
+    #[serde(default)]
+    pub system_proxy: Option<SystemProxy>,
+
+    #[serde(default)]
+    pub forward: Vec<Forward>,
+
+    #[serde(default)]
+    pub reverse_forward: Vec<ReverseForward>,
+
+    #[serde(default = "default::router")]
+    pub router: Router,
 
     #[serde(default = "default::log_level")]
     pub log_level: LevelFilter,
+
+    #[serde(skip)]
+    pub sip003: Option<Sip003>,
+
+    /// The path of the config file, for reloading
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+
+    /// The config as read from the file, for finding out the changed sections when reloading
+    #[serde(skip)]
+    pub raw: Value,
+
+    /// The unknown fields in the config, which are ignored
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+
+    /// The fields set by the environment variables and the command line, applied again when reloading
+    #[serde(skip)]
+    pub overrides: Vec<Override>,
+
+    /// Whether the config is only to be checked with `check-config`, instead of running the client
+    #[serde(skip)]
+    pub check: bool,
+
+    /// The arguments of `bench`, if the relay servers are to be measured instead of running the client
+    #[serde(skip)]
+    pub bench: Option<Bench>,
+}
+
+pub struct Bench {
+    pub server: Option<String>,
+    pub size: u64,
+    pub count: usize,
+    pub udp_size: usize,
 }
 
 #[derive(Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct Relay {
     #[serde(deserialize_with = "deserialize_server")]
     pub server: (String, u16),
@@ -51,6 +155,30 @@ pub struct Relay {
 
     pub ip: Option<IpAddr>,
 
+    #[serde(default)]
+    pub sni: Option<String>,
+
+    #[serde(default, deserialize_with = "deserialize_optional_from_str")]
+    pub proxy: Option<UpstreamProxy>,
+
+    #[serde(default, deserialize_with = "deserialize_optional_from_str")]
+    pub bridge: Option<WebSocketBridge>,
+
+    #[serde(default, deserialize_with = "deserialize_optional_password")]
+    pub obfs_password: Option<Arc<[u8]>>,
+
+    #[serde(default = "default::relay::priority")]
+    pub priority: u32,
+
+    #[serde(default = "default::relay::weight")]
+    pub weight: u32,
+
+    #[serde(
+        default = "default::relay::connections",
+        deserialize_with = "deserialize_connections"
+    )]
+    pub connections: usize,
+
     #[serde(default = "default::relay::certificates")]
     pub certificates: Vec<PathBuf>,
 
@@ -66,6 +194,9 @@ pub struct Relay {
     )]
     pub congestion_control: CongestionControl,
 
+    #[serde(default)]
+    pub bandwidth: Option<Bandwidth>,
+
     #[serde(
         default = "default::relay::alpn",
         deserialize_with = "deserialize_alpn"
@@ -80,59 +211,497 @@ pub struct Relay {
 
     #[serde(
         default = "default::relay::timeout",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub timeout: Duration,
 
     #[serde(
         default = "default::relay::heartbeat",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub heartbeat: Duration,
 
     #[serde(default = "default::relay::disable_native_certs")]
     pub disable_native_certs: bool,
 
-    #[serde(default = "default::relay::send_window")]
+    #[serde(default, deserialize_with = "deserialize_fingerprints")]
+    pub certificate_fingerprints: Vec<[u8; 32]>,
+
+    #[serde(default)]
+    pub insecure: bool,
+
+    #[serde(
+        default = "default::relay::send_window",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
     pub send_window: u64,
 
-    #[serde(default = "default::relay::receive_window")]
+    #[serde(
+        default = "default::relay::receive_window",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
     pub receive_window: u32,
 
+    #[serde(default, deserialize_with = "deserialize_connection_receive_window")]
+    pub connection_receive_window: Option<u64>,
+
+    #[serde(
+        default = "default::relay::initial_rtt",
+        deserialize_with = "deserialize_initial_rtt"
+    )]
+    pub initial_rtt: Duration,
+
+    #[serde(
+        default = "default::relay::max_udp_payload_size",
+        deserialize_with = "deserialize_max_udp_payload_size"
+    )]
+    pub max_udp_payload_size: u16,
+
+    #[serde(
+        default = "default::relay::max_concurrent_streams",
+        deserialize_with = "deserialize_max_concurrent_streams"
+    )]
+    pub max_concurrent_streams: u32,
+
+    #[serde(
+        default = "default::relay::datagram_receive_buffer_size",
+        deserialize_with = "deserialize_datagram_receive_buffer_size"
+    )]
+    pub datagram_receive_buffer_size: usize,
+
     #[serde(
         default = "default::relay::gc_interval",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub gc_interval: Duration,
 
     #[serde(
         default = "default::relay::gc_lifetime",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub gc_lifetime: Duration,
+
+    #[serde(default)]
+    pub task_log: usize,
+
+    #[serde(default, deserialize_with = "deserialize_max_datagram_size")]
+    pub max_datagram_size: Option<usize>,
+
+    #[serde(
+        default = "default::relay::happy_eyeballs_delay",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub happy_eyeballs_delay: Duration,
+
+    #[serde(default)]
+    pub udp_stream_fallback: Option<UdpStreamFallback>,
+
+    #[serde(default)]
+    pub udp_native_pacing: Option<UdpNativePacing>,
+
+    #[serde(default)]
+    pub adaptive_fragment_size: Option<AdaptiveFragmentSize>,
+
+    #[serde(default)]
+    pub fec: bool,
+
+    #[serde(default)]
+    pub udp_compression: Option<UdpCompression>,
+
+    #[serde(default)]
+    pub udp_stun: Option<UdpStun>,
+
+    #[serde(default)]
+    pub udp_session_resumption: bool,
+
+    #[serde(default)]
+    pub qlog_dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub rebind_on_network_change: bool,
+
+    #[serde(default)]
+    pub keep_warm: Option<KeepWarm>,
+
+    #[serde(default)]
+    pub on_demand: Option<OnDemand>,
+}
+
+impl Relay {
+    /// Returns the share link of the relay server
+    pub fn share_link(&self) -> ShareLink {
+        let (host, sni) = match self.ip {
+            Some(ip) => (ip.to_string(), Some(self.server.0.clone())),
+            None => (self.server.0.clone(), None),
+        };
+
+        ShareLink {
+            uuid: self.uuid,
+            password: String::from_utf8_lossy(&self.password).into_owned(),
+            host,
+            port: self.server.1,
+            sni,
+            congestion_control: Some(self.congestion_control.to_string()),
+            udp_relay_mode: Some(self.udp_relay_mode.to_string()),
+            alpn: self
+                .alpn
+                .iter()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned())
+                .collect(),
+            disable_sni: self.disable_sni,
+            name: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct UdpStreamFallback {
+    #[serde(default = "default::udp_stream_fallback::loss_threshold")]
+    pub loss_threshold: f64,
+
+    #[serde(default = "default::udp_stream_fallback::recover_threshold")]
+    pub recover_threshold: f64,
+
+    #[serde(
+        default = "default::udp_stream_fallback::hold",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub hold: Duration,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct AdaptiveFragmentSize {
+    #[serde(default = "default::adaptive_fragment_size::loss_threshold")]
+    pub loss_threshold: f64,
+
+    #[serde(default = "default::adaptive_fragment_size::recover_threshold")]
+    pub recover_threshold: f64,
+
+    #[serde(
+        default = "default::adaptive_fragment_size::min_size",
+        deserialize_with = "deserialize_min_fragment_size"
+    )]
+    pub min_size: usize,
+
+    #[serde(
+        default = "default::adaptive_fragment_size::hold",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub hold: Duration,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct UdpCompression {
+    #[serde(default = "default::udp_compression::min_size")]
+    pub min_size: u16,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct UdpNativePacing {
+    #[serde(deserialize_with = "deserialize_pacing_rate")]
+    pub rate: u64,
+
+    #[serde(
+        default = "default::udp_native_pacing::burst",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
+    pub burst: u64,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct Bandwidth {
+    #[serde(deserialize_with = "deserialize_bandwidth")]
+    pub up: u64,
+
+    #[serde(deserialize_with = "deserialize_bandwidth")]
+    pub down: u64,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct UdpStun {
+    #[serde(default = "default::udp_stun::native")]
+    pub native: bool,
+
+    #[serde(
+        default = "default::udp_stun::timeout",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct KeepWarm {
+    #[serde(
+        default = "default::keep_warm::strategy",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub strategy: KeepWarmStrategy,
+
+    #[serde(
+        default = "default::keep_warm::server_idle_timeout",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub server_idle_timeout: Duration,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct OnDemand {
+    #[serde(
+        default = "default::on_demand::idle_timeout",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub idle_timeout: Duration,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct HealthCheck {
+    #[serde(
+        default = "default::health_check::interval",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    #[serde(
+        default,
+        deserialize_with = "tuic_config::deserialize_optional_duration"
+    )]
+    pub max_rtt: Option<Duration>,
+
+    #[serde(default = "default::health_check::echo")]
+    pub echo: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Reconnect {
+    #[serde(
+        default = "default::reconnect::initial_backoff",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub initial_backoff: Duration,
+
+    #[serde(
+        default = "default::reconnect::max_backoff",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub max_backoff: Duration,
+
+    #[serde(default = "default::reconnect::max_pending")]
+    pub max_pending: usize,
 }
 
 #[derive(Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct Local {
     pub server: SocketAddr,
 
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(
+        default = "default::local::protocol",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub protocol: InboundProtocol,
+
     #[serde(deserialize_with = "deserialize_optional_bytes", default)]
     pub username: Option<Vec<u8>>,
 
     #[serde(deserialize_with = "deserialize_optional_bytes", default)]
     pub password: Option<Vec<u8>>,
 
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+
+    #[serde(default, deserialize_with = "deserialize_ip_cidrs")]
+    pub allowed_ips: Vec<IpCidr>,
+
     pub dual_stack: Option<bool>,
 
     #[serde(default = "default::local::max_packet_size")]
     pub max_packet_size: usize,
+
+    #[serde(default)]
+    pub nodelay: bool,
+
+    #[serde(default)]
+    pub fast_open: bool,
+
+    #[serde(default)]
+    pub backlog: Option<u32>,
+}
+
+/// The TCP tunnel replacing the SOCKS5 server when running as a SIP003 plugin
+pub struct Sip003 {
+    pub local: SocketAddr,
+    pub target: Address,
+}
+
+#[derive(Deserialize)]
+pub struct Dns {
+    pub server: SocketAddr,
+
+    pub upstream: SocketAddr,
+
+    #[serde(
+        default = "default::dns::timeout",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub timeout: Duration,
+
+    #[serde(default)]
+    pub fake_ip: Option<FakeIp>,
+
+    #[serde(default)]
+    pub hosts: Option<PathBuf>,
+
+    #[serde(default, deserialize_with = "deserialize_rules")]
+    pub rules: Vec<DnsRule>,
+
+    #[serde(default = "default::dns::ttl")]
+    pub ttl: u32,
+}
+
+#[derive(Deserialize)]
+pub struct FakeIp {
+    #[serde(
+        default = "default::dns::fake_ip_range",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub range: Ipv4Cidr,
+
+    #[serde(default = "default::dns::fake_ip_ttl")]
+    pub ttl: u32,
+}
+
+#[derive(Deserialize)]
+pub struct Controller {
+    pub server: SocketAddr,
+
+    pub secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Telemetry {
+    #[serde(
+        default = "default::telemetry::interval",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    #[serde(default)]
+    pub log: Option<PathBuf>,
+
+    #[serde(
+        default = "default::telemetry::format",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub format: TelemetryFormat,
+}
+
+#[derive(Deserialize)]
+pub struct SystemProxy {
+    #[serde(default = "default::system_proxy::set")]
+    pub set: bool,
+
+    #[serde(default)]
+    pub pac_server: Option<SocketAddr>,
+
+    #[serde(default = "default::system_proxy::bypass")]
+    pub bypass: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Forward {
+    pub listen: SocketAddr,
+
+    #[serde(deserialize_with = "deserialize_address")]
+    pub target: Address,
+
+    #[serde(default = "default::forward::tcp")]
+    pub tcp: bool,
+
+    #[serde(default = "default::forward::udp")]
+    pub udp: bool,
+
+    #[serde(
+        default = "default::forward::udp_timeout",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub udp_timeout: Duration,
+}
+
+#[derive(Deserialize)]
+pub struct ReverseForward {
+    pub listen: SocketAddr,
+
+    #[serde(deserialize_with = "deserialize_address")]
+    pub target: Address,
+}
+
+#[derive(Deserialize)]
+pub struct Router {
+    pub geosite: Option<PathBuf>,
+
+    #[serde(default = "default::router::domain_lists")]
+    pub domain_lists: HashMap<String, PathBuf>,
+
+    #[serde(
+        default = "default::router::rules",
+        deserialize_with = "deserialize_rules"
+    )]
+    pub rules: Vec<Rule>,
+
+    #[serde(
+        default = "default::router::default_outbound",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub default_outbound: Outbound,
+
+    #[serde(
+        default,
+        deserialize_with = "tuic_config::deserialize_optional_duration"
+    )]
+    pub reload_interval: Option<Duration>,
+
+    #[serde(default)]
+    pub sniff: bool,
+
+    #[serde(default = "default::router::cache_size")]
+    pub cache_size: usize,
+
+    #[serde(
+        default = "default::router::cache_ttl",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub cache_ttl: Duration,
+
+    #[serde(default)]
+    pub bypass: Option<Bypass>,
+}
+
+#[derive(Deserialize)]
+pub struct Bypass {
+    #[serde(default = "default::router::bypass_private")]
+    pub private: bool,
+
+    #[serde(default = "default::router::bypass_server")]
+    pub server: bool,
+
+    #[serde(default = "default::router::bypass_local")]
+    pub local: bool,
+
+    #[serde(default, deserialize_with = "deserialize_ip_cidrs")]
+    pub cidrs: Vec<IpCidr>,
 }
 
 impl Config {
     pub fn parse(args: ArgsOs) -> Result<Self, ConfigError> {
         let mut parser = Parser::from_iter(args);
         let mut path = None;
+        let mut share_link = false;
+        let mut check = false;
+        let mut bench = None;
+        let mut import = None;
+        let mut overrides = Override::from_env();
 
         while let Some(arg) = parser.next()? {
             match arg {
@@ -143,6 +712,35 @@ impl Config {
                         return Err(ConfigError::Argument(arg.unexpected()));
                     }
                 }
+                Arg::Long("set") => overrides.push(
+                    Override::from_arg(&parser.value()?.string()?)
+                        .map_err(ConfigError::Override)?,
+                ),
+                Arg::Value(cmd) if cmd == "check-config" && !check => check = true,
+                Arg::Value(cmd) if cmd == "bench" && bench.is_none() => {
+                    bench = Some(Bench {
+                        server: None,
+                        size: 16 * 1024 * 1024,
+                        count: 200,
+                        udp_size: 1200,
+                    });
+                }
+                Arg::Long("server") if bench.is_some() => {
+                    bench.as_mut().unwrap().server = Some(parser.value()?.string()?);
+                }
+                Arg::Long("size") if bench.is_some() => {
+                    bench.as_mut().unwrap().size = parser.value()?.parse()?;
+                }
+                Arg::Short('n') | Arg::Long("count") if bench.is_some() => {
+                    bench.as_mut().unwrap().count = parser.value()?.parse()?;
+                }
+                Arg::Long("udp-size") if bench.is_some() => {
+                    bench.as_mut().unwrap().udp_size = parser.value()?.parse()?;
+                }
+                Arg::Short('s') | Arg::Long("share-link") => share_link = true,
+                Arg::Short('i') | Arg::Long("import") if import.is_none() => {
+                    import = Some(parser.value()?);
+                }
                 Arg::Short('v') | Arg::Long("version") => {
                     return Err(ConfigError::Version(env!("CARGO_PKG_VERSION")))
                 }
@@ -151,23 +749,112 @@ impl Config {
             }
         }
 
+        if let Some(import) = import {
+            let file = File::open(import)?;
+            let cfg: Value = serde_json::from_reader(file)?;
+            let cfg = self::import::convert(&cfg).map_err(ConfigError::Import)?;
+            let cfg = serde_json::to_string_pretty(&cfg)?;
+            return Err(ConfigError::Imported(cfg));
+        }
+
         if path.is_none() {
-            return Err(ConfigError::NoConfig);
+            return match self::sip003::from_env().map_err(ConfigError::Sip003)? {
+                Some((cfg, sip003)) => {
+                    let mut cfg: Self = serde_json::from_value(cfg)?;
+                    cfg.sip003 = Some(sip003);
+                    cfg.check = check;
+                    cfg.bench = bench;
+                    Ok(cfg)
+                }
+                // the config can be given entirely by the overrides
+                None if !overrides.is_empty() => {
+                    let mut cfg = Self::from_json_with("{}", overrides)?;
+                    cfg.check = check;
+                    cfg.bench = bench;
+                    Ok(cfg)
+                }
+                None => Err(ConfigError::NoConfig),
+            };
+        }
+
+        let mut cfg = Self::from_file(path.unwrap(), overrides)?;
+        cfg.check = check;
+        cfg.bench = bench;
+
+        if share_link {
+            let links = cfg
+                .relay
+                .iter()
+                .map(|relay| relay.share_link().to_string())
+                .collect::<Vec<_>>();
+
+            return Err(ConfigError::ShareLinks(links.join("\n")));
         }
 
-        let file = File::open(path.unwrap())?;
-        Ok(serde_json::from_reader(file)?)
+        Ok(cfg)
+    }
+
+    /// The address of the first local listener serving SOCKS, e.g. for setting the system proxy, or of the first one if none does
+    pub fn socks_server(&self) -> SocketAddr {
+        self.local
+            .iter()
+            .find(|local| local.protocol.serves_socks())
+            .unwrap_or(&self.local[0])
+            .server
+    }
+
+    /// Reads the config file, with the fields set by the overrides in order
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        overrides: Vec<Override>,
+    ) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut cfg = Self::from_json_with(&fs::read_to_string(path)?, overrides)?;
+        cfg.path = Some(path.to_path_buf());
+
+        Ok(cfg)
+    }
+
+    /// Parses the config from a JSON string, e.g. passed by an application embedding the client
+    ///
+    /// The config cannot be reloaded, as it is not bound to a file.
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        Self::from_json_with(json, Vec::new())
+    }
+
+    fn from_json_with(json: &str, overrides: Vec<Override>) -> Result<Self, ConfigError> {
+        let loaded = tuic_config::from_str_with::<Self>(json, &overrides)?;
+        let mut cfg = loaded.config;
+        cfg.raw = serde_json::from_str(json)?;
+        tuic_config::overrides::apply(&mut cfg.raw, &overrides)?;
+        cfg.warnings = loaded.warnings;
+        cfg.overrides = overrides;
+
+        Ok(cfg)
     }
 }
 
 mod default {
+    use crate::utils::Balance;
     use log::LevelFilter;
 
     pub mod relay {
         use crate::utils::{CongestionControl, UdpRelayMode};
         use std::{path::PathBuf, time::Duration};
 
-        pub fn certificates() -> Vec<PathBuf> {
+        pub fn priority() -> u32 {
+            0
+        }
+
+        pub fn weight() -> u32 {
+            1
+        }
+
+        pub fn connections() -> usize {
+            1
+        }
+
+        pub fn certificates() -> Vec<PathBuf> {
             Vec::new()
         }
 
@@ -199,6 +886,10 @@ mod default {
             Duration::from_secs(3)
         }
 
+        pub fn happy_eyeballs_delay() -> Duration {
+            Duration::from_millis(250)
+        }
+
         pub fn disable_native_certs() -> bool {
             false
         }
@@ -211,6 +902,22 @@ mod default {
             8 * 1024 * 1024
         }
 
+        pub fn initial_rtt() -> Duration {
+            Duration::from_millis(333)
+        }
+
+        pub fn max_udp_payload_size() -> u16 {
+            65527
+        }
+
+        pub fn max_concurrent_streams() -> u32 {
+            32
+        }
+
+        pub fn datagram_receive_buffer_size() -> usize {
+            1250000
+        }
+
         pub fn gc_interval() -> Duration {
             Duration::from_secs(3)
         }
@@ -220,12 +927,262 @@ mod default {
         }
     }
 
+    pub mod udp_stream_fallback {
+        use std::time::Duration;
+
+        pub fn loss_threshold() -> f64 {
+            0.1
+        }
+
+        pub fn recover_threshold() -> f64 {
+            0.02
+        }
+
+        pub fn hold() -> Duration {
+            Duration::from_secs(10)
+        }
+    }
+
+    pub mod adaptive_fragment_size {
+        use std::time::Duration;
+
+        pub fn loss_threshold() -> f64 {
+            0.05
+        }
+
+        pub fn recover_threshold() -> f64 {
+            0.01
+        }
+
+        pub fn min_size() -> usize {
+            crate::config::MIN_DATAGRAM_SIZE
+        }
+
+        pub fn hold() -> Duration {
+            Duration::from_secs(10)
+        }
+    }
+
+    pub mod udp_compression {
+        pub fn min_size() -> u16 {
+            128
+        }
+    }
+
+    pub mod udp_native_pacing {
+        pub fn burst() -> u64 {
+            16 * 1024
+        }
+    }
+
+    pub mod udp_stun {
+        use std::time::Duration;
+
+        pub fn native() -> bool {
+            true
+        }
+
+        pub fn timeout() -> Duration {
+            Duration::from_secs(300)
+        }
+    }
+
+    pub mod keep_warm {
+        use crate::utils::KeepWarmStrategy;
+        use std::time::Duration;
+
+        pub fn strategy() -> KeepWarmStrategy {
+            KeepWarmStrategy::Heartbeat
+        }
+
+        pub fn server_idle_timeout() -> Duration {
+            Duration::from_secs(10)
+        }
+    }
+
+    pub mod on_demand {
+        use std::time::Duration;
+
+        pub fn idle_timeout() -> Duration {
+            Duration::from_secs(30)
+        }
+    }
+
+    pub mod telemetry {
+        use crate::utils::TelemetryFormat;
+        use std::time::Duration;
+
+        pub fn interval() -> Duration {
+            Duration::from_secs(10)
+        }
+
+        pub fn format() -> TelemetryFormat {
+            TelemetryFormat::Json
+        }
+    }
+
+    pub mod health_check {
+        use std::time::Duration;
+
+        pub fn interval() -> Duration {
+            Duration::from_secs(5)
+        }
+
+        pub fn echo() -> bool {
+            false
+        }
+    }
+
+    pub fn health_check() -> super::HealthCheck {
+        super::HealthCheck {
+            interval: health_check::interval(),
+            max_rtt: None,
+            echo: health_check::echo(),
+        }
+    }
+
+    pub fn balance() -> Balance {
+        Balance::Failover
+    }
+
+    pub mod reconnect {
+        use std::time::Duration;
+
+        pub fn initial_backoff() -> Duration {
+            Duration::from_millis(500)
+        }
+
+        pub fn max_backoff() -> Duration {
+            Duration::from_secs(30)
+        }
+
+        pub fn max_pending() -> usize {
+            256
+        }
+    }
+
+    pub fn reconnect() -> super::Reconnect {
+        super::Reconnect {
+            initial_backoff: reconnect::initial_backoff(),
+            max_backoff: reconnect::max_backoff(),
+            max_pending: reconnect::max_pending(),
+        }
+    }
+
     pub mod local {
+        use crate::utils::InboundProtocol;
+
+        pub fn protocol() -> InboundProtocol {
+            InboundProtocol::Mixed
+        }
+
         pub fn max_packet_size() -> usize {
             1500
         }
     }
 
+    pub mod dns {
+        use crate::utils::Ipv4Cidr;
+        use std::time::Duration;
+
+        pub fn timeout() -> Duration {
+            Duration::from_secs(5)
+        }
+
+        pub fn fake_ip_range() -> Ipv4Cidr {
+            "198.18.0.0/15".parse().unwrap()
+        }
+
+        pub fn fake_ip_ttl() -> u32 {
+            1
+        }
+
+        pub fn ttl() -> u32 {
+            60
+        }
+    }
+
+    pub mod forward {
+        use std::time::Duration;
+
+        pub fn tcp() -> bool {
+            true
+        }
+
+        pub fn udp() -> bool {
+            true
+        }
+
+        pub fn udp_timeout() -> Duration {
+            Duration::from_secs(60)
+        }
+    }
+
+    pub mod system_proxy {
+        pub fn set() -> bool {
+            true
+        }
+
+        pub fn bypass() -> Vec<String> {
+            vec![
+                String::from("localhost"),
+                String::from("127.0.0.0/8"),
+                String::from("::1"),
+            ]
+        }
+    }
+
+    pub mod router {
+        use crate::router::{Outbound, Rule};
+        use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+        pub fn domain_lists() -> HashMap<String, PathBuf> {
+            HashMap::new()
+        }
+
+        pub fn rules() -> Vec<Rule> {
+            Vec::new()
+        }
+
+        pub fn default_outbound() -> Outbound {
+            Outbound::Proxy
+        }
+
+        pub fn cache_size() -> usize {
+            4096
+        }
+
+        pub fn cache_ttl() -> Duration {
+            Duration::from_secs(60)
+        }
+
+        pub fn bypass_private() -> bool {
+            true
+        }
+
+        pub fn bypass_server() -> bool {
+            true
+        }
+
+        pub fn bypass_local() -> bool {
+            true
+        }
+    }
+
+    pub fn router() -> super::Router {
+        super::Router {
+            geosite: None,
+            domain_lists: router::domain_lists(),
+            rules: router::rules(),
+            default_outbound: router::default_outbound(),
+            reload_interval: None,
+            sniff: false,
+            cache_size: router::cache_size(),
+            cache_ttl: router::cache_ttl(),
+            bypass: None,
+        }
+    }
+
     pub fn log_level() -> LevelFilter {
         LevelFilter::Warn
     }
@@ -241,6 +1198,256 @@ where
     T::from_str(&s).map_err(DeError::custom)
 }
 
+pub fn deserialize_optional_from_str<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: FromStr,
+    <T as FromStr>::Err: Display,
+    D: Deserializer<'de>,
+{
+    deserialize_from_str(deserializer).map(Some)
+}
+
+pub fn deserialize_connection_receive_window<'de, D>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let window: u64 = tuic_config::deserialize_size(deserializer)?;
+
+    if window == 0 || VarInt::from_u64(window).is_err() {
+        return Err(DeError::custom(format!(
+            "connection_receive_window must be between 1 and {}",
+            VarInt::MAX
+        )));
+    }
+
+    Ok(Some(window))
+}
+
+pub fn deserialize_initial_rtt<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rtt = tuic_config::deserialize_duration(deserializer)?;
+
+    if rtt.is_zero() {
+        return Err(DeError::custom("initial_rtt must be greater than 0"));
+    }
+
+    Ok(rtt)
+}
+
+pub fn deserialize_max_udp_payload_size<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // the bounds accepted by quinn, from RFC 9000
+    const MIN_UDP_PAYLOAD_SIZE: u16 = 1200;
+    const MAX_UDP_PAYLOAD_SIZE: u16 = 65527;
+
+    let size = u16::deserialize(deserializer)?;
+
+    if !(MIN_UDP_PAYLOAD_SIZE..=MAX_UDP_PAYLOAD_SIZE).contains(&size) {
+        return Err(DeError::custom(format!(
+            "max_udp_payload_size must be between {MIN_UDP_PAYLOAD_SIZE} and {MAX_UDP_PAYLOAD_SIZE}"
+        )));
+    }
+
+    Ok(size)
+}
+
+pub fn deserialize_max_concurrent_streams<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let max = u32::deserialize(deserializer)?;
+
+    if max == 0 {
+        return Err(DeError::custom(
+            "max_concurrent_streams must be greater than 0",
+        ));
+    }
+
+    Ok(max)
+}
+
+pub fn deserialize_connections<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let connections = usize::deserialize(deserializer)?;
+
+    if connections == 0 {
+        return Err(DeError::custom("connections must be greater than 0"));
+    }
+
+    Ok(connections)
+}
+
+pub fn deserialize_datagram_receive_buffer_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size: usize = tuic_config::deserialize_size(deserializer)?;
+
+    if size == 0 {
+        return Err(DeError::custom(
+            "datagram_receive_buffer_size must be greater than 0",
+        ));
+    }
+
+    Ok(size)
+}
+
+pub fn deserialize_min_fragment_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size: usize = tuic_config::deserialize_size(deserializer)?;
+
+    if size < MIN_DATAGRAM_SIZE {
+        return Err(DeError::custom(format!(
+            "min_size must be at least {MIN_DATAGRAM_SIZE} bytes"
+        )));
+    }
+
+    Ok(size)
+}
+
+pub fn deserialize_max_datagram_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size: usize = tuic_config::deserialize_size(deserializer)?;
+
+    if size < MIN_DATAGRAM_SIZE {
+        return Err(DeError::custom(format!(
+            "max_datagram_size must be at least {MIN_DATAGRAM_SIZE} bytes"
+        )));
+    }
+
+    Ok(Some(size))
+}
+
+pub fn deserialize_pacing_rate<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rate = tuic_config::deserialize_bandwidth(deserializer)?;
+
+    if rate == 0 {
+        return Err(DeError::custom("pacing rate must be greater than 0"));
+    }
+
+    Ok(rate)
+}
+
+pub fn deserialize_bandwidth<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bandwidth = tuic_config::deserialize_bandwidth(deserializer)?;
+
+    if bandwidth == 0 {
+        return Err(DeError::custom("bandwidth must be greater than 0"));
+    }
+
+    Ok(bandwidth)
+}
+
+pub fn deserialize_fingerprints<'de, D>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    fn fingerprint(s: &str) -> Option<[u8; 32]> {
+        let hex = s.replace(':', "");
+
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+
+        let mut fingerprint = [0; 32];
+
+        for (idx, byte) in fingerprint.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok()?;
+        }
+
+        Some(fingerprint)
+    }
+
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| {
+            fingerprint(s).ok_or_else(|| {
+                DeError::custom(format!(
+                    "invalid certificate fingerprint `{s}`, expecting a SHA-256 digest in 64 hex digits"
+                ))
+            })
+        })
+        .collect()
+}
+
+pub fn deserialize_ip_cidrs<'de, D>(deserializer: D) -> Result<Vec<IpCidr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|cidr| cidr.parse().map_err(DeError::custom))
+        .collect()
+}
+
+pub fn deserialize_relays<'de, D>(deserializer: D) -> Result<Vec<Relay>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    fn relay<E: DeError>(relay: Value, path: &str) -> Result<Relay, E> {
+        match relay {
+            Value::String(link) => {
+                let link = link.parse::<ShareLink>().map_err(DeError::custom)?;
+                tuic_config::from_value(link.to_relay_value(), path)
+            }
+            relay => tuic_config::from_value(relay, path),
+        }
+    }
+
+    let relays = match Value::deserialize(deserializer)? {
+        Value::Array(relays) => relays
+            .into_iter()
+            .enumerate()
+            .map(|(idx, value)| relay(value, &format!("relay[{idx}]")))
+            .collect::<Result<Vec<_>, _>>()?,
+        relay_value => vec![relay(relay_value, "relay")?],
+    };
+
+    if relays.is_empty() {
+        return Err(DeError::custom("relay cannot be empty"));
+    }
+
+    Ok(relays)
+}
+
+pub fn deserialize_locals<'de, D>(deserializer: D) -> Result<Vec<Local>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let locals = match Value::deserialize(deserializer)? {
+        Value::Array(locals) => locals
+            .into_iter()
+            .enumerate()
+            .map(|(idx, value)| tuic_config::from_value(value, &format!("local[{idx}]")))
+            .collect::<Result<Vec<_>, _>>()?,
+        local => vec![tuic_config::from_value(local, "local")?],
+    };
+
+    if locals.is_empty() {
+        return Err(DeError::custom("local cannot be empty"));
+    }
+
+    Ok(locals)
+}
+
 pub fn deserialize_server<'de, D>(deserializer: D) -> Result<(String, u16), D::Error>
 where
     D: Deserializer<'de>,
@@ -257,6 +1464,14 @@ where
     Ok((s, port))
 }
 
+pub fn deserialize_address<'de, D>(deserializer: D) -> Result<Address, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(DeError::custom)
+}
+
 pub fn deserialize_password<'de, D>(deserializer: D) -> Result<Arc<[u8]>, D::Error>
 where
     D: Deserializer<'de>,
@@ -265,6 +1480,14 @@ where
     Ok(Arc::from(s.into_bytes().into_boxed_slice()))
 }
 
+pub fn deserialize_optional_password<'de, D>(deserializer: D) -> Result<Option<Arc<[u8]>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = Option::<String>::deserialize(deserializer)?;
+    Ok(s.map(|s| Arc::from(s.into_bytes().into_boxed_slice())))
+}
+
 pub fn deserialize_alpn<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
 where
     D: Deserializer<'de>,
@@ -273,23 +1496,25 @@ where
     Ok(s.into_iter().map(|alpn| alpn.into_bytes()).collect())
 }
 
-pub fn deserialize_optional_bytes<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+pub fn deserialize_rules<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
 where
+    T: FromStr,
+    <T as FromStr>::Err: Display,
     D: Deserializer<'de>,
 {
-    let s = String::deserialize(deserializer)?;
-    Ok(Some(s.into_bytes()))
+    let s = Vec::<String>::deserialize(deserializer)?;
+
+    s.into_iter()
+        .map(|rule| rule.parse().map_err(DeError::custom))
+        .collect()
 }
 
-pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+pub fn deserialize_optional_bytes<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
-
-    s.parse::<HumanDuration>()
-        .map(|d| *d)
-        .map_err(DeError::custom)
+    Ok(Some(s.into_bytes()))
 }
 
 #[derive(Debug, Error)]
@@ -299,11 +1524,23 @@ pub enum ConfigError {
     #[error("no config file specified")]
     NoConfig,
     #[error("{0}")]
+    Override(String),
+    #[error("{0}")]
     Version(&'static str),
     #[error("{0}")]
     Help(&'static str),
+    #[error("{0}")]
+    ShareLinks(String),
+    #[error("{0}")]
+    Imported(String),
+    #[error("failed to import config: {0}")]
+    Import(String),
+    #[error("invalid SIP003 plugin environment: {0}")]
+    Sip003(String),
     #[error(transparent)]
     Io(#[from] IoError),
     #[error(transparent)]
     Serde(#[from] SerdeError),
+    #[error(transparent)]
+    Invalid(#[from] tuic_config::Error),
 }
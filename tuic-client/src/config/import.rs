@@ -0,0 +1,238 @@
+//! Converting the TUIC outbounds in sing-box and v2ray-style configs into the config of this client
+
+use serde_json::{json, Map, Value};
+use std::net::IpAddr;
+
+/// Converts a sing-box or v2ray-style config, or a single outbound of them, into the config of this client
+///
+/// All TUIC outbounds are converted into `relay` entries. The listening address of the first SOCKS or mixed inbound, if any, is used as the `local` server.
+pub fn convert(cfg: &Value) -> Result<Value, String> {
+    let outbounds = match cfg.get("outbounds") {
+        Some(Value::Array(outbounds)) => outbounds.iter().collect(),
+        Some(_) => return Err(String::from("`outbounds` is not an array")),
+        None => vec![cfg],
+    };
+
+    let mut relays = Vec::new();
+
+    for outbound in outbounds {
+        if outbound.get("type").and_then(Value::as_str) == Some("tuic") {
+            relays.push(from_sing_box(outbound)?);
+        } else if outbound.get("protocol").and_then(Value::as_str) == Some("tuic") {
+            relays.extend(from_v2ray(outbound)?);
+        }
+    }
+
+    if relays.is_empty() {
+        return Err(String::from("no TUIC outbound found"));
+    }
+
+    let local = local_server(cfg).unwrap_or_else(|| String::from("127.0.0.1:1080"));
+
+    let relay = if relays.len() == 1 {
+        relays.pop().unwrap()
+    } else {
+        Value::Array(relays)
+    };
+
+    Ok(json!({
+        "relay": relay,
+        "local": { "server": local },
+    }))
+}
+
+/// Converts a sing-box TUIC outbound
+///
+/// ```json
+/// {
+///     "type": "tuic",
+///     "server": "example.com",
+///     "server_port": 443,
+///     "uuid": "...",
+///     "password": "...",
+///     "congestion_control": "bbr",
+///     "udp_relay_mode": "native",
+///     "zero_rtt_handshake": false,
+///     "heartbeat": "10s",
+///     "tls": { "server_name": "example.com", "alpn": ["h3"], "disable_sni": false, "certificate_path": "..." }
+/// }
+/// ```
+fn from_sing_box(outbound: &Value) -> Result<Value, String> {
+    let server = required_str(outbound, "server")?;
+    let port = required_port(outbound, "server_port")?;
+
+    let mut relay = Map::new();
+    relay.insert(String::from("uuid"), json!(required_str(outbound, "uuid")?));
+    relay.insert(
+        String::from("password"),
+        json!(optional_str(outbound, "password").unwrap_or_default()),
+    );
+
+    let tls = outbound.get("tls");
+    let server_name = tls.and_then(|tls| optional_str(tls, "server_name"));
+    insert_server(&mut relay, server, port, server_name);
+
+    copy_str(outbound, "congestion_control", &mut relay);
+    copy_str(outbound, "udp_relay_mode", &mut relay);
+    copy_str(outbound, "heartbeat", &mut relay);
+
+    if let Some(zero_rtt) = outbound.get("zero_rtt_handshake").and_then(Value::as_bool) {
+        relay.insert(String::from("zero_rtt_handshake"), json!(zero_rtt));
+    }
+
+    if let Some(tls) = tls {
+        copy_alpn(tls.get("alpn"), &mut relay);
+
+        if let Some(disable_sni) = tls.get("disable_sni").and_then(Value::as_bool) {
+            relay.insert(String::from("disable_sni"), json!(disable_sni));
+        }
+
+        if let Some(path) = optional_str(tls, "certificate_path") {
+            relay.insert(String::from("certificates"), json!([path]));
+        }
+    }
+
+    Ok(Value::Object(relay))
+}
+
+/// Converts a v2ray-style TUIC outbound, with one or more servers
+///
+/// ```json
+/// {
+///     "protocol": "tuic",
+///     "settings": {
+///         "servers": [{ "address": "example.com", "port": 443, "uuid": "...", "password": "...", "congestion_control": "bbr" }]
+///     },
+///     "streamSettings": { "tlsSettings": { "serverName": "example.com", "alpn": ["h3"] } }
+/// }
+/// ```
+fn from_v2ray(outbound: &Value) -> Result<Vec<Value>, String> {
+    let settings = outbound
+        .get("settings")
+        .ok_or_else(|| String::from("missing `settings` in TUIC outbound"))?;
+
+    let servers = match settings.get("servers") {
+        Some(Value::Array(servers)) => servers.iter().collect(),
+        Some(_) => return Err(String::from("`settings.servers` is not an array")),
+        None => vec![settings],
+    };
+
+    let tls = outbound
+        .get("streamSettings")
+        .and_then(|stream| stream.get("tlsSettings"));
+    let server_name = tls.and_then(|tls| optional_str(tls, "serverName"));
+
+    servers
+        .into_iter()
+        .map(|server| {
+            let mut relay = Map::new();
+            relay.insert(String::from("uuid"), json!(required_str(server, "uuid")?));
+            relay.insert(
+                String::from("password"),
+                json!(optional_str(server, "password").unwrap_or_default()),
+            );
+
+            insert_server(
+                &mut relay,
+                required_str(server, "address")?,
+                required_port(server, "port")?,
+                server_name,
+            );
+
+            copy_str(server, "congestion_control", &mut relay);
+            copy_str(server, "udp_relay_mode", &mut relay);
+            copy_alpn(tls.and_then(|tls| tls.get("alpn")), &mut relay);
+
+            Ok(Value::Object(relay))
+        })
+        .collect()
+}
+
+/// Returns the listening address of the first SOCKS or mixed inbound in a sing-box or v2ray-style config
+fn local_server(cfg: &Value) -> Option<String> {
+    cfg.get("inbounds")?.as_array()?.iter().find_map(|inbound| {
+        if let Some(inbound_type) = optional_str(inbound, "type") {
+            if inbound_type != "socks" && inbound_type != "mixed" {
+                return None;
+            }
+
+            let listen = optional_str(inbound, "listen").unwrap_or("127.0.0.1");
+            let port = inbound.get("listen_port")?.as_u64()?;
+            Some(socket_addr(listen, port))
+        } else {
+            if optional_str(inbound, "protocol")? != "socks" {
+                return None;
+            }
+
+            let listen = optional_str(inbound, "listen").unwrap_or("127.0.0.1");
+            let port = inbound.get("port")?.as_u64()?;
+            Some(socket_addr(listen, port))
+        }
+    })
+}
+
+/// Sets `server`, and `ip` if the address is an IP address and a different server name is set in TLS
+///
+/// If both the address and the server name are domains, the server name is used
+fn insert_server(relay: &mut Map<String, Value>, addr: &str, port: u16, server_name: Option<&str>) {
+    match server_name {
+        // an IP address is kept in `ip`, so the server name is used for both the SNI and certificate verification
+        Some(server_name) if server_name != addr && addr.parse::<IpAddr>().is_ok() => {
+            relay.insert(
+                String::from("server"),
+                json!(format!("{server_name}:{port}")),
+            );
+            relay.insert(String::from("ip"), json!(addr));
+        }
+        // a domain is still resolved to dial the server, with only the SNI overridden
+        Some(server_name) if server_name != addr => {
+            relay.insert(String::from("server"), json!(format!("{addr}:{port}")));
+            relay.insert(String::from("sni"), json!(server_name));
+        }
+        _ => {
+            relay.insert(String::from("server"), json!(format!("{addr}:{port}")));
+        }
+    }
+}
+
+fn copy_str(from: &Value, key: &str, to: &mut Map<String, Value>) {
+    if let Some(value) = optional_str(from, key) {
+        to.insert(String::from(key), json!(value));
+    }
+}
+
+fn copy_alpn(alpn: Option<&Value>, to: &mut Map<String, Value>) {
+    match alpn {
+        Some(Value::Array(alpn)) => {
+            to.insert(String::from("alpn"), Value::Array(alpn.clone()));
+        }
+        Some(Value::String(alpn)) => {
+            to.insert(String::from("alpn"), json!([alpn]));
+        }
+        _ => {}
+    }
+}
+
+fn optional_str<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Value::as_str)
+}
+
+fn required_str<'a>(value: &'a Value, key: &str) -> Result<&'a str, String> {
+    optional_str(value, key).ok_or_else(|| format!("missing `{key}` in TUIC outbound"))
+}
+
+fn required_port(value: &Value, key: &str) -> Result<u16, String> {
+    value
+        .get(key)
+        .and_then(Value::as_u64)
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or_else(|| format!("missing or invalid `{key}` in TUIC outbound"))
+}
+
+fn socket_addr(ip: &str, port: u64) -> String {
+    if ip.contains(':') {
+        format!("[{ip}]:{port}")
+    } else {
+        format!("{ip}:{port}")
+    }
+}
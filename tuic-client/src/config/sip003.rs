@@ -0,0 +1,148 @@
+//! Building the config from the environment variables set by a Shadowsocks SIP003 plugin host
+
+use super::{ShareLink, Sip003};
+use serde_json::{json, Value};
+use std::{env, net::IpAddr};
+use tuic::Address;
+
+/// Returns the config and the tunnel settings if running as a SIP003 plugin, i.e. `SS_REMOTE_HOST` is set
+///
+/// The TUIC server is `SS_REMOTE_HOST:SS_REMOTE_PORT`, and the tunnel listens on `SS_LOCAL_HOST:SS_LOCAL_PORT`. Options are read from `SS_PLUGIN_OPTIONS`, in the form of `uuid=UUID;password=PASSWORD;...`.
+pub fn from_env() -> Result<Option<(Value, Sip003)>, String> {
+    let Some(remote_host) = var("SS_REMOTE_HOST")? else {
+        return Ok(None);
+    };
+
+    let remote_port = parse_port("SS_REMOTE_PORT")?;
+    let local_host = var("SS_LOCAL_HOST")?.unwrap_or_else(|| String::from("127.0.0.1"));
+    let local_port = parse_port("SS_LOCAL_PORT")?;
+
+    let local = local_host
+        .parse::<IpAddr>()
+        .map_err(|_| format!("invalid `SS_LOCAL_HOST`: {local_host}"))?;
+
+    let mut uuid = None;
+    let mut password = None;
+    let mut sni = None;
+    let mut congestion_control = None;
+    let mut udp_relay_mode = None;
+    let mut alpn = Vec::new();
+    let mut disable_sni = false;
+    let mut target = None;
+    let mut log_level = None;
+
+    for (key, value) in parse_options(&var("SS_PLUGIN_OPTIONS")?.unwrap_or_default())? {
+        match key.as_str() {
+            "uuid" => uuid = Some(value),
+            "password" => password = Some(value),
+            "sni" => sni = Some(value).filter(|sni| !sni.is_empty()),
+            "congestion_control" => congestion_control = Some(value),
+            "udp_relay_mode" => udp_relay_mode = Some(value),
+            "alpn" => {
+                alpn = value
+                    .split(',')
+                    .filter(|alpn| !alpn.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            }
+            "disable_sni" => disable_sni = value.is_empty() || value == "1" || value == "true",
+            "target" => target = Some(value),
+            "log_level" => log_level = Some(value),
+            _ => return Err(format!("unknown plugin option `{key}`")),
+        }
+    }
+
+    let link = ShareLink {
+        uuid: uuid
+            .ok_or("missing plugin option `uuid`")?
+            .parse()
+            .map_err(|_| "invalid plugin option `uuid`")?,
+        password: password.ok_or("missing plugin option `password`")?,
+        host: remote_host,
+        port: remote_port,
+        sni,
+        congestion_control,
+        udp_relay_mode,
+        alpn,
+        disable_sni,
+        name: None,
+    };
+
+    let target = match target {
+        Some(target) => target
+            .parse()
+            .map_err(|err| format!("invalid plugin option `target`: {err}"))?,
+        None => Address::SocketAddress(([127, 0, 0, 1], remote_port).into()),
+    };
+
+    let local = (local, local_port).into();
+
+    let mut cfg = json!({
+        "relay": link.to_relay_value(),
+        "local": { "server": local },
+    });
+
+    if let Some(log_level) = log_level {
+        cfg["log_level"] = json!(log_level);
+    }
+
+    Ok(Some((cfg, Sip003 { local, target })))
+}
+
+/// Splits `SS_PLUGIN_OPTIONS` into key-value pairs. `\` escapes `;`, `=` and `\` itself
+fn parse_options(options: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut key = String::new();
+    let mut value = None;
+    let mut chars = options.chars();
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => chars
+                .next()
+                .ok_or("invalid escape in `SS_PLUGIN_OPTIONS`")?,
+            ';' => {
+                push_option(&mut pairs, &mut key, &mut value);
+                continue;
+            }
+            '=' if value.is_none() => {
+                value = Some(String::new());
+                continue;
+            }
+            c => c,
+        };
+
+        match &mut value {
+            Some(value) => value.push(c),
+            None => key.push(c),
+        }
+    }
+
+    push_option(&mut pairs, &mut key, &mut value);
+
+    Ok(pairs)
+}
+
+fn push_option(pairs: &mut Vec<(String, String)>, key: &mut String, value: &mut Option<String>) {
+    let key = std::mem::take(key);
+    let value = value.take().unwrap_or_default();
+
+    if !key.trim().is_empty() {
+        pairs.push((key.trim().to_owned(), value));
+    }
+}
+
+fn var(key: &str) -> Result<Option<String>, String> {
+    match env::var(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(format!("invalid `{key}`")),
+    }
+}
+
+fn parse_port(key: &str) -> Result<u16, String> {
+    var(key)?
+        .ok_or_else(|| format!("missing `{key}`"))?
+        .parse()
+        .map_err(|_| format!("invalid `{key}`"))
+}
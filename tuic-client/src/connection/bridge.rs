@@ -0,0 +1,179 @@
+use crate::{error::Error, protect, utils::WebSocketBridge};
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use quinn::{
+    udp::{RecvMeta, Transmit, UdpState},
+    AsyncUdpSocket,
+};
+use std::{
+    io::{Error as IoError, ErrorKind, IoSliceMut, Result as IoResult},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::{Context, Poll},
+};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+};
+use tokio_tungstenite::{client_async, tungstenite::Message, WebSocketStream};
+use tuic::Address;
+
+/// The number of datagrams queued in each direction, beyond which datagrams are dropped as on a congested UDP path
+const QUEUE_SIZE: usize = 1024;
+
+/// A UDP socket relaying datagrams through a WebSocket bridge, one binary message per datagram
+///
+/// The bridge forwards every datagram to the server it is paired with, so the destination address is ignored, and received datagrams are reported to be from the server address the connection was made to. The WebSocket connection lives as long as the socket.
+#[derive(Debug)]
+pub struct WebSocketUdpSocket {
+    tx: Sender<Vec<u8>>,
+    rx: Mutex<Receiver<Vec<u8>>>,
+    remote: SocketAddr,
+}
+
+impl WebSocketUdpSocket {
+    pub async fn connect(bridge: &WebSocketBridge, remote: SocketAddr) -> Result<Self, Error> {
+        let stream =
+            protect::connect_direct(&Address::DomainAddress(bridge.host.clone(), bridge.port))
+                .await?;
+        stream.set_nodelay(true)?;
+
+        let (ws, _) = client_async(bridge.url.as_str(), stream).await?;
+
+        log::debug!(
+            "[relay] [bridge] connected to {url}, relaying to {remote}",
+            url = bridge.url,
+        );
+
+        let (out_tx, out_rx) = mpsc::channel(QUEUE_SIZE);
+        let (in_tx, in_rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(Self::relay(ws, out_rx, in_tx));
+
+        Ok(Self {
+            tx: out_tx,
+            rx: Mutex::new(in_rx),
+            remote,
+        })
+    }
+
+    async fn relay(
+        ws: WebSocketStream<TcpStream>,
+        mut out_rx: Receiver<Vec<u8>>,
+        in_tx: Sender<Vec<u8>>,
+    ) {
+        let (mut sink, mut stream) = ws.split();
+
+        let res = loop {
+            tokio::select! {
+                datagram = out_rx.recv() => {
+                    // the socket is dropped
+                    let Some(mut datagram) = datagram else {
+                        break sink.close().await;
+                    };
+
+                    // queued datagrams are written together, with a single flush
+                    let res = loop {
+                        if let Err(err) = sink.feed(Message::Binary(datagram)).await {
+                            break Err(err);
+                        }
+
+                        match out_rx.try_recv() {
+                            Ok(next) => datagram = next,
+                            Err(_) => break sink.flush().await,
+                        }
+                    };
+
+                    if let Err(err) = res {
+                        break Err(err);
+                    }
+                }
+                msg = stream.next() => match msg {
+                    Some(Ok(Message::Binary(datagram))) => {
+                        let _ = in_tx.try_send(datagram);
+                    }
+                    Some(Ok(Message::Close(_))) | None => break Ok(()),
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => break Err(err),
+                },
+            }
+        };
+
+        match res {
+            Ok(()) => log::debug!("[relay] [bridge] connection closed"),
+            Err(err) => log::warn!("[relay] [bridge] connection error: {err}"),
+        }
+    }
+}
+
+impl AsyncUdpSocket for WebSocketUdpSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        _cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<IoResult<usize>> {
+        for transmit in transmits {
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+
+            for segment in transmit.contents.chunks(segment_size.max(1)) {
+                match self.tx.try_send(segment.to_vec()) {
+                    Ok(()) | Err(TrySendError::Full(_)) => {}
+                    Err(TrySendError::Closed(_)) => {
+                        return Poll::Ready(Err(IoError::new(
+                            ErrorKind::BrokenPipe,
+                            "WebSocket bridge closed",
+                        )))
+                    }
+                }
+            }
+        }
+
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<IoResult<usize>> {
+        let (Some(buf), Some(meta)) = (bufs.first_mut(), meta.first_mut()) else {
+            return Poll::Ready(Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "no buffer for receiving",
+            )));
+        };
+
+        match self.rx.lock().poll_recv(cx) {
+            Poll::Ready(Some(datagram)) => {
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+
+                *meta = RecvMeta {
+                    addr: self.remote,
+                    len,
+                    stride: len,
+                    ecn: None,
+                    dst_ip: None,
+                };
+
+                Poll::Ready(Ok(1))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(IoError::new(
+                ErrorKind::ConnectionAborted,
+                "WebSocket bridge closed",
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        match self.remote {
+            SocketAddr::V4(_) => Ok(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
+            SocketAddr::V6(_) => Ok(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))),
+        }
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
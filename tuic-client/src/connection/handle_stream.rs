@@ -1,10 +1,10 @@
-use super::Connection;
-use crate::{error::Error, utils::UdpRelayMode};
+use super::{Connection, ERROR_CODE};
+use crate::{error::Error, forward::ReverseForward, socks5::BINDS as SOCKS5_BINDS};
 use bytes::Bytes;
 use quinn::{RecvStream, SendStream, VarInt};
 use register_count::Register;
 use std::sync::atomic::Ordering;
-use tuic_quinn::Task;
+use tuic_quinn::{Task, Unknown};
 
 impl Connection {
     pub async fn accept_uni_stream(&self) -> Result<(RecvStream, Register), Error> {
@@ -48,13 +48,15 @@ impl Connection {
 
         let res = match self.model.accept_uni_stream(recv).await {
             Err(err) => Err(Error::Model(err)),
-            Ok(Task::Packet(pkt)) => match self.udp_relay_mode {
-                UdpRelayMode::Quic => {
-                    Self::handle_packet(pkt).await;
-                    Ok(())
-                }
-                UdpRelayMode::Native => Err(Error::WrongPacketSource),
-            },
+            // packets are sent back in the mode of the latest packet of the association, which may differ from `udp_relay_mode`
+            Ok(Task::Packet(pkt)) => {
+                Self::handle_packet(pkt).await;
+                Ok(())
+            }
+            Ok(Task::Unknown(unknown)) => {
+                Self::handle_unknown(unknown);
+                Ok(())
+            }
             _ => unreachable!(), // already filtered in `tuic_quinn`
         };
 
@@ -67,7 +69,26 @@ impl Connection {
         log::debug!("[relay] incoming bidirectional stream");
 
         let res = match self.model.accept_bi_stream(send, recv).await {
-            Err(err) => Err::<(), _>(Error::Model(err)),
+            Err(err) => Err(Error::Model(err)),
+            Ok(Task::Inbound(inbound)) => {
+                let bind = SOCKS5_BINDS.lock().remove(&inbound.bind_id());
+
+                // the other connections accepted on the listener of a socks5 bind are left to the reverse forwards, which reset them
+                match bind {
+                    Some(tx) => {
+                        if let Err(mut inbound) = tx.send(inbound) {
+                            let _ = inbound.reset(ERROR_CODE);
+                        }
+                    }
+                    None => ReverseForward::handle_inbound(inbound).await,
+                }
+
+                Ok(())
+            }
+            Ok(Task::Unknown(unknown)) => {
+                Self::handle_unknown(unknown);
+                Ok(())
+            }
             _ => unreachable!(), // already filtered in `tuic_quinn`
         };
 
@@ -81,13 +102,14 @@ impl Connection {
 
         let res = match self.model.accept_datagram(dg) {
             Err(err) => Err(Error::Model(err)),
-            Ok(Task::Packet(pkt)) => match self.udp_relay_mode {
-                UdpRelayMode::Native => {
-                    Self::handle_packet(pkt).await;
-                    Ok(())
-                }
-                UdpRelayMode::Quic => Err(Error::WrongPacketSource),
-            },
+            Ok(Task::Packet(pkt)) => {
+                Self::handle_packet(pkt).await;
+                Ok(())
+            }
+            Ok(Task::Unknown(unknown)) => {
+                Self::handle_unknown(unknown);
+                Ok(())
+            }
             _ => unreachable!(), // already filtered in `tuic_quinn`
         };
 
@@ -95,4 +117,14 @@ impl Connection {
             log::warn!("[relay] incoming datagram error: {err}");
         }
     }
+
+    /// Ignores a command of a type unknown to the client, e.g. from a newer server, keeping the connection
+    fn handle_unknown(unknown: Unknown) {
+        log::info!(
+            "[relay] [unknown] command {type_code:#04x} not supported, ignoring",
+            type_code = unknown.type_code(),
+        );
+
+        unknown.ignore(ERROR_CODE);
+    }
 }
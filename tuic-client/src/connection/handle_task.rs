@@ -1,12 +1,27 @@
-use super::Connection;
-use crate::{error::Error, socks5::UDP_SESSIONS as SOCKS5_UDP_SESSIONS, utils::UdpRelayMode};
+use super::{udp_fallback, udp_pacing, udp_stun, Connection};
+use crate::{
+    dns::Server as DnsServer,
+    error::Error,
+    forward::UDP_SESSIONS as FORWARD_UDP_SESSIONS,
+    router::{Resolve, Rule},
+    socks5::UDP_SESSIONS as SOCKS5_UDP_SESSIONS,
+    utils::UdpRelayMode,
+};
 use bytes::Bytes;
 use quinn::ZeroRttAccepted;
 use socks5_proto::Address as Socks5Address;
-use std::time::Duration;
-use tokio::time;
+use std::{
+    io::{Error as IoError, ErrorKind},
+    sync::atomic::Ordering,
+};
+use tokio::net;
 use tuic::Address;
-use tuic_quinn::{Connect, Packet};
+use tuic_quinn::{Binding, Connect, Packet};
+
+#[cfg(feature = "resolver")]
+use crate::resolver::UDP_SESSIONS as RESOLVER_UDP_SESSIONS;
+#[cfg(unix)]
+use crate::tun::UDP_SESSIONS as TUN_UDP_SESSIONS;
 
 impl Connection {
     pub async fn authenticate(self, zero_rtt_accepted: Option<ZeroRttAccepted>) {
@@ -17,22 +32,80 @@ impl Connection {
 
         log::debug!("[relay] [authenticate] sending authentication");
 
-        match self
-            .model
-            .authenticate(self.uuid, self.password.clone())
-            .await
-        {
+        let res = match self.bandwidth {
+            _ if self.fec => {
+                self.model
+                    .authenticate_with_fec(self.uuid, self.password.clone(), self.bandwidth)
+                    .await
+            }
+            Some(bandwidth) => {
+                self.model
+                    .authenticate_with_bandwidth(self.uuid, self.password.clone(), bandwidth)
+                    .await
+            }
+            None => {
+                self.model
+                    .authenticate(self.uuid, self.password.clone())
+                    .await
+            }
+        };
+
+        match res {
             Ok(()) => log::info!("[relay] [authenticate] {uuid}", uuid = self.uuid),
             Err(err) => log::warn!("[relay] [authenticate] authentication sending error: {err}"),
         }
     }
 
-    pub async fn connect(&self, addr: Address) -> Result<Connect, Error> {
+    /// Resolves a domain target locally if the matched rule asks for it, so that the server receives an IP address instead of the domain
+    pub async fn resolve_target(addr: Address, rule: Option<&Rule>) -> Result<Address, Error> {
+        let Address::DomainAddress(domain, port) = &addr else {
+            return Ok(addr);
+        };
+
+        if rule.and_then(|rule| rule.resolve) != Some(Resolve::Local) {
+            return Ok(addr);
+        }
+
+        match net::lookup_host((domain.as_str(), *port)).await?.next() {
+            Some(resolved) => {
+                log::debug!("[relay] [resolve] {addr} -> {resolved}");
+                Ok(Address::SocketAddress(resolved))
+            }
+            None => Err(Error::Io(IoError::new(
+                ErrorKind::NotFound,
+                format!("no address resolved for {domain}"),
+            ))),
+        }
+    }
+
+    /// Opens a TCP relay, with the congestion hint and the stream priority of the matched rule
+    ///
+    /// The congestion hint is sent only if set, as servers not supporting it may close the connection. Domain targets are resolved locally first if the rule says so.
+    pub async fn connect(&self, addr: Address, rule: Option<&Rule>) -> Result<Connect, Error> {
+        let addr = Self::resolve_target(addr, rule).await?;
         let addr_display = addr.to_string();
-        log::info!("[relay] [connect] {addr_display}");
+        let hint = rule.and_then(|rule| rule.hint);
+        let priority = rule.and_then(|rule| rule.priority);
+
+        let res = match hint {
+            Some(hint) => {
+                log::info!("[relay] [connect] {addr_display} ({hint})");
+                self.model.connect_with_hint(addr, hint).await
+            }
+            None => {
+                log::info!("[relay] [connect] {addr_display}");
+                self.model.connect(addr).await
+            }
+        };
 
-        match self.model.connect(addr).await {
-            Ok(conn) => Ok(conn),
+        match res {
+            Ok(conn) => {
+                if let Some(priority) = priority {
+                    let _ = conn.set_priority(priority);
+                }
+
+                Ok(conn)
+            }
             Err(err) => {
                 log::warn!("[relay] [connect] failed initializing relay to {addr_display}: {err}");
                 Err(Error::Model(err))
@@ -40,13 +113,72 @@ impl Connection {
         }
     }
 
-    pub async fn packet(&self, pkt: Bytes, addr: Address, assoc_id: u16) -> Result<(), Error> {
+    /// Relays a UDP packet. `udp_relay_mode` overrides the one of the relay server if set
+    ///
+    /// Without the override, associations relayed in mode `native` may fall back to mode `quic` if `udp_stream_fallback` is set.
+    pub async fn packet(
+        &self,
+        pkt: Bytes,
+        addr: Address,
+        assoc_id: u16,
+        udp_relay_mode: Option<UdpRelayMode>,
+    ) -> Result<(), Error> {
         let addr_display = addr.to_string();
 
-        match self.udp_relay_mode {
+        // STUN traffic stays in mode `native` for hole punching, if datagrams are supported
+        let stun_native = self
+            .udp_stun
+            .filter(|cfg| udp_stun::detect(assoc_id, &pkt, cfg))
+            .map_or(false, |cfg| {
+                cfg.native && self.model.max_datagram_size().is_some()
+            });
+
+        let mode = match (
+            udp_relay_mode,
+            self.udp_relay_mode,
+            &self.udp_stream_fallback,
+        ) {
+            _ if stun_native => UdpRelayMode::Native,
+            (Some(mode), _, _) => mode,
+            (None, UdpRelayMode::Native, Some(fallback)) => udp_fallback::select(
+                assoc_id,
+                fallback,
+                self.model.max_datagram_size().is_some(),
+                self.loss.rate(&self.conn),
+            ),
+            (None, mode, _) => mode,
+        };
+
+        match mode {
             UdpRelayMode::Native => {
                 log::info!("[relay] [packet] [{assoc_id:#06x}] [to-native] to {addr_display}");
-                match self.model.packet_native(pkt, addr, assoc_id) {
+                self.track_datagram_size();
+
+                let mut max_pkt_size = self.max_datagram_size.unwrap_or(usize::MAX);
+
+                if let (Some(sizer), Some(size)) =
+                    (&self.fragment_sizer, self.model.max_datagram_size())
+                {
+                    max_pkt_size = sizer.size(
+                        max_pkt_size.min(size),
+                        pkt.len(),
+                        self.loss.rate(&self.conn),
+                    );
+                }
+
+                if let Some(pacing) = &self.udp_native_pacing {
+                    udp_pacing::pace(assoc_id, pacing, pkt.len()).await;
+                }
+
+                let res = if self.fec {
+                    self.model
+                        .packet_native_with_parity(pkt, addr, assoc_id, max_pkt_size)
+                } else {
+                    self.model
+                        .packet_native_with_max_size(pkt, addr, assoc_id, max_pkt_size)
+                };
+
+                match res {
                     Ok(()) => Ok(()),
                     Err(err) => {
                         log::warn!("[relay] [packet] [{assoc_id:#06x}] [to-native] to {addr_display}: {err}");
@@ -69,8 +201,25 @@ impl Connection {
         }
     }
 
+    /// Logs changes of the maximum datagram size, which follows the path MTU discovered
+    fn track_datagram_size(&self) {
+        let Some(size) = self.model.max_datagram_size() else {
+            return;
+        };
+
+        let last_size = self.last_datagram_size.swap(size, Ordering::Relaxed);
+
+        if last_size != 0 && last_size != size {
+            log::info!("[relay] maximum datagram size changed from {last_size} to {size} bytes");
+        }
+    }
+
     pub async fn dissociate(&self, assoc_id: u16) -> Result<(), Error> {
         log::info!("[relay] [dissociate] [{assoc_id:#06x}]");
+        udp_fallback::remove(assoc_id);
+        udp_pacing::remove(assoc_id);
+        udp_stun::remove(assoc_id);
+
         match self.model.dissociate(assoc_id).await {
             Ok(()) => Ok(()),
             Err(err) => {
@@ -80,21 +229,51 @@ impl Connection {
         }
     }
 
-    pub async fn heartbeat(self, heartbeat: Duration) {
-        loop {
-            time::sleep(heartbeat).await;
+    pub async fn dissociate_all(&self) -> Result<(), Error> {
+        match self.model.dissociate_all().await {
+            Ok(assoc_ids) => {
+                for assoc_id in &assoc_ids {
+                    udp_fallback::remove(*assoc_id);
+                    udp_pacing::remove(*assoc_id);
+                    udp_stun::remove(*assoc_id);
+                }
 
-            if self.is_closed() {
-                break;
+                if !assoc_ids.is_empty() {
+                    log::info!(
+                        "[relay] [dissociate] {cnt} UDP sessions",
+                        cnt = assoc_ids.len()
+                    );
+                }
+
+                Ok(())
+            }
+            Err(err) => {
+                log::warn!("[relay] [dissociate] {err}");
+                Err(Error::Model(err))
             }
+        }
+    }
 
-            if self.model.task_connect_count() + self.model.task_associate_count() == 0 {
-                continue;
+    pub async fn bind(&self, bind_id: u16, addr: Address) -> Result<Binding, Error> {
+        log::info!("[relay] [bind] [{bind_id:#06x}] {addr}");
+
+        match self.model.bind(bind_id, addr).await {
+            Ok(binding) => Ok(binding),
+            Err(err) => {
+                log::warn!("[relay] [bind] [{bind_id:#06x}] {err}");
+                Err(Error::Model(err))
             }
+        }
+    }
+
+    pub async fn echo(&self, payload: Vec<u8>) -> Result<(), Error> {
+        log::debug!("[relay] [echo] {len} bytes", len = payload.len());
 
-            match self.model.heartbeat().await {
-                Ok(()) => log::debug!("[relay] [heartbeat]"),
-                Err(err) => log::warn!("[relay] [heartbeat] {err}"),
+        match self.model.echo(payload).await {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                log::warn!("[relay] [echo] {err}");
+                Err(Error::Model(err))
             }
         }
     }
@@ -121,6 +300,41 @@ impl Connection {
             Ok(Some((pkt, addr, _))) => {
                 log::info!("[relay] [packet] [{assoc_id:#06x}] [from-{mode}] [{pkt_id:#06x}] from {addr}");
 
+                if DnsServer::assoc_id() == Some(assoc_id) {
+                    DnsServer::handle_response(pkt, addr).await;
+                    return;
+                }
+
+                if let Some(tx) = FORWARD_UDP_SESSIONS.lock().get(&assoc_id) {
+                    // UDP sessions of local forwards have a fixed target, so the source address is dropped
+                    if tx.try_send(pkt).is_err() {
+                        log::debug!("[relay] [packet] [{assoc_id:#06x}] [from-{mode}] [{pkt_id:#06x}] dropped packet to forward session");
+                    }
+
+                    return;
+                }
+
+                #[cfg(unix)]
+                if let Some(tx) = TUN_UDP_SESSIONS.lock().get(&assoc_id) {
+                    // UDP flows of the TUN device are connected to a single target, so the source address is dropped
+                    if tx.try_send(pkt).is_err() {
+                        log::debug!("[relay] [packet] [{assoc_id:#06x}] [from-{mode}] [{pkt_id:#06x}] dropped packet to TUN flow");
+                    }
+
+                    return;
+                }
+
+                #[cfg(feature = "resolver")]
+                if let Some(tx) = RESOLVER_UDP_SESSIONS.lock().get(&assoc_id) {
+                    // responses are matched to the name servers queried by their source addresses
+                    match addr {
+                        Address::SocketAddress(addr) if tx.try_send((pkt, addr)).is_ok() => {}
+                        _ => log::debug!("[relay] [packet] [{assoc_id:#06x}] [from-{mode}] [{pkt_id:#06x}] dropped packet to resolver"),
+                    }
+
+                    return;
+                }
+
                 let addr = match addr {
                     Address::None => unreachable!(),
                     Address::DomainAddress(domain, port) => {
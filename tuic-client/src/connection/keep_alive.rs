@@ -0,0 +1,102 @@
+//! Keeping connections alive while relaying
+//!
+//! Heartbeats are only sent while the connection has TCP relay tasks or UDP associations, leaving idle connections to be closed by the idle timeout of the server. The server's idle timeout is not known in advance, so when a connection times out while relaying, the idle time observed before the timeout is remembered for the server and heartbeats on its connections are sent at least `IDLE_TIMEOUT_DIVISOR` times per idle timeout since then.
+//!
+//! Every heartbeat is acknowledged by the server on the QUIC level, so a server not sending back anything for `UNRESPONSIVE_HEARTBEATS` heartbeat intervals is considered to have stopped responding.
+
+use super::Connection;
+use crossbeam_utils::atomic::AtomicCell;
+use quinn::ConnectionError;
+use std::{sync::Arc, time::Duration};
+use tokio::time::{self, Instant};
+
+const IDLE_TIMEOUT_DIVISOR: u32 = 3;
+const UNRESPONSIVE_HEARTBEATS: u32 = 3;
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The idle timeout observed on the connections to a server
+#[derive(Clone, Default)]
+pub struct IdleTimeout(Arc<AtomicCell<Option<Duration>>>);
+
+impl IdleTimeout {
+    pub fn get(&self) -> Option<Duration> {
+        self.0.load()
+    }
+
+    fn observe(&self, idle: Duration) {
+        let idle = self.get().map_or(idle, |observed| observed.min(idle));
+        self.0.store(Some(idle));
+    }
+
+    /// Returns the heartbeat interval, shortened to fit in the observed idle timeout
+    fn interval(&self, heartbeat: Duration) -> Duration {
+        match self.get() {
+            Some(idle) => heartbeat.min((idle / IDLE_TIMEOUT_DIVISOR).max(MIN_INTERVAL)),
+            None => heartbeat,
+        }
+    }
+}
+
+impl Connection {
+    /// Sends heartbeats while relaying, until the connection is closed
+    ///
+    /// `on_unresponsive` is called with the time since anything was last received when the server stops responding, after which no more heartbeats are sent.
+    pub async fn keep_alive(
+        self,
+        heartbeat: Duration,
+        idle_timeout: IdleTimeout,
+        on_unresponsive: impl FnOnce(&Self, Duration),
+    ) {
+        let mut rx = self.conn.stats().udp_rx.datagrams;
+        let mut rx_at = Instant::now();
+
+        loop {
+            let interval = idle_timeout.interval(heartbeat);
+
+            tokio::select! {
+                () = time::sleep(interval) => {}
+                err = self.conn.closed() => {
+                    if self.is_relaying() && matches!(err, ConnectionError::TimedOut) {
+                        let idle = rx_at.elapsed();
+                        idle_timeout.observe(idle);
+
+                        log::info!(
+                            "[relay] [heartbeat] connection timed out after being idle for {idle:?} while relaying, shortening the heartbeat interval"
+                        );
+                    }
+
+                    break;
+                }
+            }
+
+            let now_rx = self.conn.stats().udp_rx.datagrams;
+
+            if now_rx != rx {
+                rx = now_rx;
+                rx_at = Instant::now();
+            }
+
+            if !self.is_relaying() {
+                // silence while idle is expected
+                rx_at = Instant::now();
+                continue;
+            }
+
+            let silent = rx_at.elapsed();
+
+            if silent >= interval * UNRESPONSIVE_HEARTBEATS {
+                on_unresponsive(&self, silent);
+                break;
+            }
+
+            match self.model.heartbeat().await {
+                Ok(()) => log::debug!("[relay] [heartbeat]"),
+                Err(err) => log::warn!("[relay] [heartbeat] {err}"),
+            }
+        }
+    }
+
+    pub(super) fn is_relaying(&self) -> bool {
+        self.model.task_connect_count() + self.model.task_associate_count() > 0
+    }
+}
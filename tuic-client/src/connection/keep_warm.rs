@@ -0,0 +1,130 @@
+//! Keeping the connections to a server ready while idle
+//!
+//! Connections are otherwise made by the first task needing them and left to the idle timeout of the server, so the first task after a while of idling waits for a handshake. With `keep_warm`, every connection in the pool is established once the server is set up, and checked on every `server_idle_timeout / CHECK_DIVISOR`: a closed one is reconnected, and an idle one is kept from timing out by either:
+//!
+//! - `heartbeat`: sending a heartbeat, as while relaying
+//! - `rehandshake`: replacing it with a new connection once it has not received anything for half of `server_idle_timeout`, closing the old one, for networks dropping long-lived flows regardless of their activity
+//!
+//! Connections relaying tasks are left to the heartbeats in `keep_alive`. The idle timeout observed on the server is used instead of `server_idle_timeout` if shorter.
+
+use super::{Connection, Endpoint, ERROR_CODE};
+use crate::{config::KeepWarm, utils::KeepWarmStrategy};
+use std::{sync::Arc, time::Duration};
+use tokio::time::{self, Instant};
+
+const CHECK_DIVISOR: u32 = 6;
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The activity last observed on the connection in a pool slot
+struct Activity {
+    stable_id: usize,
+    rx: u64,
+    at: Instant,
+}
+
+impl Endpoint {
+    /// Keeps the connections in the pool established, until aborted
+    pub(super) async fn keep_warm(self: Arc<Self>, cfg: KeepWarm) {
+        let mut activities = (0..self.pool.len())
+            .map(|_| None)
+            .collect::<Vec<Option<Activity>>>();
+
+        loop {
+            let idle_timeout = self
+                .idle_timeout
+                .get()
+                .map_or(cfg.server_idle_timeout, |observed| {
+                    observed.min(cfg.server_idle_timeout)
+                });
+
+            let interval = (idle_timeout / CHECK_DIVISOR).max(MIN_INTERVAL);
+            let checked_at = Instant::now();
+
+            for (idx, activity) in activities.iter_mut().enumerate() {
+                // reconnects the slot if its connection is closed
+                let Ok(conn) = self.connection_at(idx).await else {
+                    continue;
+                };
+
+                if conn.is_relaying() {
+                    *activity = None;
+                    continue;
+                }
+
+                match cfg.strategy {
+                    KeepWarmStrategy::Heartbeat => match conn.model.heartbeat().await {
+                        Ok(()) => log::debug!("[relay] [keep-warm] heartbeat"),
+                        Err(err) => log::warn!("[relay] [keep-warm] heartbeat: {err}"),
+                    },
+                    KeepWarmStrategy::Rehandshake => {
+                        let stable_id = conn.conn.stable_id();
+                        let rx = conn.conn.stats().udp_rx.datagrams;
+
+                        // anything received since the previous check may have arrived right after it
+                        let active_at = match activity {
+                            Some(activity) if activity.stable_id == stable_id => {
+                                if activity.rx != rx {
+                                    activity.rx = rx;
+                                    activity.at = checked_at - interval;
+                                }
+
+                                activity.at
+                            }
+                            _ => {
+                                *activity = Some(Activity {
+                                    stable_id,
+                                    rx,
+                                    at: checked_at,
+                                });
+                                continue;
+                            }
+                        };
+
+                        if active_at.elapsed() >= idle_timeout / 2 {
+                            self.rehandshake(idx, conn).await;
+                            *activity = None;
+                        }
+                    }
+                }
+            }
+
+            time::sleep_until(checked_at + interval).await;
+        }
+    }
+
+    /// Replaces the connection in the pool slot with a new one, closing the old one if still idle
+    async fn rehandshake(self: &Arc<Self>, idx: usize, old: Connection) {
+        let conn = match time::timeout(self.timeout, self.connect()).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(err)) => {
+                log::warn!(
+                    "[relay] [keep-warm] failed connecting to server {server}: {err}",
+                    server = self.server,
+                );
+                return;
+            }
+            Err(_) => {
+                log::warn!(
+                    "[relay] [keep-warm] timed out connecting to server {server}",
+                    server = self.server,
+                );
+                return;
+            }
+        };
+
+        {
+            let mut slot = self.pool[idx].lock().await;
+            self.fill_slot(idx, &mut slot, conn).await;
+        }
+
+        log::debug!(
+            "[relay] [keep-warm] replaced the idle connection to server {server}",
+            server = self.server,
+        );
+
+        // a task may have picked the old connection before it was replaced
+        if !old.is_relaying() {
+            old.conn.close(ERROR_CODE, &[]);
+        }
+    }
+}
@@ -1,47 +1,171 @@
+use self::{
+    bridge::WebSocketUdpSocket,
+    keep_alive::IdleTimeout,
+    network::NetworkEvent,
+    obfs::{ObfsRuntime, ObfsUdpSocket},
+    udp_fallback::LossMeter,
+    udp_fragment::FragmentSizer,
+    upstream::Socks5UdpSocket,
+    verifier::{InsecureVerifier, PinnedCertVerifier},
+};
 use crate::{
-    config::Relay,
+    config::{
+        AdaptiveFragmentSize, HealthCheck, KeepWarm, OnDemand, Reconnect, Relay, UdpCompression,
+        UdpNativePacing, UdpStreamFallback, UdpStun,
+    },
     error::Error,
-    utils::{self, CongestionControl, ServerAddr, UdpRelayMode},
+    events::{self, Event},
+    protect, qlog,
+    utils::{
+        self, Balance, CongestionControl, ServerAddr, UdpRelayMode, UpstreamProxy, WebSocketBridge,
+    },
 };
 use crossbeam_utils::atomic::AtomicCell;
-use once_cell::sync::OnceCell;
-use parking_lot::Mutex;
+use futures_util::{future, stream::FuturesUnordered, StreamExt};
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
 use quinn::{
     congestion::{BbrConfig, CubicConfig, NewRenoConfig},
-    ClientConfig, Connection as QuinnConnection, Endpoint as QuinnEndpoint, EndpointConfig,
-    TokioRuntime, TransportConfig, VarInt, ZeroRttAccepted,
+    AsyncUdpSocket, ClientConfig, Connection as QuinnConnection, ConnectionError,
+    Endpoint as QuinnEndpoint, EndpointConfig, Runtime, TokioRuntime, TransportConfig, VarInt,
+    ZeroRttAccepted,
 };
+use quinn_proto::ConnectionStats;
 use register_count::Counter;
-use rustls::{version, ClientConfig as RustlsClientConfig};
+use rustls::{version, ClientConfig as RustlsClientConfig, RootCertStore, ServerName};
 use std::{
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
-    sync::{atomic::AtomicU32, Arc},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fs,
+    future::Future,
+    hash::{Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    path::Path,
+    pin::Pin,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::{
-    sync::{Mutex as AsyncMutex, OnceCell as AsyncOnceCell},
-    time,
+    sync::{broadcast::error::RecvError, Mutex as AsyncMutex},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tuic::{
+    model::{AssemblyCounts, TaskEvent, TaskMetrics},
+    Address, Bandwidth,
 };
-use tuic_quinn::{side, Connection as Model};
+use tuic_quinn::{congestion::BrutalConfig, side, CloseCode, Connection as Model};
 use uuid::Uuid;
 
+mod bridge;
 mod handle_stream;
 mod handle_task;
+mod keep_alive;
+mod keep_warm;
+mod network;
+mod obfs;
+mod on_demand;
+mod udp_fallback;
+mod udp_fragment;
+mod udp_pacing;
+mod udp_stun;
+mod upstream;
+mod verifier;
+
+pub use self::{
+    udp_fallback::associations as udp_associations, udp_stun::timeout as udp_stun_timeout,
+};
 
-static ENDPOINT: OnceCell<Mutex<Endpoint>> = OnceCell::new();
-static CONNECTION: AsyncOnceCell<AsyncMutex<Connection>> = AsyncOnceCell::const_new();
-static TIMEOUT: AtomicCell<Duration> = AtomicCell::new(Duration::from_secs(0));
+static ENDPOINTS: RwLock<Vec<Arc<Endpoint>>> = RwLock::new(Vec::new());
+/// The health checks, network watchers and keep-warm tasks of the endpoints
+static BACKGROUND_TASKS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+static ACTIVE_ENDPOINT: AtomicUsize = AtomicUsize::new(0);
+static BALANCE: AtomicCell<Balance> = AtomicCell::new(Balance::Failover);
+static ROUND_ROBIN: AtomicUsize = AtomicUsize::new(0);
+static ASSOCIATIONS: Lazy<Mutex<HashMap<u16, Arc<Endpoint>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_ASSOC_ID: AtomicU16 = AtomicU16::new(0);
+/// The IDs of long-lived associations, which are never handed out to new ones
+static RESERVED_ASSOC_IDS: Lazy<Mutex<HashSet<u16>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+static NEXT_BIND_ID: AtomicU16 = AtomicU16::new(0);
 
 pub const ERROR_CODE: VarInt = VarInt::from_u32(0);
-const DEFAULT_CONCURRENT_STREAMS: u32 = 32;
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const NETWORK_SETTLE_DELAY: Duration = Duration::from_millis(500);
+const VALIDATE_TIMEOUT: Duration = Duration::from_secs(3);
+const VALIDATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The status of a relay server
+pub struct ServerStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub rtt: Option<Duration>,
+    pub current_rtt: Option<Duration>,
+    pub active: bool,
+    pub migrations: u64,
+    /// The task counters of each established connection in the pool, with the logged task events
+    pub tasks: Vec<(TaskMetrics, Vec<TaskEvent>)>,
+    /// The reassembly counters of received packets of each established connection in the pool, in total and by UDP session
+    pub assembly: Vec<(AssemblyCounts, Vec<(u16, AssemblyCounts)>)>,
+    /// The statistics of each established connection in the pool, by the stable ID of the connection
+    pub paths: Vec<(usize, ConnectionStats)>,
+}
+
+/// Allocates an ID for a new UDP association
+///
+/// As the IDs wrap around, reserved IDs and IDs of associations still being relayed are skipped.
+pub fn next_assoc_id() -> u16 {
+    for _ in 0..=u16::MAX {
+        let assoc_id = NEXT_ASSOC_ID.fetch_add(1, Ordering::Relaxed);
+
+        if !RESERVED_ASSOC_IDS.lock().contains(&assoc_id)
+            && !ASSOCIATIONS.lock().contains_key(&assoc_id)
+        {
+            return assoc_id;
+        }
+    }
+
+    // every ID is taken, which leaves no choice but to share one
+    NEXT_ASSOC_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Allocates an ID for a long-lived UDP association, keeping it from being handed out again until released
+pub fn reserve_assoc_id() -> u16 {
+    let assoc_id = next_assoc_id();
+    RESERVED_ASSOC_IDS.lock().insert(assoc_id);
+    assoc_id
+}
+
+/// Releases an ID allocated by [`reserve_assoc_id`]
+pub fn release_assoc_id(assoc_id: u16) {
+    RESERVED_ASSOC_IDS.lock().remove(&assoc_id);
+}
+
+/// Allocates an ID for a new TCP binding
+pub fn next_bind_id() -> u16 {
+    NEXT_BIND_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Clone)]
 pub struct Connection {
     conn: QuinnConnection,
+    server: Arc<str>,
     model: Model<side::Client>,
     uuid: Uuid,
     password: Arc<[u8]>,
+    bandwidth: Option<Bandwidth>,
     udp_relay_mode: UdpRelayMode,
+    udp_stream_fallback: Option<UdpStreamFallback>,
+    udp_native_pacing: Option<UdpNativePacing>,
+    udp_stun: Option<UdpStun>,
+    loss: Arc<LossMeter>,
+    fragment_sizer: Option<Arc<FragmentSizer>>,
+    fec: bool,
+    max_datagram_size: Option<usize>,
+    last_datagram_size: Arc<AtomicUsize>,
     remote_uni_stream_cnt: Counter,
     remote_bi_stream_cnt: Counter,
     max_concurrent_uni_streams: Arc<AtomicU32>,
@@ -49,143 +173,338 @@ pub struct Connection {
 }
 
 impl Connection {
-    pub fn set_config(cfg: Relay) -> Result<(), Error> {
-        let certs = utils::load_certs(cfg.certificates, cfg.disable_native_certs)?;
+    /// Sets up the relay servers, replacing the current ones when reloading the config
+    ///
+    /// Tasks already relayed keep using the connections to the previous servers until they finish, while new tasks go to the new servers. UDP associations move to the new servers with their next packet.
+    pub fn set_config(
+        relays: Vec<Relay>,
+        health_check: HealthCheck,
+        balance: Balance,
+        reconnect: Reconnect,
+    ) -> Result<(), Error> {
+        let mut endpoints = relays
+            .into_iter()
+            .map(|relay| Endpoint::new(relay, &reconnect).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let mut crypto = RustlsClientConfig::builder()
-            .with_safe_default_cipher_suites()
-            .with_safe_default_kx_groups()
-            .with_protocol_versions(&[&version::TLS13])
-            .unwrap()
-            .with_root_certificates(certs)
-            .with_no_client_auth();
+        endpoints.sort_by_key(|ep| ep.priority);
 
-        crypto.alpn_protocols = cfg.alpn;
-        crypto.enable_early_data = true;
-        crypto.enable_sni = !cfg.disable_sni;
+        let mut tasks = if endpoints.len() > 1 {
+            endpoints
+                .iter()
+                .map(|ep| tokio::spawn(ep.clone().health_check(health_check.clone())))
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        let mut config = ClientConfig::new(Arc::new(crypto));
-        let mut tp_cfg = TransportConfig::default();
+        tasks.extend(
+            endpoints
+                .iter()
+                .map(|ep| tokio::spawn(ep.clone().watch_network())),
+        );
 
-        tp_cfg
-            .max_concurrent_bidi_streams(VarInt::from(DEFAULT_CONCURRENT_STREAMS))
-            .max_concurrent_uni_streams(VarInt::from(DEFAULT_CONCURRENT_STREAMS))
-            .send_window(cfg.send_window)
-            .stream_receive_window(VarInt::from_u32(cfg.receive_window))
-            .max_idle_timeout(None);
+        tasks.extend(endpoints.iter().filter_map(|ep| {
+            let keep_warm = ep.keep_warm?;
+            Some(tokio::spawn(ep.clone().keep_warm(keep_warm)))
+        }));
 
-        match cfg.congestion_control {
-            CongestionControl::Cubic => {
-                tp_cfg.congestion_controller_factory(Arc::new(CubicConfig::default()))
-            }
-            CongestionControl::NewReno => {
-                tp_cfg.congestion_controller_factory(Arc::new(NewRenoConfig::default()))
-            }
-            CongestionControl::Bbr => {
-                tp_cfg.congestion_controller_factory(Arc::new(BbrConfig::default()))
-            }
-        };
+        tasks.extend(endpoints.iter().filter_map(|ep| {
+            let on_demand = ep.on_demand?;
+            Some(tokio::spawn(ep.clone().close_idle(on_demand)))
+        }));
 
-        config.transport_config(Arc::new(tp_cfg));
+        *ENDPOINTS.write() = endpoints;
+        ACTIVE_ENDPOINT.store(0, Ordering::Relaxed);
+        BALANCE.store(balance);
 
-        // Try to create an IPv4 socket as the placeholder first, if it fails, try IPv6.
-        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
-            .or_else(|err| {
-                UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))).map_err(|_| err)
-            })
-            .map_err(|err| Error::Socket("failed to create endpoint UDP socket", err))?;
+        for task in std::mem::replace(&mut *BACKGROUND_TASKS.lock(), tasks) {
+            task.abort();
+        }
 
-        let mut ep = QuinnEndpoint::new(
-            EndpointConfig::default(),
-            None,
-            socket,
-            Arc::new(TokioRuntime),
-        )?;
+        Ok(())
+    }
 
-        ep.set_default_client_config(config);
+    /// Stops relaying, closing the connections to all relay servers
+    pub fn stop() {
+        for task in BACKGROUND_TASKS.lock().drain(..) {
+            task.abort();
+        }
 
-        let ep = Endpoint {
-            ep,
-            server: ServerAddr::new(cfg.server.0, cfg.server.1, cfg.ip),
-            uuid: cfg.uuid,
-            password: cfg.password,
-            udp_relay_mode: cfg.udp_relay_mode,
-            zero_rtt_handshake: cfg.zero_rtt_handshake,
-            heartbeat: cfg.heartbeat,
-            gc_interval: cfg.gc_interval,
-            gc_lifetime: cfg.gc_lifetime,
-        };
+        ASSOCIATIONS.lock().clear();
+
+        for ep in std::mem::take(&mut *ENDPOINTS.write()) {
+            ep.close();
+        }
+    }
+
+    /// Dissociates the UDP sessions on all connections to the relay servers, so the servers close their relay sockets in one pass instead of leaving them to the garbage collection
+    pub async fn teardown_udp_sessions() {
+        future::join_all(Self::endpoints().iter().map(|ep| ep.dissociate_all())).await;
+    }
 
-        ENDPOINT
-            .set(Mutex::new(ep))
-            .map_err(|_| "endpoint already initialized")
-            .unwrap();
+    fn endpoints() -> Vec<Arc<Endpoint>> {
+        ENDPOINTS.read().clone()
+    }
+
+    /// Returns the status of all relay servers, in the order of priority
+    pub fn servers() -> Vec<ServerStatus> {
+        let active = ACTIVE_ENDPOINT.load(Ordering::Relaxed);
+
+        Self::endpoints()
+            .iter()
+            .enumerate()
+            .map(|(idx, ep)| ServerStatus {
+                name: ep.server.to_string(),
+                healthy: ep.healthy.load(Ordering::Relaxed),
+                rtt: ep.rtt.load(),
+                current_rtt: ep.current_rtt(),
+                active: idx == active,
+                migrations: ep.migrations.load(Ordering::Relaxed),
+                tasks: ep.task_metrics(),
+                assembly: ep.assembly_counts(),
+                paths: ep.path_stats(),
+            })
+            .collect()
+    }
 
-        TIMEOUT.store(cfg.timeout);
+    /// Migrates the connections to all relay servers onto new UDP sockets, for the app to call when notified of a network change
+    ///
+    /// The connections are validated afterwards, as on the network changes noticed by the client itself.
+    pub fn rebind() -> Result<(), Error> {
+        for ep in Self::endpoints() {
+            ep.rebind()?;
+        }
 
+        network::notify(NetworkEvent::Changed);
         Ok(())
     }
 
-    pub async fn get() -> Result<Connection, Error> {
-        let try_init_conn = async {
-            ENDPOINT
-                .get()
-                .unwrap()
-                .lock()
-                .connect()
-                .await
-                .map(AsyncMutex::new)
-        };
+    pub fn balance() -> Balance {
+        BALANCE.load()
+    }
 
-        let try_get_conn = async {
-            let mut conn = CONNECTION
-                .get_or_try_init(|| try_init_conn)
-                .await?
-                .lock()
-                .await;
+    /// Whether the target is one of the relay servers, by its domain, configured IP address or the address currently connected to
+    pub fn is_server(addr: &Address) -> bool {
+        Self::endpoints().iter().any(|ep| {
+            ep.server.matches(addr)
+                || matches!(
+                    (addr, ep.remote_addr()),
+                    (Address::SocketAddress(addr), Some(remote)) if addr.ip() == remote.ip()
+                )
+        })
+    }
+
+    /// Returns a connection to the relay server with the given name, bypassing the balancing
+    pub async fn get_for_server(name: &str) -> Option<Result<Connection, Error>> {
+        let ep = Self::endpoints()
+            .into_iter()
+            .find(|ep| ep.server.to_string() == name)?;
 
-            if conn.is_closed() {
-                let new_conn = ENDPOINT.get().unwrap().lock().connect().await?;
-                *conn = new_conn;
+        Some(ep.connection_at(0).await)
+    }
+
+    /// Returns a connection for relaying a TCP stream to the target address
+    pub async fn get_for_connect(addr: &Address) -> Result<Connection, Error> {
+        let (_, conn) = Self::select(&TaskKey::Connect(addr)).await?;
+        Ok(conn)
+    }
+
+    /// Returns a connection for relaying UDP packets of the association
+    ///
+    /// An association sticks to the server it was first relayed through, until that server becomes unhealthy. Associations carrying STUN traffic stick to it regardless, as moving changes their external address.
+    pub async fn get_for_packet(assoc_id: u16) -> Result<Connection, Error> {
+        let endpoints = Self::endpoints();
+        let pinned = ASSOCIATIONS.lock().get(&assoc_id).cloned();
+
+        // an association pinned to a server removed by reloading the config moves to another one
+        if let Some(ep) = pinned.filter(|ep| endpoints.iter().any(|cur| Arc::ptr_eq(cur, ep))) {
+            if udp_stun::timeout(assoc_id).is_some() {
+                return ep.connection(&TaskKey::Associate(assoc_id)).await;
             }
 
-            Ok::<_, Error>(conn.clone())
+            if ep.healthy.load(Ordering::Relaxed) {
+                match ep.connection(&TaskKey::Associate(assoc_id)).await {
+                    Ok(conn) => return Ok(conn),
+                    Err(_) if endpoints.len() > 1 => ep.set_healthy(false),
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        let (ep, conn) = Self::select(&TaskKey::Associate(assoc_id)).await?;
+        ASSOCIATIONS.lock().insert(assoc_id, ep);
+        Ok(conn)
+    }
+
+    /// Returns the connection the association was relayed through, if any
+    pub async fn get_for_dissociate(assoc_id: u16) -> Result<Option<Connection>, Error> {
+        let Some(ep) = ASSOCIATIONS.lock().remove(&assoc_id) else {
+            return Ok(None);
         };
 
-        let conn = time::timeout(TIMEOUT.load(), try_get_conn)
-            .await
-            .map_err(|_| Error::Timeout)??;
+        ep.connection(&TaskKey::Associate(assoc_id)).await.map(Some)
+    }
 
-        Ok(conn)
+    async fn select(key: &TaskKey<'_>) -> Result<(Arc<Endpoint>, Connection), Error> {
+        let endpoints = Self::endpoints();
+        let mut last_err = None;
+
+        let order = Self::order(&endpoints, Self::hash_key(key));
+
+        for &idx in &order {
+            let ep = &endpoints[idx];
+
+            if !ep.healthy.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            match ep.connection(key).await {
+                Ok(conn) => {
+                    let prev = ACTIVE_ENDPOINT.swap(idx, Ordering::Relaxed);
+
+                    if prev != idx && matches!(BALANCE.load(), Balance::Failover) {
+                        log::warn!("[relay] switched to server {server}", server = ep.server,);
+
+                        events::emit(Event::ServerSwitched {
+                            from: endpoints
+                                .get(prev)
+                                .map_or_else(String::new, |prev| prev.server.to_string()),
+                            to: ep.server.to_string(),
+                        });
+                    }
+
+                    return Ok((ep.clone(), conn));
+                }
+                Err(err) => {
+                    if endpoints.len() > 1 {
+                        ep.set_healthy(false);
+                    }
+
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        // no server is considered healthy, fall back to the most preferred one
+        match last_err {
+            Some(err) => Err(err),
+            None => {
+                let ep = &endpoints[order[0]];
+                Ok((ep.clone(), ep.connection(key).await?))
+            }
+        }
+    }
+
+    /// Returns the indexes of all endpoints, ordered by health, priority, and then the balancing strategy
+    fn order(endpoints: &[Arc<Endpoint>], key: u64) -> Vec<usize> {
+        let balance = BALANCE.load();
+        let round = ROUND_ROBIN.fetch_add(1, Ordering::Relaxed);
+
+        let mut scored = endpoints
+            .iter()
+            .enumerate()
+            .map(|(idx, ep)| {
+                let score = match balance {
+                    Balance::Failover => idx as f64,
+                    Balance::RoundRobin => {
+                        ((idx + endpoints.len() - round % endpoints.len()) % endpoints.len()) as f64
+                    }
+                    Balance::LeastRtt => {
+                        ep.rtt.load().map_or(f64::INFINITY, |rtt| rtt.as_secs_f64())
+                    }
+                    Balance::ConsistentHash => {
+                        // rendezvous hashing, so that only the targets of a removed server are remapped
+                        let mut hasher = DefaultHasher::new();
+                        key.hash(&mut hasher);
+                        ep.server.to_string().hash(&mut hasher);
+                        -(hasher.finish() as f64)
+                    }
+                    Balance::WeightedRandom => {
+                        // exponential variates, which gives a weighted random permutation
+                        let weight = f64::from(ep.weight.max(1));
+                        -(1.0 - rand::random::<f64>()).ln() / weight
+                    }
+                };
+
+                (idx, !ep.healthy.load(Ordering::Relaxed), ep.priority, score)
+            })
+            .collect::<Vec<_>>();
+
+        scored.sort_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2)).then(a.3.total_cmp(&b.3)));
+
+        scored.into_iter().map(|(idx, _, _, _)| idx).collect()
+    }
+
+    fn hash_key(key: &TaskKey) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        match key {
+            TaskKey::Connect(Address::DomainAddress(domain, _)) => domain.hash(&mut hasher),
+            TaskKey::Connect(Address::SocketAddress(addr)) => addr.ip().hash(&mut hasher),
+            TaskKey::Connect(Address::None) => {}
+            TaskKey::Associate(assoc_id) => assoc_id.hash(&mut hasher),
+        }
+
+        hasher.finish()
     }
 
     #[allow(clippy::too_many_arguments)]
     fn new(
         conn: QuinnConnection,
+        server: Arc<str>,
         zero_rtt_accepted: Option<ZeroRttAccepted>,
         udp_relay_mode: UdpRelayMode,
+        udp_stream_fallback: Option<UdpStreamFallback>,
+        udp_native_pacing: Option<UdpNativePacing>,
+        udp_stun: Option<UdpStun>,
+        adaptive_fragment_size: Option<AdaptiveFragmentSize>,
+        fec: bool,
+        udp_compression: Option<UdpCompression>,
+        max_datagram_size: Option<usize>,
+        max_concurrent_streams: u32,
         uuid: Uuid,
         password: Arc<[u8]>,
+        bandwidth: Option<Bandwidth>,
         heartbeat: Duration,
+        idle_timeout: IdleTimeout,
         gc_interval: Duration,
         gc_lifetime: Duration,
     ) -> Self {
+        let model = match udp_compression {
+            Some(cfg) => Model::<side::Client>::new(conn.clone()).with_compression(cfg.min_size),
+            None => Model::<side::Client>::new(conn.clone()),
+        };
+
         let conn = Self {
-            conn: conn.clone(),
-            model: Model::<side::Client>::new(conn),
+            conn,
+            server,
+            model,
             uuid,
             password,
+            bandwidth,
             udp_relay_mode,
+            udp_stream_fallback,
+            udp_native_pacing,
+            udp_stun,
+            loss: Arc::new(LossMeter::new()),
+            fragment_sizer: adaptive_fragment_size.map(|cfg| Arc::new(FragmentSizer::new(cfg))),
+            fec,
+            max_datagram_size,
+            last_datagram_size: Arc::new(AtomicUsize::new(0)),
             remote_uni_stream_cnt: Counter::new(),
             remote_bi_stream_cnt: Counter::new(),
-            max_concurrent_uni_streams: Arc::new(AtomicU32::new(DEFAULT_CONCURRENT_STREAMS)),
-            max_concurrent_bi_streams: Arc::new(AtomicU32::new(DEFAULT_CONCURRENT_STREAMS)),
+            max_concurrent_uni_streams: Arc::new(AtomicU32::new(max_concurrent_streams)),
+            max_concurrent_bi_streams: Arc::new(AtomicU32::new(max_concurrent_streams)),
         };
 
-        tokio::spawn(
-            conn.clone()
-                .init(zero_rtt_accepted, heartbeat, gc_interval, gc_lifetime),
-        );
+        tokio::spawn(conn.clone().init(
+            zero_rtt_accepted,
+            heartbeat,
+            idle_timeout,
+            gc_interval,
+            gc_lifetime,
+        ));
 
         conn
     }
@@ -194,13 +513,29 @@ impl Connection {
         self,
         zero_rtt_accepted: Option<ZeroRttAccepted>,
         heartbeat: Duration,
+        idle_timeout: IdleTimeout,
         gc_interval: Duration,
         gc_lifetime: Duration,
     ) {
         log::info!("[relay] connection established");
 
+        events::emit(Event::Connected {
+            server: self.server.to_string(),
+        });
+
         tokio::spawn(self.clone().authenticate(zero_rtt_accepted));
-        tokio::spawn(self.clone().heartbeat(heartbeat));
+        tokio::spawn(self.clone().keep_alive(
+            heartbeat,
+            idle_timeout,
+            |conn, silent| {
+                log::warn!(
+                    "[relay] [heartbeat] server {server} stopped responding for {silent:?}, closing the connection",
+                    server = conn.server,
+                );
+
+                conn.conn.close(ERROR_CODE, b"server unresponsive");
+            },
+        ));
         tokio::spawn(self.clone().collect_garbage(gc_interval, gc_lifetime));
 
         let err = loop {
@@ -220,7 +555,54 @@ impl Connection {
             };
         };
 
-        log::warn!("[relay] connection error: {err}");
+        let close_code = self
+            .conn
+            .close_reason()
+            .and_then(|err| CloseCode::from_connection_error(&err));
+
+        match close_code {
+            Some(CloseCode::AuthFailed) => {
+                log::error!(
+                    "[relay] server {server} rejected the authentication, check `uuid` and `password`",
+                    server = self.server,
+                );
+
+                events::emit(Event::AuthFailed {
+                    server: self.server.to_string(),
+                });
+            }
+            Some(CloseCode::AuthRevoked) => log::error!(
+                "[relay] server {server} revoked the authentication of the user",
+                server = self.server,
+            ),
+            Some(CloseCode::QuotaExceeded) => log::error!(
+                "[relay] the user exceeded its quota on server {server}",
+                server = self.server,
+            ),
+            Some(CloseCode::ShuttingDown) => log::warn!(
+                "[relay] server {server} is shutting down",
+                server = self.server,
+            ),
+            // closed by the client itself, e.g. when replaced by `keep_warm` or idle with `on_demand`
+            _ if matches!(
+                self.conn.close_reason(),
+                Some(ConnectionError::LocallyClosed)
+            ) =>
+            {
+                log::debug!("[relay] connection closed")
+            }
+            _ => log::warn!("[relay] connection error: {err}"),
+        }
+    }
+
+    /// Returns the name of the relay server the connection is established to
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Returns the address the relay server is reached at
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.conn.remote_address()
     }
 
     fn is_closed(&self) -> bool {
@@ -241,75 +623,971 @@ impl Connection {
     }
 }
 
+/// The key for selecting the server and the pooled connection of a task
+enum TaskKey<'a> {
+    Connect(&'a Address),
+    Associate(u16),
+}
+
 struct Endpoint {
-    ep: QuinnEndpoint,
+    ep_v4: Option<QuinnEndpoint>,
+    ep_v6: Option<QuinnEndpoint>,
+    proxy: Option<(Upstream, ClientConfig)>,
+    obfs_password: Option<Arc<[u8]>>,
+    runtime: Arc<dyn Runtime>,
+    ep_cfg: EndpointConfig,
     server: ServerAddr,
+    priority: u32,
+    weight: u32,
     uuid: Uuid,
     password: Arc<[u8]>,
+    bandwidth: Option<Bandwidth>,
     udp_relay_mode: UdpRelayMode,
+    udp_stream_fallback: Option<UdpStreamFallback>,
+    udp_native_pacing: Option<UdpNativePacing>,
+    udp_stun: Option<UdpStun>,
+    udp_session_resumption: bool,
+    adaptive_fragment_size: Option<AdaptiveFragmentSize>,
+    fec: bool,
+    udp_compression: Option<UdpCompression>,
+    max_datagram_size: Option<usize>,
+    max_concurrent_streams: u32,
     zero_rtt_handshake: bool,
+    happy_eyeballs_delay: Duration,
+    timeout: Duration,
     heartbeat: Duration,
+    idle_timeout: IdleTimeout,
     gc_interval: Duration,
     gc_lifetime: Duration,
+    task_log: usize,
+    qlog_dir: Option<Arc<Path>>,
+    rebind_on_network_change: bool,
+    keep_warm: Option<KeepWarm>,
+    on_demand: Option<OnDemand>,
+    migrations: AtomicU64,
+    pool: Vec<AsyncMutex<PoolSlot>>,
+    next_conn: AtomicUsize,
+    healthy: AtomicBool,
+    rtt: AtomicCell<Option<Duration>>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_pending: usize,
+    pending: AtomicUsize,
+}
+
+/// A task waiting for a pool slot to reconnect, counted in the pending tasks of the endpoint until dropped
+struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl<'a> PendingGuard<'a> {
+    fn acquire(pending: &'a AtomicUsize, max_pending: usize) -> Result<Self, Error> {
+        if pending.fetch_add(1, Ordering::Relaxed) >= max_pending {
+            pending.fetch_sub(1, Ordering::Relaxed);
+            return Err(Error::TooManyPendingTasks);
+        }
+
+        Ok(Self(pending))
+    }
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How an endpoint reaches the server when not sending UDP to it directly
+enum Upstream {
+    Socks5(UpstreamProxy),
+    WebSocket(WebSocketBridge),
+}
+
+#[derive(Default)]
+struct PoolSlot {
+    conn: Option<Connection>,
+    retries: u32,
+    retry_at: Option<Instant>,
+    resume_token: Option<[u8; 16]>,
 }
 
 impl Endpoint {
-    async fn connect(&mut self) -> Result<Connection, Error> {
-        let mut last_err = None;
+    fn new(cfg: Relay, reconnect: &Reconnect) -> Result<Self, Error> {
+        if cfg.insecure && !cfg.certificate_fingerprints.is_empty() {
+            return Err(Error::InvalidTls(
+                "`insecure` cannot be used together with `certificate_fingerprints`",
+            ));
+        }
+
+        if cfg.keep_warm.is_some() && cfg.on_demand.is_some() {
+            return Err(Error::InvalidTransport(
+                "`on_demand` cannot be used together with `keep_warm`",
+            ));
+        }
+
+        if let Some(sni) = &cfg.sni {
+            if cfg.disable_sni {
+                return Err(Error::InvalidTls(
+                    "`sni` cannot be set when `disable_sni` is enabled",
+                ));
+            }
+
+            if ServerName::try_from(sni.as_str()).is_err() {
+                return Err(Error::InvalidTls("`sni` is not a valid server name"));
+            }
+        }
 
-        for addr in self.server.resolve().await? {
-            let connect_to = async {
-                let match_ipv4 =
-                    addr.is_ipv4() && self.ep.local_addr().map_or(false, |addr| addr.is_ipv4());
-                let match_ipv6 =
-                    addr.is_ipv6() && self.ep.local_addr().map_or(false, |addr| addr.is_ipv6());
-
-                if !match_ipv4 && !match_ipv6 {
-                    let bind_addr = if addr.is_ipv4() {
-                        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
-                    } else {
-                        SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
-                    };
-
-                    self.ep
-                        .rebind(UdpSocket::bind(bind_addr).map_err(|err| {
-                            Error::Socket("failed to create endpoint UDP socket", err)
-                        })?)
-                        .map_err(|err| {
-                            Error::Socket("failed to rebind endpoint UDP socket", err)
-                        })?;
+        // the certificate chain is not verified when pinning certificates or in insecure mode
+        let certs = if cfg.insecure || !cfg.certificate_fingerprints.is_empty() {
+            RootCertStore::empty()
+        } else {
+            utils::load_certs(cfg.certificates, cfg.disable_native_certs)?
+        };
+
+        let mut crypto = RustlsClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&version::TLS13])
+            .unwrap()
+            .with_root_certificates(certs)
+            .with_no_client_auth();
+
+        crypto.alpn_protocols = cfg.alpn;
+        crypto.enable_early_data = true;
+        crypto.enable_sni = !cfg.disable_sni;
+
+        if cfg.insecure {
+            log::warn!(
+                "[relay] certificate verification of {server} is disabled, the connection is open to man-in-the-middle attacks",
+                server = cfg.server.0,
+            );
+            crypto
+                .dangerous()
+                .set_certificate_verifier(Arc::new(InsecureVerifier));
+        } else if !cfg.certificate_fingerprints.is_empty() {
+            crypto
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertVerifier::new(
+                    cfg.certificate_fingerprints,
+                )));
+        }
+
+        let mut config = ClientConfig::new(Arc::new(crypto));
+        let mut tp_cfg = TransportConfig::default();
+
+        tp_cfg
+            .max_concurrent_bidi_streams(VarInt::from(cfg.max_concurrent_streams))
+            .max_concurrent_uni_streams(VarInt::from(cfg.max_concurrent_streams))
+            .send_window(cfg.send_window)
+            .stream_receive_window(VarInt::from_u32(cfg.receive_window))
+            .initial_rtt(cfg.initial_rtt)
+            .datagram_receive_buffer_size(Some(cfg.datagram_receive_buffer_size))
+            .max_idle_timeout(None);
+
+        match cfg.congestion_control {
+            CongestionControl::Cubic => {
+                tp_cfg.congestion_controller_factory(Arc::new(CubicConfig::default()))
+            }
+            CongestionControl::NewReno => {
+                tp_cfg.congestion_controller_factory(Arc::new(NewRenoConfig::default()))
+            }
+            CongestionControl::Bbr => {
+                tp_cfg.congestion_controller_factory(Arc::new(BbrConfig::default()))
+            }
+            CongestionControl::Brutal => {
+                let Some(bandwidth) = &cfg.bandwidth else {
+                    return Err(Error::InvalidCongestionControl(
+                        "`bandwidth` must be set to use `brutal`",
+                    ));
+                };
+
+                tp_cfg.congestion_controller_factory(BrutalConfig::new(bandwidth.up))
+            }
+        };
+
+        // the window is already checked to be a valid `VarInt` when parsing the config
+        if let Some(window) = cfg.connection_receive_window {
+            tp_cfg.receive_window(VarInt::from_u64(window).unwrap());
+        }
+
+        config.transport_config(Arc::new(tp_cfg));
+
+        let mut ep_cfg = EndpointConfig::default();
+
+        ep_cfg
+            .max_udp_payload_size(cfg.max_udp_payload_size)
+            .map_err(|_| Error::InvalidTransport("`max_udp_payload_size` is out of bounds"))?;
+
+        // sockets given to the endpoints by the runtime, including the ones for rebinding, are obfuscated
+        let runtime: Arc<dyn Runtime> = match &cfg.obfs_password {
+            Some(password) => Arc::new(ObfsRuntime::new(password.clone())),
+            None => Arc::new(TokioRuntime),
+        };
+
+        // Create an endpoint for each address family, so handshakes to both can be raced.
+        // Either one may be unavailable on the host, but not both.
+        let bind = |addr: SocketAddr| -> Result<QuinnEndpoint, Error> {
+            let mut ep =
+                QuinnEndpoint::new(ep_cfg.clone(), None, bind_socket(addr)?, runtime.clone())?;
+
+            ep.set_default_client_config(config.clone());
+            Ok(ep)
+        };
+
+        if let Some(dir) = &cfg.qlog_dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let upstream = match (cfg.proxy, cfg.bridge) {
+            (Some(_), Some(_)) => {
+                return Err(Error::UpstreamProxy(
+                    "`proxy` cannot be used together with `bridge`",
+                ))
+            }
+            (Some(proxy), None) => Some(Upstream::Socks5(proxy)),
+            (None, Some(bridge)) => Some(Upstream::WebSocket(bridge)),
+            (None, None) => None,
+        };
+
+        // When connecting through an upstream proxy or a bridge, an endpoint is created for each connection instead
+        let (ep_v4, ep_v6) = if upstream.is_some() {
+            (None, None)
+        } else {
+            match (
+                bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
+                bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))),
+            ) {
+                (Err(err), Err(_)) => return Err(err),
+                (ep_v4, ep_v6) => (ep_v4.ok(), ep_v6.ok()),
+            }
+        };
+
+        Ok(Self {
+            ep_v4,
+            ep_v6,
+            proxy: upstream.map(|upstream| (upstream, config)),
+            obfs_password: cfg.obfs_password,
+            runtime,
+            ep_cfg,
+            server: ServerAddr::new(cfg.server.0, cfg.server.1, cfg.ip, cfg.sni),
+            priority: cfg.priority,
+            weight: cfg.weight,
+            uuid: cfg.uuid,
+            password: cfg.password,
+            bandwidth: cfg.bandwidth.map(|bw| Bandwidth::new(bw.up, bw.down)),
+            udp_relay_mode: cfg.udp_relay_mode,
+            udp_stream_fallback: cfg.udp_stream_fallback,
+            udp_native_pacing: cfg.udp_native_pacing,
+            udp_stun: cfg.udp_stun,
+            udp_session_resumption: cfg.udp_session_resumption,
+            adaptive_fragment_size: cfg.adaptive_fragment_size,
+            fec: cfg.fec,
+            udp_compression: cfg.udp_compression,
+            max_datagram_size: cfg.max_datagram_size,
+            max_concurrent_streams: cfg.max_concurrent_streams,
+            zero_rtt_handshake: cfg.zero_rtt_handshake,
+            happy_eyeballs_delay: cfg.happy_eyeballs_delay,
+            timeout: cfg.timeout,
+            heartbeat: cfg.heartbeat,
+            idle_timeout: IdleTimeout::default(),
+            gc_interval: cfg.gc_interval,
+            gc_lifetime: cfg.gc_lifetime,
+            task_log: cfg.task_log,
+            qlog_dir: cfg.qlog_dir.map(Arc::from),
+            rebind_on_network_change: cfg.rebind_on_network_change,
+            keep_warm: cfg.keep_warm,
+            on_demand: cfg.on_demand,
+            migrations: AtomicU64::new(0),
+            pool: (0..cfg.connections)
+                .map(|_| AsyncMutex::new(PoolSlot::default()))
+                .collect(),
+            next_conn: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            rtt: AtomicCell::new(None),
+            initial_backoff: reconnect.initial_backoff,
+            max_backoff: reconnect.max_backoff,
+            max_pending: reconnect.max_pending,
+            pending: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the pooled connection for the task
+    ///
+    /// TCP relay tasks are spread across the pool in turn, while a UDP association always uses the same connection.
+    async fn connection(self: &Arc<Self>, key: &TaskKey<'_>) -> Result<Connection, Error> {
+        let idx = match key {
+            TaskKey::Connect(_) => self.next_conn.fetch_add(1, Ordering::Relaxed),
+            TaskKey::Associate(assoc_id) => usize::from(*assoc_id),
+        };
+
+        self.connection_at(idx % self.pool.len()).await
+    }
+
+    /// Returns the RTT of an established connection in the pool, skipping slots being reconnected
+    /// Dissociates the UDP sessions on all pooled connections, skipping slots being reconnected
+    async fn dissociate_all(&self) {
+        let conns = self
+            .pool
+            .iter()
+            .filter_map(|slot| slot.try_lock().ok()?.conn.clone())
+            .filter(|conn| !conn.is_closed())
+            .collect::<Vec<_>>();
+
+        for conn in conns {
+            let _ = time::timeout(self.timeout, conn.dissociate_all()).await;
+        }
+    }
+
+    fn close(&self) {
+        for slot in &self.pool {
+            if let Some(conn) = slot.try_lock().ok().and_then(|mut slot| slot.conn.take()) {
+                conn.conn.close(ERROR_CODE, &[]);
+            }
+        }
+    }
+
+    fn task_metrics(&self) -> Vec<(TaskMetrics, Vec<TaskEvent>)> {
+        self.pool
+            .iter()
+            .filter_map(|slot| {
+                let slot = slot.try_lock().ok()?;
+                let conn = slot.conn.as_ref().filter(|conn| !conn.is_closed())?;
+                Some((conn.model.task_metrics(), conn.model.task_events()))
+            })
+            .collect()
+    }
+
+    fn assembly_counts(&self) -> Vec<(AssemblyCounts, Vec<(u16, AssemblyCounts)>)> {
+        self.pool
+            .iter()
+            .filter_map(|slot| {
+                let slot = slot.try_lock().ok()?;
+                let conn = slot.conn.as_ref().filter(|conn| !conn.is_closed())?;
+                Some((
+                    conn.model.assembly_counts(),
+                    conn.model.session_assembly_counts(),
+                ))
+            })
+            .collect()
+    }
+
+    fn path_stats(&self) -> Vec<(usize, ConnectionStats)> {
+        self.pool
+            .iter()
+            .filter_map(|slot| {
+                let slot = slot.try_lock().ok()?;
+                let conn = slot.conn.as_ref().filter(|conn| !conn.is_closed())?;
+                Some((conn.conn.stable_id(), conn.conn.stats()))
+            })
+            .collect()
+    }
+
+    fn current_rtt(&self) -> Option<Duration> {
+        self.pool.iter().find_map(|slot| {
+            let slot = slot.try_lock().ok()?;
+            let conn = slot.conn.as_ref().filter(|conn| !conn.is_closed())?;
+            Some(conn.conn.rtt())
+        })
+    }
+
+    /// Returns the remote address of an established connection in the pool, which changes when the server migrates
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.pool.iter().find_map(|slot| {
+            let slot = slot.try_lock().ok()?;
+            let conn = slot.conn.as_ref().filter(|conn| !conn.is_closed())?;
+            Some(conn.conn.remote_address())
+        })
+    }
+
+    /// Returns the connection in the pool slot, reconnecting if it is closed
+    ///
+    /// Failed reconnections are retried with a jittered exponential backoff. Tasks arriving in the meantime wait for the reconnection, up to `max_pending` of them.
+    async fn connection_at(self: &Arc<Self>, idx: usize) -> Result<Connection, Error> {
+        if let Ok(slot) = self.pool[idx].try_lock() {
+            if let Some(conn) = slot.conn.as_ref().filter(|conn| !conn.is_closed()) {
+                return Ok(conn.clone());
+            }
+        }
+
+        // held until the task is done waiting, even if it is cancelled
+        let _pending = PendingGuard::acquire(&self.pending, self.max_pending)?;
+
+        let try_get_conn = async {
+            let mut slot = self.pool[idx].lock().await;
+
+            if let Some(conn) = &slot.conn {
+                if !conn.is_closed() {
+                    return Ok(conn.clone());
                 }
+            }
 
-                let conn = self.ep.connect(addr, self.server.server_name())?;
-                let (conn, zero_rtt_accepted) = if self.zero_rtt_handshake {
-                    match conn.into_0rtt() {
-                        Ok((conn, zero_rtt_accepted)) => (conn, Some(zero_rtt_accepted)),
-                        Err(conn) => (conn.await?, None),
+            if let Some(retry_at) = slot.retry_at {
+                time::sleep_until(retry_at).await;
+            }
+
+            match self.connect().await {
+                Ok(conn) => {
+                    if slot.conn.is_some() {
+                        log::info!(
+                            "[relay] reconnected to server {server}",
+                            server = self.server,
+                        );
                     }
+
+                    self.fill_slot(idx, &mut slot, conn.clone()).await;
+                    Ok(conn)
+                }
+                Err(err) => {
+                    slot.retries += 1;
+                    let backoff = self.backoff(slot.retries);
+                    slot.retry_at = Some(Instant::now() + backoff);
+
+                    log::warn!(
+                        "[relay] failed connecting to server {server}, retrying in {backoff:?}: {err}",
+                        server = self.server,
+                    );
+
+                    events::emit(Event::Reconnecting {
+                        server: self.server.to_string(),
+                        retries: slot.retries,
+                        backoff,
+                    });
+
+                    Err(err)
+                }
+            }
+        };
+
+        time::timeout(self.timeout, try_get_conn)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Returns the connection in the pool slot if it is established, without connecting otherwise
+    async fn established_at(&self, idx: usize) -> Option<Connection> {
+        self.pool[idx]
+            .lock()
+            .await
+            .conn
+            .clone()
+            .filter(|conn| !conn.is_closed())
+    }
+
+    /// Puts a new connection into the pool slot
+    async fn fill_slot(self: &Arc<Self>, idx: usize, slot: &mut PoolSlot, conn: Connection) {
+        if self.udp_session_resumption {
+            slot.resume_token = self.resume(&conn, slot.resume_token).await;
+        }
+
+        slot.conn = Some(conn.clone());
+        slot.retries = 0;
+        slot.retry_at = None;
+
+        tokio::spawn(self.clone().reconnect_on_close(idx, conn));
+    }
+
+    /// Claims the UDP sessions of the previous connection of the pool slot on the server, returning the token for resuming them again after the next reconnection
+    ///
+    /// The server keeps the relay sockets of the sessions, so the targets see the same address of the associations.
+    async fn resume(&self, conn: &Connection, token: Option<[u8; 16]>) -> Option<[u8; 16]> {
+        match conn.model.resume(token).await {
+            Ok((token, assoc_ids)) => {
+                if !assoc_ids.is_empty() {
+                    log::info!(
+                        "[relay] [resume] resumed {cnt} UDP sessions on server {server}",
+                        cnt = assoc_ids.len(),
+                        server = self.server,
+                    );
+                }
+
+                Some(token)
+            }
+            Err(err) => {
+                log::warn!(
+                    "[relay] [resume] failed resuming UDP sessions on server {server}: {err}",
+                    server = self.server,
+                );
+
+                None
+            }
+        }
+    }
+
+    /// Reconnects right away when the connection is closed while UDP associations are still relayed through it
+    ///
+    /// The associations are recreated on the server with their next packet. Without active associations, reconnecting is deferred to the next task.
+    ///
+    /// The future is boxed to break the type cycle of `connection_at()` spawning it.
+    fn reconnect_on_close(
+        self: Arc<Self>,
+        idx: usize,
+        conn: Connection,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            let err = conn.conn.closed().await;
+
+            if let Some(delay) = self.close_backoff(&err) {
+                let mut slot = self.pool[idx].lock().await;
+
+                // the slot is not reconnected in the meantime
+                if slot.conn.as_ref().map_or(false, |slot_conn| {
+                    slot_conn.conn.stable_id() == conn.conn.stable_id()
+                }) {
+                    slot.retry_at = Some(Instant::now() + delay);
+                }
+            }
+
+            loop {
+                let assoc_cnt = self.association_count(idx);
+
+                if assoc_cnt == 0 {
+                    break;
+                }
+
+                log::info!(
+                    "[relay] reconnecting to server {server} for {assoc_cnt} active UDP associations",
+                    server = self.server,
+                );
+
+                if self.connection_at(idx).await.is_ok() {
+                    break;
+                }
+            }
+        })
+    }
+
+    fn association_count(&self, idx: usize) -> usize {
+        ASSOCIATIONS
+            .lock()
+            .iter()
+            .filter(|(assoc_id, ep)| {
+                ptr::eq(Arc::as_ptr(ep), self) && usize::from(**assoc_id) % self.pool.len() == idx
+            })
+            .count()
+    }
+
+    /// Returns how long to wait before reconnecting, if the server closed the connection for a reason retrying right away does not help
+    ///
+    /// Authentication failures and exceeded quotas are not resolved by retrying, so reconnections wait for the maximum backoff, and the server is marked as unhealthy for tasks to fail over. A server shutting down is given the initial backoff for restarting.
+    fn close_backoff(&self, err: &ConnectionError) -> Option<Duration> {
+        match CloseCode::from_connection_error(err)? {
+            CloseCode::AuthFailed | CloseCode::AuthRevoked | CloseCode::QuotaExceeded => {
+                self.set_healthy(false);
+                Some(self.backoff(u32::MAX))
+            }
+            CloseCode::ShuttingDown => Some(self.backoff(1)),
+            _ => None,
+        }
+    }
+
+    /// Returns the jittered exponential backoff after the given number of failed retries
+    fn backoff(&self, retries: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retries - 1))
+            .min(self.max_backoff);
+
+        backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0)
+    }
+
+    /// Probes the server with a `Heartbeat`, taking the RTT estimated by QUIC, or with an `Echo` if `echo` is set, taking its round-trip time, so that a server not handling commands is considered unhealthy too
+    ///
+    /// With `on_demand`, the server is only probed while connected, keeping its last known health otherwise.
+    async fn health_check(self: Arc<Self>, cfg: HealthCheck) {
+        loop {
+            let conn = if self.on_demand.is_some() {
+                match self.established_at(0).await {
+                    Some(conn) => Ok(conn),
+                    None => {
+                        time::sleep(cfg.interval).await;
+                        continue;
+                    }
+                }
+            } else {
+                self.connection_at(0).await
+            };
+
+            let res = match conn {
+                Ok(conn) => match Self::probe(&conn, cfg.echo).await {
+                    Ok(rtt) => {
+                        if cfg.max_rtt.map_or(true, |max_rtt| rtt <= max_rtt) {
+                            Ok(rtt)
+                        } else {
+                            Err(format!("RTT {rtt:?} exceeds the limit"))
+                        }
+                    }
+                    Err(err) => Err(err.to_string()),
+                },
+                Err(err) => Err(err.to_string()),
+            };
+
+            match res {
+                Ok(rtt) => {
+                    self.rtt.store(Some(rtt));
+
+                    log::debug!(
+                        "[relay] [health-check] server {server} RTT {rtt:?}",
+                        server = self.server,
+                    );
+
+                    if !self.healthy.load(Ordering::Relaxed) {
+                        log::warn!(
+                            "[relay] [health-check] server {server} is available again",
+                            server = self.server,
+                        );
+                        self.set_healthy(true);
+                    }
+                }
+                Err(err) => {
+                    self.rtt.store(None);
+
+                    if self.healthy.load(Ordering::Relaxed) {
+                        log::warn!(
+                            "[relay] [health-check] server {server} is unavailable: {err}",
+                            server = self.server,
+                        );
+                        self.set_healthy(false);
+                    }
+                }
+            }
+
+            time::sleep(cfg.interval).await;
+        }
+    }
+
+    async fn probe(conn: &Connection, echo: bool) -> Result<Duration, Error> {
+        if echo {
+            let start = Instant::now();
+            conn.echo(Vec::new()).await?;
+            Ok(start.elapsed())
+        } else {
+            conn.model.heartbeat().await?;
+            Ok(conn.conn.rtt())
+        }
+    }
+
+    fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// Rebinds the endpoints to new UDP sockets, migrating the connections onto the network currently routing to the server
+    ///
+    /// The connections and the UDP associations relayed through them are kept, as the server validates the new path and continues on it. Endpoints of connections through an upstream proxy are not rebound.
+    fn rebind(&self) -> Result<(), Error> {
+        if self.proxy.is_some() {
+            return Ok(());
+        }
+
+        for (ep, addr) in [
+            (&self.ep_v4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
+            (&self.ep_v6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))),
+        ] {
+            if let Some(ep) = ep {
+                ep.rebind(bind_socket(addr)?)
+                    .map_err(|err| Error::Socket("failed to rebind endpoint UDP socket", err))?;
+            }
+        }
+
+        self.migrations.fetch_add(1, Ordering::Relaxed);
+
+        log::info!(
+            "[relay] migrated connections to server {server} onto new sockets",
+            server = self.server,
+        );
+
+        Ok(())
+    }
+
+    /// Follows the network changes and system wake-ups noticed by [`network`], validating the established connections after each
+    ///
+    /// With `rebind_on_network_change`, the endpoints are also rebound when the local address routing to the server changes, e.g. when switching from Wi-Fi to cellular, checked on every notification and every `NETWORK_CHECK_INTERVAL`. Without rebinding, packets sent from the old socket may keep going out of the network that is gone, until the connection times out.
+    async fn watch_network(self: Arc<Self>) {
+        let mut events = network::subscribe();
+        let mut last = None;
+
+        loop {
+            let event = tokio::select! {
+                res = events.recv() => match res {
+                    Ok(event) => Some(event),
+                    Err(RecvError::Lagged(_)) => Some(NetworkEvent::Changed),
+                    Err(RecvError::Closed) => break,
+                },
+                () = time::sleep(NETWORK_CHECK_INTERVAL), if self.rebind_on_network_change => None,
+            };
+
+            if event.is_some() {
+                // changes come in bursts, e.g. an address removed and another added
+                time::sleep(NETWORK_SETTLE_DELAY).await;
+                while events.try_recv().is_ok() {}
+            }
+
+            if self.rebind_on_network_change {
+                // no connection to migrate, or no route to the server for now
+                if let Some(ip) = self.route_source() {
+                    match last.replace(ip) {
+                        Some(prev) if prev != ip => {
+                            log::info!(
+                                "[relay] [network] local address routing to server {server} changed from {prev} to {ip}",
+                                server = self.server,
+                            );
+
+                            if let Err(err) = self.rebind() {
+                                log::warn!(
+                                    "[relay] [network] failed migrating connections to server {server}: {err}",
+                                    server = self.server,
+                                );
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if let Some(event) = event {
+                self.validate(event).await;
+            }
+        }
+    }
+
+    /// Validates the established connections with a heartbeat, closing those not receiving anything back from the server within `VALIDATE_TIMEOUT`
+    ///
+    /// Tasks then reconnect right away instead of waiting for the connections to time out, and so do the UDP associations relayed through them.
+    async fn validate(&self, event: NetworkEvent) {
+        let mut conns = Vec::new();
+
+        for idx in 0..self.pool.len() {
+            conns.extend(self.established_at(idx).await);
+        }
+
+        if conns.is_empty() {
+            return;
+        }
+
+        log::debug!(
+            "[relay] [network] {event}, validating {cnt} connections to server {server}",
+            cnt = conns.len(),
+            server = self.server,
+        );
+
+        let validations = conns.into_iter().map(|conn| async move {
+            let rx = conn.conn.stats().udp_rx.datagrams;
+
+            let responded = async {
+                conn.model.heartbeat().await?;
+
+                while conn.conn.stats().udp_rx.datagrams == rx {
+                    time::sleep(VALIDATE_POLL_INTERVAL).await;
+                }
+
+                Ok::<_, Error>(())
+            };
+
+            match time::timeout(VALIDATE_TIMEOUT, responded).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::warn!(
+                    "[relay] [network] failed validating the connection to server {server}: {err}",
+                    server = self.server,
+                ),
+                Err(_) => {
+                    log::warn!(
+                        "[relay] [network] server {server} not responding after {event}, closing the connection",
+                        server = self.server,
+                    );
+
+                    conn.conn.close(ERROR_CODE, b"not responding");
+                }
+            }
+        });
+
+        future::join_all(validations).await;
+    }
+
+    /// Returns the local IP address the system routes packets to the server from, without sending anything
+    fn route_source(&self) -> Option<IpAddr> {
+        let remote = self.remote_addr()?;
+
+        let socket = if remote.is_ipv4() {
+            bind_socket(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+        } else {
+            bind_socket(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+        };
+
+        let socket = socket.ok()?;
+        socket.connect(remote).ok()?;
+        Some(socket.local_addr().ok()?.ip())
+    }
+
+    /// Connects to the server. When the server address resolves to multiple IP addresses, handshakes are raced as per Happy Eyeballs (RFC 8305)
+    async fn connect(&self) -> Result<Connection, Error> {
+        let mut addrs = self.sort_addrs(self.server.resolve().await?).into_iter();
+        let mut attempts = FuturesUnordered::new();
+
+        match addrs.next() {
+            Some(addr) => attempts.push(self.connect_to(addr)),
+            None => return Err(Error::DnsResolve),
+        }
+
+        loop {
+            let next_attempt = time::sleep(self.happy_eyeballs_delay);
+
+            tokio::select! {
+                Some(res) = attempts.next() => match res {
+                    Ok((conn, zero_rtt_accepted)) => {
+                        if let Some(dir) = &self.qlog_dir {
+                            tokio::spawn(qlog::trace(conn.clone(), dir.clone()));
+                        }
+
+                        let conn = Connection::new(
+                            conn,
+                            Arc::from(self.server.to_string()),
+                            zero_rtt_accepted,
+                            self.udp_relay_mode,
+                            self.udp_stream_fallback,
+                            self.udp_native_pacing,
+                            self.udp_stun,
+                            self.adaptive_fragment_size,
+                            self.fec,
+                            self.udp_compression,
+                            self.max_datagram_size,
+                            self.max_concurrent_streams,
+                            self.uuid,
+                            self.password.clone(),
+                            self.bandwidth,
+                            self.heartbeat,
+                            self.idle_timeout.clone(),
+                            self.gc_interval,
+                            self.gc_lifetime,
+                        );
+
+                        conn.model.set_task_log(self.task_log);
+                        return Ok(conn);
+                    }
+                    Err(err) => match addrs.next() {
+                        Some(addr) => attempts.push(self.connect_to(addr)),
+                        None if attempts.is_empty() => return Err(err),
+                        None => {}
+                    },
+                },
+                _ = next_attempt, if addrs.len() > 0 => {
+                    attempts.push(self.connect_to(addrs.next().unwrap()));
+                }
+            }
+        }
+    }
+
+    /// Creates an endpoint for a single connection over the socket of an upstream proxy or a bridge
+    fn upstream_endpoint(
+        &self,
+        socket: impl AsyncUdpSocket,
+        config: &ClientConfig,
+    ) -> Result<QuinnEndpoint, Error> {
+        let mut ep = match &self.obfs_password {
+            Some(password) => QuinnEndpoint::new_with_abstract_socket(
+                self.ep_cfg.clone(),
+                None,
+                ObfsUdpSocket::new(Box::new(socket), password.clone()),
+                self.runtime.clone(),
+            )?,
+            None => QuinnEndpoint::new_with_abstract_socket(
+                self.ep_cfg.clone(),
+                None,
+                socket,
+                self.runtime.clone(),
+            )?,
+        };
+
+        ep.set_default_client_config(config.clone());
+        Ok(ep)
+    }
+
+    async fn connect_to(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<(QuinnConnection, Option<ZeroRttAccepted>), Error> {
+        log::debug!(
+            "[relay] [connect] trying {server} at {addr}",
+            server = self.server
+        );
+
+        let conn = match &self.proxy {
+            Some((upstream, config)) => {
+                let ep = match upstream {
+                    Upstream::Socks5(proxy) => {
+                        let socket = Socks5UdpSocket::bind(proxy).await?;
+                        self.upstream_endpoint(socket, config)?
+                    }
+                    Upstream::WebSocket(bridge) => {
+                        let socket = WebSocketUdpSocket::connect(bridge, addr).await?;
+                        self.upstream_endpoint(socket, config)?
+                    }
+                };
+
+                // the endpoint is kept alive by the connection
+                ep.connect(addr, self.server.server_name())?
+            }
+            None => {
+                let ep = if addr.is_ipv4() {
+                    self.ep_v4.as_ref()
                 } else {
-                    (conn.await?, None)
+                    self.ep_v6.as_ref()
                 };
 
-                Ok((conn, zero_rtt_accepted))
-            };
+                ep.unwrap().connect(addr, self.server.server_name())?
+            }
+        };
 
-            match connect_to.await {
-                Ok((conn, zero_rtt_accepted)) => {
-                    return Ok(Connection::new(
-                        conn,
-                        zero_rtt_accepted,
-                        self.udp_relay_mode,
-                        self.uuid,
-                        self.password.clone(),
-                        self.heartbeat,
-                        self.gc_interval,
-                        self.gc_lifetime,
-                    ));
+        if self.zero_rtt_handshake {
+            match conn.into_0rtt() {
+                Ok((conn, zero_rtt_accepted)) => Ok((conn, Some(zero_rtt_accepted))),
+                Err(conn) => Ok((conn.await?, None)),
+            }
+        } else {
+            Ok((conn.await?, None))
+        }
+    }
+
+    /// Drops addresses of unavailable families, then interleaves the address families, starting with the family of the first address
+    fn sort_addrs(&self, addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+        let addrs = addrs
+            .filter(|addr| {
+                if self.proxy.is_some() {
+                    true
+                } else if addr.is_ipv4() {
+                    self.ep_v4.is_some()
+                } else {
+                    self.ep_v6.is_some()
                 }
-                Err(err) => last_err = Some(err),
+            })
+            .collect::<Vec<_>>();
+
+        let Some(first_is_ipv4) = addrs.first().map(SocketAddr::is_ipv4) else {
+            return addrs;
+        };
+
+        let (preferred, other): (Vec<_>, Vec<_>) = addrs
+            .into_iter()
+            .partition(|addr| addr.is_ipv4() == first_is_ipv4);
+
+        let mut preferred = preferred.into_iter();
+        let mut other = other.into_iter();
+        let mut sorted = Vec::with_capacity(preferred.len() + other.len());
+
+        loop {
+            match (preferred.next(), other.next()) {
+                (None, None) => break,
+                (addr_1, addr_2) => sorted.extend(addr_1.into_iter().chain(addr_2)),
             }
         }
 
-        Err(last_err.unwrap_or(Error::DnsResolve))
+        sorted
     }
 }
+
+/// Creates a UDP socket for an endpoint, protected from the TUN device of the app embedding the client
+fn bind_socket(addr: SocketAddr) -> Result<UdpSocket, Error> {
+    let socket = UdpSocket::bind(addr)
+        .map_err(|err| Error::Socket("failed to create endpoint UDP socket", err))?;
+
+    protect::protect(&socket)
+        .map_err(|err| Error::Socket("failed to protect endpoint UDP socket", err))?;
+
+    Ok(socket)
+}
@@ -0,0 +1,286 @@
+//! Noticing network changes and the system waking up from sleep
+//!
+//! After either, connections to the server may have silently stopped working, e.g. with the NAT mapping gone or the connection timed out on the server while asleep, which would otherwise only be noticed when they time out on the client too. Both are noticed as they happen:
+//!
+//! - network changes: from the notifications of the OS, i.e. a netlink socket on Linux, a routing socket on macOS, iOS and BSDs, and `NotifyUnicastIpAddressChange()` on Windows. On other platforms, e.g. Android where apps cannot listen on netlink, the app embedding the client is expected to call `rebind()` when notified of a network change
+//! - waking up: from the wall clock jumping ahead of a timer, as the timer does not run while asleep but the wall clock does
+//!
+//! The watchers are started on the first subscription, and run until the process exits.
+
+use once_cell::sync::Lazy;
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// The number of events kept for each subscriber
+const CAPACITY: usize = 16;
+const WAKE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const WAKE_THRESHOLD: Duration = Duration::from_secs(5);
+
+static EVENTS: Lazy<Sender<NetworkEvent>> = Lazy::new(|| {
+    start();
+    broadcast::channel(CAPACITY).0
+});
+
+#[derive(Clone, Copy, Debug)]
+pub enum NetworkEvent {
+    /// The addresses or routes of the network interfaces changed
+    Changed,
+    /// The system woke up after sleeping for about the duration
+    Resumed(Duration),
+}
+
+impl Display for NetworkEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Changed => write!(f, "network changed"),
+            Self::Resumed(slept) => write!(f, "system woke up after sleeping for {slept:?}"),
+        }
+    }
+}
+
+pub fn subscribe() -> Receiver<NetworkEvent> {
+    EVENTS.subscribe()
+}
+
+pub fn notify(event: NetworkEvent) {
+    // no subscriber is not an error
+    let _ = EVENTS.send(event);
+}
+
+fn start() {
+    let res = thread::Builder::new()
+        .name("network-watch".to_owned())
+        .spawn(|| {
+            if let Err(err) = platform::watch() {
+                log::warn!("[relay] [network] failed watching network changes: {err}");
+            }
+        });
+
+    if let Err(err) = res {
+        log::warn!("[relay] [network] failed watching network changes: {err}");
+    }
+
+    let res = thread::Builder::new()
+        .name("wake-watch".to_owned())
+        .spawn(watch_wake);
+
+    if let Err(err) = res {
+        log::warn!("[relay] [network] failed watching system wake-ups: {err}");
+    }
+}
+
+fn watch_wake() {
+    loop {
+        let before = SystemTime::now();
+        thread::sleep(WAKE_CHECK_INTERVAL);
+
+        // the wall clock going backwards, e.g. adjusted by NTP, is not a wake-up
+        let Ok(elapsed) = before.elapsed() else {
+            continue;
+        };
+
+        if elapsed > WAKE_CHECK_INTERVAL + WAKE_THRESHOLD {
+            notify(NetworkEvent::Resumed(elapsed - WAKE_CHECK_INTERVAL));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::NetworkEvent;
+    use std::{
+        io::{Error, ErrorKind, Result},
+        mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    /// Listens on a netlink socket for changes of the links, addresses and routes, until failing
+    pub fn watch() -> Result<()> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr = unsafe { mem::zeroed::<libc::sockaddr_nl>() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_LINK
+            | libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut buf = [0; 8192];
+
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+
+            if len < 0 {
+                let err = Error::last_os_error();
+
+                match err.raw_os_error() {
+                    // messages dropped for the socket buffer overflowing are changes too
+                    Some(libc::ENOBUFS) => {}
+                    _ if err.kind() == ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+
+            super::notify(NetworkEvent::Changed);
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod platform {
+    use super::NetworkEvent;
+    use std::{
+        io::{Error, ErrorKind, Result},
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    /// Listens on a routing socket for changes of the interfaces and their addresses, until failing
+    pub fn watch() -> Result<()> {
+        let fd = unsafe { libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC) };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut buf = [0; 4096];
+
+        loop {
+            let len = unsafe {
+                libc::read(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+
+            if len < 0 {
+                let err = Error::last_os_error();
+
+                match err.raw_os_error() {
+                    // messages dropped for the socket buffer overflowing are changes too
+                    Some(libc::ENOBUFS) => {
+                        super::notify(NetworkEvent::Changed);
+                        continue;
+                    }
+                    _ if err.kind() == ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+
+            // every routing message starts with its `u16` length, `u8` version and `u8` type, while the route messages are too frequent to follow, e.g. for ARP entries
+            if len >= 4
+                && matches!(
+                    libc::c_int::from(buf[3]),
+                    libc::RTM_NEWADDR | libc::RTM_DELADDR | libc::RTM_IFINFO
+                )
+            {
+                super::notify(NetworkEvent::Changed);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::NetworkEvent;
+    use std::{
+        ffi::c_void,
+        io::{Error, Result},
+        ptr,
+    };
+    use windows_sys::Win32::{
+        Foundation::{HANDLE, NO_ERROR},
+        NetworkManagement::IpHelper::{
+            NotifyUnicastIpAddressChange, MIB_NOTIFICATION_TYPE, MIB_UNICASTIPADDRESS_ROW,
+        },
+        Networking::WinSock::AF_UNSPEC,
+    };
+
+    unsafe extern "system" fn on_change(
+        _: *const c_void,
+        _: *const MIB_UNICASTIPADDRESS_ROW,
+        _: MIB_NOTIFICATION_TYPE,
+    ) {
+        super::notify(NetworkEvent::Changed);
+    }
+
+    /// Registers for changes of the unicast addresses, notified on the thread pool of the system
+    ///
+    /// The registration is kept until the process exits, so this returns right away.
+    pub fn watch() -> Result<()> {
+        let mut handle: HANDLE = 0;
+
+        let res = unsafe {
+            NotifyUnicastIpAddressChange(AF_UNSPEC, Some(on_change), ptr::null(), 0, &mut handle)
+        };
+
+        if res != NO_ERROR {
+            return Err(Error::from_raw_os_error(res as i32));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    windows
+)))]
+mod platform {
+    use std::io::Result;
+
+    /// Network changes are left to the app embedding the client
+    pub fn watch() -> Result<()> {
+        Ok(())
+    }
+}
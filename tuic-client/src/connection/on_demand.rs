@@ -0,0 +1,71 @@
+//! Dialing the server on demand
+//!
+//! Connections are made by the first task needing them either way, but are otherwise left open until the idle timeout of the server, which may be long, and kept open by the health checks of multiple servers. With `on_demand`, a connection not relaying any TCP task or UDP association for `idle_timeout` is closed by the client, and health checks only probe the connections already established, so the client stays silent on the network while no local request arrives, e.g. to save battery on mobile devices.
+//!
+//! Connections are checked on every `idle_timeout / CHECK_DIVISOR`, so they are closed after being idle for up to `idle_timeout * (1 + 1 / CHECK_DIVISOR)`.
+
+use super::{Endpoint, ERROR_CODE};
+use crate::config::OnDemand;
+use std::{sync::Arc, time::Duration};
+use tokio::time::{self, Instant};
+
+const CHECK_DIVISOR: u32 = 6;
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The connection in a pool slot found idle
+struct Idle {
+    stable_id: usize,
+    since: Instant,
+}
+
+impl Endpoint {
+    /// Closes the connections in the pool idle for `idle_timeout`, until aborted
+    pub(super) async fn close_idle(self: Arc<Self>, cfg: OnDemand) {
+        let interval = (cfg.idle_timeout / CHECK_DIVISOR).max(MIN_INTERVAL);
+
+        let mut idles = (0..self.pool.len())
+            .map(|_| None)
+            .collect::<Vec<Option<Idle>>>();
+
+        loop {
+            time::sleep(interval).await;
+
+            for (slot, idle) in self.pool.iter().zip(idles.iter_mut()) {
+                // holding the slot for tasks not to pick the connection while closing it
+                let slot = slot.lock().await;
+
+                let Some(conn) = slot.conn.as_ref().filter(|conn| !conn.is_closed()) else {
+                    *idle = None;
+                    continue;
+                };
+
+                if conn.is_relaying() {
+                    *idle = None;
+                    continue;
+                }
+
+                let stable_id = conn.conn.stable_id();
+
+                match idle {
+                    Some(idle) if idle.stable_id == stable_id => {
+                        if idle.since.elapsed() >= cfg.idle_timeout {
+                            log::info!(
+                                "[relay] [on-demand] closing the connection to server {server} after being idle for {idle:?}",
+                                server = self.server,
+                                idle = idle.since.elapsed(),
+                            );
+
+                            conn.conn.close(ERROR_CODE, &[]);
+                        }
+                    }
+                    _ => {
+                        *idle = Some(Idle {
+                            stable_id,
+                            since: Instant::now(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
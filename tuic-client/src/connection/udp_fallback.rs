@@ -0,0 +1,172 @@
+//! Falling back to relaying UDP packets over QUIC unidirectional streams
+//!
+//! With `udp_stream_fallback` set, each UDP association relayed in mode `native` is switched to mode `quic` when datagrams are not supported by the connection or the packet loss rate reaches `loss_threshold`, and back once the loss rate drops to `recover_threshold`, after staying in mode `quic` for at least `hold`.
+
+use crate::{config::UdpStreamFallback, utils::UdpRelayMode};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use quinn::Connection as QuinnConnection;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+static ASSOCIATIONS: Lazy<Mutex<HashMap<u16, Association>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_SAMPLE_PACKETS: u64 = 16;
+
+/// Why an association is relayed in its current mode
+#[derive(Clone, Copy)]
+pub enum Reason {
+    Initial,
+    DatagramUnsupported,
+    Loss(f64),
+    Recovered(f64),
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Initial => write!(f, "initial"),
+            Self::DatagramUnsupported => write!(f, "datagrams not supported"),
+            Self::Loss(rate) => write!(f, "packet loss {:.1}%", rate * 100.0),
+            Self::Recovered(rate) => write!(f, "packet loss recovered to {:.1}%", rate * 100.0),
+        }
+    }
+}
+
+/// The relaying state and counters of a UDP association
+#[derive(Clone, Copy)]
+pub struct Association {
+    pub mode: UdpRelayMode,
+    pub reason: Reason,
+    pub since: Instant,
+    pub native_packets: u64,
+    pub quic_packets: u64,
+    pub switches: u64,
+}
+
+impl Association {
+    fn new() -> Self {
+        Self {
+            mode: UdpRelayMode::Native,
+            reason: Reason::Initial,
+            since: Instant::now(),
+            native_packets: 0,
+            quic_packets: 0,
+            switches: 0,
+        }
+    }
+
+    fn switch(&mut self, assoc_id: u16, mode: UdpRelayMode, reason: Reason) {
+        log::info!("[relay] [packet] [{assoc_id:#06x}] switching to mode {mode}: {reason}");
+
+        self.mode = mode;
+        self.reason = reason;
+        self.since = Instant::now();
+        self.switches += 1;
+    }
+}
+
+/// Selects the mode for relaying a packet of the association, and counts the packet
+pub fn select(
+    assoc_id: u16,
+    cfg: &UdpStreamFallback,
+    datagram_supported: bool,
+    loss_rate: f64,
+) -> UdpRelayMode {
+    let mut assocs = ASSOCIATIONS.lock();
+    let assoc = assocs.entry(assoc_id).or_insert_with(Association::new);
+
+    match assoc.mode {
+        UdpRelayMode::Native if !datagram_supported => {
+            assoc.switch(assoc_id, UdpRelayMode::Quic, Reason::DatagramUnsupported);
+        }
+        UdpRelayMode::Native if loss_rate >= cfg.loss_threshold => {
+            assoc.switch(assoc_id, UdpRelayMode::Quic, Reason::Loss(loss_rate));
+        }
+        UdpRelayMode::Quic
+            if datagram_supported
+                && loss_rate <= cfg.recover_threshold
+                && assoc.since.elapsed() >= cfg.hold =>
+        {
+            assoc.switch(assoc_id, UdpRelayMode::Native, Reason::Recovered(loss_rate));
+        }
+        _ => {}
+    }
+
+    match assoc.mode {
+        UdpRelayMode::Native => assoc.native_packets += 1,
+        UdpRelayMode::Quic => assoc.quic_packets += 1,
+    }
+
+    assoc.mode
+}
+
+pub fn remove(assoc_id: u16) {
+    ASSOCIATIONS.lock().remove(&assoc_id);
+}
+
+/// Returns the state of all associations relayed with the fallback enabled
+pub fn associations() -> Vec<(u16, Association)> {
+    let mut assocs = ASSOCIATIONS
+        .lock()
+        .iter()
+        .map(|(assoc_id, assoc)| (*assoc_id, *assoc))
+        .collect::<Vec<_>>();
+
+    assocs.sort_by_key(|(assoc_id, _)| *assoc_id);
+    assocs
+}
+
+/// Estimates the packet loss rate of a connection, from the path statistics sampled at most once per `SAMPLE_INTERVAL`
+///
+/// Samples with too few packets sent are skipped, and the rate is smoothed across samples.
+pub struct LossMeter(Mutex<Sample>);
+
+struct Sample {
+    at: Instant,
+    sent: u64,
+    lost: u64,
+    rate: f64,
+}
+
+impl LossMeter {
+    pub fn new() -> Self {
+        Self(Mutex::new(Sample {
+            at: Instant::now(),
+            sent: 0,
+            lost: 0,
+            rate: 0.0,
+        }))
+    }
+
+    pub fn rate(&self, conn: &QuinnConnection) -> f64 {
+        let mut sample = self.0.lock();
+
+        if sample.at.elapsed() < SAMPLE_INTERVAL {
+            return sample.rate;
+        }
+
+        let stats = conn.stats().path;
+        let sent = stats.sent_packets.saturating_sub(sample.sent);
+
+        if sent < MIN_SAMPLE_PACKETS {
+            return sample.rate;
+        }
+
+        let lost = stats.lost_packets.saturating_sub(sample.lost);
+        let rate = (lost as f64 / sent as f64).min(1.0);
+
+        sample.rate = (sample.rate + rate) / 2.0;
+        sample.at = Instant::now();
+        sample.sent = stats.sent_packets;
+        sample.lost = stats.lost_packets;
+
+        sample.rate
+    }
+}
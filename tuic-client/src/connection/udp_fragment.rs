@@ -0,0 +1,77 @@
+//! Adapting the fragment size of UDP packets relayed in mode `native`
+//!
+//! A packet larger than a datagram is split into fragments, and lost altogether if any of them is lost, so on paths dropping large datagrams more often than small ones, e.g. radio links and tunnels with a smaller MTU than the one discovered, multi-fragment packets keep failing to be reassembled by the server. With `adaptive_fragment_size` set, each connection reduces the fragment size by a quarter, down to `min_size`, whenever the datagram loss rate reaches `loss_threshold` while multi-fragment packets are sent, and increases it by a third, up to the maximum datagram size, once the loss rate drops to `recover_threshold`, after keeping the size for at least `hold`.
+//!
+//! The size is adjusted at most once per `ADJUST_INTERVAL`, for the loss rate to reflect the previous adjustment.
+
+use crate::config::AdaptiveFragmentSize;
+use parking_lot::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const ADJUST_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct FragmentSizer {
+    cfg: AdaptiveFragmentSize,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The reduced fragment size, or `None` for the maximum datagram size
+    size: Option<usize>,
+    at: Instant,
+}
+
+impl FragmentSizer {
+    pub fn new(cfg: AdaptiveFragmentSize) -> Self {
+        Self {
+            cfg,
+            state: Mutex::new(State {
+                size: None,
+                at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the fragment size for a packet of `len` bytes, adjusting it from the current datagram loss rate if the packet takes multiple fragments
+    pub fn size(&self, max_size: usize, len: usize, loss_rate: f64) -> usize {
+        let mut state = self.state.lock();
+        let size = state.size.map_or(max_size, |size| size.min(max_size));
+
+        // single-fragment packets do not tell whether fragmenting less would help
+        if len < size || state.at.elapsed() < ADJUST_INTERVAL {
+            return size;
+        }
+
+        let min_size = self.cfg.min_size.min(max_size);
+
+        if loss_rate >= self.cfg.loss_threshold && size > min_size {
+            let reduced = (size - size / 4).max(min_size);
+
+            log::info!(
+                "[relay] [packet] datagram loss {:.1}%, reducing the fragment size from {size} to {reduced} bytes",
+                loss_rate * 100.0,
+            );
+
+            state.size = Some(reduced);
+            state.at = Instant::now();
+            reduced
+        } else if loss_rate <= self.cfg.recover_threshold
+            && state.size.is_some()
+            && state.at.elapsed() >= self.cfg.hold
+        {
+            let restored = (size + size / 3).min(max_size);
+
+            log::info!(
+                "[relay] [packet] datagram loss recovered to {:.1}%, increasing the fragment size from {size} to {restored} bytes",
+                loss_rate * 100.0,
+            );
+
+            state.size = (restored < max_size).then_some(restored);
+            state.at = Instant::now();
+            restored
+        } else {
+            size
+        }
+    }
+}
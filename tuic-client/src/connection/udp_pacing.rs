@@ -0,0 +1,53 @@
+//! Pacing UDP packets relayed in mode `native`
+//!
+//! With `udp_native_pacing` set, each UDP association has a token bucket filled at `rate` bytes per second, holding at most `burst` bytes. A packet takes its size from the bucket, waiting for the bucket to refill if it is running short, so that bursts are smoothed out instead of overflowing the datagram send buffer of the connection.
+
+use crate::config::UdpNativePacing;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::{self, Instant};
+
+static BUCKETS: Lazy<Mutex<HashMap<u16, Bucket>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+/// Waits until a packet of `len` bytes of the association can be sent
+///
+/// The tokens are taken before waiting, so packets sent concurrently queue up behind each other.
+pub async fn pace(assoc_id: u16, cfg: &UdpNativePacing, len: usize) {
+    let rate = cfg.rate as f64;
+    let burst = cfg.burst as f64;
+
+    let delay = {
+        let mut buckets = BUCKETS.lock();
+        let now = Instant::now();
+
+        let bucket = buckets.entry(assoc_id).or_insert_with(|| Bucket {
+            tokens: burst,
+            at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst) - len as f64;
+        bucket.at = now;
+
+        if bucket.tokens < 0.0 {
+            Some(Duration::from_secs_f64(-bucket.tokens / rate))
+        } else {
+            None
+        }
+    };
+
+    if let Some(delay) = delay {
+        log::trace!("[relay] [packet] [{assoc_id:#06x}] [to-native] paced for {delay:?}");
+        time::sleep(delay).await;
+    }
+}
+
+pub fn remove(assoc_id: u16) {
+    BUCKETS.lock().remove(&assoc_id);
+}
@@ -0,0 +1,49 @@
+//! Keeping UDP associations carrying STUN traffic stable, so that hole punching for WebRTC / VoIP works through the relay
+//!
+//! With `udp_stun` set, an association is marked once a STUN message is relayed through it. Marked associations are relayed in mode `native` regardless of `udp_stream_fallback` and the routing rules if `native` is set, stay on the relay server they were first relayed through even if it becomes unhealthy, so the external address of the association does not change, and are kept for at least `timeout` while idle.
+
+use crate::config::UdpStun;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Duration};
+
+static ASSOCIATIONS: Lazy<Mutex<HashMap<u16, Duration>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+const STUN_HEADER_LEN: usize = 20;
+const STUN_MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
+
+/// Marks the association if the packet is a STUN message, returning whether the association is marked
+pub fn detect(assoc_id: u16, pkt: &[u8], cfg: &UdpStun) -> bool {
+    let mut assocs = ASSOCIATIONS.lock();
+
+    if assocs.contains_key(&assoc_id) {
+        return true;
+    }
+
+    if is_stun(pkt) {
+        log::info!("[relay] [packet] [{assoc_id:#06x}] STUN traffic detected");
+        assocs.insert(assoc_id, cfg.timeout);
+        true
+    } else {
+        false
+    }
+}
+
+/// Returns the minimum idle timeout of the association, if it is marked
+pub fn timeout(assoc_id: u16) -> Option<Duration> {
+    ASSOCIATIONS.lock().get(&assoc_id).copied()
+}
+
+pub fn remove(assoc_id: u16) {
+    ASSOCIATIONS.lock().remove(&assoc_id);
+}
+
+/// Checks for a STUN message (RFC 5389), which starts with two zero bits, and carries the magic cookie and the length of its attributes in the header
+fn is_stun(pkt: &[u8]) -> bool {
+    if pkt.len() < STUN_HEADER_LEN || pkt[0] & 0xc0 != 0 || pkt[4..8] != STUN_MAGIC_COOKIE {
+        return false;
+    }
+
+    let len = u16::from_be_bytes([pkt[2], pkt[3]]) as usize;
+    len % 4 == 0 && len == pkt.len() - STUN_HEADER_LEN
+}
@@ -0,0 +1,230 @@
+use crate::{error::Error, utils::UpstreamProxy};
+use bytes::{BufMut, BytesMut};
+use quinn::{
+    udp::{RecvMeta, Transmit, UdpState},
+    AsyncUdpSocket,
+};
+use socks5_proto::{
+    handshake::password::{Request as PasswordRequest, Response as PasswordResponse},
+    Address, Command, HandshakeMethod, HandshakeRequest, HandshakeResponse, Reply, Request,
+    Response,
+};
+use std::{
+    io::{Error as IoError, ErrorKind, IoSliceMut, Result as IoResult},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    io::ReadBuf,
+    net::{self, TcpStream, UdpSocket},
+};
+
+/// A UDP socket relaying datagrams through an upstream SOCKS5 proxy with UDP ASSOCIATE
+///
+/// The socket reports an IPv6 local address, so quinn accepts both IPv4 and IPv6 (as mapped addresses) server addresses. The association lives as long as the socket, which holds the control connection.
+#[derive(Debug)]
+pub struct Socks5UdpSocket {
+    socket: UdpSocket,
+    _ctrl: TcpStream,
+}
+
+impl Socks5UdpSocket {
+    pub async fn bind(proxy: &UpstreamProxy) -> Result<Self, Error> {
+        let mut ctrl = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+
+        let method = match (&proxy.username, &proxy.password) {
+            (Some(_), Some(_)) => HandshakeMethod::Password,
+            _ => HandshakeMethod::None,
+        };
+
+        HandshakeRequest::new(vec![method])
+            .write_to(&mut ctrl)
+            .await?;
+
+        let resp = HandshakeResponse::read_from(&mut ctrl).await?;
+
+        if resp.method != method {
+            return Err(Error::UpstreamProxy("no acceptable authentication method"));
+        }
+
+        if let (Some(username), Some(password)) = (&proxy.username, &proxy.password) {
+            PasswordRequest::new(username.clone(), password.clone())
+                .write_to(&mut ctrl)
+                .await?;
+
+            if !PasswordResponse::read_from(&mut ctrl).await?.status {
+                return Err(Error::UpstreamProxy("authentication failed"));
+            }
+        }
+
+        Request::new(Command::Associate, Address::unspecified())
+            .write_to(&mut ctrl)
+            .await?;
+
+        let resp = Response::read_from(&mut ctrl).await?;
+
+        if resp.reply != Reply::Succeeded {
+            return Err(Error::UpstreamProxy("UDP ASSOCIATE rejected"));
+        }
+
+        let relay_addr = match resp.address {
+            // the proxy may reply with an unspecified address, meaning the address of the control connection
+            Address::SocketAddress(addr) if addr.ip().is_unspecified() => {
+                SocketAddr::new(ctrl.peer_addr()?.ip(), addr.port())
+            }
+            Address::SocketAddress(addr) => addr,
+            Address::DomainAddress(domain, port) => net::lookup_host((domain, port))
+                .await?
+                .next()
+                .ok_or(Error::UpstreamProxy("cannot resolve the UDP relay address"))?,
+        };
+
+        let bind_addr = match relay_addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(relay_addr).await?;
+
+        log::debug!(
+            "[relay] [upstream] UDP associated through {host}:{port}, relaying at {relay_addr}",
+            host = proxy.host,
+            port = proxy.port,
+        );
+
+        Ok(Self {
+            socket,
+            _ctrl: ctrl,
+        })
+    }
+
+    fn encapsulate(dst: SocketAddr, payload: &[u8]) -> BytesMut {
+        let dst = match dst {
+            SocketAddr::V6(addr) => match addr.ip().to_ipv4_mapped() {
+                Some(ip) => SocketAddr::from((ip, addr.port())),
+                None => SocketAddr::V6(addr),
+            },
+            addr => addr,
+        };
+
+        let addr = Address::SocketAddress(dst);
+        let mut buf = BytesMut::with_capacity(3 + addr.serialized_len() + payload.len());
+        buf.put_slice(&[0, 0, 0]);
+        addr.write_to_buf(&mut buf);
+        buf.put_slice(payload);
+        buf
+    }
+
+    /// Parses the SOCKS5 UDP header, returning the source address and the header length
+    fn decapsulate(buf: &[u8]) -> Option<(SocketAddr, usize)> {
+        // RSV (2 bytes), FRAG, ATYP
+        if buf.len() < 4 || buf[2] != 0 {
+            return None;
+        }
+
+        let (ip, offset) = match buf[3] {
+            0x01 if buf.len() >= 10 => {
+                let ip = <[u8; 4]>::try_from(&buf[4..8]).ok()?;
+                (IpAddr::from(ip), 8)
+            }
+            0x04 if buf.len() >= 22 => {
+                let ip = <[u8; 16]>::try_from(&buf[4..20]).ok()?;
+                (IpAddr::from(ip), 20)
+            }
+            _ => return None,
+        };
+
+        let port = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+
+        // report IPv4 sources as mapped addresses, matching the IPv6 local address
+        let ip = match ip {
+            IpAddr::V4(ip) => IpAddr::V6(ip.to_ipv6_mapped()),
+            ip => ip,
+        };
+
+        Some((SocketAddr::new(ip, port), offset + 2))
+    }
+}
+
+impl AsyncUdpSocket for Socks5UdpSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<IoResult<usize>> {
+        let mut sent = 0;
+
+        for transmit in transmits {
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            let mut segments = transmit.contents.chunks(segment_size.max(1));
+
+            let Some(first) = segments.next() else {
+                sent += 1;
+                continue;
+            };
+
+            let buf = Self::encapsulate(transmit.destination, first);
+
+            match self.socket.poll_send(cx, &buf) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) if sent == 0 => return Poll::Ready(Err(err)),
+                Poll::Pending if sent == 0 => return Poll::Pending,
+                Poll::Ready(Err(_)) | Poll::Pending => break,
+            }
+
+            // remaining segments are sent on a best-effort basis, as QUIC recovers lost packets
+            for segment in segments {
+                let buf = Self::encapsulate(transmit.destination, segment);
+                let _ = self.socket.try_send(&buf);
+            }
+
+            sent += 1;
+        }
+
+        Poll::Ready(Ok(sent))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<IoResult<usize>> {
+        let (Some(buf), Some(meta)) = (bufs.first_mut(), meta.first_mut()) else {
+            return Poll::Ready(Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "no buffer for receiving",
+            )));
+        };
+
+        loop {
+            let mut read_buf = ReadBuf::new(buf);
+            ready!(self.socket.poll_recv(cx, &mut read_buf))?;
+            let len = read_buf.filled().len();
+
+            let Some((addr, header_len)) = Self::decapsulate(&buf[..len]) else {
+                log::debug!("[relay] [upstream] dropped invalid datagram from the proxy");
+                continue;
+            };
+
+            buf.copy_within(header_len..len, 0);
+
+            *meta = RecvMeta {
+                addr,
+                len: len - header_len,
+                stride: len - header_len,
+                ecn: None,
+                dst_ip: None,
+            };
+
+            return Poll::Ready(Ok(1));
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        let port = self.socket.local_addr()?.port();
+        Ok(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))
+    }
+}
@@ -0,0 +1,80 @@
+use ring::digest::{self, SHA256};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, CertificateError, Error as RustlsError, ServerName,
+};
+use std::time::SystemTime;
+
+/// Accepts the server certificate only if its SHA-256 fingerprint is one of the pinned ones, without verifying the certificate chain
+///
+/// Signatures in the handshake are still verified against the certificate.
+pub struct PinnedCertVerifier {
+    fingerprints: Vec<[u8; 32]>,
+}
+
+impl PinnedCertVerifier {
+    pub fn new(fingerprints: Vec<[u8; 32]>) -> Self {
+        Self { fingerprints }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, RustlsError> {
+        let fingerprint = digest::digest(&SHA256, &end_entity.0);
+
+        if self
+            .fingerprints
+            .iter()
+            .any(|pinned| pinned == fingerprint.as_ref())
+        {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            log::warn!(
+                "[relay] server certificate fingerprint {} does not match any pinned one",
+                hex(fingerprint.as_ref()),
+            );
+            Err(RustlsError::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+}
+
+/// Accepts any server certificate
+///
+/// Signatures in the handshake are still verified against the certificate, but the connection is open to man-in-the-middle attacks.
+pub struct InsecureVerifier;
+
+impl ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, RustlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,950 @@
+//! A RESTful API compatible with the external controller of Clash, for managing the client from existing dashboards
+
+use self::tracker::Tracked;
+use crate::{
+    config::{Controller as ControllerConfig, Local},
+    connection::{self, Connection as TuicConnection},
+    error::Error,
+    events,
+    reload::Reloader,
+    router::{Matcher, Outbound, Router},
+    socks5::Server as Socks5Server,
+    telemetry::Telemetry,
+    utils::{self, Balance},
+};
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use hyper::{
+    body,
+    header::{self, HeaderValue},
+    server::conn::AddrIncoming,
+    service::{make_service_fn, service_fn},
+    upgrade::{self, Upgraded},
+    Body, Method, Request, Response, Server as HyperServer, StatusCode,
+};
+use log::LevelFilter;
+use parking_lot::RwLock;
+use rustls::{ClientConfig as RustlsClientConfig, ClientConnection, RootCertStore, ServerName};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    io::{Error as IoError, ErrorKind},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::broadcast::error::RecvError,
+    time,
+};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::{
+    model::{AssemblyCounts, TaskCounts, TaskEvent, TaskKind, TaskMetrics},
+    Address,
+};
+
+pub mod tracker;
+
+static CONTROLLER: RwLock<Option<Arc<Controller>>> = RwLock::new(None);
+
+pub struct Controller {
+    server: SocketAddr,
+    secret: Option<String>,
+    socks_port: u16,
+    log_level: LevelFilter,
+}
+
+impl Controller {
+    /// Sets up the controller. Changes take effect when the controller is started again
+    pub fn set_config(
+        cfg: Option<ControllerConfig>,
+        socks_port: u16,
+        log_level: LevelFilter,
+    ) -> Result<(), Error> {
+        *CONTROLLER.write() = cfg.map(|cfg| {
+            Arc::new(Self {
+                server: cfg.server,
+                secret: cfg.secret,
+                socks_port,
+                log_level,
+            })
+        });
+
+        Ok(())
+    }
+
+    pub async fn start() {
+        let Some(controller) = CONTROLLER.read().clone() else {
+            return;
+        };
+
+        let incoming = match AddrIncoming::bind(&controller.server) {
+            Ok(incoming) => incoming,
+            Err(err) => {
+                log::error!(
+                    "[controller] failed to bind to {}: {err}",
+                    controller.server
+                );
+                return;
+            }
+        };
+
+        log::warn!(
+            "[controller] server started, listening on {}",
+            incoming.local_addr()
+        );
+
+        let make_svc = make_service_fn(|_| {
+            let controller = controller.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let controller = controller.clone();
+                    async move { Ok::<_, Infallible>(controller.handle(req).await) }
+                }))
+            }
+        });
+
+        if let Err(err) = HyperServer::builder(incoming).serve(make_svc).await {
+            log::error!("[controller] server error: {err}");
+        }
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let mut res = if req.method() == Method::OPTIONS {
+            empty(StatusCode::NO_CONTENT)
+        } else if !self.is_authorized(&req) {
+            message(StatusCode::UNAUTHORIZED, "Unauthorized")
+        } else {
+            self.route(req).await
+        };
+
+        let headers = res.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, PUT, PATCH, DELETE, OPTIONS"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("Content-Type, Authorization"),
+        );
+
+        res
+    }
+
+    fn is_authorized(&self, req: &Request<Body>) -> bool {
+        let Some(secret) = &self.secret else {
+            return true;
+        };
+
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        // browsers cannot set headers for WebSocket connections, so the secret can also be passed in the query
+        let token = query(req).remove("token");
+
+        bearer == Some(secret.as_str()) || token.as_ref() == Some(secret)
+    }
+
+    async fn route(&self, req: Request<Body>) -> Response<Body> {
+        let path = req.uri().path().trim_end_matches('/').to_owned();
+        let segments = path
+            .split('/')
+            .skip(1)
+            .map(utils::percent_decode)
+            .collect::<Vec<_>>();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+        match (req.method(), segments.as_slice()) {
+            (&Method::GET, [] | [""]) => json_response(json!({ "hello": "tuic" })),
+            (&Method::GET, ["version"]) => json_response(json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "premium": false,
+            })),
+            (&Method::GET, ["configs"]) => json_response(json!({
+                "port": 0,
+                "socks-port": self.socks_port,
+                "redir-port": 0,
+                "tproxy-port": 0,
+                "mixed-port": 0,
+                "allow-lan": false,
+                "mode": Router::mode().to_string(),
+                "log-level": clash_log_level(self.log_level),
+            })),
+            (&Method::PUT, ["configs"]) => {
+                // the body is `{ "path": "PATH" }`, with the path optional
+                let body = match body::to_bytes(req.into_body()).await {
+                    Ok(body) => body,
+                    Err(_) => return message(StatusCode::BAD_REQUEST, "Body invalid"),
+                };
+
+                let path = match serde_json::from_slice::<Value>(&body) {
+                    Ok(body) => body["path"]
+                        .as_str()
+                        .filter(|path| !path.is_empty())
+                        .map(PathBuf::from),
+                    Err(_) if body.is_empty() => None,
+                    Err(_) => return message(StatusCode::BAD_REQUEST, "Body invalid"),
+                };
+
+                match Reloader::reload(path) {
+                    Ok(()) => empty(StatusCode::NO_CONTENT),
+                    Err(err) => {
+                        log::error!("[controller] failed reloading config: {err}");
+                        let mut res = json_response(json!({ "message": err.to_string() }));
+                        *res.status_mut() = StatusCode::BAD_REQUEST;
+                        res
+                    }
+                }
+            }
+            (&Method::PATCH, ["configs"]) => {
+                // only the routing mode can be changed, other fields are ignored
+                let body = match body::to_bytes(req.into_body()).await {
+                    Ok(body) => body,
+                    Err(_) => return message(StatusCode::BAD_REQUEST, "Body invalid"),
+                };
+
+                let Ok(body) = serde_json::from_slice::<Value>(&body) else {
+                    return message(StatusCode::BAD_REQUEST, "Body invalid");
+                };
+
+                if let Some(mode) = body["mode"].as_str() {
+                    match mode.parse() {
+                        Ok(mode) => Router::set_mode(mode),
+                        Err(err) => return message(StatusCode::BAD_REQUEST, err),
+                    }
+                }
+
+                empty(StatusCode::NO_CONTENT)
+            }
+            (&Method::GET, ["proxies"]) => {
+                let proxies = proxies()
+                    .into_iter()
+                    .map(|proxy| (proxy["name"].as_str().unwrap().to_owned(), proxy))
+                    .collect::<serde_json::Map<_, _>>();
+                json_response(json!({ "proxies": proxies }))
+            }
+            (&Method::GET, ["proxies", name]) => match find_proxy(name) {
+                Some(proxy) => json_response(proxy),
+                None => message(StatusCode::NOT_FOUND, "Resource not found"),
+            },
+            (&Method::GET, ["proxies", name, "delay"]) => {
+                let name = name.to_string();
+                let query = query(&req);
+
+                let Some(url) = query.get("url") else {
+                    return message(StatusCode::BAD_REQUEST, "Body invalid");
+                };
+
+                let timeout = query
+                    .get("timeout")
+                    .and_then(|timeout| timeout.parse().ok())
+                    .map_or(Duration::from_secs(5), Duration::from_millis);
+
+                if find_proxy(&name).is_none() {
+                    return message(StatusCode::NOT_FOUND, "Resource not found");
+                }
+
+                match time::timeout(timeout, delay_test(&name, url)).await {
+                    Ok(Ok(delay)) => json_response(json!({ "delay": delay.as_millis() as u64 })),
+                    Ok(Err(err)) => {
+                        log::debug!("[controller] [delay] [{name}] {err}");
+                        message(
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "An error occurred in the delay test",
+                        )
+                    }
+                    Err(_) => message(StatusCode::REQUEST_TIMEOUT, "Timeout"),
+                }
+            }
+            (&Method::GET, ["rules"]) => json_response(json!({ "rules": rules() })),
+            (&Method::GET, ["connections"]) => {
+                if is_websocket(&req) {
+                    websocket(req, |mut sink| async move {
+                        loop {
+                            send_json(&mut sink, connections()).await?;
+                            time::sleep(Duration::from_secs(1)).await;
+                        }
+                    })
+                } else {
+                    json_response(connections())
+                }
+            }
+            (&Method::DELETE, ["connections"]) => {
+                tracker::close_all();
+                empty(StatusCode::NO_CONTENT)
+            }
+            (&Method::DELETE, ["connections", id]) => {
+                match id.parse().ok().filter(|id| tracker::close(*id)) {
+                    Some(_) => empty(StatusCode::NO_CONTENT),
+                    None => message(StatusCode::NOT_FOUND, "Resource not found"),
+                }
+            }
+            (&Method::GET, ["inbounds"]) => json_response(json!({ "inbounds": inbounds() })),
+            (&Method::PUT, ["inbounds", name]) => {
+                // the body has the fields of a listener in the `local` section, with the name taken from the path
+                let body = match body::to_bytes(req.into_body()).await {
+                    Ok(body) => body,
+                    Err(_) => return message(StatusCode::BAD_REQUEST, "Body invalid"),
+                };
+
+                let mut local = match serde_json::from_slice::<Value>(&body) {
+                    Ok(local @ Value::Object(_)) => local,
+                    _ => return message(StatusCode::BAD_REQUEST, "Body invalid"),
+                };
+
+                local["name"] = Value::from(*name);
+
+                let res = tuic_config::from_value::<Local, serde_json::Error>(local, "local")
+                    .map_err(|err| err.to_string())
+                    .and_then(|local| Socks5Server::add(local).map_err(|err| err.to_string()));
+
+                match res {
+                    Ok(()) => {
+                        log::warn!("[controller] local listener {name} added");
+                        empty(StatusCode::NO_CONTENT)
+                    }
+                    Err(err) => {
+                        log::error!("[controller] failed adding local listener {name}: {err}");
+                        let mut res = json_response(json!({ "message": err }));
+                        *res.status_mut() = StatusCode::BAD_REQUEST;
+                        res
+                    }
+                }
+            }
+            (&Method::DELETE, ["inbounds", name]) => {
+                if Socks5Server::remove(name) {
+                    log::warn!("[controller] local listener {name} removed");
+                    empty(StatusCode::NO_CONTENT)
+                } else {
+                    message(StatusCode::NOT_FOUND, "Resource not found")
+                }
+            }
+            (&Method::GET, ["stats"]) => {
+                if is_websocket(&req) {
+                    websocket(req, |mut sink| async move {
+                        loop {
+                            send_json(&mut sink, stats()).await?;
+                            time::sleep(Duration::from_secs(1)).await;
+                        }
+                    })
+                } else {
+                    json_response(stats())
+                }
+            }
+            (&Method::GET, ["traffic"]) => {
+                if is_websocket(&req) {
+                    websocket(req, |mut sink| async move {
+                        let mut traffic = Traffic::new();
+
+                        loop {
+                            time::sleep(Duration::from_secs(1)).await;
+                            send_json(&mut sink, traffic.next()).await?;
+                        }
+                    })
+                } else {
+                    let (mut tx, body) = Body::channel();
+
+                    tokio::spawn(async move {
+                        let mut traffic = Traffic::new();
+
+                        loop {
+                            time::sleep(Duration::from_secs(1)).await;
+                            let chunk = format!("{}\n", traffic.next());
+
+                            if tx.send_data(chunk.into()).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    let mut res = Response::new(body);
+                    res.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    );
+                    res
+                }
+            }
+            (&Method::GET, ["events"]) => {
+                let mut events = events::subscribe();
+
+                if is_websocket(&req) {
+                    websocket(req, |mut sink| async move {
+                        loop {
+                            match events.recv().await {
+                                Ok(event) => send_json(&mut sink, event.to_json()).await?,
+                                Err(RecvError::Lagged(_)) => {}
+                                Err(RecvError::Closed) => return Ok(()),
+                            }
+                        }
+                    })
+                } else {
+                    let (mut tx, body) = Body::channel();
+
+                    tokio::spawn(async move {
+                        loop {
+                            let event = match events.recv().await {
+                                Ok(event) => event,
+                                Err(RecvError::Lagged(_)) => continue,
+                                Err(RecvError::Closed) => break,
+                            };
+
+                            let chunk = format!("{}\n", event.to_json());
+
+                            if tx.send_data(chunk.into()).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    let mut res = Response::new(body);
+                    res.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    );
+                    res
+                }
+            }
+            _ => message(StatusCode::NOT_FOUND, "Resource not found"),
+        }
+    }
+}
+
+/// Per-second traffic of the local inbound
+struct Traffic {
+    last: (u64, u64),
+}
+
+impl Traffic {
+    fn new() -> Self {
+        Self {
+            last: tracker::traffic_total(),
+        }
+    }
+
+    fn next(&mut self) -> Value {
+        let total = tracker::traffic_total();
+        let (up, down) = (total.0 - self.last.0, total.1 - self.last.1);
+        self.last = total;
+        json!({ "up": up, "down": down })
+    }
+}
+
+fn proxies() -> Vec<Value> {
+    let servers = TuicConnection::servers();
+    let now = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+
+    let group_type = match TuicConnection::balance() {
+        Balance::Failover => "Fallback",
+        _ => "LoadBalance",
+    };
+
+    let group_now = servers
+        .iter()
+        .find(|server| server.active)
+        .map_or(String::new(), |server| server.name.clone());
+
+    let mut proxies = servers
+        .iter()
+        .map(|server| {
+            let history = match server.rtt {
+                Some(rtt) if server.healthy => {
+                    json!([{ "time": now, "delay": rtt.as_millis() as u64 }])
+                }
+                _ => json!([]),
+            };
+
+            json!({
+                "name": server.name,
+                "type": "Tuic",
+                "udp": true,
+                "history": history,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    proxies.push(json!({
+        "name": "PROXY",
+        "type": group_type,
+        "udp": true,
+        "all": servers.iter().map(|server| server.name.clone()).collect::<Vec<_>>(),
+        "now": group_now,
+        "history": [],
+    }));
+
+    proxies.push(json!({
+        "name": "GLOBAL",
+        "type": "Selector",
+        "udp": true,
+        "all": ["PROXY", "DIRECT", "REJECT"],
+        "now": "PROXY",
+        "history": [],
+    }));
+
+    proxies.push(json!({ "name": "DIRECT", "type": "Direct", "udp": true, "history": [] }));
+    proxies.push(json!({ "name": "REJECT", "type": "Reject", "udp": true, "history": [] }));
+
+    proxies
+}
+
+fn find_proxy(name: &str) -> Option<Value> {
+    proxies().into_iter().find(|proxy| proxy["name"] == name)
+}
+
+fn rules() -> Vec<Value> {
+    let mut rules = Router::rules()
+        .iter()
+        .map(|rule| {
+            let rule_type = match &rule.matcher {
+                Matcher::GeoSite(_, _) => "GeoSite",
+                Matcher::DomainList(_) => "RuleSet",
+                Matcher::Domain(_) => "DomainSuffix",
+                Matcher::Full(_) => "Domain",
+                Matcher::Keyword(_) => "DomainKeyword",
+                Matcher::Regexp(_) => "DomainRegex",
+                Matcher::Process(_) => "ProcessName",
+                Matcher::ProcessPath(_) => "ProcessPath",
+                Matcher::IpCidr(_) => "IPCIDR",
+                Matcher::Bypass => "Bypass",
+            };
+
+            json!({
+                "type": rule_type,
+                "payload": rule.matcher.to_string(),
+                "proxy": outbound_name(rule.outbound),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    rules.push(json!({
+        "type": "Match",
+        "payload": "",
+        "proxy": outbound_name(Router::default_outbound()),
+    }));
+
+    rules
+}
+
+fn connections() -> Value {
+    let (upload_total, download_total) = tracker::traffic_total();
+
+    let conns = tracker::connections()
+        .iter()
+        .map(|tracked| connection(tracked))
+        .collect::<Vec<_>>();
+
+    json!({
+        "uploadTotal": upload_total,
+        "downloadTotal": download_total,
+        "connections": conns,
+    })
+}
+
+/// The task counters of a connection to a relay server, with the logged task events
+fn tasks(metrics: &TaskMetrics, events: &[TaskEvent]) -> Value {
+    let counts = |counts: &TaskCounts| {
+        json!({
+            "current": counts.current,
+            "highWater": counts.high_water,
+            "created": counts.created,
+            "closed": counts.closed,
+            "creationRate": counts.creation_rate,
+            "teardownRate": counts.teardown_rate,
+        })
+    };
+
+    let events = events
+        .iter()
+        .map(|event| {
+            json!({
+                "ago": event.at.elapsed().as_millis() as u64,
+                "kind": match event.kind {
+                    TaskKind::Connect => "connect",
+                    TaskKind::Associate => "associate",
+                },
+                "created": event.created,
+                "count": event.count,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "connect": counts(&metrics.connect),
+        "associate": counts(&metrics.associate),
+        "events": events,
+    })
+}
+
+/// The reassembly counters of received packets of a connection to a relay server, in total and by UDP session
+fn assembly(total: &AssemblyCounts, sessions: &[(u16, AssemblyCounts)]) -> Value {
+    let counts = |counts: &AssemblyCounts| {
+        json!({
+            "fragments": counts.fragments,
+            "assembled": counts.assembled,
+            "recovered": counts.recovered,
+            "timedOut": counts.timed_out,
+            "rejected": counts.rejected,
+            "pending": counts.pending,
+        })
+    };
+
+    let mut value = counts(total);
+    value["associations"] = sessions
+        .iter()
+        .map(|(assoc_id, session)| {
+            let mut value = counts(session);
+            value["id"] = json!(assoc_id);
+            value
+        })
+        .collect();
+
+    value
+}
+
+/// Traffic statistics by relay server and by rule, for status displays not speaking the Clash API
+pub fn stats() -> Value {
+    let (upload_total, download_total) = tracker::traffic_total();
+    let mut by_server = tracker::stats_by_server();
+    let by_rule = tracker::stats_by_rule();
+
+    let servers = TuicConnection::servers()
+        .into_iter()
+        .map(|server| {
+            let stats = by_server.remove(&server.name).unwrap_or_default();
+
+            let path = Telemetry::sample(&server.name).map(|sample| {
+                json!({
+                    "time": humantime::format_rfc3339_millis(sample.time).to_string(),
+                    "connections": sample.connections,
+                    "rtt": sample.rtt.as_secs_f64() * 1000.0,
+                    "cwnd": sample.cwnd,
+                    "sentPackets": sample.sent_packets,
+                    "lostPackets": sample.lost_packets,
+                    "loss": sample.loss(),
+                    "congestionEvents": sample.congestion_events,
+                })
+            });
+
+            json!({
+                "name": server.name,
+                "healthy": server.healthy,
+                "active": server.active,
+                "rtt": server.current_rtt.or(server.rtt).map(|rtt| rtt.as_millis() as u64),
+                "migrations": server.migrations,
+                "upload": stats.upload,
+                "download": stats.download,
+                "connections": stats.connections,
+                "tasks": server.tasks.iter().map(|(metrics, events)| tasks(metrics, events)).collect::<Vec<_>>(),
+                "assembly": server.assembly.iter().map(|(total, sessions)| assembly(total, sessions)).collect::<Vec<_>>(),
+                "path": path,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut rules = by_rule
+        .into_iter()
+        .map(|(rule, stats)| {
+            json!({
+                "rule": rule,
+                "upload": stats.upload,
+                "download": stats.download,
+                "connections": stats.connections,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    rules.sort_by(|a, b| a["rule"].as_str().cmp(&b["rule"].as_str()));
+
+    let associations = connection::udp_associations()
+        .into_iter()
+        .map(|(assoc_id, assoc)| {
+            json!({
+                "id": assoc_id,
+                "mode": assoc.mode.to_string(),
+                "reason": assoc.reason.to_string(),
+                "since": assoc.since.elapsed().as_secs(),
+                "nativePackets": assoc.native_packets,
+                "quicPackets": assoc.quic_packets,
+                "switches": assoc.switches,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "uploadTotal": upload_total,
+        "downloadTotal": download_total,
+        "connections": tracker::connections().len(),
+        "servers": servers,
+        "rules": rules,
+        "associations": associations,
+        "inbounds": inbounds(),
+    })
+}
+
+/// The local listeners with their counters
+fn inbounds() -> Value {
+    let inbounds = Socks5Server::inbounds()
+        .into_iter()
+        .map(|inbound| {
+            json!({
+                "name": inbound.name,
+                "addr": inbound.addr.to_string(),
+                "protocol": inbound.protocol.to_string(),
+                "active": inbound.active,
+                "connections": inbound.connections,
+                "connects": inbound.connects,
+                "associates": inbound.associates,
+                "binds": inbound.binds,
+                "httpRequests": inbound.http_requests,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Value::Array(inbounds)
+}
+
+fn connection(tracked: &Tracked) -> Value {
+    let (host, dst_ip, dst_port) = match &tracked.destination {
+        Address::DomainAddress(domain, port) => (domain.clone(), String::new(), *port),
+        Address::SocketAddress(addr) => (String::new(), addr.ip().to_string(), addr.port()),
+        Address::None => (String::new(), String::new(), 0),
+    };
+
+    json!({
+        "id": tracked.id.to_string(),
+        "metadata": {
+            "network": tracked.network,
+            "type": "Socks5",
+            "sourceIP": tracked.source.ip().to_string(),
+            "sourcePort": tracked.source.port().to_string(),
+            "destinationIP": dst_ip,
+            "destinationPort": dst_port.to_string(),
+            "host": host,
+        },
+        "upload": tracked.upload(),
+        "download": tracked.download(),
+        "start": humantime::format_rfc3339_millis(tracked.start).to_string(),
+        "chains": [tracked.chain],
+        "rule": tracked.rule,
+        "rulePayload": "",
+    })
+}
+
+/// Returns the name of the outbound in the proxy list
+pub fn outbound_name(outbound: Outbound) -> &'static str {
+    match outbound {
+        Outbound::Proxy => "PROXY",
+        Outbound::Direct => "DIRECT",
+        Outbound::Block => "REJECT",
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Measures the time until the first byte of the response to a request to the URL through the proxy
+///
+/// For HTTPS URLs, the TLS ClientHello is sent as the request, so no certificate verification is involved.
+async fn delay_test(name: &str, url: &str) -> Result<Duration, Error> {
+    let (is_https, host, port, path) =
+        parse_url(url).ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "invalid URL"))?;
+
+    let addr = match host.parse() {
+        Ok(ip) => Address::SocketAddress(SocketAddr::new(ip, port)),
+        Err(_) => Address::DomainAddress(host.clone(), port),
+    };
+
+    let start = Instant::now();
+
+    let mut stream: Box<dyn Stream> = match name {
+        "DIRECT" => Box::new(TcpStream::connect((host.as_str(), port)).await?),
+        "REJECT" => {
+            return Err(Error::from(IoError::new(
+                ErrorKind::ConnectionRefused,
+                "rejected",
+            )))
+        }
+        "PROXY" | "GLOBAL" => Box::new(
+            TuicConnection::get_for_connect(&addr)
+                .await?
+                .connect(addr, None)
+                .await?
+                .compat(),
+        ),
+        name => match TuicConnection::get_for_server(name).await {
+            Some(conn) => Box::new(conn?.connect(addr, None).await?.compat()),
+            None => return Err(Error::from(IoError::from(ErrorKind::NotFound))),
+        },
+    };
+
+    let req = if is_https {
+        let config = RustlsClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(host.as_str())
+            .map_err(|err| IoError::new(ErrorKind::InvalidInput, err))?;
+        let mut tls = ClientConnection::new(Arc::new(config), server_name)?;
+
+        let mut hello = Vec::new();
+        tls.write_tls(&mut hello)?;
+        hello
+    } else {
+        format!("HEAD {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n").into_bytes()
+    };
+
+    stream.write_all(&req).await?;
+
+    if stream.read(&mut [0; 1]).await? == 0 {
+        return Err(Error::from(IoError::from(ErrorKind::UnexpectedEof)));
+    }
+
+    let delay = start.elapsed();
+    let _ = stream.shutdown().await;
+
+    Ok(delay)
+}
+
+/// Parses a URL into `(is_https, host, port, path)`
+fn parse_url(url: &str) -> Option<(bool, String, u16, String)> {
+    let (scheme, rest) = url.split_once("://")?;
+
+    let (is_https, default_port) = match scheme.to_ascii_lowercase().as_str() {
+        "http" => (false, 80),
+        "https" => (true, 443),
+        _ => return None,
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+        _ => (authority, default_port),
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    if host.is_empty() {
+        return None;
+    }
+
+    Some((is_https, host.to_owned(), port, path.to_owned()))
+}
+
+fn is_websocket(req: &Request<Body>) -> bool {
+    req.headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.eq_ignore_ascii_case("websocket"))
+}
+
+type WebSocketSink = SplitSink<WebSocketStream<Upgraded>, Message>;
+
+/// Accepts the WebSocket upgrade and runs the handler, which pushes messages until the client goes away
+fn websocket<F, Fut>(mut req: Request<Body>, handler: F) -> Response<Body>
+where
+    F: FnOnce(WebSocketSink) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), IoError>> + Send,
+{
+    let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY) else {
+        return message(StatusCode::BAD_REQUEST, "Invalid WebSocket handshake");
+    };
+
+    let accept = derive_accept_key(key.as_bytes());
+
+    tokio::spawn(async move {
+        let upgraded = match upgrade::on(&mut req).await {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                log::warn!("[controller] WebSocket upgrade error: {err}");
+                return;
+            }
+        };
+
+        let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+        let (sink, mut stream) = ws.split();
+
+        // stop pushing once the client closes the connection
+        tokio::select! {
+            _ = handler(sink) => {}
+            _ = async { while let Some(Ok(_)) = stream.next().await {} } => {}
+        }
+    });
+
+    let mut res = empty(StatusCode::SWITCHING_PROTOCOLS);
+    let headers = res.headers_mut();
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(
+        header::SEC_WEBSOCKET_ACCEPT,
+        HeaderValue::from_str(&accept).unwrap(),
+    );
+    res
+}
+
+async fn send_json(sink: &mut WebSocketSink, value: Value) -> Result<(), IoError> {
+    sink.send(Message::Text(value.to_string()))
+        .await
+        .map_err(|err| IoError::new(ErrorKind::BrokenPipe, err))
+}
+
+fn json_response(value: Value) -> Response<Body> {
+    let mut res = Response::new(Body::from(value.to_string()));
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    res
+}
+
+fn message(status: StatusCode, msg: &'static str) -> Response<Body> {
+    let mut res = json_response(json!({ "message": msg }));
+    *res.status_mut() = status;
+    res
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = status;
+    res
+}
+
+fn query(req: &Request<Body>) -> HashMap<String, String> {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (utils::percent_decode(key), utils::percent_decode(value)))
+        .collect()
+}
+
+fn clash_log_level(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "silent",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warning",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug | LevelFilter::Trace => "debug",
+    }
+}
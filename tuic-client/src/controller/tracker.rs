@@ -0,0 +1,302 @@
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    io::Result as IoResult,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+};
+use tuic::Address;
+
+static CONNECTIONS: Lazy<Mutex<HashMap<u64, Arc<Tracked>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static UPLOAD_TOTAL: AtomicU64 = AtomicU64::new(0);
+static DOWNLOAD_TOTAL: AtomicU64 = AtomicU64::new(0);
+static CLOSED: Lazy<Mutex<Closed>> = Lazy::new(|| Mutex::new(Closed::default()));
+
+/// Traffic of closed connections, accumulated by rule and by relay server
+#[derive(Default)]
+struct Closed {
+    rules: HashMap<String, (u64, u64)>,
+    servers: HashMap<String, (u64, u64)>,
+}
+
+/// Traffic and the number of active connections of a rule or a relay server
+#[derive(Default)]
+pub struct Stats {
+    pub upload: u64,
+    pub download: u64,
+    pub connections: usize,
+}
+
+/// A connection from the local inbound, tracked for the controller API
+pub struct Tracked {
+    pub id: u64,
+    pub network: &'static str,
+    pub source: SocketAddr,
+    pub destination: Address,
+    pub chain: String,
+    pub rule: String,
+    pub start: SystemTime,
+    server: Mutex<Option<String>>,
+    upload: AtomicU64,
+    download: AtomicU64,
+    close: Notify,
+}
+
+impl Tracked {
+    /// Returns the relay server the connection is relayed through, if any
+    pub fn server(&self) -> Option<String> {
+        self.server.lock().clone()
+    }
+
+    pub fn set_server(&self, server: &str) {
+        let mut current = self.server.lock();
+
+        if current.as_deref() != Some(server) {
+            *current = Some(server.to_owned());
+        }
+    }
+
+    pub fn upload(&self) -> u64 {
+        self.upload.load(Ordering::Relaxed)
+    }
+
+    pub fn download(&self) -> u64 {
+        self.download.load(Ordering::Relaxed)
+    }
+
+    pub fn add_upload(&self, n: usize) {
+        self.upload.fetch_add(n as u64, Ordering::Relaxed);
+        UPLOAD_TOTAL.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_download(&self, n: usize) {
+        self.download.fetch_add(n as u64, Ordering::Relaxed);
+        DOWNLOAD_TOTAL.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Resolves when the connection is closed from the controller API
+    pub async fn closed(&self) {
+        self.close.notified().await;
+    }
+}
+
+/// A handle removing the tracked connection when dropped
+pub struct TrackedGuard(Arc<Tracked>);
+
+impl TrackedGuard {
+    pub fn new(
+        network: &'static str,
+        source: SocketAddr,
+        destination: Address,
+        chain: String,
+        rule: String,
+    ) -> Self {
+        let tracked = Arc::new(Tracked {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            network,
+            source,
+            destination,
+            chain,
+            rule,
+            start: SystemTime::now(),
+            server: Mutex::new(None),
+            upload: AtomicU64::new(0),
+            download: AtomicU64::new(0),
+            close: Notify::new(),
+        });
+
+        CONNECTIONS.lock().insert(tracked.id, tracked.clone());
+        Self(tracked)
+    }
+
+    pub fn tracked(&self) -> &Arc<Tracked> {
+        &self.0
+    }
+}
+
+impl Drop for TrackedGuard {
+    fn drop(&mut self) {
+        CONNECTIONS.lock().remove(&self.0.id);
+
+        let tracked = &self.0;
+        let traffic = (tracked.upload(), tracked.download());
+        let mut closed = CLOSED.lock();
+
+        if !tracked.rule.is_empty() {
+            let rule = closed.rules.entry(tracked.rule.clone()).or_default();
+            rule.0 += traffic.0;
+            rule.1 += traffic.1;
+        }
+
+        if let Some(server) = tracked.server() {
+            let server = closed.servers.entry(server).or_default();
+            server.0 += traffic.0;
+            server.1 += traffic.1;
+        }
+    }
+}
+
+/// Returns all tracked connections
+pub fn connections() -> Vec<Arc<Tracked>> {
+    CONNECTIONS.lock().values().cloned().collect()
+}
+
+/// Closes the tracked connection. Returns `false` if the connection does not exist
+pub fn close(id: u64) -> bool {
+    match CONNECTIONS.lock().get(&id) {
+        Some(tracked) => {
+            tracked.close.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn close_all() {
+    for tracked in CONNECTIONS.lock().values() {
+        tracked.close.notify_one();
+    }
+}
+
+/// Returns the total uploaded and downloaded bytes
+pub fn traffic_total() -> (u64, u64) {
+    (
+        UPLOAD_TOTAL.load(Ordering::Relaxed),
+        DOWNLOAD_TOTAL.load(Ordering::Relaxed),
+    )
+}
+
+/// Returns the traffic and active connections by rule, including closed connections
+///
+/// UDP associations are not counted, as their packets may match different rules.
+pub fn stats_by_rule() -> HashMap<String, Stats> {
+    let closed = CLOSED.lock().rules.clone();
+    stats_by(closed, |tracked| {
+        (!tracked.rule.is_empty()).then(|| tracked.rule.clone())
+    })
+}
+
+/// Returns the traffic and active connections by relay server, including closed connections
+pub fn stats_by_server() -> HashMap<String, Stats> {
+    let closed = CLOSED.lock().servers.clone();
+    stats_by(closed, Tracked::server)
+}
+
+fn stats_by(
+    closed: HashMap<String, (u64, u64)>,
+    key: impl Fn(&Tracked) -> Option<String>,
+) -> HashMap<String, Stats> {
+    let mut stats = closed
+        .into_iter()
+        .map(|(key, (upload, download))| {
+            let stats = Stats {
+                upload,
+                download,
+                connections: 0,
+            };
+            (key, stats)
+        })
+        .collect::<HashMap<_, _>>();
+
+    for tracked in connections() {
+        if let Some(key) = key(&tracked) {
+            let stats = stats.entry(key).or_default();
+            stats.upload += tracked.upload();
+            stats.download += tracked.download();
+            stats.connections += 1;
+        }
+    }
+
+    stats
+}
+
+/// A wrapper of the local inbound stream, counting bytes read as uploaded and bytes written as downloaded
+///
+/// Wrapping an outbound stream instead, e.g. when an inbound stream is shared by multiple tracked connections, counts the other way round.
+pub struct Counted<S> {
+    inner: S,
+    tracked: Arc<Tracked>,
+    is_outbound: bool,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S, tracked: Arc<Tracked>) -> Self {
+        Self {
+            inner,
+            tracked,
+            is_outbound: false,
+        }
+    }
+
+    pub fn outbound(inner: S, tracked: Arc<Tracked>) -> Self {
+        Self {
+            inner,
+            tracked,
+            is_outbound: true,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = res {
+            let n = buf.filled().len() - filled;
+
+            if self.is_outbound {
+                self.tracked.add_download(n);
+            } else {
+                self.tracked.add_upload(n);
+            }
+        }
+
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(n)) = res {
+            if self.is_outbound {
+                self.tracked.add_upload(n);
+            } else {
+                self.tracked.add_download(n);
+            }
+        }
+
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::utils::Ipv4Cidr;
+use std::{collections::HashMap, net::Ipv4Addr};
+
+/// A pool of synthetic IPv4 addresses, each mapped to a domain
+///
+/// Addresses are allocated in order. Once the pool is exhausted, the oldest mappings are reused.
+pub struct FakeIpPool {
+    range: Ipv4Cidr,
+    next: u64,
+    by_domain: HashMap<String, Ipv4Addr>,
+    by_addr: HashMap<Ipv4Addr, String>,
+}
+
+impl FakeIpPool {
+    pub fn new(range: Ipv4Cidr) -> Self {
+        Self {
+            range,
+            next: 0,
+            by_domain: HashMap::new(),
+            by_addr: HashMap::new(),
+        }
+    }
+
+    pub fn range(&self) -> Ipv4Cidr {
+        self.range
+    }
+
+    /// Returns the fake address mapped to the domain, allocating one if needed
+    pub fn get_or_allocate(&mut self, domain: &str) -> Ipv4Addr {
+        if let Some(addr) = self.by_domain.get(domain) {
+            return *addr;
+        }
+
+        let addr = self.next_addr();
+
+        if let Some(old_domain) = self.by_addr.remove(&addr) {
+            self.by_domain.remove(&old_domain);
+        }
+
+        self.by_domain.insert(domain.to_owned(), addr);
+        self.by_addr.insert(addr, domain.to_owned());
+
+        addr
+    }
+
+    /// Returns the domain mapped to the address, if it is a fake address in use
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        self.range.contains(addr)
+    }
+
+    fn next_addr(&mut self) -> Ipv4Addr {
+        // skip the network and the broadcast address
+        let usable = self.range.size().saturating_sub(2).max(1);
+        let offset = if self.range.size() > 2 {
+            self.next % usable + 1
+        } else {
+            self.next % usable
+        };
+
+        self.next = self.next.wrapping_add(1);
+
+        Ipv4Addr::from(u32::from(self.range.network()) + offset as u32)
+    }
+}
@@ -0,0 +1,57 @@
+use crate::error::Error;
+use std::{collections::HashMap, fs, net::IpAddr, path::Path};
+
+/// Static answers loaded from a hosts-style file
+///
+/// Each line is an IP address followed by one or more domains, with comments starting with `#`. A domain listed with several addresses is answered with all of them.
+#[derive(Default)]
+pub struct Hosts {
+    entries: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Hosts {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let mut hosts = Self::default();
+
+        for line in content.lines() {
+            let mut fields = line.split('#').next().unwrap().split_whitespace();
+
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+
+            let Ok(addr) = addr.parse::<IpAddr>() else {
+                log::warn!(
+                    "[dns] invalid address in hosts file {path}, ignoring: {line}",
+                    path = path.display(),
+                );
+                continue;
+            };
+
+            for domain in fields {
+                let addrs = hosts
+                    .entries
+                    .entry(domain.trim_end_matches('.').to_ascii_lowercase())
+                    .or_default();
+
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+
+        log::debug!(
+            "[dns] loaded hosts file {path} with {len} domains",
+            path = path.display(),
+            len = hosts.entries.len(),
+        );
+
+        Ok(hosts)
+    }
+
+    /// Returns the addresses of the domain, if listed
+    pub fn get(&self, domain: &str) -> Option<&[IpAddr]> {
+        self.entries.get(domain).map(Vec::as_slice)
+    }
+}
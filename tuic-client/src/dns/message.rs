@@ -0,0 +1,137 @@
+//! Just enough of the DNS message format for answering queries locally
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::net::IpAddr;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_NOERROR: u8 = 0;
+const RCODE_NXDOMAIN: u8 = 3;
+
+const HEADER_LEN: usize = 12;
+
+/// The single question of a standard query
+pub struct Question<'a> {
+    pub domain: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    query: &'a [u8],
+    end: usize,
+}
+
+impl<'a> Question<'a> {
+    /// Parses the question of a standard query with exactly one question, returning `None` for anything else
+    pub fn parse(query: &'a [u8]) -> Option<Self> {
+        if query.len() < HEADER_LEN {
+            return None;
+        }
+
+        let is_response = query[2] & 0x80 != 0;
+        let opcode = (query[2] >> 3) & 0x0f;
+        let qdcount = u16::from_be_bytes([query[4], query[5]]);
+
+        if is_response || opcode != 0 || qdcount != 1 {
+            return None;
+        }
+
+        let mut pos = HEADER_LEN;
+        let mut labels = Vec::new();
+
+        loop {
+            let len = *query.get(pos)? as usize;
+            pos += 1;
+
+            if len == 0 {
+                break;
+            }
+
+            // compression pointers are not expected in the question of a query
+            if len & 0xc0 != 0 {
+                return None;
+            }
+
+            let label = query.get(pos..pos + len)?;
+            labels.push(std::str::from_utf8(label).ok()?.to_ascii_lowercase());
+            pos += len;
+        }
+
+        let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+        let qclass = u16::from_be_bytes([*query.get(pos + 2)?, *query.get(pos + 3)?]);
+
+        Some(Self {
+            domain: labels.join("."),
+            qtype,
+            qclass,
+            query,
+            end: pos + 4,
+        })
+    }
+
+    pub fn is_internet(&self) -> bool {
+        self.qclass == CLASS_IN
+    }
+
+    /// Builds the response to the query, answering with the given addresses
+    ///
+    /// Only the addresses of the queried type are included, IPv4 for A and IPv6 for AAAA queries.
+    pub fn answer(&self, addrs: &[IpAddr], ttl: u32) -> Bytes {
+        let addrs = addrs
+            .iter()
+            .filter(|addr| match addr {
+                IpAddr::V4(_) => self.qtype == TYPE_A,
+                IpAddr::V6(_) => self.qtype == TYPE_AAAA,
+            })
+            .collect::<Vec<_>>();
+
+        let mut resp = BytesMut::with_capacity(self.end + addrs.len() * 28);
+        self.put_header(&mut resp, RCODE_NOERROR, addrs.len() as u16);
+
+        for addr in addrs {
+            // pointer to the name in the question
+            resp.put_u16(0xc000 | HEADER_LEN as u16);
+
+            match addr {
+                IpAddr::V4(addr) => {
+                    resp.put_u16(TYPE_A);
+                    resp.put_u16(CLASS_IN);
+                    resp.put_u32(ttl);
+                    resp.put_u16(4);
+                    resp.put_slice(&addr.octets());
+                }
+                IpAddr::V6(addr) => {
+                    resp.put_u16(TYPE_AAAA);
+                    resp.put_u16(CLASS_IN);
+                    resp.put_u32(ttl);
+                    resp.put_u16(16);
+                    resp.put_slice(&addr.octets());
+                }
+            }
+        }
+
+        resp.freeze()
+    }
+
+    /// Builds the response to the query, saying that the domain does not exist
+    pub fn nxdomain(&self) -> Bytes {
+        let mut resp = BytesMut::with_capacity(self.end);
+        self.put_header(&mut resp, RCODE_NXDOMAIN, 0);
+        resp.freeze()
+    }
+
+    fn put_header(&self, resp: &mut BytesMut, rcode: u8, ancount: u16) {
+        // ID
+        resp.put_slice(&self.query[0..2]);
+        // QR, the original opcode and RD, RA and RCODE
+        resp.put_u8(0x80 | (self.query[2] & 0x79));
+        resp.put_u8(0x80 | rcode);
+        // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+        resp.put_u16(1);
+        resp.put_u16(ancount);
+        resp.put_u16(0);
+        resp.put_u16(0);
+
+        resp.put_slice(&self.query[HEADER_LEN..self.end]);
+    }
+}
@@ -0,0 +1,528 @@
+use self::{
+    fake_ip::FakeIpPool,
+    hosts::Hosts,
+    message::{Question, TYPE_A, TYPE_AAAA},
+    rule::Action,
+};
+use crate::{
+    config::Dns,
+    connection::{self, Connection as TuicConnection, ERROR_CODE},
+    error::Error,
+    protect,
+    router::{DomainSet, Router},
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::Notify,
+    time,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address;
+
+mod fake_ip;
+mod hosts;
+mod message;
+mod rule;
+
+pub use self::rule::Rule;
+
+static SERVER: RwLock<Option<Arc<Server>>> = RwLock::new(None);
+static RESTART: Notify = Notify::const_new();
+
+/// A local DNS server forwarding queries to the upstream resolver through the TUIC proxy
+///
+/// UDP queries are relayed in a dedicated UDP association, while TCP queries are relayed as TCP streams.
+///
+/// In FakeIP mode, UDP queries for A records are answered locally with addresses from the FakeIP pool, which are mapped back to the domains when connecting.
+///
+/// UDP queries for domains in the hosts file are answered locally, and the DNS rules can block queries or send them to other resolvers. Both take precedence over FakeIP.
+pub struct Server {
+    addr: SocketAddr,
+    udp: Arc<UdpSocket>,
+    tcp: Arc<TcpListener>,
+    upstream: SocketAddr,
+    timeout: Duration,
+    assoc_id: u16,
+    next_query_id: Arc<AtomicU16>,
+    pending: Arc<Mutex<HashMap<u16, PendingQuery>>>,
+    fake_ip: Option<Arc<Mutex<FakeIpPool>>>,
+    fake_ip_ttl: u32,
+    hosts: Option<Hosts>,
+    rules: Vec<Rule>,
+    rule_sets: HashMap<String, DomainSet>,
+    ttl: u32,
+}
+
+/// A UDP query relayed to an upstream resolver, waiting for the response
+struct PendingQuery {
+    client_addr: SocketAddr,
+    orig_id: u16,
+    upstream: SocketAddr,
+}
+
+/// How a UDP query is handled
+enum Resolution {
+    Answer(Bytes),
+    Upstream(SocketAddr),
+    Direct(SocketAddr),
+}
+
+impl Server {
+    /// Sets up the DNS server, replacing the current one when reloading the config
+    ///
+    /// Pending queries and the FakeIP pool (if its range is unchanged) are carried over from the current server, so that fake addresses already handed out keep working. The sockets are only rebound if the listening address is changed.
+    pub fn set_config(cfg: Option<Dns>) -> Result<(), Error> {
+        let current = SERVER.read().clone();
+
+        let Some(cfg) = cfg else {
+            if let Some(current) = current {
+                connection::release_assoc_id(current.assoc_id);
+                *SERVER.write() = None;
+                RESTART.notify_waiters();
+                log::warn!("[dns] server stopped");
+            }
+
+            return Ok(());
+        };
+
+        let server = Self::new(cfg, current.as_deref())?;
+        *SERVER.write() = Some(Arc::new(server));
+        RESTART.notify_waiters();
+
+        Ok(())
+    }
+
+    fn new(cfg: Dns, current: Option<&Self>) -> Result<Self, Error> {
+        let (udp, tcp) = match current.filter(|current| current.addr == cfg.server) {
+            Some(current) => (current.udp.clone(), current.tcp.clone()),
+            None => {
+                let (udp, tcp) = Self::bind(cfg.server)?;
+                (Arc::new(udp), Arc::new(tcp))
+            }
+        };
+
+        let fake_ip = cfg.fake_ip.as_ref().map(|fake_ip| {
+            current
+                .and_then(|current| current.fake_ip.clone())
+                .filter(|pool| pool.lock().range() == fake_ip.range)
+                .unwrap_or_else(|| Arc::new(Mutex::new(FakeIpPool::new(fake_ip.range))))
+        });
+
+        let hosts = cfg.hosts.as_deref().map(Hosts::load).transpose()?;
+        let rule_sets =
+            Router::load_external_rule_sets(cfg.rules.iter().map(|rule| &rule.matcher))?;
+
+        Ok(Self {
+            addr: cfg.server,
+            udp,
+            tcp,
+            upstream: cfg.upstream,
+            timeout: cfg.timeout,
+            assoc_id: current.map_or_else(connection::reserve_assoc_id, |current| current.assoc_id),
+            next_query_id: current.map_or_else(
+                || Arc::new(AtomicU16::new(0)),
+                |current| current.next_query_id.clone(),
+            ),
+            pending: current.map_or_else(
+                || Arc::new(Mutex::new(HashMap::new())),
+                |current| current.pending.clone(),
+            ),
+            fake_ip,
+            fake_ip_ttl: cfg.fake_ip.map_or(0, |fake_ip| fake_ip.ttl),
+            hosts,
+            rules: cfg.rules,
+            rule_sets,
+            ttl: cfg.ttl,
+        })
+    }
+
+    fn bind(addr: SocketAddr) -> Result<(UdpSocket, TcpListener), Error> {
+        let udp = std::net::UdpSocket::bind(addr)
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                UdpSocket::from_std(socket)
+            })
+            .map_err(|err| Error::Socket("failed to bind DNS server UDP socket", err))?;
+
+        let tcp = std::net::TcpListener::bind(addr)
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                TcpListener::from_std(socket)
+            })
+            .map_err(|err| Error::Socket("failed to bind DNS server TCP socket", err))?;
+
+        Ok((udp, tcp))
+    }
+
+    pub async fn start() {
+        loop {
+            let restart = RESTART.notified();
+            tokio::pin!(restart);
+            restart.as_mut().enable();
+
+            let Some(server) = SERVER.read().clone() else {
+                // the DNS server may be enabled by reloading the config
+                restart.await;
+                continue;
+            };
+
+            log::warn!(
+                "[dns] server started, listening on {}, forwarding to {}",
+                server.udp.local_addr().unwrap(),
+                server.upstream,
+            );
+
+            // serve until the server is replaced by reloading the config
+            tokio::select! {
+                _ = async { tokio::join!(server.clone().serve_udp(), server.clone().serve_tcp()) } => {}
+                _ = restart => {}
+            }
+        }
+    }
+
+    /// Returns the association ID used for relaying UDP queries, if the DNS server is enabled
+    pub fn assoc_id() -> Option<u16> {
+        SERVER.read().as_ref().map(|server| server.assoc_id)
+    }
+
+    /// Maps a fake address back to its domain
+    ///
+    /// Other addresses are returned unchanged.
+    pub fn restore_fake_ip(addr: Address) -> Address {
+        let Some(fake_ip) = SERVER
+            .read()
+            .as_ref()
+            .and_then(|server| server.fake_ip.clone())
+        else {
+            return addr;
+        };
+
+        let Address::SocketAddress(SocketAddr::V4(sock_addr)) = addr else {
+            return addr;
+        };
+
+        let fake_ip = fake_ip.lock();
+
+        if !fake_ip.contains(*sock_addr.ip()) {
+            return addr;
+        }
+
+        match fake_ip.lookup(*sock_addr.ip()) {
+            Some(domain) => Address::DomainAddress(domain.to_owned(), sock_addr.port()),
+            None => {
+                log::warn!("[dns] [fake-ip] no domain mapped to {sock_addr}");
+                addr
+            }
+        }
+    }
+
+    /// Sends the response relayed back from the upstream resolver to the querying client
+    ///
+    /// Responses not from the resolver the query was sent to are dropped.
+    pub async fn handle_response(pkt: Bytes, addr: Address) {
+        let Some(server) = SERVER.read().clone() else {
+            return;
+        };
+
+        if pkt.len() < 2 {
+            log::warn!("[dns] invalid response from upstream");
+            return;
+        }
+
+        let query_id = u16::from_be_bytes([pkt[0], pkt[1]]);
+
+        let pending = {
+            let mut pending = server.pending.lock();
+
+            match pending.get(&query_id) {
+                Some(query) if addr == Address::SocketAddress(query.upstream) => {
+                    pending.remove(&query_id)
+                }
+                Some(query) => {
+                    log::warn!(
+                        "[dns] [{query_id:#06x}] response from {addr}, expected from {upstream}",
+                        upstream = query.upstream,
+                    );
+                    return;
+                }
+                None => None,
+            }
+        };
+
+        let Some(PendingQuery {
+            client_addr,
+            orig_id,
+            ..
+        }) = pending
+        else {
+            log::debug!("[dns] [{query_id:#06x}] response for an unknown or expired query");
+            return;
+        };
+
+        let mut resp = BytesMut::with_capacity(pkt.len());
+        resp.put_u16(orig_id);
+        resp.put_slice(&pkt[2..]);
+
+        if let Err(err) = server.udp.send_to(&resp, client_addr).await {
+            log::warn!("[dns] [{client_addr}] failed sending response: {err}");
+        }
+    }
+
+    async fn serve_udp(self: Arc<Self>) {
+        let mut buf = vec![0; u16::MAX as usize];
+
+        loop {
+            let (len, client_addr) = match self.udp.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(err) => {
+                    log::warn!("[dns] failed to receive UDP query: {err}");
+                    continue;
+                }
+            };
+
+            if len < 2 {
+                log::warn!("[dns] [{client_addr}] invalid query");
+                continue;
+            }
+
+            let upstream = match self.resolve(&buf[..len]) {
+                Resolution::Answer(resp) => {
+                    if let Err(err) = self.udp.send_to(&resp, client_addr).await {
+                        log::warn!("[dns] [{client_addr}] failed sending response: {err}");
+                    }
+
+                    continue;
+                }
+                Resolution::Upstream(upstream) => upstream,
+                Resolution::Direct(resolver) => {
+                    let query = Bytes::copy_from_slice(&buf[..len]);
+                    tokio::spawn(self.clone().query_direct(query, client_addr, resolver));
+                    continue;
+                }
+            };
+
+            // replace the query ID to avoid collisions between clients
+            let orig_id = u16::from_be_bytes([buf[0], buf[1]]);
+
+            let Some(query_id) = self.insert_pending(PendingQuery {
+                client_addr,
+                orig_id,
+                upstream,
+            }) else {
+                log::warn!("[dns] [{client_addr}] too many pending queries");
+                continue;
+            };
+
+            let mut query = BytesMut::with_capacity(len);
+            query.put_u16(query_id);
+            query.put_slice(&buf[2..len]);
+            let query = query.freeze();
+            let server = self.clone();
+
+            tokio::spawn(async move {
+                log::debug!("[dns] [{client_addr}] [{query_id:#06x}] [udp] query");
+
+                let res = match TuicConnection::get_for_packet(server.assoc_id).await {
+                    Ok(conn) => {
+                        conn.packet(
+                            query,
+                            Address::SocketAddress(upstream),
+                            server.assoc_id,
+                            None,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = res {
+                    log::warn!("[dns] [{client_addr}] [{query_id:#06x}] [udp] failed relaying query: {err}");
+                    server.pending.lock().remove(&query_id);
+                    return;
+                }
+
+                time::sleep(server.timeout).await;
+
+                if server.pending.lock().remove(&query_id).is_some() {
+                    log::debug!("[dns] [{client_addr}] [{query_id:#06x}] [udp] query timed out");
+                }
+            });
+        }
+    }
+
+    /// Allocates a query ID for the pending query, skipping the IDs of queries still waiting for responses
+    fn insert_pending(&self, query: PendingQuery) -> Option<u16> {
+        let mut pending = self.pending.lock();
+
+        for _ in 0..=u16::MAX {
+            let query_id = self.next_query_id.fetch_add(1, Ordering::Relaxed);
+
+            if let Entry::Vacant(entry) = pending.entry(query_id) {
+                entry.insert(query);
+                return Some(query_id);
+            }
+        }
+
+        None
+    }
+
+    /// Decides how to handle the query, by the hosts file, the DNS rules and FakeIP in order
+    fn resolve(&self, query: &[u8]) -> Resolution {
+        let Some(question) = Question::parse(query).filter(Question::is_internet) else {
+            return Resolution::Upstream(self.upstream);
+        };
+
+        let is_addr_query = question.qtype == TYPE_A || question.qtype == TYPE_AAAA;
+
+        if let Some(addrs) = self
+            .hosts
+            .as_ref()
+            .and_then(|hosts| hosts.get(&question.domain))
+        {
+            if is_addr_query {
+                log::debug!(
+                    "[dns] [hosts] {domain} -> {addrs:?}",
+                    domain = question.domain
+                );
+                return Resolution::Answer(question.answer(addrs, self.ttl));
+            }
+        }
+
+        let rule = self.rules.iter().find(|rule| {
+            rule.matcher
+                .matches_domain(&question.domain, &self.rule_sets)
+        });
+
+        if let Some(rule) = rule {
+            log::debug!(
+                "[dns] [rule] {domain} matches {matcher}",
+                domain = question.domain,
+                matcher = rule.matcher,
+            );
+
+            return match rule.action {
+                Action::Block => Resolution::Answer(question.nxdomain()),
+                Action::Zero => Resolution::Answer(question.answer(
+                    &[
+                        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                    ],
+                    self.ttl,
+                )),
+                Action::Upstream(upstream) => Resolution::Upstream(upstream),
+                Action::Direct(resolver) => Resolution::Direct(resolver),
+            };
+        }
+
+        match self.answer_fake_ip(&question) {
+            Some(resp) => Resolution::Answer(resp),
+            None => Resolution::Upstream(self.upstream),
+        }
+    }
+
+    /// Sends the query to the resolver directly, relaying the response back to the querying client
+    async fn query_direct(
+        self: Arc<Self>,
+        query: Bytes,
+        client_addr: SocketAddr,
+        resolver: SocketAddr,
+    ) {
+        log::debug!("[dns] [{client_addr}] [udp] query directly to {resolver}");
+
+        let res = async {
+            let socket = protect::bind_direct_udp()?;
+
+            let resolver = match (socket.local_addr()?, resolver) {
+                (SocketAddr::V6(_), SocketAddr::V4(addr)) => {
+                    SocketAddr::from((addr.ip().to_ipv6_mapped(), addr.port()))
+                }
+                _ => resolver,
+            };
+
+            socket.connect(resolver).await?;
+            socket.send(&query).await?;
+
+            let mut buf = vec![0; u16::MAX as usize];
+            let len = time::timeout(self.timeout, socket.recv(&mut buf))
+                .await
+                .map_err(|_| Error::Timeout)??;
+
+            self.udp.send_to(&buf[..len], client_addr).await?;
+            Ok::<_, Error>(())
+        };
+
+        if let Err(err) = res.await {
+            log::warn!("[dns] [{client_addr}] [udp] failed querying {resolver} directly: {err}");
+        }
+    }
+
+    /// Answers the query from the FakeIP pool, if FakeIP is enabled and the query is for an A or AAAA record
+    ///
+    /// AAAA queries are answered with no records, so that clients fall back to the fake IPv4 address.
+    fn answer_fake_ip(&self, question: &Question) -> Option<Bytes> {
+        let fake_ip = self.fake_ip.as_ref()?;
+
+        match question.qtype {
+            TYPE_A => {
+                let addr = fake_ip.lock().get_or_allocate(&question.domain);
+                log::debug!(
+                    "[dns] [fake-ip] {domain} -> {addr}",
+                    domain = question.domain
+                );
+                Some(question.answer(&[IpAddr::V4(addr)], self.fake_ip_ttl))
+            }
+            TYPE_AAAA => Some(question.answer(&[], self.fake_ip_ttl)),
+            _ => None,
+        }
+    }
+
+    async fn serve_tcp(self: Arc<Self>) {
+        loop {
+            match self.tcp.accept().await {
+                Ok((stream, client_addr)) => {
+                    tokio::spawn(self.clone().handle_tcp(stream, client_addr));
+                }
+                Err(err) => log::warn!("[dns] failed to accept TCP connection: {err}"),
+            }
+        }
+    }
+
+    async fn handle_tcp(self: Arc<Self>, mut stream: TcpStream, client_addr: SocketAddr) {
+        log::debug!("[dns] [{client_addr}] [tcp] connection established");
+
+        let addr = Address::SocketAddress(self.upstream);
+
+        let relay = match TuicConnection::get_for_connect(&addr).await {
+            Ok(conn) => conn.connect(addr, None).await,
+            Err(err) => Err(err),
+        };
+
+        match relay {
+            Ok(relay) => {
+                let mut relay = relay.compat();
+
+                if let Err(err) = io::copy_bidirectional(&mut stream, &mut relay).await {
+                    let _ = stream.shutdown().await;
+                    let _ = relay.get_mut().reset(ERROR_CODE);
+                    log::warn!("[dns] [{client_addr}] [tcp] relaying error: {err}");
+                }
+            }
+            Err(err) => {
+                let _ = stream.shutdown().await;
+                log::warn!("[dns] [{client_addr}] [tcp] unable to relay: {err}");
+            }
+        }
+    }
+}
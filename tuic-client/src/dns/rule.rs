@@ -0,0 +1,69 @@
+use crate::router::Matcher;
+use std::{net::SocketAddr, str::FromStr};
+
+/// A DNS rule in the form of `MATCHER -> ACTION`
+///
+/// Matchers are the domain matchers of the routing rules. Rule sets (`geosite:` and `list:`) are loaded from the geosite file and the domain lists of the router.
+pub struct Rule {
+    pub matcher: Matcher,
+    pub action: Action,
+}
+
+impl FromStr for Rule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (matcher, action) = s
+            .rsplit_once("->")
+            .ok_or("invalid DNS rule, expecting `MATCHER -> ACTION`")?;
+
+        let matcher = matcher.trim().parse::<Matcher>()?;
+
+        if matcher.is_process() {
+            return Err("process matchers are not supported in DNS rules");
+        }
+
+        if matcher.is_ip() {
+            return Err("IP matchers are not supported in DNS rules");
+        }
+
+        Ok(Self {
+            matcher,
+            action: action.trim().parse()?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Answering that the domain does not exist (NXDOMAIN)
+    Block,
+    /// Answering with the unspecified address, `0.0.0.0` or `::`
+    Zero,
+    /// Relaying the query to another upstream resolver through the TUIC proxy
+    Upstream(SocketAddr),
+    /// Sending the query to a resolver directly from the client
+    Direct(SocketAddr),
+}
+
+impl FromStr for Action {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("block") || s.eq_ignore_ascii_case("nxdomain") {
+            Ok(Self::Block)
+        } else if s.eq_ignore_ascii_case("zero") {
+            Ok(Self::Zero)
+        } else if let Some(addr) = s.strip_prefix("upstream=") {
+            addr.parse()
+                .map(Self::Upstream)
+                .map_err(|_| "invalid upstream address in DNS rule, expecting `IP:PORT`")
+        } else if let Some(addr) = s.strip_prefix("direct=") {
+            addr.parse()
+                .map(Self::Direct)
+                .map_err(|_| "invalid direct resolver address in DNS rule, expecting `IP:PORT`")
+        } else {
+            Err("invalid DNS rule action, expecting `block`, `zero`, `upstream=IP:PORT` or `direct=IP:PORT`")
+        }
+    }
+}
@@ -1,7 +1,11 @@
+use crate::config::ConfigError;
 use quinn::{ConnectError, ConnectionError};
+use regex::Error as RegexError;
 use rustls::Error as RustlsError;
 use std::io::Error as IoError;
 use thiserror::Error;
+use tokio_tungstenite::tungstenite::Error as WebSocketError;
+use tuic::AddressError;
 use tuic_quinn::Error as ModelError;
 
 #[derive(Debug, Error)]
@@ -12,20 +16,55 @@ pub enum Error {
     Connect(#[from] ConnectError),
     #[error(transparent)]
     Model(#[from] ModelError),
+    #[error("invalid address: {0}")]
+    Address(#[from] AddressError),
     #[error("load native certificates error: {0}")]
     LoadNativeCerts(IoError),
+    #[error("load certificate {0} error: {1}")]
+    LoadCert(String, IoError),
     #[error(transparent)]
     Rustls(#[from] RustlsError),
     #[error("{0}: {1}")]
     Socket(&'static str, IoError),
     #[error("timeout establishing connection")]
     Timeout,
+    #[error("too many tasks waiting for the connection")]
+    TooManyPendingTasks,
     #[error("cannot resolve the server name")]
     DnsResolve,
-    #[error("received packet from an unexpected source")]
-    WrongPacketSource,
+    #[error("upstream proxy error: {0}")]
+    UpstreamProxy(&'static str),
+    #[error("WebSocket bridge error: {0}")]
+    Bridge(Box<WebSocketError>),
+    #[error("invalid congestion control settings: {0}")]
+    InvalidCongestionControl(&'static str),
+    #[error("invalid transport settings: {0}")]
+    InvalidTransport(&'static str),
+    #[error("invalid TLS settings: {0}")]
+    InvalidTls(&'static str),
     #[error("invalid socks5 authentication")]
     InvalidSocks5Auth,
+    #[error("unknown rule set: {0}")]
+    UnknownRuleSet(String),
+    #[error("invalid geosite file: {0}")]
+    InvalidGeoSite(&'static str),
+    #[error(transparent)]
+    Regex(#[from] RegexError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("config reloading is unavailable without a config file")]
+    ReloadUnavailable,
+    #[error("failed applying {}", fmt_reload_errors(.0))]
+    Reload(Vec<(&'static str, Error)>),
+    #[error("{0}")]
+    Bench(&'static str),
+    #[error("failed opening the telemetry log: {0}")]
+    TelemetryLog(IoError),
+    #[error("failed setting the system proxy: {0}")]
+    SystemProxy(String),
+    #[cfg(target_os = "android")]
+    #[error(transparent)]
+    Jni(#[from] jni::errors::Error),
 }
 
 impl From<ConnectionError> for Error {
@@ -33,3 +72,16 @@ impl From<ConnectionError> for Error {
         Self::Io(IoError::from(err))
     }
 }
+
+impl From<WebSocketError> for Error {
+    fn from(err: WebSocketError) -> Self {
+        Self::Bridge(Box::new(err))
+    }
+}
+
+fn fmt_reload_errors(errs: &[(&'static str, Error)]) -> String {
+    errs.iter()
+        .map(|(section, err)| format!("{section}: {err}"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
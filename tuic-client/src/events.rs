@@ -0,0 +1,88 @@
+//! Events of the client for GUIs to follow its state without parsing the logs
+//!
+//! Events are broadcast to every subscriber. A subscriber lagging behind by more than [`CAPACITY`] events misses the oldest ones.
+
+use crate::controller::tracker;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::{
+    sync::broadcast::{self, Receiver, Sender},
+    time,
+};
+
+/// The number of events kept for each subscriber
+const CAPACITY: usize = 64;
+
+static EVENTS: Lazy<Sender<Event>> = Lazy::new(|| broadcast::channel(CAPACITY).0);
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// A connection to the relay server is established
+    Connected { server: String },
+    /// Connecting to the relay server failed, to be retried after the backoff
+    Reconnecting {
+        server: String,
+        retries: u32,
+        backoff: Duration,
+    },
+    /// Tasks fail over to another relay server
+    ServerSwitched { from: String, to: String },
+    /// The relay server rejected the authentication
+    AuthFailed { server: String },
+    /// The traffic of the local inbound in the last second
+    Traffic { up: u64, down: u64 },
+}
+
+impl Event {
+    /// The event in JSON, tagged with its `type`
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Connected { server } => json!({ "type": "connected", "server": server }),
+            Self::Reconnecting {
+                server,
+                retries,
+                backoff,
+            } => json!({
+                "type": "reconnecting",
+                "server": server,
+                "retries": retries,
+                "backoff": backoff.as_millis() as u64,
+            }),
+            Self::ServerSwitched { from, to } => {
+                json!({ "type": "server_switched", "from": from, "to": to })
+            }
+            Self::AuthFailed { server } => json!({ "type": "auth_failed", "server": server }),
+            Self::Traffic { up, down } => json!({ "type": "traffic", "up": up, "down": down }),
+        }
+    }
+}
+
+/// Subscribes to the events from now on, including those of the client after it is restarted
+pub fn subscribe() -> Receiver<Event> {
+    EVENTS.subscribe()
+}
+
+pub fn emit(event: Event) {
+    // no subscriber is not an error
+    let _ = EVENTS.send(event);
+}
+
+/// Emits the traffic every second while there are subscribers
+pub async fn sample_traffic() {
+    let mut last = tracker::traffic_total();
+
+    loop {
+        time::sleep(Duration::from_secs(1)).await;
+        let total = tracker::traffic_total();
+
+        if EVENTS.receiver_count() > 0 {
+            emit(Event::Traffic {
+                up: total.0 - last.0,
+                down: total.1 - last.1,
+            });
+        }
+
+        last = total;
+    }
+}
@@ -0,0 +1,411 @@
+use crate::{
+    config::{Forward as ForwardConfig, ReverseForward as ReverseForwardConfig},
+    connection::{self, udp_stun_timeout, Connection as TuicConnection, ERROR_CODE},
+    controller::{
+        self,
+        tracker::{Counted, TrackedGuard},
+    },
+    error::Error,
+    protect,
+    router::Outbound,
+};
+use bytes::Bytes;
+use futures_util::future;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpListener as StdTcpListener, UdpSocket as StdUdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc::{self, Receiver, Sender},
+    time,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address;
+use tuic_quinn::Inbound;
+
+/// Senders of packets received from the relay to the UDP sessions of local forwards, by association ID
+pub static UDP_SESSIONS: Lazy<Mutex<HashMap<u16, Sender<Bytes>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static FORWARDS: Mutex<Vec<Arc<Forward>>> = Mutex::new(Vec::new());
+
+static REVERSE_FORWARDS: Lazy<Mutex<HashMap<u16, Arc<ReverseForward>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// How long to wait before binding again after a binding fails or is closed, e.g. by the connection being closed
+const REBIND_INTERVAL: Duration = Duration::from_secs(3);
+
+type LocalSessions = Arc<Mutex<HashMap<SocketAddr, Sender<Bytes>>>>;
+
+/// A local port forwarded to a fixed target through the proxy, like `ssh -L`
+///
+/// Every TCP connection to the port is relayed to the target as is, without routing. UDP packets are relayed in a UDP association per local source address, which is dissociated after being idle for `udp_timeout`.
+pub struct Forward {
+    target: Address,
+    tcp: Option<TcpListener>,
+    udp: Option<Arc<UdpSocket>>,
+    udp_timeout: Duration,
+}
+
+impl Forward {
+    pub fn set_config(cfgs: Vec<ForwardConfig>) -> Result<(), Error> {
+        let forwards = cfgs
+            .into_iter()
+            .map(|cfg| Self::new(cfg).map(Arc::new))
+            .collect::<Result<_, _>>()?;
+
+        *FORWARDS.lock() = forwards;
+
+        Ok(())
+    }
+
+    fn new(cfg: ForwardConfig) -> Result<Self, Error> {
+        let tcp = cfg
+            .tcp
+            .then(|| {
+                StdTcpListener::bind(cfg.listen).and_then(|socket| {
+                    socket.set_nonblocking(true)?;
+                    TcpListener::from_std(socket)
+                })
+            })
+            .transpose()
+            .map_err(|err| Error::Socket("failed to bind forward TCP socket", err))?;
+
+        let udp = cfg
+            .udp
+            .then(|| {
+                StdUdpSocket::bind(cfg.listen).and_then(|socket| {
+                    socket.set_nonblocking(true)?;
+                    UdpSocket::from_std(socket)
+                })
+            })
+            .transpose()
+            .map_err(|err| Error::Socket("failed to bind forward UDP socket", err))?;
+
+        Ok(Self {
+            target: cfg.target,
+            tcp,
+            udp: udp.map(Arc::new),
+            udp_timeout: cfg.udp_timeout,
+        })
+    }
+
+    pub async fn start() {
+        let forwards = FORWARDS.lock().clone();
+
+        future::join_all(forwards.into_iter().map(|forward| async move {
+            tokio::join!(forward.clone().serve_tcp(), forward.serve_udp());
+        }))
+        .await;
+    }
+
+    pub fn stop() {
+        FORWARDS.lock().clear();
+    }
+
+    async fn serve_tcp(self: Arc<Self>) {
+        let Some(listener) = &self.tcp else {
+            return;
+        };
+
+        log::warn!(
+            "[forward] [tcp] listening on {}, forwarding to {}",
+            listener.local_addr().unwrap(),
+            self.target,
+        );
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    tokio::spawn(self.clone().handle_tcp(stream, peer_addr));
+                }
+                Err(err) => log::warn!("[forward] [tcp] failed to accept TCP connection: {err}"),
+            }
+        }
+    }
+
+    async fn handle_tcp(self: Arc<Self>, stream: TcpStream, peer_addr: SocketAddr) {
+        log::info!("[forward] [{peer_addr}] [tcp] {}", self.target);
+
+        let guard = TrackedGuard::new(
+            "tcp",
+            peer_addr,
+            self.target.clone(),
+            String::from(controller::outbound_name(Outbound::Proxy)),
+            String::new(),
+        );
+
+        let relay = match TuicConnection::get_for_connect(&self.target).await {
+            Ok(conn) => {
+                guard.tracked().set_server(conn.server());
+                conn.connect(self.target.clone(), None).await
+            }
+            Err(err) => Err(err),
+        };
+
+        let mut stream = Counted::new(stream, guard.tracked().clone());
+
+        match relay {
+            Ok(relay) => {
+                let mut relay = relay.compat();
+
+                let res = tokio::select! {
+                    res = io::copy_bidirectional(&mut stream, &mut relay) => Some(res),
+                    _ = guard.tracked().closed() => None,
+                };
+
+                match res {
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        let _ = stream.shutdown().await;
+                        let _ = relay.get_mut().reset(ERROR_CODE);
+                        log::warn!("[forward] [{peer_addr}] [tcp] relaying error: {err}");
+                    }
+                    None => {
+                        let _ = stream.shutdown().await;
+                        let _ = relay.get_mut().reset(ERROR_CODE);
+                        log::info!("[forward] [{peer_addr}] [tcp] closed by controller");
+                    }
+                }
+            }
+            Err(err) => {
+                let _ = stream.shutdown().await;
+                log::warn!("[forward] [{peer_addr}] [tcp] unable to relay: {err}");
+            }
+        }
+    }
+
+    async fn serve_udp(self: Arc<Self>) {
+        let Some(socket) = &self.udp else {
+            return;
+        };
+
+        log::warn!(
+            "[forward] [udp] listening on {}, forwarding to {}",
+            socket.local_addr().unwrap(),
+            self.target,
+        );
+
+        let sessions = LocalSessions::default();
+        let mut buf = vec![0; u16::MAX as usize];
+
+        loop {
+            let (n, src_addr) = match socket.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(err) => {
+                    log::warn!("[forward] [udp] failed to receive UDP packet: {err}");
+                    continue;
+                }
+            };
+
+            let pkt = Bytes::copy_from_slice(&buf[..n]);
+            let tx = sessions.lock().get(&src_addr).cloned();
+
+            let tx = match tx {
+                Some(tx) => tx,
+                None => {
+                    let (tx, rx) = mpsc::channel(64);
+                    sessions.lock().insert(src_addr, tx.clone());
+                    tokio::spawn(self.clone().handle_udp(src_addr, rx, sessions.clone()));
+                    tx
+                }
+            };
+
+            if tx.try_send(pkt).is_err() {
+                log::debug!("[forward] [{src_addr}] [udp] dropped packet");
+            }
+        }
+    }
+
+    async fn handle_udp(
+        self: Arc<Self>,
+        src_addr: SocketAddr,
+        mut local_rx: Receiver<Bytes>,
+        sessions: LocalSessions,
+    ) {
+        let socket = self.udp.as_ref().unwrap();
+        let assoc_id = connection::next_assoc_id();
+        log::info!(
+            "[forward] [{src_addr}] [udp] [{assoc_id:#06x}] {}",
+            self.target
+        );
+
+        let guard = TrackedGuard::new(
+            "udp",
+            src_addr,
+            self.target.clone(),
+            String::from(controller::outbound_name(Outbound::Proxy)),
+            String::new(),
+        );
+
+        let (tx, mut relay_rx) = mpsc::channel(64);
+        UDP_SESSIONS.lock().insert(assoc_id, tx);
+
+        loop {
+            // sessions carrying STUN traffic are kept longer for hole punching
+            let timeout = udp_stun_timeout(assoc_id)
+                .map_or(self.udp_timeout, |timeout| timeout.max(self.udp_timeout));
+
+            tokio::select! {
+                Some(pkt) = local_rx.recv() => {
+                    guard.tracked().add_upload(pkt.len());
+
+                    let res = match TuicConnection::get_for_packet(assoc_id).await {
+                        Ok(conn) => {
+                            guard.tracked().set_server(conn.server());
+                            conn.packet(pkt, self.target.clone(), assoc_id, None).await
+                        }
+                        Err(err) => Err(err),
+                    };
+
+                    if let Err(err) = res {
+                        log::warn!("[forward] [{src_addr}] [udp] [{assoc_id:#06x}] failed relaying packet: {err}");
+                    }
+                }
+                Some(pkt) = relay_rx.recv() => {
+                    guard.tracked().add_download(pkt.len());
+
+                    if let Err(err) = socket.send_to(&pkt, src_addr).await {
+                        log::warn!("[forward] [{src_addr}] [udp] [{assoc_id:#06x}] failed sending packet: {err}");
+                    }
+                }
+                () = time::sleep(timeout) => break,
+                _ = guard.tracked().closed() => break,
+            }
+        }
+
+        log::debug!("[forward] [{src_addr}] [udp] [{assoc_id:#06x}] session closed");
+        sessions.lock().remove(&src_addr);
+        UDP_SESSIONS.lock().remove(&assoc_id);
+
+        match TuicConnection::get_for_dissociate(assoc_id).await {
+            Ok(Some(conn)) => {
+                let _ = conn.dissociate(assoc_id).await;
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!(
+                "[forward] [{src_addr}] [udp] [{assoc_id:#06x}] failed stopping UDP relaying session: {err}"
+            ),
+        }
+    }
+}
+
+/// A port on the relay server forwarded back to a fixed target reachable from the client, like `ssh -R`
+///
+/// The port is listened on by the server with a `Bind` command, which is sent again whenever the binding is closed, e.g. after reconnecting.
+pub struct ReverseForward {
+    bind_id: u16,
+    listen: SocketAddr,
+    target: Address,
+}
+
+impl ReverseForward {
+    pub fn set_config(cfgs: Vec<ReverseForwardConfig>) {
+        let forwards = cfgs
+            .into_iter()
+            .map(|cfg| {
+                let bind_id = connection::next_bind_id();
+                let forward = Self {
+                    bind_id,
+                    listen: cfg.listen,
+                    target: cfg.target,
+                };
+                (bind_id, Arc::new(forward))
+            })
+            .collect();
+
+        *REVERSE_FORWARDS.lock() = forwards;
+    }
+
+    pub async fn start() {
+        let forwards = REVERSE_FORWARDS
+            .lock()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        future::join_all(forwards.into_iter().map(Self::bind)).await;
+    }
+
+    pub fn stop() {
+        REVERSE_FORWARDS.lock().clear();
+    }
+
+    async fn bind(self: Arc<Self>) {
+        let bind_id = self.bind_id;
+        let listen = Address::SocketAddress(self.listen);
+
+        loop {
+            let binding = match TuicConnection::get_for_connect(&listen).await {
+                Ok(conn) => conn
+                    .bind(bind_id, listen.clone())
+                    .await
+                    .map(|binding| (conn, binding)),
+                Err(err) => Err(err),
+            };
+
+            match binding {
+                Ok((conn, mut binding)) => {
+                    log::warn!(
+                        "[forward] [reverse] [{bind_id:#06x}] listening on {addr} of {server}, forwarding to {target}",
+                        addr = binding.addr(),
+                        server = conn.server(),
+                        target = self.target,
+                    );
+
+                    binding.closed().await;
+                    log::warn!("[forward] [reverse] [{bind_id:#06x}] binding closed");
+                }
+                Err(err) => {
+                    log::warn!(
+                        "[forward] [reverse] [{bind_id:#06x}] unable to bind {listen}: {err}"
+                    )
+                }
+            }
+
+            time::sleep(REBIND_INTERVAL).await;
+        }
+    }
+
+    /// Relays a connection accepted on the port to the target
+    pub async fn handle_inbound(mut inbound: Inbound) {
+        let bind_id = inbound.bind_id();
+        let peer_addr = inbound.addr().clone();
+
+        let Some(forward) = REVERSE_FORWARDS.lock().get(&bind_id).cloned() else {
+            let _ = inbound.reset(ERROR_CODE);
+            log::warn!("[forward] [reverse] [{bind_id:#06x}] [{peer_addr}] unknown binding");
+            return;
+        };
+
+        log::info!(
+            "[forward] [reverse] [{bind_id:#06x}] [{peer_addr}] {}",
+            forward.target
+        );
+
+        match protect::connect_direct(&forward.target).await {
+            Ok(mut stream) => {
+                let mut inbound = inbound.compat();
+                let res = io::copy_bidirectional(&mut inbound, &mut stream).await;
+                let _ = inbound.get_mut().reset(ERROR_CODE);
+                let _ = stream.shutdown().await;
+
+                if let Err(err) = res {
+                    log::warn!(
+                        "[forward] [reverse] [{bind_id:#06x}] [{peer_addr}] relaying error: {err}"
+                    );
+                }
+            }
+            Err(err) => {
+                let _ = inbound.reset(ERROR_CODE);
+                log::warn!("[forward] [reverse] [{bind_id:#06x}] [{peer_addr}] unable to connect to {}: {err}", forward.target);
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! A plain HTTP health check endpoint for orchestrators, e.g. a Docker `HEALTHCHECK` or a Kubernetes probe
+//!
+//! `GET /healthz` is answered with `200 OK` while at least one relay server is healthy, and `503 Service Unavailable` otherwise. Any other path is `404 Not Found`.
+
+use crate::{connection::Connection, error::Error};
+use parking_lot::Mutex;
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+static LISTENER: Mutex<Option<Arc<TcpListener>>> = Mutex::new(None);
+
+/// How long a probe has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The request head is truncated to this, which is plenty for the request line
+const MAX_REQUEST_SIZE: usize = 1024;
+
+pub struct Healthz;
+
+impl Healthz {
+    pub fn set_config(listen: Option<SocketAddr>) -> Result<(), Error> {
+        let listener = listen
+            .map(|listen| {
+                StdTcpListener::bind(listen).and_then(|socket| {
+                    socket.set_nonblocking(true)?;
+                    TcpListener::from_std(socket)
+                })
+            })
+            .transpose()
+            .map_err(|err| Error::Socket("failed to bind health check listener", err))?;
+
+        *LISTENER.lock() = listener.map(Arc::new);
+
+        Ok(())
+    }
+
+    pub async fn start() {
+        let Some(listener) = LISTENER.lock().clone() else {
+            return;
+        };
+
+        log::warn!("[healthz] listening on {}", listener.local_addr().unwrap());
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = handle(stream).await {
+                            log::debug!("[healthz] {err}");
+                        }
+                    });
+                }
+                Err(err) => log::warn!("[healthz] failed accepting connection: {err}"),
+            }
+        }
+    }
+
+    pub fn stop() {
+        *LISTENER.lock() = None;
+    }
+}
+
+async fn handle(mut stream: TcpStream) -> Result<(), IoError> {
+    let mut buf = vec![0; MAX_REQUEST_SIZE];
+    let mut len = 0;
+
+    // the request line is all that matters, so the headers are not read past the buffer
+    time::timeout(REQUEST_TIMEOUT, async {
+        while len < buf.len() && !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            match stream.read(&mut buf[len..]).await? {
+                0 => break,
+                n => len += n,
+            }
+        }
+
+        Ok::<_, IoError>(())
+    })
+    .await
+    .map_err(|_| IoError::from(ErrorKind::TimedOut))??;
+
+    let req = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = req.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let (status, body) = match (method, path) {
+        ("GET" | "HEAD", "/healthz") => {
+            if Connection::servers().iter().any(|server| server.healthy) {
+                ("200 OK", "ok\n")
+            } else {
+                ("503 Service Unavailable", "no healthy relay server\n")
+            }
+        }
+        ("GET" | "HEAD", _) => ("404 Not Found", "not found\n"),
+        _ => ("405 Method Not Allowed", "method not allowed\n"),
+    };
+
+    let mut res = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+
+    if method != "HEAD" {
+        res.push_str(body);
+    }
+
+    stream.write_all(res.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
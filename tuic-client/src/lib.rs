@@ -0,0 +1,215 @@
+//! The TUIC client, as a library for embedding it in other applications
+//!
+//! The client keeps its state globally, so only one instance runs in a process. Set it up with [`set_config()`] inside a Tokio runtime, then drive it with [`run()`]. It can be set up and run again after stopping.
+
+use crate::{
+    config::ShareLink,
+    connection::Connection,
+    controller::Controller,
+    dns::Server as DnsServer,
+    forward::{Forward, ReverseForward},
+    healthz::Healthz,
+    reload::Reloader,
+    router::Router,
+    sip003::Server as Sip003Server,
+    socks5::Server as Socks5Server,
+    system_proxy::SystemProxy,
+    telemetry::Telemetry,
+};
+use serde_json::Value;
+use std::future::Future;
+use tokio::sync::broadcast::Receiver;
+
+#[cfg(unix)]
+use {crate::tun::Tun, std::os::fd::RawFd};
+
+#[cfg(target_os = "android")]
+pub mod android;
+mod bench;
+mod config;
+mod connection;
+mod controller;
+mod dns;
+mod error;
+mod events;
+mod forward;
+mod healthz;
+mod protect;
+mod qlog;
+mod reload;
+#[cfg(feature = "resolver")]
+pub mod resolver;
+mod router;
+mod sip003;
+mod socks5;
+mod system_proxy;
+mod telemetry;
+#[cfg(unix)]
+mod tun;
+mod utils;
+
+pub use crate::{
+    config::{Config, ConfigError},
+    error::Error,
+    events::Event,
+    router::Mode,
+};
+
+#[cfg(feature = "resolver")]
+pub use crate::resolver::TuicResolver;
+
+/// Sets up the client from the config, binding the local listeners
+pub fn set_config(cfg: Config) -> Result<(), Error> {
+    for warning in &cfg.warnings {
+        log::warn!("[config] {warning}");
+    }
+
+    let socks_server = cfg.socks_server();
+
+    Connection::set_config(cfg.relay, cfg.health_check, cfg.balance, cfg.reconnect)?;
+    Router::set_config(cfg.router)?;
+    Controller::set_config(cfg.controller, socks_server.port(), cfg.log_level)?;
+
+    if let Some(sip003) = cfg.sip003 {
+        return Sip003Server::set_config(sip003);
+    }
+
+    SystemProxy::set_config(cfg.system_proxy, socks_server)?;
+    Healthz::set_config(cfg.healthz)?;
+    Telemetry::set_config(cfg.telemetry)?;
+    Socks5Server::set_config(cfg.local)?;
+    DnsServer::set_config(cfg.dns)?;
+    Forward::set_config(cfg.forward)?;
+    ReverseForward::set_config(cfg.reverse_forward);
+
+    if let Some(path) = cfg.path {
+        Reloader::set_config(path, cfg.raw, cfg.overrides)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the relay servers and the router can be set up with the config, without binding any local listener
+///
+/// The relay server endpoints are created for loading the certificates, and the router for loading the rule sets, then both are stopped. Must be called within a Tokio runtime.
+pub fn check_config(cfg: Config) -> Result<(), Error> {
+    let res = Connection::set_config(cfg.relay, cfg.health_check, cfg.balance, cfg.reconnect)
+        .and_then(|()| Router::set_config(cfg.router));
+
+    Router::stop();
+    Connection::stop();
+
+    res
+}
+
+/// Measures the throughput and latency to the relay servers in the config with the `bench` arguments, printing the results
+///
+/// The relay servers must have `allow_bench` enabled. No local listener is bound. Must be called within a Tokio runtime.
+pub async fn bench(cfg: Config) -> Result<(), Error> {
+    bench::run(cfg).await
+}
+
+/// Runs the client set up with [`set_config()`] until `shutdown` resolves, then stops it
+///
+/// Stopping restores the system proxy, closes the local listeners, dissociates the UDP sessions and closes the connections to the relay servers.
+pub async fn run(shutdown: impl Future<Output = ()>) {
+    if Sip003Server::is_enabled() {
+        tokio::select! {
+            _ = Sip003Server::start() => {}
+            _ = shutdown => {}
+        }
+
+        Connection::teardown_udp_sessions().await;
+        Connection::stop();
+        return;
+    }
+
+    let tasks = [
+        tokio::spawn(DnsServer::start()),
+        tokio::spawn(Controller::start()),
+        tokio::spawn(Healthz::start()),
+        tokio::spawn(Reloader::start()),
+        tokio::spawn(SystemProxy::start()),
+        tokio::spawn(events::sample_traffic()),
+        tokio::spawn(Telemetry::start()),
+        tokio::spawn(Forward::start()),
+        tokio::spawn(ReverseForward::start()),
+        #[cfg(unix)]
+        tokio::spawn(Tun::start()),
+    ];
+
+    tokio::select! {
+        _ = Socks5Server::start() => {}
+        _ = shutdown => {}
+    }
+
+    for task in tasks {
+        task.abort();
+    }
+
+    SystemProxy::restore();
+    Socks5Server::stop();
+    Healthz::stop();
+    Telemetry::stop();
+    Forward::stop();
+    ReverseForward::stop();
+    #[cfg(unix)]
+    Tun::stop();
+    let _ = DnsServer::set_config(None);
+    Router::stop();
+    Connection::teardown_udp_sessions().await;
+    Connection::stop();
+}
+
+/// Sets the protector called with each outbound socket before use, to exclude it from the TUN device of the app embedding the client
+///
+/// It should return `false` if the socket could not be protected. Set it before [`set_config()`], which creates the sockets of the relay server endpoints.
+#[cfg(unix)]
+pub fn set_socket_protector(protector: impl Fn(RawFd) -> bool + Send + Sync + 'static) {
+    protect::set_protector(Some(std::sync::Arc::new(protector)));
+}
+
+/// Migrates the connections to the relay servers onto new sockets, e.g. when the app embedding the client is notified of a network change
+///
+/// The connections and the UDP associations relayed through them are kept. Must be called within a Tokio runtime.
+pub fn rebind() -> Result<(), Error> {
+    Connection::rebind()
+}
+
+/// Starts reading IP packets from a TUN device with the MTU, replacing the current one
+///
+/// The file descriptor is duplicated, so the caller keeps the ownership of it. The device is released when the client stops. Must be called within a Tokio runtime.
+#[cfg(unix)]
+pub fn set_tun(fd: RawFd, mtu: u16) -> Result<(), Error> {
+    Tun::set_fd(Some((fd, mtu)))
+}
+
+pub fn mode() -> Mode {
+    Router::mode()
+}
+
+/// Sets the routing mode, taking effect for new connections and UDP packets
+pub fn set_mode(mode: Mode) {
+    Router::set_mode(mode);
+}
+
+/// Traffic statistics by relay server and by rule, in the format of `/stats` of the controller
+pub fn stats() -> Value {
+    controller::stats()
+}
+
+/// Subscribes to the events of the client, e.g. for a GUI to show the state of the connections and the traffic
+///
+/// The subscription outlives the client, receiving the events after it is set up and run again. Events missed by lagging behind are skipped.
+pub fn subscribe_events() -> Receiver<Event> {
+    events::subscribe()
+}
+
+/// Converts a share link into the relay config, which can be added to `relay` of the config
+pub fn import_share_link(link: &str) -> Result<Value, Error> {
+    let link = link
+        .parse::<ShareLink>()
+        .map_err(|err| ConfigError::Import(err.to_owned()))?;
+
+    Ok(link.to_relay_value())
+}
@@ -1,16 +1,14 @@
-use crate::{
-    config::{Config, ConfigError},
-    connection::Connection,
-    socks5::Server as Socks5Server,
-};
 use env_logger::Builder as LoggerBuilder;
+use log::LevelFilter;
 use std::{env, process};
+use tuic_client::{Config, ConfigError};
 
-mod config;
-mod connection;
-mod error;
-mod socks5;
-mod utils;
+/// The config is invalid, or the files it refers to cannot be loaded
+const EXIT_INVALID_CONFIG: i32 = 1;
+/// The arguments are wrong, or no config is given
+const EXIT_USAGE: i32 = 2;
+/// The config is valid, but the client fails to start, e.g. a local listener cannot be bound
+const EXIT_STARTUP: i32 = 3;
 
 #[tokio::main]
 async fn main() {
@@ -20,33 +18,98 @@ async fn main() {
             println!("{msg}");
             process::exit(0);
         }
+        Err(ConfigError::ShareLinks(links)) => {
+            println!("{links}");
+            process::exit(0);
+        }
+        Err(ConfigError::Imported(cfg)) => {
+            println!("{cfg}");
+            process::exit(0);
+        }
+        Err(
+            err @ (ConfigError::Argument(_) | ConfigError::NoConfig | ConfigError::Override(_)),
+        ) => {
+            eprintln!("{err}");
+            process::exit(EXIT_USAGE);
+        }
         Err(err) => {
             eprintln!("{err}");
-            process::exit(1);
+            process::exit(EXIT_INVALID_CONFIG);
         }
     };
 
+    if cfg.check {
+        for warning in &cfg.warnings {
+            eprintln!("warning: {warning}");
+        }
+
+        match tuic_client::check_config(cfg) {
+            Ok(()) => {
+                println!("config OK");
+                process::exit(0);
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(EXIT_INVALID_CONFIG);
+            }
+        }
+    }
+
+    // the results of bench are not to be buried in the logs
+    let log_level = if cfg.bench.is_some() {
+        cfg.log_level.min(LevelFilter::Warn)
+    } else {
+        cfg.log_level
+    };
+
     LoggerBuilder::new()
-        .filter_level(cfg.log_level)
+        .filter_level(log_level)
         .format_module_path(false)
         .format_target(false)
         .init();
 
-    match Connection::set_config(cfg.relay) {
-        Ok(()) => {}
-        Err(err) => {
-            eprintln!("{err}");
-            process::exit(1);
+    if cfg.bench.is_some() {
+        match tuic_client::bench(cfg).await {
+            Ok(()) => process::exit(0),
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(EXIT_STARTUP);
+            }
         }
     }
 
-    match Socks5Server::set_config(cfg.local) {
+    match tuic_client::set_config(cfg) {
         Ok(()) => {}
         Err(err) => {
             eprintln!("{err}");
-            process::exit(1);
+            process::exit(EXIT_STARTUP);
         }
     }
 
-    Socks5Server::start().await;
+    tuic_client::run(shutdown_signal()).await;
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+
+        match unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
@@ -0,0 +1,111 @@
+//! Protecting the outbound sockets of the client from being routed back into the TUN device
+//!
+//! When the client is embedded in an app owning a TUN device, sockets connecting to the relay servers and direct targets must be excluded from the device, e.g. with `VpnService.protect()` on Android. The protector set by the app is called with each of them before use.
+
+use crate::error::Error;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket},
+};
+use tokio::net::{self, TcpSocket, TcpStream, UdpSocket};
+use tuic::Address;
+
+#[cfg(unix)]
+use {
+    parking_lot::RwLock,
+    std::{
+        os::fd::{AsRawFd, RawFd},
+        sync::Arc,
+    },
+};
+
+#[cfg(unix)]
+pub type Protector = Arc<dyn Fn(RawFd) -> bool + Send + Sync>;
+
+#[cfg(unix)]
+static PROTECTOR: RwLock<Option<Protector>> = RwLock::new(None);
+
+/// Sets the protector, which returns `false` if the socket could not be protected
+///
+/// Sockets of the relay server endpoints are created when setting up the client, so the protector should be set before that.
+#[cfg(unix)]
+pub fn set_protector(protector: Option<Protector>) {
+    *PROTECTOR.write() = protector;
+}
+
+#[cfg(unix)]
+pub fn protect(socket: &impl AsRawFd) -> Result<(), IoError> {
+    let Some(protector) = PROTECTOR.read().clone() else {
+        return Ok(());
+    };
+
+    if protector(socket.as_raw_fd()) {
+        Ok(())
+    } else {
+        Err(IoError::new(
+            ErrorKind::PermissionDenied,
+            "failed to protect socket",
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn protect<S>(_: &S) -> Result<(), IoError> {
+    Ok(())
+}
+
+/// Connects to the target directly, trying each address it resolves to in turn
+pub async fn connect_direct(addr: &Address) -> Result<TcpStream, IoError> {
+    let addrs = match addr {
+        Address::DomainAddress(domain, port) => {
+            net::lookup_host((domain.as_str(), *port)).await?.collect()
+        }
+        Address::SocketAddress(addr) => vec![*addr],
+        Address::None => unreachable!(),
+    };
+
+    let mut last_err = None;
+
+    for addr in addrs {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+
+        protect(&socket)?;
+
+        match socket.connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| IoError::new(ErrorKind::NotFound, "no address resolved")))
+}
+
+/// Binds a UDP socket for sending packets directly to the targets
+///
+/// A dual-stack IPv6 socket is tried first, falling back to IPv4.
+pub fn bind_direct_udp() -> Result<UdpSocket, Error> {
+    fn bind(domain: Domain, addr: SocketAddr) -> Result<Socket, IoError> {
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+
+        if domain == Domain::IPV6 {
+            socket.set_only_v6(false)?;
+        }
+
+        socket.set_nonblocking(true)?;
+        socket.bind(&SockAddr::from(addr))?;
+        protect(&socket)?;
+        Ok(socket)
+    }
+
+    let socket = bind(Domain::IPV6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+        .or_else(|_| bind(Domain::IPV4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))))
+        .map_err(|err| Error::Socket("failed to create direct UDP socket", err))?;
+
+    UdpSocket::from_std(StdUdpSocket::from(socket))
+        .map_err(|err| Error::Socket("failed to create direct UDP socket", err))
+}
@@ -0,0 +1,182 @@
+//! qlog traces of the QUIC connections, for analyzing transport-level issues like loss and congestion window collapse with standard QUIC tooling, e.g. qvis
+//!
+//! quinn 0.10 does not emit qlog events itself, so the recovery state is sampled from the statistics of the connection. Each connection is traced into its own file in the JSON-SEQ serialization of qlog 0.3.
+
+use quinn::{Connection, ConnectionError};
+use quinn_proto::ConnectionStats;
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{BufWriter, Error as IoError, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::time;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Trace {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl Trace {
+    /// Creates the trace file, named `<unix time in ms>-<connection ID>.sqlog` in the directory
+    fn create(dir: &Path, id: u32) -> Result<Self, IoError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let path = dir.join(format!("{}-{id:08x}.sqlog", now.as_millis()));
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let header = json!({
+            "qlog_version": "0.3",
+            "qlog_format": "JSON-SEQ",
+            "title": "tuic-client",
+            "trace": {
+                "vantage_point": { "name": "tuic-client", "type": "client" },
+                "common_fields": {
+                    "group_id": format!("{id:#010x}"),
+                    "time_format": "relative",
+                    "reference_time": now.as_secs_f64() * 1000.0,
+                },
+            },
+        });
+
+        write_record(&mut file, &header)?;
+
+        Ok(Self {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    fn event(&mut self, name: &str, data: Value) -> Result<(), IoError> {
+        let event = json!({
+            "time": self.start.elapsed().as_secs_f64() * 1000.0,
+            "name": name,
+            "data": data,
+        });
+
+        write_record(&mut self.file, &event)
+    }
+
+    /// Records the changes of the connection statistics since the last sample
+    fn sample(&mut self, prev: &ConnectionStats, stats: &ConnectionStats) -> Result<(), IoError> {
+        if stats.path.rtt != prev.path.rtt || stats.path.cwnd != prev.path.cwnd {
+            self.event(
+                "recovery:metrics_updated",
+                json!({
+                    "smoothed_rtt": stats.path.rtt.as_secs_f64() * 1000.0,
+                    "congestion_window": stats.path.cwnd,
+                }),
+            )?;
+        }
+
+        if stats.path.congestion_events > prev.path.congestion_events {
+            self.event(
+                "recovery:congestion_state_updated",
+                json!({ "new": "recovery" }),
+            )?;
+        }
+
+        if stats.path.lost_packets > prev.path.lost_packets {
+            self.event(
+                "recovery:packets_lost",
+                json!({
+                    "count": stats.path.lost_packets - prev.path.lost_packets,
+                    "bytes": stats.path.lost_bytes - prev.path.lost_bytes,
+                }),
+            )?;
+        }
+
+        if stats.path.black_holes_detected > prev.path.black_holes_detected {
+            self.event("transport:mtu_black_hole_detected", json!({}))?;
+        }
+
+        if stats.udp_tx.datagrams != prev.udp_tx.datagrams
+            || stats.udp_rx.datagrams != prev.udp_rx.datagrams
+        {
+            self.event(
+                "transport:datagrams_updated",
+                json!({
+                    "sent_datagrams": stats.udp_tx.datagrams,
+                    "sent_bytes": stats.udp_tx.bytes,
+                    "received_datagrams": stats.udp_rx.datagrams,
+                    "received_bytes": stats.udp_rx.bytes,
+                    "sent_packets": stats.path.sent_packets,
+                    "lost_packets": stats.path.lost_packets,
+                }),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_record(file: &mut BufWriter<File>, record: &Value) -> Result<(), IoError> {
+    file.write_all(b"\x1e")?;
+    serde_json::to_writer(&mut *file, record)?;
+    file.write_all(b"\n")
+}
+
+/// Traces the connection into the directory until it is closed
+pub async fn trace(conn: Connection, dir: Arc<Path>) {
+    let id = conn.stable_id() as u32;
+    let addr = conn.remote_address();
+
+    let res = async {
+        let mut trace = Trace::create(&dir, id)?;
+
+        trace.event(
+            "connectivity:connection_started",
+            json!({
+                "ip_version": if addr.is_ipv4() { "ipv4" } else { "ipv6" },
+                "dst_ip": addr.ip(),
+                "dst_port": addr.port(),
+                "protocol": "QUIC",
+            }),
+        )?;
+
+        let mut prev = ConnectionStats::default();
+
+        let err = loop {
+            let err = tokio::select! {
+                _ = time::sleep(SAMPLE_INTERVAL) => None,
+                err = conn.closed() => Some(err),
+            };
+
+            let stats = conn.stats();
+            trace.sample(&prev, &stats)?;
+            prev = stats;
+
+            if let Some(err) = err {
+                break err;
+            }
+
+            trace.file.flush()?;
+        };
+
+        let (owner, trigger) = match err {
+            ConnectionError::LocallyClosed => ("local", "clean"),
+            ConnectionError::TimedOut => ("local", "idle_timeout"),
+            ConnectionError::ConnectionClosed(_) | ConnectionError::ApplicationClosed(_) => {
+                ("remote", "clean")
+            }
+            _ => ("local", "error"),
+        };
+
+        trace.event(
+            "connectivity:connection_closed",
+            json!({ "owner": owner, "trigger": trigger, "reason": err.to_string() }),
+        )?;
+
+        trace.file.flush()
+    };
+
+    if let Err(err) = res.await {
+        log::warn!("[relay] [{addr}] failed writing qlog of connection {id:#010x}: {err}");
+    }
+}
@@ -0,0 +1,183 @@
+//! Reloading the config file on SIGHUP or from the controller API
+//!
+//! Only the sections changed since the last load are applied. Changes to `controller`, `healthz`, `telemetry`, `system_proxy`, `forward`, `reverse_forward` and `log_level` require a restart.
+
+use crate::{
+    config::Config, connection::Connection, dns::Server as DnsServer, error::Error, router::Router,
+    socks5::Server as Socks5Server,
+};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::path::PathBuf;
+use tuic_config::Override;
+
+static RELOADER: OnceCell<Reloader> = OnceCell::new();
+
+const RELAY_SECTIONS: &[&str] = &["relay", "health_check", "balance", "reconnect"];
+const RESTART_SECTIONS: &[&str] = &[
+    "controller",
+    "healthz",
+    "telemetry",
+    "system_proxy",
+    "forward",
+    "reverse_forward",
+    "log_level",
+];
+
+pub struct Reloader {
+    /// The path of the config file and the config currently applied
+    state: Mutex<(PathBuf, Value)>,
+    /// The fields set by the environment variables and the command line, taking precedence over the config file
+    overrides: Mutex<Vec<Override>>,
+}
+
+impl Reloader {
+    pub fn set_config(path: PathBuf, raw: Value, overrides: Vec<Override>) -> Result<(), Error> {
+        let reloader = RELOADER.get_or_init(|| Self {
+            state: Mutex::new((PathBuf::new(), Value::Null)),
+            overrides: Mutex::new(Vec::new()),
+        });
+
+        *reloader.state.lock() = (path, raw);
+        *reloader.overrides.lock() = overrides;
+
+        Ok(())
+    }
+
+    /// Reloads the config on SIGHUP
+    pub async fn start() {
+        if RELOADER.get().is_none() {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{self, SignalKind};
+
+            let mut hangup = match unix::signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(err) => {
+                    log::error!("[reload] failed to listen for SIGHUP: {err}");
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                log::info!("[reload] received SIGHUP");
+
+                if let Err(err) = Self::reload(None) {
+                    log::error!("[reload] failed reloading config: {err}");
+                }
+            }
+        }
+    }
+
+    /// Reloads the config from the given path, or the current config file if not set
+    ///
+    /// Each changed section is applied on its own. Sections failing to apply are kept as before and retried with the next reload, and their errors are returned together.
+    pub fn reload(path: Option<PathBuf>) -> Result<(), Error> {
+        let reloader = RELOADER.get().ok_or(Error::ReloadUnavailable)?;
+        let mut state = reloader.state.lock();
+        let (current_path, applied) = &mut *state;
+
+        let path = path.unwrap_or_else(|| current_path.clone());
+        let cfg = Config::from_file(&path, reloader.overrides.lock().clone())?;
+        *current_path = path;
+
+        for warning in &cfg.warnings {
+            log::warn!("[reload] {warning}");
+        }
+
+        let restart_required = changed(&cfg.raw, applied, RESTART_SECTIONS);
+        let relay_changed = !changed(&cfg.raw, applied, RELAY_SECTIONS).is_empty();
+        let router_changed = !changed(&cfg.raw, applied, &["router"]).is_empty();
+        let local_changed = !changed(&cfg.raw, applied, &["local"]).is_empty();
+        let dns_changed = !changed(&cfg.raw, applied, &["dns"]).is_empty();
+
+        if !restart_required.is_empty() {
+            log::warn!(
+                "[reload] changes to {} are ignored until restart",
+                restart_required.join(", ")
+            );
+        }
+
+        let mut reloaded = Vec::new();
+        let mut failed = Vec::new();
+
+        if router_changed {
+            match Router::set_config(cfg.router) {
+                Ok(()) => {
+                    mark_applied(applied, &cfg.raw, &["router"]);
+                    reloaded.push("router");
+                }
+                Err(err) => failed.push(("router", err)),
+            }
+        }
+
+        // the rule sets of the DNS rules are loaded with the geosite file and domain lists of the router
+        if dns_changed || router_changed {
+            match DnsServer::set_config(cfg.dns) {
+                Ok(()) => {
+                    mark_applied(applied, &cfg.raw, &["dns"]);
+                    reloaded.push("dns");
+                }
+                Err(err) => failed.push(("dns", err)),
+            }
+        }
+
+        if local_changed {
+            match Socks5Server::set_config(cfg.local) {
+                Ok(()) => {
+                    mark_applied(applied, &cfg.raw, &["local"]);
+                    reloaded.push("local");
+                }
+                Err(err) => failed.push(("local", err)),
+            }
+        }
+
+        if relay_changed {
+            match Connection::set_config(cfg.relay, cfg.health_check, cfg.balance, cfg.reconnect)
+            {
+                Ok(()) => {
+                    mark_applied(applied, &cfg.raw, RELAY_SECTIONS);
+                    reloaded.push("relay");
+                }
+                Err(err) => failed.push(("relay", err)),
+            }
+        }
+
+        if !reloaded.is_empty() {
+            log::warn!("[reload] reloaded {}", reloaded.join(", "));
+        } else if failed.is_empty() {
+            log::info!("[reload] config unchanged");
+        }
+
+        if !failed.is_empty() {
+            return Err(Error::Reload(failed));
+        }
+
+        Ok(())
+    }
+}
+
+fn section<'a>(cfg: &'a Value, key: &str) -> &'a Value {
+    cfg.get(key).unwrap_or(&Value::Null)
+}
+
+fn changed(cfg: &Value, applied: &Value, keys: &[&'static str]) -> Vec<&'static str> {
+    keys.iter()
+        .filter(|key| section(cfg, key) != section(applied, key))
+        .copied()
+        .collect()
+}
+
+fn mark_applied(applied: &mut Value, cfg: &Value, keys: &[&str]) {
+    let Value::Object(applied) = applied else {
+        return;
+    };
+
+    for key in keys {
+        applied.insert(key.to_string(), section(cfg, key).clone());
+    }
+}
@@ -0,0 +1,230 @@
+//! Resolving names through the relay servers, for programs embedding the client
+//!
+//! [`TuicResolver`] is a runtime provider of hickory-resolver, connecting to the name servers through the relay servers of the client set up with [`set_config()`](crate::set_config), so that neither the queries nor the responses leave the tunnel. TCP connections to the name servers are relayed as TCP streams, and each UDP socket of the resolver is a UDP association of its own. The routing rules and the local DNS server are not involved, so the name servers should be reachable from the relay servers.
+
+use crate::connection::{self, Connection as TuicConnection};
+use bytes::Bytes;
+use futures_util::{
+    future,
+    io::{AsyncRead, AsyncWrite},
+    ready,
+};
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    name_server::{GenericConnector, RuntimeProvider, TokioHandle},
+    proto::{tcp::DnsTcpStream, udp::DnsUdpSocket, TokioTime},
+    AsyncResolver,
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{self, Receiver, Sender},
+};
+use tuic::Address;
+use tuic_quinn::Connect;
+
+/// Senders of packets received from the relay to the UDP sockets of the resolvers, by association ID
+pub(crate) static UDP_SESSIONS: Lazy<Mutex<HashMap<u16, Sender<Response>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A packet from a name server, with its address
+type Response = (Bytes, SocketAddr);
+
+/// A hickory-resolver runtime provider relaying the connections to the name servers through the relay servers
+///
+/// ```ignore
+/// let resolver = TuicResolver::resolver(ResolverConfig::cloudflare(), ResolverOpts::default());
+/// let addrs = resolver.lookup_ip("example.com").await?;
+/// ```
+#[derive(Clone, Default)]
+pub struct TuicResolver(TokioHandle);
+
+impl TuicResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a resolver querying the name servers in `config` through the relay servers
+    pub fn resolver(
+        config: ResolverConfig,
+        options: ResolverOpts,
+    ) -> AsyncResolver<GenericConnector<Self>> {
+        AsyncResolver::new(config, options, GenericConnector::new(Self::new()))
+    }
+}
+
+impl RuntimeProvider for TuicResolver {
+    type Handle = TokioHandle;
+    type Timer = TokioTime;
+    type Udp = TuicUdpSocket;
+    type Tcp = TuicTcpStream;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.0.clone()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = IoResult<Self::Tcp>>>> {
+        Box::pin(async move {
+            let addr = Address::SocketAddress(server_addr);
+            log::debug!("[resolver] [tcp] {addr}");
+
+            let relay = match TuicConnection::get_for_connect(&addr).await {
+                Ok(conn) => conn.connect(addr, None).await,
+                Err(err) => Err(err),
+            };
+
+            relay
+                .map(TuicTcpStream)
+                .map_err(|err| IoError::new(ErrorKind::Other, err))
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        _local_addr: SocketAddr,
+        _server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = IoResult<Self::Udp>>>> {
+        Box::pin(future::ready(Ok(TuicUdpSocket::new())))
+    }
+}
+
+/// A TCP stream to a name server, relayed through a relay server
+pub struct TuicTcpStream(Connect);
+
+impl DnsTcpStream for TuicTcpStream {
+    type Time = TokioTime;
+}
+
+impl AsyncRead for TuicTcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TuicTcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+/// A UDP socket of the resolver, relaying packets to the name servers in a UDP association of its own
+///
+/// The association is dissociated once the socket is dropped.
+pub struct TuicUdpSocket {
+    assoc_id: u16,
+    rx: Mutex<Receiver<Response>>,
+}
+
+impl TuicUdpSocket {
+    fn new() -> Self {
+        let assoc_id = connection::next_assoc_id();
+        let (tx, rx) = mpsc::channel(64);
+        UDP_SESSIONS.lock().insert(assoc_id, tx);
+
+        Self {
+            assoc_id,
+            rx: Mutex::new(rx),
+        }
+    }
+}
+
+impl DnsUdpSocket for TuicUdpSocket {
+    type Time = TokioTime;
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<(usize, SocketAddr)>> {
+        match ready!(self.rx.lock().poll_recv(cx)) {
+            Some((pkt, addr)) => {
+                let len = pkt.len().min(buf.len());
+                buf[..len].copy_from_slice(&pkt[..len]);
+                Poll::Ready(Ok((len, addr)))
+            }
+            None => Poll::Ready(Err(IoError::from(ErrorKind::BrokenPipe))),
+        }
+    }
+
+    // the packet is relayed in the background, as UDP gives no guarantee of delivery anyway
+    fn poll_send_to(
+        &self,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<IoResult<usize>> {
+        let assoc_id = self.assoc_id;
+        let pkt = Bytes::copy_from_slice(buf);
+
+        tokio::spawn(async move {
+            log::debug!("[resolver] [udp] [{assoc_id:#06x}] {target}");
+
+            let res = match TuicConnection::get_for_packet(assoc_id).await {
+                Ok(conn) => {
+                    conn.packet(pkt, Address::SocketAddress(target), assoc_id, None)
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = res {
+                log::warn!(
+                    "[resolver] [udp] [{assoc_id:#06x}] failed relaying query to {target}: {err}"
+                );
+            }
+        });
+
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+impl Drop for TuicUdpSocket {
+    fn drop(&mut self) {
+        let assoc_id = self.assoc_id;
+        UDP_SESSIONS.lock().remove(&assoc_id);
+
+        let Ok(handle) = Handle::try_current() else {
+            return;
+        };
+
+        handle.spawn(async move {
+            let res = match TuicConnection::get_for_dissociate(assoc_id).await {
+                Ok(Some(conn)) => conn.dissociate(assoc_id).await,
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = res {
+                log::warn!("[resolver] [udp] [{assoc_id:#06x}] failed stopping UDP relaying session: {err}");
+            }
+        });
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{config::Bypass as BypassConfig, connection::Connection, protect, utils::IpCidr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use tuic::Address;
+
+/// Targets always connected to directly, so that the traffic of the client itself is never looped back into the relay, e.g. when a TUN device captures it
+///
+/// These are the private address ranges, the relay servers, the addresses of the client host itself and the configured CIDRs.
+pub struct Bypass {
+    private: bool,
+    server: bool,
+    local: bool,
+    cidrs: Vec<IpCidr>,
+}
+
+impl Bypass {
+    pub fn new(cfg: BypassConfig) -> Self {
+        Self {
+            private: cfg.private,
+            server: cfg.server,
+            local: cfg.local,
+            cidrs: cfg.cidrs,
+        }
+    }
+
+    pub fn matches(&self, addr: &Address) -> bool {
+        let ip = match addr {
+            Address::SocketAddress(addr) => Some(canonicalize(addr.ip())),
+            _ => None,
+        };
+
+        if let Some(ip) = ip {
+            if (self.private && is_private(ip)) || self.cidrs.iter().any(|cidr| cidr.contains(ip)) {
+                return true;
+            }
+        }
+
+        if self.server && Connection::is_server(addr) {
+            return true;
+        }
+
+        ip.map_or(false, |ip| self.local && is_local(ip))
+    }
+}
+
+fn canonicalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        },
+        ip => ip,
+    }
+}
+
+/// Whether the address is in the loopback, private, shared (CGNAT), link-local or unique local ranges
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Whether the address belongs to the client host, by asking the system routing table for the source address it would use to reach the address
+///
+/// The probing socket is protected, so that the route is looked up outside the TUN device. No packet is sent.
+fn is_local(ip: IpAddr) -> bool {
+    let bind_addr = match ip {
+        IpAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        IpAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+
+    let probe = || {
+        let socket = UdpSocket::bind(bind_addr)?;
+        protect::protect(&socket)?;
+        socket.connect((ip, 9))?;
+        socket.local_addr()
+    };
+
+    probe().map_or(false, |addr| addr.ip() == ip)
+}
@@ -0,0 +1,92 @@
+use super::Rule;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tuic::Address;
+
+/// Routing verdicts of recent targets, so that the rules are not matched again for every packet of a UDP flow
+///
+/// Verdicts expire after the TTL. When the cache is full, the least recently used verdict is evicted.
+pub struct RouteCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<Address, Entry>,
+    lru: BTreeMap<u64, Address>,
+    next_tick: u64,
+}
+
+struct Entry {
+    rule: Option<Arc<Rule>>,
+    expires_at: Instant,
+    tick: u64,
+}
+
+impl RouteCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && !self.ttl.is_zero()
+    }
+
+    /// Returns the cached verdict of the target, `None` if not cached or expired
+    pub fn get(&mut self, addr: &Address) -> Option<Option<Arc<Rule>>> {
+        let tick = self.tick();
+        let entry = self.entries.get_mut(addr)?;
+
+        if entry.expires_at <= Instant::now() {
+            self.lru.remove(&entry.tick);
+            self.entries.remove(addr);
+            return None;
+        }
+
+        let addr = self.lru.remove(&entry.tick).unwrap();
+        self.lru.insert(tick, addr);
+        entry.tick = tick;
+
+        Some(entry.rule.clone())
+    }
+
+    pub fn insert(&mut self, addr: Address, rule: Option<Arc<Rule>>) {
+        let tick = self.tick();
+
+        if let Some(old) = self.entries.remove(&addr) {
+            self.lru.remove(&old.tick);
+        } else if self.entries.len() >= self.capacity {
+            let oldest = self.lru.keys().next().copied();
+
+            if let Some(oldest) = oldest.and_then(|tick| self.lru.remove(&tick)) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.lru.insert(tick, addr.clone());
+        self.entries.insert(
+            addr,
+            Entry {
+                rule,
+                expires_at: Instant::now() + self.ttl,
+                tick,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+}
@@ -0,0 +1,112 @@
+use crate::error::Error;
+use regex::Regex;
+use std::{collections::HashSet, fs, path::Path};
+use tuic::Address;
+
+/// A set of domain matching entries, loaded from a geosite file or a plain-text domain list
+#[derive(Default)]
+pub struct DomainSet {
+    full: HashSet<String>,
+    suffix: HashSet<String>,
+    keyword: Vec<String>,
+    regex: Vec<Regex>,
+}
+
+impl DomainSet {
+    /// Loads a v2ray-style plain-text domain list
+    ///
+    /// Each line is one of `domain:DOMAIN`, `full:DOMAIN`, `keyword:KEYWORD`, `regexp:REGEX` or a bare `DOMAIN` (same as `domain:`). Attributes (` @attr`), anything else after whitespace and lines starting with `#` are ignored.
+    pub fn load_list(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let mut set = Self::default();
+
+        for line in content.lines() {
+            // `#` only starts a comment at the beginning of a line, as it can be part of a regular expression
+            let line = line.split_whitespace().next().unwrap_or_default();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some(("domain", domain)) => set.insert_suffix(domain),
+                Some(("full", domain)) => set.insert_full(domain),
+                Some(("keyword", keyword)) => set.insert_keyword(keyword),
+                Some(("regexp", regex)) => set.insert_regex(regex)?,
+                Some(("include", _)) => {
+                    log::warn!(
+                        "[router] `include` in domain list {path} is not supported, ignoring: {line}",
+                        path = path.display(),
+                    );
+                }
+                Some(_) => {
+                    log::warn!(
+                        "[router] invalid entry in domain list {path}, ignoring: {line}",
+                        path = path.display(),
+                    );
+                }
+                None => set.insert_suffix(line),
+            }
+        }
+
+        Ok(set)
+    }
+
+    pub fn insert_full(&mut self, domain: &str) {
+        self.full.insert(normalize(domain));
+    }
+
+    pub fn insert_suffix(&mut self, domain: &str) {
+        self.suffix.insert(normalize(domain));
+    }
+
+    pub fn insert_keyword(&mut self, keyword: &str) {
+        self.keyword.push(keyword.to_ascii_lowercase());
+    }
+
+    pub fn insert_regex(&mut self, regex: &str) -> Result<(), Error> {
+        self.regex.push(Regex::new(regex)?);
+        Ok(())
+    }
+
+    /// Returns the number of entries in the set
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.full.len() + self.suffix.len() + self.keyword.len() + self.regex.len()
+    }
+
+    /// Returns `true` if the domain matches any entry in the set
+    pub fn contains(&self, domain: &str) -> bool {
+        let domain = normalize(domain);
+
+        if self.full.contains(&domain) {
+            return true;
+        }
+
+        let mut suffix = domain.as_str();
+
+        loop {
+            if self.suffix.contains(suffix) {
+                return true;
+            }
+
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => break,
+            }
+        }
+
+        self.keyword.iter().any(|keyword| domain.contains(keyword))
+            || self.regex.iter().any(|regex| regex.is_match(&domain))
+    }
+}
+
+/// Canonicalizes the domain like domain addresses, so that internationalized domain names match in either form
+///
+/// Entries that are not valid domains, e.g. with a leading dot, are only lowercased with the trailing dot stripped.
+pub fn normalize(domain: &str) -> String {
+    match Address::domain(domain, 0) {
+        Ok(Address::DomainAddress(domain, _)) => domain,
+        _ => domain.trim_end_matches('.').to_ascii_lowercase(),
+    }
+}
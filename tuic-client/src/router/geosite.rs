@@ -0,0 +1,229 @@
+//! A minimal decoder for v2ray's `geosite.dat`, which is a protobuf-encoded `GeoSiteList`
+//!
+//! ```plain
+//! message Domain {
+//!   enum Type { Plain = 0; Regex = 1; RootDomain = 2; Full = 3; }
+//!   Type type = 1;
+//!   string value = 2;
+//!   repeated Attribute attribute = 3;
+//! }
+//!
+//! message GeoSite {
+//!   string country_code = 1;
+//!   repeated Domain domain = 2;
+//! }
+//!
+//! message GeoSiteList {
+//!   repeated GeoSite entry = 1;
+//! }
+//! ```
+
+use super::domain_set::DomainSet;
+use crate::error::Error;
+use std::{collections::HashMap, fs, path::Path};
+
+const DOMAIN_TYPE_PLAIN: u64 = 0;
+const DOMAIN_TYPE_REGEX: u64 = 1;
+const DOMAIN_TYPE_ROOT_DOMAIN: u64 = 2;
+const DOMAIN_TYPE_FULL: u64 = 3;
+
+/// Loads the requested `(code, attribute)` pairs from a geosite file
+///
+/// Codes are matched case-insensitively. Codes not found in the file are not included in the result.
+pub fn load(
+    path: &Path,
+    requested: &[(String, Option<String>)],
+) -> Result<HashMap<(String, Option<String>), DomainSet>, Error> {
+    let buf = fs::read(path)?;
+    let mut sets = HashMap::new();
+
+    for (field, value) in Fields::new(&buf) {
+        let Field::Bytes(entry) = value? else {
+            continue;
+        };
+
+        if field != 1 {
+            continue;
+        }
+
+        let Some(code) = country_code(entry)? else {
+            continue;
+        };
+        let code = code.to_ascii_lowercase();
+
+        for (req_code, req_attr) in requested {
+            if *req_code != code {
+                continue;
+            }
+
+            let mut set = DomainSet::default();
+
+            for (field, value) in Fields::new(entry) {
+                if let (2, Field::Bytes(domain)) = (field, value?) {
+                    insert_domain(&mut set, domain, req_attr.as_deref())?;
+                }
+            }
+
+            sets.insert((req_code.clone(), req_attr.clone()), set);
+        }
+    }
+
+    Ok(sets)
+}
+
+fn country_code(geosite: &[u8]) -> Result<Option<&str>, Error> {
+    for (field, value) in Fields::new(geosite) {
+        if let (1, Field::Bytes(code)) = (field, value?) {
+            return std::str::from_utf8(code)
+                .map(Some)
+                .map_err(|_| Error::InvalidGeoSite("non-UTF-8 country code"));
+        }
+    }
+
+    Ok(None)
+}
+
+fn insert_domain(set: &mut DomainSet, domain: &[u8], attr: Option<&str>) -> Result<(), Error> {
+    let mut domain_type = DOMAIN_TYPE_PLAIN;
+    let mut value = None;
+    let mut has_attr = attr.is_none();
+
+    for (field, field_value) in Fields::new(domain) {
+        match (field, field_value?) {
+            (1, Field::Varint(v)) => domain_type = v,
+            (2, Field::Bytes(v)) => {
+                value = Some(
+                    std::str::from_utf8(v)
+                        .map_err(|_| Error::InvalidGeoSite("non-UTF-8 domain"))?,
+                )
+            }
+            (3, Field::Bytes(v)) => {
+                if let Some(attr) = attr {
+                    has_attr |=
+                        attribute_key(v)?.map_or(false, |key| key.eq_ignore_ascii_case(attr));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let Some(value) = value else {
+        return Err(Error::InvalidGeoSite("domain entry without value"));
+    };
+
+    if !has_attr {
+        return Ok(());
+    }
+
+    match domain_type {
+        DOMAIN_TYPE_PLAIN => set.insert_keyword(value),
+        DOMAIN_TYPE_REGEX => set.insert_regex(value)?,
+        DOMAIN_TYPE_ROOT_DOMAIN => set.insert_suffix(value),
+        DOMAIN_TYPE_FULL => set.insert_full(value),
+        _ => return Err(Error::InvalidGeoSite("unknown domain type")),
+    }
+
+    Ok(())
+}
+
+fn attribute_key(attr: &[u8]) -> Result<Option<&str>, Error> {
+    for (field, value) in Fields::new(attr) {
+        if let (1, Field::Bytes(key)) = (field, value?) {
+            return std::str::from_utf8(key)
+                .map(Some)
+                .map_err(|_| Error::InvalidGeoSite("non-UTF-8 attribute key"));
+        }
+    }
+
+    Ok(None)
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterator over the `(field_number, value)` pairs of a protobuf message
+struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .buf
+                .get(self.pos)
+                .ok_or(Error::InvalidGeoSite("unexpected end of varint"))?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::InvalidGeoSite("varint too long"))
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or(Error::InvalidGeoSite("unexpected end of field"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_field(&mut self) -> Result<(u64, Field<'a>), Error> {
+        let key = self.read_varint()?;
+
+        let value = match key & 0x07 {
+            0 => Field::Varint(self.read_varint()?),
+            1 => {
+                self.read_bytes(8)?;
+                Field::Fixed
+            }
+            2 => {
+                let len = self.read_varint()? as usize;
+                Field::Bytes(self.read_bytes(len)?)
+            }
+            5 => {
+                self.read_bytes(4)?;
+                Field::Fixed
+            }
+            _ => return Err(Error::InvalidGeoSite("unsupported wire type")),
+        };
+
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (u64, Result<Field<'a>, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.buf.len() {
+            return None;
+        }
+
+        match self.read_field() {
+            Ok((field, value)) => Some((field, Ok(value))),
+            Err(err) => {
+                // stop iterating after a malformed field
+                self.pos = self.buf.len();
+                Some((0, Err(err)))
+            }
+        }
+    }
+}
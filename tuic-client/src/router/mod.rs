@@ -0,0 +1,346 @@
+use self::{bypass::Bypass, cache::RouteCache};
+use crate::{config::Router as RouterConfig, error::Error};
+use crossbeam_utils::atomic::AtomicCell;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{task::JoinHandle, time};
+use tuic::Address;
+
+mod bypass;
+mod cache;
+mod domain_set;
+mod geosite;
+mod process;
+mod rule;
+
+pub use self::{
+    domain_set::DomainSet,
+    process::Process,
+    rule::{Matcher, Outbound, Resolve, Rule},
+};
+
+static ROUTER: RwLock<Option<Arc<Router>>> = RwLock::new(None);
+static RELOAD_TASK: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static MODE: AtomicCell<Mode> = AtomicCell::new(Mode::Rule);
+
+pub struct Router {
+    rules: Vec<Arc<Rule>>,
+    default_outbound: Outbound,
+    geosite: Option<PathBuf>,
+    domain_lists: HashMap<String, PathBuf>,
+    rule_sets: RwLock<HashMap<String, DomainSet>>,
+    process_rules: bool,
+    cache: Mutex<RouteCache>,
+    bypass: Option<Bypass>,
+    bypass_rule: Arc<Rule>,
+    sniff: bool,
+}
+
+impl Router {
+    /// Sets up the router, replacing the current one when reloading the config
+    ///
+    /// Connections already routed are not affected.
+    pub fn set_config(cfg: RouterConfig) -> Result<(), Error> {
+        let process_rules = cfg.rules.iter().any(|rule| rule.matcher.is_process());
+
+        let router = Self {
+            rules: cfg.rules.into_iter().map(Arc::new).collect(),
+            default_outbound: cfg.default_outbound,
+            geosite: cfg.geosite,
+            domain_lists: cfg.domain_lists,
+            rule_sets: RwLock::new(HashMap::new()),
+            process_rules,
+            cache: Mutex::new(RouteCache::new(cfg.cache_size, cfg.cache_ttl)),
+            bypass: cfg.bypass.map(Bypass::new),
+            bypass_rule: Arc::new(Rule {
+                matcher: Matcher::Bypass,
+                outbound: Outbound::Direct,
+                udp_relay_mode: None,
+                hint: None,
+                priority: None,
+                resolve: None,
+            }),
+            sniff: cfg.sniff,
+        };
+
+        *router.rule_sets.write() =
+            router.load_rule_sets(router.rules.iter().map(|rule| &rule.matcher))?;
+
+        if !process::SUPPORTED && process_rules {
+            log::warn!("[router] process rules are not supported on this platform and never match");
+        }
+
+        *ROUTER.write() = Some(Arc::new(router));
+
+        let reload_task = cfg
+            .reload_interval
+            .map(|reload_interval| tokio::spawn(Self::reload(reload_interval)));
+
+        if let Some(task) = std::mem::replace(&mut *RELOAD_TASK.lock(), reload_task) {
+            task.abort();
+        }
+
+        Ok(())
+    }
+
+    /// Stops reloading the rule sets
+    pub fn stop() {
+        if let Some(task) = RELOAD_TASK.lock().take() {
+            task.abort();
+        }
+    }
+
+    fn get() -> Arc<Self> {
+        ROUTER.read().clone().unwrap()
+    }
+
+    pub fn mode() -> Mode {
+        MODE.load()
+    }
+
+    /// Sets the routing mode, which is kept when reloading the config
+    pub fn set_mode(mode: Mode) {
+        MODE.store(mode);
+
+        if let Some(router) = ROUTER.read().as_ref() {
+            router.cache.lock().clear();
+        }
+
+        log::info!("[router] routing mode set to {mode}");
+    }
+
+    /// Returns the first rule matching the target address, or the local process the connection is from
+    ///
+    /// Targets in the bypass list are matched first, to the direct outbound, in modes `rule` and `global`. Rules are only matched in mode `rule`. Verdicts not depending on the process are cached.
+    pub fn matched_rule(addr: &Address, process: Option<&Process>) -> Option<Arc<Rule>> {
+        let mode = Self::mode();
+        let router = Self::get();
+
+        if mode == Mode::Direct || (mode == Mode::Global && router.bypass.is_none()) {
+            return None;
+        }
+
+        // with process rules, the verdict for a connection from a known process may differ from others to the same target
+        let cacheable =
+            router.cache.lock().is_enabled() && !(router.process_rules && process.is_some());
+
+        if !cacheable {
+            return router.route(addr, process, mode);
+        }
+
+        if let Some(rule) = router.cache.lock().get(addr) {
+            return rule;
+        }
+
+        let rule = router.route(addr, process, mode);
+        router.cache.lock().insert(addr.clone(), rule.clone());
+        rule
+    }
+
+    fn route(&self, addr: &Address, process: Option<&Process>, mode: Mode) -> Option<Arc<Rule>> {
+        if self
+            .bypass
+            .as_ref()
+            .map_or(false, |bypass| bypass.matches(addr))
+        {
+            log::debug!("[router] {addr} bypassed");
+            return Some(self.bypass_rule.clone());
+        }
+
+        match mode {
+            Mode::Rule => self.match_rules(addr, process),
+            Mode::Global | Mode::Direct => None,
+        }
+    }
+
+    fn match_rules(&self, addr: &Address, process: Option<&Process>) -> Option<Arc<Rule>> {
+        let domain = match addr {
+            Address::DomainAddress(domain, _) => Some(domain_set::normalize(domain)),
+            _ => None,
+        };
+
+        let ip = match addr {
+            Address::SocketAddress(addr) => Some(addr.ip()),
+            _ => None,
+        };
+
+        let rule_sets = self.rule_sets.read();
+
+        self.rules
+            .iter()
+            .find(|rule| match (&rule.matcher, &domain) {
+                (Matcher::Process(name), _) => process.map_or(false, |proc| proc.is_named(name)),
+                (Matcher::ProcessPath(path), _) => {
+                    process.map_or(false, |proc| proc.has_path(path))
+                }
+                (Matcher::IpCidr(cidr), _) => ip.map_or(false, |ip| cidr.contains(ip)),
+                (_, None) => false,
+                (matcher, Some(domain)) => matcher.matches_domain(domain, &rule_sets),
+            })
+            .cloned()
+    }
+
+    /// Whether any rule matches by the local process, so that the process needs to be looked up for routing
+    pub fn has_process_rules() -> bool {
+        process::SUPPORTED
+            && Self::mode() == Mode::Rule
+            && Self::get()
+                .rules
+                .iter()
+                .any(|rule| rule.matcher.is_process())
+    }
+
+    /// Whether to sniff the domain of TCP connections targeting IP addresses, so that domain rules apply to them
+    pub fn sniff() -> bool {
+        Self::mode() == Mode::Rule && Self::get().sniff
+    }
+
+    pub fn rules() -> Vec<Arc<Rule>> {
+        Self::get().rules.clone()
+    }
+
+    /// Returns the outbound for targets not matching any rule, which is every target in modes `global` and `direct`
+    pub fn default_outbound() -> Outbound {
+        match Self::mode() {
+            Mode::Rule => Self::get().default_outbound,
+            Mode::Global => Outbound::Proxy,
+            Mode::Direct => Outbound::Direct,
+        }
+    }
+
+    async fn reload(reload_interval: Duration) {
+        loop {
+            time::sleep(reload_interval).await;
+            let router = Self::get();
+
+            match router.load_rule_sets(router.rules.iter().map(|rule| &rule.matcher)) {
+                Ok(rule_sets) => {
+                    *router.rule_sets.write() = rule_sets;
+                    router.cache.lock().clear();
+                    log::info!("[router] rule sets reloaded");
+                }
+                Err(err) => log::warn!("[router] failed reloading rule sets: {err}"),
+            }
+        }
+    }
+
+    /// Loads the rule sets referred to by the matchers of other rules, e.g. the DNS rules, from the geosite file and the domain lists of the router
+    pub fn load_external_rule_sets<'a>(
+        matchers: impl Iterator<Item = &'a Matcher>,
+    ) -> Result<HashMap<String, DomainSet>, Error> {
+        Self::get().load_rule_sets(matchers)
+    }
+
+    fn load_rule_sets<'a>(
+        &self,
+        matchers: impl Iterator<Item = &'a Matcher>,
+    ) -> Result<HashMap<String, DomainSet>, Error> {
+        let matchers = matchers.collect::<Vec<_>>();
+        let mut rule_sets = HashMap::new();
+        let mut geosite_requested = Vec::new();
+
+        for matcher in &matchers {
+            match matcher {
+                Matcher::GeoSite(code, attr) => {
+                    geosite_requested.push((code.clone(), attr.clone()));
+                }
+                Matcher::DomainList(list) => {
+                    let name = matcher.rule_set().unwrap();
+
+                    if rule_sets.contains_key(&name) {
+                        continue;
+                    }
+
+                    let path = self
+                        .domain_lists
+                        .get(list)
+                        .ok_or_else(|| Error::UnknownRuleSet(name.clone()))?;
+                    let set = DomainSet::load_list(path)?;
+                    log_loaded(&name, path, &set);
+                    rule_sets.insert(name, set);
+                }
+                _ => {}
+            }
+        }
+
+        if geosite_requested.is_empty() {
+            return Ok(rule_sets);
+        }
+
+        let Some(path) = &self.geosite else {
+            return Err(Error::UnknownRuleSet(String::from("geosite")));
+        };
+
+        let mut sets = geosite::load(path, &geosite_requested)?;
+
+        for matcher in &matchers {
+            if let Matcher::GeoSite(code, attr) = matcher {
+                let name = matcher.rule_set().unwrap();
+
+                if rule_sets.contains_key(&name) {
+                    continue;
+                }
+
+                let set = sets
+                    .remove(&(code.clone(), attr.clone()))
+                    .ok_or_else(|| Error::UnknownRuleSet(name.clone()))?;
+                log_loaded(&name, path, &set);
+                rule_sets.insert(name, set);
+            }
+        }
+
+        Ok(rule_sets)
+    }
+}
+
+/// The routing mode, as in Clash
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Routing by the rules
+    Rule,
+    /// Relaying everything through the TUIC proxy
+    Global,
+    /// Connecting to every target directly
+    Direct,
+}
+
+impl FromStr for Mode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("rule") {
+            Ok(Self::Rule)
+        } else if s.eq_ignore_ascii_case("global") {
+            Ok(Self::Global)
+        } else if s.eq_ignore_ascii_case("direct") {
+            Ok(Self::Direct)
+        } else {
+            Err("invalid routing mode")
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Rule => write!(f, "rule"),
+            Self::Global => write!(f, "global"),
+            Self::Direct => write!(f, "direct"),
+        }
+    }
+}
+
+fn log_loaded(name: &str, path: &Path, set: &DomainSet) {
+    log::debug!(
+        "[router] loaded rule set {name} from {path} with {len} entries",
+        path = path.display(),
+        len = set.len(),
+    );
+}
@@ -0,0 +1,301 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+/// A local process owning a TCP connection to the inbound
+#[derive(Clone, Debug)]
+pub struct Process {
+    pub pid: u32,
+    pub path: PathBuf,
+}
+
+impl Process {
+    /// Finds the local process owning the TCP connection from `local` to `remote`, as seen by that process
+    ///
+    /// Returns `None` if the connection is not from this host, the owner is not accessible (e.g. a process of another user without privileges), or the platform is not supported.
+    pub async fn lookup(local: SocketAddr, remote: SocketAddr) -> Option<Self> {
+        let (local, remote) = (canonicalize(local), canonicalize(remote));
+
+        tokio::task::spawn_blocking(move || platform::lookup(local, remote))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Returns the file name of the executable
+    pub fn name(&self) -> Option<&str> {
+        self.path.file_name().and_then(|name| name.to_str())
+    }
+
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name().map_or(false, |own| {
+            if cfg!(windows) {
+                own.eq_ignore_ascii_case(name)
+            } else {
+                own == name
+            }
+        })
+    }
+
+    pub fn has_path(&self, path: &Path) -> bool {
+        if cfg!(windows) {
+            self.path
+                .to_string_lossy()
+                .eq_ignore_ascii_case(&path.to_string_lossy())
+        } else {
+            self.path == path
+        }
+    }
+}
+
+/// Whether looking up the owning process is supported on this platform
+pub const SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "android", windows));
+
+/// Maps IPv4-mapped IPv6 addresses to IPv4, as dual-stack sockets are listed under either family
+fn canonicalize(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => SocketAddr::from((ip, addr.port())),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod platform {
+    use super::{canonicalize, Process};
+    use std::{
+        fs,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    };
+
+    /// Finds the socket inode in the procfs TCP tables, then the process holding a file descriptor of it
+    pub fn lookup(local: SocketAddr, remote: SocketAddr) -> Option<Process> {
+        let inode = ["/proc/net/tcp", "/proc/net/tcp6"]
+            .into_iter()
+            .find_map(|table| find_inode(table, local, remote))?;
+
+        let pid = find_pid(inode)?;
+        let path = fs::read_link(format!("/proc/{pid}/exe")).ok()?;
+
+        Some(Process { pid, path })
+    }
+
+    fn find_inode(table: &str, local: SocketAddr, remote: SocketAddr) -> Option<u64> {
+        let table = fs::read_to_string(table).ok()?;
+
+        // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode ...
+        table.lines().skip(1).find_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+
+            if fields.len() < 10
+                || parse_addr(fields[1]) != Some(local)
+                || parse_addr(fields[2]) != Some(remote)
+            {
+                return None;
+            }
+
+            fields[9].parse().ok().filter(|inode| *inode != 0)
+        })
+    }
+
+    /// Parses an address in the procfs TCP tables, e.g. `0100007F:1F90`
+    ///
+    /// The address is printed as 32-bit words in the host byte order, while the port is in the usual order.
+    fn parse_addr(s: &str) -> Option<SocketAddr> {
+        let (ip, port) = s.split_once(':')?;
+        let port = u16::from_str_radix(port, 16).ok()?;
+
+        let octets = (0..ip.len())
+            .step_by(8)
+            .map(|idx| {
+                ip.get(idx..idx + 8)
+                    .and_then(|word| u32::from_str_radix(word, 16).ok())
+                    .map(u32::to_ne_bytes)
+            })
+            .collect::<Option<Vec<_>>>()?
+            .concat();
+
+        let ip = match octets.len() {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(octets).ok()?)),
+            16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(octets).ok()?)),
+            _ => return None,
+        };
+
+        Some(canonicalize(SocketAddr::new(ip, port)))
+    }
+
+    fn find_pid(inode: u64) -> Option<u32> {
+        let target = format!("socket:[{inode}]");
+
+        fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+
+            fs::read_dir(entry.path().join("fd"))
+                .ok()?
+                .flatten()
+                .any(|fd| {
+                    fs::read_link(fd.path())
+                        .map_or(false, |link| link.as_os_str() == target.as_str())
+                })
+                .then_some(pid)
+        })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{canonicalize, Process};
+    use std::{
+        ffi::{c_void, OsString},
+        mem,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        os::windows::ffi::OsStringExt,
+        path::PathBuf,
+        ptr,
+    };
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, ERROR_INSUFFICIENT_BUFFER, NO_ERROR},
+        NetworkManagement::IpHelper::{
+            GetExtendedTcpTable, MIB_TCP6ROW_OWNER_PID, MIB_TCP6TABLE_OWNER_PID,
+            MIB_TCPROW_OWNER_PID, MIB_TCPTABLE_OWNER_PID, TCP_TABLE_OWNER_PID_ALL,
+        },
+        Networking::WinSock::{AF_INET, AF_INET6},
+        System::Threading::{
+            OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+    };
+
+    /// Finds the owning process ID in the extended TCP tables, then the image path of the process
+    pub fn lookup(local: SocketAddr, remote: SocketAddr) -> Option<Process> {
+        let pid = find_pid_v4(local, remote).or_else(|| find_pid_v6(local, remote))?;
+        let path = image_path(pid)?;
+
+        Some(Process { pid, path })
+    }
+
+    fn tcp_table(family: u16) -> Option<Vec<u8>> {
+        let mut size = 0;
+        let mut buf = Vec::new();
+
+        loop {
+            let res = unsafe {
+                GetExtendedTcpTable(
+                    buf.as_mut_ptr() as *mut c_void,
+                    &mut size,
+                    0,
+                    family as u32,
+                    TCP_TABLE_OWNER_PID_ALL,
+                    0,
+                )
+            };
+
+            match res {
+                NO_ERROR => return Some(buf),
+                // the table may grow between the calls
+                ERROR_INSUFFICIENT_BUFFER => buf.resize(size as usize, 0),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Reads the rows of a table `T`, consisting of a `u32` entry count followed by rows of type `R`
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be filled by `GetExtendedTcpTable` with a table of type `T`.
+    unsafe fn rows<T, R: Copy>(buf: &[u8]) -> Vec<R> {
+        if buf.len() < mem::size_of::<T>() {
+            return Vec::new();
+        }
+
+        // the table is declared with a single row
+        let offset = mem::size_of::<T>() - mem::size_of::<R>();
+        let len = ptr::read_unaligned(buf.as_ptr() as *const u32) as usize;
+        let rows = buf.as_ptr().add(offset) as *const R;
+
+        (0..len)
+            .map(|idx| ptr::read_unaligned(rows.add(idx)))
+            .collect()
+    }
+
+    fn port(port: u32) -> u16 {
+        u16::from_be(port as u16)
+    }
+
+    fn find_pid_v4(local: SocketAddr, remote: SocketAddr) -> Option<u32> {
+        let buf = tcp_table(AF_INET)?;
+        let rows = unsafe { rows::<MIB_TCPTABLE_OWNER_PID, MIB_TCPROW_OWNER_PID>(&buf) };
+
+        rows.into_iter()
+            .find(|row| {
+                let row_local = SocketAddr::from((
+                    Ipv4Addr::from(row.dwLocalAddr.to_ne_bytes()),
+                    port(row.dwLocalPort),
+                ));
+                let row_remote = SocketAddr::from((
+                    Ipv4Addr::from(row.dwRemoteAddr.to_ne_bytes()),
+                    port(row.dwRemotePort),
+                ));
+
+                row_local == local && row_remote == remote
+            })
+            .map(|row| row.dwOwningPid)
+    }
+
+    fn find_pid_v6(local: SocketAddr, remote: SocketAddr) -> Option<u32> {
+        let buf = tcp_table(AF_INET6)?;
+        let rows = unsafe { rows::<MIB_TCP6TABLE_OWNER_PID, MIB_TCP6ROW_OWNER_PID>(&buf) };
+
+        rows.into_iter()
+            .find(|row| {
+                let row_local = canonicalize(SocketAddr::from((
+                    Ipv6Addr::from(row.ucLocalAddr),
+                    port(row.dwLocalPort),
+                )));
+                let row_remote = canonicalize(SocketAddr::from((
+                    Ipv6Addr::from(row.ucRemoteAddr),
+                    port(row.dwRemotePort),
+                )));
+
+                row_local == local && row_remote == remote
+            })
+            .map(|row| row.dwOwningPid)
+    }
+
+    fn image_path(pid: u32) -> Option<PathBuf> {
+        let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+
+        if handle == 0 {
+            return None;
+        }
+
+        let mut buf = vec![0u16; 1024];
+        let mut len = buf.len() as u32;
+
+        let res = unsafe {
+            QueryFullProcessImageNameW(handle, PROCESS_NAME_WIN32, buf.as_mut_ptr(), &mut len)
+        };
+
+        unsafe { CloseHandle(handle) };
+
+        if res == 0 {
+            return None;
+        }
+
+        Some(PathBuf::from(OsString::from_wide(&buf[..len as usize])))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+mod platform {
+    use super::Process;
+    use std::net::SocketAddr;
+
+    pub fn lookup(_local: SocketAddr, _remote: SocketAddr) -> Option<Process> {
+        None
+    }
+}
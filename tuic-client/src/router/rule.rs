@@ -0,0 +1,258 @@
+use super::DomainSet;
+use crate::utils::{IpCidr, UdpRelayMode};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::PathBuf,
+    str::FromStr,
+};
+use tuic::CongestionHint;
+
+/// A routing rule in the form of `MATCHER -> OUTBOUND[:OPTION]...`
+///
+/// Options are only allowed for the `proxy` outbound. An option can be a UDP relay mode, overriding the one of the relay server for matched UDP packets, a congestion hint (`interactive` or `bulk`) sent with matched TCP connections, `priority=N` for sending the data of matched TCP connections ahead of (or behind) other streams on the relay connection, or `resolve=local` / `resolve=remote` for choosing where domain targets are resolved.
+pub struct Rule {
+    pub matcher: Matcher,
+    pub outbound: Outbound,
+    pub udp_relay_mode: Option<UdpRelayMode>,
+    pub hint: Option<CongestionHint>,
+    pub priority: Option<i32>,
+    pub resolve: Option<Resolve>,
+}
+
+impl FromStr for Rule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (matcher, outbound) = s
+            .rsplit_once("->")
+            .ok_or("invalid rule, expecting `MATCHER -> OUTBOUND`")?;
+
+        let mut options = outbound.trim().split(':');
+        let outbound = options.next().unwrap_or_default().parse()?;
+
+        let mut udp_relay_mode = None;
+        let mut hint = None;
+        let mut priority = None;
+        let mut resolve = None;
+
+        for option in options {
+            if outbound != Outbound::Proxy {
+                return Err("rule options can only be set for the proxy outbound");
+            }
+
+            if let Some(p) = option.strip_prefix("priority=") {
+                let p = p.parse().map_err(|_| "invalid priority in rule")?;
+
+                if priority.replace(p).is_some() {
+                    return Err("duplicated priority in rule");
+                }
+            } else if let Some(r) = option.strip_prefix("resolve=") {
+                if resolve.replace(r.parse()?).is_some() {
+                    return Err("duplicated resolve in rule");
+                }
+            } else if let Some(h) = parse_hint(option) {
+                if hint.replace(h).is_some() {
+                    return Err("duplicated congestion hint in rule");
+                }
+            } else {
+                let mode = option.parse().map_err(|_| {
+                    "invalid rule option, expecting a UDP relay mode or a congestion hint"
+                })?;
+
+                if udp_relay_mode.replace(mode).is_some() {
+                    return Err("duplicated UDP relay mode in rule");
+                }
+            }
+        }
+
+        Ok(Self {
+            matcher: matcher.trim().parse()?,
+            outbound,
+            udp_relay_mode,
+            hint,
+            priority,
+            resolve,
+        })
+    }
+}
+
+fn parse_hint(s: &str) -> Option<CongestionHint> {
+    if s.eq_ignore_ascii_case("interactive") {
+        Some(CongestionHint::Interactive)
+    } else if s.eq_ignore_ascii_case("bulk") {
+        Some(CongestionHint::Bulk)
+    } else {
+        None
+    }
+}
+
+pub enum Matcher {
+    /// `geosite:CODE[@ATTR]` - a domain list from the geosite file
+    GeoSite(String, Option<String>),
+    /// `list:NAME` - a plain-text domain list from `domain_lists`
+    DomainList(String),
+    /// `domain:DOMAIN` - the domain and all its subdomains
+    Domain(String),
+    /// `full:DOMAIN` - the exact domain
+    Full(String),
+    /// `keyword:KEYWORD` - domains containing the keyword
+    Keyword(String),
+    /// `regexp:REGEX` - domains matching the regular expression
+    Regexp(Regex),
+    /// `process:NAME` - connections from a local process with the executable name
+    Process(String),
+    /// `process-path:PATH` - connections from a local process with the executable path
+    ProcessPath(PathBuf),
+    /// `ip-cidr:CIDR` - targets given as IP addresses in the network
+    IpCidr(IpCidr),
+    /// Targets in the bypass list of the router, not available in rules
+    Bypass,
+}
+
+impl Matcher {
+    /// Returns the name of the rule set this matcher refers to, if any
+    pub fn rule_set(&self) -> Option<String> {
+        match self {
+            Self::GeoSite(code, None) => Some(format!("geosite:{code}")),
+            Self::GeoSite(code, Some(attr)) => Some(format!("geosite:{code}@{attr}")),
+            Self::DomainList(name) => Some(format!("list:{name}")),
+            _ => None,
+        }
+    }
+
+    /// Whether this matcher matches by the local process instead of the target domain
+    pub fn is_process(&self) -> bool {
+        matches!(self, Self::Process(_) | Self::ProcessPath(_))
+    }
+
+    /// Whether this matcher matches by the target IP address instead of the target domain
+    pub fn is_ip(&self) -> bool {
+        matches!(self, Self::IpCidr(_))
+    }
+
+    /// Whether the normalized domain matches, looking up the rule set this matcher refers to in `rule_sets`
+    ///
+    /// Process and IP matchers never match a domain.
+    pub fn matches_domain(&self, domain: &str, rule_sets: &HashMap<String, DomainSet>) -> bool {
+        match self {
+            Self::GeoSite(_, _) | Self::DomainList(_) => self
+                .rule_set()
+                .and_then(|name| rule_sets.get(&name))
+                .map_or(false, |set| set.contains(domain)),
+            Self::Domain(suffix) => domain == suffix || domain.ends_with(&format!(".{suffix}")),
+            Self::Full(full) => domain == full,
+            Self::Keyword(keyword) => domain.contains(keyword.as_str()),
+            Self::Regexp(regex) => regex.is_match(domain),
+            Self::Process(_) | Self::ProcessPath(_) | Self::IpCidr(_) | Self::Bypass => false,
+        }
+    }
+}
+
+impl Display for Matcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::GeoSite(_, _) | Self::DomainList(_) => write!(f, "{}", self.rule_set().unwrap()),
+            Self::Domain(domain) => write!(f, "domain:{domain}"),
+            Self::Full(domain) => write!(f, "full:{domain}"),
+            Self::Keyword(keyword) => write!(f, "keyword:{keyword}"),
+            Self::Regexp(regex) => write!(f, "regexp:{regex}"),
+            Self::Process(name) => write!(f, "process:{name}"),
+            Self::ProcessPath(path) => write!(f, "process-path:{}", path.display()),
+            Self::IpCidr(cidr) => write!(f, "ip-cidr:{cidr}"),
+            Self::Bypass => write!(f, "bypass"),
+        }
+    }
+}
+
+impl FromStr for Matcher {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or("invalid rule matcher, expecting `TYPE:VALUE`")?;
+
+        if value.is_empty() {
+            return Err("empty rule matcher value");
+        }
+
+        match kind {
+            "geosite" => {
+                let (code, attr) = match value.split_once('@') {
+                    Some((code, attr)) => (code, Some(attr.to_ascii_lowercase())),
+                    None => (value, None),
+                };
+                Ok(Self::GeoSite(code.to_ascii_lowercase(), attr))
+            }
+            "list" => Ok(Self::DomainList(value.to_owned())),
+            "domain" => Ok(Self::Domain(value.to_ascii_lowercase())),
+            "full" => Ok(Self::Full(value.to_ascii_lowercase())),
+            "keyword" => Ok(Self::Keyword(value.to_ascii_lowercase())),
+            "regexp" => Regex::new(value)
+                .map(Self::Regexp)
+                .map_err(|_| "invalid regular expression in rule matcher"),
+            "process" => Ok(Self::Process(value.to_owned())),
+            "process-path" => Ok(Self::ProcessPath(PathBuf::from(value))),
+            "ip-cidr" => value.parse().map(Self::IpCidr),
+            _ => Err("invalid rule matcher type"),
+        }
+    }
+}
+
+/// Where the domain targets of a rule are resolved
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resolve {
+    /// By the client, sending the resolved IP address to the server
+    Local,
+    /// By the server, sending the domain as is
+    Remote,
+}
+
+impl FromStr for Resolve {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            Ok(Self::Local)
+        } else if s.eq_ignore_ascii_case("remote") {
+            Ok(Self::Remote)
+        } else {
+            Err("invalid resolve in rule, expecting `local` or `remote`")
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outbound {
+    Proxy,
+    Direct,
+    Block,
+}
+
+impl FromStr for Outbound {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("proxy") {
+            Ok(Self::Proxy)
+        } else if s.eq_ignore_ascii_case("direct") {
+            Ok(Self::Direct)
+        } else if s.eq_ignore_ascii_case("block") || s.eq_ignore_ascii_case("reject") {
+            Ok(Self::Block)
+        } else {
+            Err("invalid outbound")
+        }
+    }
+}
+
+impl Display for Outbound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Proxy => write!(f, "proxy"),
+            Self::Direct => write!(f, "direct"),
+            Self::Block => write!(f, "block"),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    config::Sip003,
+    connection::{Connection as TuicConnection, ERROR_CODE},
+    error::Error,
+};
+use once_cell::sync::OnceCell;
+use std::net::SocketAddr;
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address;
+
+static SERVER: OnceCell<Server> = OnceCell::new();
+
+/// A TCP tunnel used when running as a Shadowsocks SIP003 plugin
+///
+/// Every TCP connection from the Shadowsocks client is relayed to the target address through the TUIC proxy.
+pub struct Server {
+    listener: TcpListener,
+    target: Address,
+}
+
+impl Server {
+    pub fn set_config(cfg: Sip003) -> Result<(), Error> {
+        let listener = std::net::TcpListener::bind(cfg.local)
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                TcpListener::from_std(socket)
+            })
+            .map_err(|err| Error::Socket("failed to bind SIP003 plugin socket", err))?;
+
+        SERVER
+            .set(Self {
+                listener,
+                target: cfg.target,
+            })
+            .map_err(|_| "failed initializing SIP003 plugin")
+            .unwrap();
+
+        Ok(())
+    }
+
+    pub fn is_enabled() -> bool {
+        SERVER.get().is_some()
+    }
+
+    pub async fn start() {
+        let server = SERVER.get().unwrap();
+
+        log::warn!(
+            "[sip003] plugin started, listening on {}, relaying to {}",
+            server.listener.local_addr().unwrap(),
+            server.target,
+        );
+
+        loop {
+            match server.listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    tokio::spawn(server.handle(stream, peer_addr));
+                }
+                Err(err) => log::warn!("[sip003] failed to accept TCP connection: {err}"),
+            }
+        }
+    }
+
+    async fn handle(&'static self, mut stream: TcpStream, peer_addr: SocketAddr) {
+        log::debug!("[sip003] [{peer_addr}] connection established");
+
+        let relay = match TuicConnection::get_for_connect(&self.target).await {
+            Ok(conn) => conn.connect(self.target.clone(), None).await,
+            Err(err) => Err(err),
+        };
+
+        match relay {
+            Ok(relay) => {
+                let mut relay = relay.compat();
+
+                if let Err(err) = io::copy_bidirectional(&mut stream, &mut relay).await {
+                    let _ = stream.shutdown().await;
+                    let _ = relay.get_mut().reset(ERROR_CODE);
+                    log::warn!("[sip003] [{peer_addr}] relaying error: {err}");
+                }
+            }
+            Err(err) => {
+                let _ = stream.shutdown().await;
+                log::warn!("[sip003] [{peer_addr}] unable to relay: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use socks5_proto::{
+    handshake::password::{Request as PasswordRequest, Response as PasswordResponse},
+    HandshakeMethod,
+};
+use socks5_server::Auth;
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+};
+use tokio::net::TcpStream;
+
+/// RFC 1929 username / password authentication, accepting any of the configured credentials
+///
+/// The same credentials are checked against the `Proxy-Authorization` header of HTTP proxy requests. No authentication is required if there are no credentials. Credentials can be replaced when reloading the config, which applies to new connections.
+pub struct Credentials(RwLock<HashMap<Vec<u8>, Vec<u8>>>);
+
+impl Credentials {
+    pub fn new(users: HashMap<Vec<u8>, Vec<u8>>) -> Self {
+        Self(RwLock::new(users))
+    }
+
+    pub fn set(&self, users: HashMap<Vec<u8>, Vec<u8>>) {
+        *self.0.write() = users;
+    }
+
+    /// Checks the value of a `Proxy-Authorization` header with the `Basic` scheme
+    pub fn verify_basic(&self, header: Option<&[u8]>) -> bool {
+        let users = self.0.read();
+
+        if users.is_empty() {
+            return true;
+        }
+
+        let Some(header) = header.and_then(|header| std::str::from_utf8(header).ok()) else {
+            return false;
+        };
+
+        let Some((scheme, encoded)) = header.trim().split_once(' ') else {
+            return false;
+        };
+
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return false;
+        }
+
+        let Some(decoded) = decode_base64(encoded.trim()) else {
+            return false;
+        };
+
+        let Some(idx) = decoded.iter().position(|&b| b == b':') else {
+            return false;
+        };
+
+        users.get(&decoded[..idx]).map(Vec::as_slice) == Some(&decoded[idx + 1..])
+    }
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let s = s.trim_end_matches('=').as_bytes();
+    let mut buf = Vec::with_capacity(s.len() * 3 / 4);
+
+    for chunk in s.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+
+        let mut acc = 0;
+
+        for (i, &c) in chunk.iter().enumerate() {
+            acc |= value(c)? << (18 - i * 6);
+        }
+
+        buf.extend_from_slice(&acc.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Some(buf)
+}
+
+#[async_trait]
+impl Auth for Credentials {
+    fn as_handshake_method(&self) -> HandshakeMethod {
+        if self.0.read().is_empty() {
+            HandshakeMethod::None
+        } else {
+            HandshakeMethod::Password
+        }
+    }
+
+    async fn execute(&self, stream: &mut TcpStream) -> IoResult<()> {
+        if self.0.read().is_empty() {
+            return Ok(());
+        }
+
+        let req = PasswordRequest::read_from(stream).await?;
+        let is_valid = self.0.read().get(&req.username) == Some(&req.password);
+
+        PasswordResponse::new(is_valid).write_to(stream).await?;
+
+        if is_valid {
+            Ok(())
+        } else {
+            Err(IoError::new(
+                ErrorKind::InvalidData,
+                "socks5 username / password authentication failed",
+            ))
+        }
+    }
+}
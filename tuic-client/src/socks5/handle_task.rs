@@ -1,25 +1,65 @@
-use super::{udp_session::UdpSession, Server, UDP_SESSIONS};
-use crate::connection::{Connection as TuicConnection, ERROR_CODE};
+use super::{
+    handshake::{self, Incoming},
+    udp_session::UdpSession,
+    Server, BINDS, UDP_SESSIONS,
+};
+use crate::{
+    connection::{self, Connection as TuicConnection, ERROR_CODE},
+    controller::{
+        self,
+        tracker::{Counted, TrackedGuard},
+    },
+    dns::Server as DnsServer,
+    protect,
+    router::{Outbound, Process, Router},
+};
 use socks5_proto::{Address, Reply};
-use socks5_server::{
-    connection::{associate, bind, connect},
-    Associate, Bind, Connect,
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    sync::oneshot,
+    time,
 };
-use tokio::io::{self, AsyncWriteExt};
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tuic::Address as TuicAddress;
+use tuic::{Address as TuicAddress, AddressError};
+
+/// How long a `BIND` command waits for the connection from the target
+const BIND_ACCEPT_TIMEOUT: Duration = Duration::from_secs(120);
 
 impl Server {
     pub async fn handle_associate(
-        assoc: Associate<associate::NeedReply>,
+        assoc: Incoming,
         assoc_id: u16,
         dual_stack: Option<bool>,
         max_pkt_size: usize,
     ) {
         let peer_addr = assoc.peer_addr().unwrap();
-        let local_ip = assoc.local_addr().unwrap().ip();
+        let local_addr = assoc.local_addr().unwrap();
+        let local_ip = local_addr.ip();
+
+        // the process sending UDP packets is assumed to be the one owning the associate connection
+        let process = Arc::new(Self::lookup_process(peer_addr, local_addr).await);
+
+        let guard = TrackedGuard::new(
+            "udp",
+            peer_addr,
+            TuicAddress::None,
+            String::from(controller::outbound_name(Outbound::Proxy)),
+            String::new(),
+        );
 
-        match UdpSession::new(assoc_id, peer_addr, local_ip, dual_stack, max_pkt_size) {
+        match UdpSession::new(
+            assoc_id,
+            peer_addr,
+            local_ip,
+            dual_stack,
+            max_pkt_size,
+            guard.tracked().clone(),
+        ) {
             Ok(session) => {
                 let local_addr = session.local_addr().unwrap();
                 log::debug!(
@@ -43,6 +83,8 @@ impl Server {
                     .lock()
                     .insert(assoc_id, session.clone());
 
+                let tracked = guard.tracked().clone();
+
                 let handle_local_incoming_pkt = async move {
                     loop {
                         let (pkt, target_addr) = match session.recv().await {
@@ -53,17 +95,44 @@ impl Server {
                             }
                         };
 
+                        let session = session.clone();
+                        let tracked = tracked.clone();
+                        let process = process.clone();
+
                         let forward = async move {
-                            let target_addr = match target_addr {
-                                Address::DomainAddress(domain, port) => {
-                                    TuicAddress::DomainAddress(domain, port)
-                                }
-                                Address::SocketAddress(addr) => TuicAddress::SocketAddress(addr),
-                            };
+                            let target_addr = Self::target_addr(target_addr)?;
+                            let target_addr = DnsServer::restore_fake_ip(target_addr);
+
+                            let rule =
+                                Router::matched_rule(&target_addr, process.as_ref().as_ref());
+                            let outbound = rule
+                                .as_ref()
+                                .map_or(Router::default_outbound(), |rule| rule.outbound);
+
+                            match outbound {
+                                Outbound::Proxy => {
+                                    let udp_relay_mode =
+                                        rule.as_ref().and_then(|rule| rule.udp_relay_mode);
+                                    let target_addr = TuicConnection::resolve_target(
+                                        target_addr,
+                                        rule.as_deref(),
+                                    )
+                                    .await?;
 
-                            match TuicConnection::get().await {
-                                Ok(conn) => conn.packet(pkt, target_addr, assoc_id).await,
-                                Err(err) => Err(err),
+                                    match TuicConnection::get_for_packet(assoc_id).await {
+                                        Ok(conn) => {
+                                            tracked.set_server(conn.server());
+                                            conn.packet(pkt, target_addr, assoc_id, udp_relay_mode)
+                                                .await
+                                        }
+                                        Err(err) => Err(err),
+                                    }
+                                }
+                                Outbound::Direct => session.send_direct(pkt, target_addr).await,
+                                Outbound::Block => {
+                                    log::debug!("[socks5] [{peer_addr}] [associate] [{assoc_id:#06x}] [{target_addr}] blocked by router");
+                                    Ok(())
+                                }
                             }
                         };
 
@@ -79,7 +148,11 @@ impl Server {
                 };
 
                 match tokio::select! {
-                    res = assoc.wait_until_closed() => res,
+                    res = handshake::wait_until_closed(&mut assoc) => res,
+                    _ = guard.tracked().closed() => {
+                        log::info!("[socks5] [{peer_addr}] [associate] [{assoc_id:#06x}] closed by controller");
+                        Ok(())
+                    }
                     _ = handle_local_incoming_pkt => unreachable!(),
                 } {
                     Ok(()) => {}
@@ -97,10 +170,12 @@ impl Server {
                     .unwrap()
                     .lock()
                     .remove(&assoc_id)
-                    .unwrap();
+                    .unwrap()
+                    .close();
 
-                let res = match TuicConnection::get().await {
-                    Ok(conn) => conn.dissociate(assoc_id).await,
+                let res = match TuicConnection::get_for_dissociate(assoc_id).await {
+                    Ok(Some(conn)) => conn.dissociate(assoc_id).await,
+                    Ok(None) => Ok(()),
                     Err(err) => Err(err),
                 };
 
@@ -127,30 +202,310 @@ impl Server {
         }
     }
 
-    pub async fn handle_bind(bind: Bind<bind::NeedFirstReply>) {
+    /// Converts the target requested by the client, canonicalizing its domain
+    fn target_addr(addr: Address) -> Result<TuicAddress, AddressError> {
+        match addr {
+            Address::DomainAddress(domain, port) => TuicAddress::domain(&domain, port),
+            Address::SocketAddress(addr) => Ok(TuicAddress::SocketAddress(addr)),
+        }
+    }
+
+    /// Looks up the local process the connection is from, if required by the routing rules
+    pub async fn lookup_process(peer_addr: SocketAddr, local_addr: SocketAddr) -> Option<Process> {
+        if !Router::has_process_rules() {
+            return None;
+        }
+
+        let process = Process::lookup(peer_addr, local_addr).await;
+
+        match &process {
+            Some(process) => log::debug!(
+                "[socks5] [{peer_addr}] connection from {path} ({pid})",
+                path = process.path.display(),
+                pid = process.pid,
+            ),
+            None => log::debug!("[socks5] [{peer_addr}] owning process not found"),
+        }
+
+        process
+    }
+
+    /// Relays the `BIND` command as a `Bind` command, with the relay server listening for a single connection from the target
+    ///
+    /// The first reply carries the address the relay server listens on, with an unspecified IP replaced by the one the relay server is reached at, and the second one the address of the accepted connection. The target only selects the routing rule and the relay server, and the connection accepted is not checked against it. As listening on the client is of no use to the peers of the target, the command is refused unless routed through the proxy.
+    pub async fn handle_bind(mut bind: Incoming, addr: Address) {
         let peer_addr = bind.peer_addr().unwrap();
-        log::warn!("[socks5] [{peer_addr}] [bind] command not supported");
 
-        match bind
-            .reply(Reply::CommandNotSupported, Address::unspecified())
+        let target_addr = match Self::target_addr(addr) {
+            Ok(addr) => DnsServer::restore_fake_ip(addr),
+            Err(err) => {
+                log::warn!("[socks5] [{peer_addr}] [bind] invalid target: {err}");
+
+                match bind
+                    .reply(Reply::GeneralFailure, Address::unspecified())
+                    .await
+                {
+                    Ok(mut bind) => {
+                        let _ = bind.shutdown().await;
+                    }
+                    Err(err) => {
+                        log::warn!("[socks5] [{peer_addr}] [bind] command reply error: {err}")
+                    }
+                }
+
+                return;
+            }
+        };
+
+        let process = Self::lookup_process(peer_addr, bind.local_addr().unwrap()).await;
+        let rule = Router::matched_rule(&target_addr, process.as_ref());
+        let outbound = rule
+            .as_ref()
+            .map_or(Router::default_outbound(), |rule| rule.outbound);
+
+        if outbound != Outbound::Proxy {
+            log::info!(
+                "[socks5] [{peer_addr}] [bind] [{target_addr}] not routed through the proxy"
+            );
+
+            match bind
+                .reply(Reply::ConnectionNotAllowed, Address::unspecified())
+                .await
+            {
+                Ok(mut bind) => {
+                    let _ = bind.shutdown().await;
+                }
+                Err(err) => {
+                    log::warn!(
+                        "[socks5] [{peer_addr}] [bind] [{target_addr}] command reply error: {err}"
+                    )
+                }
+            }
+
+            return;
+        }
+
+        let guard = TrackedGuard::new(
+            "tcp",
+            peer_addr,
+            target_addr.clone(),
+            String::from(controller::outbound_name(outbound)),
+            rule.as_ref()
+                .map_or(String::from("Match"), |rule| rule.matcher.to_string()),
+        );
+
+        let listen = match target_addr {
+            TuicAddress::SocketAddress(SocketAddr::V6(_)) => {
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+            }
+            _ => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        };
+
+        // registered before binding, as the connection may arrive right after the server listens
+        let bind_id = connection::next_bind_id();
+        let (tx, rx) = oneshot::channel();
+        BINDS.lock().insert(bind_id, tx);
+
+        let binding = match TuicConnection::get_for_connect(&target_addr).await {
+            Ok(conn) => {
+                guard.tracked().set_server(conn.server());
+                conn.bind(bind_id, TuicAddress::SocketAddress(listen))
+                    .await
+                    .map(|binding| (conn, binding))
+            }
+            Err(err) => Err(err),
+        };
+
+        let (conn, mut binding) = match binding {
+            Ok(res) => res,
+            Err(err) => {
+                BINDS.lock().remove(&bind_id);
+                log::warn!("[socks5] [{peer_addr}] [bind] [{target_addr}] unable to bind on relay server: {err}");
+
+                match bind
+                    .reply(Reply::GeneralFailure, Address::unspecified())
+                    .await
+                {
+                    Ok(mut bind) => {
+                        let _ = bind.shutdown().await;
+                    }
+                    Err(err) => {
+                        log::warn!("[socks5] [{peer_addr}] [bind] [{target_addr}] command reply error: {err}")
+                    }
+                }
+
+                return;
+            }
+        };
+
+        let bound_addr = match binding.addr() {
+            TuicAddress::SocketAddress(addr) if addr.ip().is_unspecified() => {
+                SocketAddr::new(conn.remote_addr().ip(), addr.port())
+            }
+            TuicAddress::SocketAddress(addr) => *addr,
+            _ => SocketAddr::new(conn.remote_addr().ip(), 0),
+        };
+
+        log::debug!("[socks5] [{peer_addr}] [bind] [{target_addr}] [{bind_id:#06x}] listening on {bound_addr}");
+
+        let res = match bind
+            .reply_pending(Reply::Succeeded, Address::SocketAddress(bound_addr))
             .await
         {
-            Ok(mut bind) => {
-                let _ = bind.shutdown().await;
+            Ok(()) => tokio::select! {
+                inbound = rx => inbound.map_err(|_| "binding dropped"),
+                () = binding.closed() => Err("binding closed by relay server"),
+                () = bind.closed() => Err("closed by socks5 client"),
+                () = guard.tracked().closed() => Err("closed by controller"),
+                () = time::sleep(BIND_ACCEPT_TIMEOUT) => Err("timed out waiting for connection"),
+            },
+            Err(err) => {
+                log::warn!(
+                    "[socks5] [{peer_addr}] [bind] [{target_addr}] command reply error: {err}"
+                );
+                Err("first reply failed")
+            }
+        };
+
+        // only a single connection is accepted, so the relay server stops listening either way
+        BINDS.lock().remove(&bind_id);
+        binding.close(ERROR_CODE);
+
+        let inbound = match res {
+            Ok(inbound) => inbound,
+            Err(err) => {
+                log::info!("[socks5] [{peer_addr}] [bind] [{target_addr}] [{bind_id:#06x}] {err}");
+
+                if let Ok(mut bind) = bind
+                    .reply(Reply::GeneralFailure, Address::unspecified())
+                    .await
+                {
+                    let _ = bind.shutdown().await;
+                }
+
+                return;
+            }
+        };
+
+        let from_addr = match inbound.addr() {
+            TuicAddress::SocketAddress(addr) => Address::SocketAddress(*addr),
+            TuicAddress::DomainAddress(domain, port) => {
+                Address::DomainAddress(domain.clone(), *port)
+            }
+            TuicAddress::None => Address::unspecified(),
+        };
+
+        log::info!("[socks5] [{peer_addr}] [bind] [{target_addr}] [{bind_id:#06x}] accepted connection from {from_addr}");
+
+        let mut inbound = inbound.compat();
+
+        match bind.reply(Reply::Succeeded, from_addr).await {
+            Ok(bind) => {
+                let mut bind = Counted::new(bind, guard.tracked().clone());
+
+                let res = tokio::select! {
+                    res = io::copy_bidirectional(&mut bind, &mut inbound) => Some(res),
+                    _ = guard.tracked().closed() => None,
+                };
+
+                match res {
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        let _ = bind.shutdown().await;
+                        let _ = inbound.get_mut().reset(ERROR_CODE);
+                        log::warn!("[socks5] [{peer_addr}] [bind] [{target_addr}] TCP stream relaying error: {err}");
+                    }
+                    None => {
+                        let _ = bind.shutdown().await;
+                        let _ = inbound.get_mut().reset(ERROR_CODE);
+                        log::info!(
+                            "[socks5] [{peer_addr}] [bind] [{target_addr}] closed by controller"
+                        );
+                    }
+                }
+            }
+            Err(err) => {
+                let _ = inbound.get_mut().reset(ERROR_CODE);
+                log::warn!(
+                    "[socks5] [{peer_addr}] [bind] [{target_addr}] command reply error: {err}"
+                );
             }
-            Err(err) => log::warn!("[socks5] [{peer_addr}] [bind] command reply error: {err}"),
         }
     }
 
-    pub async fn handle_connect(conn: Connect<connect::NeedReply>, addr: Address) {
+    pub async fn handle_connect(conn: Incoming, addr: Address) {
         let peer_addr = conn.peer_addr().unwrap();
-        let target_addr = match addr {
-            Address::DomainAddress(domain, port) => TuicAddress::DomainAddress(domain, port),
-            Address::SocketAddress(addr) => TuicAddress::SocketAddress(addr),
+
+        let target_addr = match Self::target_addr(addr) {
+            Ok(addr) => DnsServer::restore_fake_ip(addr),
+            Err(err) => {
+                log::warn!("[socks5] [{peer_addr}] [connect] invalid target: {err}");
+
+                match conn
+                    .reply(Reply::GeneralFailure, Address::unspecified())
+                    .await
+                {
+                    Ok(mut conn) => {
+                        let _ = conn.shutdown().await;
+                    }
+                    Err(err) => {
+                        log::warn!("[socks5] [{peer_addr}] [connect] command reply error: {err}")
+                    }
+                }
+
+                return;
+            }
         };
 
-        let relay = match TuicConnection::get().await {
-            Ok(conn) => conn.connect(target_addr.clone()).await,
+        let process = Self::lookup_process(peer_addr, conn.local_addr().unwrap()).await;
+        let rule = Router::matched_rule(&target_addr, process.as_ref());
+        let outbound = rule
+            .as_ref()
+            .map_or(Router::default_outbound(), |rule| rule.outbound);
+
+        let guard = || {
+            TrackedGuard::new(
+                "tcp",
+                peer_addr,
+                target_addr.clone(),
+                String::from(controller::outbound_name(outbound)),
+                rule.as_ref()
+                    .map_or(String::from("Match"), |rule| rule.matcher.to_string()),
+            )
+        };
+
+        match outbound {
+            Outbound::Proxy => {}
+            Outbound::Direct => {
+                let guard = guard();
+                return Self::handle_connect_direct(conn, target_addr, guard).await;
+            }
+            Outbound::Block => {
+                log::info!("[socks5] [{peer_addr}] [connect] [{target_addr}] blocked by router");
+
+                match conn
+                    .reply(Reply::ConnectionNotAllowed, Address::unspecified())
+                    .await
+                {
+                    Ok(mut conn) => {
+                        let _ = conn.shutdown().await;
+                    }
+                    Err(err) => {
+                        log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] command reply error: {err}")
+                    }
+                }
+
+                return;
+            }
+        }
+
+        let guard = guard();
+
+        let relay = match TuicConnection::get_for_connect(&target_addr).await {
+            Ok(conn) => {
+                guard.tracked().set_server(conn.server());
+                conn.connect(target_addr.clone(), rule.as_deref()).await
+            }
             Err(err) => Err(err),
         };
 
@@ -159,14 +514,28 @@ impl Server {
                 let mut relay = relay.compat();
 
                 match conn.reply(Reply::Succeeded, Address::unspecified()).await {
-                    Ok(mut conn) => match io::copy_bidirectional(&mut conn, &mut relay).await {
-                        Ok(_) => {}
-                        Err(err) => {
-                            let _ = conn.shutdown().await;
-                            let _ = relay.get_mut().reset(ERROR_CODE);
-                            log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] TCP stream relaying error: {err}");
+                    Ok(conn) => {
+                        let mut conn = Counted::new(conn, guard.tracked().clone());
+
+                        let res = tokio::select! {
+                            res = io::copy_bidirectional(&mut conn, &mut relay) => Some(res),
+                            _ = guard.tracked().closed() => None,
+                        };
+
+                        match res {
+                            Some(Ok(_)) => {}
+                            Some(Err(err)) => {
+                                let _ = conn.shutdown().await;
+                                let _ = relay.get_mut().reset(ERROR_CODE);
+                                log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] TCP stream relaying error: {err}");
+                            }
+                            None => {
+                                let _ = conn.shutdown().await;
+                                let _ = relay.get_mut().reset(ERROR_CODE);
+                                log::info!("[socks5] [{peer_addr}] [connect] [{target_addr}] closed by controller");
+                            }
                         }
-                    },
+                    }
                     Err(err) => {
                         let _ = relay.shutdown().await;
                         log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] command reply error: {err}");
@@ -190,4 +559,55 @@ impl Server {
             }
         }
     }
+
+    async fn handle_connect_direct(conn: Incoming, target_addr: TuicAddress, guard: TrackedGuard) {
+        let peer_addr = conn.peer_addr().unwrap();
+        log::info!("[socks5] [{peer_addr}] [connect] [{target_addr}] [direct]");
+
+        match protect::connect_direct(&target_addr).await {
+            Ok(mut stream) => match conn.reply(Reply::Succeeded, Address::unspecified()).await {
+                Ok(conn) => {
+                    let mut conn = Counted::new(conn, guard.tracked().clone());
+
+                    let res = tokio::select! {
+                        res = io::copy_bidirectional(&mut conn, &mut stream) => Some(res),
+                        _ = guard.tracked().closed() => None,
+                    };
+
+                    match res {
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => {
+                            let _ = conn.shutdown().await;
+                            let _ = stream.shutdown().await;
+                            log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] [direct] TCP stream relaying error: {err}");
+                        }
+                        None => {
+                            let _ = conn.shutdown().await;
+                            let _ = stream.shutdown().await;
+                            log::info!("[socks5] [{peer_addr}] [connect] [{target_addr}] [direct] closed by controller");
+                        }
+                    }
+                }
+                Err(err) => {
+                    let _ = stream.shutdown().await;
+                    log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] [direct] command reply error: {err}");
+                }
+            },
+            Err(err) => {
+                log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] [direct] unable to connect: {err}");
+
+                match conn
+                    .reply(Reply::HostUnreachable, Address::unspecified())
+                    .await
+                {
+                    Ok(mut conn) => {
+                        let _ = conn.shutdown().await;
+                    }
+                    Err(err) => {
+                        log::warn!("[socks5] [{peer_addr}] [connect] [{target_addr}] [direct] command reply error: {err}")
+                    }
+                }
+            }
+        }
+    }
 }
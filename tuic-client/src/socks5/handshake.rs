@@ -0,0 +1,374 @@
+//! Handshaking on the local listener, which accepts SOCKS4 / SOCKS4a requests and HTTP proxy requests besides SOCKS5 ones
+//!
+//! The protocol version is taken from the first byte sent by the client, with anything other than a SOCKS version treated as HTTP. Listeners serving only SOCKS or only HTTP close the connections speaking the other protocol. SOCKS4 has no UDP associate command and no password authentication, so SOCKS4 requests are rejected if the listener requires credentials.
+//!
+//! HTTP `CONNECT` requests are handled here like SOCKS connect commands. Other HTTP requests are left to the HTTP proxy, which forwards them one by one over a kept-alive local connection.
+
+use super::auth::Credentials;
+use crate::utils::InboundProtocol;
+use futures_util::future;
+use socks5_proto::{
+    Address, Command as Socks5Command, HandshakeMethod, HandshakeRequest, HandshakeResponse, Reply,
+    Request, Response,
+};
+use socks5_server::Auth;
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::{Ipv4Addr, SocketAddr},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const SOCKS4_VERSION: u8 = 0x04;
+const SOCKS5_VERSION: u8 = 0x05;
+
+const SOCKS4_CMD_CONNECT: u8 = 0x01;
+const SOCKS4_CMD_BIND: u8 = 0x02;
+
+const SOCKS4_REPLY_VERSION: u8 = 0x00;
+const SOCKS4_REPLY_GRANTED: u8 = 0x5a;
+const SOCKS4_REPLY_REJECTED: u8 = 0x5b;
+
+/// The maximum length of the user ID and the domain in SOCKS4 requests
+const SOCKS4_MAX_FIELD_LEN: usize = 255;
+
+/// The maximum length of the head of HTTP `CONNECT` requests
+const HTTP_MAX_HEAD_LEN: usize = 8192;
+
+const HTTP_AUTH_REQUIRED: &[u8] = b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"tuic\"\r\nContent-Length: 0\r\n\r\n";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Version {
+    Socks4,
+    Socks5,
+    Http,
+}
+
+pub enum Command {
+    Connect(Address),
+    Bind(Address),
+    Associate,
+    /// An HTTP request other than `CONNECT`, not read yet
+    Http,
+}
+
+/// A connection on the local listener that finished handshaking and is waiting for the reply to its command
+pub struct Incoming {
+    stream: TcpStream,
+    version: Version,
+}
+
+impl Incoming {
+    /// Performs the handshake in the protocol version the client speaks
+    pub async fn handshake(
+        stream: TcpStream,
+        credentials: &Credentials,
+        protocol: InboundProtocol,
+    ) -> IoResult<(Self, Command)> {
+        let mut ver = [0];
+
+        if stream.peek(&mut ver).await? == 0 {
+            return Err(IoError::from(ErrorKind::UnexpectedEof));
+        }
+
+        let is_socks = matches!(ver[0], SOCKS5_VERSION | SOCKS4_VERSION);
+
+        if is_socks && !protocol.serves_socks() || !is_socks && !protocol.serves_http() {
+            return Err(IoError::new(
+                ErrorKind::Unsupported,
+                format!("the protocol is not served on this {protocol} listener"),
+            ));
+        }
+
+        match ver[0] {
+            SOCKS5_VERSION => Self::handshake_socks5(stream, credentials).await,
+            SOCKS4_VERSION => Self::handshake_socks4(stream, credentials).await,
+            _ => Self::handshake_http(stream, credentials).await,
+        }
+    }
+
+    async fn handshake_socks5(
+        mut stream: TcpStream,
+        credentials: &Credentials,
+    ) -> IoResult<(Self, Command)> {
+        let hs_req = HandshakeRequest::read_from(&mut stream).await?;
+        let method = credentials.as_handshake_method();
+
+        if !hs_req.methods.contains(&method) {
+            HandshakeResponse::new(HandshakeMethod::Unacceptable)
+                .write_to(&mut stream)
+                .await?;
+            let _ = stream.shutdown().await;
+
+            return Err(IoError::new(
+                ErrorKind::Unsupported,
+                "no acceptable socks5 authentication method provided by client",
+            ));
+        }
+
+        HandshakeResponse::new(method).write_to(&mut stream).await?;
+
+        if let Err(err) = credentials.execute(&mut stream).await {
+            let _ = stream.shutdown().await;
+            return Err(err);
+        }
+
+        let req = match Request::read_from(&mut stream).await {
+            Ok(req) => req,
+            Err(err) => {
+                let resp = Response::new(Reply::GeneralFailure, Address::unspecified());
+                resp.write_to(&mut stream).await?;
+                let _ = stream.shutdown().await;
+                return Err(err);
+            }
+        };
+
+        let cmd = match req.command {
+            Socks5Command::Connect => Command::Connect(req.address),
+            Socks5Command::Bind => Command::Bind(req.address),
+            Socks5Command::Associate => Command::Associate,
+        };
+
+        let incoming = Self {
+            stream,
+            version: Version::Socks5,
+        };
+
+        Ok((incoming, cmd))
+    }
+
+    async fn handshake_socks4(
+        mut stream: TcpStream,
+        credentials: &Credentials,
+    ) -> IoResult<(Self, Command)> {
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf).await?;
+
+        let [_, cmd, port @ .., a, b, c, d] = buf;
+        let port = u16::from_be_bytes(port);
+
+        // the user ID is not used for authentication
+        read_null_terminated(&mut stream).await?;
+
+        // SOCKS4a, with the domain following the user ID if the address is `0.0.0.x` with x being non-zero
+        let addr = if [a, b, c] == [0, 0, 0] && d != 0 {
+            let domain = read_null_terminated(&mut stream).await?;
+            Address::DomainAddress(domain, port)
+        } else {
+            Address::SocketAddress(SocketAddr::from((Ipv4Addr::new(a, b, c, d), port)))
+        };
+
+        let mut incoming = Self {
+            stream,
+            version: Version::Socks4,
+        };
+
+        if credentials.as_handshake_method() != HandshakeMethod::None {
+            let _ = incoming
+                .write_reply(Reply::ConnectionNotAllowed, &Address::unspecified())
+                .await;
+            let _ = incoming.stream.shutdown().await;
+
+            return Err(IoError::new(
+                ErrorKind::PermissionDenied,
+                "socks4 requests are not allowed when authentication is required",
+            ));
+        }
+
+        match cmd {
+            SOCKS4_CMD_CONNECT => Ok((incoming, Command::Connect(addr))),
+            SOCKS4_CMD_BIND => Ok((incoming, Command::Bind(addr))),
+            cmd => {
+                let _ = incoming
+                    .write_reply(Reply::CommandNotSupported, &Address::unspecified())
+                    .await;
+                let _ = incoming.stream.shutdown().await;
+
+                Err(IoError::new(
+                    ErrorKind::Unsupported,
+                    format!("unsupported socks4 command {cmd:#x}"),
+                ))
+            }
+        }
+    }
+
+    async fn handshake_http(
+        stream: TcpStream,
+        credentials: &Credentials,
+    ) -> IoResult<(Self, Command)> {
+        let mut method = [0; 8];
+        let n = stream.peek(&mut method).await?;
+
+        let mut incoming = Self {
+            stream,
+            version: Version::Http,
+        };
+
+        if &method[..n] != b"CONNECT " {
+            return Ok((incoming, Command::Http));
+        }
+
+        // read byte by byte, as the client may send data right after the head
+        let mut head = Vec::new();
+
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= HTTP_MAX_HEAD_LEN {
+                let _ = incoming.stream.shutdown().await;
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    "http request head too long",
+                ));
+            }
+
+            head.push(incoming.stream.read_u8().await?);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        let mut lines = head.split("\r\n");
+
+        let target = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(parse_authority);
+
+        let auth = lines.find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("proxy-authorization")
+                .then_some(value.as_bytes())
+        });
+
+        if !credentials.verify_basic(auth) {
+            incoming.stream.write_all(HTTP_AUTH_REQUIRED).await?;
+            let _ = incoming.stream.shutdown().await;
+
+            return Err(IoError::new(
+                ErrorKind::PermissionDenied,
+                "http proxy authentication failed",
+            ));
+        }
+
+        match target {
+            Some(addr) => Ok((incoming, Command::Connect(addr))),
+            None => {
+                let _ = incoming.write_http_reply(Reply::GeneralFailure).await;
+                let _ = incoming.stream.shutdown().await;
+
+                Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    "invalid http connect target",
+                ))
+            }
+        }
+    }
+
+    /// Replies to the command, returning the stream for relaying
+    ///
+    /// SOCKS4 replies only carry the bound address if it is an IPv4 one, and HTTP replies only tell whether the request is granted.
+    pub async fn reply(mut self, reply: Reply, addr: Address) -> IoResult<TcpStream> {
+        self.reply_pending(reply, addr).await?;
+        Ok(self.stream)
+    }
+
+    /// Replies to the command without finishing it, for the first of the two replies to `BIND`
+    pub async fn reply_pending(&mut self, reply: Reply, addr: Address) -> IoResult<()> {
+        match self.version {
+            Version::Socks5 => Response::new(reply, addr).write_to(&mut self.stream).await,
+            Version::Socks4 => self.write_reply(reply, &addr).await,
+            Version::Http => self.write_http_reply(reply).await,
+        }
+    }
+
+    /// Resolves when the client closes the connection while waiting for the reply, keeping any data sent early
+    pub async fn closed(&self) {
+        if matches!(self.stream.peek(&mut [0]).await, Ok(n) if n > 0) {
+            future::pending().await
+        }
+    }
+
+    async fn write_reply(&mut self, reply: Reply, addr: &Address) -> IoResult<()> {
+        let status = match reply {
+            Reply::Succeeded => SOCKS4_REPLY_GRANTED,
+            _ => SOCKS4_REPLY_REJECTED,
+        };
+
+        let (ip, port) = match addr {
+            Address::SocketAddress(SocketAddr::V4(addr)) => (addr.ip().octets(), addr.port()),
+            _ => ([0; 4], 0),
+        };
+
+        let [port_hi, port_lo] = port.to_be_bytes();
+        let [a, b, c, d] = ip;
+        let buf = [SOCKS4_REPLY_VERSION, status, port_hi, port_lo, a, b, c, d];
+        self.stream.write_all(&buf).await
+    }
+
+    async fn write_http_reply(&mut self, reply: Reply) -> IoResult<()> {
+        let status = match reply {
+            Reply::Succeeded => "200 Connection Established",
+            Reply::ConnectionNotAllowed => "403 Forbidden",
+            Reply::CommandNotSupported => "405 Method Not Allowed",
+            Reply::GeneralFailure => "400 Bad Request",
+            _ => "502 Bad Gateway",
+        };
+
+        let resp = format!("HTTP/1.1 {status}\r\n\r\n");
+        self.stream.write_all(resp.as_bytes()).await
+    }
+
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+
+    pub fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> IoResult<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+/// Waits until the client closes the connection the UDP associate command was sent on
+pub async fn wait_until_closed(stream: &mut TcpStream) -> IoResult<()> {
+    loop {
+        match stream.read(&mut [0]).await {
+            Ok(0) => break Ok(()),
+            Ok(_) => {}
+            Err(err) => break Err(err),
+        }
+    }
+}
+
+/// Parses the authority form `HOST:PORT` of HTTP request targets, with IPv6 addresses in brackets
+fn parse_authority(authority: &str) -> Option<Address> {
+    let (host, port) = authority.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    match host.parse() {
+        Ok(ip) => Some(Address::SocketAddress(SocketAddr::new(ip, port))),
+        Err(_) if !host.is_empty() => Some(Address::DomainAddress(host.to_owned(), port)),
+        Err(_) => None,
+    }
+}
+
+async fn read_null_terminated(stream: &mut TcpStream) -> IoResult<String> {
+    let mut buf = Vec::new();
+
+    loop {
+        match stream.read_u8().await? {
+            0 => break,
+            _ if buf.len() >= SOCKS4_MAX_FIELD_LEN => {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    "socks4 request field too long",
+                ))
+            }
+            b => buf.push(b),
+        }
+    }
+
+    String::from_utf8(buf).map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+}
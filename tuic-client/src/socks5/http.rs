@@ -0,0 +1,224 @@
+//! Forwarding HTTP proxy requests other than `CONNECT`
+
+use super::{auth::Credentials, Server};
+use crate::{
+    connection::Connection as TuicConnection,
+    controller::{
+        self,
+        tracker::{Counted, TrackedGuard},
+    },
+    dns::Server as DnsServer,
+    error::Error,
+    protect,
+    router::{Outbound, Router},
+};
+use hyper::{
+    client::conn as client_conn,
+    header::{self, HeaderValue},
+    server::conn::Http,
+    service::service_fn,
+    Body, Request, Response, StatusCode, Uri,
+};
+use std::{
+    convert::Infallible,
+    io::{Error as IoError, ErrorKind},
+    net::SocketAddr,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address as TuicAddress;
+
+impl Server {
+    /// Serves HTTP proxy requests on a local connection until the client closes it
+    ///
+    /// The connection is kept alive across requests, which may be pipelined. Each request is relayed on its own TCP relay to the host in its absolute-form target, as requests on the same connection may be for different hosts.
+    pub async fn handle_http(stream: TcpStream, credentials: Arc<Credentials>) {
+        let peer_addr = stream.peer_addr().unwrap();
+        let local_addr = stream.local_addr().unwrap();
+
+        let service = service_fn(move |req| {
+            let credentials = credentials.clone();
+
+            async move {
+                let resp =
+                    Self::handle_http_request(req, peer_addr, local_addr, &credentials).await;
+                Ok::<_, Infallible>(resp)
+            }
+        });
+
+        let res = Http::new()
+            .http1_only(true)
+            .http1_keep_alive(true)
+            .serve_connection(stream, service)
+            .await;
+
+        if let Err(err) = res {
+            log::warn!("[socks5] [{peer_addr}] [http] connection error: {err}");
+        }
+    }
+
+    async fn handle_http_request(
+        mut req: Request<Body>,
+        peer_addr: SocketAddr,
+        local_addr: SocketAddr,
+        credentials: &Credentials,
+    ) -> Response<Body> {
+        let auth = req
+            .headers()
+            .get(header::PROXY_AUTHORIZATION)
+            .map(HeaderValue::as_bytes);
+
+        if !credentials.verify_basic(auth) {
+            log::warn!("[socks5] [{peer_addr}] [http] proxy authentication failed");
+
+            let mut resp = status(StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+            resp.headers_mut().insert(
+                header::PROXY_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"tuic\""),
+            );
+            return resp;
+        }
+
+        let Some(target_addr) = target(req.uri()) else {
+            log::warn!(
+                "[socks5] [{peer_addr}] [http] invalid request target: {uri}",
+                uri = req.uri()
+            );
+            return status(StatusCode::BAD_REQUEST);
+        };
+
+        let target_addr = DnsServer::restore_fake_ip(target_addr);
+        log::info!(
+            "[socks5] [{peer_addr}] [http] [{target_addr}] {method}",
+            method = req.method()
+        );
+
+        // the target expects the origin form, without the proxy headers
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        *req.uri_mut() = path.parse().unwrap();
+        req.headers_mut().remove(header::PROXY_AUTHORIZATION);
+        req.headers_mut().remove("proxy-connection");
+
+        let process = Self::lookup_process(peer_addr, local_addr).await;
+        let rule = Router::matched_rule(&target_addr, process.as_ref());
+        let outbound = rule
+            .as_ref()
+            .map_or(Router::default_outbound(), |rule| rule.outbound);
+
+        if let Outbound::Block = outbound {
+            log::info!("[socks5] [{peer_addr}] [http] [{target_addr}] blocked by router");
+            return status(StatusCode::FORBIDDEN);
+        }
+
+        let guard = TrackedGuard::new(
+            "tcp",
+            peer_addr,
+            target_addr.clone(),
+            String::from(controller::outbound_name(outbound)),
+            rule.as_ref()
+                .map_or(String::from("Match"), |rule| rule.matcher.to_string()),
+        );
+
+        let res = match outbound {
+            Outbound::Proxy => {
+                let relay = match TuicConnection::get_for_connect(&target_addr).await {
+                    Ok(conn) => {
+                        guard.tracked().set_server(conn.server());
+                        conn.connect(target_addr.clone(), rule.as_deref()).await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                match relay {
+                    Ok(relay) => Self::forward_http(req, relay.compat(), guard).await,
+                    Err(err) => Err(err),
+                }
+            }
+            Outbound::Direct => match protect::connect_direct(&target_addr).await {
+                Ok(stream) => Self::forward_http(req, stream, guard).await,
+                Err(err) => Err(Error::from(err)),
+            },
+            Outbound::Block => unreachable!(),
+        };
+
+        match res {
+            Ok(resp) => resp,
+            Err(err) => {
+                log::warn!("[socks5] [{peer_addr}] [http] [{target_addr}] unable to relay HTTP request: {err}");
+                status(StatusCode::BAD_GATEWAY)
+            }
+        }
+    }
+
+    /// Sends the request to the target over the stream, returning the response
+    ///
+    /// The stream is closed after the response is fully received.
+    async fn forward_http<S>(
+        req: Request<Body>,
+        stream: S,
+        guard: TrackedGuard,
+    ) -> Result<Response<Body>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let stream = Counted::outbound(stream, guard.tracked().clone());
+
+        let (mut sender, conn) = client_conn::handshake(stream)
+            .await
+            .map_err(|err| IoError::new(ErrorKind::Other, err))?;
+
+        tokio::spawn(async move {
+            tokio::select! {
+                res = conn => {
+                    if let Err(err) = res {
+                        log::debug!("[socks5] [http] relay error: {err}");
+                    }
+                }
+                _ = guard.tracked().closed() => {}
+            }
+        });
+
+        let version = req.version();
+
+        let mut resp = sender
+            .send_request(req)
+            .await
+            .map_err(|err| IoError::new(ErrorKind::Other, err))?;
+
+        // the local connection is kept alive regardless of how the target handles its own
+        *resp.version_mut() = version;
+        resp.headers_mut().remove(header::CONNECTION);
+        resp.headers_mut().remove("keep-alive");
+
+        Ok(resp)
+    }
+}
+
+/// Returns the target address of an absolute-form `http://` request target
+fn target(uri: &Uri) -> Option<TuicAddress> {
+    if uri.scheme_str() != Some("http") {
+        return None;
+    }
+
+    let authority = uri.authority()?;
+    let port = authority.port_u16().unwrap_or(80);
+    let host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+
+    match host.parse() {
+        Ok(ip) => Some(TuicAddress::SocketAddress(SocketAddr::new(ip, port))),
+        Err(_) => TuicAddress::domain(host, port).ok(),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+    resp
+}
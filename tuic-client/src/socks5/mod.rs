@@ -1,63 +1,245 @@
-use crate::{config::Local, error::Error};
-use once_cell::sync::OnceCell;
-use parking_lot::Mutex;
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use socks5_server::{
-    auth::{NoAuth, Password},
-    Auth, Connection, Server as Socks5Server,
+//! The local listeners, serving SOCKS5, SOCKS4 and HTTP proxy requests
+//!
+//! Every listener in `local` relays through the same relay server connections, with the associate IDs of their UDP sessions taken from the same counter, so that packets from the relay servers are dispatched by the associate ID alone. Each listener counts its own connections and commands for the controller API.
+
+use self::{
+    auth::Credentials,
+    handshake::{Command, Incoming},
+};
+use crate::{
+    config::Local,
+    connection,
+    error::Error,
+    utils::{InboundProtocol, IpCidr},
 };
+use crossbeam_utils::atomic::AtomicCell;
+use futures_util::future;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 use std::{
     collections::HashMap,
+    io::Error as IoError,
+    mem,
     net::{SocketAddr, TcpListener as StdTcpListener},
     sync::{
-        atomic::{AtomicU16, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
 };
-use tokio::net::TcpListener;
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, Notify},
+};
+use tuic_quinn::Inbound;
 
+mod auth;
 mod handle_task;
+mod handshake;
+mod http;
 mod udp_session;
 
 pub use self::udp_session::UDP_SESSIONS;
 
-static SERVER: OnceCell<Server> = OnceCell::new();
+/// Senders of the connections accepted by the relay servers to the pending `BIND` commands, by binding ID
+pub static BINDS: Lazy<Mutex<HashMap<u16, oneshot::Sender<Inbound>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static SERVERS: RwLock<Vec<Arc<Server>>> = RwLock::new(Vec::new());
+static RESTART: Notify = Notify::const_new();
 
 pub struct Server {
-    inner: Socks5Server,
+    listener: TcpListener,
+    addr: SocketAddr,
     dual_stack: Option<bool>,
-    max_pkt_size: usize,
-    next_assoc_id: AtomicU16,
+    fast_open: bool,
+    backlog: Option<u32>,
+    name: RwLock<Option<String>>,
+    protocol: AtomicCell<InboundProtocol>,
+    credentials: Arc<Credentials>,
+    allowed_ips: RwLock<Vec<IpCidr>>,
+    max_pkt_size: AtomicUsize,
+    nodelay: AtomicBool,
+    stats: Arc<Stats>,
+}
+
+#[derive(Default)]
+struct Stats {
+    active: AtomicUsize,
+    connections: AtomicU64,
+    connects: AtomicU64,
+    associates: AtomicU64,
+    binds: AtomicU64,
+    http_requests: AtomicU64,
+}
+
+/// The status of a local listener, for the controller API
+pub struct InboundStatus {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub protocol: InboundProtocol,
+    /// The number of connections currently open
+    pub active: usize,
+    /// The number of connections accepted since the listener was bound
+    pub connections: u64,
+    pub connects: u64,
+    pub associates: u64,
+    pub binds: u64,
+    /// The number of connections carrying HTTP requests other than `CONNECT`
+    pub http_requests: u64,
 }
 
 impl Server {
-    pub fn set_config(cfg: Local) -> Result<(), Error> {
-        SERVER
-            .set(Self::new(
-                cfg.server,
-                cfg.dual_stack,
-                cfg.max_packet_size,
-                cfg.username,
-                cfg.password,
-            )?)
-            .map_err(|_| "failed initializing socks5 server")
-            .unwrap();
-
-        UDP_SESSIONS
-            .set(Mutex::new(HashMap::new()))
-            .map_err(|_| "failed initializing socks5 UDP session pool")
-            .unwrap();
+    /// Sets up the local listeners, or applies the new settings when reloading the config
+    ///
+    /// A listener is only rebound if its address, dual-stack, fast open or backlog setting is changed, keeping its statistics otherwise. Established connections are kept either way.
+    pub fn set_config(cfg: Vec<Local>) -> Result<(), Error> {
+        let mut current = SERVERS.write();
+
+        let servers = cfg
+            .into_iter()
+            .map(|local| Self::apply(local, &current))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let changed = servers.len() != current.len()
+            || servers
+                .iter()
+                .zip(current.iter())
+                .any(|(server, current)| !Arc::ptr_eq(server, current));
+
+        *current = servers;
+
+        if changed {
+            RESTART.notify_waiters();
+        }
+
+        UDP_SESSIONS.get_or_init(|| Mutex::new(HashMap::new()));
+
+        Ok(())
+    }
+
+    /// Adds a local listener at runtime, replacing the one with the same name, or with the same address if it has no name
+    ///
+    /// The other listeners and the established connections are kept. The change is lost once the `local` section of the config file is reloaded.
+    pub fn add(cfg: Local) -> Result<(), Error> {
+        let mut current = SERVERS.write();
+        let name = cfg.name.clone().unwrap_or_else(|| cfg.server.to_string());
+        let server = Self::apply(cfg, &current)?;
+
+        // a listener reused on the same address is renamed already, so it is replaced along with the one with the name
+        let idx = current.iter().position(|server| server.name() == name);
+        current.retain(|server| server.name() != name);
+        let idx = idx.map_or(current.len(), |idx| idx.min(current.len()));
+        current.insert(idx, server);
 
+        RESTART.notify_waiters();
         Ok(())
     }
 
-    fn new(
-        addr: SocketAddr,
-        dual_stack: Option<bool>,
-        max_pkt_size: usize,
+    /// Removes the local listener with the name at runtime, returning `false` if there is none
+    ///
+    /// The listener is closed, while the connections accepted on it are kept until they close.
+    pub fn remove(name: &str) -> bool {
+        let mut current = SERVERS.write();
+        let len = current.len();
+        current.retain(|server| server.name() != name);
+
+        if current.len() == len {
+            return false;
+        }
+
+        RESTART.notify_waiters();
+        true
+    }
+
+    /// Applies the settings to the current listener on the same address, or binds a new one
+    fn apply(mut cfg: Local, current: &[Arc<Self>]) -> Result<Arc<Self>, Error> {
+        let users = Self::users(
+            cfg.username.take(),
+            cfg.password.take(),
+            mem::take(&mut cfg.users),
+        )?;
+
+        let reused = current.iter().find(|server| {
+            server.addr == cfg.server
+                && server.dual_stack == cfg.dual_stack
+                && server.fast_open == cfg.fast_open
+                && server.backlog == cfg.backlog
+        });
+
+        let Some(server) = reused else {
+            return Ok(Arc::new(Self::new(cfg, users)?));
+        };
+
+        *server.name.write() = cfg.name;
+        server.protocol.store(cfg.protocol);
+        server.credentials.set(users);
+        *server.allowed_ips.write() = cfg.allowed_ips;
+        server
+            .max_pkt_size
+            .store(cfg.max_packet_size, Ordering::Relaxed);
+        server.nodelay.store(cfg.nodelay, Ordering::Relaxed);
+
+        Ok(server.clone())
+    }
+
+    /// Stops the local listeners, closing them
+    pub fn stop() {
+        SERVERS.write().clear();
+        RESTART.notify_waiters();
+    }
+
+    /// Returns the status of every local listener
+    pub fn inbounds() -> Vec<InboundStatus> {
+        SERVERS
+            .read()
+            .iter()
+            .map(|server| InboundStatus {
+                name: server.name(),
+                addr: server.addr,
+                protocol: server.protocol.load(),
+                active: server.stats.active.load(Ordering::Relaxed),
+                connections: server.stats.connections.load(Ordering::Relaxed),
+                connects: server.stats.connects.load(Ordering::Relaxed),
+                associates: server.stats.associates.load(Ordering::Relaxed),
+                binds: server.stats.binds.load(Ordering::Relaxed),
+                http_requests: server.stats.http_requests.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// The name of the listener, or its address if it has none
+    fn name(&self) -> String {
+        self.name
+            .read()
+            .clone()
+            .unwrap_or_else(|| self.addr.to_string())
+    }
+
+    fn users(
         username: Option<Vec<u8>>,
         password: Option<Vec<u8>>,
-    ) -> Result<Self, Error> {
+        users: HashMap<String, String>,
+    ) -> Result<HashMap<Vec<u8>, Vec<u8>>, Error> {
+        let mut users = users
+            .into_iter()
+            .map(|(username, password)| (username.into_bytes(), password.into_bytes()))
+            .collect::<HashMap<_, _>>();
+
+        match (username, password) {
+            (Some(username), Some(password)) => {
+                users.insert(username, password);
+            }
+            (None, None) => {}
+            _ => return Err(Error::InvalidSocks5Auth),
+        }
+
+        Ok(users)
+    }
+
+    fn new(cfg: Local, users: HashMap<Vec<u8>, Vec<u8>>) -> Result<Self, Error> {
+        let addr = cfg.server;
+
         let socket = {
             let domain = match addr {
                 SocketAddr::V4(_) => Domain::IPV4,
@@ -67,7 +249,7 @@ impl Server {
             let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))
                 .map_err(|err| Error::Socket("failed to create socks5 server socket", err))?;
 
-            if let Some(dual_stack) = dual_stack {
+            if let Some(dual_stack) = cfg.dual_stack {
                 socket.set_only_v6(!dual_stack).map_err(|err| {
                     Error::Socket("socks5 server dual-stack socket setting error", err)
                 })?;
@@ -85,65 +267,162 @@ impl Server {
                 .bind(&SockAddr::from(addr))
                 .map_err(|err| Error::Socket("failed to bind socks5 server socket", err))?;
 
+            if cfg.fast_open {
+                Self::set_fast_open(&socket).map_err(|err| {
+                    Error::Socket("failed to enable fast open on socks5 server socket", err)
+                })?;
+            }
+
+            // the system caps the backlog at its own maximum, which is used if not set
+            let backlog = cfg.backlog.map_or(i32::MAX, |backlog| {
+                i32::try_from(backlog).unwrap_or(i32::MAX)
+            });
+
             socket
-                .listen(i32::MAX)
+                .listen(backlog)
                 .map_err(|err| Error::Socket("failed to listen on socks5 server socket", err))?;
 
             TcpListener::from_std(StdTcpListener::from(socket))
                 .map_err(|err| Error::Socket("failed to create socks5 server socket", err))?
         };
 
-        let auth: Arc<dyn Auth + Send + Sync> = match (username, password) {
-            (Some(username), Some(password)) => Arc::new(Password::new(username, password)),
-            (None, None) => Arc::new(NoAuth),
-            _ => return Err(Error::InvalidSocks5Auth),
-        };
-
         Ok(Self {
-            inner: Socks5Server::new(socket, auth),
-            dual_stack,
-            max_pkt_size,
-            next_assoc_id: AtomicU16::new(0),
+            listener: socket,
+            addr,
+            dual_stack: cfg.dual_stack,
+            fast_open: cfg.fast_open,
+            backlog: cfg.backlog,
+            name: RwLock::new(cfg.name),
+            protocol: AtomicCell::new(cfg.protocol),
+            credentials: Arc::new(Credentials::new(users)),
+            allowed_ips: RwLock::new(cfg.allowed_ips),
+            max_pkt_size: AtomicUsize::new(cfg.max_packet_size),
+            nodelay: AtomicBool::new(cfg.nodelay),
+            stats: Arc::new(Stats::default()),
         })
     }
 
+    /// Accepts TCP Fast Open connections on the listening socket, with data in their SYN
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_fast_open(socket: &Socket) -> Result<(), IoError> {
+        use std::os::fd::AsRawFd;
+
+        // the maximum number of pending fast open requests not yet accepted
+        let qlen: libc::c_int = 256;
+
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN,
+                &qlen as *const _ as *const libc::c_void,
+                mem::size_of_val(&qlen) as libc::socklen_t,
+            )
+        };
+
+        if res < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn set_fast_open(_: &Socket) -> Result<(), IoError> {
+        log::warn!("[socks5] TCP fast open is not supported on this platform, ignored");
+        Ok(())
+    }
+
     pub async fn start() {
-        let server = SERVER.get().unwrap();
+        loop {
+            let restart = RESTART.notified();
+            tokio::pin!(restart);
+            restart.as_mut().enable();
+
+            let servers = SERVERS.read().clone();
+
+            if servers.is_empty() {
+                restart.await;
+                continue;
+            }
+
+            for server in &servers {
+                log::warn!(
+                    "[socks5] {protocol} server {name} started, listening on {addr}",
+                    protocol = server.protocol.load(),
+                    name = server.name(),
+                    addr = server.listener.local_addr().unwrap(),
+                );
+            }
 
-        log::warn!(
-            "[socks5] server started, listening on {}",
-            server.inner.local_addr().unwrap()
-        );
+            // serve until the listeners are replaced by reloading the config
+            tokio::select! {
+                _ = future::join_all(servers.into_iter().map(Self::serve)) => {}
+                _ = restart => {}
+            }
+        }
+    }
 
+    async fn serve(self: Arc<Self>) {
         loop {
-            match server.inner.accept().await {
-                Ok((conn, addr)) => {
+            match self.listener.accept().await {
+                Ok((stream, addr)) => {
+                    if !self.is_allowed(&addr) {
+                        log::warn!("[socks5] [{addr}] rejected, source address not allowed");
+                        continue;
+                    }
+
                     log::debug!("[socks5] [{addr}] connection established");
 
+                    if self.nodelay.load(Ordering::Relaxed) {
+                        if let Err(err) = stream.set_nodelay(true) {
+                            log::warn!("[socks5] [{addr}] failed to set TCP_NODELAY: {err}");
+                        }
+                    }
+
+                    self.stats.connections.fetch_add(1, Ordering::Relaxed);
+                    self.stats.active.fetch_add(1, Ordering::Relaxed);
+
+                    // the connection does not keep the listener open once it is removed
+                    let dual_stack = self.dual_stack;
+                    let max_pkt_size = self.max_pkt_size.load(Ordering::Relaxed);
+                    let credentials = self.credentials.clone();
+                    let protocol = self.protocol.load();
+                    let stats = self.stats.clone();
+
                     tokio::spawn(async move {
-                        match conn.handshake().await {
-                            Ok(Connection::Associate(associate, _)) => {
-                                let assoc_id = server.next_assoc_id.fetch_add(1, Ordering::Relaxed);
+                        match Incoming::handshake(stream, &credentials, protocol).await {
+                            Ok((associate, Command::Associate)) => {
+                                stats.associates.fetch_add(1, Ordering::Relaxed);
+                                let assoc_id = connection::next_assoc_id();
                                 log::info!("[socks5] [{addr}] [associate] [{assoc_id:#06x}]");
                                 Self::handle_associate(
                                     associate,
                                     assoc_id,
-                                    server.dual_stack,
-                                    server.max_pkt_size,
+                                    dual_stack,
+                                    max_pkt_size,
                                 )
                                 .await;
                             }
-                            Ok(Connection::Bind(bind, _)) => {
-                                log::info!("[socks5] [{addr}] [bind]");
-                                Self::handle_bind(bind).await;
+                            Ok((bind, Command::Bind(target_addr))) => {
+                                stats.binds.fetch_add(1, Ordering::Relaxed);
+                                log::info!("[socks5] [{addr}] [bind] {target_addr}");
+                                Self::handle_bind(bind, target_addr).await;
                             }
-                            Ok(Connection::Connect(connect, target_addr)) => {
+                            Ok((connect, Command::Connect(target_addr))) => {
+                                stats.connects.fetch_add(1, Ordering::Relaxed);
                                 log::info!("[socks5] [{addr}] [connect] {target_addr}");
                                 Self::handle_connect(connect, target_addr).await;
                             }
+                            Ok((http, Command::Http)) => {
+                                stats.http_requests.fetch_add(1, Ordering::Relaxed);
+                                log::debug!("[socks5] [{addr}] [http]");
+                                Self::handle_http(http.into_inner(), credentials).await;
+                            }
                             Err(err) => log::warn!("[socks5] [{addr}] handshake error: {err}"),
                         };
 
+                        stats.active.fetch_sub(1, Ordering::Relaxed);
                         log::debug!("[socks5] [{addr}] connection closed");
                     });
                 }
@@ -151,4 +430,9 @@ impl Server {
             }
         }
     }
+
+    fn is_allowed(&self, addr: &SocketAddr) -> bool {
+        let allowed_ips = self.allowed_ips.read();
+        allowed_ips.is_empty() || allowed_ips.iter().any(|cidr| cidr.contains(addr.ip()))
+    }
 }
@@ -1,4 +1,4 @@
-use crate::error::Error;
+use crate::{controller::tracker::Tracked, error::Error, protect};
 use bytes::Bytes;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
@@ -10,16 +10,33 @@ use std::{
     io::{Error as IoError, ErrorKind},
     net::{IpAddr, SocketAddr, UdpSocket as StdUdpSocket},
     sync::Arc,
+    time::Duration,
 };
-use tokio::net::UdpSocket;
+use tokio::{
+    net::{self, UdpSocket},
+    sync::{
+        oneshot::{self, Receiver, Sender},
+        OnceCell as AsyncOnceCell,
+    },
+    time,
+};
+use tuic::Address as TuicAddress;
 
 pub static UDP_SESSIONS: OnceCell<Mutex<HashMap<u16, UdpSession>>> = OnceCell::new();
 
+/// How long to wait before receiving again after the direct socket fails, so a persistent error does not spin the loop
+const DIRECT_RECV_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
 #[derive(Clone)]
 pub struct UdpSession {
     socket: Arc<AssociatedUdpSocket>,
     assoc_id: u16,
     ctrl_addr: SocketAddr,
+    max_pkt_size: usize,
+    direct: Arc<AsyncOnceCell<Arc<UdpSocket>>>,
+    close_tx: Arc<Mutex<Option<Sender<()>>>>,
+    close_rx: Arc<Mutex<Option<Receiver<()>>>>,
+    tracked: Arc<Tracked>,
 }
 
 impl UdpSession {
@@ -29,6 +46,7 @@ impl UdpSession {
         local_ip: IpAddr,
         dual_stack: Option<bool>,
         max_pkt_size: usize,
+        tracked: Arc<Tracked>,
     ) -> Result<Self, Error> {
         let domain = match local_ip {
             IpAddr::V4(_) => Domain::IPV4,
@@ -65,10 +83,17 @@ impl UdpSession {
             Error::Socket("failed to create socks5 server UDP associate socket", err)
         })?;
 
+        let (tx, rx) = oneshot::channel();
+
         Ok(Self {
             socket: Arc::new(AssociatedUdpSocket::from((socket, max_pkt_size))),
             assoc_id,
             ctrl_addr,
+            max_pkt_size,
+            direct: Arc::new(AsyncOnceCell::new()),
+            close_tx: Arc::new(Mutex::new(Some(tx))),
+            close_rx: Arc::new(Mutex::new(Some(rx))),
+            tracked,
         })
     }
 
@@ -82,6 +107,8 @@ impl UdpSession {
             dst_addr = self.socket.peer_addr().unwrap(),
         );
 
+        let len = pkt.len();
+
         if let Err(err) = self.socket.send(pkt, 0, src_addr).await {
             log::warn!(
                 "[socks5] [{ctrl_addr}] [associate] [{assoc_id:#06x}] send packet from {src_addr_display} to {dst_addr} error: {err}",
@@ -93,6 +120,7 @@ impl UdpSession {
             return Err(Error::Io(err));
         }
 
+        self.tracked.add_download(len);
         Ok(())
     }
 
@@ -143,10 +171,98 @@ impl UdpSession {
             assoc_id = self.assoc_id
         );
 
+        self.tracked.add_upload(pkt.len());
         Ok((pkt, dst_addr))
     }
 
     pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
         self.socket.local_addr()
     }
+
+    /// Sends a packet directly to the target, bypassing the relay
+    pub async fn send_direct(&self, pkt: Bytes, dst_addr: TuicAddress) -> Result<(), Error> {
+        let socket = self
+            .direct
+            .get_or_try_init(|| async { self.bind_direct() })
+            .await?;
+
+        let dst_addr = match dst_addr {
+            TuicAddress::DomainAddress(domain, port) => net::lookup_host((domain.as_str(), port))
+                .await?
+                .next()
+                .ok_or_else(|| IoError::new(ErrorKind::NotFound, "no address resolved"))?,
+            TuicAddress::SocketAddress(addr) => addr,
+            TuicAddress::None => unreachable!(),
+        };
+
+        let dst_addr = match (socket.local_addr()?, dst_addr) {
+            (SocketAddr::V6(_), SocketAddr::V4(addr)) => {
+                SocketAddr::new(addr.ip().to_ipv6_mapped().into(), addr.port())
+            }
+            _ => dst_addr,
+        };
+
+        log::debug!(
+            "[socks5] [{ctrl_addr}] [associate] [{assoc_id:#06x}] [direct] send packet to {dst_addr}",
+            ctrl_addr = self.ctrl_addr,
+            assoc_id = self.assoc_id,
+        );
+
+        socket.send_to(&pkt, dst_addr).await?;
+        Ok(())
+    }
+
+    fn bind_direct(&self) -> Result<Arc<UdpSocket>, Error> {
+        let socket = Arc::new(protect::bind_direct_udp()?);
+
+        let session = self.clone();
+        let listen_socket = socket.clone();
+        let close_rx = self.close_rx.lock().take().unwrap();
+
+        let listen = async move {
+            let mut buf = vec![0; session.max_pkt_size];
+
+            loop {
+                let (n, src_addr) = match listen_socket.recv_from(&mut buf).await {
+                    Ok(res) => res,
+                    Err(err) => {
+                        log::warn!(
+                            "[socks5] [{ctrl_addr}] [associate] [{assoc_id:#06x}] [direct] failed to receive UDP packet: {err}",
+                            ctrl_addr = session.ctrl_addr,
+                            assoc_id = session.assoc_id,
+                        );
+                        time::sleep(DIRECT_RECV_ERROR_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                let src_addr = match src_addr {
+                    SocketAddr::V6(addr) => match addr.ip().to_ipv4_mapped() {
+                        Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+                        None => src_addr,
+                    },
+                    SocketAddr::V4(_) => src_addr,
+                };
+
+                let pkt = Bytes::copy_from_slice(&buf[..n]);
+                let _ = session.send(pkt, Address::SocketAddress(src_addr)).await;
+            }
+        };
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = listen => unreachable!(),
+                _ = close_rx => {},
+            }
+        });
+
+        Ok(socket)
+    }
+
+    /// Stops the direct relaying of this session
+    pub fn close(&self) {
+        if let Some(tx) = self.close_tx.lock().take() {
+            let _ = tx.send(());
+        }
+    }
 }
@@ -0,0 +1,469 @@
+//! Setting the system proxy to the local socks5 server, and serving a PAC file for automatic proxy configuration
+//!
+//! The system proxy settings changed on start are restored on exit.
+
+use crate::{config::SystemProxy as SystemProxyConfig, error::Error};
+use bytes::Bytes;
+use hyper::{
+    header::{self, HeaderValue},
+    server::conn::AddrIncoming,
+    service::{make_service_fn, service_fn},
+    Body, Response, Server as HyperServer,
+};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    process::Command,
+    sync::Arc,
+};
+
+static SYSTEM_PROXY: RwLock<Option<Arc<SystemProxy>>> = RwLock::new(None);
+
+pub struct SystemProxy {
+    set: bool,
+    socks5: SocketAddr,
+    pac_server: Option<SocketAddr>,
+    bypass: Vec<String>,
+    restore: Mutex<Vec<Command>>,
+}
+
+impl SystemProxy {
+    /// Sets up the system proxy. Changes take effect when started again, after restoring the current settings
+    pub fn set_config(cfg: Option<SystemProxyConfig>, socks5: SocketAddr) -> Result<(), Error> {
+        let Some(cfg) = cfg else {
+            *SYSTEM_PROXY.write() = None;
+            return Ok(());
+        };
+
+        if cfg.set && cfg!(windows) && cfg.pac_server.is_none() {
+            return Err(Error::SystemProxy(String::from(
+                "`pac_server` is required on Windows, where the system SOCKS proxy is treated as SOCKS4",
+            )));
+        }
+
+        *SYSTEM_PROXY.write() = Some(Arc::new(Self {
+            set: cfg.set,
+            socks5: loopback(socks5),
+            pac_server: cfg.pac_server.map(loopback),
+            bypass: cfg.bypass,
+            restore: Mutex::new(Vec::new()),
+        }));
+
+        Ok(())
+    }
+
+    pub async fn start() {
+        let Some(proxy) = SYSTEM_PROXY.read().clone() else {
+            return;
+        };
+
+        // the PAC server must be up before the system proxy points to it
+        let incoming = match proxy.pac_server {
+            Some(addr) => match AddrIncoming::bind(&addr) {
+                Ok(incoming) => Some(incoming),
+                Err(err) => {
+                    log::error!("[system-proxy] failed to bind PAC server to {addr}: {err}");
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        if proxy.set {
+            let res = apply(
+                proxy.socks5,
+                proxy.pac_url().as_deref(),
+                &proxy.bypass,
+                &mut proxy.restore.lock(),
+            );
+
+            match res {
+                Ok(()) => log::warn!(
+                    "[system-proxy] system proxy set to {}",
+                    proxy.pac_url().unwrap_or_else(|| proxy.socks5.to_string()),
+                ),
+                Err(err) => log::error!("[system-proxy] {err}"),
+            }
+        }
+
+        let Some(incoming) = incoming else {
+            return;
+        };
+
+        let pac = Bytes::from(proxy.pac());
+
+        log::warn!(
+            "[system-proxy] PAC server started, serving on {}",
+            proxy.pac_url().unwrap(),
+        );
+
+        let make_svc = make_service_fn(|_| {
+            let pac = pac.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    let mut res = Response::new(Body::from(pac.clone()));
+                    res.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/x-ns-proxy-autoconfig"),
+                    );
+                    async move { Ok::<_, Infallible>(res) }
+                }))
+            }
+        });
+
+        if let Err(err) = HyperServer::builder(incoming).serve(make_svc).await {
+            log::error!("[system-proxy] PAC server error: {err}");
+        }
+    }
+
+    /// Restores the system proxy settings changed on start
+    pub fn restore() {
+        let Some(proxy) = SYSTEM_PROXY.read().clone() else {
+            return;
+        };
+
+        let restore = std::mem::take(&mut *proxy.restore.lock());
+
+        if restore.is_empty() {
+            return;
+        }
+
+        for mut cmd in restore {
+            if let Err(err) = output(&mut cmd) {
+                log::error!("[system-proxy] failed restoring the system proxy: {err}");
+            }
+        }
+
+        log::warn!("[system-proxy] system proxy restored");
+    }
+
+    fn pac_url(&self) -> Option<String> {
+        self.pac_server
+            .map(|addr| format!("http://{addr}/proxy.pac"))
+    }
+
+    /// Generates the PAC file, sending everything except the bypassed hosts to the socks5 server
+    ///
+    /// Bypass entries are IPv4 CIDRs or host patterns with `*` wildcards.
+    fn pac(&self) -> String {
+        let conditions = self
+            .bypass
+            .iter()
+            .map(|entry| match ipv4_cidr(entry) {
+                Some((network, mask)) => format!(
+                    "(/^\\d+\\.\\d+\\.\\d+\\.\\d+$/.test(host) && isInNet(host, \"{network}\", \"{mask}\"))"
+                ),
+                None => format!(
+                    "shExpMatch(host, {})",
+                    serde_json::to_string(entry).unwrap()
+                ),
+            })
+            .collect::<Vec<_>>();
+
+        let bypass = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "    if ({}) {{\n        return \"DIRECT\";\n    }}\n\n",
+                conditions.join(" ||\n        ")
+            )
+        };
+
+        format!(
+            "function FindProxyForURL(url, host) {{\n{bypass}    return \"SOCKS5 {socks5}; SOCKS {socks5}\";\n}}\n",
+            socks5 = self.socks5,
+        )
+    }
+}
+
+/// Replaces an unspecified address with the loopback address of the same family
+fn loopback(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::from((Ipv4Addr::LOCALHOST, addr.port()))
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::from((Ipv6Addr::LOCALHOST, addr.port()))
+        }
+        _ => addr,
+    }
+}
+
+/// Parses an IPv4 CIDR into the network address and the netmask
+fn ipv4_cidr(s: &str) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    let (addr, prefix_len) = s.split_once('/')?;
+    let addr = addr.parse::<Ipv4Addr>().ok()?;
+    let prefix_len = prefix_len.parse::<u32>().ok().filter(|len| *len <= 32)?;
+    let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+    Some((Ipv4Addr::from(u32::from(addr) & mask), Ipv4Addr::from(mask)))
+}
+
+fn command<const N: usize>(program: &str, args: [&str; N]) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd
+}
+
+/// Runs the command, returning its standard output
+fn output(cmd: &mut Command) -> Result<String, Error> {
+    let output = cmd.output().map_err(|err| {
+        Error::SystemProxy(format!("failed to run {:?}: {err}", cmd.get_program()))
+    })?;
+
+    if !output.status.success() {
+        return Err(Error::SystemProxy(format!(
+            "{:?} exited with {}: {}",
+            cmd.get_program(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Sets the system proxy through GNOME settings, which are also read by other desktop environments and browsers on Linux
+#[cfg(all(unix, not(target_os = "macos")))]
+fn apply(
+    socks5: SocketAddr,
+    pac_url: Option<&str>,
+    bypass: &[String],
+    restore: &mut Vec<Command>,
+) -> Result<(), Error> {
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+
+    let settings = match pac_url {
+        Some(url) => vec![
+            ("org.gnome.system.proxy", "autoconfig-url", quote(url)),
+            ("org.gnome.system.proxy", "mode", quote("auto")),
+        ],
+        None => vec![
+            (
+                "org.gnome.system.proxy.socks",
+                "host",
+                quote(&socks5.ip().to_string()),
+            ),
+            (
+                "org.gnome.system.proxy.socks",
+                "port",
+                socks5.port().to_string(),
+            ),
+            // HTTP proxies take precedence over the SOCKS proxy in the manual mode
+            ("org.gnome.system.proxy.http", "host", quote("")),
+            ("org.gnome.system.proxy.https", "host", quote("")),
+            (
+                "org.gnome.system.proxy",
+                "ignore-hosts",
+                format!(
+                    "[{}]",
+                    bypass
+                        .iter()
+                        .map(|host| quote(host))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ),
+            ("org.gnome.system.proxy", "mode", quote("manual")),
+        ],
+    };
+
+    for (schema, key, value) in settings {
+        let prev = output(&mut command("gsettings", ["get", schema, key]))?;
+        output(&mut command("gsettings", ["set", schema, key, &value]))?;
+        restore.push(command("gsettings", ["set", schema, key, prev.trim()]));
+    }
+
+    Ok(())
+}
+
+/// Sets the system proxy of all enabled network services
+#[cfg(target_os = "macos")]
+fn apply(
+    socks5: SocketAddr,
+    pac_url: Option<&str>,
+    bypass: &[String],
+    restore: &mut Vec<Command>,
+) -> Result<(), Error> {
+    let field = |output: &str, name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(|value| value.trim().to_owned())
+            .unwrap_or_default()
+    };
+
+    let state = |enabled: bool| if enabled { "on" } else { "off" };
+
+    let services = output(&mut command("networksetup", ["-listallnetworkservices"]))?;
+
+    // the first line is a note, and disabled services are marked with `*`
+    for service in services
+        .lines()
+        .skip(1)
+        .filter(|service| !service.starts_with('*'))
+    {
+        match pac_url {
+            Some(url) => {
+                let prev = output(&mut command("networksetup", ["-getautoproxyurl", service]))?;
+                output(&mut command(
+                    "networksetup",
+                    ["-setautoproxyurl", service, url],
+                ))?;
+
+                let prev_url = field(&prev, "URL");
+                let prev_enabled = field(&prev, "Enabled") == "Yes";
+
+                if !prev_url.is_empty() && prev_url != "(null)" {
+                    restore.push(command(
+                        "networksetup",
+                        ["-setautoproxyurl", service, &prev_url],
+                    ));
+                }
+
+                restore.push(command(
+                    "networksetup",
+                    ["-setautoproxystate", service, state(prev_enabled)],
+                ));
+            }
+            None => {
+                let prev = output(&mut command(
+                    "networksetup",
+                    ["-getsocksfirewallproxy", service],
+                ))?;
+                output(&mut command(
+                    "networksetup",
+                    [
+                        "-setsocksfirewallproxy",
+                        service,
+                        &socks5.ip().to_string(),
+                        &socks5.port().to_string(),
+                    ],
+                ))?;
+
+                let prev_server = field(&prev, "Server");
+                let prev_port = field(&prev, "Port");
+                let prev_enabled = field(&prev, "Enabled") == "Yes";
+
+                if !prev_server.is_empty() {
+                    restore.push(command(
+                        "networksetup",
+                        ["-setsocksfirewallproxy", service, &prev_server, &prev_port],
+                    ));
+                }
+
+                restore.push(command(
+                    "networksetup",
+                    ["-setsocksfirewallproxystate", service, state(prev_enabled)],
+                ));
+
+                let prev = output(&mut command(
+                    "networksetup",
+                    ["-getproxybypassdomains", service],
+                ))?;
+
+                let mut cmd = command("networksetup", ["-setproxybypassdomains", service]);
+
+                if bypass.is_empty() {
+                    cmd.arg("Empty");
+                } else {
+                    cmd.args(bypass);
+                }
+
+                output(&mut cmd)?;
+
+                let mut cmd = command("networksetup", ["-setproxybypassdomains", service]);
+
+                if prev.starts_with("There aren't any") {
+                    cmd.arg("Empty");
+                } else {
+                    cmd.args(prev.lines().map(str::trim).filter(|line| !line.is_empty()));
+                }
+
+                restore.push(cmd);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the automatic proxy configuration URL of the current user
+///
+/// Applications started afterwards pick up the setting, while running ones may need a restart.
+#[cfg(windows)]
+fn apply(
+    _socks5: SocketAddr,
+    pac_url: Option<&str>,
+    _bypass: &[String],
+    restore: &mut Vec<Command>,
+) -> Result<(), Error> {
+    const INTERNET_SETTINGS: &str =
+        r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+    let url = pac_url.unwrap();
+
+    // querying fails if the value does not exist
+    let prev = output(&mut command(
+        "reg",
+        ["query", INTERNET_SETTINGS, "/v", "AutoConfigURL"],
+    ))
+    .ok()
+    .and_then(|output| {
+        output.lines().find_map(|line| {
+            let (_, value) = line.split_once("REG_SZ")?;
+            Some(value.trim().to_owned())
+        })
+    });
+
+    output(&mut command(
+        "reg",
+        [
+            "add",
+            INTERNET_SETTINGS,
+            "/v",
+            "AutoConfigURL",
+            "/t",
+            "REG_SZ",
+            "/d",
+            url,
+            "/f",
+        ],
+    ))?;
+
+    restore.push(match prev {
+        Some(prev) => command(
+            "reg",
+            [
+                "add",
+                INTERNET_SETTINGS,
+                "/v",
+                "AutoConfigURL",
+                "/t",
+                "REG_SZ",
+                "/d",
+                &prev,
+                "/f",
+            ],
+        ),
+        None => command(
+            "reg",
+            ["delete", INTERNET_SETTINGS, "/v", "AutoConfigURL", "/f"],
+        ),
+    });
+
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn apply(
+    _socks5: SocketAddr,
+    _pac_url: Option<&str>,
+    _bypass: &[String],
+    _restore: &mut Vec<Command>,
+) -> Result<(), Error> {
+    Err(Error::SystemProxy(String::from(
+        "unsupported on this platform",
+    )))
+}
@@ -0,0 +1,244 @@
+//! Sampling the path statistics of the connections to the relay servers, for comparing the servers and looking into slow connections with data
+//!
+//! Every `interval`, the RTT, the congestion window and the packets sent and lost by the established connections to each server are sampled. The latest sample of each server is reported by `/stats` of the controller. With `log`, every sample is also appended to the file, as a line of JSON:
+//!
+//! ```json
+//! {"time":"2023-06-01T12:00:00.000Z","server":"example.com:443","connections":1,"rtt":35.2,"cwnd":120000,"sentPackets":1024,"lostPackets":3,"loss":0.0029,"congestionEvents":1}
+//! ```
+//!
+//! or as a row of CSV with the same columns. `rtt` is the mean smoothed RTT of the connections in milliseconds, `cwnd` the sum of their congestion windows in bytes, while the packet and congestion event counts are since the previous sample.
+
+use crate::{
+    config::Telemetry as TelemetryConfig, connection::Connection, error::Error,
+    utils::TelemetryFormat,
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use quinn_proto::ConnectionStats;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Error as IoError, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tokio::time;
+
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+static SAMPLES: Lazy<Mutex<HashMap<String, Sample>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The sampling interval without `telemetry` set
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+const CSV_HEADER: &str =
+    "time,server,connections,rtt,cwnd,sent_packets,lost_packets,loss,congestion_events\n";
+
+struct Config {
+    interval: Duration,
+    log: Option<(BufWriter<File>, TelemetryFormat)>,
+}
+
+/// The path statistics of the connections to a server
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub connections: usize,
+    /// The mean smoothed RTT of the connections
+    pub rtt: Duration,
+    /// The sum of the congestion windows of the connections
+    pub cwnd: u64,
+    /// The packets sent since the previous sample
+    pub sent_packets: u64,
+    /// The packets lost since the previous sample
+    pub lost_packets: u64,
+    /// The congestion events since the previous sample
+    pub congestion_events: u64,
+}
+
+impl Sample {
+    /// The rate of the packets lost since the previous sample
+    pub fn loss(&self) -> f64 {
+        if self.sent_packets == 0 {
+            0.0
+        } else {
+            self.lost_packets as f64 / self.sent_packets as f64
+        }
+    }
+}
+
+pub struct Telemetry;
+
+impl Telemetry {
+    pub fn set_config(cfg: Option<TelemetryConfig>) -> Result<(), Error> {
+        let cfg = match cfg {
+            Some(cfg) => {
+                let log = cfg
+                    .log
+                    .map(|path| open(&path, cfg.format).map(|file| (file, cfg.format)))
+                    .transpose()
+                    .map_err(Error::TelemetryLog)?;
+
+                Config {
+                    interval: cfg.interval.max(Duration::from_secs(1)),
+                    log,
+                }
+            }
+            None => Config {
+                interval: DEFAULT_INTERVAL,
+                log: None,
+            },
+        };
+
+        *CONFIG.lock() = Some(cfg);
+        Ok(())
+    }
+
+    pub async fn start() {
+        let Some(interval) = CONFIG.lock().as_ref().map(|cfg| cfg.interval) else {
+            return;
+        };
+
+        // the statistics of each connection at the previous sample, by its stable ID
+        let mut prev = HashMap::new();
+
+        loop {
+            time::sleep(interval).await;
+
+            let time = SystemTime::now();
+            let mut stats = HashMap::new();
+            let mut samples = HashMap::new();
+
+            for server in Connection::servers() {
+                if server.paths.is_empty() {
+                    continue;
+                }
+
+                let sample = sample(time, &server.paths, &prev);
+                stats.extend(server.paths);
+                samples.insert(server.name, sample);
+            }
+
+            if let Some(Config {
+                log: Some((file, format)),
+                ..
+            }) = &mut *CONFIG.lock()
+            {
+                if let Err(err) = write(file, *format, &samples) {
+                    log::warn!("[telemetry] failed writing the log: {err}");
+                }
+            }
+
+            prev = stats;
+            *SAMPLES.lock() = samples;
+        }
+    }
+
+    /// The latest sample of the server, if it has established connections
+    pub fn sample(server: &str) -> Option<Sample> {
+        SAMPLES.lock().get(server).copied()
+    }
+
+    pub fn stop() {
+        *CONFIG.lock() = None;
+        SAMPLES.lock().clear();
+    }
+}
+
+fn sample(
+    time: SystemTime,
+    paths: &[(usize, ConnectionStats)],
+    prev: &HashMap<usize, ConnectionStats>,
+) -> Sample {
+    let mut sample = Sample {
+        time,
+        connections: paths.len(),
+        rtt: Duration::ZERO,
+        cwnd: 0,
+        sent_packets: 0,
+        lost_packets: 0,
+        congestion_events: 0,
+    };
+
+    for (id, stats) in paths {
+        // a connection established since the previous sample is counted from its start
+        let prev = prev.get(id).copied().unwrap_or_default();
+
+        sample.rtt += stats.path.rtt;
+        sample.cwnd += stats.path.cwnd;
+        sample.sent_packets += stats
+            .path
+            .sent_packets
+            .saturating_sub(prev.path.sent_packets);
+        sample.lost_packets += stats
+            .path
+            .lost_packets
+            .saturating_sub(prev.path.lost_packets);
+        sample.congestion_events += stats
+            .path
+            .congestion_events
+            .saturating_sub(prev.path.congestion_events);
+    }
+
+    sample.rtt /= paths.len() as u32;
+    sample
+}
+
+fn open(path: &Path, format: TelemetryFormat) -> Result<BufWriter<File>, IoError> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut file = BufWriter::new(file);
+
+    if empty && format == TelemetryFormat::Csv {
+        file.write_all(CSV_HEADER.as_bytes())?;
+        file.flush()?;
+    }
+
+    Ok(file)
+}
+
+fn write(
+    file: &mut BufWriter<File>,
+    format: TelemetryFormat,
+    samples: &HashMap<String, Sample>,
+) -> Result<(), IoError> {
+    let mut servers = samples.keys().collect::<Vec<_>>();
+    servers.sort();
+
+    for server in servers {
+        let sample = &samples[server];
+        let time = humantime::format_rfc3339_millis(sample.time);
+        let rtt = sample.rtt.as_secs_f64() * 1000.0;
+
+        match format {
+            TelemetryFormat::Json => {
+                let line = json!({
+                    "time": time.to_string(),
+                    "server": server,
+                    "connections": sample.connections,
+                    "rtt": rtt,
+                    "cwnd": sample.cwnd,
+                    "sentPackets": sample.sent_packets,
+                    "lostPackets": sample.lost_packets,
+                    "loss": sample.loss(),
+                    "congestionEvents": sample.congestion_events,
+                });
+
+                serde_json::to_writer(&mut *file, &line)?;
+                file.write_all(b"\n")?;
+            }
+            TelemetryFormat::Csv => writeln!(
+                file,
+                "{time},{server},{},{rtt:.3},{},{},{},{:.4},{}",
+                sample.connections,
+                sample.cwnd,
+                sample.sent_packets,
+                sample.lost_packets,
+                sample.loss(),
+                sample.congestion_events,
+            )?,
+        }
+    }
+
+    file.flush()
+}
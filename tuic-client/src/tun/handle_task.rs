@@ -0,0 +1,308 @@
+use super::{sniff, Tun, UDP_SESSIONS};
+use crate::{
+    connection::{self, Connection as TuicConnection, ERROR_CODE},
+    controller::{
+        self,
+        tracker::{Counted, TrackedGuard},
+    },
+    dns::Server as DnsServer,
+    error::Error,
+    protect,
+    router::{Outbound, Router},
+};
+use bytes::Bytes;
+use ipstack::stream::{IpStackTcpStream, IpStackUdpStream};
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::SocketAddr,
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{self, UdpSocket},
+    sync::mpsc,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address as TuicAddress;
+
+impl Tun {
+    pub(super) async fn handle_tcp(mut stream: IpStackTcpStream) {
+        let src_addr = stream.local_addr();
+        let target_addr =
+            DnsServer::restore_fake_ip(TuicAddress::SocketAddress(stream.peer_addr()));
+
+        log::info!("[tun] [{src_addr}] [tcp] {target_addr}");
+
+        // the sniffed domain is only used for routing, while the stream still goes to the original IP address
+        let (sniffed, route_addr) = match target_addr {
+            TuicAddress::SocketAddress(addr) if Router::sniff() => {
+                match sniff::sniff(&mut stream).await {
+                    (data, Some(domain)) => {
+                        log::debug!("[tun] [{src_addr}] [tcp] [{target_addr}] sniffed {domain}");
+
+                        // an invalid sniffed domain is ignored, routing by the IP address
+                        match TuicAddress::domain(&domain, addr.port()) {
+                            Ok(route_addr) => (data, route_addr),
+                            Err(_) => (data, target_addr.clone()),
+                        }
+                    }
+                    (data, None) => (data, target_addr.clone()),
+                }
+            }
+            _ => (Vec::new(), target_addr.clone()),
+        };
+
+        let rule = Router::matched_rule(&route_addr, None);
+        let outbound = rule
+            .as_ref()
+            .map_or(Router::default_outbound(), |rule| rule.outbound);
+
+        if let Outbound::Block = outbound {
+            log::info!("[tun] [{src_addr}] [tcp] [{target_addr}] blocked by router");
+            return;
+        }
+
+        let guard = TrackedGuard::new(
+            "tcp",
+            src_addr,
+            route_addr,
+            String::from(controller::outbound_name(outbound)),
+            rule.as_ref()
+                .map_or(String::from("Match"), |rule| rule.matcher.to_string()),
+        );
+
+        let mut stream = Counted::new(stream, guard.tracked().clone());
+        guard.tracked().add_upload(sniffed.len());
+
+        let res = match outbound {
+            Outbound::Proxy => {
+                let relay = match TuicConnection::get_for_connect(&target_addr).await {
+                    Ok(conn) => {
+                        guard.tracked().set_server(conn.server());
+                        conn.connect(target_addr.clone(), rule.as_deref()).await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                let mut relay = match relay {
+                    Ok(relay) => relay.compat(),
+                    Err(err) => {
+                        log::warn!("[tun] [{src_addr}] [tcp] [{target_addr}] unable to relay TCP stream: {err}");
+                        return;
+                    }
+                };
+
+                let res = tokio::select! {
+                    res = async {
+                        relay.write_all(&sniffed).await?;
+                        io::copy_bidirectional(&mut stream, &mut relay).await
+                    } => Some(res),
+                    _ = guard.tracked().closed() => None,
+                };
+
+                if !matches!(res, Some(Ok(_))) {
+                    let _ = relay.get_mut().reset(ERROR_CODE);
+                }
+
+                res
+            }
+            Outbound::Direct => {
+                log::info!("[tun] [{src_addr}] [tcp] [{target_addr}] [direct]");
+
+                let mut remote = match protect::connect_direct(&target_addr).await {
+                    Ok(remote) => remote,
+                    Err(err) => {
+                        log::warn!("[tun] [{src_addr}] [tcp] [{target_addr}] [direct] unable to connect: {err}");
+                        return;
+                    }
+                };
+
+                let res = tokio::select! {
+                    res = async {
+                        remote.write_all(&sniffed).await?;
+                        io::copy_bidirectional(&mut stream, &mut remote).await
+                    } => Some(res),
+                    _ = guard.tracked().closed() => None,
+                };
+
+                let _ = remote.shutdown().await;
+                res
+            }
+            Outbound::Block => unreachable!(),
+        };
+
+        let _ = stream.shutdown().await;
+
+        match res {
+            Some(Ok(_)) => {}
+            // the stack aborts the stream once the app closes it
+            Some(Err(err)) if err.kind() == ErrorKind::ConnectionAborted => {
+                log::debug!("[tun] [{src_addr}] [tcp] [{target_addr}] closed by app")
+            }
+            Some(Err(err)) => {
+                log::warn!(
+                    "[tun] [{src_addr}] [tcp] [{target_addr}] TCP stream relaying error: {err}"
+                )
+            }
+            None => log::info!("[tun] [{src_addr}] [tcp] [{target_addr}] closed by controller"),
+        }
+    }
+
+    /// Relays a UDP flow, until it stays idle for the UDP timeout of the stack
+    pub(super) async fn handle_udp(stream: IpStackUdpStream, max_pkt_size: usize) {
+        let src_addr = stream.local_addr();
+        let target_addr =
+            DnsServer::restore_fake_ip(TuicAddress::SocketAddress(stream.peer_addr()));
+
+        let rule = Router::matched_rule(&target_addr, None);
+        let outbound = rule
+            .as_ref()
+            .map_or(Router::default_outbound(), |rule| rule.outbound);
+
+        if let Outbound::Block = outbound {
+            log::debug!("[tun] [{src_addr}] [udp] [{target_addr}] blocked by router");
+            return;
+        }
+
+        let guard = TrackedGuard::new(
+            "udp",
+            src_addr,
+            target_addr.clone(),
+            String::from(controller::outbound_name(outbound)),
+            rule.as_ref()
+                .map_or(String::from("Match"), |rule| rule.matcher.to_string()),
+        );
+
+        let tracked = guard.tracked().clone();
+        let (mut local_rx, mut local_tx) = io::split(stream);
+        let mut buf = vec![0; max_pkt_size];
+
+        let res = match outbound {
+            Outbound::Proxy => {
+                let assoc_id = connection::next_assoc_id();
+                let udp_relay_mode = rule.as_ref().and_then(|rule| rule.udp_relay_mode);
+                log::info!("[tun] [{src_addr}] [udp] [{assoc_id:#06x}] {target_addr}");
+
+                let (tx, mut rx) = mpsc::channel(64);
+                UDP_SESSIONS.lock().insert(assoc_id, tx);
+
+                let relay = async {
+                    let target_addr =
+                        TuicConnection::resolve_target(target_addr.clone(), rule.as_deref())
+                            .await?;
+
+                    loop {
+                        tokio::select! {
+                            res = local_rx.read(&mut buf) => {
+                                let n = res?;
+
+                                // the flow is closed by the stack
+                                if n == 0 {
+                                    break;
+                                }
+
+                                tracked.add_upload(n);
+
+                                let conn = TuicConnection::get_for_packet(assoc_id).await?;
+                                tracked.set_server(conn.server());
+
+                                let pkt = Bytes::copy_from_slice(&buf[..n]);
+                                conn.packet(pkt, target_addr.clone(), assoc_id, udp_relay_mode).await?;
+                            }
+                            Some(pkt) = rx.recv() => {
+                                tracked.add_download(pkt.len());
+                                local_tx.write_all(&pkt).await?;
+                            }
+                        }
+                    }
+
+                    Ok::<_, Error>(())
+                };
+
+                let res = tokio::select! {
+                    res = relay => Some(res),
+                    _ = guard.tracked().closed() => None,
+                };
+
+                UDP_SESSIONS.lock().remove(&assoc_id);
+
+                match TuicConnection::get_for_dissociate(assoc_id).await {
+                    Ok(Some(conn)) => {
+                        let _ = conn.dissociate(assoc_id).await;
+                    }
+                    Ok(None) => {}
+                    Err(err) => log::warn!("[tun] [{src_addr}] [udp] [{assoc_id:#06x}] failed stopping UDP relaying session: {err}"),
+                }
+
+                res
+            }
+            Outbound::Direct => {
+                log::info!("[tun] [{src_addr}] [udp] [{target_addr}] [direct]");
+
+                let relay = async {
+                    let socket = protect::bind_direct_udp()?;
+                    Self::connect_udp(&socket, &target_addr).await?;
+                    let mut remote_buf = vec![0; max_pkt_size];
+
+                    loop {
+                        tokio::select! {
+                            res = local_rx.read(&mut buf) => {
+                                let n = res?;
+
+                                if n == 0 {
+                                    break;
+                                }
+
+                                tracked.add_upload(n);
+                                socket.send(&buf[..n]).await?;
+                            }
+                            res = socket.recv(&mut remote_buf) => {
+                                let n = res?;
+                                tracked.add_download(n);
+                                local_tx.write_all(&remote_buf[..n]).await?;
+                            }
+                        }
+                    }
+
+                    Ok::<_, Error>(())
+                };
+
+                tokio::select! {
+                    res = relay => Some(res),
+                    _ = guard.tracked().closed() => None,
+                }
+            }
+            Outbound::Block => unreachable!(),
+        };
+
+        match res {
+            Some(Err(Error::Io(err))) if err.kind() == ErrorKind::TimedOut => {
+                log::debug!("[tun] [{src_addr}] [udp] [{target_addr}] flow timed out")
+            }
+            Some(Err(err)) => {
+                log::warn!("[tun] [{src_addr}] [udp] [{target_addr}] UDP relaying error: {err}")
+            }
+            Some(Ok(())) => {}
+            None => log::info!("[tun] [{src_addr}] [udp] [{target_addr}] closed by controller"),
+        }
+    }
+
+    async fn connect_udp(socket: &UdpSocket, addr: &TuicAddress) -> Result<(), IoError> {
+        let addr = match addr {
+            TuicAddress::DomainAddress(domain, port) => net::lookup_host((domain.as_str(), *port))
+                .await?
+                .next()
+                .ok_or_else(|| IoError::new(ErrorKind::NotFound, "no address resolved"))?,
+            TuicAddress::SocketAddress(addr) => *addr,
+            TuicAddress::None => unreachable!(),
+        };
+
+        let addr = match (socket.local_addr()?, addr) {
+            (SocketAddr::V6(_), SocketAddr::V4(addr)) => {
+                (addr.ip().to_ipv6_mapped(), addr.port()).into()
+            }
+            _ => addr,
+        };
+
+        socket.connect(addr).await
+    }
+}
@@ -0,0 +1,110 @@
+use self::device::Device;
+use crate::error::Error;
+use bytes::Bytes;
+use ipstack::{stream::IpStackStream, IpStack, IpStackConfig};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{collections::HashMap, os::fd::RawFd};
+use tokio::sync::{mpsc::Sender, Notify};
+
+mod device;
+mod handle_task;
+mod sniff;
+
+/// Senders of packets received from the relay to the UDP flows of the TUN device, by association ID
+pub static UDP_SESSIONS: Lazy<Mutex<HashMap<u16, Sender<Bytes>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static DEVICE: Mutex<Option<(Device, u16)>> = Mutex::new(None);
+static RESTART: Notify = Notify::const_new();
+
+/// Inbound of IP packets from a TUN device owned by the app embedding the client
+///
+/// TCP connections and UDP flows are terminated by a userspace TCP/IP stack, then routed and relayed the same as the ones from the socks5 server. Each UDP flow is relayed as a UDP association.
+pub struct Tun;
+
+impl Tun {
+    /// Sets the TUN device to read packets from, replacing the current one, or removes it with `None`
+    ///
+    /// The file descriptor is duplicated, so the caller keeps the ownership of it. Must be called within a Tokio runtime.
+    pub fn set_fd(fd: Option<(RawFd, u16)>) -> Result<(), Error> {
+        let device = fd
+            .map(|(fd, mtu)| {
+                Device::new(fd)
+                    .map(|device| (device, mtu))
+                    .map_err(|err| Error::Socket("failed to set up TUN device", err))
+            })
+            .transpose()?;
+
+        *DEVICE.lock() = device;
+        RESTART.notify_waiters();
+
+        Ok(())
+    }
+
+    pub async fn start() {
+        loop {
+            let restart = RESTART.notified();
+            tokio::pin!(restart);
+            restart.as_mut().enable();
+
+            let Some((device, mtu)) = DEVICE.lock().take() else {
+                restart.await;
+                continue;
+            };
+
+            let mut cfg = IpStackConfig::default();
+            cfg.mtu(mtu);
+
+            // utun devices prefix each packet with the address family
+            cfg.packet_information(cfg!(any(target_os = "macos", target_os = "ios")));
+
+            let mut stack = IpStack::new(cfg, device);
+
+            log::warn!("[tun] started, MTU {mtu}");
+
+            // serve until the device is replaced or removed
+            tokio::select! {
+                _ = Self::serve(&mut stack, mtu) => {}
+                _ = restart => {}
+            }
+
+            stack.handle.abort();
+            log::warn!("[tun] stopped");
+        }
+    }
+
+    async fn serve(stack: &mut IpStack, mtu: u16) {
+        loop {
+            match stack.accept().await {
+                Ok(IpStackStream::Tcp(stream)) => {
+                    tokio::spawn(Self::handle_tcp(stream));
+                }
+                Ok(IpStackStream::Udp(stream)) => {
+                    tokio::spawn(Self::handle_udp(stream, mtu as usize));
+                }
+                Ok(IpStackStream::UnknownTransport(pkt)) => {
+                    log::debug!(
+                        "[tun] [{src_addr}] dropped packet of unsupported protocol {proto:?} to {dst_addr}",
+                        src_addr = pkt.src_addr(),
+                        dst_addr = pkt.dst_addr(),
+                        proto = pkt.ip_protocol(),
+                    );
+                }
+                Ok(IpStackStream::UnknownNetwork(_)) => {
+                    log::debug!("[tun] dropped non-IP packet");
+                }
+                Err(err) => {
+                    log::warn!("[tun] stack stopped: {err}");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Stops reading packets from the TUN device, releasing it
+    pub fn stop() {
+        *DEVICE.lock() = None;
+        RESTART.notify_waiters();
+    }
+}
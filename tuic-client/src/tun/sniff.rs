@@ -0,0 +1,230 @@
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    time::{self, Instant},
+};
+
+/// How long to wait for the app to send enough data for sniffing, as server-first protocols send nothing
+const SNIFF_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// The maximum data read for sniffing, enough for a TLS record
+const MAX_LEN: usize = 5 + 16384;
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"HEAD ",
+    b"PUT ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+
+enum Sniffed {
+    Domain(String),
+    Incomplete,
+    Unknown,
+}
+
+/// Reads the first data of a TCP stream sent by the app, looking for the domain in a TLS ClientHello (SNI) or an HTTP request (Host)
+///
+/// Returns the data read, which must be relayed before the rest of the stream, and the domain if found.
+pub(super) async fn sniff<S: AsyncRead + Unpin>(stream: &mut S) -> (Vec<u8>, Option<String>) {
+    let deadline = Instant::now() + SNIFF_TIMEOUT;
+    let mut buf = Vec::new();
+
+    while buf.len() < MAX_LEN {
+        let mut chunk = [0; 4096];
+
+        let n = match time::timeout_at(deadline, stream.read(&mut chunk)).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ => break,
+        };
+
+        buf.extend_from_slice(&chunk[..n]);
+
+        match parse(&buf) {
+            Sniffed::Domain(domain) => return (buf, Some(domain)),
+            Sniffed::Incomplete => {}
+            Sniffed::Unknown => break,
+        }
+    }
+
+    (buf, None)
+}
+
+fn parse(buf: &[u8]) -> Sniffed {
+    match buf.first() {
+        Some(0x16) => parse_tls(buf),
+        Some(_) => parse_http(buf),
+        None => Sniffed::Incomplete,
+    }
+}
+
+/// Parses the SNI extension of a TLS ClientHello, which is assumed to fit in the first record
+fn parse_tls(buf: &[u8]) -> Sniffed {
+    if buf.len() < 5 {
+        return Sniffed::Incomplete;
+    }
+
+    if buf[1] != 0x03 {
+        return Sniffed::Unknown;
+    }
+
+    let record_len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+
+    if buf.len() < 5 + record_len {
+        return Sniffed::Incomplete;
+    }
+
+    let mut r = Reader(&buf[5..5 + record_len]);
+
+    parse_client_hello(&mut r).map_or(Sniffed::Unknown, Sniffed::Domain)
+}
+
+fn parse_client_hello(r: &mut Reader) -> Option<String> {
+    // handshake type ClientHello
+    if r.u8()? != 0x01 {
+        return None;
+    }
+
+    let hello_len = r.u24()?;
+    let mut hello = Reader(r.take(hello_len)?);
+
+    // legacy version and random
+    hello.take(2 + 32)?;
+
+    let session_id_len = hello.u8()? as usize;
+    hello.take(session_id_len)?;
+    let cipher_suites_len = hello.u16()? as usize;
+    hello.take(cipher_suites_len)?;
+    let compression_methods_len = hello.u8()? as usize;
+    hello.take(compression_methods_len)?;
+
+    let extensions_len = hello.u16()? as usize;
+    let mut exts = Reader(hello.take(extensions_len)?);
+
+    while !exts.0.is_empty() {
+        let ext_type = exts.u16()?;
+        let ext_len = exts.u16()? as usize;
+        let mut ext = Reader(exts.take(ext_len)?);
+
+        // server_name
+        if ext_type != 0x0000 {
+            continue;
+        }
+
+        let list_len = ext.u16()? as usize;
+        let mut list = Reader(ext.take(list_len)?);
+
+        while !list.0.is_empty() {
+            let name_type = list.u8()?;
+            let name_len = list.u16()? as usize;
+            let name = list.take(name_len)?;
+
+            // host_name
+            if name_type == 0x00 {
+                return valid_domain(name);
+            }
+        }
+
+        return None;
+    }
+
+    None
+}
+
+/// Parses the Host header of an HTTP/1.x request
+fn parse_http(buf: &[u8]) -> Sniffed {
+    let is_method_prefix = HTTP_METHODS.iter().any(|method| {
+        let len = method.len().min(buf.len());
+        method[..len] == buf[..len]
+    });
+
+    if !is_method_prefix {
+        return Sniffed::Unknown;
+    }
+
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Sniffed::Incomplete;
+    };
+
+    let host = buf[..end]
+        .split(|b| *b == b'\n')
+        .skip(1)
+        .filter_map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let colon = line.iter().position(|b| *b == b':')?;
+            let (name, value) = line.split_at(colon);
+            name.eq_ignore_ascii_case(b"host")
+                .then(|| trim(&value[1..]))
+        })
+        .next();
+
+    let Some(host) = host else {
+        return Sniffed::Unknown;
+    };
+
+    // strip the port, leaving IPv6 literals in brackets to be rejected below
+    let host = match host.iter().rposition(|b| *b == b':') {
+        Some(pos) if !host.contains(&b']') => &host[..pos],
+        _ => host,
+    };
+
+    valid_domain(host).map_or(Sniffed::Unknown, Sniffed::Domain)
+}
+
+/// Accepts only domain names, not IP addresses, as an IP address gives nothing new for routing
+fn valid_domain(name: &[u8]) -> Option<String> {
+    let name = std::str::from_utf8(name).ok()?.trim_end_matches('.');
+
+    let is_valid = !name.is_empty()
+        && name.len() <= 253
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+        && name.parse::<std::net::IpAddr>().is_err();
+
+    is_valid.then(|| name.to_ascii_lowercase())
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = s {
+        s = rest;
+    }
+
+    while let [rest @ .., b' ' | b'\t'] = s {
+        s = rest;
+    }
+
+    s
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|b| (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+}
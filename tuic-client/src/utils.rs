@@ -2,19 +2,25 @@ use crate::error::Error;
 use rustls::{Certificate, RootCertStore};
 use rustls_pemfile::Item;
 use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
     fs::{self, File},
     io::BufReader,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
     str::FromStr,
 };
 use tokio::net;
+use tuic::Address;
+
+pub use tuic_config::percent_decode;
 
 pub fn load_certs(paths: Vec<PathBuf>, disable_native: bool) -> Result<RootCertStore, Error> {
     let mut certs = RootCertStore::empty();
 
     for path in &paths {
-        let mut file = BufReader::new(File::open(path)?);
+        let file =
+            File::open(path).map_err(|err| Error::LoadCert(path.display().to_string(), err))?;
+        let mut file = BufReader::new(file);
 
         while let Ok(Some(item)) = rustls_pemfile::read_one(&mut file) {
             if let Item::X509Certificate(cert) = item {
@@ -25,7 +31,9 @@ pub fn load_certs(paths: Vec<PathBuf>, disable_native: bool) -> Result<RootCertS
 
     if certs.is_empty() {
         for path in &paths {
-            certs.add(&Certificate(fs::read(path)?))?;
+            let cert =
+                fs::read(path).map_err(|err| Error::LoadCert(path.display().to_string(), err))?;
+            certs.add(&Certificate(cert))?;
         }
     }
 
@@ -42,15 +50,31 @@ pub struct ServerAddr {
     domain: String,
     port: u16,
     ip: Option<IpAddr>,
+    sni: Option<String>,
 }
 
 impl ServerAddr {
-    pub fn new(domain: String, port: u16, ip: Option<IpAddr>) -> Self {
-        Self { domain, port, ip }
+    pub fn new(domain: String, port: u16, ip: Option<IpAddr>, sni: Option<String>) -> Self {
+        Self {
+            domain,
+            port,
+            ip,
+            sni,
+        }
     }
 
+    /// Returns the name for TLS SNI and certificate verification, which is the domain unless overridden
     pub fn server_name(&self) -> &str {
-        &self.domain
+        self.sni.as_deref().unwrap_or(&self.domain)
+    }
+
+    /// Whether the target is this server by its domain or configured IP address, regardless of the port
+    pub fn matches(&self, addr: &Address) -> bool {
+        match addr {
+            Address::DomainAddress(domain, _) => domain.eq_ignore_ascii_case(&self.domain),
+            Address::SocketAddress(addr) => self.ip == Some(addr.ip()),
+            Address::None => false,
+        }
     }
 
     pub async fn resolve(&self) -> Result<impl Iterator<Item = SocketAddr>, Error> {
@@ -65,12 +89,27 @@ impl ServerAddr {
     }
 }
 
+impl Display for ServerAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}:{}", self.domain, self.port)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum UdpRelayMode {
     Native,
     Quic,
 }
 
+impl Display for UdpRelayMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Native => write!(f, "native"),
+            Self::Quic => write!(f, "quic"),
+        }
+    }
+}
+
 impl FromStr for UdpRelayMode {
     type Err = &'static str;
 
@@ -89,6 +128,18 @@ pub enum CongestionControl {
     Cubic,
     NewReno,
     Bbr,
+    Brutal,
+}
+
+impl Display for CongestionControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Cubic => write!(f, "cubic"),
+            Self::NewReno => write!(f, "new_reno"),
+            Self::Bbr => write!(f, "bbr"),
+            Self::Brutal => write!(f, "brutal"),
+        }
+    }
 }
 
 impl FromStr for CongestionControl {
@@ -101,8 +152,351 @@ impl FromStr for CongestionControl {
             Ok(Self::NewReno)
         } else if s.eq_ignore_ascii_case("bbr") {
             Ok(Self::Bbr)
+        } else if s.eq_ignore_ascii_case("brutal") {
+            Ok(Self::Brutal)
         } else {
             Err("invalid congestion control")
         }
     }
 }
+
+#[derive(Clone, Copy)]
+pub enum Balance {
+    Failover,
+    RoundRobin,
+    LeastRtt,
+    ConsistentHash,
+    WeightedRandom,
+}
+
+impl FromStr for Balance {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("failover") {
+            Ok(Self::Failover)
+        } else if s.eq_ignore_ascii_case("round_robin") {
+            Ok(Self::RoundRobin)
+        } else if s.eq_ignore_ascii_case("least_rtt") {
+            Ok(Self::LeastRtt)
+        } else if s.eq_ignore_ascii_case("consistent_hash") {
+            Ok(Self::ConsistentHash)
+        } else if s.eq_ignore_ascii_case("weighted_random") {
+            Ok(Self::WeightedRandom)
+        } else {
+            Err("invalid balancing strategy")
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KeepWarmStrategy {
+    Heartbeat,
+    Rehandshake,
+}
+
+impl FromStr for KeepWarmStrategy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("heartbeat") {
+            Ok(Self::Heartbeat)
+        } else if s.eq_ignore_ascii_case("rehandshake") {
+            Ok(Self::Rehandshake)
+        } else {
+            Err("invalid keep-warm strategy")
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for TelemetryFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("json") {
+            Ok(Self::Json)
+        } else if s.eq_ignore_ascii_case("csv") {
+            Ok(Self::Csv)
+        } else {
+            Err("invalid telemetry log format")
+        }
+    }
+}
+
+/// The protocols served on a local listener
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InboundProtocol {
+    /// SOCKS5, SOCKS4 and HTTP, told apart by the first byte from the client
+    Mixed,
+    Socks,
+    Http,
+}
+
+impl InboundProtocol {
+    pub fn serves_socks(self) -> bool {
+        matches!(self, Self::Mixed | Self::Socks)
+    }
+
+    pub fn serves_http(self) -> bool {
+        matches!(self, Self::Mixed | Self::Http)
+    }
+}
+
+impl Display for InboundProtocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Mixed => write!(f, "mixed"),
+            Self::Socks => write!(f, "socks"),
+            Self::Http => write!(f, "http"),
+        }
+    }
+}
+
+impl FromStr for InboundProtocol {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("mixed") {
+            Ok(Self::Mixed)
+        } else if s.eq_ignore_ascii_case("socks") || s.eq_ignore_ascii_case("socks5") {
+            Ok(Self::Socks)
+        } else if s.eq_ignore_ascii_case("http") {
+            Ok(Self::Http)
+        } else {
+            Err("invalid inbound protocol")
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Returns the first address of the network
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) & self.mask())
+    }
+
+    /// Returns the number of addresses in the network
+    pub fn size(&self) -> u64 {
+        1 << (32 - self.prefix_len)
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & self.mask() == u32::from(self.network())
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - u32::from(self.prefix_len))
+            .unwrap_or(0)
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = s
+            .split_once('/')
+            .ok_or("invalid IPv4 CIDR, expecting `ADDRESS/PREFIX_LENGTH`")?;
+
+        let addr = addr.parse().map_err(|_| "invalid IPv4 CIDR address")?;
+        let prefix_len = prefix_len
+            .parse()
+            .ok()
+            .filter(|len| *len <= 32)
+            .ok_or("invalid IPv4 CIDR prefix length")?;
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// An IPv4 or IPv6 network. A bare address is parsed as a single-address network
+#[derive(Clone, Copy)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(addr)) => addr
+                .to_ipv4_mapped()
+                .map_or(false, |addr| self.contains(IpAddr::V4(addr))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| "invalid IP CIDR address")?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or("invalid IP CIDR prefix length")?,
+            None => max_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl Display for IpCidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// An upstream SOCKS5 proxy in the form of `socks5://[USERNAME:PASSWORD@]HOST:PORT`
+///
+/// The proxy must support UDP ASSOCIATE, as QUIC runs over UDP.
+#[derive(Clone)]
+pub struct UpstreamProxy {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<Vec<u8>>,
+    pub password: Option<Vec<u8>>,
+}
+
+impl FromStr for UpstreamProxy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or("invalid upstream proxy, expecting `socks5://HOST:PORT`")?;
+
+        if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") {
+            return Err("HTTP proxies cannot relay UDP, which QUIC requires, use a SOCKS5 proxy with UDP ASSOCIATE support");
+        }
+
+        if !scheme.eq_ignore_ascii_case("socks5") && !scheme.eq_ignore_ascii_case("socks5h") {
+            return Err("invalid upstream proxy scheme, expecting `socks5`");
+        }
+
+        let rest = rest.trim_end_matches('/');
+
+        let (user_info, host_port) = match rest.rsplit_once('@') {
+            Some((user_info, host_port)) => (Some(user_info), host_port),
+            None => (None, rest),
+        };
+
+        let (username, password) = match user_info {
+            Some(user_info) => {
+                let (username, password) = user_info
+                    .split_once(':')
+                    .ok_or("invalid upstream proxy, expecting `USERNAME:PASSWORD`")?;
+                (
+                    Some(percent_decode(username).into_bytes()),
+                    Some(percent_decode(password).into_bytes()),
+                )
+            }
+            None => (None, None),
+        };
+
+        let (host, port) = host_port
+            .rsplit_once(':')
+            .ok_or("invalid upstream proxy, expecting `HOST:PORT`")?;
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = port.parse().map_err(|_| "invalid upstream proxy port")?;
+
+        if host.is_empty() {
+            return Err("invalid upstream proxy, empty host");
+        }
+
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            username,
+            password,
+        })
+    }
+}
+
+/// A WebSocket bridge in front of the server in the form of `ws://HOST[:PORT][/PATH]`, relaying QUIC datagrams over TCP
+///
+/// The WebSocket connection itself is not encrypted, which leaves TLS to a CDN or reverse proxy in front of the bridge. QUIC traffic inside is encrypted end-to-end anyway.
+#[derive(Clone)]
+pub struct WebSocketBridge {
+    pub host: String,
+    pub port: u16,
+    pub url: String,
+}
+
+impl FromStr for WebSocketBridge {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or("invalid bridge, expecting `ws://HOST[:PORT][/PATH]`")?;
+
+        if scheme.eq_ignore_ascii_case("wss") {
+            return Err("`wss` bridges are not supported, use `ws` with TLS terminated by a CDN or reverse proxy in front of the bridge");
+        }
+
+        if !scheme.eq_ignore_ascii_case("ws") {
+            return Err("invalid bridge scheme, expecting `ws`");
+        }
+
+        let (host_port, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| "invalid bridge port")?)
+            }
+            _ => (host_port, 80),
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        if host.is_empty() {
+            return Err("invalid bridge, empty host");
+        }
+
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            url: format!("ws://{host_port}{path}"),
+        })
+    }
+}
@@ -0,0 +1,242 @@
+//! Loading the JSON configs of the TUIC server and client
+//!
+//! [`from_str()`] deserializes a config, reporting errors with the line, the column and the path of the offending field, along with an excerpt of the source. Unknown fields are not errors, but collected as warnings for the caller to log, so that a config written for a newer version still loads.
+//!
+//! Fields can be overridden from outside the config file with [`from_str_with()`], e.g. by environment variables and command line arguments parsed as an [`Override`].
+//!
+//! The [`share_link`] module parses and generates the `tuic://` share links of relay servers with [`ShareLink`].
+//!
+//! The [`units`] module parses sizes, bandwidths and durations with unit suffixes, e.g. `2GiB`, `10mbps` and `30s`, with `deserialize_*` functions for use with `#[serde(deserialize_with)]`.
+
+use serde::de::{DeserializeOwned, Deserializer, Error as DeError};
+use serde_json::{Error as JsonError, Value};
+use std::{
+    cell::RefCell,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+use thiserror::Error;
+
+pub mod overrides;
+pub mod share_link;
+pub mod units;
+
+pub use self::{
+    overrides::Override,
+    share_link::{percent_decode, percent_encode, ShareLink},
+    units::{
+        deserialize_bandwidth, deserialize_duration, deserialize_optional_bandwidth,
+        deserialize_optional_duration, deserialize_optional_size, deserialize_size,
+        parse_bandwidth, parse_duration, parse_size,
+    },
+};
+
+/// Prefixes the errors of [`from_value()`] with the path of the field, taken out by [`from_str()`] to replace the path of the part
+const NESTED_PATH: &str = "in `";
+
+thread_local! {
+    /// The unknown fields found by [`from_value()`] while a config is being loaded on the thread
+    static NESTED_WARNINGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// A config loaded with [`from_str()`]
+pub struct Loaded<T> {
+    pub config: T,
+    /// The unknown fields, which are ignored
+    pub warnings: Vec<String>,
+}
+
+/// An invalid config
+#[derive(Debug, Error)]
+pub struct Error {
+    line: usize,
+    column: usize,
+    path: Option<String>,
+    message: String,
+    excerpt: Option<String>,
+}
+
+impl Error {
+    fn at(path: String, message: String) -> Self {
+        Self {
+            line: 0,
+            column: 0,
+            path: Some(path),
+            message,
+            excerpt: None,
+        }
+    }
+
+    fn new(json: &str, err: JsonError, path: Option<String>) -> Self {
+        let (line, column) = (err.line(), err.column());
+
+        // the position is appended by `serde_json`, and reported separately here
+        let message = err.to_string();
+        let message = message
+            .strip_suffix(&format!(" at line {line} column {column}"))
+            .map_or(message.clone(), str::to_owned);
+
+        let (path, message) = match message
+            .strip_prefix(NESTED_PATH)
+            .and_then(|nested| nested.split_once("`: "))
+        {
+            Some((nested, message)) => (Some(nested.to_owned()), message.to_owned()),
+            None => (path, message),
+        };
+
+        let excerpt = (line > 0)
+            .then(|| json.lines().nth(line - 1))
+            .flatten()
+            .map(|src| excerpt(src, line, column));
+
+        Self {
+            line,
+            column,
+            path,
+            message,
+            excerpt,
+        }
+    }
+
+    /// The line of the error, starting from 1, or 0 if it is not caused by the input
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The path of the offending field, e.g. `relay.bandwidth.up`, if the error is inside the root object
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The description of the error, without the position
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "invalid config")?;
+
+        if self.line > 0 {
+            write!(f, " at line {} column {}", self.line, self.column)?;
+        }
+
+        if let Some(path) = &self.path {
+            write!(f, ", in `{path}`")?;
+        }
+
+        write!(f, ": {}", self.message)?;
+
+        if let Some(excerpt) = &self.excerpt {
+            write!(f, "\n{excerpt}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes the config from the JSON string
+pub fn from_str<T: DeserializeOwned>(json: &str) -> Result<Loaded<T>, Error> {
+    from_str_with(json, &[])
+}
+
+/// Deserializes the config from the JSON string, with the fields set by the overrides in order
+///
+/// Errors in the fields are reported without their position if anything is overridden, but with the override setting the offending field.
+pub fn from_str_with<T: DeserializeOwned>(
+    json: &str,
+    overrides: &[Override],
+) -> Result<Loaded<T>, Error> {
+    let mut warnings = Vec::new();
+
+    if overrides.is_empty() {
+        let mut de = serde_json::Deserializer::from_str(json);
+
+        return deserialize(&mut de, &mut warnings)
+            .and_then(|config| de.end().map(|()| config).map_err(|err| (err, None)))
+            .map(|config| Loaded { config, warnings })
+            .map_err(|(err, path)| Error::new(json, err, path));
+    }
+
+    let mut value =
+        serde_json::from_str::<Value>(json).map_err(|err| Error::new(json, err, None))?;
+    overrides::apply(&mut value, overrides)?;
+
+    match deserialize(value, &mut warnings) {
+        Ok(config) => Ok(Loaded { config, warnings }),
+        Err((err, path)) => {
+            let mut err = Error::new(json, err, path);
+            overrides::blame(&mut err, overrides);
+            Err(err)
+        }
+    }
+}
+
+/// Deserializes with the unknown fields collected, returning the path of the offending field on error
+fn deserialize<'de, D, T>(
+    de: D,
+    warnings: &mut Vec<String>,
+) -> Result<T, (D::Error, Option<String>)>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let mut track = serde_path_to_error::Track::new();
+
+    let prev = NESTED_WARNINGS.with(|nested| nested.borrow_mut().replace(Vec::new()));
+
+    let res = serde_ignored::deserialize(
+        serde_path_to_error::Deserializer::new(de, &mut track),
+        |path| warnings.push(format!("unknown field `{path}` is ignored")),
+    );
+
+    let nested = NESTED_WARNINGS.with(|nested| std::mem::replace(&mut *nested.borrow_mut(), prev));
+    warnings.extend(nested.into_iter().flatten());
+
+    res.map_err(|err| {
+        let path = track.path().to_string();
+        (err, (path != ".").then_some(path))
+    })
+}
+
+/// Deserializes a part of the config, e.g. taken as a [`Value`] for accepting more than one form
+///
+/// The unknown fields are reported along with those of the config being loaded, prefixed with `path`. Errors are prefixed with the path of the offending field, as they have no position in the source.
+pub fn from_value<T: DeserializeOwned, E: DeError>(value: Value, path: &str) -> Result<T, E> {
+    let mut track = serde_path_to_error::Track::new();
+    let mut warnings = Vec::new();
+
+    let res = serde_ignored::deserialize(
+        serde_path_to_error::Deserializer::new(value, &mut track),
+        |field| warnings.push(format!("unknown field `{path}.{field}` is ignored")),
+    );
+
+    NESTED_WARNINGS.with(|nested| {
+        if let Some(nested) = &mut *nested.borrow_mut() {
+            nested.extend(warnings);
+        }
+    });
+
+    res.map_err(|err| match track.path().to_string().as_str() {
+        "." => E::custom(err),
+        field => E::custom(format!("{NESTED_PATH}{path}.{field}`: {err}")),
+    })
+}
+
+/// The source line with a caret under the column, keeping tabs for the caret to line up
+fn excerpt(src: &str, line: usize, column: usize) -> String {
+    let gutter = line.to_string();
+    let pad = " ".repeat(gutter.len());
+
+    let indent = src
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+
+    format!("{gutter} | {src}\n{pad} | {indent}^")
+}
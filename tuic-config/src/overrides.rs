@@ -0,0 +1,220 @@
+//! Overriding config fields from outside the config file
+//!
+//! A field is addressed by its path, with the keys of objects and the indexes of arrays separated by dots, e.g. `relay.bandwidth.up` or `relay.0.server`. As a command line argument, an override is `PATH=VALUE`. As an environment variable, the path is uppercased with the dots replaced by double underscores and prefixed with `TUIC_`, e.g. `TUIC_RELAY__BANDWIDTH__UP`.
+//!
+//! The value is parsed as JSON, e.g. `true`, `1500` or `["h3"]`, falling back to a string if it is not valid JSON. A field that is a string in the config is always set to a string, unless the value is quoted as a JSON string.
+
+use crate::Error;
+use serde_json::{Map, Value};
+use std::{
+    env,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// The prefix of the environment variables overriding config fields
+pub const ENV_PREFIX: &str = "TUIC_";
+
+/// A config field set by an environment variable or a command line argument
+#[derive(Clone, Debug)]
+pub struct Override {
+    path: Vec<String>,
+    value: String,
+    source: String,
+}
+
+impl Override {
+    /// Parses a command line argument in the form of `PATH=VALUE`
+    pub fn from_arg(arg: &str) -> Result<Self, String> {
+        let (path, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("invalid override `{arg}`, expecting `PATH=VALUE`"))?;
+
+        // `relay[0].server` is accepted as `relay.0.server`
+        let path = path.replace('[', ".").replace(']', "");
+
+        let path = path
+            .split('.')
+            .filter(|seg| !seg.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+
+        if path.is_empty() {
+            return Err(format!("invalid override `{arg}`, the path is empty"));
+        }
+
+        Ok(Self {
+            path,
+            value: value.to_owned(),
+            source: format!("`{arg}`"),
+        })
+    }
+
+    /// Parses an environment variable, returning `None` if the name does not start with [`ENV_PREFIX`]
+    pub fn from_env_var(name: &str, value: &str) -> Option<Self> {
+        let path = name
+            .strip_prefix(ENV_PREFIX)?
+            .split("__")
+            .filter(|seg| !seg.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>();
+
+        if path.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            path,
+            value: value.to_owned(),
+            source: format!("environment variable `{name}`"),
+        })
+    }
+
+    /// The overrides of the environment variables of the process, ordered by name
+    ///
+    /// Variables whose name or value is not valid UTF-8 are skipped.
+    pub fn from_env() -> Vec<Self> {
+        let mut vars = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect::<Vec<_>>();
+
+        vars.sort();
+
+        vars.iter()
+            .filter_map(|(name, value)| Self::from_env_var(name, value))
+            .collect()
+    }
+
+    /// Whether the field at the path, as reported in errors, is set by this override, or contains the field set
+    fn covers(&self, path: &str) -> bool {
+        fn is_within(path: &str, parent: &str) -> bool {
+            path.strip_prefix(parent).map_or(false, |rest| {
+                rest.is_empty() || rest.starts_with(['.', '['])
+            })
+        }
+
+        let own = self.to_string();
+        is_within(path, &own) || is_within(&own, path)
+    }
+
+    fn value(&self, current: Option<&Value>) -> Value {
+        let quoted = self.value.starts_with('"');
+
+        match current {
+            Some(Value::String(_)) if !quoted => Value::String(self.value.clone()),
+            _ => serde_json::from_str(&self.value)
+                .unwrap_or_else(|_| Value::String(self.value.clone())),
+        }
+    }
+}
+
+impl Display for Override {
+    /// The path in the format of errors, e.g. `relay[0].server`
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for (idx, seg) in self.path.iter().enumerate() {
+            if seg.parse::<usize>().is_ok() {
+                write!(f, "[{seg}]")?;
+            } else if idx == 0 {
+                write!(f, "{seg}")?;
+            } else {
+                write!(f, ".{seg}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets the fields of the config in order, so that later overrides take precedence
+///
+/// Objects missing on the path are created. An array index may be one past the end, appending an object to the array.
+pub fn apply(config: &mut Value, overrides: &[Override]) -> Result<(), Error> {
+    for ovr in overrides {
+        set(config, ovr)
+            .map_err(|msg| Error::at(ovr.to_string(), format!("{msg}, set by {}", ovr.source)))?;
+    }
+
+    Ok(())
+}
+
+fn set(config: &mut Value, ovr: &Override) -> Result<(), String> {
+    let (last, parents) = ovr.path.split_last().unwrap();
+    let mut value = config;
+
+    for seg in parents {
+        value = child(value, seg)?;
+    }
+
+    match value {
+        Value::Null => {
+            let mut obj = Map::new();
+            obj.insert(last.clone(), ovr.value(None));
+            *value = Value::Object(obj);
+        }
+        Value::Object(obj) => {
+            let new = ovr.value(obj.get(last));
+            obj.insert(last.clone(), new);
+        }
+        Value::Array(arr) => {
+            let idx = index(last, arr.len())?;
+            let new = ovr.value(arr.get(idx));
+
+            if idx == arr.len() {
+                arr.push(new);
+            } else {
+                arr[idx] = new;
+            }
+        }
+        _ => {
+            return Err(format!(
+                "cannot set `{last}` in a value that is not an object or an array"
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the value at the key or the index, creating an object if it is missing
+fn child<'a>(value: &'a mut Value, seg: &str) -> Result<&'a mut Value, String> {
+    if value.is_null() {
+        *value = Value::Object(Map::new());
+    }
+
+    match value {
+        Value::Object(obj) => Ok(obj
+            .entry(seg.to_owned())
+            .or_insert_with(|| Value::Object(Map::new()))),
+        Value::Array(arr) => {
+            let idx = index(seg, arr.len())?;
+
+            if idx == arr.len() {
+                arr.push(Value::Object(Map::new()));
+            }
+
+            Ok(&mut arr[idx])
+        }
+        _ => Err(format!(
+            "cannot look up `{seg}` in a value that is not an object or an array"
+        )),
+    }
+}
+
+fn index(seg: &str, len: usize) -> Result<usize, String> {
+    match seg.parse::<usize>() {
+        Ok(idx) if idx <= len => Ok(idx),
+        Ok(idx) => Err(format!("index {idx} is out of bounds of an array of {len}")),
+        Err(_) => Err(format!("`{seg}` is not an index of an array")),
+    }
+}
+
+/// Appends the source of the override setting the offending field to the error, as its position in the config file is lost
+pub(crate) fn blame(err: &mut Error, overrides: &[Override]) {
+    let Some(path) = &err.path else {
+        return;
+    };
+
+    if let Some(ovr) = overrides.iter().rev().find(|ovr| ovr.covers(path)) {
+        err.message = format!("{}, set by {}", err.message, ovr.source);
+    }
+}
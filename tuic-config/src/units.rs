@@ -0,0 +1,228 @@
+//! Parsing values with unit suffixes
+//!
+//! - Sizes are in bytes, with decimal (`KB`, `MB`, `GB`, `TB`) or binary (`KiB`, `MiB`, `GiB`, `TiB`) multiples, e.g. `1500`, `64KiB` or `1.5MB`
+//! - Bandwidths are in bytes per second, either in bits per second (`bps`, `kbps`, `mbps`, `gbps`) or as a size per second, e.g. `100mbps` or `12.5MB/s`
+//! - Durations are in the format of [`humantime`], e.g. `30s`, `500ms` or `1h 30m`
+//!
+//! Units are case-insensitive, and may be separated from the number by spaces. Sizes and bandwidths are also accepted as JSON numbers, in bytes and bytes per second.
+
+use humantime::Duration as HumanDuration;
+use serde::de::{Deserializer, Error as DeError, Unexpected, Visitor};
+use std::{
+    fmt::{Formatter, Result as FmtResult},
+    time::Duration,
+};
+
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("", 1),
+    ("b", 1),
+    ("k", 1000),
+    ("kb", 1000),
+    ("m", 1000 * 1000),
+    ("mb", 1000 * 1000),
+    ("g", 1000 * 1000 * 1000),
+    ("gb", 1000 * 1000 * 1000),
+    ("tb", 1000 * 1000 * 1000 * 1000),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("tib", 1 << 40),
+];
+
+/// Bits per second, as bytes per second times 8
+const BIT_RATE_UNITS: &[(&str, u64)] = &[
+    ("bps", 1),
+    ("kbps", 1000),
+    ("mbps", 1000 * 1000),
+    ("gbps", 1000 * 1000 * 1000),
+    ("tbps", 1000 * 1000 * 1000 * 1000),
+];
+
+/// Parses a size into bytes, e.g. `64KiB`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (num, unit) = split(s);
+
+    let mul = lookup(SIZE_UNITS, &unit).ok_or_else(|| {
+        format!("invalid size `{s}`, expecting bytes with an optional unit, e.g. `1500`, `64KiB` or `2GB`")
+    })?;
+
+    scale(num, mul, 1).ok_or_else(|| format!("invalid size `{s}`"))
+}
+
+/// Parses a bandwidth into bytes per second, e.g. `100mbps` or `12.5MB/s`
+pub fn parse_bandwidth(s: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!("invalid bandwidth `{s}`, expecting bytes per second, or with a unit, e.g. `100mbps` or `12.5MB/s`")
+    };
+
+    let (num, unit) = split(s);
+
+    let res = if let Some(unit) = unit.strip_suffix("/s") {
+        let mul = lookup(SIZE_UNITS, unit.trim_end()).ok_or_else(invalid)?;
+        scale(num, mul, 1)
+    } else if let Some(mul) = lookup(BIT_RATE_UNITS, &unit) {
+        scale(num, mul, 8)
+    } else if unit.is_empty() {
+        scale(num, 1, 1)
+    } else {
+        return Err(invalid());
+    };
+
+    res.ok_or_else(invalid)
+}
+
+/// Parses a duration, e.g. `30s`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    s.trim()
+        .parse::<HumanDuration>()
+        .map(Into::into)
+        .map_err(|err| format!("invalid duration `{s}`: {err}, expecting e.g. `30s` or `500ms`"))
+}
+
+/// Splits the number from the unit, which is lowercased
+fn split(s: &str) -> (&str, String) {
+    let s = s.trim();
+    let idx = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(idx);
+    (num, unit.trim_start().to_ascii_lowercase())
+}
+
+fn lookup(units: &[(&str, u64)], unit: &str) -> Option<u64> {
+    units
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|(_, mul)| *mul)
+}
+
+/// Multiplies the number by `mul / div`, rounding down, or `None` if it is invalid or overflows
+fn scale(num: &str, mul: u64, div: u64) -> Option<u64> {
+    match num.split_once('.') {
+        None => num.parse::<u64>().ok()?.checked_mul(mul).map(|n| n / div),
+        Some((int, frac)) => {
+            if int.is_empty() && frac.is_empty() {
+                return None;
+            }
+
+            let int = if int.is_empty() {
+                0
+            } else {
+                int.parse::<u64>().ok()?
+            };
+            let frac = frac.get(..frac.len().min(9))?;
+            let frac_num = if frac.is_empty() {
+                0
+            } else {
+                frac.parse::<u64>().ok()?
+            };
+            let frac_den = 10u64.pow(frac.len() as u32);
+
+            let whole = int.checked_mul(mul)?;
+            let part = u128::from(frac_num) * u128::from(mul) / u128::from(frac_den);
+            whole
+                .checked_add(u64::try_from(part).ok()?)
+                .map(|n| n / div)
+        }
+    }
+}
+
+/// Deserializes a size in bytes, from a number or a string with a unit
+pub fn deserialize_size<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: TryFrom<u64>,
+    D: Deserializer<'de>,
+{
+    let size = deserializer.deserialize_any(UnitVisitor::new("a size", parse_size))?;
+
+    T::try_from(size)
+        .map_err(|_| DeError::invalid_value(Unexpected::Unsigned(size), &"a size in range"))
+}
+
+pub fn deserialize_optional_size<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: TryFrom<u64>,
+    D: Deserializer<'de>,
+{
+    deserialize_size(deserializer).map(Some)
+}
+
+/// Deserializes a bandwidth in bytes per second, from a number or a string with a unit
+pub fn deserialize_bandwidth<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(UnitVisitor::new("a bandwidth", parse_bandwidth))
+}
+
+pub fn deserialize_optional_bandwidth<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_bandwidth(deserializer).map(Some)
+}
+
+/// Deserializes a duration from a string with units
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DurationVisitor;
+
+    impl<'de> Visitor<'de> for DurationVisitor {
+        type Value = Duration;
+
+        fn expecting(&self, f: &mut Formatter) -> FmtResult {
+            write!(f, "a duration with a unit, e.g. \"30s\"")
+        }
+
+        fn visit_str<E: DeError>(self, v: &str) -> Result<Duration, E> {
+            parse_duration(v).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_str(DurationVisitor)
+}
+
+pub fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+/// Accepts a non-negative integer as is, or a string parsed with the unit
+struct UnitVisitor<'a> {
+    name: &'a str,
+    parse: fn(&str) -> Result<u64, String>,
+}
+
+impl<'a> UnitVisitor<'a> {
+    fn new(name: &'a str, parse: fn(&str) -> Result<u64, String>) -> Self {
+        Self { name, parse }
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for UnitVisitor<'a> {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{} as a non-negative integer or a string with a unit",
+            self.name
+        )
+    }
+
+    fn visit_u64<E: DeError>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: DeError>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::invalid_value(Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: DeError>(self, v: &str) -> Result<u64, E> {
+        (self.parse)(v).map_err(E::custom)
+    }
+}
@@ -0,0 +1,367 @@
+//! The checks of a client under test, which connects to the suite acting as a TUIC server
+
+use crate::{
+    command::{self, Reassembly},
+    quic, Failure, Outcome, Verdict,
+};
+use bytes::Bytes;
+use quinn::{Connection, Endpoint, RecvStream, SendStream, VarInt};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::{self, Instant};
+use tuic::{Header, Packet, UnmarshalError};
+use uuid::Uuid;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What has been observed from the client under test
+#[derive(Default)]
+struct Observed {
+    authenticate: Option<Result<(), String>>,
+    connect: Option<Result<(), String>>,
+    packet: Option<Result<(), String>>,
+    heartbeat: bool,
+    malformed: Vec<String>,
+}
+
+impl Observed {
+    fn is_complete(&self) -> bool {
+        self.authenticate.is_some()
+            && self.connect.is_some()
+            && self.packet.is_some()
+            && self.heartbeat
+    }
+}
+
+pub struct ClientSuite {
+    ep: Endpoint,
+    uuid: Uuid,
+    password: Vec<u8>,
+    wait: Duration,
+}
+
+impl ClientSuite {
+    /// Listens for the client under test, returning the suite with the certificate in PEM for the client to trust
+    pub fn new(
+        listen: SocketAddr,
+        server_name: &str,
+        alpn: Vec<Vec<u8>>,
+        uuid: Uuid,
+        password: Vec<u8>,
+        wait: Duration,
+    ) -> io::Result<(Self, String)> {
+        let (ep, cert) = quic::server(listen, server_name, alpn)?;
+
+        Ok((
+            Self {
+                ep,
+                uuid,
+                password,
+                wait,
+            },
+            cert,
+        ))
+    }
+
+    /// Serves the first connection of the client under test until everything is observed, or the wait is over
+    pub async fn run(&self) -> Vec<(&'static str, Outcome)> {
+        let deadline = Instant::now() + self.wait;
+
+        let conn = match time::timeout_at(deadline, self.accept()).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(err)) => return vec![("client-connection", Err(err))],
+            Err(_) => {
+                return vec![(
+                    "client-connection",
+                    Err(Failure::new("no connection from the client under test")),
+                )]
+            }
+        };
+
+        let observed = Arc::new(Mutex::new(Observed::default()));
+        let session = Session {
+            conn: conn.clone(),
+            uuid: self.uuid,
+            password: self.password.clone(),
+            observed: observed.clone(),
+            reassembly: Arc::new(Mutex::new(Reassembly::default())),
+            next_pkt_id: Arc::new(AtomicU16::new(0)),
+        };
+
+        let serve = tokio::spawn(session.serve());
+
+        while Instant::now() < deadline && !observed.lock().unwrap().is_complete() {
+            if conn.close_reason().is_some() {
+                break;
+            }
+
+            time::sleep(POLL_INTERVAL).await;
+        }
+
+        serve.abort();
+        conn.close(VarInt::from_u32(0), &[]);
+
+        let observed = observed.lock().unwrap();
+
+        let not_observed = |what: &str| Ok(Verdict::Skip(format!("no {what} observed")));
+        let check = |res: &Option<Result<(), String>>, what: &str| match res {
+            Some(Ok(())) => Ok(Verdict::Pass(String::new())),
+            Some(Err(err)) => Err(Failure::new(err.clone())),
+            None => not_observed(what),
+        };
+
+        vec![
+            (
+                "client-authenticate",
+                match &observed.authenticate {
+                    None => Err(Failure::new("no Authenticate received")),
+                    res => check(res, "Authenticate"),
+                },
+            ),
+            (
+                "client-connect",
+                check(
+                    &observed.connect,
+                    "Connect, relay a TCP connection through the client",
+                ),
+            ),
+            (
+                "client-packet",
+                check(
+                    &observed.packet,
+                    "Packet, relay a UDP packet through the client",
+                ),
+            ),
+            (
+                "client-heartbeat",
+                if observed.heartbeat {
+                    Ok(Verdict::Pass(String::new()))
+                } else {
+                    not_observed("Heartbeat")
+                },
+            ),
+            (
+                "client-well-formed",
+                match observed.malformed.first() {
+                    None => Ok(Verdict::Pass(String::new())),
+                    Some(err) => Err(Failure::new(format!(
+                        "{} malformed commands, the first one: {err}",
+                        observed.malformed.len()
+                    ))),
+                },
+            ),
+        ]
+    }
+
+    async fn accept(&self) -> Result<Connection, Failure> {
+        let connecting = self
+            .ep
+            .accept()
+            .await
+            .ok_or_else(|| Failure::new("endpoint closed"))?;
+
+        Ok(connecting.await?)
+    }
+}
+
+#[derive(Clone)]
+struct Session {
+    conn: Connection,
+    uuid: Uuid,
+    password: Vec<u8>,
+    observed: Arc<Mutex<Observed>>,
+    reassembly: Arc<Mutex<Reassembly>>,
+    next_pkt_id: Arc<AtomicU16>,
+}
+
+impl Session {
+    async fn serve(self) {
+        loop {
+            let res = tokio::select! {
+                recv = self.conn.accept_uni() => recv.map(|recv| {
+                    tokio::spawn(self.clone().handle_uni(recv));
+                }),
+                stream = self.conn.accept_bi() => stream.map(|(send, recv)| {
+                    tokio::spawn(self.clone().handle_bi(send, recv));
+                }),
+                data = self.conn.read_datagram() => data.map(|data| self.handle_datagram(data)),
+            };
+
+            if res.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn handle_uni(self, mut recv: RecvStream) {
+        let data = match recv.read_to_end(u16::MAX as usize * 2).await {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+
+        match Header::unmarshal(&mut data.as_slice()) {
+            Ok(Header::Authenticate(auth)) => self.check_authenticate(auth.uuid(), auth.token()),
+            Ok(Header::Packet(_)) => self.handle_packet(&data, false).await,
+            Ok(Header::Dissociate(_)) => {}
+            Ok(header) => self.malformed(format!(
+                "command type {:#04x} in a unidirectional stream",
+                header.type_code()
+            )),
+            Err(err) => self.malformed(format!("unidirectional stream: {err}")),
+        }
+    }
+
+    async fn handle_bi(self, mut send: SendStream, mut recv: RecvStream) {
+        match Header::async_unmarshal(&mut recv).await {
+            Ok(Header::Connect(_)) => {
+                self.observed.lock().unwrap().connect.get_or_insert(Ok(()));
+
+                // echo the stream back instead of connecting to the target
+                let mut buf = vec![0; 16 * 1024];
+
+                while let Ok(Some(n)) = recv.read(&mut buf).await {
+                    if send.write_all(&buf[..n]).await.is_err() {
+                        return;
+                    }
+                }
+
+                let _ = send.finish().await;
+            }
+            Ok(Header::Dissociate(dissociate)) => {
+                let ack = command::encode(Header::DissociateAck(tuic::DissociateAck::new(
+                    dissociate.assoc_id(),
+                )));
+                let _ = send.write_all(&ack).await;
+                let _ = send.finish().await;
+            }
+            Ok(_) => {
+                // optional commands, e.g. Resume and BindUdp, are declined as the spec allows
+                let _ = send.reset(VarInt::from_u32(0));
+            }
+            Err(UnmarshalError::Io(_)) => {}
+            Err(err) => {
+                let connect = matches!(
+                    err,
+                    UnmarshalError::InvalidAddressType(_) | UnmarshalError::AddressParse(_)
+                );
+
+                if connect {
+                    self.observed.lock().unwrap().connect =
+                        Some(Err(format!("malformed Connect: {err}")));
+                }
+
+                self.malformed(format!("bidirectional stream: {err}"));
+            }
+        }
+    }
+
+    fn handle_datagram(&self, data: Bytes) {
+        match Header::unmarshal(&mut &data[..]) {
+            Ok(Header::Heartbeat(_)) => self.observed.lock().unwrap().heartbeat = true,
+            Ok(Header::Packet(_)) => {
+                let session = self.clone();
+                tokio::spawn(async move { session.handle_packet(&data, true).await });
+            }
+            Ok(header) => self.malformed(format!(
+                "command type {:#04x} in a datagram",
+                header.type_code()
+            )),
+            Err(err) => self.malformed(format!("datagram: {err}")),
+        }
+    }
+
+    fn check_authenticate(&self, uuid: Uuid, token: [u8; 32]) {
+        let res = if uuid != self.uuid {
+            Err(format!(
+                "Authenticate with UUID {uuid}, expecting {}",
+                self.uuid
+            ))
+        } else if token != command::token(&self.conn, uuid, &self.password) {
+            Err(String::from(
+                "Authenticate with a token not derived from the password with the TLS keying material exporter",
+            ))
+        } else {
+            Ok(())
+        };
+
+        self.observed.lock().unwrap().authenticate = Some(res);
+    }
+
+    /// Reassembles the UDP packet, then echoes it back from its target in the same mode
+    async fn handle_packet(&self, data: &[u8], native: bool) {
+        let (pkt, frag) = match command::decode_packet(data) {
+            Ok(res) => res,
+            Err(err) => {
+                self.observed.lock().unwrap().packet = Some(Err(err.to_string()));
+                return;
+            }
+        };
+
+        if let Err(err) = check_fragment(&pkt) {
+            self.observed.lock().unwrap().packet = Some(Err(err));
+            return;
+        }
+
+        let reassembled = self.reassembly.lock().unwrap().insert(&pkt, frag);
+
+        let Some((target, buf)) = reassembled else {
+            return;
+        };
+
+        if target.is_none() {
+            self.observed.lock().unwrap().packet = Some(Err(String::from(
+                "first fragment of a Packet without the target address",
+            )));
+            return;
+        }
+
+        self.observed.lock().unwrap().packet.get_or_insert(Ok(()));
+
+        let pkt_id = self.next_pkt_id.fetch_add(1, Ordering::Relaxed);
+        let frag_total = match (native, self.conn.max_datagram_size()) {
+            (true, Some(max)) => {
+                let frag_size = max.saturating_sub(64).max(1);
+                ((buf.len() + frag_size - 1) / frag_size).max(1) as u8
+            }
+            _ => 1,
+        };
+
+        for frag in command::fragments(pkt.assoc_id(), pkt_id, target, &buf, frag_total) {
+            if native {
+                let _ = self.conn.send_datagram(frag);
+            } else if let Ok(mut send) = self.conn.open_uni().await {
+                let _ = send.write_all(&frag).await;
+                let _ = send.finish().await;
+            }
+        }
+    }
+
+    fn malformed(&self, err: String) {
+        self.observed.lock().unwrap().malformed.push(err);
+    }
+}
+
+fn check_fragment(pkt: &Packet) -> Result<(), String> {
+    if pkt.frag_total() == 0 || pkt.frag_id() >= pkt.frag_total() {
+        Err(format!(
+            "Packet fragment {} of {}",
+            pkt.frag_id(),
+            pkt.frag_total()
+        ))
+    } else if pkt.frag_id() == 0 && pkt.addr().is_none() {
+        Err(String::from(
+            "first fragment of a Packet without the target address",
+        ))
+    } else if pkt.frag_id() != 0 && !pkt.addr().is_none() {
+        Err(String::from("later fragment of a Packet with an address"))
+    } else {
+        Ok(())
+    }
+}
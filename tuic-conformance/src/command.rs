@@ -0,0 +1,214 @@
+//! Encoding commands, both valid ones with the `tuic` crate and malformed ones byte by byte
+
+use bytes::{BufMut, Bytes, BytesMut};
+use quinn::Connection;
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+use tuic::{Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION};
+use uuid::Uuid;
+
+/// A type code no TUIC version assigns
+pub const TYPE_CODE_UNKNOWN: u8 = 0xfe;
+
+/// An extension type of `Authenticate` no TUIC version assigns, which the receiver should skip
+pub const EXTENSION_TYPE_UNKNOWN: u16 = 0xfffe;
+
+/// An address type code no TUIC version assigns
+pub const ADDRESS_TYPE_UNKNOWN: u8 = 0x7f;
+
+/// Computes the token of the user on the TLS session of the connection
+pub fn token(conn: &Connection, uuid: Uuid, password: &[u8]) -> [u8; 32] {
+    let mut token = [0; 32];
+    conn.export_keying_material(&mut token, uuid.as_bytes(), password)
+        .unwrap();
+    token
+}
+
+pub fn encode(header: Header) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(header.len());
+    header.write(&mut buf);
+    buf
+}
+
+pub fn authenticate(conn: &Connection, uuid: Uuid, password: &[u8]) -> Bytes {
+    let token = token(conn, uuid, password);
+    encode(Header::Authenticate(Authenticate::new(uuid, token))).freeze()
+}
+
+/// `Authenticate` with an extension of an unknown type, which the receiver should skip
+pub fn authenticate_with_unknown_extension(
+    conn: &Connection,
+    uuid: Uuid,
+    password: &[u8],
+) -> Bytes {
+    let mut buf = encode(Header::Authenticate(Authenticate::new(
+        uuid,
+        token(conn, uuid, password),
+    )));
+    buf.put_u16(EXTENSION_TYPE_UNKNOWN);
+    buf.put_u16(4);
+    buf.put_u32(0xdeadbeef);
+    buf.freeze()
+}
+
+pub fn connect(addr: Address) -> Bytes {
+    encode(Header::Connect(Connect::new(addr))).freeze()
+}
+
+/// `Connect` to an address of an unknown type
+pub fn connect_unknown_address() -> Bytes {
+    Bytes::from(vec![
+        VERSION,
+        Header::TYPE_CODE_CONNECT,
+        ADDRESS_TYPE_UNKNOWN,
+        127,
+        0,
+        0,
+        1,
+        0,
+        80,
+    ])
+}
+
+pub fn heartbeat() -> Bytes {
+    encode(Header::Heartbeat(Heartbeat::new())).freeze()
+}
+
+pub fn dissociate(assoc_id: u16) -> Bytes {
+    encode(Header::Dissociate(Dissociate::new(assoc_id))).freeze()
+}
+
+/// A `Packet` carrying the fragment, with `SIZE` set to the length of the fragment unless overridden
+pub fn packet(
+    assoc_id: u16,
+    pkt_id: u16,
+    frag_total: u8,
+    frag_id: u8,
+    addr: Address,
+    frag: &[u8],
+    size: Option<u16>,
+) -> Bytes {
+    let size = size.unwrap_or(frag.len() as u16);
+    let mut buf = encode(Header::Packet(Packet::new(
+        assoc_id, pkt_id, frag_total, frag_id, size, addr,
+    )));
+    buf.put_slice(frag);
+    buf.freeze()
+}
+
+/// Splits the UDP packet into `Packet` commands of `frag_total` fragments, with the address only in the first one
+pub fn fragments(
+    assoc_id: u16,
+    pkt_id: u16,
+    addr: Address,
+    pkt: &[u8],
+    frag_total: u8,
+) -> Vec<Bytes> {
+    let frag_size = (pkt.len() + frag_total as usize - 1) / frag_total as usize;
+
+    pkt.chunks(frag_size.max(1))
+        .enumerate()
+        .map(|(frag_id, frag)| {
+            let addr = if frag_id == 0 {
+                addr.clone()
+            } else {
+                Address::None
+            };
+            packet(
+                assoc_id,
+                pkt_id,
+                frag_total,
+                frag_id as u8,
+                addr,
+                frag,
+                None,
+            )
+        })
+        .collect()
+}
+
+/// Bytes starting with the version and the type code, followed by data that is not a valid command of any type
+pub fn raw(ver: u8, type_code: u8, data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(2 + data.len());
+    buf.put_u8(ver);
+    buf.put_u8(type_code);
+    buf.put_slice(data);
+    buf.freeze()
+}
+
+/// Decodes a `Packet` command with its fragment from a datagram or a unidirectional stream
+pub fn decode_packet(mut buf: &[u8]) -> Result<(Packet, Bytes), Error> {
+    let header =
+        Header::unmarshal(&mut buf).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+    let Header::Packet(pkt) = header else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "expecting a Packet command, got type {:#04x}",
+                header.type_code()
+            ),
+        ));
+    };
+
+    if buf.len() < pkt.size() as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Packet command with SIZE {} carrying only {} bytes",
+                pkt.size(),
+                buf.len()
+            ),
+        ));
+    }
+
+    let frag = Bytes::copy_from_slice(&buf[..pkt.size() as usize]);
+    Ok((pkt, frag))
+}
+
+/// UDP packets being reassembled from their fragments, by the associate ID and the packet ID
+#[derive(Default)]
+pub struct Reassembly {
+    pkts: HashMap<(u16, u16), Fragments>,
+}
+
+struct Fragments {
+    addr: Address,
+    parts: Vec<Option<Bytes>>,
+}
+
+impl Reassembly {
+    /// Adds the fragment, returning the address and the whole packet once all its fragments are received
+    ///
+    /// `FRAG_ID` must be checked to be below `FRAG_TOTAL`.
+    pub fn insert(&mut self, pkt: &Packet, frag: Bytes) -> Option<(Address, Bytes)> {
+        let key = (pkt.assoc_id(), pkt.pkt_id());
+        let frags = self.pkts.entry(key).or_insert_with(|| Fragments {
+            addr: Address::None,
+            parts: vec![None; pkt.frag_total() as usize],
+        });
+
+        if pkt.frag_id() == 0 {
+            frags.addr = pkt.addr().clone();
+        }
+
+        if let Some(part) = frags.parts.get_mut(pkt.frag_id() as usize) {
+            *part = Some(frag);
+        }
+
+        if !frags.parts.iter().all(Option::is_some) {
+            return None;
+        }
+
+        let frags = self.pkts.remove(&key).unwrap();
+        let mut buf = BytesMut::new();
+
+        for part in frags.parts.into_iter().flatten() {
+            buf.extend_from_slice(&part);
+        }
+
+        Some((frags.addr, buf.freeze()))
+    }
+}
@@ -0,0 +1,50 @@
+//! The TCP and UDP echo servers the server under test relays to
+
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    thread,
+};
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Starts the echo servers on the address, returning their addresses
+///
+/// The address must be reachable from the server under test.
+pub fn start(ip: IpAddr) -> io::Result<(SocketAddr, SocketAddr)> {
+    let tcp = TcpListener::bind((ip, 0))?;
+    let udp = UdpSocket::bind((ip, 0))?;
+    let addrs = (tcp.local_addr()?, udp.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in tcp.incoming().flatten() {
+            thread::spawn(move || {
+                let _ = echo_tcp(stream);
+            });
+        }
+    });
+
+    thread::spawn(move || {
+        let mut buf = vec![0; BUF_SIZE];
+
+        while let Ok((n, addr)) = udp.recv_from(&mut buf) {
+            let _ = udp.send_to(&buf[..n], addr);
+        }
+    });
+
+    Ok(addrs)
+}
+
+fn echo_tcp(mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = vec![0; BUF_SIZE];
+
+    loop {
+        let n = stream.read(&mut buf)?;
+
+        if n == 0 {
+            return Ok(());
+        }
+
+        stream.write_all(&buf[..n])?;
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{client::ClientSuite, server::ServerSuite};
+use lexopt::{Arg, Error as ArgumentError, Parser, ValueExt};
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    process,
+    time::Duration,
+};
+use uuid::Uuid;
+
+mod client;
+mod command;
+mod echo;
+mod quic;
+mod server;
+
+const HELP_MSG: &str = r#"
+Usage tuic-conformance [arguments]
+
+Runs a scripted sequence of valid and invalid command exchanges against a TUIC server over QUIC, checking that the valid ones are relayed and the invalid ones are ignored or rejected with the right error code
+
+With `--listen`, acts as a TUIC server instead, waiting for a TUIC client to connect and checking the commands it sends. Relay some TCP and UDP traffic through the client under test meanwhile; it is echoed back instead of reaching the targets
+
+Arguments:
+    -s, --server <addr>         Address of the server under test, as IP:PORT
+    --server-name <name>        Server name for TLS SNI, or in the certificate generated with `--listen`, defaults to "localhost"
+    -u, --uuid <uuid>           UUID of a user of the server under test, or expected from the client under test
+    -p, --password <password>   Password of the user
+    --alpn <protocol>           ALPN protocol, can be specified multiple times
+    --echo-ip <ip>              IP address of the echo servers the server under test relays to, which must be reachable from it, defaults to 127.0.0.1
+    --timeout <ms>              How long to wait for each response, defaults to 3000
+    -t, --test <name>           Run only the cases whose name contains the string, can be specified multiple times
+    -l, --list                  List the cases of checking a server
+    --listen <addr>             Check a client instead, listening on the address as IP:PORT
+    --certificate <path>        Where to write the self-signed certificate for the client under test to trust, defaults to tuic-conformance.pem
+    --wait <secs>               How long to wait for the client under test, defaults to 60
+    -v, --version               Print the version
+    -h, --help                  Print this help message
+"#;
+
+struct Args {
+    server: Option<SocketAddr>,
+    server_name: String,
+    uuid: Option<Uuid>,
+    password: Option<Vec<u8>>,
+    alpn: Vec<Vec<u8>>,
+    echo_ip: IpAddr,
+    timeout: Duration,
+    tests: Vec<String>,
+    listen: Option<SocketAddr>,
+    certificate: PathBuf,
+    wait: Duration,
+}
+
+/// The result of a case that did not fail
+pub enum Verdict {
+    Pass(String),
+    Skip(String),
+}
+
+/// Why a case failed
+pub struct Failure(String);
+
+impl Failure {
+    pub fn new(msg: impl Into<String>) -> Self {
+        Self(msg.into())
+    }
+}
+
+impl<E: StdError> From<E> for Failure {
+    fn from(err: E) -> Self {
+        Self(err.to_string())
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub type Outcome = Result<Verdict, Failure>;
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+
+    let (Some(uuid), Some(password)) = (args.uuid, args.password.clone()) else {
+        eprintln!("both `--uuid` and `--password` are required");
+        process::exit(1);
+    };
+
+    let results = if let Some(listen) = args.listen {
+        let suite = match ClientSuite::new(
+            listen,
+            &args.server_name,
+            args.alpn.clone(),
+            uuid,
+            password,
+            args.wait,
+        ) {
+            Ok((suite, cert)) => {
+                if let Err(err) = fs::write(&args.certificate, cert) {
+                    eprintln!("failed to write the certificate: {err}");
+                    process::exit(1);
+                }
+
+                suite
+            }
+            Err(err) => {
+                eprintln!("failed to listen on {listen}: {err}");
+                process::exit(1);
+            }
+        };
+
+        println!(
+            "waiting for the client under test on {listen}, trusting {cert}, for up to {wait:?}",
+            cert = args.certificate.display(),
+            wait = args.wait,
+        );
+
+        suite.run().await
+    } else {
+        let Some(server) = args.server else {
+            eprintln!("either `--server` or `--listen` is required");
+            process::exit(1);
+        };
+
+        let (tcp_echo, udp_echo) = match echo::start(args.echo_ip) {
+            Ok(addrs) => addrs,
+            Err(err) => {
+                eprintln!("failed to start the echo servers: {err}");
+                process::exit(1);
+            }
+        };
+
+        let suite = match ServerSuite::new(
+            server,
+            args.server_name.clone(),
+            args.alpn.clone(),
+            uuid,
+            password,
+            tcp_echo,
+            udp_echo,
+            args.timeout,
+        ) {
+            Ok(suite) => suite,
+            Err(err) => {
+                eprintln!("failed to set up the QUIC endpoint: {err}");
+                process::exit(1);
+            }
+        };
+
+        let mut results = Vec::new();
+
+        for (name, _) in server::CASES {
+            if !args.tests.is_empty() && !args.tests.iter().any(|t| name.contains(t.as_str())) {
+                continue;
+            }
+
+            results.push((*name, suite.run(name).await));
+        }
+
+        results
+    };
+
+    let mut failed = false;
+
+    for (name, outcome) in results {
+        match outcome {
+            Ok(Verdict::Pass(note)) if note.is_empty() => println!("{name:<24} pass"),
+            Ok(Verdict::Pass(note)) => println!("{name:<24} pass ({note})"),
+            Ok(Verdict::Skip(reason)) => println!("{name:<24} skip ({reason})"),
+            Err(err) => {
+                println!("{name:<24} FAIL: {err}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Args, ArgumentError> {
+    let mut args = Args {
+        server: None,
+        server_name: String::from("localhost"),
+        uuid: None,
+        password: None,
+        alpn: Vec::new(),
+        echo_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        timeout: Duration::from_millis(3000),
+        tests: Vec::new(),
+        listen: None,
+        certificate: PathBuf::from("tuic-conformance.pem"),
+        wait: Duration::from_secs(60),
+    };
+
+    let mut parser = Parser::from_env();
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Arg::Short('s') | Arg::Long("server") => args.server = Some(parser.value()?.parse()?),
+            Arg::Long("server-name") => args.server_name = parser.value()?.string()?,
+            Arg::Short('u') | Arg::Long("uuid") => args.uuid = Some(parser.value()?.parse()?),
+            Arg::Short('p') | Arg::Long("password") => {
+                args.password = Some(parser.value()?.string()?.into_bytes())
+            }
+            Arg::Long("alpn") => args.alpn.push(parser.value()?.string()?.into_bytes()),
+            Arg::Long("echo-ip") => args.echo_ip = parser.value()?.parse()?,
+            Arg::Long("timeout") => args.timeout = Duration::from_millis(parser.value()?.parse()?),
+            Arg::Short('t') | Arg::Long("test") => args.tests.push(parser.value()?.string()?),
+            Arg::Short('l') | Arg::Long("list") => {
+                for (name, desc) in server::CASES {
+                    println!("{name:<24} {desc}");
+                }
+
+                process::exit(0);
+            }
+            Arg::Long("listen") => args.listen = Some(parser.value()?.parse()?),
+            Arg::Long("certificate") => args.certificate = parser.value()?.into(),
+            Arg::Long("wait") => args.wait = Duration::from_secs(parser.value()?.parse()?),
+            Arg::Short('v') | Arg::Long("version") => {
+                println!("{}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            Arg::Short('h') | Arg::Long("help") => {
+                println!("{HELP_MSG}");
+                process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    Ok(args)
+}
@@ -0,0 +1,88 @@
+//! Setting up the QUIC endpoints of the suites
+
+use quinn::{ClientConfig, Endpoint, ServerConfig};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    version, Certificate, Error as RustlsError, PrivateKey, ServerName,
+};
+use std::{
+    io::{self, Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::SystemTime,
+};
+
+/// A client endpoint for connecting to the server under test
+///
+/// The server certificate is not verified, as the suite tests the TUIC protocol rather than the TLS setup of the server.
+pub fn client(server: SocketAddr, alpn: Vec<Vec<u8>>) -> io::Result<Endpoint> {
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&version::TLS13])
+        .map_err(|err| Error::new(ErrorKind::Other, err))?
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+        .with_no_client_auth();
+
+    crypto.alpn_protocols = alpn;
+
+    let bind_addr = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+
+    let mut ep = Endpoint::client(bind_addr)?;
+    ep.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+    Ok(ep)
+}
+
+/// A server endpoint for the client under test to connect to, with a self-signed certificate for the server name
+///
+/// Returns the endpoint with the certificate in PEM, for the client under test to trust.
+pub fn server(
+    addr: SocketAddr,
+    server_name: &str,
+    alpn: Vec<Vec<u8>>,
+) -> io::Result<(Endpoint, String)> {
+    let cert = rcgen::generate_simple_self_signed(vec![server_name.to_owned()])
+        .map_err(|err| Error::new(ErrorKind::Other, err))?;
+
+    let cert_pem = cert
+        .serialize_pem()
+        .map_err(|err| Error::new(ErrorKind::Other, err))?;
+    let cert_der = cert
+        .serialize_der()
+        .map_err(|err| Error::new(ErrorKind::Other, err))?;
+    let key_der = cert.serialize_private_key_der();
+
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&version::TLS13])
+        .map_err(|err| Error::new(ErrorKind::Other, err))?
+        .with_no_client_auth()
+        .with_single_cert(vec![Certificate(cert_der)], PrivateKey(key_der))
+        .map_err(|err| Error::new(ErrorKind::Other, err))?;
+
+    crypto.alpn_protocols = alpn;
+    crypto.max_early_data_size = u32::MAX;
+
+    let ep = Endpoint::server(ServerConfig::with_crypto(Arc::new(crypto)), addr)?;
+    Ok((ep, cert_pem))
+}
+
+struct AcceptAnyCert;
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, RustlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
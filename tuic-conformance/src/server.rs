@@ -0,0 +1,521 @@
+//! The cases run against a server under test
+
+use crate::{
+    command::{self, Reassembly},
+    quic, Failure, Outcome, Verdict,
+};
+use bytes::Bytes;
+use quinn::{Connection, ConnectionError, Endpoint};
+use std::{future::Future, io, net::SocketAddr, time::Duration};
+use tokio::time;
+use tuic::{Address, Header, VERSION};
+use uuid::Uuid;
+
+/// The application error code for closing a connection on a protocol error
+const CLOSE_PROTOCOL_ERROR: u64 = 1;
+
+/// The application error code for closing a connection on a failed authentication
+const CLOSE_AUTH_FAILED: u64 = 2;
+
+/// How long to wait for the server to close the connection after sending something invalid
+const CLOSE_GRACE: Duration = Duration::from_millis(500);
+
+const PAYLOAD: &[u8] = b"tuic-conformance";
+
+/// The cases in the order they are run, with what each one checks
+///
+/// Cases with valid exchanges come first. Each case uses a new connection, so a case leaving its connection broken does not affect the next ones, and `server-alive` at last checks that the server still serves new connections after all the malformed traffic.
+pub const CASES: &[(&str, &str)] = &[
+    (
+        "authenticate",
+        "authenticating, then relaying a TCP stream with Connect",
+    ),
+    (
+        "authenticate-extension",
+        "an unknown extension of Authenticate is skipped",
+    ),
+    (
+        "connect-before-auth",
+        "a Connect arriving before Authenticate is relayed once authenticated",
+    ),
+    (
+        "packet-native",
+        "a UDP packet in a datagram is relayed, and the response comes back in a datagram",
+    ),
+    (
+        "packet-quic",
+        "a UDP packet in a unidirectional stream is relayed, and the response comes back in a unidirectional stream",
+    ),
+    (
+        "packet-fragmented",
+        "a UDP packet split into fragments is reassembled and relayed",
+    ),
+    (
+        "heartbeat",
+        "a Heartbeat is accepted without affecting the connection",
+    ),
+    (
+        "dissociate",
+        "a Dissociate in a bidirectional stream is answered with DissociateAck",
+    ),
+    (
+        "bad-password",
+        "a wrong password is rejected, closing with code 2 or never relaying",
+    ),
+    (
+        "unknown-user",
+        "an unknown UUID is rejected, closing with code 2 or never relaying",
+    ),
+    (
+        "bad-version",
+        "a command of another protocol version is ignored or closes with code 1",
+    ),
+    (
+        "unknown-command",
+        "a command of an unknown type is ignored or closes with code 1",
+    ),
+    (
+        "truncated-command",
+        "a command cut short by the end of the stream is ignored or closes with code 1",
+    ),
+    (
+        "unknown-address-type",
+        "a Connect with an unknown address type is not relayed, and is ignored or closes with code 1",
+    ),
+    (
+        "fragment-out-of-range",
+        "a fragment with FRAG_ID not below FRAG_TOTAL is not relayed, and is ignored or closes with code 1",
+    ),
+    (
+        "zero-fragments",
+        "a Packet with FRAG_TOTAL 0 is not relayed, and is ignored or closes with code 1",
+    ),
+    (
+        "size-mismatch",
+        "a Packet with SIZE beyond the datagram is not relayed, and is ignored or closes with code 1",
+    ),
+    (
+        "oversized-packet",
+        "fragments adding up to more than a UDP packet can hold are not relayed, and are ignored or close with code 1",
+    ),
+    (
+        "server-alive",
+        "a new connection is still served after the malformed traffic",
+    ),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    Native,
+    Quic,
+}
+
+pub struct ServerSuite {
+    ep: Endpoint,
+    server: SocketAddr,
+    server_name: String,
+    uuid: Uuid,
+    password: Vec<u8>,
+    tcp_echo: SocketAddr,
+    udp_echo: SocketAddr,
+    timeout: Duration,
+}
+
+#[allow(clippy::too_many_arguments)]
+impl ServerSuite {
+    pub fn new(
+        server: SocketAddr,
+        server_name: String,
+        alpn: Vec<Vec<u8>>,
+        uuid: Uuid,
+        password: Vec<u8>,
+        tcp_echo: SocketAddr,
+        udp_echo: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            ep: quic::client(server, alpn)?,
+            server,
+            server_name,
+            uuid,
+            password,
+            tcp_echo,
+            udp_echo,
+            timeout,
+        })
+    }
+
+    pub async fn run(&self, name: &str) -> Outcome {
+        let conn = self
+            .ep
+            .connect(self.server, &self.server_name)
+            .map_err(Failure::from)?
+            .await
+            .map_err(|err| Failure::new(format!("failed to connect: {err}")))?;
+
+        let outcome = match name {
+            "authenticate" => self.authenticate(&conn).await,
+            "authenticate-extension" => self.authenticate_extension(&conn).await,
+            "connect-before-auth" => self.connect_before_auth(&conn).await,
+            "packet-native" => self.packet(&conn, Mode::Native, 1).await,
+            "packet-quic" => self.packet(&conn, Mode::Quic, 1).await,
+            "packet-fragmented" => self.packet(&conn, Mode::Native, 3).await,
+            "heartbeat" => self.heartbeat(&conn).await,
+            "dissociate" => self.dissociate(&conn).await,
+            "bad-password" => {
+                let auth = command::authenticate(&conn, self.uuid, b"tuic-conformance-wrong");
+                self.rejected_auth(&conn, auth).await
+            }
+            "unknown-user" => {
+                let auth =
+                    command::authenticate(&conn, Uuid::from_u128(0x7475_6963), &self.password);
+                self.rejected_auth(&conn, auth).await
+            }
+            "bad-version" => {
+                let auth = command::authenticate(&conn, self.uuid, &self.password);
+                self.malformed_uni(&conn, command::raw(0xff, auth[1], &auth[2..]))
+                    .await
+            }
+            "unknown-command" => {
+                self.malformed_uni(
+                    &conn,
+                    command::raw(VERSION, command::TYPE_CODE_UNKNOWN, &[0; 16]),
+                )
+                .await
+            }
+            "truncated-command" => {
+                self.malformed_uni(
+                    &conn,
+                    command::raw(VERSION, Header::TYPE_CODE_DISSOCIATE, &[0]),
+                )
+                .await
+            }
+            "unknown-address-type" => self.unknown_address_type(&conn).await,
+            "fragment-out-of-range" => {
+                let addr = Address::SocketAddress(self.udp_echo);
+                let pkt = command::packet(1, 0, 2, 2, addr, b"out-of-range", None);
+                self.malformed_packets(&conn, Mode::Native, vec![pkt]).await
+            }
+            "zero-fragments" => {
+                let addr = Address::SocketAddress(self.udp_echo);
+                let pkt = command::packet(1, 0, 0, 0, addr, b"zero-fragments", None);
+                self.malformed_packets(&conn, Mode::Native, vec![pkt]).await
+            }
+            "size-mismatch" => {
+                let addr = Address::SocketAddress(self.udp_echo);
+                let pkt = command::packet(1, 0, 1, 0, addr, b"size-mismatch", Some(1000));
+                self.malformed_packets(&conn, Mode::Native, vec![pkt]).await
+            }
+            "oversized-packet" => {
+                let addr = Address::SocketAddress(self.udp_echo);
+                let frag = vec![0x55; 40000];
+                let pkts = vec![
+                    command::packet(1, 0, 2, 0, addr, &frag, None),
+                    command::packet(1, 0, 2, 1, Address::None, &frag, None),
+                ];
+                self.malformed_packets(&conn, Mode::Quic, pkts).await
+            }
+            "server-alive" => self.server_alive(&conn).await,
+            _ => Err(Failure::new(format!("unknown case {name}"))),
+        };
+
+        conn.close(0u32.into(), &[]);
+        outcome
+    }
+
+    async fn authenticate(&self, conn: &Connection) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+        self.echo_tcp(conn).await?;
+        Ok(Verdict::Pass(String::new()))
+    }
+
+    async fn authenticate_extension(&self, conn: &Connection) -> Outcome {
+        let auth = command::authenticate_with_unknown_extension(conn, self.uuid, &self.password);
+        send_uni(conn, auth).await?;
+        self.echo_tcp(conn).await?;
+        Ok(Verdict::Pass(String::new()))
+    }
+
+    async fn connect_before_auth(&self, conn: &Connection) -> Outcome {
+        let (mut send, mut recv) = conn.open_bi().await?;
+        send.write_all(&command::connect(Address::SocketAddress(self.tcp_echo)))
+            .await?;
+        send.write_all(PAYLOAD).await?;
+
+        time::sleep(Duration::from_millis(200)).await;
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+
+        let mut buf = vec![0; PAYLOAD.len()];
+        self.within(recv.read_exact(&mut buf), "the echo of the TCP stream")
+            .await??;
+
+        if buf != PAYLOAD {
+            return Err(Failure::new("TCP stream echoed with different data"));
+        }
+
+        Ok(Verdict::Pass(String::new()))
+    }
+
+    async fn packet(&self, conn: &Connection, mode: Mode, frag_total: u8) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+        self.echo_udp(conn, mode, 1, 0, PAYLOAD, frag_total).await?;
+        Ok(Verdict::Pass(String::new()))
+    }
+
+    async fn heartbeat(&self, conn: &Connection) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+        conn.send_datagram(command::heartbeat())?;
+
+        if let Ok(err) = time::timeout(CLOSE_GRACE, conn.closed()).await {
+            return Err(Failure::new(format!("connection closed: {err}")));
+        }
+
+        self.echo_tcp(conn).await?;
+        Ok(Verdict::Pass(String::new()))
+    }
+
+    async fn dissociate(&self, conn: &Connection) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+        self.echo_udp(conn, Mode::Native, 7, 0, PAYLOAD, 1).await?;
+
+        let (mut send, mut recv) = conn.open_bi().await?;
+        send.write_all(&command::dissociate(7)).await?;
+        send.finish().await?;
+
+        let resp = self
+            .within(recv.read_to_end(64), "DissociateAck")
+            .await?
+            .map_err(|err| Failure::new(format!("no DissociateAck: {err}")))?;
+
+        match Header::unmarshal(&mut resp.as_slice())? {
+            Header::DissociateAck(ack) if ack.assoc_id() == 7 => Ok(Verdict::Pass(String::new())),
+            Header::DissociateAck(ack) => Err(Failure::new(format!(
+                "DissociateAck of associate ID {}, expecting 7",
+                ack.assoc_id()
+            ))),
+            header => Err(Failure::new(format!(
+                "answered with command type {:#04x}, expecting DissociateAck",
+                header.type_code()
+            ))),
+        }
+    }
+
+    async fn rejected_auth(&self, conn: &Connection, auth: Bytes) -> Outcome {
+        let relay = async {
+            send_uni(conn, auth).await?;
+
+            let (mut send, mut recv) = conn.open_bi().await?;
+            send.write_all(&command::connect(Address::SocketAddress(self.tcp_echo)))
+                .await?;
+            send.write_all(PAYLOAD).await?;
+
+            let mut buf = vec![0; PAYLOAD.len()];
+            recv.read_exact(&mut buf).await?;
+            Ok::<_, Failure>(())
+        };
+
+        match time::timeout(self.timeout, relay).await {
+            Ok(Ok(())) => Err(Failure::new(
+                "relayed a TCP stream for an unauthenticated connection",
+            )),
+            Ok(Err(_)) | Err(_) => match conn.close_reason() {
+                Some(reason) => closed_with(reason, CLOSE_AUTH_FAILED),
+                None => Ok(Verdict::Pass(String::from("ignored"))),
+            },
+        }
+    }
+
+    async fn malformed_uni(&self, conn: &Connection, data: Bytes) -> Outcome {
+        send_uni(conn, data).await?;
+
+        self.ignored_or_closed(conn, async {
+            send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+            self.echo_tcp(conn).await
+        })
+        .await
+    }
+
+    async fn unknown_address_type(&self, conn: &Connection) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+
+        let (mut send, mut recv) = conn.open_bi().await?;
+        send.write_all(&command::connect_unknown_address()).await?;
+        let _ = send.write_all(PAYLOAD).await;
+        let _ = send.finish().await;
+
+        if let Ok(Ok(data)) = time::timeout(CLOSE_GRACE, recv.read_to_end(1024)).await {
+            if !data.is_empty() {
+                return Err(Failure::new("relayed a Connect to an unknown address type"));
+            }
+        }
+
+        self.ignored_or_closed(conn, self.echo_tcp(conn)).await
+    }
+
+    async fn malformed_packets(&self, conn: &Connection, mode: Mode, pkts: Vec<Bytes>) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+
+        for pkt in pkts {
+            match mode {
+                Mode::Native => conn.send_datagram(pkt)?,
+                Mode::Quic => send_uni(conn, pkt).await?,
+            }
+        }
+
+        // a malformed packet relayed would be echoed back ahead of the valid one, failing the probe
+        self.ignored_or_closed(conn, self.echo_udp(conn, mode, 1, 1, PAYLOAD, 1))
+            .await
+    }
+
+    async fn server_alive(&self, conn: &Connection) -> Outcome {
+        send_uni(conn, command::authenticate(conn, self.uuid, &self.password)).await?;
+        self.echo_tcp(conn).await?;
+        self.echo_udp(conn, Mode::Native, 1, 0, PAYLOAD, 1).await?;
+        Ok(Verdict::Pass(String::new()))
+    }
+
+    /// Passes if the server either closes the connection with the protocol error code, or keeps serving it
+    async fn ignored_or_closed(
+        &self,
+        conn: &Connection,
+        probe: impl Future<Output = Result<(), Failure>>,
+    ) -> Outcome {
+        if let Ok(reason) = time::timeout(CLOSE_GRACE, conn.closed()).await {
+            return closed_with(reason, CLOSE_PROTOCOL_ERROR);
+        }
+
+        match probe.await {
+            Ok(()) => Ok(Verdict::Pass(String::from("ignored"))),
+            Err(err) => match conn.close_reason() {
+                Some(reason) => closed_with(reason, CLOSE_PROTOCOL_ERROR),
+                None => Err(Failure::new(format!(
+                    "connection left open, but no longer served: {err}"
+                ))),
+            },
+        }
+    }
+
+    /// Relays a TCP stream to the echo server, checking that the data comes back
+    async fn echo_tcp(&self, conn: &Connection) -> Result<(), Failure> {
+        let (mut send, mut recv) = conn.open_bi().await?;
+        send.write_all(&command::connect(Address::SocketAddress(self.tcp_echo)))
+            .await?;
+        send.write_all(PAYLOAD).await?;
+
+        let mut buf = vec![0; PAYLOAD.len()];
+        self.within(recv.read_exact(&mut buf), "the echo of the TCP stream")
+            .await??;
+
+        if buf != PAYLOAD {
+            return Err(Failure::new("TCP stream echoed with different data"));
+        }
+
+        Ok(())
+    }
+
+    /// Relays a UDP packet to the echo server, checking that it comes back whole, from the echo server and in the same mode
+    async fn echo_udp(
+        &self,
+        conn: &Connection,
+        mode: Mode,
+        assoc_id: u16,
+        pkt_id: u16,
+        pkt: &[u8],
+        frag_total: u8,
+    ) -> Result<(), Failure> {
+        let addr = Address::SocketAddress(self.udp_echo);
+
+        for frag in command::fragments(assoc_id, pkt_id, addr, pkt, frag_total) {
+            match mode {
+                Mode::Native => conn.send_datagram(frag)?,
+                Mode::Quic => send_uni(conn, frag).await?,
+            }
+        }
+
+        let (resp_mode, src, resp) = self
+            .within(recv_packet(conn, assoc_id), "the echo of the UDP packet")
+            .await??;
+
+        if resp != pkt {
+            return Err(Failure::new(format!(
+                "UDP packet echoed with different data ({} bytes, expecting {})",
+                resp.len(),
+                pkt.len()
+            )));
+        }
+
+        if resp_mode != mode {
+            return Err(Failure::new(format!(
+                "UDP packet sent in mode {mode:?}, but the response came back in mode {resp_mode:?}"
+            )));
+        }
+
+        if src != Address::SocketAddress(self.udp_echo) {
+            return Err(Failure::new(format!(
+                "response from {src}, expecting the echo server {}",
+                self.udp_echo
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn within<T>(&self, fut: impl Future<Output = T>, what: &str) -> Result<T, Failure> {
+        time::timeout(self.timeout, fut)
+            .await
+            .map_err(|_| Failure::new(format!("timed out waiting for {what}")))
+    }
+}
+
+async fn send_uni(conn: &Connection, data: Bytes) -> Result<(), Failure> {
+    let mut send = conn.open_uni().await?;
+    send.write_all(&data).await?;
+    send.finish().await?;
+    Ok(())
+}
+
+/// Receives the fragments of a UDP packet of the associate ID from both datagrams and unidirectional streams, returning the mode, the source address and the reassembled packet
+async fn recv_packet(conn: &Connection, assoc_id: u16) -> Result<(Mode, Address, Bytes), Failure> {
+    let mut reassembly = Reassembly::default();
+
+    loop {
+        let (mode, data) = tokio::select! {
+            data = conn.read_datagram() => (Mode::Native, data?),
+            recv = conn.accept_uni() => (Mode::Quic, Bytes::from(recv?.read_to_end(u16::MAX as usize * 2).await?)),
+        };
+
+        let (pkt, frag) = command::decode_packet(&data)?;
+
+        if pkt.assoc_id() != assoc_id {
+            return Err(Failure::new(format!(
+                "Packet of associate ID {}, expecting {assoc_id}",
+                pkt.assoc_id()
+            )));
+        }
+
+        if pkt.frag_total() == 0 || pkt.frag_id() >= pkt.frag_total() {
+            return Err(Failure::new(format!(
+                "Packet fragment {} of {}",
+                pkt.frag_id(),
+                pkt.frag_total()
+            )));
+        }
+
+        if let Some((src, pkt)) = reassembly.insert(&pkt, frag) {
+            return Ok((mode, src, pkt));
+        }
+    }
+}
+
+fn closed_with(reason: ConnectionError, code: u64) -> Outcome {
+    match reason {
+        ConnectionError::ApplicationClosed(close) if close.error_code.into_inner() == code => {
+            Ok(Verdict::Pass(format!("closed with code {code}")))
+        }
+        ConnectionError::ApplicationClosed(close) => Err(Failure::new(format!(
+            "closed with code {}, expecting {code}",
+            close.error_code
+        ))),
+        reason => Err(Failure::new(format!("connection lost: {reason}"))),
+    }
+}
@@ -0,0 +1,588 @@
+//! Decoding TUIC commands from decrypted QUIC payloads
+//!
+//! Feed the [`Decoder`] with the data of each QUIC stream and datagram of a TUIC connection in order, e.g. extracted from a capture decrypted with `SSLKEYLOGFILE`. It decodes the command headers, and reassembles the fragmented UDP packets the same way a TUIC implementation does.
+
+use bytes::Bytes;
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::Cursor,
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+use tuic::{
+    model::{AssembleError, Connection},
+    Address, Header, UnmarshalError,
+};
+
+/// The endpoint sending the payload
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl FromStr for Side {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" | "client" => Ok(Self::Client),
+            "s" | "server" => Ok(Self::Server),
+            _ => Err(ParseError::Side(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Client => write!(f, "client"),
+            Self::Server => write!(f, "server"),
+        }
+    }
+}
+
+/// Where the payload is carried in the QUIC connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// All data of a unidirectional stream
+    Uni,
+    /// All data sent on one direction of a bidirectional stream
+    Bi,
+    /// A datagram
+    Datagram,
+}
+
+impl FromStr for Source {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uni" => Ok(Self::Uni),
+            "bi" => Ok(Self::Bi),
+            "datagram" | "dgram" => Ok(Self::Datagram),
+            _ => Err(ParseError::Source(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Uni => write!(f, "uni"),
+            Self::Bi => write!(f, "bi"),
+            Self::Datagram => write!(f, "datagram"),
+        }
+    }
+}
+
+/// Decodes the payloads of a TUIC connection, keeping the fragments of UDP packets for reassembly
+pub struct Decoder {
+    client: Connection<Bytes>,
+    server: Connection<Bytes>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            client: Connection::new(),
+            server: Connection::new(),
+        }
+    }
+
+    /// Decodes a payload sent by `side`, returning the decoded command, followed by the reassembled UDP packet if it completes one
+    pub fn decode(&self, side: Side, source: Source, payload: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        // the server replies on bidirectional streams with the raw TCP payload
+        if let (Side::Server, Source::Bi) = (side, source) {
+            events.push(Event::Data {
+                side,
+                payload: Bytes::copy_from_slice(payload),
+            });
+            return events;
+        }
+
+        let mut cursor = Cursor::new(payload);
+
+        let header = match Header::unmarshal(&mut cursor) {
+            Ok(header) => header,
+            Err(err) => {
+                events.push(Event::Error(side, source, DecodeError::Unmarshal(err)));
+                return events;
+            }
+        };
+
+        let rest = Bytes::copy_from_slice(&payload[cursor.position() as usize..]);
+
+        if !is_expected(side, source, &header) {
+            events.push(Event::Error(
+                side,
+                source,
+                DecodeError::UnexpectedCommand(command_name(&header)),
+            ));
+        }
+
+        let (payload, reassembled) = match &header {
+            Header::Connect(_) => (rest, None),
+            Header::Packet(pkt) => {
+                let size = pkt.size() as usize;
+
+                if rest.len() < size {
+                    events.push(Event::Command(Command {
+                        side,
+                        source,
+                        header: header.clone(),
+                        payload: rest.clone(),
+                    }));
+                    events.push(Event::Error(
+                        side,
+                        source,
+                        DecodeError::Truncated(size, rest.len()),
+                    ));
+                    return events;
+                }
+
+                if rest.len() > size {
+                    events.push(Event::Error(
+                        side,
+                        source,
+                        DecodeError::TrailingBytes(rest.len() - size),
+                    ));
+                }
+
+                let frag = rest.slice(..size);
+                let reassembled = self.assemble(side, pkt.clone(), frag.clone());
+                (frag, Some(reassembled))
+            }
+            Header::Parity(parity) => {
+                let size = parity.size() as usize;
+
+                if rest.len() < size {
+                    events.push(Event::Command(Command {
+                        side,
+                        source,
+                        header: header.clone(),
+                        payload: rest.clone(),
+                    }));
+                    events.push(Event::Error(
+                        side,
+                        source,
+                        DecodeError::Truncated(size, rest.len()),
+                    ));
+                    return events;
+                }
+
+                if rest.len() > size {
+                    events.push(Event::Error(
+                        side,
+                        source,
+                        DecodeError::TrailingBytes(rest.len() - size),
+                    ));
+                }
+
+                let data = rest.slice(..size);
+                let reassembled = self.recover(side, parity.clone(), data.clone());
+                (data, Some(reassembled))
+            }
+            Header::Dissociate(dissoc) => {
+                self.connection(side).recv_dissociate(dissoc.clone());
+                (Self::check_trailing(&mut events, side, source, rest), None)
+            }
+            Header::BindUdp(bind) => {
+                self.connection(side).recv_bind_udp(bind.clone());
+                (Self::check_trailing(&mut events, side, source, rest), None)
+            }
+            Header::DissociateAck(ack) => {
+                self.connection(side).recv_dissociate_ack(ack.clone());
+                (Self::check_trailing(&mut events, side, source, rest), None)
+            }
+            Header::Resume(resume) => {
+                self.connection(side).recv_resume(resume.clone());
+                (Self::check_trailing(&mut events, side, source, rest), None)
+            }
+            _ => (Self::check_trailing(&mut events, side, source, rest), None),
+        };
+
+        events.push(Event::Command(Command {
+            side,
+            source,
+            header,
+            payload,
+        }));
+
+        match reassembled {
+            // packets in one fragment are reported with the command itself
+            Some(Ok(Some(pkt))) if pkt.frag_total > 1 => events.push(Event::Reassembled(pkt)),
+            Some(Err(err)) => events.push(Event::Error(side, source, DecodeError::Assemble(err))),
+            Some(Ok(_)) | None => {}
+        }
+
+        events
+    }
+
+    /// Drops the fragments of the UDP packets that have not been completed within the timeout since the first one received, as the relay server does
+    pub fn collect_garbage(&self, timeout: Duration) {
+        self.client.collect_garbage(timeout);
+        self.server.collect_garbage(timeout);
+    }
+
+    fn connection(&self, side: Side) -> &Connection<Bytes> {
+        match side {
+            Side::Client => &self.client,
+            Side::Server => &self.server,
+        }
+    }
+
+    fn assemble(
+        &self,
+        side: Side,
+        header: tuic::Packet,
+        frag: Bytes,
+    ) -> Result<Option<Reassembled>, AssembleError> {
+        let pkt = self.connection(side).recv_packet_unrestricted(header);
+        let pkt_id = pkt.pkt_id();
+        let frag_total = pkt.frag_total();
+
+        pkt.assemble(frag).map(|pkt| {
+            pkt.map(|pkt| {
+                let mut buf = Vec::new();
+                let (addr, assoc_id) = pkt.assemble(&mut buf);
+
+                Reassembled {
+                    side,
+                    assoc_id,
+                    pkt_id,
+                    frag_total,
+                    addr,
+                    payload: Bytes::from(buf),
+                    recovered: false,
+                }
+            })
+        })
+    }
+
+    fn recover(
+        &self,
+        side: Side,
+        header: tuic::Parity,
+        parity: Bytes,
+    ) -> Result<Option<Reassembled>, AssembleError> {
+        let model = self.connection(side).recv_parity_unrestricted(header);
+        let pkt_id = model.pkt_id();
+        let frag_total = model.frag_total();
+
+        model.recover(parity).map(|pkt| {
+            pkt.map(|pkt| {
+                let mut buf = Vec::new();
+                let (addr, assoc_id) = pkt.assemble(&mut buf);
+
+                Reassembled {
+                    side,
+                    assoc_id,
+                    pkt_id,
+                    frag_total,
+                    addr,
+                    payload: Bytes::from(buf),
+                    recovered: true,
+                }
+            })
+        })
+    }
+
+    fn check_trailing(events: &mut Vec<Event>, side: Side, source: Source, rest: Bytes) -> Bytes {
+        if !rest.is_empty() {
+            events.push(Event::Error(
+                side,
+                source,
+                DecodeError::TrailingBytes(rest.len()),
+            ));
+        }
+
+        Bytes::new()
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns whether the command is allowed to be carried on `source` by `side`, as in the specification
+fn is_expected(side: Side, source: Source, header: &Header) -> bool {
+    match header {
+        Header::Authenticate(_) => side == Side::Client && source == Source::Uni,
+        Header::Dissociate(_) => side == Side::Client && source != Source::Datagram,
+        Header::DissociateAck(_) => side == Side::Server && source == Source::Bi,
+        Header::Connect(_) | Header::BindUdp(_) => side == Side::Client && source == Source::Bi,
+        Header::Packet(_) => source != Source::Bi,
+        Header::Parity(_) => source == Source::Datagram,
+        Header::Heartbeat(_) => source == Source::Datagram,
+        Header::Resume(_) => source == Source::Bi,
+        Header::Bind(_) => source == Source::Bi,
+        Header::Echo(_) => source == Source::Bi,
+        _ => false,
+    }
+}
+
+fn command_name(header: &Header) -> &'static str {
+    match header {
+        Header::Authenticate(_) => "Authenticate",
+        Header::Connect(_) => "Connect",
+        Header::Packet(_) => "Packet",
+        Header::Dissociate(_) => "Dissociate",
+        Header::Heartbeat(_) => "Heartbeat",
+        Header::BindUdp(_) => "BindUdp",
+        Header::DissociateAck(_) => "DissociateAck",
+        Header::Resume(_) => "Resume",
+        Header::Bind(_) => "Bind",
+        Header::Echo(_) => "Echo",
+        Header::Parity(_) => "Parity",
+        _ => "unknown",
+    }
+}
+
+/// A result of decoding a payload
+#[derive(Debug)]
+pub enum Event {
+    Command(Command),
+    /// A UDP packet completed with its last fragment received, or with a lost fragment recovered from its `Parity`
+    Reassembled(Reassembled),
+    /// Data without a command header, i.e. the TCP payload relayed by the server
+    Data {
+        side: Side,
+        payload: Bytes,
+    },
+    Error(Side, Source, DecodeError),
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Command(cmd) => write!(f, "{cmd}"),
+            Self::Reassembled(pkt) => write!(f, "{pkt}"),
+            Self::Data { side, payload } => {
+                write!(f, "[{side}] [bi] data ({} bytes)", payload.len())
+            }
+            Self::Error(side, source, err) => write!(f, "[{side}] [{source}] error: {err}"),
+        }
+    }
+}
+
+/// A decoded command, with the payload following it
+#[derive(Debug)]
+pub struct Command {
+    pub side: Side,
+    pub source: Source,
+    pub header: Header,
+    /// The TCP payload following `Connect`, the fragment of `Packet`, or the parity of `Parity`
+    pub payload: Bytes,
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "[{}] [{}] ", self.side, self.source)?;
+
+        match &self.header {
+            Header::Authenticate(auth) => {
+                write!(
+                    f,
+                    "Authenticate uuid={} token={}",
+                    auth.uuid(),
+                    encode_hex(&auth.token())
+                )?;
+
+                if let Some(bw) = auth.bandwidth() {
+                    write!(f, " up={}B/s down={}B/s", bw.up(), bw.down())?;
+                }
+
+                if auth.fec() {
+                    write!(f, " fec")?;
+                }
+
+                if let Some(min_size) = auth.compression() {
+                    write!(f, " compression={min_size}")?;
+                }
+
+                Ok(())
+            }
+            Header::Connect(conn) => {
+                write!(f, "Connect addr={}", conn.addr())?;
+
+                if let Some(hint) = conn.hint() {
+                    write!(f, " hint={hint}")?;
+                }
+
+                write!(f, " ({} bytes of payload)", self.payload.len())
+            }
+            Header::Packet(pkt) => {
+                write!(
+                    f,
+                    "Packet assoc_id={:#06x} pkt_id={:#06x} frag={}/{} size={}",
+                    pkt.assoc_id(),
+                    pkt.pkt_id(),
+                    pkt.frag_id() + 1,
+                    pkt.frag_total(),
+                    pkt.size()
+                )?;
+
+                if !pkt.addr().is_none() {
+                    write!(f, " addr={}", pkt.addr())?;
+                }
+
+                if pkt.is_compressed() {
+                    write!(f, " compressed")?;
+                }
+
+                Ok(())
+            }
+            Header::Parity(parity) => {
+                write!(
+                    f,
+                    "Parity assoc_id={:#06x} pkt_id={:#06x} frag_total={} pkt_size={} size={}",
+                    parity.assoc_id(),
+                    parity.pkt_id(),
+                    parity.frag_total(),
+                    parity.pkt_size(),
+                    parity.size()
+                )?;
+
+                if !parity.addr().is_none() {
+                    write!(f, " addr={}", parity.addr())?;
+                }
+
+                Ok(())
+            }
+            Header::Dissociate(dissoc) => {
+                write!(f, "Dissociate assoc_id={:#06x}", dissoc.assoc_id())
+            }
+            Header::Heartbeat(_) => write!(f, "Heartbeat"),
+            Header::DissociateAck(ack) => {
+                write!(f, "DissociateAck assoc_id={:#06x}", ack.assoc_id())
+            }
+            Header::Resume(resume) => write!(
+                f,
+                "Resume token={} assoc_ids={:04x?}",
+                encode_hex(&resume.token()),
+                resume.assoc_ids()
+            ),
+            Header::BindUdp(bind) => write!(
+                f,
+                "BindUdp assoc_id={:#06x} addr={}",
+                bind.assoc_id(),
+                bind.addr()
+            ),
+            Header::Bind(bind) => write!(
+                f,
+                "Bind bind_id={:#06x} addr={}",
+                bind.bind_id(),
+                bind.addr()
+            ),
+            Header::Echo(echo) => write!(
+                f,
+                "Echo len={} payload={}",
+                echo.payload().len(),
+                encode_hex(echo.payload())
+            ),
+            header => write!(f, "{header:?}"),
+        }
+    }
+}
+
+/// A UDP packet reassembled from its fragments
+#[derive(Debug)]
+pub struct Reassembled {
+    pub side: Side,
+    pub assoc_id: u16,
+    pub pkt_id: u16,
+    pub frag_total: u8,
+    pub addr: Address,
+    pub payload: Bytes,
+    /// Whether a lost fragment was recovered from the `Parity` of the packet
+    pub recovered: bool,
+}
+
+impl Display for Reassembled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "[{}] reassembled assoc_id={:#06x} pkt_id={:#06x} from {} fragments: {} bytes, addr={}",
+            self.side,
+            self.assoc_id,
+            self.pkt_id,
+            self.frag_total,
+            self.payload.len(),
+            self.addr
+        )?;
+
+        if self.recovered {
+            write!(f, " (recovered from parity)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Encodes the bytes in lowercase hexadecimal
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decodes the bytes from hexadecimal, ignoring whitespaces and `:` separators
+pub fn decode_hex(s: &str) -> Result<Vec<u8>, ParseError> {
+    let digits = s
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .map(|c| c.to_digit(16).ok_or(ParseError::Hex(c)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if digits.len() % 2 != 0 {
+        return Err(ParseError::OddHexLength);
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4 | pair[1]) as u8)
+        .collect())
+}
+
+/// Parses a line of the input format of `tuic-decode`, `<side> <source> <hex payload>`
+pub fn parse_line(line: &str) -> Result<(Side, Source, Vec<u8>), ParseError> {
+    let mut parts = line.split_whitespace();
+
+    let side = parts.next().ok_or(ParseError::MissingField("side"))?;
+    let source = parts.next().ok_or(ParseError::MissingField("source"))?;
+    let payload = parts.collect::<String>();
+
+    Ok((side.parse()?, source.parse()?, decode_hex(&payload)?))
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error(transparent)]
+    Unmarshal(#[from] UnmarshalError),
+    #[error("unexpected command {0} for the side and source")]
+    UnexpectedCommand(&'static str),
+    #[error("fragment truncated: expected {0} bytes, got {1}")]
+    Truncated(usize, usize),
+    #[error("{0} trailing bytes after the command")]
+    TrailingBytes(usize),
+    #[error(transparent)]
+    Assemble(#[from] AssembleError),
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("invalid side: {0}, expected `client` or `server`")]
+    Side(String),
+    #[error("invalid source: {0}, expected `uni`, `bi` or `datagram`")]
+    Source(String),
+    #[error("missing {0}")]
+    MissingField(&'static str),
+    #[error("invalid hex digit: {0:?}")]
+    Hex(char),
+    #[error("odd number of hex digits")]
+    OddHexLength,
+}
@@ -0,0 +1,116 @@
+use lexopt::{Arg, Parser};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+    process,
+};
+use tuic_decode::{Decoder, Event};
+
+const HELP_MSG: &str = r#"
+Usage tuic-decode [arguments] [path]
+
+Decodes the TUIC commands in decrypted QUIC payloads read from the file, or stdin if not given
+
+Each line of the input is one payload, in the format of `<side> <source> <hex>`:
+
+    <side>      The endpoint sending the payload, `client` or `server`
+    <source>    `uni` for all data of a unidirectional stream, `bi` for all data sent on one direction of a bidirectional stream, or `datagram`
+    <hex>       The payload in hex
+
+Empty lines and lines starting with `#` are ignored
+
+Arguments:
+    -p, --payload           Print the payloads following the commands and the reassembled UDP packets in hex
+    -v, --version           Print the version
+    -h, --help              Print this help message
+"#;
+
+fn main() {
+    let (path, print_payload) = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+
+    let input: Box<dyn BufRead> = match path {
+        Some(path) => match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(err) => {
+                eprintln!("failed to open {}: {err}", path.display());
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+
+    let decoder = Decoder::new();
+
+    for (idx, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(1);
+            }
+        };
+
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (side, source, payload) = match tuic_decode::parse_line(line) {
+            Ok(res) => res,
+            Err(err) => {
+                println!("line {}: {err}", idx + 1);
+                continue;
+            }
+        };
+
+        for event in decoder.decode(side, source, &payload) {
+            println!("{event}");
+
+            if print_payload {
+                let payload = match &event {
+                    Event::Command(cmd) => &cmd.payload,
+                    Event::Reassembled(pkt) => &pkt.payload,
+                    Event::Data { payload, .. } => payload,
+                    Event::Error(..) => continue,
+                };
+
+                if !payload.is_empty() {
+                    println!("    {}", tuic_decode::encode_hex(payload));
+                }
+            }
+        }
+    }
+}
+
+fn parse_args() -> Result<(Option<PathBuf>, bool), lexopt::Error> {
+    let mut parser = Parser::from_env();
+    let mut path = None;
+    let mut print_payload = false;
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Arg::Short('p') | Arg::Long("payload") => print_payload = true,
+            Arg::Short('v') | Arg::Long("version") => {
+                println!("{}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            Arg::Short('h') | Arg::Long("help") => {
+                println!("{HELP_MSG}");
+                process::exit(0);
+            }
+            Arg::Value(val) if path.is_none() => path = Some(PathBuf::from(val)),
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    // `-` reads from stdin, as the default
+    Ok((path.filter(|path| path.as_os_str() != "-"), print_payload))
+}
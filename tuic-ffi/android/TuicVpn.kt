@@ -0,0 +1,30 @@
+package tuic
+
+import android.net.VpnService
+
+/**
+ * Runs the TUIC client in a [VpnService]
+ *
+ * The sockets of the client are protected with [VpnService.protect], and IP packets are read from the TUN device
+ * established with [VpnService.Builder]. The config is in JSON, in the same format as the config file of tuic-client.
+ */
+object TuicVpn {
+    init {
+        System.loadLibrary("tuic_ffi")
+    }
+
+    /** Throws [IllegalStateException] if the client fails to start */
+    @JvmStatic
+    external fun start(service: VpnService, config: String, tunFd: Int, mtu: Int)
+
+    /**
+     * Migrates the connections to the relay servers onto new sockets, to call from
+     * [android.net.ConnectivityManager.NetworkCallback] when the default network changes
+     */
+    @JvmStatic
+    external fun rebind()
+
+    /** Stops the client, releasing the TUN device */
+    @JvmStatic
+    external fun stop()
+}
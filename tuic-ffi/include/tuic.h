@@ -0,0 +1,82 @@
+/*
+ * C ABI of the TUIC client
+ *
+ * Functions returning int32_t return TUIC_OK on success, or TUIC_ERROR with the
+ * error message available from tuic_last_error() on the calling thread.
+ * Strings returned are owned by the caller and must be freed with
+ * tuic_string_free(), except the one returned by tuic_version().
+ */
+
+#ifndef TUIC_H
+#define TUIC_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TUIC_OK 0
+#define TUIC_ERROR -1
+
+/* Starts the client with the config in JSON, in the same format as the config file of tuic-client */
+int32_t tuic_start(const char *config);
+
+/* Stops the client, waiting until the system proxy is restored and the listeners are closed */
+int32_t tuic_stop(void);
+
+bool tuic_is_running(void);
+
+/* Sets the routing mode, "rule", "global" or "direct", taking effect for new connections */
+int32_t tuic_set_routing_mode(const char *mode);
+
+/* Returns the routing mode */
+char *tuic_routing_mode(void);
+
+/* Returns the traffic statistics in JSON, in the format of "/stats" of the controller */
+char *tuic_stats(void);
+
+/*
+ * Sets the listener called with each event of the client in JSON and ctx, replacing the current one, or removes it with NULL.
+ * The event string is only valid during the call, and must not be freed. Events are "connected", "reconnecting",
+ * "server_switched", "auth_failed" and "traffic" every second, in the format of "/events" of the controller
+ */
+void tuic_set_event_listener(void (*listener)(const char *event, void *ctx), void *ctx);
+
+/* Converts a tuic:// share link into the relay config in JSON, or returns NULL on error */
+char *tuic_import_share_link(const char *link);
+
+/*
+ * Sets the protector called with each outbound socket and ctx before use, to exclude it from the TUN device,
+ * e.g. with VpnService.protect() on Android. It should return false if the socket could not be protected.
+ * Set it before starting the client. Not available on Windows
+ */
+void tuic_set_socket_protector(bool (*protect)(int32_t fd, void *ctx), void *ctx);
+
+/*
+ * Starts reading IP packets from the TUN device with the MTU, replacing the current one. The file descriptor is
+ * duplicated, so the caller keeps the ownership of it. The device is released when the client stops. Not available on Windows
+ */
+int32_t tuic_set_tun(int32_t fd, uint16_t mtu);
+
+/*
+ * Migrates the connections to the relay servers onto new sockets, to call when the network changes, e.g. from Wi-Fi to
+ * cellular. The connections and the UDP associations relayed through them are kept
+ */
+int32_t tuic_rebind(void);
+
+/* Returns the message of the last error on the calling thread, or NULL if there is none */
+char *tuic_last_error(void);
+
+/* Returns the version of the library, as a static string not to be freed */
+const char *tuic_version(void);
+
+/* Frees a string returned by this library */
+void tuic_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TUIC_H */
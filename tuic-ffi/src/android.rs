@@ -0,0 +1,53 @@
+//! JNI functions of `tuic.TuicVpn` in `android/TuicVpn.kt`, for running the client in a `VpnService`
+
+use crate::engine::{self, TuicError};
+use jni::{
+    objects::{JClass, JObject, JString},
+    sys::jint,
+    JNIEnv,
+};
+
+/// Starts the client in the `VpnService`, reading IP packets from the TUN device established by it
+///
+/// Throws `IllegalStateException` on error.
+#[no_mangle]
+pub extern "system" fn Java_tuic_TuicVpn_start(
+    mut env: JNIEnv,
+    _class: JClass,
+    service: JObject,
+    config: JString,
+    tun_fd: jint,
+    mtu: jint,
+) {
+    let res = env
+        .get_string(&config)
+        .map(String::from)
+        .map_err(|err| TuicError::Client(err.into()))
+        .and_then(|config| {
+            engine::start_with(&config, || {
+                let mtu = u16::try_from(mtu).unwrap_or(u16::MAX);
+                tuic_client::android::set_vpn_service(&mut env, &service, tun_fd, mtu)?;
+                Ok(())
+            })
+        });
+
+    if let Err(err) = res {
+        let _ = env.throw_new("java/lang/IllegalStateException", err.to_string());
+    }
+}
+
+/// Migrates the connections to the relay servers onto new sockets, to call when the default network changes
+#[no_mangle]
+pub extern "system" fn Java_tuic_TuicVpn_rebind(mut env: JNIEnv, _class: JClass) {
+    if let Err(err) = engine::rebind() {
+        let _ = env.throw_new("java/lang/IllegalStateException", err.to_string());
+    }
+}
+
+/// Stops the client, releasing the TUN device
+#[no_mangle]
+pub extern "system" fn Java_tuic_TuicVpn_stop(mut env: JNIEnv, _class: JClass) {
+    if let Err(err) = engine::stop() {
+        let _ = env.throw_new("java/lang/IllegalStateException", err.to_string());
+    }
+}
@@ -0,0 +1,174 @@
+//! The client engine shared by the C ABI and the UniFFI bindings
+
+use env_logger::Builder as LoggerBuilder;
+use log::LevelFilter;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::sync::Once;
+use thiserror::Error;
+use tokio::{
+    runtime::{Builder as RuntimeBuilder, Runtime},
+    sync::{
+        broadcast::error::RecvError,
+        oneshot::{self, Sender},
+    },
+    task::{JoinError, JoinHandle},
+};
+use tuic_client::{Config, ConfigError, Mode};
+
+#[cfg(unix)]
+use std::os::fd::RawFd;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    RuntimeBuilder::new_multi_thread()
+        .enable_all()
+        .thread_name("tuic")
+        .build()
+        .expect("failed to build the TUIC runtime")
+});
+
+/// The running client, with the sender for stopping it
+static CLIENT: Mutex<Option<(Sender<()>, JoinHandle<()>)>> = Mutex::new(None);
+
+/// The task calling the event listener
+static EVENT_LISTENER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+static LOGGER: Once = Once::new();
+
+#[derive(Debug, Error)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
+pub enum TuicError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Client(#[from] tuic_client::Error),
+    #[error("the client is already running")]
+    AlreadyRunning,
+    #[error("the client is not running")]
+    NotRunning,
+    #[error("invalid routing mode: {0}")]
+    InvalidMode(String),
+    #[error("failed to stop the client: {0}")]
+    Stop(#[from] JoinError),
+}
+
+pub fn start(config: &str) -> Result<(), TuicError> {
+    start_with(config, || Ok(()))
+}
+
+/// Starts the client, calling `setup` within the runtime before setting it up
+pub fn start_with(
+    config: &str,
+    setup: impl FnOnce() -> Result<(), TuicError>,
+) -> Result<(), TuicError> {
+    let cfg = Config::from_json(config)?;
+    let mut client = CLIENT.lock();
+
+    if client.is_some() {
+        return Err(TuicError::AlreadyRunning);
+    }
+
+    LOGGER.call_once(|| {
+        let _ = LoggerBuilder::new()
+            .filter_level(LevelFilter::Trace)
+            .format_module_path(false)
+            .format_target(false)
+            .try_init();
+    });
+
+    log::set_max_level(cfg.log_level);
+
+    // the local listeners are bound when setting up, so they need the runtime
+    let _guard = RUNTIME.enter();
+    setup()?;
+    tuic_client::set_config(cfg)?;
+
+    let (tx, rx) = oneshot::channel();
+    let handle = RUNTIME.spawn(tuic_client::run(async {
+        let _ = rx.await;
+    }));
+
+    *client = Some((tx, handle));
+    Ok(())
+}
+
+pub fn stop() -> Result<(), TuicError> {
+    let (tx, handle) = CLIENT.lock().take().ok_or(TuicError::NotRunning)?;
+    let _ = tx.send(());
+    RUNTIME.block_on(handle)?;
+    Ok(())
+}
+
+pub fn is_running() -> bool {
+    CLIENT.lock().is_some()
+}
+
+pub fn mode() -> Mode {
+    tuic_client::mode()
+}
+
+pub fn set_mode(mode: &str) -> Result<(), TuicError> {
+    let mode = mode
+        .parse::<Mode>()
+        .map_err(|_| TuicError::InvalidMode(mode.to_owned()))?;
+
+    tuic_client::set_mode(mode);
+    Ok(())
+}
+
+pub fn stats() -> String {
+    tuic_client::stats().to_string()
+}
+
+/// Calls the listener with each event of the client in JSON, replacing the current one, or removes it with `None`
+pub fn set_event_listener(listener: Option<impl Fn(String) + Send + 'static>) {
+    let mut current = EVENT_LISTENER.lock();
+
+    if let Some(task) = current.take() {
+        task.abort();
+    }
+
+    if let Some(listener) = listener {
+        let mut events = tuic_client::subscribe_events();
+
+        *current = Some(RUNTIME.spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => listener(event.to_json().to_string()),
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }));
+    }
+}
+
+pub fn rebind() -> Result<(), TuicError> {
+    if !is_running() {
+        return Err(TuicError::NotRunning);
+    }
+
+    let _guard = RUNTIME.enter();
+    tuic_client::rebind()?;
+    Ok(())
+}
+
+pub fn import_share_link(link: &str) -> Result<String, TuicError> {
+    Ok(tuic_client::import_share_link(link)?.to_string())
+}
+
+#[cfg(unix)]
+pub fn set_socket_protector(protector: impl Fn(RawFd) -> bool + Send + Sync + 'static) {
+    tuic_client::set_socket_protector(protector);
+}
+
+#[cfg(unix)]
+pub fn set_tun(fd: RawFd, mtu: u16) -> Result<(), TuicError> {
+    if !is_running() {
+        return Err(TuicError::NotRunning);
+    }
+
+    let _guard = RUNTIME.enter();
+    tuic_client::set_tun(fd, mtu)?;
+    Ok(())
+}
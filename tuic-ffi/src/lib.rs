@@ -0,0 +1,245 @@
+//! C ABI of the TUIC client, for embedding it in applications written in other languages
+//!
+//! The client runs on a runtime owned by this library. Functions returning `int32_t` return `TUIC_OK` on success, or `TUIC_ERROR` with the error message available from `tuic_last_error()` on the calling thread. Strings returned are owned by the caller and must be freed with `tuic_string_free()`.
+//!
+//! See `include/tuic.h` for the C declarations. With feature `uniffi`, the library also exports the UniFFI scaffolding for generating the Kotlin and Swift bindings.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    fmt::Display,
+    ptr,
+};
+
+#[cfg(target_os = "android")]
+mod android;
+mod engine;
+
+#[cfg(feature = "uniffi")]
+mod mobile;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!("tuic");
+
+pub const TUIC_OK: i32 = 0;
+pub const TUIC_ERROR: i32 = -1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Starts the client with the config in JSON, in the same format as the config file of `tuic-client`
+///
+/// # Safety
+///
+/// `config` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tuic_start(config: *const c_char) -> i32 {
+    let Some(config) = str_arg(config, "config") else {
+        return TUIC_ERROR;
+    };
+
+    result(engine::start(config))
+}
+
+/// Stops the client, waiting until the system proxy is restored and the listeners are closed
+#[no_mangle]
+pub extern "C" fn tuic_stop() -> i32 {
+    result(engine::stop())
+}
+
+#[no_mangle]
+pub extern "C" fn tuic_is_running() -> bool {
+    engine::is_running()
+}
+
+/// Sets the routing mode, `"rule"`, `"global"` or `"direct"`, taking effect for new connections
+///
+/// # Safety
+///
+/// `mode` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tuic_set_routing_mode(mode: *const c_char) -> i32 {
+    let Some(mode) = str_arg(mode, "mode") else {
+        return TUIC_ERROR;
+    };
+
+    result(engine::set_mode(mode))
+}
+
+/// Returns the routing mode
+#[no_mangle]
+pub extern "C" fn tuic_routing_mode() -> *mut c_char {
+    string(engine::mode().to_string())
+}
+
+/// Returns the traffic statistics in JSON, in the format of `/stats` of the controller
+#[no_mangle]
+pub extern "C" fn tuic_stats() -> *mut c_char {
+    string(engine::stats())
+}
+
+/// Sets the listener called with each event of the client in JSON and `ctx`, replacing the current one, or removes it with `NULL`
+///
+/// The event string is only valid during the call, and must not be freed. Events are `connected`, `reconnecting`, `server_switched`, `auth_failed` and `traffic` every second, in the format of `/events` of the controller. The listener is kept across restarts of the client.
+///
+/// # Safety
+///
+/// `listener` must be safe to call with `ctx` from any thread, until the listener is replaced.
+#[no_mangle]
+pub unsafe extern "C" fn tuic_set_event_listener(
+    listener: Option<extern "C" fn(event: *const c_char, ctx: *mut c_void)>,
+    ctx: *mut c_void,
+) {
+    struct Context(*mut c_void);
+
+    // SAFETY: the caller guarantees `ctx` to be usable from any thread
+    unsafe impl Send for Context {}
+
+    impl Context {
+        fn get(&self) -> *mut c_void {
+            self.0
+        }
+    }
+
+    let ctx = Context(ctx);
+
+    engine::set_event_listener(listener.map(|listener| {
+        move |event: String| {
+            if let Ok(event) = CString::new(event) {
+                listener(event.as_ptr(), ctx.get());
+            }
+        }
+    }));
+}
+
+/// Converts a `tuic://` share link into the relay config in JSON, or returns `NULL` on error
+///
+/// # Safety
+///
+/// `link` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tuic_import_share_link(link: *const c_char) -> *mut c_char {
+    let Some(link) = str_arg(link, "link") else {
+        return ptr::null_mut();
+    };
+
+    match engine::import_share_link(link) {
+        Ok(relay) => string(relay),
+        Err(err) => {
+            fail(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Sets the protector called with each outbound socket and `ctx` before use, to exclude it from the TUN device, e.g. with `VpnService.protect()` on Android
+///
+/// It should return `false` if the socket could not be protected. Set it before starting the client.
+///
+/// # Safety
+///
+/// `protect` must be safe to call with `ctx` from any thread, until the protector is replaced.
+#[cfg(unix)]
+#[no_mangle]
+pub unsafe extern "C" fn tuic_set_socket_protector(
+    protect: extern "C" fn(fd: i32, ctx: *mut c_void) -> bool,
+    ctx: *mut c_void,
+) {
+    struct Context(*mut c_void);
+
+    // SAFETY: the caller guarantees `ctx` to be usable from any thread
+    unsafe impl Send for Context {}
+    unsafe impl Sync for Context {}
+
+    impl Context {
+        fn get(&self) -> *mut c_void {
+            self.0
+        }
+    }
+
+    let ctx = Context(ctx);
+    engine::set_socket_protector(move |fd| protect(fd, ctx.get()));
+}
+
+/// Starts reading IP packets from the TUN device with the MTU, replacing the current one
+///
+/// The file descriptor is duplicated, so the caller keeps the ownership of it. The device is released when the client stops.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "C" fn tuic_set_tun(fd: i32, mtu: u16) -> i32 {
+    result(engine::set_tun(fd, mtu))
+}
+
+/// Migrates the connections to the relay servers onto new sockets, to call when the network changes, e.g. from Wi-Fi to cellular
+///
+/// The connections and the UDP associations relayed through them are kept.
+#[no_mangle]
+pub extern "C" fn tuic_rebind() -> i32 {
+    result(engine::rebind())
+}
+
+/// Returns the message of the last error on the calling thread, or `NULL` if there is none
+#[no_mangle]
+pub extern "C" fn tuic_last_error() -> *mut c_char {
+    LAST_ERROR.with(|err| {
+        err.borrow()
+            .as_ref()
+            .map_or(ptr::null_mut(), |err| err.clone().into_raw())
+    })
+}
+
+/// Returns the version of the library, as a static string not to be freed
+#[no_mangle]
+pub extern "C" fn tuic_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Frees a string returned by this library
+///
+/// # Safety
+///
+/// `s` must be a string returned by this library and not freed yet, or `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn tuic_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Reads a string argument, recording the error if it is `NULL` or not valid UTF-8
+///
+/// # Safety
+///
+/// `s` must be a valid NUL-terminated string or `NULL`.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Option<&'a str> {
+    if s.is_null() {
+        fail(format!("`{name}` is NULL"));
+        return None;
+    }
+
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            fail(format!("`{name}` is not valid UTF-8"));
+            None
+        }
+    }
+}
+
+fn result(res: Result<(), engine::TuicError>) -> i32 {
+    match res {
+        Ok(()) => TUIC_OK,
+        Err(err) => fail(err),
+    }
+}
+
+fn fail(err: impl Display) -> i32 {
+    let err = err.to_string().replace('\0', "");
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(err).ok());
+    TUIC_ERROR
+}
+
+fn string(s: String) -> *mut c_char {
+    CString::new(s).map_or(ptr::null_mut(), CString::into_raw)
+}
@@ -0,0 +1,116 @@
+//! UniFFI bindings, for generating the Kotlin and Swift APIs of the library
+//!
+//! Errors are raised as `TuicException` in Kotlin and thrown as `TuicError` in Swift.
+
+use crate::engine::{self, TuicError};
+use tuic_client::Mode;
+
+#[derive(uniffi::Enum)]
+pub enum RoutingMode {
+    Rule,
+    Global,
+    Direct,
+}
+
+impl From<Mode> for RoutingMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Rule => Self::Rule,
+            Mode::Global => Self::Global,
+            Mode::Direct => Self::Direct,
+        }
+    }
+}
+
+/// Protects the sockets of the client from being routed back into the TUN device, e.g. by calling `VpnService.protect()` on Android
+#[uniffi::export(callback_interface)]
+pub trait SocketProtector: Send + Sync {
+    /// Returns `false` if the socket could not be protected
+    fn protect(&self, fd: i32) -> bool;
+}
+
+/// Receives the events of the client, e.g. for a GUI to show the state of the connections and the traffic
+#[uniffi::export(callback_interface)]
+pub trait EventListener: Send + Sync {
+    /// Called with each event in JSON, in the format of `/events` of the controller
+    fn on_event(&self, event: String);
+}
+
+/// Starts the client with the config in JSON, in the same format as the config file of `tuic-client`
+#[uniffi::export]
+pub fn start(config: String) -> Result<(), TuicError> {
+    engine::start(&config)
+}
+
+/// Stops the client, releasing the TUN device
+#[uniffi::export]
+pub fn stop() -> Result<(), TuicError> {
+    engine::stop()
+}
+
+#[uniffi::export]
+pub fn is_running() -> bool {
+    engine::is_running()
+}
+
+/// Sets the protector called with each outbound socket before use. Set it before starting the client
+#[cfg(unix)]
+#[uniffi::export]
+pub fn set_socket_protector(protector: Box<dyn SocketProtector>) {
+    engine::set_socket_protector(move |fd| protector.protect(fd));
+}
+
+/// Starts reading IP packets from the TUN device with the MTU, e.g. the file descriptor of the `ParcelFileDescriptor` established with `VpnService.Builder` on Android
+///
+/// The file descriptor is duplicated, so the caller keeps the ownership of it.
+#[cfg(unix)]
+#[uniffi::export]
+pub fn set_tun(fd: i32, mtu: u16) -> Result<(), TuicError> {
+    engine::set_tun(fd, mtu)
+}
+
+/// Migrates the connections to the relay servers onto new sockets, to call when the network changes, e.g. from `ConnectivityManager.NetworkCallback` on Android
+#[uniffi::export]
+pub fn rebind() -> Result<(), TuicError> {
+    engine::rebind()
+}
+
+#[uniffi::export]
+pub fn routing_mode() -> RoutingMode {
+    RoutingMode::from(engine::mode())
+}
+
+/// Sets the routing mode, taking effect for new connections
+#[uniffi::export]
+pub fn set_routing_mode(mode: RoutingMode) {
+    let mode = match mode {
+        RoutingMode::Rule => Mode::Rule,
+        RoutingMode::Global => Mode::Global,
+        RoutingMode::Direct => Mode::Direct,
+    };
+
+    tuic_client::set_mode(mode);
+}
+
+/// Returns the traffic statistics in JSON, in the format of `/stats` of the controller
+#[uniffi::export]
+pub fn stats() -> String {
+    engine::stats()
+}
+
+/// Sets the listener of the events of the client, replacing the current one, or removes it with `null` / `nil`. It is kept across restarts of the client
+#[uniffi::export]
+pub fn set_event_listener(listener: Option<Box<dyn EventListener>>) {
+    engine::set_event_listener(listener.map(|listener| move |event| listener.on_event(event)));
+}
+
+/// Converts a `tuic://` share link into the relay config in JSON, to be put in `relay` of the config
+#[uniffi::export]
+pub fn import_share_link(link: String) -> Result<String, TuicError> {
+    engine::import_share_link(&link)
+}
+
+#[uniffi::export]
+pub fn version() -> String {
+    String::from(env!("CARGO_PKG_VERSION"))
+}
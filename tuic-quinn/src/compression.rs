@@ -0,0 +1,192 @@
+//! Compression of relayed UDP packets, negotiated by the `Compression` extension of `Authenticate`.
+//!
+//! A compressed UDP packet is an [LZ4 block](https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md), with its fragments sent in `Packet` type `0x0c`. Packets of at least the negotiated size are compressed, unless that does not make them smaller.
+//!
+//! The client declares compression and accepts compressed packets from then on. A server accepting it compresses the first packet it sends regardless of its size, which acknowledges compression to the client. The client only compresses its packets after receiving a compressed one.
+
+use lz4_flex::block;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+/// The maximum length of a decompressed packet
+const MAX_PKT_LEN: usize = u16::MAX as usize;
+
+#[derive(Debug)]
+pub(crate) struct Compression {
+    /// Whether compressed packets are accepted
+    accepted: AtomicBool,
+    /// Whether sent packets are compressed
+    active: AtomicBool,
+    /// Whether the next sent packet is compressed regardless of its size, to acknowledge compression
+    ack_pending: AtomicBool,
+    min_size: AtomicU16,
+}
+
+impl Compression {
+    pub(crate) fn new() -> Self {
+        Self {
+            accepted: AtomicBool::new(false),
+            active: AtomicBool::new(false),
+            ack_pending: AtomicBool::new(false),
+            min_size: AtomicU16::new(0),
+        }
+    }
+
+    /// Declares compression on the client side. Packets are compressed once a compressed packet is received
+    pub(crate) fn declare(&self, min_size: u16) {
+        self.min_size.store(min_size, Ordering::Relaxed);
+        self.accepted.store(true, Ordering::Release);
+    }
+
+    /// Accepts compression on the server side, compressing the next packet to acknowledge it
+    pub(crate) fn accept(&self, min_size: u16) {
+        self.min_size.store(min_size, Ordering::Relaxed);
+        self.ack_pending.store(true, Ordering::Relaxed);
+        self.accepted.store(true, Ordering::Release);
+        self.active.store(true, Ordering::Release);
+    }
+
+    /// Returns the minimum size of packets to compress, if compression is declared or accepted
+    pub(crate) fn min_size(&self) -> Option<u16> {
+        self.accepted
+            .load(Ordering::Acquire)
+            .then(|| self.min_size.load(Ordering::Relaxed))
+    }
+
+    /// Compresses the packet if compression is active and it is large enough, or if it acknowledges compression. Returns `None` if the packet is to be sent as is
+    pub(crate) fn compress(&self, pkt: &[u8]) -> Option<Vec<u8>> {
+        if !self.active.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let min_size = self.min_size.load(Ordering::Relaxed) as usize;
+
+        if pkt.len() < min_size && !self.ack_pending.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let buf = block::compress(pkt);
+
+        if self.ack_pending.swap(false, Ordering::Relaxed) || buf.len() < pkt.len() {
+            Some(buf)
+        } else {
+            None
+        }
+    }
+
+    /// Decompresses a compressed packet, which also acknowledges compression on the client side. Returns `None` if compression is not accepted or the packet is malformed
+    pub(crate) fn decompress(&self, pkt: &[u8]) -> Option<Vec<u8>> {
+        if !self.accepted.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let buf = block::decompress(pkt, MAX_PKT_LEN).ok()?;
+        self.active.store(true, Ordering::Release);
+        Some(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(len: usize) -> Vec<u8> {
+        b"tuic relays udp packets over quic. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn server() -> Compression {
+        let comp = Compression::new();
+        comp.accept(64);
+        comp
+    }
+
+    #[test]
+    fn round_trip() {
+        let (client, server) = (Compression::new(), server());
+        client.declare(64);
+
+        // a run of a single byte is encoded with matches overlapping their own output
+        for pkt in [text(1200), vec![0; 1200], text(MAX_PKT_LEN)] {
+            let buf = server.compress(&pkt).unwrap();
+            assert!(buf.len() < pkt.len());
+            assert_eq!(client.decompress(&buf).unwrap(), pkt);
+        }
+    }
+
+    #[test]
+    fn not_compressed_before_ack() {
+        let (client, server) = (Compression::new(), server());
+        client.declare(64);
+        assert_eq!(client.min_size(), Some(64));
+
+        assert!(client.compress(&text(1200)).is_none());
+
+        // the first packet of the server is compressed even if small or incompressible
+        let ack = server.compress(&noise(16)).unwrap();
+        assert_eq!(client.decompress(&ack).unwrap(), noise(16));
+        assert!(server.compress(&noise(16)).is_none());
+        assert!(server.compress(&noise(1200)).is_none());
+
+        let buf = client.compress(&text(1200)).unwrap();
+        assert_eq!(server.decompress(&buf).unwrap(), text(1200));
+        assert!(client.compress(&text(16)).is_none());
+    }
+
+    #[test]
+    fn not_accepted() {
+        let (comp, server) = (Compression::new(), server());
+        let buf = server.compress(&text(1200)).unwrap();
+
+        assert!(comp.min_size().is_none());
+        assert!(comp.compress(&text(1200)).is_none());
+        assert!(comp.decompress(&buf).is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        let (client, server) = (Compression::new(), server());
+        client.declare(64);
+
+        let buf = server.compress(&text(1200)).unwrap();
+
+        // truncated within the trailing literals, and within the first sequence
+        assert!(client.decompress(&buf[..buf.len() - 1]).is_none());
+        assert!(client.decompress(&buf[..1]).is_none());
+        assert!(client.decompress(&[0xf0]).is_none());
+    }
+
+    #[test]
+    fn offset_past_output() {
+        let client = Compression::new();
+        client.declare(64);
+
+        // one literal followed by a match 2 bytes back
+        assert!(client.decompress(&[0x10, b'a', 2, 0, 0x10, b'b']).is_none());
+    }
+
+    #[test]
+    fn too_large() {
+        let client = Compression::new();
+        client.declare(64);
+
+        let buf = block::compress(&[0; MAX_PKT_LEN + 1]);
+        assert!(client.decompress(&buf).is_none());
+    }
+}
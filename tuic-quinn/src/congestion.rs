@@ -0,0 +1,172 @@
+//! A fixed-rate congestion controller, for links where loss-based and model-based controllers under-utilize the bandwidth.
+
+use quinn::{
+    congestion::{Controller, ControllerFactory},
+    Connection as QuinnConnection,
+};
+use quinn_proto::RttEstimator;
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The number of one-second slots the ack rate is measured over
+const SLOTS: usize = 5;
+
+/// The minimum bytes acked and lost in the slots for the ack rate to be measured
+const MIN_SAMPLE_BYTES: u64 = 50 * 1200;
+
+/// The lowest ack rate compensated for, so that a heavily congested link is not flooded
+const MIN_ACK_RATE: f64 = 0.8;
+
+/// The minimum window, in packets
+const MIN_WINDOW_PACKETS: u64 = 16;
+
+/// Configuration for the [`Brutal`] congestion controller
+#[derive(Clone, Debug)]
+pub struct BrutalConfig {
+    rate: u64,
+}
+
+impl BrutalConfig {
+    /// Creates a configuration sending at `rate` bytes per second, until changed with [`Brutal::set_rate`]
+    pub fn new(rate: u64) -> Self {
+        Self { rate }
+    }
+}
+
+impl ControllerFactory for BrutalConfig {
+    fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Brutal::new(self.rate, now, current_mtu))
+    }
+}
+
+/// A congestion controller sending at a fixed rate regardless of the RTT and loss, compensating for the loss by sending more
+///
+/// The window is the rate multiplied by the smoothed RTT, divided by the ratio of the bytes acked in the last few seconds. It should only be used with a rate known to be available on the link, as it never backs off.
+#[derive(Clone, Debug)]
+pub struct Brutal {
+    rate: Arc<AtomicU64>,
+    epoch: Instant,
+    rtt: Option<Duration>,
+    mtu: u16,
+    slots: [Slot; SLOTS],
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Slot {
+    sec: u64,
+    acked: u64,
+    lost: u64,
+}
+
+impl Brutal {
+    fn new(rate: u64, now: Instant, mtu: u16) -> Self {
+        Self {
+            rate: Arc::new(AtomicU64::new(rate)),
+            epoch: now,
+            rtt: None,
+            mtu,
+            slots: [Slot::default(); SLOTS],
+        }
+    }
+
+    /// Changes the sending rate of the connection, in bytes per second, e.g. to the bandwidth declared by the peer
+    ///
+    /// Returns `false` if the connection is not using the `Brutal` congestion controller. A rate set this way is lost when the connection migrates to a new path, which starts over with the rate of the [`BrutalConfig`].
+    pub fn set_rate(conn: &QuinnConnection, rate: u64) -> bool {
+        // the controller state cloned out of the connection shares the rate with the original one
+        match conn.congestion_state().into_any().downcast::<Self>() {
+            Ok(brutal) => {
+                brutal.rate.store(rate, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn slot(&mut self, now: Instant) -> &mut Slot {
+        let sec = now.saturating_duration_since(self.epoch).as_secs();
+        let slot = &mut self.slots[sec as usize % SLOTS];
+
+        if slot.sec != sec {
+            *slot = Slot {
+                sec,
+                ..Slot::default()
+            };
+        }
+
+        slot
+    }
+
+    fn ack_rate(&self) -> f64 {
+        let (acked, lost) = self.slots.iter().fold((0, 0), |(acked, lost), slot| {
+            (acked + slot.acked, lost + slot.lost)
+        });
+
+        if acked + lost < MIN_SAMPLE_BYTES {
+            return 1.0;
+        }
+
+        (acked as f64 / (acked + lost) as f64).max(MIN_ACK_RATE)
+    }
+
+    fn min_window(&self) -> u64 {
+        MIN_WINDOW_PACKETS * self.mtu as u64
+    }
+}
+
+impl Controller for Brutal {
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        _sent: Instant,
+        bytes: u64,
+        _app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.rtt = Some(rtt.get());
+        self.slot(now).acked += bytes;
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        _sent: Instant,
+        _is_persistent_congestion: bool,
+        lost_bytes: u64,
+    ) {
+        self.slot(now).lost += lost_bytes;
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.mtu = new_mtu;
+    }
+
+    fn window(&self) -> u64 {
+        let Some(rtt) = self.rtt else {
+            return self.initial_window();
+        };
+
+        let rate = self.rate.load(Ordering::Relaxed);
+        let window = rate as f64 * rtt.as_secs_f64() / self.ack_rate();
+
+        (window as u64).max(self.min_window())
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.min_window()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
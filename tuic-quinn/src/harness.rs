@@ -0,0 +1,416 @@
+//! A harness running TUIC connections over a [`MockNetwork`], for reproducing relay issues under injected loss, latency, reordering and NAT timeouts.
+//!
+//! The server side of a [`Harness`] runs a relay loop echoing every `Connect` stream and UDP packet back to the client in the mode it came in, with the garbage collection of fragments running as on a real server. The client side is a [`Connection`] driven by the scenario, with the packets echoed back collected by [`HarnessClient::recv_packet`].
+//!
+//! Scenarios run on wall-clock time, as quinn reads the system clock, so they are neither instant nor exactly reproducible in their timing. Scenarios over long periods, like "UDP breaks after 30 seconds idle", are reproduced by scaling down the timeouts involved, e.g. a NAT timeout of 300 milliseconds against a keep-alive interval of 100 milliseconds.
+
+use crate::{
+    mock::MockNetwork,
+    side::{Client, Server},
+    CloseCode, Connection, Error, Packet, Task,
+};
+use bytes::Bytes;
+use futures_util::{
+    future::{self, Either},
+    pin_mut,
+    task::AtomicWaker,
+    AsyncReadExt, AsyncWriteExt,
+};
+use quinn::{Connection as QuinnConnection, Endpoint, Runtime, VarInt};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+use tuic::Address;
+use uuid::Uuid;
+
+/// The buffer size of echoing a `Connect` stream
+const ECHO_BUF_SIZE: usize = 16 * 1024;
+
+/// A TUIC server relay loop and the client endpoint connecting to it, both on a [`MockNetwork`]
+pub struct Harness {
+    runtime: Arc<dyn Runtime>,
+    server_addr: SocketAddr,
+    server_name: String,
+    client: Endpoint,
+    relay: Arc<Relay>,
+}
+
+/// The state shared by the relay loops of the server
+struct Relay {
+    runtime: Arc<dyn Runtime>,
+    uuid: Uuid,
+    password: Box<[u8]>,
+    /// How often fragments are garbage collected, and how long they live
+    gc: Mutex<(Duration, Duration)>,
+    /// How long UDP packets are held before being echoed back
+    echo_delay: Mutex<Duration>,
+}
+
+impl Harness {
+    /// Starts the relay loop with the user on the server endpoint, to be connected to from the client endpoint
+    ///
+    /// Both endpoints should be created with [`MockNetwork::endpoint`], and the default client config of `client` must trust the certificate of `server` for `server_name`. Fragments are garbage collected every 3 seconds after a lifetime of 15 seconds, unless changed with [`Harness::set_gc`].
+    pub fn new(
+        net: &MockNetwork,
+        server: Endpoint,
+        client: Endpoint,
+        server_name: impl Into<String>,
+        user: (Uuid, impl AsRef<[u8]>),
+    ) -> IoResult<Self> {
+        let runtime = net.runtime();
+        let relay = Arc::new(Relay {
+            runtime: runtime.clone(),
+            uuid: user.0,
+            password: Box::from(user.1.as_ref()),
+            gc: Mutex::new((Duration::from_secs(3), Duration::from_secs(15))),
+            echo_delay: Mutex::new(Duration::ZERO),
+        });
+
+        runtime.spawn(Box::pin(relay.clone().accept(server.clone())));
+
+        Ok(Self {
+            runtime,
+            server_addr: server.local_addr()?,
+            server_name: server_name.into(),
+            client,
+            relay,
+        })
+    }
+
+    /// Sets how often the server garbage collects fragments, and how long they live, taking effect after the current interval
+    pub fn set_gc(&self, interval: Duration, lifetime: Duration) {
+        *self.relay.gc.lock().unwrap() = (interval, lifetime);
+    }
+
+    /// Sets how long the server holds UDP packets before echoing them back, as a target answering late would
+    pub fn set_echo_delay(&self, delay: Duration) {
+        *self.relay.echo_delay.lock().unwrap() = delay;
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    /// The address of the client endpoint, e.g. for putting it behind a NAT with [`MockNetwork::set_nat`]
+    pub fn client_addr(&self) -> SocketAddr {
+        self.client.local_addr().unwrap()
+    }
+
+    /// Connects to the server and authenticates with `password`, which may be wrong on purpose
+    pub async fn connect(&self, password: impl AsRef<[u8]>) -> Result<HarnessClient, Error> {
+        let conn = self
+            .client
+            .connect(self.server_addr, &self.server_name)
+            .map_err(|err| IoError::new(ErrorKind::Other, err))?
+            .await?;
+
+        let client = HarnessClient {
+            conn: Connection::<Client>::new(conn.clone()),
+            quinn: conn,
+            inbox: Arc::new(Inbox::default()),
+        };
+
+        client
+            .conn
+            .authenticate(self.relay.uuid, password.as_ref())
+            .await?;
+
+        self.runtime
+            .spawn(Box::pin(client.clone().accept_uni_streams()));
+        self.runtime
+            .spawn(Box::pin(client.clone().accept_datagrams()));
+
+        Ok(client)
+    }
+
+    /// Waits for the duration on the timers of the runtime
+    pub async fn sleep(&self, dur: Duration) {
+        sleep(&*self.runtime, dur).await
+    }
+
+    /// Runs the future for up to the duration, returning `None` if it takes longer
+    pub async fn timeout<F: Future>(&self, dur: Duration, fut: F) -> Option<F::Output> {
+        let sleep = self.sleep(dur);
+        pin_mut!(fut, sleep);
+
+        match future::select(fut, sleep).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+impl Relay {
+    async fn accept(self: Arc<Self>, ep: Endpoint) {
+        while let Some(connecting) = ep.accept().await {
+            let relay = self.clone();
+
+            self.runtime.spawn(Box::pin(async move {
+                if let Ok(conn) = connecting.await {
+                    relay.serve(conn);
+                }
+            }));
+        }
+    }
+
+    fn serve(self: Arc<Self>, conn: QuinnConnection) {
+        let tuic = Connection::<Server>::new(conn.clone());
+
+        self.runtime.spawn(Box::pin(
+            self.clone().accept_uni_streams(conn.clone(), tuic.clone()),
+        ));
+        self.runtime.spawn(Box::pin(
+            self.clone().accept_bi_streams(conn.clone(), tuic.clone()),
+        ));
+        self.runtime.spawn(Box::pin(
+            self.clone().accept_datagrams(conn.clone(), tuic.clone()),
+        ));
+        let runtime = self.runtime.clone();
+        runtime.spawn(Box::pin(self.collect_garbage(conn, tuic)));
+    }
+
+    async fn accept_uni_streams(self: Arc<Self>, conn: QuinnConnection, tuic: Connection<Server>) {
+        while let Ok(recv) = conn.accept_uni().await {
+            let relay = self.clone();
+            let conn = conn.clone();
+            let tuic = tuic.clone();
+
+            self.runtime.spawn(Box::pin(async move {
+                if let Ok(task) = tuic.accept_uni_stream(recv).await {
+                    relay.handle(&conn, &tuic, task).await;
+                }
+            }));
+        }
+    }
+
+    async fn accept_bi_streams(self: Arc<Self>, conn: QuinnConnection, tuic: Connection<Server>) {
+        while let Ok((send, recv)) = conn.accept_bi().await {
+            let relay = self.clone();
+            let conn = conn.clone();
+            let tuic = tuic.clone();
+
+            self.runtime.spawn(Box::pin(async move {
+                if let Ok(task) = tuic.accept_bi_stream(send, recv).await {
+                    relay.handle(&conn, &tuic, task).await;
+                }
+            }));
+        }
+    }
+
+    async fn accept_datagrams(self: Arc<Self>, conn: QuinnConnection, tuic: Connection<Server>) {
+        while let Ok(dg) = conn.read_datagram().await {
+            let relay = self.clone();
+            let conn = conn.clone();
+            let tuic = tuic.clone();
+
+            self.runtime.spawn(Box::pin(async move {
+                if let Ok(task) = tuic.accept_datagram(dg) {
+                    relay.handle(&conn, &tuic, task).await;
+                }
+            }));
+        }
+    }
+
+    async fn collect_garbage(self: Arc<Self>, conn: QuinnConnection, tuic: Connection<Server>) {
+        while conn.close_reason().is_none() {
+            let (interval, lifetime) = *self.gc.lock().unwrap();
+            sleep(&*self.runtime, interval).await;
+            tuic.collect_garbage(lifetime);
+        }
+    }
+
+    /// Handles a task as a server would, except that `Connect` streams and UDP packets are echoed back instead of relayed to their targets
+    async fn handle(&self, conn: &QuinnConnection, tuic: &Connection<Server>, task: Task) {
+        match task {
+            Task::Authenticate(auth) => {
+                if auth.uuid() == self.uuid && auth.validate(&self.password) {
+                    tuic.set_authenticated();
+                } else {
+                    conn.close(CloseCode::AuthFailed.into(), b"");
+                }
+            }
+            Task::Connect(mut connect) => {
+                let mut buf = vec![0; ECHO_BUF_SIZE];
+
+                while let Ok(n) = connect.read(&mut buf).await {
+                    if n == 0 || connect.write_all(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+
+                let _ = connect.close().await;
+            }
+            Task::Packet(pkt) => {
+                let from_quic = pkt.is_from_quic();
+
+                if let Ok(Some((pkt, addr, assoc_id))) = pkt.accept().await {
+                    let delay = *self.echo_delay.lock().unwrap();
+
+                    if !delay.is_zero() {
+                        sleep(&*self.runtime, delay).await;
+                    }
+
+                    let _ = if from_quic {
+                        tuic.packet_quic(pkt, addr, assoc_id).await
+                    } else {
+                        tuic.packet_native(pkt, addr, assoc_id)
+                    };
+                }
+            }
+            Task::ConfirmDissociate(dissoc) => {
+                let _ = dissoc.ack().await;
+            }
+            Task::BindUdp(bind) => bind.reject(VarInt::from_u32(0)),
+            Task::Resume(resume) => resume.reject(VarInt::from_u32(0)),
+            Task::Bind(bind) => bind.reject(VarInt::from_u32(0)),
+            Task::Dissociate(_) | Task::Heartbeat | Task::Inbound(_) => {}
+        }
+    }
+}
+
+/// A client connection of a [`Harness`]
+#[derive(Clone)]
+pub struct HarnessClient {
+    conn: Connection<Client>,
+    quinn: QuinnConnection,
+    inbox: Arc<Inbox>,
+}
+
+/// The UDP packets echoed back to a client, waiting to be taken
+#[derive(Default)]
+struct Inbox {
+    pkts: Mutex<VecDeque<(Bytes, Address, u16)>>,
+    waker: AtomicWaker,
+    closed: AtomicBool,
+}
+
+impl HarnessClient {
+    /// The TUIC connection, for sending commands
+    pub fn connection(&self) -> &Connection<Client> {
+        &self.conn
+    }
+
+    /// The underlying QUIC connection, e.g. for checking the stats or why it is closed
+    pub fn quinn(&self) -> &QuinnConnection {
+        &self.quinn
+    }
+
+    /// Waits for the next UDP packet echoed back by the server, returning its payload, address and associate ID
+    ///
+    /// Returns `None` once the connection is closed and every packet is taken.
+    pub async fn recv_packet(&self) -> Option<(Bytes, Address, u16)> {
+        future::poll_fn(|cx| {
+            self.inbox.waker.register(cx.waker());
+
+            if let Some(pkt) = self.inbox.pkts.lock().unwrap().pop_front() {
+                Poll::Ready(Some(pkt))
+            } else if self.inbox.closed.load(Ordering::Acquire) {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    async fn accept_uni_streams(self) {
+        while let Ok(recv) = self.quinn.accept_uni().await {
+            if let Ok(Task::Packet(pkt)) = self.conn.accept_uni_stream(recv).await {
+                self.receive(pkt).await;
+            }
+        }
+
+        self.inbox.close();
+    }
+
+    async fn accept_datagrams(self) {
+        while let Ok(dg) = self.quinn.read_datagram().await {
+            if let Ok(Task::Packet(pkt)) = self.conn.accept_datagram(dg) {
+                self.receive(pkt).await;
+            }
+        }
+
+        self.inbox.close();
+    }
+
+    async fn receive(&self, pkt: Packet) {
+        if let Ok(Some(pkt)) = pkt.accept().await {
+            self.inbox.pkts.lock().unwrap().push_back(pkt);
+            self.inbox.waker.wake();
+        }
+    }
+}
+
+impl Inbox {
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+async fn sleep(runtime: &dyn Runtime, dur: Duration) {
+    let mut timer = runtime.new_timer(Instant::now() + dur);
+    future::poll_fn(|cx| timer.as_mut().poll(cx)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tests::endpoints;
+    use quinn::{TokioRuntime, TransportConfig};
+
+    const PASSWORD: &[u8] = b"password";
+
+    async fn connect(net: &MockNetwork, keep_alive: Option<Duration>) -> (Harness, HarnessClient) {
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(keep_alive);
+
+        let (server, client) = endpoints(net, transport);
+        let harness =
+            Harness::new(net, server, client, "localhost", (Uuid::nil(), PASSWORD)).unwrap();
+        let client = harness.connect(PASSWORD).await.unwrap();
+
+        (harness, client)
+    }
+
+    /// A UDP response arriving after the NAT in front of the idle client forgot its mapping is dropped, unless keep-alives of the client hold the mapping
+    #[tokio::test]
+    async fn udp_response_after_nat_timeout() {
+        for keep_alive in [None, Some(Duration::from_millis(100))] {
+            let net = MockNetwork::new(Arc::new(TokioRuntime));
+            let (harness, client) = connect(&net, keep_alive).await;
+
+            harness.set_echo_delay(Duration::from_millis(600));
+            net.set_nat(harness.client_addr(), Some(Duration::from_millis(300)))
+                .unwrap();
+
+            let addr = Address::SocketAddress(SocketAddr::from(([192, 0, 2, 1], 53)));
+            client
+                .connection()
+                .packet_native(b"query", addr.clone(), 1)
+                .unwrap();
+
+            let echo = harness
+                .timeout(Duration::from_secs(2), client.recv_packet())
+                .await
+                .flatten();
+
+            if keep_alive.is_some() {
+                assert_eq!(echo, Some((Bytes::from_static(b"query"), addr, 1)));
+                assert_eq!(net.stats().filtered, 0);
+            } else {
+                assert_eq!(echo, None);
+                assert!(net.stats().filtered > 0);
+            }
+        }
+    }
+}
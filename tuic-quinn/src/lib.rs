@@ -1,31 +1,54 @@
 #![doc = include_str!("../README.md")]
 
-use self::side::Side;
+use self::{compression::Compression, side::Side};
 use bytes::{BufMut, Bytes, BytesMut};
-use futures_util::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures_util::{
+    future::{self, Either},
+    task::AtomicWaker,
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
 use quinn::{
     Connection as QuinnConnection, ConnectionError, RecvStream, SendDatagramError, SendStream,
     UnknownStream, VarInt,
 };
 use std::{
-    fmt::{Debug, Formatter, Result as FmtResult},
-    io::{Cursor, Error as IoError},
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    future::Future,
+    io::{Cursor, Error as IoError, ErrorKind},
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 use tuic::{
     model::{
         side::{Rx, Tx},
-        AssembleError, Authenticate as AuthenticateModel, Connect as ConnectModel,
-        Connection as ConnectionModel, KeyingMaterialExporter as KeyingMaterialExporterImpl,
-        Packet as PacketModel,
+        AssembleError, AssemblyCounts, Authenticate as AuthenticateModel, Bind as BindModel,
+        BindUdp as BindUdpModel, Connect as ConnectModel, Connection as ConnectionModel,
+        Dissociate as DissociateModel, DissociateAck as DissociateAckModel, Echo as EchoModel,
+        KeyingMaterialExporter as KeyingMaterialExporterImpl, Packet as PacketModel,
+        Parity as ParityModel, Resume as ResumeModel, TaskEvent, TaskMetrics,
     },
-    Address, Header, UnmarshalError,
+    Address, Bandwidth, Bind as BindHeader, BindUdp as BindUdpHeader, CongestionHint,
+    Echo as EchoHeader, Header, Packet as PacketHeader, Parity as ParityHeader,
+    Resume as ResumeHeader, UnmarshalError,
 };
 use uuid::Uuid;
 
+pub mod congestion;
+
+mod compression;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+
+#[cfg(feature = "harness")]
+pub mod harness;
+
 pub mod side {
     //! Side marker types for a connection.
 
@@ -49,10 +72,38 @@ pub mod side {
 pub struct Connection<Side> {
     conn: QuinnConnection,
     model: ConnectionModel<Bytes>,
+    max_pkt_size: u16,
+    compression: Arc<Compression>,
+    pre_auth: Arc<PreAuth>,
+    bad_cmd_policy: BadCommandPolicy,
     _marker: Side,
 }
 
 impl<Side> Connection<Side> {
+    /// Sets the maximum `SIZE` accepted in received `Packet` commands. Defaults to 65535.
+    ///
+    /// A `Packet` declaring a larger size is rejected with [`Error::PacketTooLarge`] when accepted, before any buffer for the payload is allocated.
+    pub fn with_max_packet_size(mut self, max_pkt_size: u16) -> Self {
+        self.max_pkt_size = max_pkt_size;
+        self
+    }
+
+    fn check_packet_size(&self, pkt: &PacketHeader) -> Result<(), Error> {
+        if pkt.size() > self.max_pkt_size {
+            Err(Error::PacketTooLarge(pkt.size(), self.max_pkt_size))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_parity_size(&self, parity: &ParityHeader) -> Result<(), Error> {
+        if parity.size() > self.max_pkt_size {
+            Err(Error::PacketTooLarge(parity.size(), self.max_pkt_size))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Sends a `Packet` using UDP relay mode `native`.
     pub fn packet_native(
         &self,
@@ -60,12 +111,72 @@ impl<Side> Connection<Side> {
         addr: Address,
         assoc_id: u16,
     ) -> Result<(), Error> {
-        let Some(max_pkt_size) = self.conn.max_datagram_size() else {
+        self.packet_native_with_max_size(pkt, addr, assoc_id, usize::MAX)
+    }
+
+    /// Sends a `Packet` using UDP relay mode `native`, with each datagram no larger than `max_pkt_size`.
+    ///
+    /// The size is further limited by the current maximum datagram size of the connection, which follows the path MTU.
+    pub fn packet_native_with_max_size(
+        &self,
+        pkt: impl AsRef<[u8]>,
+        addr: Address,
+        assoc_id: u16,
+        max_pkt_size: usize,
+    ) -> Result<(), Error> {
+        let Some(max_datagram_size) = self.conn.max_datagram_size() else {
             return Err(Error::SendDatagram(SendDatagramError::Disabled));
         };
 
+        let max_pkt_size = max_pkt_size.min(max_datagram_size);
+
+        let compressed = self.compression.compress(pkt.as_ref());
         let model = self.model.send_packet(assoc_id, addr, max_pkt_size);
 
+        let (model, pkt) = match &compressed {
+            Some(compressed) => (model.compressed(), compressed.as_slice()),
+            None => (model, pkt.as_ref()),
+        };
+
+        for (header, frag) in model.into_fragments(pkt) {
+            let mut buf = BytesMut::with_capacity(header.len() + frag.len());
+            header.write(&mut buf);
+            buf.put_slice(frag);
+            self.conn.send_datagram(Bytes::from(buf))?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a `Packet` using UDP relay mode `native`, with each datagram no larger than `max_pkt_size`, followed by a `Parity` of its fragments if it is fragmented.
+    ///
+    /// The peer can recover a single lost fragment of the packet from the `Parity`. Only send it to a peer accepting `Parity` commands, i.e. a client authenticated with [`Connection::authenticate_with_fec()`], or a server known to support them. Other peers ignore it as an unknown command.
+    pub fn packet_native_with_parity(
+        &self,
+        pkt: impl AsRef<[u8]>,
+        addr: Address,
+        assoc_id: u16,
+        max_pkt_size: usize,
+    ) -> Result<(), Error> {
+        let Some(max_datagram_size) = self.conn.max_datagram_size() else {
+            return Err(Error::SendDatagram(SendDatagramError::Disabled));
+        };
+
+        let max_pkt_size = max_pkt_size.min(max_datagram_size);
+
+        let model = self
+            .model
+            .send_packet_with_parity(assoc_id, addr, max_pkt_size);
+
+        let compressed = self.compression.compress(pkt.as_ref());
+
+        let (model, pkt) = match &compressed {
+            Some(compressed) => (model.compressed(), compressed.as_slice()),
+            None => (model, pkt.as_ref()),
+        };
+
+        let parity = model.parity(pkt);
+
         for (header, frag) in model.into_fragments(pkt) {
             let mut buf = BytesMut::with_capacity(header.len() + frag.len());
             header.write(&mut buf);
@@ -73,9 +184,21 @@ impl<Side> Connection<Side> {
             self.conn.send_datagram(Bytes::from(buf))?;
         }
 
+        if let Some(parity) = parity {
+            let mut buf = BytesMut::with_capacity(parity.header().len() + parity.parity().len());
+            parity.header().write(&mut buf);
+            buf.put_slice(parity.parity());
+            self.conn.send_datagram(Bytes::from(buf))?;
+        }
+
         Ok(())
     }
 
+    /// Returns the current maximum datagram size of the connection, which follows the path MTU.
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.conn.max_datagram_size()
+    }
+
     /// Sends a `Packet` using UDP relay mode `quic`.
     pub async fn packet_quic(
         &self,
@@ -83,8 +206,14 @@ impl<Side> Connection<Side> {
         addr: Address,
         assoc_id: u16,
     ) -> Result<(), Error> {
+        let compressed = self.compression.compress(pkt.as_ref());
         let model = self.model.send_packet(assoc_id, addr, u16::MAX as usize);
 
+        let (model, pkt) = match &compressed {
+            Some(compressed) => (model.compressed(), compressed.as_slice()),
+            None => (model, pkt.as_ref()),
+        };
+
         for (header, frag) in model.into_fragments(pkt) {
             let mut send = self.conn.open_uni().await?;
             header.async_marshal(&mut send).await?;
@@ -105,6 +234,31 @@ impl<Side> Connection<Side> {
         self.model.task_associate_count()
     }
 
+    /// Returns the counters of `Connect` tasks and UDP sessions, with their high-water marks and creation and teardown rates
+    pub fn task_metrics(&self) -> TaskMetrics {
+        self.model.task_metrics()
+    }
+
+    /// Returns the reassembly counters of received packets of all UDP sessions, including the dissociated ones
+    pub fn assembly_counts(&self) -> AssemblyCounts {
+        self.model.assembly_counts()
+    }
+
+    /// Returns the reassembly counters of received packets of each associated UDP session, ordered by the association ID
+    pub fn session_assembly_counts(&self) -> Vec<(u16, AssemblyCounts)> {
+        self.model.session_assembly_counts()
+    }
+
+    /// Keeps a log of the last `capacity` task creations and teardowns, or stops logging with 0
+    pub fn set_task_log(&self, capacity: usize) {
+        self.model.set_task_log(capacity);
+    }
+
+    /// Returns the logged task events, oldest first
+    pub fn task_events(&self) -> Vec<TaskEvent> {
+        self.model.task_events()
+    }
+
     /// Removes packet fragments that can not be reassembled within the specified timeout
     pub fn collect_garbage(&self, timeout: Duration) {
         self.model.collect_garbage(timeout);
@@ -121,15 +275,76 @@ impl Connection<side::Client> {
         Self {
             conn,
             model: ConnectionModel::new(),
+            max_pkt_size: u16::MAX,
+            compression: Arc::new(Compression::new()),
+            pre_auth: Arc::new(PreAuth::new(PreAuthPolicy::default())),
+            bad_cmd_policy: BadCommandPolicy::default(),
             _marker: side::Client,
         }
     }
 
+    /// Declares compression of the relayed UDP packets of at least `min_size` bytes to the server in the `Authenticate` command, accepting compressed packets from then on.
+    ///
+    /// The client only compresses its packets after receiving a compressed one, with which a server accepting compression acknowledges it. Packets to other servers are left uncompressed.
+    pub fn with_compression(self, min_size: u16) -> Self {
+        self.compression.declare(min_size);
+        self
+    }
+
     /// Sends an `Authenticate` command.
     pub async fn authenticate(&self, uuid: Uuid, password: impl AsRef<[u8]>) -> Result<(), Error> {
         let model = self
             .model
-            .send_authenticate(uuid, password, &self.keying_material_exporter());
+            .send_authenticate(uuid, password, &self.keying_material_exporter())
+            .ok_or(Error::ExportKeyingMaterial)?;
+
+        self.send_authenticate(model).await
+    }
+
+    /// Sends an `Authenticate` command, declaring the bandwidth of the client.
+    ///
+    /// The server may use the declared download bandwidth as its sending rate, e.g. with the [`Brutal`](congestion::Brutal) congestion controller.
+    pub async fn authenticate_with_bandwidth(
+        &self,
+        uuid: Uuid,
+        password: impl AsRef<[u8]>,
+        bandwidth: Bandwidth,
+    ) -> Result<(), Error> {
+        let model = self
+            .model
+            .send_authenticate_with_bandwidth(
+                uuid,
+                password,
+                &self.keying_material_exporter(),
+                bandwidth,
+            )
+            .ok_or(Error::ExportKeyingMaterial)?;
+
+        self.send_authenticate(model).await
+    }
+
+    /// Sends an `Authenticate` command, declaring that the client accepts `Parity` commands, optionally with the bandwidth of the client.
+    ///
+    /// The server then follows fragmented packets relayed in mode `native` with a `Parity`, from which a single lost fragment can be recovered.
+    pub async fn authenticate_with_fec(
+        &self,
+        uuid: Uuid,
+        password: impl AsRef<[u8]>,
+        bandwidth: Option<Bandwidth>,
+    ) -> Result<(), Error> {
+        let model = self
+            .model
+            .send_authenticate_with_fec(uuid, password, &self.keying_material_exporter(), bandwidth)
+            .ok_or(Error::ExportKeyingMaterial)?;
+
+        self.send_authenticate(model).await
+    }
+
+    async fn send_authenticate(&self, model: AuthenticateModel<Tx>) -> Result<(), Error> {
+        let model = match self.compression.min_size() {
+            Some(min_size) => model.with_compression(min_size),
+            None => model,
+        };
 
         let mut send = self.conn.open_uni().await?;
         model.header().async_marshal(&mut send).await?;
@@ -145,6 +360,67 @@ impl Connection<side::Client> {
         Ok(Connect::new(Side::Client(model), send, recv))
     }
 
+    /// Sends a `Connect` command, giving up with [`Error::ConnectTimeout`] once `timeout` resolves before the stream is opened and the command is sent.
+    ///
+    /// `timeout` is a future of the runtime in use, e.g. `tokio::time::sleep(duration)`. Opening the stream is pending while the server allows no more streams, e.g. when it is unresponsive. The task is not counted by the connection after giving up.
+    pub async fn connect_with_timeout(
+        &self,
+        addr: Address,
+        timeout: impl Future<Output = ()>,
+    ) -> Result<Connect, Error> {
+        self.connect_until(addr, async {
+            timeout.await;
+            Error::ConnectTimeout
+        })
+        .await
+    }
+
+    /// Sends a `Connect` command that can be aborted with the handle, from opening the stream to relaying through the returned `Connect`.
+    ///
+    /// Aborting before the `Connect` is returned fails it with [`Error::ConnectAborted`], without the task being counted by the connection afterwards. See [`ConnectAbortHandle`] for aborting afterwards.
+    pub async fn connect_abortable(
+        &self,
+        addr: Address,
+        abort: ConnectAbortHandle,
+    ) -> Result<Connect, Error> {
+        let conn = self
+            .connect_until(addr, async {
+                abort.aborted().await;
+                Error::ConnectAborted
+            })
+            .await?;
+
+        Ok(conn.with_abort_handle(abort))
+    }
+
+    async fn connect_until(
+        &self,
+        addr: Address,
+        until: impl Future<Output = Error>,
+    ) -> Result<Connect, Error> {
+        let connect = self.connect(addr);
+        futures_util::pin_mut!(connect, until);
+
+        match future::select(connect, until).await {
+            Either::Left((res, _)) => res,
+            Either::Right((err, _)) => Err(err),
+        }
+    }
+
+    /// Sends a `Connect` command with a congestion hint.
+    ///
+    /// The hint is sent in a command type unknown to servers not supporting it, which may close the connection.
+    pub async fn connect_with_hint(
+        &self,
+        addr: Address,
+        hint: CongestionHint,
+    ) -> Result<Connect, Error> {
+        let model = self.model.send_connect_with_hint(addr, hint);
+        let (mut send, recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        Ok(Connect::new(Side::Client(model), send, recv))
+    }
+
     /// Sends a `Dissociate` command.
     pub async fn dissociate(&self, assoc_id: u16) -> Result<(), Error> {
         let model = self.model.send_dissociate(assoc_id);
@@ -154,11 +430,126 @@ impl Connection<side::Client> {
         Ok(())
     }
 
+    /// Sends a `Dissociate` command for every UDP session of the connection, all through a single unidirectional stream. Returns the IDs of the UDP sessions dissociated.
+    ///
+    /// Servers not reading batches of `Dissociate` only tear down the first UDP session of the batch.
+    pub async fn dissociate_all(&self) -> Result<Vec<u16>, Error> {
+        let models = self.model.send_dissociate_all();
+
+        if models.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut send = self.conn.open_uni().await?;
+
+        for model in &models {
+            model.header().async_marshal(&mut send).await?;
+        }
+
+        send.close().await?;
+        Ok(models.iter().map(|model| model.assoc_id()).collect())
+    }
+
+    /// Sends a `Dissociate` command through a bidirectional stream, resolving once the server confirms with a `DissociateAck` that the UDP session is torn down.
+    ///
+    /// Useful before reusing `assoc_id`. The command is unexpected on bidirectional streams for servers not supporting the confirmation, which may close the connection.
+    pub async fn dissociate_confirmed(&self, assoc_id: u16) -> Result<(), Error> {
+        let model = self.model.send_dissociate(assoc_id);
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+
+        match Header::async_unmarshal(&mut recv).await {
+            Ok(Header::DissociateAck(ack)) if ack.assoc_id() == assoc_id => {
+                let _ = self.model.recv_dissociate_ack(ack);
+                Ok(())
+            }
+            Ok(_) => Err(Error::BadDissociateAck),
+            Err(err) => Err(Error::UnmarshalDissociateAck(err)),
+        }
+    }
+
+    /// Sends a `Resume` command with the resumption token issued for a previous connection, or `None` for only requesting a token.
+    ///
+    /// Returns the token issued for this connection, and the IDs of the UDP sessions of the previous connection resumed on this connection. Packets of the resumed sessions can be received since then. The command is unknown to servers not supporting resumption, which may close the connection.
+    pub async fn resume(&self, token: Option<[u8; 16]>) -> Result<([u8; 16], Vec<u16>), Error> {
+        let model = self
+            .model
+            .send_resume(token.unwrap_or_default(), Vec::new());
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+
+        match Header::async_unmarshal(&mut recv).await {
+            Ok(Header::Resume(resume)) => {
+                let model = self.model.recv_resume(resume);
+                Ok((model.token(), model.assoc_ids().to_vec()))
+            }
+            Ok(_) => Err(Error::BadResumeResponse),
+            Err(err) => Err(Error::UnmarshalResumeResponse(err)),
+        }
+    }
+
+    /// Sends a `BindUdp` command, returning the address bound by the server.
+    ///
+    /// The server relays packets from any source to the UDP session since then, without waiting for a packet from the client. `addr` is the address to bind on the server, or `Address::None` for any.
+    pub async fn bind_udp(&self, assoc_id: u16, addr: Address) -> Result<Address, Error> {
+        let model = self.model.send_bind_udp(assoc_id, addr);
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+
+        match Header::async_unmarshal(&mut recv).await {
+            Ok(Header::BindUdp(bind)) if bind.assoc_id() == assoc_id => {
+                let (_, addr) = bind.into();
+                Ok(addr)
+            }
+            Ok(_) => Err(Error::BadBindUdpResponse),
+            Err(err) => Err(Error::UnmarshalBindUdpResponse(err)),
+        }
+    }
+
+    /// Sends a `Bind` command, returning the binding with the address the server listens on.
+    ///
+    /// The server relays each inbound connection to the address back to the client as a [`Task::Inbound`] carrying `bind_id`, until the returned [`Binding`] is dropped. `addr` is the address to listen on the server. The command is unknown to servers not supporting binding, which may close the connection.
+    pub async fn bind(&self, bind_id: u16, addr: Address) -> Result<Binding, Error> {
+        let model = self.model.send_bind(bind_id, addr);
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+
+        match Header::async_unmarshal(&mut recv).await {
+            Ok(Header::Bind(bind)) if bind.bind_id() == bind_id => {
+                let (_, addr) = bind.into();
+                Ok(Binding::new(bind_id, addr, send, recv))
+            }
+            Ok(_) => Err(Error::BadBindResponse),
+            Err(err) => Err(Error::UnmarshalBindResponse(err)),
+        }
+    }
+
+    /// Sends an `Echo` command with the payload, waiting for the server to reflect it back.
+    ///
+    /// This verifies that the server handles commands end to end, without relaying to any target. The payload cannot exceed 65535 bytes. The command is unknown to servers not supporting it, which may close the connection.
+    pub async fn echo(&self, payload: Vec<u8>) -> Result<(), Error> {
+        let model = self.model.send_echo(payload);
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+
+        match Header::async_unmarshal(&mut recv).await {
+            Ok(Header::Echo(echo)) if matches!(model.header(), Header::Echo(sent) if sent.payload() == echo.payload()) => {
+                Ok(())
+            }
+            Ok(_) => Err(Error::BadEchoResponse),
+            Err(err) => Err(Error::UnmarshalEchoResponse(err)),
+        }
+    }
+
     /// Sends a `Heartbeat` command.
     pub async fn heartbeat(&self) -> Result<(), Error> {
         let model = self.model.send_heartbeat();
         let mut buf = Vec::with_capacity(model.header().len());
-        model.header().async_marshal(&mut buf).await.unwrap();
+        model.header().async_marshal(&mut buf).await?;
         self.conn.send_datagram(Bytes::from(buf))?;
         Ok(())
     }
@@ -167,26 +558,49 @@ impl Connection<side::Client> {
     ///
     /// The `quinn::RecvStream` should be accepted by `quinn::Connection::accept_uni()` from the same `quinn::Connection`.
     pub async fn accept_uni_stream(&self, mut recv: RecvStream) -> Result<Task, Error> {
-        let header = match Header::async_unmarshal(&mut recv).await {
+        let header = match unmarshal_stream(&mut recv).await {
             Ok(header) => header,
-            Err(err) => return Err(Error::UnmarshalUniStream(err, recv)),
+            Err((err, len)) => {
+                if let UnmarshalError::InvalidCommand(type_code) = *err {
+                    return Ok(Task::Unknown(Unknown::new(
+                        type_code,
+                        UnknownSource::UniStream(recv),
+                    )));
+                }
+
+                return Err(Error::UnmarshalUniStream(err, len, recv));
+            }
         };
 
         match header {
             Header::Authenticate(_) => Err(Error::BadCommandUniStream("authenticate", recv)),
             Header::Connect(_) => Err(Error::BadCommandUniStream("connect", recv)),
             Header::Packet(pkt) => {
+                self.check_packet_size(&pkt)?;
                 let assoc_id = pkt.assoc_id();
                 let pkt_id = pkt.pkt_id();
-                self.model
-                    .recv_packet(pkt)
-                    .map_or(Err(Error::InvalidUdpSession(assoc_id, pkt_id)), |pkt| {
-                        Ok(Task::Packet(Packet::new(pkt, PacketSource::Quic(recv))))
-                    })
+                self.model.recv_packet(pkt).map_or(
+                    Err(Error::InvalidUdpSession(assoc_id, pkt_id)),
+                    |pkt| {
+                        Ok(Task::Packet(Packet::new(
+                            pkt,
+                            PacketSource::Quic(recv),
+                            self.compression.clone(),
+                        )))
+                    },
+                )
             }
             Header::Dissociate(_) => Err(Error::BadCommandUniStream("dissociate", recv)),
             Header::Heartbeat(_) => Err(Error::BadCommandUniStream("heartbeat", recv)),
-            _ => unreachable!(),
+            Header::BindUdp(_) => Err(Error::BadCommandUniStream("bind_udp", recv)),
+            Header::DissociateAck(_) => Err(Error::BadCommandUniStream("dissociate_ack", recv)),
+            Header::Resume(_) => Err(Error::BadCommandUniStream("resume", recv)),
+            Header::Bind(_) => Err(Error::BadCommandUniStream("bind", recv)),
+            Header::Echo(_) => Err(Error::BadCommandUniStream("echo", recv)),
+            Header::Parity(_) => Err(Error::BadCommandUniStream("parity", recv)),
+            header => Err(Error::ProtocolViolation(
+                ProtocolViolation::UnsupportedCommand(header.type_code()),
+            )),
         }
     }
 
@@ -198,9 +612,18 @@ impl Connection<side::Client> {
         send: SendStream,
         mut recv: RecvStream,
     ) -> Result<Task, Error> {
-        let header = match Header::async_unmarshal(&mut recv).await {
+        let header = match unmarshal_stream(&mut recv).await {
             Ok(header) => header,
-            Err(err) => return Err(Error::UnmarshalBiStream(err, send, recv)),
+            Err((err, len)) => {
+                if let UnmarshalError::InvalidCommand(type_code) = *err {
+                    return Ok(Task::Unknown(Unknown::new(
+                        type_code,
+                        UnknownSource::BiStream(send, recv),
+                    )));
+                }
+
+                return Err(Error::UnmarshalBiStream(err, len, send, recv));
+            }
         };
 
         match header {
@@ -209,7 +632,20 @@ impl Connection<side::Client> {
             Header::Packet(_) => Err(Error::BadCommandBiStream("packet", send, recv)),
             Header::Dissociate(_) => Err(Error::BadCommandBiStream("dissociate", send, recv)),
             Header::Heartbeat(_) => Err(Error::BadCommandBiStream("heartbeat", send, recv)),
-            _ => unreachable!(),
+            Header::BindUdp(_) => Err(Error::BadCommandBiStream("bind_udp", send, recv)),
+            Header::DissociateAck(_) => {
+                Err(Error::BadCommandBiStream("dissociate_ack", send, recv))
+            }
+            Header::Resume(_) => Err(Error::BadCommandBiStream("resume", send, recv)),
+            Header::Bind(bind) => {
+                let model = self.model.recv_bind(bind);
+                Ok(Task::Inbound(Inbound::new(Side::Client(model), send, recv)))
+            }
+            Header::Echo(_) => Err(Error::BadCommandBiStream("echo", send, recv)),
+            Header::Parity(_) => Err(Error::BadCommandBiStream("parity", send, recv)),
+            header => Err(Error::ProtocolViolation(
+                ProtocolViolation::UnsupportedCommand(header.type_code()),
+            )),
         }
     }
 
@@ -221,6 +657,14 @@ impl Connection<side::Client> {
 
         let header = match Header::unmarshal(&mut dg) {
             Ok(header) => header,
+            Err(UnmarshalError::InvalidCommand(type_code)) => {
+                let pos = dg.position() as usize;
+                let dg = dg.into_inner().slice(pos..);
+                return Ok(Task::Unknown(Unknown::new(
+                    type_code,
+                    UnknownSource::Datagram(dg),
+                )));
+            }
             Err(err) => return Err(Error::UnmarshalDatagram(err, dg.into_inner())),
         };
 
@@ -230,6 +674,7 @@ impl Connection<side::Client> {
             }
             Header::Connect(_) => Err(Error::BadCommandDatagram("connect", dg.into_inner())),
             Header::Packet(pkt) => {
+                self.check_packet_size(&pkt)?;
                 let assoc_id = pkt.assoc_id();
                 let pkt_id = pkt.pkt_id();
                 if let Some(pkt) = self.model.recv_packet(pkt) {
@@ -237,7 +682,11 @@ impl Connection<side::Client> {
                     let mut buf = dg.into_inner();
                     if (pos + pkt.size() as usize) <= buf.len() {
                         buf = buf.slice(pos..pos + pkt.size() as usize);
-                        Ok(Task::Packet(Packet::new(pkt, PacketSource::Native(buf))))
+                        Ok(Task::Packet(Packet::new(
+                            pkt,
+                            PacketSource::Native(buf),
+                            self.compression.clone(),
+                        )))
                     } else {
                         Err(Error::PayloadLength(pkt.size() as usize, buf.len() - pos))
                     }
@@ -245,9 +694,42 @@ impl Connection<side::Client> {
                     Err(Error::InvalidUdpSession(assoc_id, pkt_id))
                 }
             }
+            Header::Parity(parity) => {
+                self.check_parity_size(&parity)?;
+                let assoc_id = parity.assoc_id();
+                let pkt_id = parity.pkt_id();
+                if let Some(parity) = self.model.recv_parity(parity) {
+                    let pos = dg.position() as usize;
+                    let mut buf = dg.into_inner();
+                    if (pos + parity.size() as usize) <= buf.len() {
+                        buf = buf.slice(pos..pos + parity.size() as usize);
+                        Ok(Task::Packet(Packet::with_parity(
+                            parity,
+                            buf,
+                            self.compression.clone(),
+                        )))
+                    } else {
+                        Err(Error::PayloadLength(
+                            parity.size() as usize,
+                            buf.len() - pos,
+                        ))
+                    }
+                } else {
+                    Err(Error::InvalidUdpSession(assoc_id, pkt_id))
+                }
+            }
             Header::Dissociate(_) => Err(Error::BadCommandDatagram("dissociate", dg.into_inner())),
             Header::Heartbeat(_) => Err(Error::BadCommandDatagram("heartbeat", dg.into_inner())),
-            _ => unreachable!(),
+            Header::BindUdp(_) => Err(Error::BadCommandDatagram("bind_udp", dg.into_inner())),
+            Header::DissociateAck(_) => {
+                Err(Error::BadCommandDatagram("dissociate_ack", dg.into_inner()))
+            }
+            Header::Resume(_) => Err(Error::BadCommandDatagram("resume", dg.into_inner())),
+            Header::Bind(_) => Err(Error::BadCommandDatagram("bind", dg.into_inner())),
+            Header::Echo(_) => Err(Error::BadCommandDatagram("echo", dg.into_inner())),
+            header => Err(Error::ProtocolViolation(
+                ProtocolViolation::UnsupportedCommand(header.type_code()),
+            )),
         }
     }
 }
@@ -258,19 +740,122 @@ impl Connection<side::Server> {
         Self {
             conn,
             model: ConnectionModel::new(),
+            max_pkt_size: u16::MAX,
+            compression: Arc::new(Compression::new()),
+            pre_auth: Arc::new(PreAuth::new(PreAuthPolicy::default())),
+            bad_cmd_policy: BadCommandPolicy::default(),
             _marker: side::Server,
         }
     }
 
+    /// Sets the policy for commands received before the connection is authenticated. See [`PreAuthPolicy`].
+    pub fn with_pre_auth_policy(mut self, policy: PreAuthPolicy) -> Self {
+        self.pre_auth = Arc::new(PreAuth::new(policy));
+        self
+    }
+
+    /// Sets what to do with commands received where they are not expected. See [`BadCommandPolicy`].
+    pub fn with_bad_command_policy(mut self, policy: BadCommandPolicy) -> Self {
+        self.bad_cmd_policy = policy;
+        self
+    }
+
+    /// Marks the connection as authenticated, lifting the restrictions of the [`PreAuthPolicy`].
+    ///
+    /// Should be called once an `Authenticate` command is validated.
+    pub fn set_authenticated(&self) {
+        self.pre_auth.authenticated.store(true, Ordering::Release);
+    }
+
+    /// Accepts compression of the relayed UDP packets of at least `min_size` bytes, as declared by the client with [`Authenticate::compression()`].
+    ///
+    /// The next packet sent to the client is compressed regardless of its size, acknowledging compression. The client only compresses its packets after receiving it.
+    pub fn set_compression(&self, min_size: u16) {
+        self.compression.accept(min_size);
+    }
+
+    fn check_pre_auth(&self, header: &Header, payload_len: usize) -> Result<(), Error> {
+        let is_task = !matches!(header, Header::Authenticate(_) | Header::Heartbeat(_));
+        self.check_pre_auth_len(is_task, header.len() + payload_len)
+    }
+
+    fn check_pre_auth_len(&self, is_task: bool, len: usize) -> Result<(), Error> {
+        self.pre_auth.check(is_task, len)
+    }
+
+    /// Applies the [`BadCommandPolicy`] to the stream of a bad command error, returning [`Error::BadCommand`] if the stream is taken by the policy.
+    async fn quarantine(&self, err: Error) -> Error {
+        let cmd = match err {
+            Error::BadCommandUniStream(cmd, recv) => BadCommand::UniStream(cmd, recv),
+            Error::BadCommandBiStream(cmd, send, recv) => BadCommand::BiStream(cmd, send, recv),
+            Error::BadCommandDatagram(cmd, dg) => BadCommand::Datagram(cmd, dg),
+            err => return err,
+        };
+
+        let name = cmd.command();
+
+        match &self.bad_cmd_policy {
+            BadCommandPolicy::Return => return cmd.into_error(),
+            BadCommandPolicy::Discard => cmd.discard().await,
+            BadCommandPolicy::Tarpit => {
+                let _ = self.conn.closed().await;
+            }
+            BadCommandPolicy::Handle(handler) => handler(cmd),
+        }
+
+        Error::BadCommand(name)
+    }
+
+    /// The datagram counterpart of `quarantine()`. Datagrams are simply dropped when discarded or tarpitted.
+    fn quarantine_datagram(&self, err: Error) -> Error {
+        let Error::BadCommandDatagram(cmd, dg) = err else {
+            return err;
+        };
+
+        match &self.bad_cmd_policy {
+            BadCommandPolicy::Return => return Error::BadCommandDatagram(cmd, dg),
+            BadCommandPolicy::Discard | BadCommandPolicy::Tarpit => {}
+            BadCommandPolicy::Handle(handler) => handler(BadCommand::Datagram(cmd, dg)),
+        }
+
+        Error::BadCommand(cmd)
+    }
+
     /// Try to parse a `quinn::RecvStream` as a TUIC command.
     ///
     /// The `quinn::RecvStream` should be accepted by `quinn::Connection::accept_uni()` from the same `quinn::Connection`.
-    pub async fn accept_uni_stream(&self, mut recv: RecvStream) -> Result<Task, Error> {
-        let header = match Header::async_unmarshal(&mut recv).await {
+    pub async fn accept_uni_stream(&self, recv: RecvStream) -> Result<Task, Error> {
+        match self.recv_uni_stream(recv).await {
+            Err(err) => Err(self.quarantine(err).await),
+            res => res,
+        }
+    }
+
+    async fn recv_uni_stream(&self, mut recv: RecvStream) -> Result<Task, Error> {
+        let header = match unmarshal_stream(&mut recv).await {
             Ok(header) => header,
-            Err(err) => return Err(Error::UnmarshalUniStream(err, recv)),
+            Err((err, len)) => {
+                if let UnmarshalError::InvalidCommand(type_code) = *err {
+                    self.check_pre_auth_len(true, len)?;
+
+                    return Ok(Task::Unknown(Unknown::new(
+                        type_code,
+                        UnknownSource::UniStream(recv),
+                    )));
+                }
+
+                return Err(Error::UnmarshalUniStream(err, len, recv));
+            }
+        };
+
+        // the payload of a `Packet` follows the header on the stream, and is buffered when the packet is accepted
+        let payload_len = match &header {
+            Header::Packet(pkt) => pkt.size() as usize,
+            _ => 0,
         };
 
+        self.check_pre_auth(&header, payload_len)?;
+
         match header {
             Header::Authenticate(auth) => {
                 let model = self.model.recv_authenticate(auth);
@@ -281,72 +866,225 @@ impl Connection<side::Server> {
             }
             Header::Connect(_) => Err(Error::BadCommandUniStream("connect", recv)),
             Header::Packet(pkt) => {
+                self.check_packet_size(&pkt)?;
                 let model = self.model.recv_packet_unrestricted(pkt);
-                Ok(Task::Packet(Packet::new(model, PacketSource::Quic(recv))))
+                Ok(Task::Packet(Packet::new(
+                    model,
+                    PacketSource::Quic(recv),
+                    self.compression.clone(),
+                )))
             }
             Header::Dissociate(dissoc) => {
-                let model = self.model.recv_dissociate(dissoc);
-                Ok(Task::Dissociate(model.assoc_id()))
+                let mut dissocs = vec![dissoc];
+
+                // a batch of `Dissociate` may follow on the same stream, ending with the stream
+                loop {
+                    let header = match unmarshal_stream(&mut recv).await {
+                        Ok(header) => header,
+                        Err((err, 0)) if is_end_of_stream(&err) => break,
+                        Err((err, len)) => return Err(Error::UnmarshalUniStream(err, len, recv)),
+                    };
+
+                    self.check_pre_auth(&header, 0)?;
+
+                    match header {
+                        Header::Dissociate(dissoc) if dissocs.len() <= u16::MAX as usize => {
+                            dissocs.push(dissoc)
+                        }
+                        _ => return Err(Error::BadCommandUniStream("dissociate", recv)),
+                    }
+                }
+
+                let mut assoc_ids = dissocs
+                    .into_iter()
+                    .map(|dissoc| self.model.recv_dissociate(dissoc).assoc_id())
+                    .collect::<Vec<_>>();
+
+                if assoc_ids.len() == 1 {
+                    Ok(Task::Dissociate(assoc_ids.remove(0)))
+                } else {
+                    Ok(Task::DissociateAll(assoc_ids))
+                }
             }
             Header::Heartbeat(_) => Err(Error::BadCommandUniStream("heartbeat", recv)),
-            _ => unreachable!(),
+            Header::BindUdp(_) => Err(Error::BadCommandUniStream("bind_udp", recv)),
+            Header::DissociateAck(_) => Err(Error::BadCommandUniStream("dissociate_ack", recv)),
+            Header::Resume(_) => Err(Error::BadCommandUniStream("resume", recv)),
+            Header::Bind(_) => Err(Error::BadCommandUniStream("bind", recv)),
+            Header::Echo(_) => Err(Error::BadCommandUniStream("echo", recv)),
+            Header::Parity(_) => Err(Error::BadCommandUniStream("parity", recv)),
+            header => Err(Error::ProtocolViolation(
+                ProtocolViolation::UnsupportedCommand(header.type_code()),
+            )),
         }
     }
 
+    /// Opens a stream relaying a connection accepted on the listener of a `Bind` command, sending a `Bind` command with the address of the connection.
+    pub async fn inbound(&self, bind_id: u16, addr: Address) -> Result<Inbound, Error> {
+        let model = self.model.send_bind(bind_id, addr);
+        let (mut send, recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        Ok(Inbound::new(Side::Server(model), send, recv))
+    }
+
     /// Try to parse a pair of `quinn::SendStream` and `quinn::RecvStream` as a TUIC command.
     ///
     /// The pair of stream should be accepted by `quinn::Connection::accept_bi()` from the same `quinn::Connection`.
     pub async fn accept_bi_stream(
         &self,
         send: SendStream,
-        mut recv: RecvStream,
+        recv: RecvStream,
     ) -> Result<Task, Error> {
-        let header = match Header::async_unmarshal(&mut recv).await {
+        match self.recv_bi_stream(send, recv).await {
+            Err(err) => Err(self.quarantine(err).await),
+            res => res,
+        }
+    }
+
+    async fn recv_bi_stream(&self, send: SendStream, mut recv: RecvStream) -> Result<Task, Error> {
+        let header = match unmarshal_stream(&mut recv).await {
             Ok(header) => header,
-            Err(err) => return Err(Error::UnmarshalBiStream(err, send, recv)),
+            Err((err, len)) => {
+                if let UnmarshalError::InvalidCommand(type_code) = *err {
+                    self.check_pre_auth_len(true, len)?;
+
+                    return Ok(Task::Unknown(Unknown::new(
+                        type_code,
+                        UnknownSource::BiStream(send, recv),
+                    )));
+                }
+
+                return Err(Error::UnmarshalBiStream(err, len, send, recv));
+            }
         };
 
+        self.check_pre_auth(&header, 0)?;
+
         match header {
             Header::Authenticate(_) => Err(Error::BadCommandBiStream("authenticate", send, recv)),
             Header::Connect(conn) => {
                 let model = self.model.recv_connect(conn);
-                Ok(Task::Connect(Connect::new(Side::Server(model), send, recv)))
+                Ok(Task::Connect(
+                    Connect::new(Side::Server(model), send, recv)
+                        .with_pre_auth(self.pre_auth.clone()),
+                ))
             }
             Header::Packet(_) => Err(Error::BadCommandBiStream("packet", send, recv)),
-            Header::Dissociate(_) => Err(Error::BadCommandBiStream("dissociate", send, recv)),
+            Header::Dissociate(dissoc) => {
+                let model = self.model.recv_dissociate(dissoc);
+                let ack = self.model.send_dissociate_ack(model.assoc_id());
+                Ok(Task::ConfirmDissociate(ConfirmDissociate::new(
+                    model, ack, send,
+                )))
+            }
             Header::Heartbeat(_) => Err(Error::BadCommandBiStream("heartbeat", send, recv)),
-            _ => unreachable!(),
-        }
-    }
-
-    /// Try to parse a QUIC Datagram as a TUIC command.
+            Header::BindUdp(bind) => {
+                let model = self.model.recv_bind_udp(bind);
+                Ok(Task::BindUdp(BindUdp::new(model, send, recv)))
+            }
+            Header::DissociateAck(_) => {
+                Err(Error::BadCommandBiStream("dissociate_ack", send, recv))
+            }
+            Header::Resume(resume) => {
+                let model = self.model.recv_resume(resume);
+                Ok(Task::Resume(Resume::new(model, send, recv)))
+            }
+            Header::Bind(bind) => {
+                let model = self.model.recv_bind(bind);
+                Ok(Task::Bind(Bind::new(model, send, recv)))
+            }
+            Header::Echo(echo) => {
+                let model = self.model.recv_echo(echo);
+                Ok(Task::Echo(Echo::new(model, send, recv)))
+            }
+            Header::Parity(_) => Err(Error::BadCommandBiStream("parity", send, recv)),
+            header => Err(Error::ProtocolViolation(
+                ProtocolViolation::UnsupportedCommand(header.type_code()),
+            )),
+        }
+    }
+
+    /// Try to parse a QUIC Datagram as a TUIC command.
     ///
     /// The Datagram should be accepted by `quinn::Connection::read_datagram()` from the same `quinn::Connection`.
     pub fn accept_datagram(&self, dg: Bytes) -> Result<Task, Error> {
+        self.recv_datagram(dg)
+            .map_err(|err| self.quarantine_datagram(err))
+    }
+
+    fn recv_datagram(&self, dg: Bytes) -> Result<Task, Error> {
         let mut dg = Cursor::new(dg);
 
         let header = match Header::unmarshal(&mut dg) {
             Ok(header) => header,
+            Err(UnmarshalError::InvalidCommand(type_code)) => {
+                self.check_pre_auth_len(true, dg.get_ref().len())?;
+
+                let pos = dg.position() as usize;
+                let dg = dg.into_inner().slice(pos..);
+                return Ok(Task::Unknown(Unknown::new(
+                    type_code,
+                    UnknownSource::Datagram(dg),
+                )));
+            }
             Err(err) => return Err(Error::UnmarshalDatagram(err, dg.into_inner())),
         };
 
+        let payload_len = dg.get_ref().len() - dg.position() as usize;
+        self.check_pre_auth(&header, payload_len)?;
+
         match header {
             Header::Authenticate(_) => {
                 Err(Error::BadCommandDatagram("authenticate", dg.into_inner()))
             }
             Header::Connect(_) => Err(Error::BadCommandDatagram("connect", dg.into_inner())),
             Header::Packet(pkt) => {
+                self.check_packet_size(&pkt)?;
                 let model = self.model.recv_packet_unrestricted(pkt);
                 let pos = dg.position() as usize;
-                let buf = dg.into_inner().slice(pos..pos + model.size() as usize);
-                Ok(Task::Packet(Packet::new(model, PacketSource::Native(buf))))
+                let mut buf = dg.into_inner();
+                if (pos + model.size() as usize) <= buf.len() {
+                    buf = buf.slice(pos..pos + model.size() as usize);
+                    Ok(Task::Packet(Packet::new(
+                        model,
+                        PacketSource::Native(buf),
+                        self.compression.clone(),
+                    )))
+                } else {
+                    Err(Error::PayloadLength(model.size() as usize, buf.len() - pos))
+                }
+            }
+            Header::Parity(parity) => {
+                self.check_parity_size(&parity)?;
+                let model = self.model.recv_parity_unrestricted(parity);
+                let pos = dg.position() as usize;
+                let mut buf = dg.into_inner();
+                if (pos + model.size() as usize) <= buf.len() {
+                    buf = buf.slice(pos..pos + model.size() as usize);
+                    Ok(Task::Packet(Packet::with_parity(
+                        model,
+                        buf,
+                        self.compression.clone(),
+                    )))
+                } else {
+                    Err(Error::PayloadLength(model.size() as usize, buf.len() - pos))
+                }
             }
             Header::Dissociate(_) => Err(Error::BadCommandDatagram("dissociate", dg.into_inner())),
             Header::Heartbeat(hb) => {
                 let _ = self.model.recv_heartbeat(hb);
                 Ok(Task::Heartbeat)
             }
-            _ => unreachable!(),
+            Header::BindUdp(_) => Err(Error::BadCommandDatagram("bind_udp", dg.into_inner())),
+            Header::DissociateAck(_) => {
+                Err(Error::BadCommandDatagram("dissociate_ack", dg.into_inner()))
+            }
+            Header::Resume(_) => Err(Error::BadCommandDatagram("resume", dg.into_inner())),
+            Header::Bind(_) => Err(Error::BadCommandDatagram("bind", dg.into_inner())),
+            Header::Echo(_) => Err(Error::BadCommandDatagram("echo", dg.into_inner())),
+            header => Err(Error::ProtocolViolation(
+                ProtocolViolation::UnsupportedCommand(header.type_code()),
+            )),
         }
     }
 }
@@ -360,6 +1098,67 @@ impl<Side> Debug for Connection<Side> {
     }
 }
 
+/// A handle for aborting a `Connect` opened by [`Connection::connect_abortable`], e.g. from another task when the server turns unresponsive.
+///
+/// Aborting fails the pending and further reads and writes of the `Connect` with [`ErrorKind::ConnectionAborted`], resetting its streams with the given error code.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectAbortHandle(Arc<ConnectAbort>);
+
+#[derive(Debug, Default)]
+struct ConnectAbort {
+    // the error code plus one, or zero if not aborted
+    error_code: AtomicU64,
+    read_waker: AtomicWaker,
+    write_waker: AtomicWaker,
+}
+
+impl ConnectAbortHandle {
+    /// Creates a new handle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aborts the `Connect` with the error code. Only the first abort takes effect.
+    pub fn abort(&self, error_code: VarInt) {
+        let _ = self.0.error_code.compare_exchange(
+            0,
+            error_code.into_inner() + 1,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+
+        self.0.read_waker.wake();
+        self.0.write_waker.wake();
+    }
+
+    /// Returns whether the `Connect` is aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.error_code().is_some()
+    }
+
+    fn error_code(&self) -> Option<VarInt> {
+        match self.0.error_code.load(Ordering::Acquire) {
+            0 => None,
+            // stored from a `VarInt`
+            code => VarInt::from_u64(code - 1).ok(),
+        }
+    }
+
+    /// Resolves once aborted. The `Connect` is not established yet meanwhile, so the write waker is free to use.
+    async fn aborted(&self) {
+        future::poll_fn(|cx| {
+            self.0.write_waker.register(cx.waker());
+
+            if self.is_aborted() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
 /// A received `Authenticate` command.
 #[derive(Debug)]
 pub struct Authenticate {
@@ -382,6 +1181,21 @@ impl Authenticate {
         self.model.token()
     }
 
+    /// The bandwidth declared by the client, if any.
+    pub fn bandwidth(&self) -> Option<Bandwidth> {
+        self.model.bandwidth()
+    }
+
+    /// Whether the client accepts `Parity` commands, to be sent with [`Connection::packet_native_with_parity()`].
+    pub fn fec(&self) -> bool {
+        self.model.fec()
+    }
+
+    /// The minimum size of UDP packets to compress, if the client declared compression, to be set with [`Connection::set_compression()`].
+    pub fn compression(&self) -> Option<u16> {
+        self.model.compression()
+    }
+
     /// Validates if the given password is matching the hashed token.
     pub fn validate(&self, password: impl AsRef<[u8]>) -> bool {
         self.model.is_valid(password, &self.exporter)
@@ -393,6 +1207,12 @@ pub struct Connect {
     model: Side<ConnectModel<Tx>, ConnectModel<Rx>>,
     send: SendStream,
     recv: RecvStream,
+    abort: Option<ConnectAbortHandle>,
+    pre_auth: Option<Arc<PreAuth>>,
+    bytes_read: u64,
+    bytes_written: u64,
+    since: Instant,
+    on_summary: Option<Box<dyn FnOnce(ConnectSummary) + Send + Sync>>,
 }
 
 impl Connect {
@@ -401,20 +1221,113 @@ impl Connect {
         send: SendStream,
         recv: RecvStream,
     ) -> Self {
-        Self { model, send, recv }
+        Self {
+            model,
+            send,
+            recv,
+            abort: None,
+            pre_auth: None,
+            bytes_read: 0,
+            bytes_written: 0,
+            since: Instant::now(),
+            on_summary: None,
+        }
+    }
+
+    fn with_abort_handle(mut self, abort: ConnectAbortHandle) -> Self {
+        self.abort = Some(abort);
+        self
+    }
+
+    /// Counts the bytes read before the connection is authenticated against the [`PreAuthPolicy`].
+    fn with_pre_auth(mut self, pre_auth: Arc<PreAuth>) -> Self {
+        self.pre_auth = Some(pre_auth);
+        self
+    }
+
+    /// Returns the number of bytes read from the `Connect` so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of bytes written to the `Connect` so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Sets the callback receiving the [`ConnectSummary`] when the `Connect` is dropped, replacing the previous one.
+    ///
+    /// The bytes are counted as they pass through the `AsyncRead` and `AsyncWrite` implementations of the `Connect`, so the summary holds whatever copy loop is used.
+    pub fn on_summary(&mut self, f: impl FnOnce(ConnectSummary) + Send + Sync + 'static) {
+        self.on_summary = Some(Box::new(f));
+    }
+
+    /// Returns the handle for aborting the `Connect`, if it is opened by [`Connection::connect_abortable`].
+    pub fn abort_handle(&self) -> Option<&ConnectAbortHandle> {
+        self.abort.as_ref()
+    }
+
+    /// Checks if the `Connect` is aborted, resetting the streams with the error code of the abort if so.
+    fn poll_aborted(&mut self, cx: &mut Context<'_>, is_read: bool) -> Poll<IoError> {
+        let Some(abort) = &self.abort else {
+            return Poll::Pending;
+        };
+
+        let waker = if is_read {
+            &abort.0.read_waker
+        } else {
+            &abort.0.write_waker
+        };
+        waker.register(cx.waker());
+
+        match abort.error_code() {
+            Some(error_code) => {
+                let _ = self.send.reset(error_code);
+                let _ = self.recv.stop(error_code);
+                Poll::Ready(IoError::from(ErrorKind::ConnectionAborted))
+            }
+            None => Poll::Pending,
+        }
     }
 
     /// Returns the `Connect` address
     pub fn addr(&self) -> &Address {
         match &self.model {
             Side::Client(model) => {
-                let Header::Connect(conn) = model.header() else { unreachable!() };
+                let Header::Connect(conn) = model.header() else {
+                    unreachable!()
+                };
                 conn.addr()
             }
             Side::Server(model) => model.addr(),
         }
     }
 
+    /// Returns the `Connect` congestion hint
+    pub fn hint(&self) -> Option<CongestionHint> {
+        match &self.model {
+            Side::Client(model) => {
+                let Header::Connect(conn) = model.header() else {
+                    unreachable!()
+                };
+                conn.hint()
+            }
+            Side::Server(model) => model.hint(),
+        }
+    }
+
+    /// Sets the priority of sending data on the `Connect` stream, relative to other streams of the connection. Data of streams with higher priority is sent first. Defaults to 0.
+    ///
+    /// The priority only applies to the sending side, so each side sets it for the data it sends.
+    pub fn set_priority(&self, priority: i32) -> Result<(), UnknownStream> {
+        self.send.set_priority(priority)
+    }
+
+    /// Returns the priority of sending data on the `Connect` stream.
+    pub fn priority(&self) -> Result<i32, UnknownStream> {
+        self.send.priority()
+    }
+
     /// Immediately closes the `Connect` streams with the given error code. Returns the result of closing the send and receive streams, respectively.
     pub fn reset(
         &mut self,
@@ -432,7 +1345,25 @@ impl AsyncRead for Connect {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, IoError>> {
-        AsyncRead::poll_read(Pin::new(&mut self.get_mut().recv), cx, buf)
+        let this = self.get_mut();
+
+        if let Poll::Ready(err) = this.poll_aborted(cx, true) {
+            return Poll::Ready(Err(err));
+        }
+
+        let res = AsyncRead::poll_read(Pin::new(&mut this.recv), cx, buf);
+
+        if let Poll::Ready(Ok(len)) = res {
+            this.bytes_read += len as u64;
+
+            if let Some(pre_auth) = &this.pre_auth {
+                if let Err(err) = pre_auth.check(false, len) {
+                    return Poll::Ready(Err(IoError::new(ErrorKind::Other, err.to_string())));
+                }
+            }
+        }
+
+        res
     }
 }
 
@@ -442,15 +1373,39 @@ impl AsyncWrite for Connect {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, IoError>> {
-        AsyncWrite::poll_write(Pin::new(&mut self.get_mut().send), cx, buf)
+        let this = self.get_mut();
+
+        if let Poll::Ready(err) = this.poll_aborted(cx, false) {
+            return Poll::Ready(Err(err));
+        }
+
+        let res = AsyncWrite::poll_write(Pin::new(&mut this.send), cx, buf);
+
+        if let Poll::Ready(Ok(len)) = res {
+            this.bytes_written += len as u64;
+        }
+
+        res
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
-        AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().send), cx)
+        let this = self.get_mut();
+
+        if let Poll::Ready(err) = this.poll_aborted(cx, false) {
+            return Poll::Ready(Err(err));
+        }
+
+        AsyncWrite::poll_flush(Pin::new(&mut this.send), cx)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
-        AsyncWrite::poll_close(Pin::new(&mut self.get_mut().send), cx)
+        let this = self.get_mut();
+
+        if let Poll::Ready(err) = this.poll_aborted(cx, false) {
+            return Poll::Ready(Err(err));
+        }
+
+        AsyncWrite::poll_close(Pin::new(&mut this.send), cx)
     }
 }
 
@@ -465,15 +1420,408 @@ impl Debug for Connect {
             .field("model", model)
             .field("send", &self.send)
             .field("recv", &self.recv)
+            .field("bytes_read", &self.bytes_read)
+            .field("bytes_written", &self.bytes_written)
             .finish()
     }
 }
 
-/// A received `Packet` command.
+impl Drop for Connect {
+    fn drop(&mut self) {
+        if let Some(f) = self.on_summary.take() {
+            f(ConnectSummary {
+                addr: self.addr().clone(),
+                bytes_read: self.bytes_read,
+                bytes_written: self.bytes_written,
+                duration: self.since.elapsed(),
+            });
+        }
+    }
+}
+
+/// The bytes relayed through a `Connect` in each direction, reported to the callback set by [`Connect::on_summary`] when the `Connect` is dropped.
+#[derive(Clone, Debug)]
+pub struct ConnectSummary {
+    /// The `Connect` address.
+    pub addr: Address,
+    /// The number of bytes read from the `Connect`.
+    pub bytes_read: u64,
+    /// The number of bytes written to the `Connect`.
+    pub bytes_written: u64,
+    /// The time since the `Connect` was opened or accepted.
+    pub duration: Duration,
+}
+
+/// A received `BindUdp` command.
+#[derive(Debug)]
+pub struct BindUdp {
+    model: BindUdpModel<Rx>,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl BindUdp {
+    fn new(model: BindUdpModel<Rx>, send: SendStream, recv: RecvStream) -> Self {
+        Self { model, send, recv }
+    }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.model.assoc_id()
+    }
+
+    /// Returns the address to bind, or `Address::None` for any
+    pub fn addr(&self) -> &Address {
+        self.model.addr()
+    }
+
+    /// Replies the bound address to the client.
+    pub async fn reply(mut self, addr: Address) -> Result<(), Error> {
+        let header = Header::BindUdp(BindUdpHeader::new(self.assoc_id(), addr));
+        header.async_marshal(&mut self.send).await?;
+        self.send.close().await?;
+        Ok(())
+    }
+
+    /// Rejects the `BindUdp` by closing the streams with the given error code.
+    pub fn reject(mut self, error_code: VarInt) {
+        let _ = self.send.reset(error_code);
+        let _ = self.recv.stop(error_code);
+    }
+}
+
+/// A received `Bind` command.
+///
+/// The listener should be kept until [`Bind::closed`] resolves, i.e. the client closes the binding.
+#[derive(Debug)]
+pub struct Bind {
+    model: BindModel<Rx>,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl Bind {
+    fn new(model: BindModel<Rx>, send: SendStream, recv: RecvStream) -> Self {
+        Self { model, send, recv }
+    }
+
+    /// Returns the TCP binding ID
+    pub fn bind_id(&self) -> u16 {
+        self.model.bind_id()
+    }
+
+    /// Returns the address to listen on
+    pub fn addr(&self) -> &Address {
+        self.model.addr()
+    }
+
+    /// Replies the bound address to the client.
+    pub async fn reply(&mut self, addr: Address) -> Result<(), Error> {
+        let header = Header::Bind(BindHeader::new(self.bind_id(), addr));
+        header.async_marshal(&mut self.send).await?;
+        Ok(())
+    }
+
+    /// Resolves when the client closes the binding.
+    pub async fn closed(&mut self) {
+        while let Ok(Some(_)) = self.recv.read_chunk(usize::MAX, true).await {}
+    }
+
+    /// Rejects or closes the `Bind` by closing the streams with the given error code.
+    pub fn reject(mut self, error_code: VarInt) {
+        let _ = self.send.reset(error_code);
+        let _ = self.recv.stop(error_code);
+    }
+}
+
+/// A TCP binding established by [`Connection::bind`], closed when dropped.
+#[derive(Debug)]
+pub struct Binding {
+    bind_id: u16,
+    addr: Address,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl Binding {
+    fn new(bind_id: u16, addr: Address, send: SendStream, recv: RecvStream) -> Self {
+        Self {
+            bind_id,
+            addr,
+            send,
+            recv,
+        }
+    }
+
+    /// Returns the TCP binding ID
+    pub fn bind_id(&self) -> u16 {
+        self.bind_id
+    }
+
+    /// Returns the address the server listens on
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Resolves when the server closes the binding, e.g. when the listener fails.
+    pub async fn closed(&mut self) {
+        while let Ok(Some(_)) = self.recv.read_chunk(usize::MAX, true).await {}
+    }
+
+    /// Closes the binding with the given error code.
+    pub fn close(mut self, error_code: VarInt) {
+        let _ = self.send.reset(error_code);
+        let _ = self.recv.stop(error_code);
+    }
+}
+
+/// A connection accepted on the listener of a `Bind` command, relayed through a bidirectional stream.
+pub struct Inbound {
+    model: Side<BindModel<Rx>, BindModel<Tx>>,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl Inbound {
+    fn new(model: Side<BindModel<Rx>, BindModel<Tx>>, send: SendStream, recv: RecvStream) -> Self {
+        Self { model, send, recv }
+    }
+
+    /// Returns the ID of the TCP binding the connection is accepted on
+    pub fn bind_id(&self) -> u16 {
+        match &self.model {
+            Side::Client(model) => model.bind_id(),
+            Side::Server(model) => {
+                let Header::Bind(bind) = model.header() else {
+                    unreachable!()
+                };
+                bind.bind_id()
+            }
+        }
+    }
+
+    /// Returns the address of the peer of the connection
+    pub fn addr(&self) -> &Address {
+        match &self.model {
+            Side::Client(model) => model.addr(),
+            Side::Server(model) => {
+                let Header::Bind(bind) = model.header() else {
+                    unreachable!()
+                };
+                bind.addr()
+            }
+        }
+    }
+
+    /// Immediately closes the `Inbound` streams with the given error code. Returns the result of closing the send and receive streams, respectively.
+    pub fn reset(
+        &mut self,
+        error_code: VarInt,
+    ) -> (Result<(), UnknownStream>, Result<(), UnknownStream>) {
+        let send_res = self.send.reset(error_code);
+        let recv_res = self.recv.stop(error_code);
+        (send_res, recv_res)
+    }
+}
+
+impl AsyncRead for Inbound {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, IoError>> {
+        AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
+    }
+}
+
+impl AsyncWrite for Inbound {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        AsyncWrite::poll_close(Pin::new(&mut self.send), cx)
+    }
+}
+
+impl Debug for Inbound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let model = match &self.model {
+            Side::Client(model) => model as &dyn Debug,
+            Side::Server(model) => model as &dyn Debug,
+        };
+
+        f.debug_struct("Inbound")
+            .field("model", model)
+            .field("send", &self.send)
+            .field("recv", &self.recv)
+            .finish()
+    }
+}
+
+/// A `Dissociate` command received through a bidirectional stream, expecting a `DissociateAck` once the UDP session is torn down.
+#[derive(Debug)]
+pub struct ConfirmDissociate {
+    model: DissociateModel<Rx>,
+    ack: DissociateAckModel<Tx>,
+    send: SendStream,
+}
+
+impl ConfirmDissociate {
+    fn new(model: DissociateModel<Rx>, ack: DissociateAckModel<Tx>, send: SendStream) -> Self {
+        Self { model, ack, send }
+    }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.model.assoc_id()
+    }
+
+    /// Confirms to the client that the UDP session is torn down.
+    pub async fn ack(mut self) -> Result<(), Error> {
+        self.ack.header().async_marshal(&mut self.send).await?;
+        self.send.close().await?;
+        Ok(())
+    }
+}
+
+/// A received `Resume` command.
+#[derive(Debug)]
+pub struct Resume {
+    model: ResumeModel<Rx>,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl Resume {
+    fn new(model: ResumeModel<Rx>, send: SendStream, recv: RecvStream) -> Self {
+        Self { model, send, recv }
+    }
+
+    /// Returns the resumption token of the previous connection, or `None` if the client only requests a token
+    pub fn token(&self) -> Option<[u8; 16]> {
+        Some(self.model.token()).filter(|token| token != &[0; 16])
+    }
+
+    /// Replies the token issued for this connection, and the IDs of the UDP sessions resumed, to the client.
+    pub async fn reply(mut self, token: [u8; 16], assoc_ids: Vec<u16>) -> Result<(), Error> {
+        let header = Header::Resume(ResumeHeader::new(token, assoc_ids));
+        header.async_marshal(&mut self.send).await?;
+        self.send.close().await?;
+        Ok(())
+    }
+
+    /// Rejects the `Resume` by closing the streams with the given error code.
+    pub fn reject(mut self, error_code: VarInt) {
+        let _ = self.send.reset(error_code);
+        let _ = self.recv.stop(error_code);
+    }
+}
+
+/// A received command of a type unknown to this version of the crate, e.g. from a newer peer.
+///
+/// Only the version and the type code of the command are read, leaving the rest of it on its stream or datagram, so that the command can be ignored instead of closing the connection.
+#[derive(Debug)]
+pub struct Unknown {
+    type_code: u8,
+    source: UnknownSource,
+}
+
+/// Where the rest of an [`Unknown`] command is left.
+#[derive(Debug)]
+pub enum UnknownSource {
+    UniStream(RecvStream),
+    BiStream(SendStream, RecvStream),
+    Datagram(Bytes),
+}
+
+impl Unknown {
+    fn new(type_code: u8, source: UnknownSource) -> Self {
+        Self { type_code, source }
+    }
+
+    /// Returns the type code of the command
+    pub fn type_code(&self) -> u8 {
+        self.type_code
+    }
+
+    /// Returns where the rest of the command is left
+    pub fn source(&self) -> &UnknownSource {
+        &self.source
+    }
+
+    pub fn into_source(self) -> UnknownSource {
+        self.source
+    }
+
+    /// Ignores the command by closing its streams with the given error code. The peer sees the sending side of a bidirectional stream reset, as for a rejected command.
+    pub fn ignore(self, error_code: VarInt) {
+        match self.source {
+            UnknownSource::UniStream(mut recv) => {
+                let _ = recv.stop(error_code);
+            }
+            UnknownSource::BiStream(mut send, mut recv) => {
+                let _ = send.reset(error_code);
+                let _ = recv.stop(error_code);
+            }
+            UnknownSource::Datagram(_) => {}
+        }
+    }
+}
+
+/// A received `Echo` command.
+#[derive(Debug)]
+pub struct Echo {
+    model: EchoModel<Rx>,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl Echo {
+    fn new(model: EchoModel<Rx>, send: SendStream, recv: RecvStream) -> Self {
+        Self { model, send, recv }
+    }
+
+    /// Returns the payload to reflect
+    pub fn payload(&self) -> &[u8] {
+        self.model.payload()
+    }
+
+    /// Reflects the payload back to the client.
+    pub async fn reply(mut self) -> Result<(), Error> {
+        let header = Header::Echo(EchoHeader::new(self.model.into_payload()));
+        header.async_marshal(&mut self.send).await?;
+        self.send.close().await?;
+        Ok(())
+    }
+
+    /// Rejects the `Echo` by closing the streams with the given error code.
+    pub fn reject(mut self, error_code: VarInt) {
+        let _ = self.send.reset(error_code);
+        let _ = self.recv.stop(error_code);
+    }
+}
+
+/// A received `Packet` command, or a received `Parity` command of a packet.
 #[derive(Debug)]
 pub struct Packet {
-    model: PacketModel<Rx, Bytes>,
+    model: PacketKind,
     src: PacketSource,
+    compression: Arc<Compression>,
+}
+
+#[derive(Debug)]
+enum PacketKind {
+    Fragment(PacketModel<Rx, Bytes>),
+    Parity(ParityModel<Rx, Bytes>),
 }
 
 #[derive(Debug)]
@@ -483,28 +1831,65 @@ enum PacketSource {
 }
 
 impl Packet {
-    fn new(model: PacketModel<Rx, Bytes>, src: PacketSource) -> Self {
-        Self { src, model }
+    fn new(
+        model: PacketModel<Rx, Bytes>,
+        src: PacketSource,
+        compression: Arc<Compression>,
+    ) -> Self {
+        Self {
+            src,
+            model: PacketKind::Fragment(model),
+            compression,
+        }
+    }
+
+    fn with_parity(
+        model: ParityModel<Rx, Bytes>,
+        parity: Bytes,
+        compression: Arc<Compression>,
+    ) -> Self {
+        Self {
+            src: PacketSource::Native(parity),
+            model: PacketKind::Parity(model),
+            compression,
+        }
     }
 
     /// Returns the UDP session ID
     pub fn assoc_id(&self) -> u16 {
-        self.model.assoc_id()
+        match &self.model {
+            PacketKind::Fragment(model) => model.assoc_id(),
+            PacketKind::Parity(model) => model.assoc_id(),
+        }
     }
 
     /// Returns the packet ID
     pub fn pkt_id(&self) -> u16 {
-        self.model.pkt_id()
+        match &self.model {
+            PacketKind::Fragment(model) => model.pkt_id(),
+            PacketKind::Parity(model) => model.pkt_id(),
+        }
     }
 
-    /// Returns the fragment ID
+    /// Returns the fragment ID, or the total number of fragments for a `Parity`
     pub fn frag_id(&self) -> u8 {
-        self.model.frag_id()
+        match &self.model {
+            PacketKind::Fragment(model) => model.frag_id(),
+            PacketKind::Parity(model) => model.frag_total(),
+        }
     }
 
     /// Returns the total number of fragments
     pub fn frag_total(&self) -> u8 {
-        self.model.frag_total()
+        match &self.model {
+            PacketKind::Fragment(model) => model.frag_total(),
+            PacketKind::Parity(model) => model.frag_total(),
+        }
+    }
+
+    /// Whether this is the `Parity` of a packet rather than a fragment of it
+    pub fn is_parity(&self) -> bool {
+        matches!(self.model, PacketKind::Parity(_))
     }
 
     /// Whether the packet is from UDP relay mode `quic`
@@ -518,23 +1903,272 @@ impl Packet {
     }
 
     /// Accepts the packet payload. If the packet is fragmented and not yet fully assembled, `Ok(None)` is returned.
+    ///
+    /// A `Parity` completes the packet if it is the only fragment missing, by recovering it.
     pub async fn accept(self) -> Result<Option<(Bytes, Address, u16)>, Error> {
         let pkt = match self.src {
             PacketSource::Quic(mut recv) => {
-                let mut buf = vec![0; self.model.size() as usize];
+                let size = match &self.model {
+                    PacketKind::Fragment(model) => model.size(),
+                    PacketKind::Parity(model) => model.size(),
+                };
+                let mut buf = vec![0; size as usize];
                 AsyncReadExt::read_exact(&mut recv, &mut buf).await?;
                 Bytes::from(buf)
             }
             PacketSource::Native(pkt) => pkt,
         };
 
+        let assemblable = match self.model {
+            PacketKind::Fragment(model) => model.assemble(pkt)?,
+            PacketKind::Parity(model) => model.recover(pkt)?,
+        };
+
+        let Some(pkt) = assemblable else {
+            return Ok(None);
+        };
+
+        let compressed = pkt.is_compressed();
         let mut asm = Vec::new();
+        let (addr, assoc_id) = pkt.assemble(&mut asm);
 
-        Ok(self
-            .model
-            .assemble(pkt)?
-            .map(|pkt| pkt.assemble(&mut asm))
-            .map(|(addr, assoc_id)| (Bytes::from(asm), addr, assoc_id)))
+        if compressed {
+            let Some(pkt) = self.compression.decompress(&asm) else {
+                return Err(Error::Decompress(assoc_id));
+            };
+
+            return Ok(Some((Bytes::from(pkt), addr, assoc_id)));
+        }
+
+        Ok(Some((Bytes::from(asm), addr, assoc_id)))
+    }
+}
+
+/// The policy for commands received on a server side `Connection` before it is authenticated.
+///
+/// Clients usually send tasks along with the `Authenticate` command without waiting, so tasks received before authentication are accepted by default, and their handling is expected to wait for the authentication. Those tasks are never relayed, so the bytes they cost the server only amplify traffic from unauthenticated peers.
+#[derive(Clone, Copy, Debug)]
+pub struct PreAuthPolicy {
+    /// The maximum number of bytes of commands, including the payloads of packets and the data read from `Connect` streams, accepted before authentication. Exceeding it fails the command with [`Error::PreAuthLimit`]. Defaults to unlimited.
+    pub max_bytes: usize,
+    /// Whether to reject tasks other than `Authenticate` and `Heartbeat` before authentication with [`Error::Unauthenticated`]. Defaults to `false`.
+    pub reject_tasks: bool,
+}
+
+impl Default for PreAuthPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: usize::MAX,
+            reject_tasks: false,
+        }
+    }
+}
+
+struct PreAuth {
+    policy: PreAuthPolicy,
+    authenticated: AtomicBool,
+    bytes: AtomicUsize,
+}
+
+impl PreAuth {
+    fn new(policy: PreAuthPolicy) -> Self {
+        Self {
+            policy,
+            authenticated: AtomicBool::new(false),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    fn check(&self, is_task: bool, len: usize) -> Result<(), Error> {
+        if self.authenticated.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        if self.policy.reject_tasks && is_task {
+            return Err(Error::Unauthenticated);
+        }
+
+        let total = self.bytes.fetch_add(len, Ordering::AcqRel) + len;
+
+        if total > self.policy.max_bytes {
+            Err(Error::PreAuthLimit(self.policy.max_bytes))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What a server side `Connection` does with a command received where it is not expected, e.g. a `Connect` on a unidirectional stream or a `Heartbeat` on a bidirectional stream.
+///
+/// Dropping the streams of a bad command without reading them leaves the peer waiting on them, so the streams are returned in the error by default for the caller to clean up. With any other policy the streams are taken by the policy, and [`Error::BadCommand`] is returned instead.
+#[derive(Clone, Default)]
+pub enum BadCommandPolicy {
+    /// Return the stream or datagram in [`Error::BadCommandUniStream`], [`Error::BadCommandBiStream`] or [`Error::BadCommandDatagram`].
+    #[default]
+    Return,
+    /// Read and discard the rest of the stream until the peer finishes it, then finish the sending side of a bidirectional stream.
+    Discard,
+    /// Hold the stream open without reading it until the connection is closed, occupying the stream concurrency limit of the peer. The `accept_*` function only returns then, unless the caller gives up on it earlier, e.g. with a timeout.
+    Tarpit,
+    /// Hand the stream or datagram to the handler.
+    Handle(Arc<dyn Fn(BadCommand) + Send + Sync>),
+}
+
+impl Debug for BadCommandPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Return => write!(f, "Return"),
+            Self::Discard => write!(f, "Discard"),
+            Self::Tarpit => write!(f, "Tarpit"),
+            Self::Handle(_) => write!(f, "Handle"),
+        }
+    }
+}
+
+/// A command received where it is not expected, with the stream or datagram carrying it.
+#[derive(Debug)]
+pub enum BadCommand {
+    UniStream(&'static str, RecvStream),
+    BiStream(&'static str, SendStream, RecvStream),
+    Datagram(&'static str, Bytes),
+}
+
+impl BadCommand {
+    /// The name of the command.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::UniStream(cmd, _) | Self::BiStream(cmd, _, _) | Self::Datagram(cmd, _) => cmd,
+        }
+    }
+
+    /// Reads and discards the rest of the stream, then finishes the sending side of a bidirectional stream.
+    pub async fn discard(self) {
+        match self {
+            Self::UniStream(_, mut recv) => {
+                while let Ok(Some(_)) = recv.read_chunk(usize::MAX, false).await {}
+            }
+            Self::BiStream(_, mut send, mut recv) => {
+                while let Ok(Some(_)) = recv.read_chunk(usize::MAX, false).await {}
+                let _ = send.finish().await;
+            }
+            Self::Datagram(_, _) => {}
+        }
+    }
+
+    fn into_error(self) -> Error {
+        match self {
+            Self::UniStream(cmd, recv) => Error::BadCommandUniStream(cmd, recv),
+            Self::BiStream(cmd, send, recv) => Error::BadCommandBiStream(cmd, send, recv),
+            Self::Datagram(cmd, dg) => Error::BadCommandDatagram(cmd, dg),
+        }
+    }
+}
+
+/// Unmarshals a command header from the stream, returning the number of bytes consumed on failure.
+async fn unmarshal_stream(recv: &mut RecvStream) -> Result<Header, (Box<UnmarshalError>, usize)> {
+    let mut recv = CountingRead {
+        inner: recv,
+        len: 0,
+    };
+
+    match Header::async_unmarshal(&mut recv).await {
+        Ok(header) => Ok(header),
+        Err(err) => Err((Box::new(err), recv.len)),
+    }
+}
+
+/// Whether the stream finished before the header
+fn is_end_of_stream(err: &UnmarshalError) -> bool {
+    matches!(err, UnmarshalError::Io(err) if err.kind() == ErrorKind::UnexpectedEof)
+}
+
+struct CountingRead<'a, R> {
+    inner: &'a mut R,
+    len: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingRead<'_, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.get_mut();
+        let res = AsyncRead::poll_read(Pin::new(&mut *this.inner), cx, buf);
+
+        if let Poll::Ready(Ok(len)) = res {
+            this.len += len;
+        }
+
+        res
+    }
+}
+
+/// The application error codes for closing a connection, telling the peer why it is closed.
+///
+/// Peers not knowing the codes close connections with `0`, which is [`CloseCode::Normal`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseCode {
+    /// No specific reason.
+    Normal,
+    /// The peer sent something violating the protocol, e.g. a bad command.
+    ProtocolError,
+    /// The client failed authenticating, e.g. with a wrong password or a timeout.
+    AuthFailed,
+    /// The user of the client is no longer allowed.
+    AuthRevoked,
+    /// The user of the client exceeded its quota.
+    QuotaExceeded,
+    /// The server is shutting down.
+    ShuttingDown,
+}
+
+impl CloseCode {
+    /// Returns the code of a `VarInt`, or `None` if unknown.
+    pub fn from_varint(code: VarInt) -> Option<Self> {
+        match code.into_inner() {
+            0 => Some(Self::Normal),
+            1 => Some(Self::ProtocolError),
+            2 => Some(Self::AuthFailed),
+            3 => Some(Self::AuthRevoked),
+            4 => Some(Self::QuotaExceeded),
+            5 => Some(Self::ShuttingDown),
+            _ => None,
+        }
+    }
+
+    /// Returns the code the peer closed the connection with, or `None` if the connection is not closed by the peer with a known code.
+    pub fn from_connection_error(err: &ConnectionError) -> Option<Self> {
+        match err {
+            ConnectionError::ApplicationClosed(close) => Self::from_varint(close.error_code),
+            _ => None,
+        }
+    }
+}
+
+impl From<CloseCode> for VarInt {
+    fn from(code: CloseCode) -> Self {
+        match code {
+            CloseCode::Normal => VarInt::from_u32(0),
+            CloseCode::ProtocolError => VarInt::from_u32(1),
+            CloseCode::AuthFailed => VarInt::from_u32(2),
+            CloseCode::AuthRevoked => VarInt::from_u32(3),
+            CloseCode::QuotaExceeded => VarInt::from_u32(4),
+            CloseCode::ShuttingDown => VarInt::from_u32(5),
+        }
+    }
+}
+
+impl Display for CloseCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Normal => write!(f, "normal"),
+            Self::ProtocolError => write!(f, "protocol error"),
+            Self::AuthFailed => write!(f, "authentication failed"),
+            Self::AuthRevoked => write!(f, "authentication revoked"),
+            Self::QuotaExceeded => write!(f, "quota exceeded"),
+            Self::ShuttingDown => write!(f, "shutting down"),
+        }
     }
 }
 
@@ -546,19 +2180,27 @@ pub enum Task {
     Connect(Connect),
     Packet(Packet),
     Dissociate(u16),
+    DissociateAll(Vec<u16>),
     Heartbeat,
+    BindUdp(BindUdp),
+    ConfirmDissociate(ConfirmDissociate),
+    Resume(Resume),
+    Bind(Bind),
+    Inbound(Inbound),
+    Echo(Echo),
+    Unknown(Unknown),
 }
 
 #[derive(Debug)]
 struct KeyingMaterialExporter(QuinnConnection);
 
 impl KeyingMaterialExporterImpl for KeyingMaterialExporter {
-    fn export_keying_material(&self, label: &[u8], context: &[u8]) -> [u8; 32] {
+    fn export_keying_material(&self, label: &[u8], context: &[u8]) -> Option<[u8; 32]> {
         let mut buf = [0; 32];
         self.0
             .export_keying_material(&mut buf, label, context)
-            .unwrap();
-        buf
+            .ok()?;
+        Some(buf)
     }
 }
 
@@ -573,14 +2215,24 @@ pub enum Error {
     SendDatagram(#[from] SendDatagramError),
     #[error("expecting payload length {0} but got {1}")]
     PayloadLength(usize, usize),
+    #[error("failed to export keying material for authentication")]
+    ExportKeyingMaterial,
+    #[error("task received before authentication")]
+    Unauthenticated,
+    #[error("exceeded the limit of {0} bytes before authentication")]
+    PreAuthLimit(usize),
+    #[error("packet size {0} exceeds the maximum {1}")]
+    PacketTooLarge(u16, u16),
     #[error("packet {1:#06x} on invalid udp session {0:#06x}")]
     InvalidUdpSession(u16, u16),
+    #[error("invalid compressed packet on udp session {0:#06x}")]
+    Decompress(u16),
     #[error(transparent)]
     Assemble(#[from] AssembleError),
-    #[error("error unmarshalling uni_stream: {0}")]
-    UnmarshalUniStream(UnmarshalError, RecvStream),
-    #[error("error unmarshalling bi_stream: {0}")]
-    UnmarshalBiStream(UnmarshalError, SendStream, RecvStream),
+    #[error("error unmarshalling uni_stream after {1} bytes: {0}")]
+    UnmarshalUniStream(Box<UnmarshalError>, usize, RecvStream),
+    #[error("error unmarshalling bi_stream after {1} bytes: {0}")]
+    UnmarshalBiStream(Box<UnmarshalError>, usize, SendStream, RecvStream),
     #[error("error unmarshalling datagram: {0}")]
     UnmarshalDatagram(UnmarshalError, Bytes),
     #[error("bad command `{0}` from uni_stream")]
@@ -589,4 +2241,74 @@ pub enum Error {
     BadCommandBiStream(&'static str, SendStream, RecvStream),
     #[error("bad command `{0}` from datagram")]
     BadCommandDatagram(&'static str, Bytes),
+    #[error("bad command `{0}`")]
+    BadCommand(&'static str),
+    #[error("error unmarshalling `bind_udp` response: {0}")]
+    UnmarshalBindUdpResponse(UnmarshalError),
+    #[error("bad `bind_udp` response")]
+    BadBindUdpResponse,
+    #[error("error unmarshalling `dissociate_ack`: {0}")]
+    UnmarshalDissociateAck(UnmarshalError),
+    #[error("bad `dissociate_ack`")]
+    BadDissociateAck,
+    #[error("error unmarshalling `resume` response: {0}")]
+    UnmarshalResumeResponse(UnmarshalError),
+    #[error("bad `resume` response")]
+    BadResumeResponse,
+    #[error("error unmarshalling `echo` response: {0}")]
+    UnmarshalEchoResponse(UnmarshalError),
+    #[error("bad `echo` response")]
+    BadEchoResponse,
+    #[error("error unmarshalling `bind` response: {0}")]
+    UnmarshalBindResponse(UnmarshalError),
+    #[error("bad `bind` response")]
+    BadBindResponse,
+    #[error("timed out opening `connect`")]
+    ConnectTimeout,
+    #[error("`connect` aborted")]
+    ConnectAborted,
+    #[error("protocol violation: {0}")]
+    ProtocolViolation(ProtocolViolation),
+}
+
+/// A violation of the protocol by the peer that is not specific to a command
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ProtocolViolation {
+    /// A command that unmarshals but is not handled by this version of the crate
+    #[error("unsupported command {0:#04x}")]
+    UnsupportedCommand(u8),
+}
+
+impl Error {
+    /// Cleans up the streams carried by an unmarshal or bad command error.
+    ///
+    /// Reads and discards up to `max_len` bytes of the receiving side until the peer finishes it, then stops it with `error_code` if unfinished. The sending side of a bidirectional stream is reset with `error_code`. Errors carrying no stream are ignored.
+    pub async fn drain_and_stop(self, max_len: usize, error_code: VarInt) {
+        let (send, mut recv) = match self {
+            Self::UnmarshalUniStream(_, _, recv) | Self::BadCommandUniStream(_, recv) => {
+                (None, recv)
+            }
+            Self::UnmarshalBiStream(_, _, send, recv) | Self::BadCommandBiStream(_, send, recv) => {
+                (Some(send), recv)
+            }
+            _ => return,
+        };
+
+        if let Some(mut send) = send {
+            let _ = send.reset(error_code);
+        }
+
+        let mut len = 0;
+
+        while len < max_len {
+            match recv.read_chunk(max_len - len, true).await {
+                Ok(Some(chunk)) => len += chunk.bytes.len(),
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        let _ = recv.stop(error_code);
+    }
 }
@@ -0,0 +1,677 @@
+//! An in-memory network for running QUIC endpoints without sockets, with controllable datagram loss and reordering.
+//!
+//! Datagrams are passed through queues instead of the OS, and the loss, reordering and jitter are decided by a seeded pseudo-random generator, so a scenario with the same seed and the same traffic plays out the same way every time. This is meant for exercising the relay logic, garbage collection and fragment reassembly deterministically and quickly.
+//!
+//! Only the sockets are simulated. Timers and tasks are still provided by the [`Runtime`] the network is created with, and quinn reads the system clock, so latency and timeouts take real time.
+
+use bytes::Bytes;
+use quinn::{
+    udp::{EcnCodepoint, RecvMeta, Transmit, UdpState},
+    AsyncTimer, AsyncUdpSocket, Endpoint, EndpointConfig, Runtime, ServerConfig,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{Error as IoError, ErrorKind, IoSliceMut, Result as IoResult},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// The seed of networks created with [`MockNetwork::new`]
+const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The first port assigned to sockets bound to port 0
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// The number of datagrams queued for each socket, beyond which datagrams are dropped as on a congested path
+const QUEUE_SIZE: usize = 1024;
+
+/// How datagrams are delivered in a [`MockNetwork`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditions {
+    /// The probability of dropping a datagram, from 0 to 1
+    pub loss: f64,
+    /// The probability of holding a datagram back until the next datagram to the same socket is delivered, from 0 to 1
+    pub reorder: f64,
+    /// The delay of every datagram
+    pub latency: Duration,
+    /// The maximum extra delay of a datagram, drawn uniformly for each one, which reorders datagrams sent closer together than it
+    pub jitter: Duration,
+}
+
+/// Counters of the datagrams sent through a [`MockNetwork`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub lost: u64,
+    pub reordered: u64,
+    /// Datagrams to an address no socket is bound to, or to a socket with a full queue
+    pub unreachable: u64,
+    /// Datagrams dropped by the NAT in front of the receiving socket, see [`MockNetwork::set_nat`]
+    pub filtered: u64,
+}
+
+/// An in-memory network connecting [`MockUdpSocket`]s by their addresses
+///
+/// Cloning the handle gives access to the same network, e.g. for changing the conditions while endpoints are running on it.
+#[derive(Clone)]
+pub struct MockNetwork {
+    inner: Arc<Mutex<Network>>,
+}
+
+struct Network {
+    runtime: Arc<dyn Runtime>,
+    sockets: HashMap<SocketAddr, Queue>,
+    conditions: Conditions,
+    rng: XorShift,
+    next_port: u16,
+    stats: Stats,
+}
+
+#[derive(Default)]
+struct Queue {
+    datagrams: VecDeque<Datagram>,
+    held: Option<Datagram>,
+    waker: Option<Waker>,
+    nat: Option<Nat>,
+}
+
+struct Datagram {
+    src: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    data: Bytes,
+    deliver_at: Instant,
+}
+
+/// A NAT in front of a socket, only letting datagrams in while its mapping is kept alive by datagrams sent out
+struct Nat {
+    timeout: Duration,
+    last_sent: Instant,
+}
+
+impl MockNetwork {
+    /// Creates a lossless network with the default seed, delaying datagrams with the timers of `runtime`
+    pub fn new(runtime: Arc<dyn Runtime>) -> Self {
+        Self::with_seed(DEFAULT_SEED, runtime)
+    }
+
+    /// Creates a lossless network, deciding the loss, reordering and jitter with a generator seeded with `seed`
+    pub fn with_seed(seed: u64, runtime: Arc<dyn Runtime>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Network {
+                runtime,
+                sockets: HashMap::new(),
+                conditions: Conditions::default(),
+                rng: XorShift::new(seed),
+                next_port: EPHEMERAL_PORT_START,
+                stats: Stats::default(),
+            })),
+        }
+    }
+
+    /// Binds a socket to the address, assigning an unused port if the port is 0
+    pub fn bind(&self, addr: SocketAddr) -> IoResult<MockUdpSocket> {
+        let mut net = self.inner.lock().unwrap();
+        let mut addr = addr;
+
+        if addr.port() == 0 {
+            let start = net.next_port;
+
+            loop {
+                addr.set_port(net.next_port);
+                net.next_port = net.next_port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+
+                if !net.sockets.contains_key(&addr) {
+                    break;
+                }
+
+                if net.next_port == start {
+                    return Err(IoError::new(
+                        ErrorKind::AddrNotAvailable,
+                        "no ephemeral port available",
+                    ));
+                }
+            }
+        } else if net.sockets.contains_key(&addr) {
+            return Err(IoError::new(
+                ErrorKind::AddrInUse,
+                format!("{addr} is already bound"),
+            ));
+        }
+
+        net.sockets.insert(addr, Queue::default());
+
+        Ok(MockUdpSocket {
+            net: self.inner.clone(),
+            runtime: net.runtime.clone(),
+            addr,
+            timer: Mutex::new(None),
+        })
+    }
+
+    /// Creates a QUIC endpoint on a socket bound to the address
+    pub fn endpoint(
+        &self,
+        addr: SocketAddr,
+        config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+    ) -> IoResult<Endpoint> {
+        let socket = self.bind(addr)?;
+        let runtime = self.inner.lock().unwrap().runtime.clone();
+        Endpoint::new_with_abstract_socket(config, server_config, socket, runtime)
+    }
+
+    /// Puts the socket bound to the address behind a NAT, or removes it with `None`
+    ///
+    /// The NAT drops datagrams to the socket unless the socket sent a datagram within `timeout`, as a home router forgets an idle UDP mapping. The mapping starts fresh.
+    pub fn set_nat(&self, addr: SocketAddr, timeout: Option<Duration>) -> IoResult<()> {
+        let mut net = self.inner.lock().unwrap();
+
+        let Some(queue) = net.sockets.get_mut(&addr) else {
+            return Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("{addr} is not bound"),
+            ));
+        };
+
+        queue.nat = timeout.map(|timeout| Nat {
+            timeout,
+            last_sent: Instant::now(),
+        });
+
+        Ok(())
+    }
+
+    pub fn runtime(&self) -> Arc<dyn Runtime> {
+        self.inner.lock().unwrap().runtime.clone()
+    }
+
+    pub fn conditions(&self) -> Conditions {
+        self.inner.lock().unwrap().conditions
+    }
+
+    /// Sets how datagrams sent from now on are delivered
+    pub fn set_conditions(&self, conditions: Conditions) {
+        self.inner.lock().unwrap().conditions = conditions;
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.inner.lock().unwrap().stats
+    }
+
+    /// Delivers the datagrams held back for reordering
+    pub fn flush(&self) {
+        let mut net = self.inner.lock().unwrap();
+
+        for queue in net.sockets.values_mut() {
+            if let Some(datagram) = queue.held.take() {
+                queue.push(datagram);
+                queue.wake();
+            }
+        }
+    }
+}
+
+impl Debug for MockNetwork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let net = self.inner.lock().unwrap();
+
+        f.debug_struct("MockNetwork")
+            .field("sockets", &net.sockets.len())
+            .field("conditions", &net.conditions)
+            .field("stats", &net.stats)
+            .finish()
+    }
+}
+
+impl Network {
+    fn send(&mut self, src: SocketAddr, dst: SocketAddr, ecn: Option<EcnCodepoint>, data: Bytes) {
+        let now = Instant::now();
+        self.stats.sent += 1;
+
+        if let Some(nat) = self
+            .sockets
+            .get_mut(&src)
+            .and_then(|queue| queue.nat.as_mut())
+        {
+            nat.last_sent = now;
+        }
+
+        // every draw is made for every datagram, so that changing one condition does not shift the sequence of the others
+        let lost = self.rng.next_f64() < self.conditions.loss;
+        let reordered = self.rng.next_f64() < self.conditions.reorder;
+        let jitter = self.conditions.jitter.mul_f64(self.rng.next_f64());
+
+        if lost {
+            self.stats.lost += 1;
+            return;
+        }
+
+        let Some(queue) = self.sockets.get_mut(&dst) else {
+            self.stats.unreachable += 1;
+            return;
+        };
+
+        if let Some(nat) = &queue.nat {
+            if now.duration_since(nat.last_sent) > nat.timeout {
+                self.stats.filtered += 1;
+                return;
+            }
+        }
+
+        if queue.datagrams.len() >= QUEUE_SIZE {
+            self.stats.unreachable += 1;
+            return;
+        }
+
+        let datagram = Datagram {
+            src,
+            ecn,
+            data,
+            deliver_at: now + self.conditions.latency + jitter,
+        };
+
+        if reordered && queue.held.is_none() {
+            queue.held = Some(datagram);
+            self.stats.reordered += 1;
+            return;
+        }
+
+        let deliver_at = datagram.deliver_at;
+        queue.push(datagram);
+
+        if let Some(mut held) = queue.held.take() {
+            held.deliver_at = held.deliver_at.max(deliver_at);
+            queue.push(held);
+        }
+
+        queue.wake();
+    }
+}
+
+impl Queue {
+    /// Queues the datagram after those to be delivered no later than it
+    fn push(&mut self, datagram: Datagram) {
+        let idx = self
+            .datagrams
+            .partition_point(|queued| queued.deliver_at <= datagram.deliver_at);
+        self.datagrams.insert(idx, datagram);
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A UDP socket of a [`MockNetwork`], unbound when dropped
+pub struct MockUdpSocket {
+    net: Arc<Mutex<Network>>,
+    runtime: Arc<dyn Runtime>,
+    addr: SocketAddr,
+    timer: Mutex<Option<Pin<Box<dyn AsyncTimer>>>>,
+}
+
+impl AsyncUdpSocket for MockUdpSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        _cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<IoResult<usize>> {
+        let mut net = self.net.lock().unwrap();
+
+        for transmit in transmits {
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            let mut offset = 0;
+
+            while offset < transmit.contents.len() {
+                let end = (offset + segment_size.max(1)).min(transmit.contents.len());
+                let segment = transmit.contents.slice(offset..end);
+                net.send(self.addr, transmit.destination, transmit.ecn, segment);
+                offset = end;
+            }
+        }
+
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<IoResult<usize>> {
+        loop {
+            let mut net = self.net.lock().unwrap();
+
+            let Some(queue) = net.sockets.get_mut(&self.addr) else {
+                return Poll::Ready(Err(IoError::new(ErrorKind::NotConnected, "socket unbound")));
+            };
+
+            let count = Self::deliver(queue, self.addr, bufs, meta);
+
+            if count > 0 {
+                net.stats.delivered += count as u64;
+                return Poll::Ready(Ok(count));
+            }
+
+            // woken by the next datagram arriving, or by the timer if one is in flight
+            queue.waker = Some(cx.waker().clone());
+
+            let Some(next) = queue.datagrams.front().map(|datagram| datagram.deliver_at) else {
+                return Poll::Pending;
+            };
+
+            drop(net);
+
+            let mut timer = self.timer.lock().unwrap();
+            let timer = match timer.as_mut() {
+                Some(timer) => {
+                    timer.as_mut().reset(next);
+                    timer
+                }
+                None => timer.insert(self.runtime.new_timer(next)),
+            };
+
+            if timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+impl MockUdpSocket {
+    /// Copies the datagrams due for delivery into the buffers, returning how many are copied
+    fn deliver(
+        queue: &mut Queue,
+        addr: SocketAddr,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> usize {
+        let now = Instant::now();
+        let mut count = 0;
+
+        for (buf, meta) in bufs.iter_mut().zip(meta.iter_mut()) {
+            if queue
+                .datagrams
+                .front()
+                .map_or(true, |datagram| datagram.deliver_at > now)
+            {
+                break;
+            }
+
+            let datagram = queue.datagrams.pop_front().unwrap();
+
+            let len = datagram.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram.data[..len]);
+
+            *meta = RecvMeta {
+                addr: datagram.src,
+                len,
+                stride: len,
+                ecn: datagram.ecn,
+                dst_ip: Some(addr.ip()),
+            };
+
+            count += 1;
+        }
+
+        count
+    }
+}
+
+impl Drop for MockUdpSocket {
+    fn drop(&mut self) {
+        if let Ok(mut net) = self.net.lock() {
+            net.sockets.remove(&self.addr);
+        }
+    }
+}
+
+impl Debug for MockUdpSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("MockUdpSocket")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+/// The xorshift64* generator, which is enough for deciding the fate of datagrams reproducibly
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // the state must not be zero
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        side::{Client, Server},
+        Connection, Task,
+    };
+    use quinn::{ClientConfig, Connection as QuinnConnection, TokioRuntime, TransportConfig};
+    use rustls::{Certificate, PrivateKey, RootCertStore};
+    use std::collections::HashSet;
+    use tuic::Address;
+
+    const SERVER_ADDR: ([u8; 4], u16) = ([10, 0, 0, 1], 443);
+    const CLIENT_ADDR: ([u8; 4], u16) = ([10, 0, 0, 2], 0);
+
+    /// Creates a server endpoint, and a client endpoint trusting it for `localhost` with the transport config
+    pub(crate) fn endpoints(net: &MockNetwork, transport: TransportConfig) -> (Endpoint, Endpoint) {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let cert_der = Certificate(cert.serialize_der().unwrap());
+        let key_der = PrivateKey(cert.serialize_private_key_der());
+
+        let server_cfg = ServerConfig::with_single_cert(vec![cert_der.clone()], key_der).unwrap();
+        let server = net
+            .endpoint(
+                SocketAddr::from(SERVER_ADDR),
+                EndpointConfig::default(),
+                Some(server_cfg),
+            )
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&cert_der).unwrap();
+
+        let mut client_cfg = ClientConfig::with_root_certificates(roots);
+        client_cfg.transport_config(Arc::new(transport));
+
+        let mut client = net
+            .endpoint(
+                SocketAddr::from(CLIENT_ADDR),
+                EndpointConfig::default(),
+                None,
+            )
+            .unwrap();
+        client.set_default_client_config(client_cfg);
+
+        (server, client)
+    }
+
+    /// Connects the TUIC client and server over the network, with the server side already authenticated
+    async fn connect(
+        net: &MockNetwork,
+    ) -> (Connection<Client>, Connection<Server>, QuinnConnection) {
+        let (server, client) = endpoints(net, TransportConfig::default());
+
+        let connecting = client
+            .connect(server.local_addr().unwrap(), "localhost")
+            .unwrap();
+
+        let (client_conn, server_conn) =
+            tokio::join!(connecting, async { server.accept().await.unwrap().await });
+        let (client_conn, server_conn) = (client_conn.unwrap(), server_conn.unwrap());
+
+        let server = Connection::<Server>::new(server_conn.clone());
+        server.set_authenticated();
+
+        (Connection::<Client>::new(client_conn), server, server_conn)
+    }
+
+    /// The payloads of the packets, each of several fragments of at most 200 bytes
+    fn payloads() -> Vec<Vec<u8>> {
+        (0..20u8).map(|idx| vec![idx; 900]).collect()
+    }
+
+    fn send_all(client: &Connection<Client>) {
+        let addr = Address::SocketAddress(SocketAddr::from(([192, 0, 2, 1], 53)));
+
+        for payload in payloads() {
+            client
+                .packet_native_with_max_size(payload, addr.clone(), 1, 200)
+                .unwrap();
+        }
+    }
+
+    /// Reassembles the packets received by the server, until no datagram arrives for 200 milliseconds
+    async fn recv_all(
+        net: &MockNetwork,
+        server: &Connection<Server>,
+        conn: &QuinnConnection,
+    ) -> Vec<Vec<u8>> {
+        let mut pkts = Vec::new();
+
+        loop {
+            let dg = match tokio::time::timeout(Duration::from_millis(200), conn.read_datagram())
+                .await
+            {
+                Ok(dg) => dg.unwrap(),
+                // a datagram held back for reordering is only delivered with the next one
+                Err(_) if is_holding(net) => {
+                    net.flush();
+                    continue;
+                }
+                Err(_) => return pkts,
+            };
+
+            let Ok(Task::Packet(pkt)) = server.accept_datagram(dg) else {
+                panic!("not a packet");
+            };
+
+            if let Some((pkt, _, _)) = pkt.accept().await.unwrap() {
+                pkts.push(pkt.to_vec());
+            }
+        }
+    }
+
+    fn is_holding(net: &MockNetwork) -> bool {
+        let net = net.inner.lock().unwrap();
+        net.sockets.values().any(|queue| queue.held.is_some())
+    }
+
+    #[test]
+    fn same_seed_same_fate() {
+        let run = |seed| {
+            let net = MockNetwork::with_seed(seed, Arc::new(TokioRuntime));
+            let _socket = net.bind(SocketAddr::from(SERVER_ADDR)).unwrap();
+
+            net.set_conditions(Conditions {
+                loss: 0.2,
+                reorder: 0.2,
+                ..Conditions::default()
+            });
+
+            let mut net = net.inner.lock().unwrap();
+
+            for _ in 0..1000 {
+                net.send(
+                    SocketAddr::from(([10, 0, 0, 2], 1)),
+                    SocketAddr::from(SERVER_ADDR),
+                    None,
+                    Bytes::from_static(b"datagram"),
+                );
+            }
+
+            let queued = net.sockets[&SocketAddr::from(SERVER_ADDR)].datagrams.len();
+            (net.stats, queued)
+        };
+
+        let (stats, queued) = run(1);
+        assert!(stats.lost > 0 && stats.reordered > 0);
+        assert_eq!(run(1), (stats, queued));
+        assert_ne!(run(2).0, stats);
+    }
+
+    #[tokio::test]
+    async fn reassemble_reordered_fragments() {
+        let net = MockNetwork::new(Arc::new(TokioRuntime));
+        let (client, server, conn) = connect(&net).await;
+
+        net.set_conditions(Conditions {
+            reorder: 0.3,
+            jitter: Duration::from_millis(5),
+            ..Conditions::default()
+        });
+
+        send_all(&client);
+        let pkts = recv_all(&net, &server, &conn).await;
+
+        assert!(net.stats().reordered > 0);
+        assert_eq!(
+            pkts.into_iter().collect::<HashSet<_>>(),
+            payloads().into_iter().collect::<HashSet<_>>()
+        );
+
+        let counts = server.assembly_counts();
+        assert_eq!((counts.assembled, counts.pending), (20, 0));
+    }
+
+    #[tokio::test]
+    async fn collect_incomplete_packets() {
+        let net = MockNetwork::new(Arc::new(TokioRuntime));
+        let (client, server, conn) = connect(&net).await;
+
+        net.set_conditions(Conditions {
+            loss: 0.2,
+            ..Conditions::default()
+        });
+
+        send_all(&client);
+        let pkts = recv_all(&net, &server, &conn).await;
+
+        // packets losing any fragment are never assembled, and the others are intact
+        assert!(pkts.len() < 20);
+        assert!(pkts.iter().all(|pkt| payloads().contains(pkt)));
+
+        let pending = server.assembly_counts().pending;
+        assert!(pending > 0);
+
+        server.collect_garbage(Duration::ZERO);
+
+        let counts = server.assembly_counts();
+        assert_eq!(counts.pending, 0);
+        assert_eq!(counts.timed_out, pending as u64);
+        assert_eq!(counts.assembled, pkts.len() as u64);
+    }
+}
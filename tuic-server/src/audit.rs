@@ -0,0 +1,236 @@
+//! Recording the TCP relays of the users, for operators required to keep such records
+//!
+//! A record is written when a relay closes, as a line of JSON:
+//!
+//! ```json
+//! {"time":"2023-06-01T12:00:00Z","user":"UUID","client":"203.0.113.0","target":"example.com:443","up":1024,"down":65536,"duration":1.5}
+//! ```
+//!
+//! `up` is the number of bytes sent by the client, `down` the number received, and `duration` in seconds. The IP addresses of the clients and the targets keep only the first `ipv4_prefix` / `ipv6_prefix` bits, the rest being zeroed. With `hash_domains`, a domain is replaced by the hex-encoded first 16 bytes of the SHA-256 of `hash_salt` followed by the lowercase domain, so a known domain can still be matched against the log.
+//!
+//! The records are written by a thread of their own. Once the file would grow past `max_size`, it is renamed with the suffix `.1`, shifting the older files up to `.{max_files}`, and a new file is started.
+
+use crate::{config::AuditLog as AuditLogConfig, error::Error};
+use ring::digest::{self, SHA256};
+use serde::Serialize;
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Error as IoError, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tuic::Address;
+use uuid::Uuid;
+
+/// The number of bytes of the SHA-256 in a hashed domain
+const HASH_LEN: usize = 16;
+
+pub struct AuditLog {
+    tx: UnboundedSender<Vec<u8>>,
+    hash_salt: Option<String>,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: &'a str,
+    user: Uuid,
+    client: IpAddr,
+    target: &'a str,
+    up: u64,
+    down: u64,
+    duration: f64,
+}
+
+impl AuditLog {
+    pub fn new(cfg: AuditLogConfig) -> Result<Self, Error> {
+        Self::check(&cfg)?;
+
+        let writer = Writer::open(cfg.path, cfg.max_size, cfg.max_files)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        thread::Builder::new()
+            .name(String::from("audit-log"))
+            .spawn(move || writer.run(rx))?;
+
+        Ok(Self {
+            tx,
+            hash_salt: cfg.hash_domains.then_some(cfg.hash_salt),
+            ipv4_prefix: cfg.ipv4_prefix,
+            ipv6_prefix: cfg.ipv6_prefix,
+        })
+    }
+
+    /// Checks the settings, without opening the file
+    pub fn check(cfg: &AuditLogConfig) -> Result<(), Error> {
+        if cfg.ipv4_prefix > 32 {
+            return Err(Error::InvalidAuditLog("`ipv4_prefix` cannot exceed 32"));
+        }
+
+        if cfg.ipv6_prefix > 128 {
+            return Err(Error::InvalidAuditLog("`ipv6_prefix` cannot exceed 128"));
+        }
+
+        Ok(())
+    }
+
+    /// Records a closed TCP relay
+    pub fn record(
+        &self,
+        user: Uuid,
+        client: SocketAddr,
+        target: &Address,
+        up: u64,
+        down: u64,
+        duration: Duration,
+    ) {
+        let target = match target {
+            Address::DomainAddress(domain, port) => match &self.hash_salt {
+                Some(salt) => format!("{}:{port}", hash(salt, domain)),
+                None => format!("{domain}:{port}"),
+            },
+            Address::SocketAddress(addr) => {
+                SocketAddr::new(self.truncate(addr.ip()), addr.port()).to_string()
+            }
+            Address::None => String::from("none"),
+        };
+
+        let time = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+
+        let record = Record {
+            time: &time,
+            user,
+            client: self.truncate(client.ip()),
+            target: &target,
+            up,
+            down,
+            duration: duration.as_secs_f64(),
+        };
+
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+        let _ = self.tx.send(line);
+    }
+
+    fn truncate(&self, ip: IpAddr) -> IpAddr {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.ipv4_prefix));
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask.unwrap_or(0)))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.ipv6_prefix));
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask.unwrap_or(0)))
+            }
+        }
+    }
+}
+
+fn hash(salt: &str, domain: &str) -> String {
+    let mut ctx = digest::Context::new(&SHA256);
+    ctx.update(salt.as_bytes());
+    ctx.update(domain.to_ascii_lowercase().as_bytes());
+
+    ctx.finish().as_ref()[..HASH_LEN].iter().fold(
+        String::with_capacity(HASH_LEN * 2),
+        |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        },
+    )
+}
+
+struct Writer {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl Writer {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(Error::AuditLog)?;
+
+        let size = file.metadata().map_err(Error::AuditLog)?.len();
+
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn run(mut self, mut rx: UnboundedReceiver<Vec<u8>>) {
+        while let Some(line) = rx.blocking_recv() {
+            let mut res = self.write(&line);
+
+            // the records queued meanwhile are flushed together
+            while res.is_ok() {
+                let Ok(line) = rx.try_recv() else {
+                    break;
+                };
+                res = self.write(&line);
+            }
+
+            if let Err(err) = res.and_then(|()| self.file.flush()) {
+                log::warn!("[audit] failed writing {}: {err}", self.path.display());
+            }
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<(), IoError> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), IoError> {
+        self.file.flush()?;
+
+        if self.max_files > 0 {
+            for idx in (1..self.max_files).rev() {
+                let _ = fs::rename(self.rotated(idx), self.rotated(idx + 1));
+            }
+
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, idx: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{idx}"));
+        PathBuf::from(path)
+    }
+}
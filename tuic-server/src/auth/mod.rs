@@ -0,0 +1,205 @@
+//! Looking up the passwords of users for authenticating connections
+//!
+//! The users in the config are looked up first, then the tokens issued with `tokens.secret`, then the backend in `auth`, if any, so that panels can manage users without the config being rewritten. The passwords found by the backend are cached for `cache_ttl`, while users not found are looked up again on every connection.
+
+use self::tokens::Tokens;
+use crate::{
+    config::{Auth as AuthConfig, AuthBackend, Tokens as TokensConfig},
+    error::Error,
+    sni,
+};
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod tokens;
+mod webhook;
+
+/// A source of users
+pub trait Authenticator: Send + Sync {
+    /// The password of the user, or `None` if there is no such user
+    fn password(&self, uuid: Uuid) -> BoxFuture<'_, Result<Option<Box<[u8]>>, Error>>;
+}
+
+pub struct Auth {
+    users: HashMap<Uuid, Box<[u8]>>,
+    tokens: Option<Tokens>,
+    backend: Option<Box<dyn Authenticator>>,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<Uuid, Cached>>,
+}
+
+/// The users of the connections, by the SNI of the clients
+///
+/// Server names with users of their own in `sni` authenticate only those users, without the tokens and the backend.
+pub struct Tenants {
+    default: Arc<Auth>,
+    by_name: HashMap<String, Arc<Auth>>,
+}
+
+impl Tenants {
+    pub fn new(
+        default: Auth,
+        by_name: HashMap<String, HashMap<Uuid, Box<[u8]>>>,
+    ) -> Result<Self, Error> {
+        if default.users.is_empty()
+            && default.tokens.is_none()
+            && default.backend.is_none()
+            && by_name.is_empty()
+        {
+            return Err(Error::InvalidAuthConfig(
+                "`users` cannot be empty without `tokens`, an `auth` backend or users in `sni`",
+            ));
+        }
+
+        Ok(Self {
+            default: Arc::new(default),
+            by_name: by_name
+                .into_iter()
+                .map(|(name, users)| {
+                    let auth = Auth {
+                        users,
+                        tokens: None,
+                        backend: None,
+                        cache_ttl: Duration::ZERO,
+                        cache: Mutex::new(HashMap::new()),
+                    };
+                    (name.to_ascii_lowercase(), Arc::new(auth))
+                })
+                .collect(),
+        })
+    }
+
+    /// The users of a connection with the server name
+    pub fn get(&self, server_name: Option<&str>) -> Arc<Auth> {
+        server_name
+            .and_then(|name| sni::lookup(&self.by_name, name))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+/// A password found by the backend, with when it was looked up
+struct Cached {
+    password: Box<[u8]>,
+    at: Instant,
+}
+
+impl Auth {
+    pub fn new(
+        users: HashMap<Uuid, Box<[u8]>>,
+        tokens: Option<TokensConfig>,
+        cfg: Option<AuthConfig>,
+    ) -> Result<Self, Error> {
+        let tokens = tokens.map(Tokens::new);
+
+        let Some(cfg) = cfg else {
+            return Ok(Self {
+                users,
+                tokens,
+                backend: None,
+                cache_ttl: Duration::ZERO,
+                cache: Mutex::new(HashMap::new()),
+            });
+        };
+
+        let backend: Box<dyn Authenticator> = match cfg.backend {
+            AuthBackend::Webhook {
+                url,
+                headers,
+                timeout,
+            } => Box::new(webhook::Webhook::new(url, headers, timeout)?),
+            #[cfg(feature = "sqlite")]
+            AuthBackend::Sqlite { path, query } => Box::new(sqlite::Sqlite::new(path, query)?),
+            #[cfg(not(feature = "sqlite"))]
+            AuthBackend::Sqlite { .. } => {
+                return Err(Error::InvalidAuthConfig(
+                    "the `sqlite` backend requires building with the `sqlite` feature",
+                ))
+            }
+            #[cfg(feature = "mysql")]
+            AuthBackend::Mysql { url, query } => Box::new(mysql::Mysql::new(&url, query)?),
+            #[cfg(not(feature = "mysql"))]
+            AuthBackend::Mysql { .. } => {
+                return Err(Error::InvalidAuthConfig(
+                    "the `mysql` backend requires building with the `mysql` feature",
+                ))
+            }
+        };
+
+        Ok(Self {
+            users,
+            tokens,
+            backend: Some(backend),
+            cache_ttl: cfg.cache_ttl,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The password of the user, from the config, the tokens, the cache or the backend
+    pub async fn password(&self, uuid: Uuid) -> Result<Option<Box<[u8]>>, Error> {
+        if let Some(password) = self.users.get(&uuid) {
+            return Ok(Some(password.clone()));
+        }
+
+        if let Some(password) = self
+            .tokens
+            .as_ref()
+            .and_then(|tokens| tokens.password(uuid))
+        {
+            return Ok(Some(password));
+        }
+
+        let Some(backend) = &self.backend else {
+            return Ok(None);
+        };
+
+        {
+            let mut cache = self.cache.lock();
+
+            match cache.get(&uuid) {
+                Some(cached) if cached.at.elapsed() < self.cache_ttl => {
+                    return Ok(Some(cached.password.clone()))
+                }
+                Some(_) => {
+                    cache.remove(&uuid);
+                }
+                None => {}
+            }
+        }
+
+        let password = backend.password(uuid).await?;
+
+        if let Some(password) = &password {
+            if !self.cache_ttl.is_zero() {
+                let mut cache = self.cache.lock();
+                cache.retain(|_, cached| cached.at.elapsed() < self.cache_ttl);
+
+                let cached = Cached {
+                    password: password.clone(),
+                    at: Instant::now(),
+                };
+                cache.insert(uuid, cached);
+            }
+        }
+
+        Ok(password)
+    }
+
+    /// Called once a connection is authenticated as the user, returning `false` if the user is a single-use token already used
+    pub fn consume(&self, uuid: Uuid) -> bool {
+        match &self.tokens {
+            Some(tokens) if !self.users.contains_key(&uuid) => tokens.consume(uuid),
+            _ => true,
+        }
+    }
+}
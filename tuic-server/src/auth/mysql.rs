@@ -0,0 +1,44 @@
+//! Looking up users in a MySQL table
+//!
+//! The query is run with the UUID of the user, in the hyphenated lowercase form, as its only parameter, and should select the password as text, e.g. `SELECT password FROM users WHERE uuid = ?`. No row means an unknown user.
+
+use super::Authenticator;
+use crate::error::Error;
+use futures_util::future::BoxFuture;
+use mysql_async::{prelude::Queryable, Opts, Pool};
+use uuid::Uuid;
+
+pub struct Mysql {
+    pool: Pool,
+    query: String,
+}
+
+impl Mysql {
+    /// Creates the connection pool, connecting lazily on the first lookup
+    pub fn new(url: &str, query: String) -> Result<Self, Error> {
+        let opts =
+            Opts::from_url(url).map_err(|_| Error::InvalidAuthConfig("invalid MySQL URL"))?;
+
+        Ok(Self {
+            pool: Pool::new(opts),
+            query,
+        })
+    }
+
+    async fn query(&self, uuid: Uuid) -> Result<Option<String>, mysql_async::Error> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.exec_first(self.query.as_str(), (uuid.to_string(),))
+            .await
+    }
+}
+
+impl Authenticator for Mysql {
+    fn password(&self, uuid: Uuid) -> BoxFuture<'_, Result<Option<Box<[u8]>>, Error>> {
+        Box::pin(async move {
+            self.query(uuid)
+                .await
+                .map(|password| password.map(|password| password.into_bytes().into_boxed_slice()))
+                .map_err(|err| Error::AuthBackend(err.to_string()))
+        })
+    }
+}
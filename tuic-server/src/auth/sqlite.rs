@@ -0,0 +1,58 @@
+//! Looking up users in a SQLite table
+//!
+//! The query is run with the UUID of the user, in the hyphenated lowercase form, as its only parameter, and should select the password as text, e.g. `SELECT password FROM users WHERE uuid = ?1`. No row means an unknown user.
+
+use super::Authenticator;
+use crate::error::Error;
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::{path::PathBuf, sync::Arc};
+use tokio::task;
+use uuid::Uuid;
+
+pub struct Sqlite {
+    conn: Arc<Mutex<Connection>>,
+    query: Arc<str>,
+}
+
+impl Sqlite {
+    pub fn new(path: PathBuf, query: String) -> Result<Self, Error> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|err| Error::AuthBackend(err.to_string()))?;
+
+        // checks the query against the schema up front
+        conn.prepare_cached(&query)
+            .map_err(|err| Error::AuthBackend(err.to_string()))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            query: Arc::from(query),
+        })
+    }
+}
+
+impl Authenticator for Sqlite {
+    fn password(&self, uuid: Uuid) -> BoxFuture<'_, Result<Option<Box<[u8]>>, Error>> {
+        let conn = self.conn.clone();
+        let query = self.query.clone();
+
+        Box::pin(async move {
+            // queries block on the file, so they are run off the runtime threads
+            task::spawn_blocking(move || {
+                let conn = conn.lock();
+                let mut stmt = conn.prepare_cached(&query)?;
+
+                stmt.query_row([uuid.to_string()], |row| row.get::<_, String>(0))
+                    .optional()
+            })
+            .await
+            .map_err(|err| Error::AuthBackend(err.to_string()))?
+            .map(|password| password.map(|password| password.into_bytes().into_boxed_slice()))
+            .map_err(|err| Error::AuthBackend(err.to_string()))
+        })
+    }
+}
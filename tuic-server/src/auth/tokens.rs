@@ -0,0 +1,121 @@
+//! Access tokens issued by the server, expiring without a config change
+//!
+//! A token is a UUID and a password, used by the client as any other user. The UUID carries the expiry and the flags of the token, in the layout of a version 8 UUID:
+//!
+//! - bytes 0 - 4: the expiry, in seconds since the Unix epoch, big-endian
+//! - byte 5: the flags, with bit 0 set for a single-use token
+//! - bytes 6 - 15: random, except for the version and variant bits
+//!
+//! The password is the hex-encoded first 16 bytes of the HMAC-SHA256 of the UUID, keyed by `tokens.secret`, so any server with the secret validates the token without storing it. A single-use token is consumed by its first authenticated connection, which is remembered in memory until the token expires.
+
+use crate::config::Tokens as TokensConfig;
+use parking_lot::Mutex;
+use ring::hmac::{self, Key, HMAC_SHA256};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+const FLAG_SINGLE_USE: u8 = 0b1;
+
+/// The expiry is 40 bits
+const MAX_EXPIRY: u64 = (1 << 40) - 1;
+
+/// The number of bytes of the HMAC in the password
+const PASSWORD_LEN: usize = 16;
+
+pub struct Tokens {
+    key: Key,
+    /// The consumed single-use tokens, with their expiry
+    used: Mutex<HashMap<Uuid, SystemTime>>,
+}
+
+/// A token issued with [`Tokens::issue()`]
+pub struct Token {
+    pub uuid: Uuid,
+    pub password: String,
+    pub expires: SystemTime,
+}
+
+impl Tokens {
+    pub fn new(cfg: TokensConfig) -> Self {
+        Self {
+            key: Key::new(HMAC_SHA256, cfg.secret.as_bytes()),
+            used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issues a token valid for the duration, rounded up to a second
+    pub fn issue(&self, valid_for: Duration, single_use: bool) -> Token {
+        let expires = (SystemTime::now() + valid_for)
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+        let secs = (expires.as_secs() + u64::from(expires.subsec_nanos() > 0)).min(MAX_EXPIRY);
+
+        let mut bytes = Uuid::new_v4().into_bytes();
+        bytes[..5].copy_from_slice(&secs.to_be_bytes()[3..]);
+        bytes[5] = if single_use { FLAG_SINGLE_USE } else { 0 };
+        bytes[6] = 0x80 | (bytes[6] & 0x0f);
+        bytes[8] = 0x80 | (bytes[8] & 0x3f);
+
+        let uuid = Uuid::from_bytes(bytes);
+
+        Token {
+            uuid,
+            password: self.password_of(uuid),
+            expires: UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    /// The password of the token, or `None` if the UUID is not a token, or the token has expired or been used
+    pub fn password(&self, uuid: Uuid) -> Option<Box<[u8]>> {
+        let (expires, single_use) = parse(uuid)?;
+
+        if expires <= SystemTime::now() || (single_use && self.used.lock().contains_key(&uuid)) {
+            return None;
+        }
+
+        Some(self.password_of(uuid).into_bytes().into_boxed_slice())
+    }
+
+    /// Consumes a single-use token once a connection is authenticated with it, returning `false` if it was already used
+    pub fn consume(&self, uuid: Uuid) -> bool {
+        let Some((expires, true)) = parse(uuid) else {
+            return true;
+        };
+
+        let mut used = self.used.lock();
+        let now = SystemTime::now();
+        used.retain(|_, expires| *expires > now);
+        used.insert(uuid, expires).is_none()
+    }
+
+    fn password_of(&self, uuid: Uuid) -> String {
+        let tag = hmac::sign(&self.key, uuid.as_bytes());
+
+        tag.as_ref()[..PASSWORD_LEN].iter().fold(
+            String::with_capacity(PASSWORD_LEN * 2),
+            |mut s, b| {
+                let _ = write!(s, "{b:02x}");
+                s
+            },
+        )
+    }
+}
+
+/// The expiry and whether the token is single-use, if the UUID is laid out as a token
+fn parse(uuid: Uuid) -> Option<(SystemTime, bool)> {
+    let bytes = uuid.as_bytes();
+
+    if bytes[6] >> 4 != 8 || bytes[8] >> 6 != 0b10 || bytes[5] & !FLAG_SINGLE_USE != 0 {
+        return None;
+    }
+
+    let mut secs = [0; 8];
+    secs[3..].copy_from_slice(&bytes[..5]);
+    let expires = UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(secs));
+
+    Some((expires, bytes[5] & FLAG_SINGLE_USE != 0))
+}
@@ -0,0 +1,131 @@
+//! Looking up users with an HTTP(S) request to an external service
+//!
+//! The service is sent a `POST` request with a JSON body `{ "uuid": "..." }`, and answers with `200` and a JSON body `{ "password": "..." }` for an existing user, or `404` for an unknown one. Any other status is an error, failing the authentication.
+
+use super::Authenticator;
+use crate::error::Error;
+use futures_util::future::BoxFuture;
+use hyper::{
+    body::{Body, HttpBody},
+    client::HttpConnector,
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Client, Method, Request, StatusCode, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::HashMap, time::Duration};
+use tokio::time;
+use uuid::Uuid;
+
+/// Longer response bodies are rejected
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+pub struct Webhook {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    password: String,
+}
+
+impl Webhook {
+    pub fn new(
+        url: String,
+        headers: HashMap<String, String>,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let url = url
+            .parse::<Uri>()
+            .map_err(|_| Error::InvalidAuthConfig("invalid webhook URL"))?;
+
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| Error::InvalidAuthConfig("invalid webhook header name"))?;
+                let value = HeaderValue::from_str(&value)
+                    .map_err(|_| Error::InvalidAuthConfig("invalid webhook header value"))?;
+                Ok((name, value))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Ok(Self {
+            client: Client::builder().build(connector),
+            url,
+            headers,
+            timeout,
+        })
+    }
+
+    async fn request(&self, uuid: Uuid) -> Result<Option<Box<[u8]>>, Error> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(CONTENT_TYPE, "application/json");
+
+        for (name, value) in &self.headers {
+            req = req.header(name, value);
+        }
+
+        let req = req
+            .body(Body::from(json!({ "uuid": uuid }).to_string()))
+            .map_err(|err| Error::AuthBackend(err.to_string()))?;
+
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|err| Error::AuthBackend(err.to_string()))?;
+
+        match res.status() {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Ok(None),
+            status => {
+                return Err(Error::AuthBackend(format!(
+                    "webhook responded with {status}"
+                )))
+            }
+        }
+
+        let mut body = res.into_body();
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| Error::AuthBackend(err.to_string()))?;
+
+            if buf.len() + chunk.len() > MAX_RESPONSE_SIZE {
+                return Err(Error::AuthBackend(String::from(
+                    "webhook response is too large",
+                )));
+            }
+
+            buf.extend_from_slice(&chunk);
+        }
+
+        let res = serde_json::from_slice::<Response>(&buf)
+            .map_err(|err| Error::AuthBackend(format!("invalid webhook response: {err}")))?;
+
+        Ok(Some(res.password.into_bytes().into_boxed_slice()))
+    }
+}
+
+impl Authenticator for Webhook {
+    fn password(&self, uuid: Uuid) -> BoxFuture<'_, Result<Option<Box<[u8]>>, Error>> {
+        Box::pin(async move {
+            time::timeout(self.timeout, self.request(uuid))
+                .await
+                .map_err(|_| Error::AuthBackend(String::from("webhook timed out")))?
+        })
+    }
+}
@@ -0,0 +1,49 @@
+//! Serving `tuic-client bench` on the server itself, with `allow_bench`
+//!
+//! TCP relays and UDP packets to `DOMAIN`, on any port, are served by the server instead of being relayed, so throughput and latency can be measured without a target of their own. Names under `.invalid` never resolve (RFC 6761), so no real target is shadowed.
+//!
+//! A TCP relay starts with a byte of the mode:
+//!
+//! - `MODE_UPLOAD` - reads until EOF, then replies the number of bytes read as a big-endian `u64`
+//! - `MODE_DOWNLOAD` - reads a big-endian `u64`, then sends that many bytes and closes
+//! - `MODE_ECHO` - echoes everything back
+//!
+//! UDP packets are echoed back.
+
+use std::io::{Error as IoError, ErrorKind};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tuic::Address;
+
+pub const DOMAIN: &str = "bench.tuic.invalid";
+
+const MODE_UPLOAD: u8 = b'u';
+const MODE_DOWNLOAD: u8 = b'd';
+const MODE_ECHO: u8 = b'e';
+
+pub fn is_target(addr: &Address) -> bool {
+    matches!(addr, Address::DomainAddress(domain, _) if domain.eq_ignore_ascii_case(DOMAIN))
+}
+
+pub async fn serve<S>(stream: S) -> Result<(), IoError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut recv, mut send) = io::split(stream);
+
+    match recv.read_u8().await? {
+        MODE_UPLOAD => {
+            let size = io::copy(&mut recv, &mut io::sink()).await?;
+            send.write_u64(size).await?;
+        }
+        MODE_DOWNLOAD => {
+            let size = recv.read_u64().await?;
+            io::copy(&mut io::repeat(0).take(size), &mut send).await?;
+        }
+        MODE_ECHO => {
+            io::copy(&mut recv, &mut send).await?;
+        }
+        _ => return Err(IoError::new(ErrorKind::InvalidData, "unknown bench mode")),
+    }
+
+    send.shutdown().await
+}
@@ -0,0 +1,121 @@
+//! A WebSocket bridge for clients on networks where UDP is blocked
+//!
+//! Each WebSocket connection carries the QUIC datagrams of a client, one binary message per datagram. The bridge relays them over a UDP socket of its own to the local QUIC endpoint, so the server sees the client as a local address.
+
+use crate::{config::Bridge as BridgeConfig, error::Error};
+use futures_util::{SinkExt, StreamExt};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener as StdTcpListener};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+
+pub struct Bridge {
+    listener: TcpListener,
+    path: String,
+    server: SocketAddr,
+}
+
+impl Bridge {
+    /// Binds the bridge listener, relaying to the QUIC endpoint listening on `server`
+    pub fn new(cfg: BridgeConfig, server: SocketAddr) -> Result<Self, Error> {
+        let listener = StdTcpListener::bind(cfg.listen)
+            .map_err(|err| Error::Socket("failed to bind bridge listener", err))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| Error::Socket("failed to bind bridge listener", err))?;
+
+        // the endpoint listening on an unspecified address is reached through the loopback address
+        let server = match server.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => {
+                SocketAddr::from((Ipv4Addr::LOCALHOST, server.port()))
+            }
+            IpAddr::V6(ip) if ip.is_unspecified() => {
+                SocketAddr::from((Ipv6Addr::LOCALHOST, server.port()))
+            }
+            _ => server,
+        };
+
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+            path: cfg.path,
+            server,
+        })
+    }
+
+    pub async fn start(self) {
+        log::warn!(
+            "[bridge] started, listening on {}",
+            self.listener.local_addr().unwrap()
+        );
+
+        loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    log::warn!("[bridge] failed accepting connection: {err}");
+                    continue;
+                }
+            };
+
+            let path = self.path.clone();
+            let server = self.server;
+
+            tokio::spawn(async move {
+                match Self::handle(stream, &path, server).await {
+                    Ok(()) => log::debug!("[bridge] [{addr}] connection closed"),
+                    Err(err) => log::warn!("[bridge] [{addr}] {err}"),
+                }
+            });
+        }
+    }
+
+    async fn handle(stream: TcpStream, path: &str, server: SocketAddr) -> Result<(), Error> {
+        stream.set_nodelay(true)?;
+
+        // the error response type is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let check_path = |req: &Request, res: Response| -> Result<Response, ErrorResponse> {
+            if req.uri().path() == path {
+                Ok(res)
+            } else {
+                let mut res = ErrorResponse::new(None);
+                *res.status_mut() = StatusCode::NOT_FOUND;
+                Err(res)
+            }
+        };
+
+        let ws = tokio_tungstenite::accept_hdr_async(stream, check_path).await?;
+        let (mut sink, mut stream) = ws.split();
+
+        let bind_addr = match server {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(server).await?;
+
+        let mut buf = vec![0; u16::MAX as usize];
+
+        loop {
+            tokio::select! {
+                msg = stream.next() => match msg {
+                    // as on any UDP path, a datagram failing to reach the endpoint is lost
+                    Some(Ok(Message::Binary(datagram))) => {
+                        let _ = socket.send(&datagram).await;
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return Err(Error::from(err)),
+                },
+                res = socket.recv(&mut buf) => {
+                    let len = res?;
+                    sink.send(Message::Binary(buf[..len].to_vec())).await?;
+                }
+            }
+        }
+    }
+}
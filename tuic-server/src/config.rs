@@ -1,101 +1,538 @@
-use crate::utils::CongestionControl;
-use humantime::Duration as HumanDuration;
-use lexopt::{Arg, Error as ArgumentError, Parser};
+use crate::utils::{BadCommand, CongestionControl, Destination, Ipv6SourcePolicy, RetryMode};
+use lexopt::{Arg, Error as ArgumentError, Parser, ValueExt};
 use log::LevelFilter;
+use quinn::VarInt;
 use serde::{de::Error as DeError, Deserialize, Deserializer};
-use serde_json::Error as SerdeError;
 use std::{
-    collections::HashMap, env::ArgsOs, fmt::Display, fs::File, io::Error as IoError,
-    net::SocketAddr, path::PathBuf, str::FromStr, time::Duration,
+    collections::HashMap,
+    env::ArgsOs,
+    fmt::Display,
+    fs,
+    io::Error as IoError,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
 };
 use thiserror::Error;
+use tuic_config::Override;
 use uuid::Uuid;
 
 const HELP_MSG: &str = r#"
-Usage tuic-server [arguments]
+Usage tuic-server [check-config | issue-token] [arguments]
+
+Commands:
+    check-config            Check the config and the certificate and exit, with status 0 if they are valid, 1 if invalid or 2 on wrong arguments
+    issue-token             Print a new access token signed with `tokens.secret` in the config
+        --valid-for <time>      How long the token is valid for, e.g. `7d`, defaults to `1d`
+        --single-use            Allow only one connection authenticated with the token
 
 Arguments:
-    -c, --config <path>     Path to the config file (required)
+    -c, --config <path>     Path to the config file
+    --set <path=value>      Override a field of the config, e.g. `--set log_level=info`, can be specified multiple times
     -v, --version           Print the version
     -h, --help              Print this help message
 "#;
 
 #[derive(Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct Config {
     pub server: SocketAddr,
 
-    #[serde(deserialize_with = "deserialize_users")]
+    #[serde(default, deserialize_with = "deserialize_users")]
     pub users: HashMap<Uuid, Box<[u8]>>,
 
+    #[serde(default)]
+    pub tokens: Option<Tokens>,
+
+    #[serde(default)]
+    pub auth: Option<Auth>,
+
     pub certificate: PathBuf,
 
     pub private_key: PathBuf,
 
+    #[serde(default)]
+    pub sni: HashMap<String, Sni>,
+
     #[serde(
         default = "default::congestion_control",
         deserialize_with = "deserialize_from_str"
     )]
     pub congestion_control: CongestionControl,
 
+    #[serde(
+        default,
+        deserialize_with = "tuic_config::deserialize_optional_bandwidth"
+    )]
+    pub bandwidth: Option<u64>,
+
     #[serde(default = "default::alpn", deserialize_with = "deserialize_alpn")]
     pub alpn: Vec<Vec<u8>>,
 
     #[serde(default = "default::udp_relay_ipv6")]
     pub udp_relay_ipv6: bool,
 
+    #[serde(default = "default::udp_relay_mode_switching")]
+    pub udp_relay_mode_switching: bool,
+
+    #[serde(default = "default::udp_compression")]
+    pub udp_compression: bool,
+
+    #[serde(default = "default::allow_bind")]
+    pub allow_bind: bool,
+
+    #[serde(default = "default::allow_bench")]
+    pub allow_bench: bool,
+
+    #[serde(default)]
+    pub ipv6_source: Option<Ipv6Source>,
+
+    #[serde(default)]
+    pub udp_socket_pool: Option<UdpSocketPool>,
+
+    #[serde(default)]
+    pub connect_limits: Option<ConnectLimits>,
+
     #[serde(default = "default::zero_rtt_handshake")]
     pub zero_rtt_handshake: bool,
 
+    #[serde(default)]
+    pub address_validation: AddressValidation,
+
+    #[serde(default)]
+    pub outbound_tcp: OutboundTcp,
+
     pub dual_stack: Option<bool>,
 
+    #[serde(default)]
+    pub obfs_password: Option<String>,
+
     #[serde(
         default = "default::auth_timeout",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub auth_timeout: Duration,
 
     #[serde(
         default = "default::task_negotiation_timeout",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub task_negotiation_timeout: Duration,
 
     #[serde(
         default = "default::max_idle_time",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub max_idle_time: Duration,
 
-    #[serde(default = "default::max_external_packet_size")]
+    #[serde(
+        default = "default::max_external_packet_size",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
     pub max_external_packet_size: usize,
 
-    #[serde(default = "default::send_window")]
+    #[serde(
+        default = "default::max_packet_size",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
+    pub max_packet_size: u16,
+
+    #[serde(
+        default = "default::send_window",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
     pub send_window: u64,
 
-    #[serde(default = "default::receive_window")]
+    #[serde(
+        default = "default::receive_window",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
     pub receive_window: u32,
 
+    #[serde(default, deserialize_with = "deserialize_connection_receive_window")]
+    pub connection_receive_window: Option<u64>,
+
+    #[serde(
+        default = "default::initial_rtt",
+        deserialize_with = "deserialize_initial_rtt"
+    )]
+    pub initial_rtt: Duration,
+
+    #[serde(
+        default = "default::max_udp_payload_size",
+        deserialize_with = "deserialize_max_udp_payload_size"
+    )]
+    pub max_udp_payload_size: u16,
+
+    #[serde(
+        default = "default::max_concurrent_streams",
+        deserialize_with = "deserialize_max_concurrent_streams"
+    )]
+    pub max_concurrent_streams: u32,
+
+    #[serde(
+        default = "default::datagram_receive_buffer_size",
+        deserialize_with = "deserialize_datagram_receive_buffer_size"
+    )]
+    pub datagram_receive_buffer_size: usize,
+
     #[serde(
         default = "default::gc_interval",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub gc_interval: Duration,
 
     #[serde(
         default = "default::gc_lifetime",
-        deserialize_with = "deserialize_duration"
+        deserialize_with = "tuic_config::deserialize_duration"
     )]
     pub gc_lifetime: Duration,
 
+    #[serde(default)]
+    pub qlog_dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub masque: Option<Masque>,
+
+    #[serde(default)]
+    pub bridge: Option<Bridge>,
+
+    #[serde(default)]
+    pub healthz: Option<SocketAddr>,
+
+    #[serde(default)]
+    pub audit_log: Option<AuditLog>,
+
+    #[serde(default)]
+    pub pre_auth: PreAuth,
+
+    #[serde(default)]
+    pub udp_resumption: Option<UdpResumption>,
+
+    #[serde(
+        default = "default::bad_command",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub bad_command: BadCommand,
+
+    #[serde(default)]
+    pub malformed_traffic: Option<MalformedTraffic>,
+
     #[serde(default = "default::log_level")]
     pub log_level: LevelFilter,
+
+    /// The unknown fields in the config file, to be logged once the logger is set up
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+
+    /// What to do with the config, given as a subcommand
+    #[serde(skip)]
+    pub command: Command,
+}
+
+#[derive(Default, Deserialize)]
+pub struct PreAuth {
+    #[serde(default, deserialize_with = "tuic_config::deserialize_optional_size")]
+    pub max_bytes: Option<usize>,
+
+    #[serde(default)]
+    pub reject_tasks: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AddressValidation {
+    #[serde(
+        default = "default::address_validation::retry",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub retry: RetryMode,
+
+    #[serde(default = "default::address_validation::auto_threshold")]
+    pub auto_threshold: u32,
+
+    #[serde(
+        default = "default::address_validation::token_lifetime",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub token_lifetime: Duration,
+
+    #[serde(default)]
+    pub token_secret: Option<String>,
+
+    #[serde(default = "default::address_validation::max_connections")]
+    pub max_connections: u32,
+}
+
+impl Default for AddressValidation {
+    fn default() -> Self {
+        Self {
+            retry: default::address_validation::retry(),
+            auto_threshold: default::address_validation::auto_threshold(),
+            token_lifetime: default::address_validation::token_lifetime(),
+            token_secret: None,
+            max_connections: default::address_validation::max_connections(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct OutboundTcp {
+    #[serde(default = "default::outbound_tcp::nodelay")]
+    pub nodelay: bool,
+
+    #[serde(
+        default,
+        deserialize_with = "tuic_config::deserialize_optional_duration"
+    )]
+    pub keepalive: Option<Duration>,
+
+    #[serde(
+        default = "default::outbound_tcp::connect_timeout",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub connect_timeout: Duration,
+
+    #[serde(
+        default = "default::outbound_tcp::happy_eyeballs_delay",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub happy_eyeballs_delay: Duration,
+}
+
+impl Default for OutboundTcp {
+    fn default() -> Self {
+        Self {
+            nodelay: default::outbound_tcp::nodelay(),
+            keepalive: None,
+            connect_timeout: default::outbound_tcp::connect_timeout(),
+            happy_eyeballs_delay: default::outbound_tcp::happy_eyeballs_delay(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MalformedTraffic {
+    #[serde(
+        default = "default::malformed_traffic::window",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub window: Duration,
+
+    #[serde(default = "default::malformed_traffic::delay_after")]
+    pub delay_after: u32,
+
+    #[serde(
+        default = "default::malformed_traffic::delay",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub delay: Duration,
+
+    #[serde(
+        default = "default::malformed_traffic::max_delay",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub max_delay: Duration,
+
+    #[serde(default = "default::malformed_traffic::ban_after")]
+    pub ban_after: u32,
+
+    #[serde(
+        default = "default::malformed_traffic::ban_duration",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub ban_duration: Duration,
+}
+
+#[derive(Default)]
+pub enum Command {
+    #[default]
+    Run,
+    CheckConfig,
+    IssueToken {
+        valid_for: Duration,
+        single_use: bool,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct Sni {
+    #[serde(default)]
+    pub certificate: Option<PathBuf>,
+
+    #[serde(default)]
+    pub private_key: Option<PathBuf>,
+
+    #[serde(default, deserialize_with = "deserialize_optional_users")]
+    pub users: Option<HashMap<Uuid, Box<[u8]>>>,
+}
+
+#[derive(Deserialize)]
+pub struct Ipv6Source {
+    #[serde(default)]
+    pub addresses: Vec<Ipv6Addr>,
+
+    #[serde(
+        default = "default::ipv6_source::policy",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub policy: Ipv6SourcePolicy,
+
+    #[serde(default)]
+    pub users: HashMap<Uuid, Ipv6Addr>,
+}
+
+#[derive(Deserialize)]
+pub struct UdpSocketPool {
+    #[serde(default = "default::udp_socket_pool::size")]
+    pub size: usize,
+}
+
+#[derive(Deserialize)]
+pub struct ConnectLimits {
+    #[serde(default)]
+    pub per_host: Option<usize>,
+
+    #[serde(default)]
+    pub rules: Vec<ConnectLimitRule>,
+}
+
+#[derive(Deserialize)]
+pub struct ConnectLimitRule {
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub destination: Destination,
+
+    pub max: usize,
+}
+
+#[derive(Deserialize)]
+pub struct AuditLog {
+    pub path: PathBuf,
+
+    #[serde(default)]
+    pub hash_domains: bool,
+
+    #[serde(default)]
+    pub hash_salt: String,
+
+    #[serde(default = "default::audit_log::ipv4_prefix")]
+    pub ipv4_prefix: u8,
+
+    #[serde(default = "default::audit_log::ipv6_prefix")]
+    pub ipv6_prefix: u8,
+
+    #[serde(
+        default = "default::audit_log::max_size",
+        deserialize_with = "tuic_config::deserialize_size"
+    )]
+    pub max_size: u64,
+
+    #[serde(default = "default::audit_log::max_files")]
+    pub max_files: usize,
+}
+
+#[derive(Deserialize)]
+pub struct Tokens {
+    pub secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct Auth {
+    pub backend: AuthBackend,
+
+    #[serde(
+        default = "default::auth::cache_ttl",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub cache_ttl: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthBackend {
+    Webhook {
+        url: String,
+
+        #[serde(default)]
+        headers: HashMap<String, String>,
+
+        #[serde(
+            default = "default::auth::webhook_timeout",
+            deserialize_with = "tuic_config::deserialize_duration"
+        )]
+        timeout: Duration,
+    },
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    Sqlite {
+        path: PathBuf,
+
+        #[serde(default = "default::auth::sqlite_query")]
+        query: String,
+    },
+    #[cfg_attr(not(feature = "mysql"), allow(dead_code))]
+    Mysql {
+        url: String,
+
+        #[serde(default = "default::auth::mysql_query")]
+        query: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct UdpResumption {
+    #[serde(
+        default = "default::udp_resumption::lifetime",
+        deserialize_with = "tuic_config::deserialize_duration"
+    )]
+    pub lifetime: Duration,
+
+    #[serde(default)]
+    pub standby: Option<Standby>,
+}
+
+#[derive(Deserialize)]
+pub struct Standby {
+    pub listen: SocketAddr,
+
+    pub peer: SocketAddr,
+
+    pub secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct Masque {
+    #[serde(deserialize_with = "deserialize_server")]
+    pub server: (String, u16),
+
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+
+    #[serde(default = "default::masque::path")]
+    pub path: String,
+
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    #[serde(default)]
+    pub certificates: Vec<PathBuf>,
+
+    #[serde(default)]
+    pub disable_native_certs: bool,
+}
+
+#[derive(Deserialize)]
+pub struct Bridge {
+    pub listen: SocketAddr,
+
+    #[serde(default = "default::bridge::path")]
+    pub path: String,
 }
 
 impl Config {
     pub fn parse(args: ArgsOs) -> Result<Self, ConfigError> {
         let mut parser = Parser::from_iter(args);
         let mut path = None;
+        let mut command = Command::Run;
+        let mut overrides = Override::from_env();
 
         while let Some(arg) = parser.next()? {
             match arg {
@@ -106,6 +543,32 @@ impl Config {
                         return Err(ConfigError::Argument(arg.unexpected()));
                     }
                 }
+                Arg::Value(cmd) if matches!(command, Command::Run) && cmd == "check-config" => {
+                    command = Command::CheckConfig;
+                }
+                Arg::Value(cmd) if matches!(command, Command::Run) && cmd == "issue-token" => {
+                    command = Command::IssueToken {
+                        valid_for: default::token_valid_for(),
+                        single_use: false,
+                    };
+                }
+                Arg::Long("valid-for") if matches!(command, Command::IssueToken { .. }) => {
+                    let value = parser.value()?.string()?;
+                    let time = tuic_config::parse_duration(&value).map_err(ConfigError::Usage)?;
+
+                    if let Command::IssueToken { valid_for, .. } = &mut command {
+                        *valid_for = time;
+                    }
+                }
+                Arg::Long("single-use") if matches!(command, Command::IssueToken { .. }) => {
+                    if let Command::IssueToken { single_use, .. } = &mut command {
+                        *single_use = true;
+                    }
+                }
+                Arg::Long("set") => overrides.push(
+                    Override::from_arg(&parser.value()?.string()?)
+                        .map_err(ConfigError::Override)?,
+                ),
                 Arg::Short('v') | Arg::Long("version") => {
                     return Err(ConfigError::Version(env!("CARGO_PKG_VERSION")))
                 }
@@ -114,17 +577,24 @@ impl Config {
             }
         }
 
-        if path.is_none() {
-            return Err(ConfigError::NoConfig);
-        }
+        // the config can be given entirely by the overrides
+        let json = match path {
+            Some(path) => fs::read_to_string(path)?,
+            None if !overrides.is_empty() => String::from("{}"),
+            None => return Err(ConfigError::NoConfig),
+        };
+
+        let loaded = tuic_config::from_str_with::<Self>(&json, &overrides)?;
+        let mut cfg = loaded.config;
+        cfg.warnings = loaded.warnings;
+        cfg.command = command;
 
-        let file = File::open(path.unwrap())?;
-        Ok(serde_json::from_reader(file)?)
+        Ok(cfg)
     }
 }
 
 mod default {
-    use crate::utils::CongestionControl;
+    use crate::utils::{BadCommand, CongestionControl};
     use log::LevelFilter;
     use std::time::Duration;
 
@@ -132,6 +602,10 @@ mod default {
         CongestionControl::Cubic
     }
 
+    pub fn bad_command() -> BadCommand {
+        BadCommand::Close
+    }
+
     pub fn alpn() -> Vec<Vec<u8>> {
         Vec::new()
     }
@@ -140,6 +614,22 @@ mod default {
         true
     }
 
+    pub fn udp_relay_mode_switching() -> bool {
+        false
+    }
+
+    pub fn udp_compression() -> bool {
+        true
+    }
+
+    pub fn allow_bind() -> bool {
+        false
+    }
+
+    pub fn allow_bench() -> bool {
+        false
+    }
+
     pub fn zero_rtt_handshake() -> bool {
         false
     }
@@ -160,6 +650,10 @@ mod default {
         1500
     }
 
+    pub fn max_packet_size() -> u16 {
+        u16::MAX
+    }
+
     pub fn send_window() -> u64 {
         8 * 1024 * 1024 * 2
     }
@@ -168,6 +662,22 @@ mod default {
         8 * 1024 * 1024
     }
 
+    pub fn initial_rtt() -> Duration {
+        Duration::from_millis(333)
+    }
+
+    pub fn max_udp_payload_size() -> u16 {
+        65527
+    }
+
+    pub fn max_concurrent_streams() -> u32 {
+        32
+    }
+
+    pub fn datagram_receive_buffer_size() -> usize {
+        1250000
+    }
+
     pub fn gc_interval() -> Duration {
         Duration::from_secs(3)
     }
@@ -179,6 +689,147 @@ mod default {
     pub fn log_level() -> LevelFilter {
         LevelFilter::Warn
     }
+
+    pub mod udp_resumption {
+        use std::time::Duration;
+
+        pub fn lifetime() -> Duration {
+            Duration::from_secs(30)
+        }
+    }
+
+    pub mod ipv6_source {
+        use crate::utils::Ipv6SourcePolicy;
+
+        pub fn policy() -> Ipv6SourcePolicy {
+            Ipv6SourcePolicy::PerUser
+        }
+    }
+
+    pub mod udp_socket_pool {
+        pub fn size() -> usize {
+            64
+        }
+    }
+
+    pub mod outbound_tcp {
+        use std::time::Duration;
+
+        pub fn nodelay() -> bool {
+            false
+        }
+
+        pub fn connect_timeout() -> Duration {
+            Duration::from_secs(10)
+        }
+
+        pub fn happy_eyeballs_delay() -> Duration {
+            Duration::from_millis(250)
+        }
+    }
+
+    pub mod address_validation {
+        use crate::utils::RetryMode;
+        use std::time::Duration;
+
+        pub fn retry() -> RetryMode {
+            RetryMode::Never
+        }
+
+        pub fn auto_threshold() -> u32 {
+            100
+        }
+
+        pub fn token_lifetime() -> Duration {
+            Duration::from_secs(15)
+        }
+
+        pub fn max_connections() -> u32 {
+            100_000
+        }
+    }
+
+    pub mod malformed_traffic {
+        use std::time::Duration;
+
+        pub fn window() -> Duration {
+            Duration::from_secs(60)
+        }
+
+        pub fn delay_after() -> u32 {
+            3
+        }
+
+        pub fn delay() -> Duration {
+            Duration::from_secs(1)
+        }
+
+        pub fn max_delay() -> Duration {
+            Duration::from_secs(30)
+        }
+
+        pub fn ban_after() -> u32 {
+            10
+        }
+
+        pub fn ban_duration() -> Duration {
+            Duration::from_secs(10 * 60)
+        }
+    }
+
+    pub mod audit_log {
+        pub fn ipv4_prefix() -> u8 {
+            32
+        }
+
+        pub fn ipv6_prefix() -> u8 {
+            128
+        }
+
+        pub fn max_size() -> u64 {
+            100 * 1024 * 1024
+        }
+
+        pub fn max_files() -> usize {
+            5
+        }
+    }
+
+    pub mod masque {
+        pub fn path() -> String {
+            String::from("/.well-known/masque/udp/{target_host}/{target_port}/")
+        }
+    }
+
+    pub mod bridge {
+        pub fn path() -> String {
+            String::from("/")
+        }
+    }
+
+    pub fn token_valid_for() -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    pub mod auth {
+        use std::time::Duration;
+
+        pub fn cache_ttl() -> Duration {
+            Duration::from_secs(60)
+        }
+
+        pub fn webhook_timeout() -> Duration {
+            Duration::from_secs(3)
+        }
+
+        pub fn sqlite_query() -> String {
+            String::from("SELECT password FROM users WHERE uuid = ?1")
+        }
+
+        pub fn mysql_query() -> String {
+            String::from("SELECT password FROM users WHERE uuid = ?")
+        }
+    }
 }
 
 pub fn deserialize_from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
@@ -197,16 +848,22 @@ where
 {
     let users = HashMap::<Uuid, String>::deserialize(deserializer)?;
 
-    if users.is_empty() {
-        return Err(DeError::custom("users cannot be empty"));
-    }
-
     Ok(users
         .into_iter()
         .map(|(k, v)| (k, v.into_bytes().into_boxed_slice()))
         .collect())
 }
 
+#[allow(clippy::type_complexity)]
+pub fn deserialize_optional_users<'de, D>(
+    deserializer: D,
+) -> Result<Option<HashMap<Uuid, Box<[u8]>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_users(deserializer).map(Some)
+}
+
 pub fn deserialize_alpn<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
 where
     D: Deserializer<'de>,
@@ -215,15 +872,100 @@ where
     Ok(s.into_iter().map(|alpn| alpn.into_bytes()).collect())
 }
 
-pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+pub fn deserialize_server<'de, D>(deserializer: D) -> Result<(String, u16), D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
 
-    s.parse::<HumanDuration>()
-        .map(|d| *d)
-        .map_err(DeError::custom)
+    let (host, port) = s
+        .rsplit_once(':')
+        .ok_or(DeError::custom("invalid server address"))?;
+
+    let port = port.parse().map_err(DeError::custom)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok((host.to_owned(), port))
+}
+
+pub fn deserialize_connection_receive_window<'de, D>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let window: u64 = tuic_config::deserialize_size(deserializer)?;
+
+    if window == 0 || VarInt::from_u64(window).is_err() {
+        return Err(DeError::custom(format!(
+            "connection_receive_window must be between 1 and {}",
+            VarInt::MAX
+        )));
+    }
+
+    Ok(Some(window))
+}
+
+pub fn deserialize_initial_rtt<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rtt = tuic_config::deserialize_duration(deserializer)?;
+
+    if rtt.is_zero() {
+        return Err(DeError::custom("initial_rtt must be greater than 0"));
+    }
+
+    Ok(rtt)
+}
+
+pub fn deserialize_max_udp_payload_size<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // the bounds accepted by quinn, from RFC 9000
+    const MIN_UDP_PAYLOAD_SIZE: u16 = 1200;
+    const MAX_UDP_PAYLOAD_SIZE: u16 = 65527;
+
+    let size = u16::deserialize(deserializer)?;
+
+    if !(MIN_UDP_PAYLOAD_SIZE..=MAX_UDP_PAYLOAD_SIZE).contains(&size) {
+        return Err(DeError::custom(format!(
+            "max_udp_payload_size must be between {MIN_UDP_PAYLOAD_SIZE} and {MAX_UDP_PAYLOAD_SIZE}"
+        )));
+    }
+
+    Ok(size)
+}
+
+pub fn deserialize_max_concurrent_streams<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let max = u32::deserialize(deserializer)?;
+
+    if max == 0 {
+        return Err(DeError::custom(
+            "max_concurrent_streams must be greater than 0",
+        ));
+    }
+
+    Ok(max)
+}
+
+pub fn deserialize_datagram_receive_buffer_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size: usize = tuic_config::deserialize_size(deserializer)?;
+
+    if size == 0 {
+        return Err(DeError::custom(
+            "datagram_receive_buffer_size must be greater than 0",
+        ));
+    }
+
+    Ok(size)
 }
 
 #[derive(Debug, Error)]
@@ -233,11 +975,15 @@ pub enum ConfigError {
     #[error("no config file specified")]
     NoConfig,
     #[error("{0}")]
+    Override(String),
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}")]
     Version(&'static str),
     #[error("{0}")]
     Help(&'static str),
     #[error(transparent)]
     Io(#[from] IoError),
     #[error(transparent)]
-    Serde(#[from] SerdeError),
+    Invalid(#[from] tuic_config::Error),
 }
@@ -0,0 +1,103 @@
+//! Limiting the concurrent relayed TCP connections to each destination, so the server cannot be used to hammer a single target
+//!
+//! A connection is counted against the first rule whose destination covers its target, a network or a domain with its subdomains, the connections to all the targets of the rule sharing its limit. Connections to targets covered by no rule are counted against their own host, a domain or an address, up to `per_host` if set. The limits apply across all clients of the server.
+
+use crate::{config::ConnectLimits as ConnectLimitsConfig, error::Error, utils::Destination};
+use parking_lot::Mutex;
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+use tuic::Address;
+
+pub struct ConnectLimits {
+    rules: Vec<(Destination, usize)>,
+    per_host: Option<usize>,
+    active: Mutex<HashMap<Key, usize>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Rule(usize),
+    Domain(String),
+    Ip(IpAddr),
+}
+
+impl ConnectLimits {
+    pub fn new(cfg: ConnectLimitsConfig) -> Result<Self, Error> {
+        if cfg.per_host == Some(0) || cfg.rules.iter().any(|rule| rule.max == 0) {
+            return Err(Error::InvalidConnectLimits("limits cannot be zero"));
+        }
+
+        Ok(Self {
+            rules: cfg
+                .rules
+                .into_iter()
+                .map(|rule| (rule.destination, rule.max))
+                .collect(),
+            per_host: cfg.per_host,
+            active: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Counts a connection to `ip` for the target, or returns `None` if the limit of its destination is reached. The connection is counted until the permit is dropped
+    pub fn acquire(self: &Arc<Self>, target: &Address, ip: IpAddr) -> Option<ConnectPermit> {
+        let domain = match target {
+            Address::DomainAddress(domain, _) => Some(domain.as_str()),
+            _ => None,
+        };
+
+        let rule = self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, (dest, _))| dest.matches(domain, ip));
+
+        let (key, max) = match (rule, domain, self.per_host) {
+            (Some((idx, (_, max))), _, _) => (Key::Rule(idx), *max),
+            (None, Some(domain), Some(max)) => (Key::Domain(domain.to_ascii_lowercase()), max),
+            (None, None, Some(max)) => (Key::Ip(ip), max),
+            (None, _, None) => {
+                return Some(ConnectPermit {
+                    limits: self.clone(),
+                    key: None,
+                })
+            }
+        };
+
+        let mut active = self.active.lock();
+        let cnt = active.entry(key.clone()).or_default();
+
+        if *cnt >= max {
+            return None;
+        }
+
+        *cnt += 1;
+
+        Some(ConnectPermit {
+            limits: self.clone(),
+            key: Some(key),
+        })
+    }
+}
+
+/// A relayed connection counted against the limit of its destination, if any
+pub struct ConnectPermit {
+    limits: Arc<ConnectLimits>,
+    key: Option<Key>,
+}
+
+impl Drop for ConnectPermit {
+    fn drop(&mut self) {
+        let Some(key) = &self.key else {
+            return;
+        };
+
+        let mut active = self.limits.active.lock();
+
+        if let Some(cnt) = active.get_mut(key) {
+            *cnt -= 1;
+
+            if *cnt == 0 {
+                active.remove(key);
+            }
+        }
+    }
+}
@@ -35,7 +35,7 @@ impl Connection {
             .map_err(|_| Error::TaskNegotiationTimeout)??;
 
             if let Task::Authenticate(auth) = &task {
-                self.authenticate(auth)?;
+                self.authenticate(auth).await?;
             }
 
             tokio::select! {
@@ -43,10 +43,8 @@ impl Connection {
                 err = self.inner.closed() => return Err(Error::from(err)),
             };
 
-            let same_pkt_src = matches!(task, Task::Packet(_))
-                && matches!(self.udp_relay_mode.load(), Some(UdpRelayMode::Native));
-            if same_pkt_src {
-                return Err(Error::UnexpectedPacketSource);
+            if matches!(task, Task::Packet(_)) {
+                self.check_packet_source(UdpRelayMode::Quic)?;
             }
 
             Ok(task)
@@ -56,6 +54,8 @@ impl Connection {
             Ok(Task::Authenticate(auth)) => self.handle_authenticate(auth).await,
             Ok(Task::Packet(pkt)) => self.handle_packet(pkt, UdpRelayMode::Quic).await,
             Ok(Task::Dissociate(assoc_id)) => self.handle_dissociate(assoc_id).await,
+            Ok(Task::DissociateAll(assoc_ids)) => self.handle_dissociate_all(assoc_ids).await,
+            Ok(Task::Unknown(unknown)) => self.handle_unknown(unknown).await,
             Ok(_) => unreachable!(), // already filtered in `tuic_quinn`
             Err(err) => {
                 log::warn!(
@@ -64,7 +64,14 @@ impl Connection {
                     addr = self.inner.remote_address(),
                     user = self.auth,
                 );
-                self.close();
+
+                if err.is_malformed() {
+                    self.penalize().await;
+                }
+
+                if !err.is_quarantined() {
+                    self.close(err.close_code());
+                }
             }
         }
     }
@@ -105,6 +112,12 @@ impl Connection {
 
         match pre_process.await {
             Ok(Task::Connect(conn)) => self.handle_connect(conn).await,
+            Ok(Task::BindUdp(bind)) => self.handle_bind_udp(bind).await,
+            Ok(Task::ConfirmDissociate(dissoc)) => self.handle_confirm_dissociate(dissoc).await,
+            Ok(Task::Resume(resume)) => self.handle_resume(resume).await,
+            Ok(Task::Bind(bind)) => self.handle_bind(bind).await,
+            Ok(Task::Echo(echo)) => self.handle_echo(echo).await,
+            Ok(Task::Unknown(unknown)) => self.handle_unknown(unknown).await,
             Ok(_) => unreachable!(), // already filtered in `tuic_quinn`
             Err(err) => {
                 log::warn!(
@@ -113,7 +126,14 @@ impl Connection {
                     addr = self.inner.remote_address(),
                     user = self.auth,
                 );
-                self.close();
+
+                if err.is_malformed() {
+                    self.penalize().await;
+                }
+
+                if !err.is_quarantined() {
+                    self.close(err.close_code());
+                }
             }
         }
     }
@@ -134,10 +154,8 @@ impl Connection {
                 err = self.inner.closed() => return Err(Error::from(err)),
             };
 
-            let same_pkt_src = matches!(task, Task::Packet(_))
-                && matches!(self.udp_relay_mode.load(), Some(UdpRelayMode::Quic));
-            if same_pkt_src {
-                return Err(Error::UnexpectedPacketSource);
+            if matches!(task, Task::Packet(_)) {
+                self.check_packet_source(UdpRelayMode::Native)?;
             }
 
             Ok(task)
@@ -146,6 +164,7 @@ impl Connection {
         match pre_process.await {
             Ok(Task::Packet(pkt)) => self.handle_packet(pkt, UdpRelayMode::Native).await,
             Ok(Task::Heartbeat) => self.handle_heartbeat().await,
+            Ok(Task::Unknown(unknown)) => self.handle_unknown(unknown).await,
             Ok(_) => unreachable!(),
             Err(err) => {
                 log::warn!(
@@ -154,7 +173,14 @@ impl Connection {
                     addr = self.inner.remote_address(),
                     user = self.auth,
                 );
-                self.close();
+
+                if err.is_malformed() {
+                    self.penalize().await;
+                }
+
+                if !err.is_quarantined() {
+                    self.close(err.close_code());
+                }
             }
         }
     }
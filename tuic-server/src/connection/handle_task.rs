@@ -1,18 +1,28 @@
-use super::{Connection, UdpSession, ERROR_CODE};
-use crate::{error::Error, utils::UdpRelayMode};
+use super::{Connection, Resumption, UdpSession, CONNECT_LIMIT_ERROR_CODE, ERROR_CODE};
+use crate::{bench, connect_limit::ConnectPermit, error::Error, utils::UdpRelayMode};
 use bytes::Bytes;
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
 use std::{
     collections::hash_map::Entry,
     io::{Error as IoError, ErrorKind},
-    net::SocketAddr,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::Ordering,
 };
 use tokio::{
     io::{self, AsyncWriteExt},
-    net::{self, TcpStream},
+    net::{self, TcpListener, TcpSocket, TcpStream},
+    time,
 };
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tuic::Address;
-use tuic_quinn::{Authenticate, Connect, Packet};
+use tuic::{Address, CongestionHint};
+use tuic_quinn::{
+    congestion::Brutal, Authenticate, Bind, BindUdp, ConfirmDissociate, Connect, Echo, Packet,
+    Resume, Unknown,
+};
+
+const DEFAULT_COPY_BUFFER_SIZE: usize = 8 * 1024;
+const BULK_COPY_BUFFER_SIZE: usize = 64 * 1024;
 
 impl Connection {
     pub async fn handle_authenticate(&self, auth: Authenticate) {
@@ -23,48 +33,114 @@ impl Connection {
             user = self.auth,
             auth_uuid = auth.uuid(),
         );
+
+        // with the brutal congestion control, send at the download bandwidth declared by the client, capped by the server's
+        if let Some(bw) = auth.bandwidth().filter(|bw| bw.down() > 0) {
+            let rate = self.bandwidth.map_or(bw.down(), |max| bw.down().min(max));
+
+            if Brutal::set_rate(&self.inner, rate) {
+                log::debug!(
+                    "[{id:#010x}] [{addr}] [{user}] [authenticate] sending at {rate} bytes/s",
+                    id = self.id(),
+                    addr = self.inner.remote_address(),
+                    user = self.auth,
+                );
+            }
+        }
+
+        // follow fragmented packets relayed in mode native with a parity for the client to recover a lost fragment
+        if auth.fec() {
+            self.fec.store(true, Ordering::Relaxed);
+        }
+
+        if let Some(min_size) = auth.compression().filter(|_| self.udp_compression) {
+            log::debug!(
+                "[{id:#010x}] [{addr}] [{user}] [authenticate] compressing UDP packets of at least {min_size} bytes",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+            );
+        }
     }
 
-    pub async fn handle_connect(&self, conn: Connect) {
+    pub async fn handle_connect(&self, mut conn: Connect) {
         let target_addr = conn.addr().to_string();
+        let hint = conn.hint();
 
         log::info!(
-            "[{id:#010x}] [{addr}] [{user}] [connect] {target_addr}",
+            "[{id:#010x}] [{addr}] [{user}] [connect] {target_addr}{hint}",
             id = self.id(),
             addr = self.inner.remote_address(),
             user = self.auth,
+            hint = hint.map_or(String::new(), |hint| format!(" ({hint})")),
         );
 
+        // interactive tasks are sent ahead of the others on the connection, while bulk ones are sent behind them with larger buffers
+        let (priority, buf_size) = match hint {
+            Some(CongestionHint::Interactive) => (1, DEFAULT_COPY_BUFFER_SIZE),
+            Some(CongestionHint::Bulk) => (-1, BULK_COPY_BUFFER_SIZE),
+            None => (0, DEFAULT_COPY_BUFFER_SIZE),
+        };
+
+        let _ = conn.set_priority(priority);
+
+        let (id, addr, user) = (self.id(), self.inner.remote_address(), self.auth.clone());
+        let audit_log = self.audit_log.clone();
+
+        conn.on_summary(move |summary| {
+            log::debug!(
+                "[{id:#010x}] [{addr}] [{user}] [connect] {target_addr} closed, {up} bytes up, {down} bytes down in {duration:?}",
+                target_addr = summary.addr,
+                up = summary.bytes_read,
+                down = summary.bytes_written,
+                duration = summary.duration,
+            );
+
+            if let (Some(audit_log), Some(user)) = (audit_log, user.get()) {
+                audit_log.record(
+                    user,
+                    addr,
+                    &summary.addr,
+                    summary.bytes_read,
+                    summary.bytes_written,
+                    summary.duration,
+                );
+            }
+        });
+
         let process = async {
-            let mut stream = None;
-            let mut last_err = None;
-
-            match resolve_dns(conn.addr()).await {
-                Ok(addrs) => {
-                    for addr in addrs {
-                        match TcpStream::connect(addr).await {
-                            Ok(s) => {
-                                stream = Some(s);
-                                break;
-                            }
-                            Err(err) => last_err = Some(err),
-                        }
-                    }
-                }
-                Err(err) => last_err = Some(err),
+            if self.allow_bench && bench::is_target(conn.addr()) {
+                bench::serve(conn.compat()).await?;
+                return Ok(());
             }
 
-            if let Some(mut stream) = stream {
-                let mut conn = conn.compat();
-                let res = io::copy_bidirectional(&mut conn, &mut stream).await;
-                let _ = conn.get_mut().reset(ERROR_CODE);
-                let _ = stream.shutdown().await;
-                res?;
-                Ok::<_, Error>(())
-            } else {
-                let _ = conn.compat().shutdown().await;
-                Err(last_err
-                    .unwrap_or_else(|| IoError::new(ErrorKind::NotFound, "no address resolved")))?
+            match self.connect_target(conn.addr()).await {
+                Ok((mut stream, _permit)) => {
+                    if self.outbound_tcp.nodelay || hint == Some(CongestionHint::Interactive) {
+                        let _ = stream.set_nodelay(true);
+                    }
+
+                    let mut conn = conn.compat();
+                    let res = io::copy_bidirectional_with_sizes(
+                        &mut conn,
+                        &mut stream,
+                        buf_size,
+                        buf_size,
+                    )
+                    .await;
+                    let _ = conn.get_mut().reset(ERROR_CODE);
+                    let _ = stream.shutdown().await;
+                    res?;
+                    Ok::<_, Error>(())
+                }
+                Err(err @ Error::ConnectLimitReached(_)) => {
+                    let _ = conn.reset(CONNECT_LIMIT_ERROR_CODE);
+                    Err(err)
+                }
+                Err(err) => {
+                    let _ = conn.compat().shutdown().await;
+                    Err(err)
+                }
             }
         };
 
@@ -79,6 +155,89 @@ impl Connection {
         }
     }
 
+    /// Connects to the target with the outbound TCP options. When it resolves to multiple addresses, attempts are raced as per Happy Eyeballs (RFC 8305), starting a new one after `happy_eyeballs_delay` or once the previous one fails
+    ///
+    /// The connection is counted against the limit of its destination while the permit is held.
+    async fn connect_target(
+        &self,
+        target: &Address,
+    ) -> Result<(TcpStream, Option<ConnectPermit>), Error> {
+        let opts = self.outbound_tcp;
+
+        let attempt = |addr: SocketAddr| {
+            let permit = match &self.connect_limits {
+                Some(limits) => Some(
+                    limits
+                        .acquire(target, addr.ip())
+                        .ok_or(Error::ConnectLimitReached(addr))?,
+                ),
+                None => None,
+            };
+
+            let source = addr.is_ipv6().then(|| self.select_ipv6_source()).flatten();
+
+            Ok::<_, Error>(async move {
+                match time::timeout(opts.connect_timeout, connect_tcp(addr, source)).await {
+                    Ok(Ok(stream)) => Ok((stream, permit)),
+                    Ok(Err(err)) => Err(err),
+                    Err(_) => Err(IoError::new(
+                        ErrorKind::TimedOut,
+                        format!("connecting to {addr} timed out"),
+                    )),
+                }
+            })
+        };
+
+        let mut addrs = interleave(resolve_dns(target).await?).into_iter();
+        let mut attempts = FuturesUnordered::new();
+
+        match addrs.next() {
+            Some(addr) => attempts.push(attempt(addr)?),
+            None => {
+                return Err(Error::from(IoError::new(
+                    ErrorKind::NotFound,
+                    "no address resolved",
+                )))
+            }
+        }
+
+        let (stream, permit) = loop {
+            let next_attempt = time::sleep(opts.happy_eyeballs_delay);
+
+            tokio::select! {
+                Some(res) = attempts.next() => match res {
+                    Ok(res) => break res,
+                    Err(err) => match addrs.next() {
+                        Some(addr) => attempts.push(attempt(addr)?),
+                        None if attempts.is_empty() => return Err(Error::from(err)),
+                        None => {}
+                    },
+                },
+                _ = next_attempt, if addrs.len() > 0 => {
+                    attempts.push(attempt(addrs.next().unwrap())?);
+                }
+            }
+        };
+
+        if let Some(keepalive) = opts.keepalive {
+            let params = TcpKeepalive::new().with_time(keepalive);
+
+            #[cfg(any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "ios",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "windows",
+            ))]
+            let params = params.with_interval(keepalive);
+
+            let _ = SockRef::from(&stream).set_tcp_keepalive(&params);
+        }
+
+        Ok((stream, permit))
+    }
+
     pub async fn handle_packet(&self, pkt: Packet, mode: UdpRelayMode) {
         let assoc_id = pkt.assoc_id();
         let pkt_id = pkt.pkt_id();
@@ -93,8 +252,6 @@ impl Connection {
             frag_id = frag_id + 1,
         );
 
-        self.udp_relay_mode.store(Some(mode));
-
         let (pkt, addr, assoc_id) = match pkt.accept().await {
             Ok(None) => return,
             Ok(Some(res)) => res,
@@ -119,25 +276,39 @@ impl Connection {
                 src_addr = addr,
             );
 
-            let session = match self.udp_sessions.lock().entry(assoc_id) {
-                Entry::Occupied(entry) => entry.get().clone(),
+            if self.allow_bench && bench::is_target(&addr) {
+                self.clone()
+                    .relay_packet(pkt, addr.clone(), assoc_id, mode)
+                    .await;
+                return Ok(());
+            }
+
+            // packets are sent back in the mode of the latest packet from the session, which is the mode of the whole connection unless the client may switch modes with `udp_relay_mode_switching`
+            let (session, opened) = match self.udp_sessions.lock().entry(assoc_id) {
+                Entry::Occupied(entry) => {
+                    entry.get().set_mode(mode);
+                    (entry.get().clone(), false)
+                }
                 Entry::Vacant(entry) => {
                     let session = UdpSession::new(
                         self.clone(),
                         assoc_id,
+                        mode,
                         self.udp_relay_ipv6,
+                        self.masque.clone(),
+                        self.socket_pool.clone(),
                         self.max_external_pkt_size,
                     )?;
                     entry.insert(session.clone());
-                    session
+                    (session, true)
                 }
             };
 
-            let Some(socket_addr) = resolve_dns(&addr).await?.next() else {
-                return Err(Error::from(IoError::new(ErrorKind::NotFound, "no address resolved")));
-            };
+            if opened {
+                self.replicate_udp_sessions();
+            }
 
-            session.send(pkt, socket_addr).await
+            session.send(pkt, addr.clone()).await
         };
 
         if let Err(err) = process.await {
@@ -159,8 +330,355 @@ impl Connection {
             user = self.auth,
         );
 
-        if let Some(session) = self.udp_sessions.lock().remove(&assoc_id) {
+        let session = self.udp_sessions.lock().remove(&assoc_id);
+
+        if let Some(session) = session {
             session.close();
+            self.replicate_udp_sessions();
+        }
+    }
+
+    pub async fn handle_dissociate_all(&self, assoc_ids: Vec<u16>) {
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [dissociate] {cnt} UDP sessions",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+            cnt = assoc_ids.len(),
+        );
+
+        // tear down all the sessions in one pass, replicating the table once
+        let sessions = {
+            let mut udp_sessions = self.udp_sessions.lock();
+
+            assoc_ids
+                .iter()
+                .filter_map(|assoc_id| udp_sessions.remove(assoc_id))
+                .collect::<Vec<_>>()
+        };
+
+        if !sessions.is_empty() {
+            for session in &sessions {
+                session.close();
+            }
+
+            self.replicate_udp_sessions();
+        }
+    }
+
+    pub async fn handle_confirm_dissociate(&self, dissoc: ConfirmDissociate) {
+        let assoc_id = dissoc.assoc_id();
+        self.handle_dissociate(assoc_id).await;
+
+        if let Err(err) = dissoc.ack().await {
+            log::warn!(
+                "[{id:#010x}] [{addr}] [{user}] [dissociate] [{assoc_id:#06x}] failed sending acknowledgement: {err}",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+            );
+        }
+    }
+
+    pub async fn handle_bind_udp(&self, bind: BindUdp) {
+        let assoc_id = bind.assoc_id();
+        let bind_addr = bind.addr().to_string();
+
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [bind-udp] [{assoc_id:#06x}] {bind_addr}",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+        );
+
+        let process = || {
+            if self.masque.is_some() {
+                return Err(Error::BindUdpMasque);
+            }
+
+            let addr = match bind.addr() {
+                Address::None => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                Address::SocketAddress(addr) => *addr,
+                Address::DomainAddress(_, _) => return Err(Error::BindUdpDomain),
+            };
+
+            // packets are pushed in the mode of the latest packet from the session once the client sends any
+            let mode = if self.inner.max_datagram_size().is_some() {
+                UdpRelayMode::Native
+            } else {
+                UdpRelayMode::Quic
+            };
+
+            let session = UdpSession::bind(
+                self.clone(),
+                assoc_id,
+                mode,
+                self.udp_relay_ipv6,
+                addr,
+                self.max_external_pkt_size,
+            )?;
+
+            let mut local_addr = session.local_addr(addr.is_ipv6())?;
+
+            // report the address that the client connected to, if the socket is bound on all interfaces
+            if local_addr.ip().is_unspecified() {
+                if let Some(ip) = self.inner.local_ip() {
+                    if ip.is_ipv6() == local_addr.is_ipv6() {
+                        local_addr.set_ip(ip);
+                    }
+                }
+            }
+
+            if let Some(old) = self.udp_sessions.lock().insert(assoc_id, session) {
+                old.close();
+            }
+
+            self.replicate_udp_sessions();
+
+            Ok(local_addr)
+        };
+
+        let res = match process() {
+            Ok(local_addr) => bind
+                .reply(Address::SocketAddress(local_addr))
+                .await
+                .map_err(Error::from),
+            Err(err) => {
+                bind.reject(ERROR_CODE);
+                Err(err)
+            }
+        };
+
+        if let Err(err) = res {
+            log::warn!(
+                "[{id:#010x}] [{addr}] [{user}] [bind-udp] [{assoc_id:#06x}] {bind_addr}: {err}",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+            );
+        }
+    }
+
+    pub async fn handle_bind(&self, mut bind: Bind) {
+        let bind_id = bind.bind_id();
+        let bind_addr = bind.addr().to_string();
+
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [bind] [{bind_id:#06x}] {bind_addr}",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+        );
+
+        let listen = async {
+            if !self.allow_bind {
+                return Err(Error::BindDisabled);
+            }
+
+            let addr = match bind.addr() {
+                Address::None => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                Address::SocketAddress(addr) => *addr,
+                Address::DomainAddress(_, _) => return Err(Error::BindDomain),
+            };
+
+            let listener = TcpListener::bind(addr).await?;
+            let mut local_addr = listener.local_addr()?;
+
+            // report the address that the client connected to, if the listener is bound on all interfaces
+            if local_addr.ip().is_unspecified() {
+                if let Some(ip) = self.inner.local_ip() {
+                    if ip.is_ipv6() == local_addr.is_ipv6() {
+                        local_addr.set_ip(ip);
+                    }
+                }
+            }
+
+            bind.reply(Address::SocketAddress(local_addr)).await?;
+            Ok(listener)
+        };
+
+        let listener = match listen.await {
+            Ok(listener) => listener,
+            Err(err) => {
+                bind.reject(ERROR_CODE);
+                log::warn!(
+                    "[{id:#010x}] [{addr}] [{user}] [bind] [{bind_id:#06x}] {bind_addr}: {err}",
+                    id = self.id(),
+                    addr = self.inner.remote_address(),
+                    user = self.auth,
+                );
+                return;
+            }
+        };
+
+        // the listener is kept until the client closes the binding, or the connection is closed
+        loop {
+            tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, peer_addr)) => {
+                        tokio::spawn(self.clone().handle_inbound(bind_id, stream, peer_addr));
+                    }
+                    Err(err) => {
+                        bind.reject(ERROR_CODE);
+                        log::warn!(
+                            "[{id:#010x}] [{addr}] [{user}] [bind] [{bind_id:#06x}] {bind_addr}: {err}",
+                            id = self.id(),
+                            addr = self.inner.remote_address(),
+                            user = self.auth,
+                        );
+                        return;
+                    }
+                },
+                () = bind.closed() => break,
+            }
+        }
+
+        log::debug!(
+            "[{id:#010x}] [{addr}] [{user}] [bind] [{bind_id:#06x}] {bind_addr} closed",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+        );
+    }
+
+    async fn handle_inbound(self, bind_id: u16, mut stream: TcpStream, peer_addr: SocketAddr) {
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [bind] [{bind_id:#06x}] inbound from {peer_addr}",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+        );
+
+        let process = async {
+            let inbound = self
+                .model
+                .inbound(bind_id, Address::SocketAddress(peer_addr))
+                .await?;
+
+            let mut inbound = inbound.compat();
+            let res = io::copy_bidirectional(&mut inbound, &mut stream).await;
+            let _ = inbound.get_mut().reset(ERROR_CODE);
+            let _ = stream.shutdown().await;
+            res?;
+            Ok::<_, Error>(())
+        };
+
+        if let Err(err) = process.await {
+            log::warn!(
+                "[{id:#010x}] [{addr}] [{user}] [bind] [{bind_id:#06x}] inbound from {peer_addr}: {err}",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+            );
+        }
+    }
+
+    pub async fn handle_resume(&self, resume: Resume) {
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [resume]",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+        );
+
+        let Some(resumption) = &self.resumption else {
+            resume.reject(ERROR_CODE);
+
+            log::warn!(
+                "[{id:#010x}] [{addr}] [{user}] [resume] {err}",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+                err = Error::ResumptionDisabled,
+            );
+
+            return;
+        };
+
+        // the task is handled after authentication
+        let user = self.auth.get().unwrap();
+
+        let mut resumed = resume
+            .token()
+            .and_then(|token| resumption.take(token, user))
+            .unwrap_or_default();
+
+        // the sessions of a token issued by the standby peer are opened again with their association IDs
+        if let Some(replica) = resume
+            .token()
+            .filter(|_| resumed.is_empty())
+            .and_then(|token| resumption.take_replica(token, user))
+        {
+            let mode = if self.inner.max_datagram_size().is_some() {
+                UdpRelayMode::Native
+            } else {
+                UdpRelayMode::Quic
+            };
+
+            for assoc_id in replica {
+                match UdpSession::new(
+                    self.clone(),
+                    assoc_id,
+                    mode,
+                    self.udp_relay_ipv6,
+                    self.masque.clone(),
+                    self.socket_pool.clone(),
+                    self.max_external_pkt_size,
+                ) {
+                    Ok(session) => {
+                        resumed.insert(assoc_id, session);
+                    }
+                    Err(err) => log::warn!(
+                        "[{id:#010x}] [{addr}] [{user}] [resume] [{assoc_id:#06x}] failed reopening UDP session of standby peer: {err}",
+                        id = self.id(),
+                        addr = self.inner.remote_address(),
+                        user = self.auth,
+                    ),
+                }
+            }
+        }
+
+        let mut assoc_ids = Vec::with_capacity(resumed.len());
+
+        {
+            let mut sessions = self.udp_sessions.lock();
+
+            for (assoc_id, session) in resumed {
+                session.set_connection(self.clone());
+
+                if let Some(old) = sessions.insert(assoc_id, session) {
+                    old.close();
+                }
+
+                assoc_ids.push(assoc_id);
+            }
+        }
+
+        assoc_ids.sort_unstable();
+
+        let token = Resumption::issue_token();
+
+        if let Some(old) = self.resume_token.swap(Some(token)) {
+            resumption.forget(old);
+        }
+
+        self.replicate_udp_sessions();
+
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [resume] resumed {cnt} UDP sessions",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+            cnt = assoc_ids.len(),
+        );
+
+        if let Err(err) = resume.reply(token, assoc_ids).await {
+            log::warn!(
+                "[{id:#010x}] [{addr}] [{user}] [resume] failed sending the resumption token: {err}",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+            );
         }
     }
 
@@ -173,7 +691,39 @@ impl Connection {
         );
     }
 
-    pub async fn relay_packet(self, pkt: Bytes, addr: Address, assoc_id: u16) {
+    pub async fn handle_echo(&self, echo: Echo) {
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [echo] {len} bytes",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+            len = echo.payload().len(),
+        );
+
+        if let Err(err) = echo.reply().await {
+            log::warn!(
+                "[{id:#010x}] [{addr}] [{user}] [echo] failed replying: {err}",
+                id = self.id(),
+                addr = self.inner.remote_address(),
+                user = self.auth,
+            );
+        }
+    }
+
+    /// Ignores a command of a type unknown to the server, e.g. from a newer client, keeping the connection
+    pub async fn handle_unknown(&self, unknown: Unknown) {
+        log::info!(
+            "[{id:#010x}] [{addr}] [{user}] [unknown] command {type_code:#04x} not supported, ignoring",
+            id = self.id(),
+            addr = self.inner.remote_address(),
+            user = self.auth,
+            type_code = unknown.type_code(),
+        );
+
+        unknown.ignore(ERROR_CODE);
+    }
+
+    pub async fn relay_packet(self, pkt: Bytes, addr: Address, assoc_id: u16, mode: UdpRelayMode) {
         let addr_display = addr.to_string();
 
         log::info!(
@@ -181,11 +731,13 @@ impl Connection {
             id = self.id(),
             addr = self.inner.remote_address(),
             user = self.auth,
-            mode = self.udp_relay_mode.load().unwrap(),
             src_addr = addr_display,
         );
 
-        let res = match self.udp_relay_mode.load().unwrap() {
+        let res = match mode {
+            UdpRelayMode::Native if self.fec.load(Ordering::Relaxed) => self
+                .model
+                .packet_native_with_parity(pkt, addr, assoc_id, usize::MAX),
             UdpRelayMode::Native => self.model.packet_native(pkt, addr, assoc_id),
             UdpRelayMode::Quic => self.model.packet_quic(pkt, addr, assoc_id).await,
         };
@@ -196,14 +748,47 @@ impl Connection {
                 id = self.id(),
                 addr = self.inner.remote_address(),
                 user = self.auth,
-                mode = self.udp_relay_mode.load().unwrap(),
                 src_addr = addr_display,
             );
         }
     }
 }
 
-async fn resolve_dns(addr: &Address) -> Result<impl Iterator<Item = SocketAddr>, IoError> {
+/// Orders the addresses alternating between the address families, starting with the family of the first one
+fn interleave(addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let mut addrs = addrs.peekable();
+
+    let Some(first_ipv6) = addrs.peek().map(SocketAddr::is_ipv6) else {
+        return Vec::new();
+    };
+
+    let (first, second): (Vec<_>, Vec<_>) = addrs.partition(|addr| addr.is_ipv6() == first_ipv6);
+    let mut res = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return res,
+            (a, b) => res.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connects to the target, from the source address if it is an IPv6 one
+async fn connect_tcp(addr: SocketAddr, source: Option<Ipv6Addr>) -> Result<TcpStream, IoError> {
+    match (addr, source) {
+        (SocketAddr::V6(_), Some(source)) => {
+            let socket = TcpSocket::new_v6()?;
+            socket.bind(SocketAddr::from((source, 0)))?;
+            socket.connect(addr).await
+        }
+        _ => TcpStream::connect(addr).await,
+    }
+}
+
+pub(super) async fn resolve_dns(
+    addr: &Address,
+) -> Result<impl Iterator<Item = SocketAddr>, IoError> {
     match addr {
         Address::None => Err(IoError::new(ErrorKind::InvalidInput, "empty address")),
         Address::DomainAddress(domain, port) => Ok(net::lookup_host((domain.as_str(), *port))
@@ -1,55 +1,113 @@
 use self::{authenticated::Authenticated, udp_session::UdpSession};
-use crate::{error::Error, utils::UdpRelayMode};
+use crate::{
+    audit::AuditLog,
+    auth::{Auth, Tenants},
+    config::OutboundTcp,
+    connect_limit::ConnectLimits,
+    error::Error,
+    ipv6_source::Ipv6Source,
+    masque::Masque,
+    penalty::{Penalties, Penalty},
+    qlog,
+    utils::UdpRelayMode,
+};
 use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::Mutex;
-use quinn::{Connecting, Connection as QuinnConnection, VarInt};
+use quinn::{crypto::rustls::HandshakeData, Connecting, Connection as QuinnConnection, VarInt};
 use register_count::Counter;
 use std::{
     collections::HashMap,
-    sync::{atomic::AtomicU32, Arc},
+    mem,
+    net::Ipv6Addr,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::time;
-use tuic_quinn::{side, Authenticate, Connection as Model};
-use uuid::Uuid;
+use tuic_quinn::{
+    side, Authenticate, BadCommandPolicy, CloseCode, Connection as Model, PreAuthPolicy,
+};
 
 mod authenticated;
 mod handle_stream;
 mod handle_task;
+mod resumption;
+mod socket_pool;
 mod udp_session;
 
+pub use self::{resumption::Resumption, socket_pool::SocketPool};
+
 pub const ERROR_CODE: VarInt = VarInt::from_u32(0);
-pub const DEFAULT_CONCURRENT_STREAMS: u32 = 32;
+
+/// Resets a `Connect` refused for reaching the limit of connections to its destination
+pub const CONNECT_LIMIT_ERROR_CODE: VarInt = VarInt::from_u32(1);
 
 #[derive(Clone)]
 pub struct Connection {
     inner: QuinnConnection,
     model: Model<side::Server>,
-    users: Arc<HashMap<Uuid, Box<[u8]>>>,
+    users: Arc<Auth>,
+    bandwidth: Option<u64>,
     udp_relay_ipv6: bool,
+    udp_relay_mode_switching: bool,
+    udp_compression: bool,
+    allow_bind: bool,
+    allow_bench: bool,
+    masque: Option<Arc<Masque>>,
+    socket_pool: Option<Arc<SocketPool>>,
+    connect_limits: Option<Arc<ConnectLimits>>,
+    outbound_tcp: OutboundTcp,
+    ipv6_source: Option<Arc<Ipv6Source>>,
+    audit_log: Option<Arc<AuditLog>>,
+    penalties: Option<Arc<Penalties>>,
     auth: Authenticated,
     task_negotiation_timeout: Duration,
     udp_sessions: Arc<Mutex<HashMap<u16, UdpSession>>>,
+    /// The UDP relay mode of the first packet, which all packets of the connection must follow unless `udp_relay_mode_switching` is enabled
     udp_relay_mode: Arc<AtomicCell<Option<UdpRelayMode>>>,
     max_external_pkt_size: usize,
     remote_uni_stream_cnt: Counter,
     remote_bi_stream_cnt: Counter,
     max_concurrent_uni_streams: Arc<AtomicU32>,
     max_concurrent_bi_streams: Arc<AtomicU32>,
+    resumption: Option<Arc<Resumption>>,
+    resume_token: Arc<AtomicCell<Option<[u8; 16]>>>,
+    fec: Arc<AtomicBool>,
 }
 
 #[allow(clippy::too_many_arguments)]
 impl Connection {
     pub async fn handle(
         conn: Connecting,
-        users: Arc<HashMap<Uuid, Box<[u8]>>>,
+        users: Arc<Tenants>,
+        bandwidth: Option<u64>,
         udp_relay_ipv6: bool,
+        udp_relay_mode_switching: bool,
+        udp_compression: bool,
+        allow_bind: bool,
+        allow_bench: bool,
         zero_rtt_handshake: bool,
         auth_timeout: Duration,
         task_negotiation_timeout: Duration,
         max_external_pkt_size: usize,
+        max_pkt_size: u16,
+        max_concurrent_streams: u32,
+        pre_auth: PreAuthPolicy,
+        bad_command: BadCommandPolicy,
         gc_interval: Duration,
         gc_lifetime: Duration,
+        qlog_dir: Option<Arc<Path>>,
+        masque: Option<Arc<Masque>>,
+        socket_pool: Option<Arc<SocketPool>>,
+        connect_limits: Option<Arc<ConnectLimits>>,
+        outbound_tcp: OutboundTcp,
+        ipv6_source: Option<Arc<Ipv6Source>>,
+        audit_log: Option<Arc<AuditLog>>,
+        penalties: Option<Arc<Penalties>>,
+        resumption: Option<Arc<Resumption>>,
     ) {
         let addr = conn.remote_address();
 
@@ -63,12 +121,36 @@ impl Connection {
                 conn.await?
             };
 
+            // the users are told apart by the SNI of the client
+            let server_name = conn
+                .handshake_data()
+                .and_then(|data| data.downcast::<HandshakeData>().ok())
+                .and_then(|data| data.server_name);
+            let users = users.get(server_name.as_deref());
+
             Ok::<_, Error>(Self::new(
                 conn,
                 users,
+                bandwidth,
                 udp_relay_ipv6,
+                udp_relay_mode_switching,
+                udp_compression,
+                allow_bind,
+                allow_bench,
+                masque,
+                socket_pool,
+                connect_limits,
+                outbound_tcp,
+                ipv6_source,
+                audit_log,
+                penalties,
                 task_negotiation_timeout,
                 max_external_pkt_size,
+                max_pkt_size,
+                max_concurrent_streams,
+                pre_auth,
+                bad_command,
+                resumption,
             ))
         };
 
@@ -80,6 +162,10 @@ impl Connection {
                     user = conn.auth,
                 );
 
+                if let Some(dir) = qlog_dir {
+                    tokio::spawn(qlog::trace(conn.inner.clone(), dir));
+                }
+
                 tokio::spawn(conn.clone().timeout_authenticate(auth_timeout));
                 tokio::spawn(conn.clone().collect_garbage(gc_interval, gc_lifetime));
 
@@ -117,6 +203,38 @@ impl Connection {
                         ),
                     }
                 }
+
+                let tasks = conn.model.task_metrics();
+
+                log::debug!(
+                    "[{id:#010x}] [{addr}] [{user}] connection closed with {connect} of {connect_created} TCP relay tasks (at most {connect_high_water} at once) and {associate} of {associate_created} UDP sessions (at most {associate_high_water} at once) left",
+                    id = conn.id(),
+                    user = conn.auth,
+                    connect = tasks.connect.current,
+                    connect_created = tasks.connect.created,
+                    connect_high_water = tasks.connect.high_water,
+                    associate = tasks.associate.current,
+                    associate_created = tasks.associate.created,
+                    associate_high_water = tasks.associate.high_water,
+                );
+
+                let assembly = conn.model.assembly_counts();
+
+                if assembly.fragments > 0 {
+                    log::debug!(
+                        "[{id:#010x}] [{addr}] [{user}] {fragments} packet fragments received, {assembled} packets assembled ({recovered} recovered), {timed_out} incomplete packets timed out, {rejected} fragments rejected and {pending} packets left incomplete",
+                        id = conn.id(),
+                        user = conn.auth,
+                        fragments = assembly.fragments,
+                        assembled = assembly.assembled,
+                        recovered = assembly.recovered,
+                        timed_out = assembly.timed_out,
+                        rejected = assembly.rejected,
+                        pending = assembly.pending,
+                    );
+                }
+
+                conn.release_udp_sessions();
             }
             Err(err) if err.is_trivial() => {
                 log::debug!(
@@ -135,16 +253,48 @@ impl Connection {
 
     fn new(
         conn: QuinnConnection,
-        users: Arc<HashMap<Uuid, Box<[u8]>>>,
+        users: Arc<Auth>,
+        bandwidth: Option<u64>,
         udp_relay_ipv6: bool,
+        udp_relay_mode_switching: bool,
+        udp_compression: bool,
+        allow_bind: bool,
+        allow_bench: bool,
+        masque: Option<Arc<Masque>>,
+        socket_pool: Option<Arc<SocketPool>>,
+        connect_limits: Option<Arc<ConnectLimits>>,
+        outbound_tcp: OutboundTcp,
+        ipv6_source: Option<Arc<Ipv6Source>>,
+        audit_log: Option<Arc<AuditLog>>,
+        penalties: Option<Arc<Penalties>>,
         task_negotiation_timeout: Duration,
         max_external_pkt_size: usize,
+        max_pkt_size: u16,
+        max_concurrent_streams: u32,
+        pre_auth: PreAuthPolicy,
+        bad_command: BadCommandPolicy,
+        resumption: Option<Arc<Resumption>>,
     ) -> Self {
         Self {
             inner: conn.clone(),
-            model: Model::<side::Server>::new(conn),
+            model: Model::<side::Server>::new(conn)
+                .with_max_packet_size(max_pkt_size)
+                .with_pre_auth_policy(pre_auth)
+                .with_bad_command_policy(bad_command),
             users,
+            bandwidth,
             udp_relay_ipv6,
+            udp_relay_mode_switching,
+            udp_compression,
+            allow_bind,
+            allow_bench,
+            masque,
+            socket_pool,
+            connect_limits,
+            outbound_tcp,
+            ipv6_source,
+            audit_log,
+            penalties,
             auth: Authenticated::new(),
             task_negotiation_timeout,
             udp_sessions: Arc::new(Mutex::new(HashMap::new())),
@@ -152,19 +302,30 @@ impl Connection {
             max_external_pkt_size,
             remote_uni_stream_cnt: Counter::new(),
             remote_bi_stream_cnt: Counter::new(),
-            max_concurrent_uni_streams: Arc::new(AtomicU32::new(DEFAULT_CONCURRENT_STREAMS)),
-            max_concurrent_bi_streams: Arc::new(AtomicU32::new(DEFAULT_CONCURRENT_STREAMS)),
+            max_concurrent_uni_streams: Arc::new(AtomicU32::new(max_concurrent_streams)),
+            max_concurrent_bi_streams: Arc::new(AtomicU32::new(max_concurrent_streams)),
+            resumption,
+            resume_token: Arc::new(AtomicCell::new(None)),
+            fec: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    fn authenticate(&self, auth: &Authenticate) -> Result<(), Error> {
+    async fn authenticate(&self, auth: &Authenticate) -> Result<(), Error> {
         if self.auth.get().is_some() {
             Err(Error::DuplicatedAuth)
         } else if self
             .users
-            .get(&auth.uuid())
+            .password(auth.uuid())
+            .await?
             .map_or(false, |password| auth.validate(password))
+            && self.users.consume(auth.uuid())
         {
+            // the client compresses its packets once the first packet sent to it is compressed, acknowledging compression
+            if let Some(min_size) = auth.compression().filter(|_| self.udp_compression) {
+                self.model.set_compression(min_size);
+            }
+
+            self.model.set_authenticated();
             self.auth.set(auth.uuid());
             Ok(())
         } else {
@@ -181,7 +342,7 @@ impl Connection {
                 id = self.id(),
                 addr = self.inner.remote_address(),
             );
-            self.close();
+            self.close(CloseCode::AuthFailed);
         }
     }
 
@@ -203,6 +364,84 @@ impl Connection {
         }
     }
 
+    /// Checks that a `Packet` is received in the UDP relay mode of the first one on the connection, unless `udp_relay_mode_switching` is enabled
+    fn check_packet_source(&self, mode: UdpRelayMode) -> Result<(), Error> {
+        if self.udp_relay_mode_switching {
+            return Ok(());
+        }
+
+        match self.udp_relay_mode.compare_exchange(None, Some(mode)) {
+            Ok(_) => Ok(()),
+            Err(Some(current)) if current == mode => Ok(()),
+            Err(_) => Err(Error::UnexpectedPacketSource),
+        }
+    }
+
+    /// Replicates the association IDs of the UDP sessions to the standby peer, if a resumption token is issued
+    fn replicate_udp_sessions(&self) {
+        let (Some(resumption), Some(token), Some(user)) =
+            (&self.resumption, self.resume_token.load(), self.auth.get())
+        else {
+            return;
+        };
+
+        let mut assoc_ids = self.udp_sessions.lock().keys().copied().collect::<Vec<_>>();
+        assoc_ids.sort_unstable();
+        resumption.track(token, user, assoc_ids);
+    }
+
+    /// Releases the UDP sessions of the closed connection, keeping them for resumption if a resumption token is issued
+    fn release_udp_sessions(&self) {
+        let sessions = mem::take(&mut *self.udp_sessions.lock());
+
+        match (&self.resumption, self.resume_token.load(), self.auth.get()) {
+            (Some(resumption), Some(token), Some(user)) => {
+                if !sessions.is_empty() {
+                    log::info!(
+                        "[{id:#010x}] [{addr}] [{user}] [resume] keeping {cnt} UDP sessions for resumption",
+                        id = self.id(),
+                        addr = self.inner.remote_address(),
+                        cnt = sessions.len(),
+                    );
+                }
+
+                resumption.park(token, user, sessions);
+            }
+            _ => {
+                for session in sessions.values() {
+                    session.close();
+                }
+            }
+        }
+    }
+
+    /// Counts malformed traffic against the source of the connection, delaying the error handling, or closing the connection if the source is banned
+    async fn penalize(&self) {
+        let Some(penalties) = &self.penalties else {
+            return;
+        };
+
+        match penalties.strike(self.inner.remote_address().ip()) {
+            Penalty::None => {}
+            Penalty::Delay(delay) => time::sleep(delay).await,
+            Penalty::Ban(duration) => {
+                log::warn!(
+                    "[{id:#010x}] [{addr}] [{user}] source banned for {duration:?} on malformed traffic",
+                    id = self.id(),
+                    addr = self.inner.remote_address(),
+                    user = self.auth,
+                );
+
+                self.close(CloseCode::ProtocolError);
+            }
+        }
+    }
+
+    /// The source address for a relay to an IPv6 target, or `None` to leave it to the system
+    fn select_ipv6_source(&self) -> Option<Ipv6Addr> {
+        self.ipv6_source.as_ref()?.select(self.auth.get())
+    }
+
     fn id(&self) -> u32 {
         self.inner.stable_id() as u32
     }
@@ -211,7 +450,7 @@ impl Connection {
         self.inner.close_reason().is_some()
     }
 
-    fn close(&self) {
-        self.inner.close(ERROR_CODE, &[]);
+    fn close(&self, code: CloseCode) {
+        self.inner.close(code.into(), code.to_string().as_bytes());
     }
 }
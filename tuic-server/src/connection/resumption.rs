@@ -0,0 +1,94 @@
+use super::UdpSession;
+use crate::standby::Standby;
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::time;
+use uuid::Uuid;
+
+/// The UDP sessions of closed connections, kept for the clients to resume them on new connections with the resumption tokens
+///
+/// The relay sockets stay open meanwhile, so the clients keep their addresses seen by the targets, and NAT mappings on the way stay valid. Sessions not resumed within `lifetime` are closed.
+///
+/// With a standby peer, the issued tokens are replicated to it, and tokens issued by the peer are resumed with the association IDs only.
+pub struct Resumption {
+    lifetime: Duration,
+    parked: Mutex<HashMap<[u8; 16], Parked>>,
+    standby: Option<Arc<Standby>>,
+}
+
+struct Parked {
+    user: Uuid,
+    sessions: HashMap<u16, UdpSession>,
+}
+
+impl Resumption {
+    pub fn new(lifetime: Duration, standby: Option<Arc<Standby>>) -> Self {
+        Self {
+            lifetime,
+            parked: Mutex::new(HashMap::new()),
+            standby,
+        }
+    }
+
+    pub fn issue_token() -> [u8; 16] {
+        Uuid::new_v4().into_bytes()
+    }
+
+    /// Keeps the sessions of a closed connection under its token, until resumed or `lifetime` elapses
+    pub fn park(self: &Arc<Self>, token: [u8; 16], user: Uuid, sessions: HashMap<u16, UdpSession>) {
+        if sessions.is_empty() {
+            self.forget(token);
+            return;
+        }
+
+        self.parked.lock().insert(token, Parked { user, sessions });
+
+        let resumption = self.clone();
+
+        tokio::spawn(async move {
+            time::sleep(resumption.lifetime).await;
+
+            if let Some(parked) = resumption.parked.lock().remove(&token) {
+                for session in parked.sessions.values() {
+                    session.close();
+                }
+
+                resumption.forget(token);
+            }
+        });
+    }
+
+    /// Takes the sessions parked under the token, if they belong to the user
+    pub fn take(&self, token: [u8; 16], user: Uuid) -> Option<HashMap<u16, UdpSession>> {
+        let mut parked = self.parked.lock();
+
+        if parked.get(&token)?.user != user {
+            return None;
+        }
+
+        let sessions = parked.remove(&token).map(|parked| parked.sessions);
+        drop(parked);
+
+        self.forget(token);
+        sessions
+    }
+
+    /// Takes the association IDs behind a token issued by the standby peer, if it belongs to the user
+    pub fn take_replica(&self, token: [u8; 16], user: Uuid) -> Option<Vec<u16>> {
+        self.standby.as_ref()?.take(token, user)
+    }
+
+    /// Replicates the UDP sessions behind a token to the standby peer, if any
+    pub fn track(&self, token: [u8; 16], user: Uuid, assoc_ids: Vec<u16>) {
+        if let Some(standby) = &self.standby {
+            standby.track(token, user, assoc_ids);
+        }
+    }
+
+    /// Tells the standby peer, if any, that the token can no longer be resumed
+    pub fn forget(&self, token: [u8; 16]) {
+        if let Some(standby) = &self.standby {
+            standby.untrack(token);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use super::UdpSession;
+use crate::{config::UdpSocketPool as UdpSocketPoolConfig, error::Error};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tuic::Address;
+
+/// A bounded pool of UDP sockets shared by the UDP sessions of all connections, instead of a socket for each session
+///
+/// A session sends to each target through one pooled socket, the first one found on which no other session is talking to the target. Packets from the targets are demultiplexed back to the sessions by the socket they arrive on and their source address, so a session only receives packets from the targets it has sent to (port-restricted cone, rather than full cone with a socket of its own). Sending fails once every socket in the pool has a session talking to the target.
+pub struct SocketPool {
+    sockets_v4: Vec<Arc<PooledSocket>>,
+    sockets_v6: Vec<Arc<PooledSocket>>,
+    next: AtomicUsize,
+    listen: Vec<JoinHandle<()>>,
+}
+
+/// A socket of the pool, with the session talking to each target through it
+pub struct PooledSocket {
+    socket: UdpSocket,
+    flows: Mutex<HashMap<SocketAddr, UdpSession>>,
+}
+
+impl SocketPool {
+    pub fn new(
+        cfg: UdpSocketPoolConfig,
+        udp_relay_ipv6: bool,
+        max_pkt_size: usize,
+    ) -> Result<Self, Error> {
+        Self::check(&cfg)?;
+
+        let sockets_v4 = (0..cfg.size)
+            .map(|_| PooledSocket::bind(false))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sockets_v6 = if udp_relay_ipv6 {
+            (0..cfg.size)
+                .map(|_| PooledSocket::bind(true))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let listen = sockets_v4
+            .iter()
+            .chain(&sockets_v6)
+            .map(|socket| tokio::spawn(socket.clone().listen(max_pkt_size)))
+            .collect();
+
+        Ok(Self {
+            sockets_v4,
+            sockets_v6,
+            next: AtomicUsize::new(0),
+            listen,
+        })
+    }
+
+    pub fn check(cfg: &UdpSocketPoolConfig) -> Result<(), Error> {
+        if cfg.size == 0 {
+            return Err(Error::InvalidUdpSocketPool("`size` cannot be zero"));
+        }
+
+        Ok(())
+    }
+
+    /// Takes a socket on which no other session is talking to `addr` for the session
+    pub fn claim(
+        &self,
+        session: &UdpSession,
+        addr: SocketAddr,
+    ) -> Result<Arc<PooledSocket>, Error> {
+        let sockets = match addr {
+            SocketAddr::V4(_) => &self.sockets_v4,
+            SocketAddr::V6(_) => &self.sockets_v6,
+        };
+
+        if sockets.is_empty() {
+            return Err(Error::UdpRelayIpv6Disabled(addr));
+        }
+
+        // start from a different socket every time, spreading the sessions across the pool
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        (0..sockets.len())
+            .map(|idx| &sockets[(start + idx) % sockets.len()])
+            .find(|socket| match socket.flows.lock().entry(addr) {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(entry) => {
+                    entry.insert(session.clone());
+                    true
+                }
+            })
+            .cloned()
+            .ok_or(Error::UdpSocketPoolExhausted(addr))
+    }
+}
+
+impl Drop for SocketPool {
+    fn drop(&mut self) {
+        for listen in &self.listen {
+            listen.abort();
+        }
+    }
+}
+
+impl PooledSocket {
+    fn bind(ipv6: bool) -> Result<Arc<Self>, Error> {
+        let (domain, addr) = if ipv6 {
+            (Domain::IPV6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+        } else {
+            (Domain::IPV4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+        };
+
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))
+            .map_err(|err| Error::Socket("failed to create pooled UDP socket", err))?;
+
+        socket.set_nonblocking(true).map_err(|err| {
+            Error::Socket("failed setting pooled UDP socket as non-blocking", err)
+        })?;
+
+        if ipv6 {
+            socket.set_only_v6(true).map_err(|err| {
+                Error::Socket("failed setting pooled UDP socket as IPv6-only", err)
+            })?;
+        }
+
+        socket
+            .bind(&SockAddr::from(addr))
+            .map_err(|err| Error::Socket("failed to bind pooled UDP socket", err))?;
+
+        Ok(Arc::new(Self {
+            socket: UdpSocket::from_std(StdUdpSocket::from(socket))?,
+            flows: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    pub async fn send_to(&self, pkt: &[u8], addr: SocketAddr) -> Result<(), Error> {
+        self.socket.send_to(pkt, addr).await?;
+        Ok(())
+    }
+
+    /// Stops relaying packets from `addr` to the session, if it is still the one talking to it
+    pub fn release(&self, addr: &SocketAddr, session: &UdpSession) {
+        let mut flows = self.flows.lock();
+
+        if flows.get(addr).map_or(false, |cur| cur.ptr_eq(session)) {
+            flows.remove(addr);
+        }
+    }
+
+    /// Relays packets to the sessions talking to their sources, dropping the others
+    async fn listen(self: Arc<Self>, max_pkt_size: usize) {
+        loop {
+            let mut buf = vec![0u8; max_pkt_size];
+
+            let (n, addr) = match self.socket.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(err) => {
+                    log::warn!("[udp-socket-pool] outbound listening error: {err}");
+                    continue;
+                }
+            };
+
+            let session = self.flows.lock().get(&addr).cloned();
+
+            if let Some(session) = session {
+                buf.truncate(n);
+                session.relay_back(Bytes::from(buf), Address::SocketAddress(addr));
+            }
+        }
+    }
+}
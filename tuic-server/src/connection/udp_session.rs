@@ -1,16 +1,30 @@
-use super::Connection;
-use crate::error::Error;
+use super::{
+    handle_task::resolve_dns,
+    socket_pool::{PooledSocket, SocketPool},
+    Connection,
+};
+use crate::{
+    error::Error,
+    masque::{Masque, TunnelReceiver, TunnelSender},
+    utils::UdpRelayMode,
+};
 use bytes::Bytes;
+use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::Mutex;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 use std::{
-    io::Error as IoError,
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind},
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket},
     sync::Arc,
 };
 use tokio::{
     net::UdpSocket,
-    sync::oneshot::{self, Sender},
+    sync::{
+        oneshot::{self, Sender},
+        Mutex as AsyncMutex, OnceCell,
+    },
+    task::JoinHandle,
 };
 use tuic::Address;
 
@@ -19,20 +33,129 @@ pub struct UdpSession(Arc<UdpSessionInner>);
 
 struct UdpSessionInner {
     assoc_id: u16,
-    conn: Connection,
-    socket_v4: UdpSocket,
-    socket_v6: Option<UdpSocket>,
+    /// Replaced when the session is resumed on a new connection
+    conn: Mutex<Connection>,
+    mode: AtomicCell<UdpRelayMode>,
+    outbound: Outbound,
     max_pkt_size: usize,
     close: Mutex<Option<Sender<()>>>,
 }
 
+enum Outbound {
+    Direct {
+        socket_v4: UdpSocket,
+        socket_v6: Option<UdpSocket>,
+    },
+    /// A socket of the pool for each target, claimed with the first packet to it
+    Pooled {
+        pool: Arc<SocketPool>,
+        sockets: Mutex<HashMap<SocketAddr, Arc<PooledSocket>>>,
+    },
+    /// A CONNECT-UDP tunnel through the MASQUE proxy for each target, opened with the first packet to it
+    Masque {
+        masque: Arc<Masque>,
+        tunnels: Mutex<HashMap<Address, Arc<OnceCell<Tunnel>>>>,
+    },
+}
+
+struct Tunnel {
+    sender: AsyncMutex<TunnelSender>,
+    listen: JoinHandle<()>,
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.listen.abort();
+    }
+}
+
 impl UdpSession {
     pub fn new(
         conn: Connection,
         assoc_id: u16,
+        mode: UdpRelayMode,
+        udp_relay_ipv6: bool,
+        masque: Option<Arc<Masque>>,
+        socket_pool: Option<Arc<SocketPool>>,
+        max_pkt_size: usize,
+    ) -> Result<Self, Error> {
+        if let Some(masque) = masque {
+            return Ok(Self(Arc::new(UdpSessionInner {
+                conn: Mutex::new(conn),
+                assoc_id,
+                mode: AtomicCell::new(mode),
+                outbound: Outbound::Masque {
+                    masque,
+                    tunnels: Mutex::new(HashMap::new()),
+                },
+                max_pkt_size,
+                close: Mutex::new(None),
+            })));
+        }
+
+        if let Some(pool) = socket_pool {
+            return Ok(Self(Arc::new(UdpSessionInner {
+                conn: Mutex::new(conn),
+                assoc_id,
+                mode: AtomicCell::new(mode),
+                outbound: Outbound::Pooled {
+                    pool,
+                    sockets: Mutex::new(HashMap::new()),
+                },
+                max_pkt_size,
+                close: Mutex::new(None),
+            })));
+        }
+
+        Self::new_direct(conn, assoc_id, mode, udp_relay_ipv6, None, max_pkt_size)
+    }
+
+    /// Creates a UDP session relaying from the socket bound on `addr`, for the `BindUdp` command
+    pub fn bind(
+        conn: Connection,
+        assoc_id: u16,
+        mode: UdpRelayMode,
         udp_relay_ipv6: bool,
+        addr: SocketAddr,
         max_pkt_size: usize,
     ) -> Result<Self, Error> {
+        Self::new_direct(
+            conn,
+            assoc_id,
+            mode,
+            udp_relay_ipv6,
+            Some(addr),
+            max_pkt_size,
+        )
+    }
+
+    fn new_direct(
+        conn: Connection,
+        assoc_id: u16,
+        mode: UdpRelayMode,
+        udp_relay_ipv6: bool,
+        bind_addr: Option<SocketAddr>,
+        max_pkt_size: usize,
+    ) -> Result<Self, Error> {
+        let (bind_v4, bind_v6) = match bind_addr {
+            Some(addr @ SocketAddr::V4(_)) => (addr, SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))),
+            Some(addr @ SocketAddr::V6(_)) if udp_relay_ipv6 => {
+                (SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)), addr)
+            }
+            Some(addr @ SocketAddr::V6(_)) => return Err(Error::BindUdpIpv6Disabled(addr)),
+            None => {
+                let source = udp_relay_ipv6
+                    .then(|| conn.select_ipv6_source())
+                    .flatten()
+                    .unwrap_or(Ipv6Addr::UNSPECIFIED);
+
+                (
+                    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    SocketAddr::from((source, 0)),
+                )
+            }
+        };
+
         let socket_v4 = {
             let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
                 .map_err(|err| Error::Socket("failed to create UDP associate IPv4 socket", err))?;
@@ -45,10 +168,7 @@ impl UdpSession {
             })?;
 
             socket
-                .bind(&SockAddr::from(SocketAddr::from((
-                    Ipv4Addr::UNSPECIFIED,
-                    0,
-                ))))
+                .bind(&SockAddr::from(bind_v4))
                 .map_err(|err| Error::Socket("failed to bind UDP associate IPv4 socket", err))?;
 
             UdpSocket::from_std(StdUdpSocket::from(socket))?
@@ -70,10 +190,7 @@ impl UdpSession {
             })?;
 
             socket
-                .bind(&SockAddr::from(SocketAddr::from((
-                    Ipv6Addr::UNSPECIFIED,
-                    0,
-                ))))
+                .bind(&SockAddr::from(bind_v6))
                 .map_err(|err| Error::Socket("failed to bind UDP associate IPv6 socket", err))?;
 
             Some(UdpSocket::from_std(StdUdpSocket::from(socket))?)
@@ -84,10 +201,13 @@ impl UdpSession {
         let (tx, rx) = oneshot::channel();
 
         let session = Self(Arc::new(UdpSessionInner {
-            conn,
+            conn: Mutex::new(conn),
             assoc_id,
-            socket_v4,
-            socket_v6,
+            mode: AtomicCell::new(mode),
+            outbound: Outbound::Direct {
+                socket_v4,
+                socket_v6,
+            },
             max_pkt_size,
             close: Mutex::new(Some(tx)),
         }));
@@ -95,24 +215,23 @@ impl UdpSession {
         let session_listening = session.clone();
         let listen = async move {
             loop {
-                let (pkt, addr) = match session_listening.recv().await {
+                let res = session_listening.recv().await;
+                let conn = session_listening.conn();
+
+                let (pkt, addr) = match res {
                     Ok(res) => res,
                     Err(err) => {
                         log::warn!(
                             "[{id:#010x}] [{addr}] [{user}] [packet] [{assoc_id:#06x}] outbound listening error: {err}",
-                            id = session_listening.0.conn.id(),
-                            addr = session_listening.0.conn.inner.remote_address(),
-                            user = session_listening.0.conn.auth,
+                            id = conn.id(),
+                            addr = conn.inner.remote_address(),
+                            user = conn.auth,
                         );
                         continue;
                     }
                 };
 
-                tokio::spawn(session_listening.0.conn.clone().relay_packet(
-                    pkt,
-                    Address::SocketAddress(addr),
-                    session_listening.0.assoc_id,
-                ));
+                session_listening.relay_back(pkt, Address::SocketAddress(addr));
             }
         };
 
@@ -126,12 +245,73 @@ impl UdpSession {
         Ok(session)
     }
 
-    pub async fn send(&self, pkt: Bytes, addr: SocketAddr) -> Result<(), Error> {
+    /// Moves the session onto a new connection, relaying packets from the target back through it
+    pub fn set_connection(&self, conn: Connection) {
+        *self.0.conn.lock() = conn;
+    }
+
+    fn conn(&self) -> Connection {
+        self.0.conn.lock().clone()
+    }
+
+    /// Sets the UDP relay mode for sending packets back to the client
+    pub fn set_mode(&self, mode: UdpRelayMode) {
+        self.0.mode.store(mode);
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Relays a packet from the target back to the client, through the current connection of the session
+    pub fn relay_back(&self, pkt: Bytes, addr: Address) {
+        tokio::spawn(
+            self.conn()
+                .relay_packet(pkt, addr, self.0.assoc_id, self.0.mode.load()),
+        );
+    }
+
+    /// Returns the local address of the IPv4 or IPv6 socket of the session
+    pub fn local_addr(&self, ipv6: bool) -> Result<SocketAddr, IoError> {
+        let Outbound::Direct {
+            socket_v4,
+            socket_v6,
+        } = &self.0.outbound
+        else {
+            return Err(IoError::new(ErrorKind::Unsupported, "no local socket"));
+        };
+
+        match (ipv6, socket_v6) {
+            (false, _) => socket_v4.local_addr(),
+            (true, Some(socket_v6)) => socket_v6.local_addr(),
+            (true, None) => Err(IoError::new(ErrorKind::Unsupported, "no IPv6 socket")),
+        }
+    }
+
+    pub async fn send(&self, pkt: Bytes, addr: Address) -> Result<(), Error> {
+        let (socket_v4, socket_v6) = match &self.0.outbound {
+            Outbound::Direct {
+                socket_v4,
+                socket_v6,
+            } => (socket_v4, socket_v6),
+            Outbound::Pooled { pool, sockets } => {
+                return self.send_pooled(pool, sockets, pkt, addr).await
+            }
+            Outbound::Masque { masque, tunnels } => {
+                return self.send_masque(masque, tunnels, pkt, addr).await
+            }
+        };
+
+        let Some(addr) = resolve_dns(&addr).await?.next() else {
+            return Err(Error::from(IoError::new(
+                ErrorKind::NotFound,
+                "no address resolved",
+            )));
+        };
+
         let socket = match addr {
-            SocketAddr::V4(_) => &self.0.socket_v4,
-            SocketAddr::V6(_) => self
-                .0
-                .socket_v6
+            SocketAddr::V4(_) => socket_v4,
+            SocketAddr::V6(_) => socket_v6
                 .as_ref()
                 .ok_or_else(|| Error::UdpRelayIpv6Disabled(addr))?,
         };
@@ -140,7 +320,127 @@ impl UdpSession {
         Ok(())
     }
 
+    async fn send_pooled(
+        &self,
+        pool: &SocketPool,
+        sockets: &Mutex<HashMap<SocketAddr, Arc<PooledSocket>>>,
+        pkt: Bytes,
+        addr: Address,
+    ) -> Result<(), Error> {
+        let Some(addr) = resolve_dns(&addr).await?.next() else {
+            return Err(Error::from(IoError::new(
+                ErrorKind::NotFound,
+                "no address resolved",
+            )));
+        };
+
+        let socket = {
+            let mut sockets = sockets.lock();
+
+            match sockets.get(&addr) {
+                Some(socket) => socket.clone(),
+                None => {
+                    let socket = pool.claim(self, addr)?;
+                    sockets.insert(addr, socket.clone());
+                    socket
+                }
+            }
+        };
+
+        socket.send_to(&pkt, addr).await
+    }
+
+    async fn send_masque(
+        &self,
+        masque: &Masque,
+        tunnels: &Mutex<HashMap<Address, Arc<OnceCell<Tunnel>>>>,
+        pkt: Bytes,
+        addr: Address,
+    ) -> Result<(), Error> {
+        let cell = tunnels.lock().entry(addr.clone()).or_default().clone();
+
+        let tunnel = cell
+            .get_or_try_init(|| async {
+                let (sender, receiver) = masque.connect_udp(&addr).await?;
+
+                let listen = tokio::spawn(self.clone().listen_tunnel(
+                    receiver,
+                    addr.clone(),
+                    cell.clone(),
+                ));
+
+                Ok::<_, Error>(Tunnel {
+                    sender: AsyncMutex::new(sender),
+                    listen,
+                })
+            })
+            .await;
+
+        let res = match tunnel {
+            Ok(tunnel) => tunnel.sender.lock().await.send(pkt).await,
+            Err(err) => Err(err),
+        };
+
+        // the tunnel is reopened with the next packet
+        if res.is_err() {
+            self.remove_tunnel(&addr, &cell);
+        }
+
+        res
+    }
+
+    /// Relays packets from the target back to the client, until the proxy closes the tunnel
+    async fn listen_tunnel(
+        self,
+        mut receiver: TunnelReceiver,
+        addr: Address,
+        cell: Arc<OnceCell<Tunnel>>,
+    ) {
+        loop {
+            let res = receiver.recv().await;
+            let conn = self.conn();
+
+            match res {
+                Ok(Some(pkt)) => self.relay_back(pkt, addr.clone()),
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!(
+                        "[{id:#010x}] [{conn_addr}] [{user}] [packet] [{assoc_id:#06x}] [masque] tunnel to {addr} error: {err}",
+                        id = conn.id(),
+                        conn_addr = conn.inner.remote_address(),
+                        user = conn.auth,
+                        assoc_id = self.0.assoc_id,
+                    );
+                    break;
+                }
+            }
+        }
+
+        self.remove_tunnel(&addr, &cell);
+    }
+
+    fn remove_tunnel(&self, addr: &Address, cell: &Arc<OnceCell<Tunnel>>) {
+        if let Outbound::Masque { tunnels, .. } = &self.0.outbound {
+            let mut tunnels = tunnels.lock();
+
+            if tunnels
+                .get(addr)
+                .map_or(false, |cur| Arc::ptr_eq(cur, cell))
+            {
+                tunnels.remove(addr);
+            }
+        }
+    }
+
     async fn recv(&self) -> Result<(Bytes, SocketAddr), IoError> {
+        let Outbound::Direct {
+            socket_v4,
+            socket_v6,
+        } = &self.0.outbound
+        else {
+            unreachable!()
+        };
+
         async fn recv(
             socket: &UdpSocket,
             max_pkt_size: usize,
@@ -151,17 +451,27 @@ impl UdpSession {
             Ok((Bytes::from(buf), addr))
         }
 
-        if let Some(socket_v6) = &self.0.socket_v6 {
+        if let Some(socket_v6) = socket_v6 {
             tokio::select! {
-                res = recv(&self.0.socket_v4, self.0.max_pkt_size) => res,
+                res = recv(socket_v4, self.0.max_pkt_size) => res,
                 res = recv(socket_v6, self.0.max_pkt_size) => res,
             }
         } else {
-            recv(&self.0.socket_v4, self.0.max_pkt_size).await
+            recv(socket_v4, self.0.max_pkt_size).await
         }
     }
 
     pub fn close(&self) {
-        let _ = self.0.close.lock().take().unwrap().send(());
+        match &self.0.outbound {
+            Outbound::Direct { .. } => {
+                let _ = self.0.close.lock().take().unwrap().send(());
+            }
+            Outbound::Pooled { sockets, .. } => {
+                for (addr, socket) in sockets.lock().drain() {
+                    socket.release(&addr, self);
+                }
+            }
+            Outbound::Masque { tunnels, .. } => tunnels.lock().clear(),
+        }
     }
 }
@@ -1,8 +1,9 @@
-use quinn::ConnectionError;
+use quinn::{ConnectError, ConnectionError};
 use rustls::Error as RustlsError;
 use std::{io::Error as IoError, net::SocketAddr};
 use thiserror::Error;
-use tuic_quinn::Error as ModelError;
+use tokio_tungstenite::tungstenite::Error as WebSocketError;
+use tuic_quinn::{CloseCode, Error as ModelError};
 use uuid::Uuid;
 
 #[derive(Debug, Error)]
@@ -13,6 +14,20 @@ pub enum Error {
     Rustls(#[from] RustlsError),
     #[error("invalid max idle time")]
     InvalidMaxIdleTime,
+    #[error("invalid transport settings: {0}")]
+    InvalidTransport(&'static str),
+    #[error("invalid audit log settings: {0}")]
+    InvalidAuditLog(&'static str),
+    #[error("failed to open audit log: {0}")]
+    AuditLog(IoError),
+    #[error("invalid IPv6 source settings: {0}")]
+    InvalidIpv6Source(&'static str),
+    #[error("invalid UDP socket pool settings: {0}")]
+    InvalidUdpSocketPool(&'static str),
+    #[error("invalid connect limits: {0}")]
+    InvalidConnectLimits(&'static str),
+    #[error("invalid congestion control settings: {0}")]
+    InvalidCongestionControl(&'static str),
     #[error("connection timed out")]
     TimedOut,
     #[error("connection locally closed")]
@@ -31,12 +46,101 @@ pub enum Error {
     TaskNegotiationTimeout,
     #[error("failed sending packet to {0}: relaying IPv6 UDP packet is disabled")]
     UdpRelayIpv6Disabled(SocketAddr),
+    #[error("failed sending packet to {0}: every pooled UDP socket is in use for the target")]
+    UdpSocketPoolExhausted(SocketAddr),
+    #[error("too many connections to the destination of {0}")]
+    ConnectLimitReached(SocketAddr),
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error("WebSocket bridge error: {0}")]
+    WebSocket(Box<WebSocketError>),
+    #[error("MASQUE proxy error: {0}")]
+    H3(#[from] h3::Error),
+    #[error("MASQUE proxy rejected the request with status {0}")]
+    MasqueStatus(u16),
+    #[error("invalid MASQUE config: {0}")]
+    InvalidMasqueConfig(&'static str),
+    #[error("invalid MASQUE target address")]
+    InvalidMasqueTarget,
+    #[error("cannot resolve the MASQUE proxy name")]
+    MasqueDnsResolve,
+    #[error("failed binding UDP on {0}: relaying IPv6 UDP packet is disabled")]
+    BindUdpIpv6Disabled(SocketAddr),
+    #[error("binding UDP on a domain address is not supported")]
+    BindUdpDomain,
+    #[error("binding UDP is not supported with the MASQUE outbound")]
+    BindUdpMasque,
+    #[error("UDP session resumption is disabled")]
+    ResumptionDisabled,
+    #[error("invalid SNI config for `{0}`: {1}")]
+    InvalidSniConfig(String, &'static str),
+    #[error("invalid auth config: {0}")]
+    InvalidAuthConfig(&'static str),
+    #[error("auth backend error: {0}")]
+    AuthBackend(String),
+    #[error("standby peer failed to authenticate")]
+    StandbyAuthFailed,
+    #[error("binding TCP is disabled")]
+    BindDisabled,
+    #[error("binding TCP on a domain address is not supported")]
+    BindDomain,
 }
 
 impl Error {
     pub fn is_trivial(&self) -> bool {
         matches!(self, Self::TimedOut | Self::LocallyClosed)
     }
+
+    /// The code to close the connection with on the error
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            Self::AuthFailed(_) => CloseCode::AuthFailed,
+            Self::DuplicatedAuth | Self::UnexpectedPacketSource | Self::TaskNegotiationTimeout => {
+                CloseCode::ProtocolError
+            }
+            Self::Model(ModelError::Unauthenticated) => CloseCode::AuthFailed,
+            Self::Model(
+                ModelError::PayloadLength(_, _)
+                | ModelError::PreAuthLimit(_)
+                | ModelError::PacketTooLarge(_, _)
+                | ModelError::InvalidUdpSession(_, _)
+                | ModelError::Assemble(_)
+                | ModelError::UnmarshalUniStream(_, _, _)
+                | ModelError::UnmarshalBiStream(_, _, _, _)
+                | ModelError::UnmarshalDatagram(_, _)
+                | ModelError::BadCommandUniStream(_, _)
+                | ModelError::BadCommandBiStream(_, _, _)
+                | ModelError::BadCommandDatagram(_, _)
+                | ModelError::BadCommand(_)
+                | ModelError::ProtocolViolation(_),
+            ) => CloseCode::ProtocolError,
+            _ => CloseCode::Normal,
+        }
+    }
+
+    /// A command failing to unmarshal or to reassemble, or not allowed from the client or not supported, counted against its source by `malformed_traffic`
+    pub fn is_malformed(&self) -> bool {
+        matches!(
+            self,
+            Self::Model(
+                ModelError::PayloadLength(_, _)
+                    | ModelError::Assemble(_)
+                    | ModelError::UnmarshalUniStream(_, _, _)
+                    | ModelError::UnmarshalBiStream(_, _, _, _)
+                    | ModelError::UnmarshalDatagram(_, _)
+                    | ModelError::BadCommandUniStream(_, _)
+                    | ModelError::BadCommandBiStream(_, _, _)
+                    | ModelError::BadCommandDatagram(_, _)
+                    | ModelError::BadCommand(_)
+                    | ModelError::ProtocolViolation(_)
+            )
+        )
+    }
+
+    /// A bad command taken by the `bad_command` policy, which does not close the connection
+    pub fn is_quarantined(&self) -> bool {
+        matches!(self, Self::Model(ModelError::BadCommand(_)))
+    }
 }
 
 impl From<ConnectionError> for Error {
@@ -48,3 +152,9 @@ impl From<ConnectionError> for Error {
         }
     }
 }
+
+impl From<WebSocketError> for Error {
+    fn from(err: WebSocketError) -> Self {
+        Self::WebSocket(Box::new(err))
+    }
+}
@@ -0,0 +1,112 @@
+//! A plain HTTP health check endpoint for orchestrators, e.g. a Docker `HEALTHCHECK` or a Kubernetes probe
+//!
+//! `GET /healthz` is answered with `200 OK` while the server accepts connections, and `503 Service Unavailable` once it is shutting down, so that no new clients are sent to it while the existing ones are being closed. Any other path is `404 Not Found`.
+
+use crate::error::Error;
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// How long a probe has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The request head is truncated to this, which is plenty for the request line
+const MAX_REQUEST_SIZE: usize = 1024;
+
+pub struct Healthz {
+    listener: TcpListener,
+    serving: Arc<AtomicBool>,
+}
+
+impl Healthz {
+    /// Binds the health check listener, reporting healthy while `serving` is set
+    pub fn new(listen: SocketAddr, serving: Arc<AtomicBool>) -> Result<Self, Error> {
+        let listener = StdTcpListener::bind(listen)
+            .map_err(|err| Error::Socket("failed to bind health check listener", err))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| Error::Socket("failed to bind health check listener", err))?;
+
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+            serving,
+        })
+    }
+
+    pub async fn start(self) {
+        log::warn!(
+            "[healthz] listening on {}",
+            self.listener.local_addr().unwrap()
+        );
+
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let serving = self.serving.load(Ordering::Relaxed);
+
+                    tokio::spawn(async move {
+                        if let Err(err) = handle(stream, serving).await {
+                            log::debug!("[healthz] {err}");
+                        }
+                    });
+                }
+                Err(err) => log::warn!("[healthz] failed accepting connection: {err}"),
+            }
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, serving: bool) -> Result<(), IoError> {
+    let mut buf = vec![0; MAX_REQUEST_SIZE];
+    let mut len = 0;
+
+    // the request line is all that matters, so the headers are not read past the buffer
+    time::timeout(REQUEST_TIMEOUT, async {
+        while len < buf.len() && !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            match stream.read(&mut buf[len..]).await? {
+                0 => break,
+                n => len += n,
+            }
+        }
+
+        Ok::<_, IoError>(())
+    })
+    .await
+    .map_err(|_| IoError::from(ErrorKind::TimedOut))??;
+
+    let req = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = req.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let (status, body) = match (method, path) {
+        ("GET" | "HEAD", "/healthz") if serving => ("200 OK", "ok\n"),
+        ("GET" | "HEAD", "/healthz") => ("503 Service Unavailable", "shutting down\n"),
+        ("GET" | "HEAD", _) => ("404 Not Found", "not found\n"),
+        _ => ("405 Method Not Allowed", "method not allowed\n"),
+    };
+
+    let mut res = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+
+    if method != "HEAD" {
+        res.push_str(body);
+    }
+
+    stream.write_all(res.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
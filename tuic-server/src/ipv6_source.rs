@@ -0,0 +1,65 @@
+//! Choosing the source address of outbound relays to IPv6 targets, among the IPv6 addresses of the server
+//!
+//! Users pinned in `ipv6_source.users` always relay from their own address. The others relay from one of `ipv6_source.addresses`, picked by the policy: `per_user` keeps every user on the same address, so the reputation of a user does not spill over to the others, while `rotate` moves to the next address for every TCP connection and UDP session. Relays to IPv4 targets are not affected.
+
+use crate::{config::Ipv6Source as Ipv6SourceConfig, error::Error, utils::Ipv6SourcePolicy};
+use std::{
+    collections::HashMap,
+    net::{Ipv6Addr, UdpSocket},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use uuid::Uuid;
+
+pub struct Ipv6Source {
+    addresses: Vec<Ipv6Addr>,
+    policy: Ipv6SourcePolicy,
+    users: HashMap<Uuid, Ipv6Addr>,
+    next: AtomicUsize,
+}
+
+impl Ipv6Source {
+    pub fn new(cfg: Ipv6SourceConfig) -> Result<Self, Error> {
+        if cfg.addresses.is_empty() && cfg.users.is_empty() {
+            return Err(Error::InvalidIpv6Source(
+                "`addresses` and `users` cannot both be empty",
+            ));
+        }
+
+        Ok(Self {
+            addresses: cfg.addresses,
+            policy: cfg.policy,
+            users: cfg.users,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Checks that every address is assigned to the server, by binding a socket on it
+    pub fn check_addresses(&self) -> Result<(), Error> {
+        for addr in self.addresses.iter().chain(self.users.values()) {
+            UdpSocket::bind((*addr, 0))
+                .map_err(|err| Error::Socket("failed to bind IPv6 source address", err))?;
+        }
+
+        Ok(())
+    }
+
+    /// The source address for a relay of the user, or `None` to leave it to the system
+    pub fn select(&self, user: Option<Uuid>) -> Option<Ipv6Addr> {
+        if let Some(addr) = user.and_then(|user| self.users.get(&user)) {
+            return Some(*addr);
+        }
+
+        if self.addresses.is_empty() {
+            return None;
+        }
+
+        let idx = match self.policy {
+            Ipv6SourcePolicy::PerUser => (user?.as_u128() % self.addresses.len() as u128) as usize,
+            Ipv6SourcePolicy::Rotate => {
+                self.next.fetch_add(1, Ordering::Relaxed) % self.addresses.len()
+            }
+        };
+
+        Some(self.addresses[idx])
+    }
+}
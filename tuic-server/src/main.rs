@@ -1,14 +1,36 @@
 use crate::{
-    config::{Config, ConfigError},
+    auth::tokens::Tokens,
+    config::{Command, Config, ConfigError},
     server::Server,
 };
 use env_logger::Builder as LoggerBuilder;
 use std::{env, process};
 
+/// The config is invalid, or the files it refers to cannot be loaded
+const EXIT_INVALID_CONFIG: i32 = 1;
+/// The arguments are wrong, or no config is given
+const EXIT_USAGE: i32 = 2;
+/// The config is valid, but the server fails to start, e.g. the address cannot be bound
+const EXIT_STARTUP: i32 = 3;
+
+mod audit;
+mod auth;
+mod bench;
+mod bridge;
 mod config;
+mod connect_limit;
 mod connection;
 mod error;
+mod healthz;
+mod ipv6_source;
+mod masque;
+mod obfs;
+mod penalty;
+mod qlog;
+mod retry;
 mod server;
+mod sni;
+mod standby;
 mod utils;
 
 #[tokio::main]
@@ -19,23 +41,102 @@ async fn main() {
             println!("{msg}");
             process::exit(0);
         }
+        Err(
+            err @ (ConfigError::Argument(_)
+            | ConfigError::NoConfig
+            | ConfigError::Override(_)
+            | ConfigError::Usage(_)),
+        ) => {
+            eprintln!("{err}");
+            process::exit(EXIT_USAGE);
+        }
         Err(err) => {
             eprintln!("{err}");
-            process::exit(1);
+            process::exit(EXIT_INVALID_CONFIG);
         }
     };
 
+    match cfg.command {
+        Command::Run => {}
+        Command::CheckConfig => {
+            for warning in &cfg.warnings {
+                eprintln!("warning: {warning}");
+            }
+
+            match Server::check(cfg) {
+                Ok(()) => {
+                    println!("config OK");
+                    process::exit(0);
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    process::exit(EXIT_INVALID_CONFIG);
+                }
+            }
+        }
+        Command::IssueToken {
+            valid_for,
+            single_use,
+        } => {
+            let Some(tokens) = cfg.tokens else {
+                eprintln!("`tokens.secret` must be set in the config to issue tokens");
+                process::exit(EXIT_INVALID_CONFIG);
+            };
+
+            let token = Tokens::new(tokens).issue(valid_for, single_use);
+
+            println!("uuid: {}", token.uuid);
+            println!("password: {}", token.password);
+            println!("expires: {}", humantime::format_rfc3339(token.expires));
+            process::exit(0);
+        }
+    }
+
     LoggerBuilder::new()
         .filter_level(cfg.log_level)
         .format_module_path(false)
         .format_target(false)
         .init();
 
+    for warning in &cfg.warnings {
+        log::warn!("[config] {warning}");
+    }
+
     match Server::init(cfg) {
-        Ok(server) => server.start().await,
+        Ok(server) => {
+            tokio::select! {
+                () = server.start() => {}
+                () = shutdown_signal() => server.shutdown().await,
+            }
+        }
         Err(err) => {
             eprintln!("{err}");
-            process::exit(1);
+            process::exit(EXIT_STARTUP);
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+
+        match unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
         }
     }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
@@ -0,0 +1,294 @@
+//! Forwarding UDP associations to an upstream MASQUE proxy, with HTTP/3 CONNECT-UDP (RFC 9298)
+//!
+//! Each target of an association is tunneled in its own CONNECT-UDP request, so the proxy resolves domain targets itself. UDP payloads are carried in DATAGRAM capsules on the request stream (RFC 9297), as HTTP/3 datagrams are not negotiated with the proxy.
+
+use crate::{config::Masque as MasqueConfig, error::Error, utils};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use h3::{
+    client::{RequestStream, SendRequest},
+    ext::Protocol,
+};
+use h3_quinn::{OpenStreams, RecvStream, SendStream};
+use http::{
+    header::{HeaderName, HeaderValue},
+    HeaderMap, Method, Request, Uri,
+};
+use quinn::{ClientConfig, Connection as QuinnConnection, Endpoint};
+use rustls::{version, ClientConfig as RustlsClientConfig, RootCertStore};
+use std::{
+    future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{net, sync::Mutex as AsyncMutex};
+use tuic::Address;
+
+const CAPSULE_DATAGRAM: u64 = 0x00;
+const CONTEXT_ID_UDP: u64 = 0x00;
+
+pub struct Masque {
+    server: (String, u16),
+    ip: Option<IpAddr>,
+    path: String,
+    headers: HeaderMap,
+    config: ClientConfig,
+    conn: AsyncMutex<Option<(QuinnConnection, SendRequest<OpenStreams, Bytes>)>>,
+}
+
+impl Masque {
+    pub fn new(cfg: MasqueConfig) -> Result<Self, Error> {
+        let mut certs = RootCertStore::empty();
+
+        for path in cfg.certificates {
+            for cert in utils::load_certs(path)? {
+                certs.add(&cert)?;
+            }
+        }
+
+        if !cfg.disable_native_certs {
+            for cert in rustls_native_certs::load_native_certs()? {
+                let _ = certs.add(&rustls::Certificate(cert.0));
+            }
+        }
+
+        let mut crypto = RustlsClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&version::TLS13])
+            .unwrap()
+            .with_root_certificates(certs)
+            .with_no_client_auth();
+
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in cfg.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::InvalidMasqueConfig("invalid header name"))?;
+            let value = HeaderValue::from_str(&value)
+                .map_err(|_| Error::InvalidMasqueConfig("invalid header value"))?;
+            headers.insert(name, value);
+        }
+
+        headers.insert("capsule-protocol", HeaderValue::from_static("?1"));
+
+        if !cfg.path.contains("{target_host}") || !cfg.path.contains("{target_port}") {
+            return Err(Error::InvalidMasqueConfig(
+                "`path` must contain `{target_host}` and `{target_port}`",
+            ));
+        }
+
+        Ok(Self {
+            server: cfg.server,
+            ip: cfg.ip,
+            path: cfg.path,
+            headers,
+            config: ClientConfig::new(Arc::new(crypto)),
+            conn: AsyncMutex::new(None),
+        })
+    }
+
+    /// Opens a CONNECT-UDP tunnel to the target through the proxy
+    pub async fn connect_udp(
+        &self,
+        addr: &Address,
+    ) -> Result<(TunnelSender, TunnelReceiver), Error> {
+        let (host, port) = match addr {
+            Address::None => return Err(Error::InvalidMasqueTarget),
+            // IPv6 addresses are percent-encoded in the URI template, as per RFC 9298
+            Address::SocketAddress(SocketAddr::V6(addr)) => {
+                (addr.ip().to_string().replace(':', "%3A"), addr.port())
+            }
+            Address::SocketAddress(SocketAddr::V4(addr)) => (addr.ip().to_string(), addr.port()),
+            Address::DomainAddress(domain, port) => (domain.clone(), *port),
+        };
+
+        let path = self
+            .path
+            .replace("{target_host}", &host)
+            .replace("{target_port}", &port.to_string());
+
+        let authority = match self.server.0.parse::<Ipv6Addr>() {
+            Ok(ip) => format!("[{ip}]:{}", self.server.1),
+            Err(_) => format!("{}:{}", self.server.0, self.server.1),
+        };
+
+        let uri = Uri::try_from(format!("https://{authority}{path}"))
+            .map_err(|_| Error::InvalidMasqueTarget)?;
+
+        let mut req = Request::builder()
+            .method(Method::CONNECT)
+            .uri(uri)
+            .extension(Protocol::CONNECT_UDP)
+            .body(())
+            .unwrap();
+
+        *req.headers_mut() = self.headers.clone();
+
+        let mut stream = self.send_request().await?.send_request(req).await?;
+        let resp = stream.recv_response().await?;
+
+        if !resp.status().is_success() {
+            return Err(Error::MasqueStatus(resp.status().as_u16()));
+        }
+
+        let (send, recv) = stream.split();
+
+        Ok((
+            TunnelSender(send),
+            TunnelReceiver {
+                stream: recv,
+                buf: BytesMut::new(),
+            },
+        ))
+    }
+
+    /// Returns the HTTP/3 connection to the proxy, reconnecting if it is closed
+    async fn send_request(&self) -> Result<SendRequest<OpenStreams, Bytes>, Error> {
+        let mut conn = self.conn.lock().await;
+
+        if let Some((quinn_conn, send_request)) = &*conn {
+            if quinn_conn.close_reason().is_none() {
+                return Ok(send_request.clone());
+            }
+        }
+
+        let addr = match self.ip {
+            Some(ip) => SocketAddr::from((ip, self.server.1)),
+            None => net::lookup_host((self.server.0.as_str(), self.server.1))
+                .await?
+                .next()
+                .ok_or(Error::MasqueDnsResolve)?,
+        };
+
+        let bind_addr = if addr.is_ipv4() {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+
+        // the endpoint is kept alive by the connection
+        let ep = Endpoint::client(bind_addr)?;
+        let quinn_conn = ep
+            .connect_with(self.config.clone(), addr, &self.server.0)?
+            .await?;
+
+        let (mut driver, send_request) =
+            h3::client::new(h3_quinn::Connection::new(quinn_conn.clone())).await?;
+
+        tokio::spawn(async move {
+            let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+        });
+
+        log::info!(
+            "[masque] connected to proxy {server}:{port}",
+            server = self.server.0,
+            port = self.server.1,
+        );
+
+        *conn = Some((quinn_conn, send_request.clone()));
+        Ok(send_request)
+    }
+}
+
+pub struct TunnelSender(RequestStream<SendStream<Bytes>, Bytes>);
+
+impl TunnelSender {
+    pub async fn send(&mut self, pkt: Bytes) -> Result<(), Error> {
+        let mut buf = BytesMut::with_capacity(16 + pkt.len());
+        put_varint(&mut buf, CAPSULE_DATAGRAM);
+        put_varint(&mut buf, varint_len(CONTEXT_ID_UDP) + pkt.len() as u64);
+        put_varint(&mut buf, CONTEXT_ID_UDP);
+        buf.put(pkt);
+
+        self.0.send_data(buf.freeze()).await?;
+        Ok(())
+    }
+}
+
+pub struct TunnelReceiver {
+    stream: RequestStream<RecvStream, Bytes>,
+    buf: BytesMut,
+}
+
+impl TunnelReceiver {
+    /// Receives a UDP packet from the target, or `None` if the tunnel is closed by the proxy
+    ///
+    /// Capsules of other types and datagrams with other context IDs are skipped.
+    pub async fn recv(&mut self) -> Result<Option<Bytes>, Error> {
+        loop {
+            while let Some((ty, mut value)) = self.next_capsule() {
+                if ty == CAPSULE_DATAGRAM && get_varint(&mut value) == Some(CONTEXT_ID_UDP) {
+                    return Ok(Some(value));
+                }
+            }
+
+            match self.stream.recv_data().await? {
+                Some(mut data) => {
+                    while data.has_remaining() {
+                        let chunk = data.chunk();
+                        let len = chunk.len();
+                        self.buf.extend_from_slice(chunk);
+                        data.advance(len);
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn next_capsule(&mut self) -> Option<(u64, Bytes)> {
+        let mut header = &self.buf[..];
+        let ty = get_varint(&mut header)?;
+        let len = get_varint(&mut header)?;
+        let header_len = self.buf.len() - header.len();
+
+        if (header.len() as u64) < len {
+            return None;
+        }
+
+        self.buf.advance(header_len);
+        Some((ty, self.buf.split_to(len as usize).freeze()))
+    }
+}
+
+fn varint_len(v: u64) -> u64 {
+    match v {
+        0..=0x3f => 1,
+        0x40..=0x3fff => 2,
+        0x4000..=0x3fff_ffff => 4,
+        _ => 8,
+    }
+}
+
+fn put_varint(buf: &mut BytesMut, v: u64) {
+    match varint_len(v) {
+        1 => buf.put_u8(v as u8),
+        2 => buf.put_u16(0x4000 | v as u16),
+        4 => buf.put_u32(0x8000_0000 | v as u32),
+        _ => buf.put_u64(0xc000_0000_0000_0000 | v),
+    }
+}
+
+/// Reads a QUIC variable-length integer, or returns `None` if the buffer is too short
+fn get_varint(buf: &mut impl Buf) -> Option<u64> {
+    if !buf.has_remaining() {
+        return None;
+    }
+
+    let len = 1 << (buf.chunk()[0] >> 6);
+
+    if buf.remaining() < len {
+        return None;
+    }
+
+    let v = match len {
+        1 => u64::from(buf.get_u8()),
+        2 => u64::from(buf.get_u16() & 0x3fff),
+        4 => u64::from(buf.get_u32() & 0x3fff_ffff),
+        _ => buf.get_u64() & 0x3fff_ffff_ffff_ffff,
+    };
+
+    Some(v)
+}
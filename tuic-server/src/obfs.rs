@@ -0,0 +1,179 @@
+use bytes::{BufMut, BytesMut};
+use quinn::{
+    udp::{RecvMeta, Transmit, UdpState},
+    AsyncTimer, AsyncUdpSocket, Runtime, TokioRuntime,
+};
+use ring::{
+    digest::{self, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{
+    future::Future,
+    io::{IoSliceMut, Result as IoResult},
+    net::{SocketAddr, UdpSocket as StdUdpSocket},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+
+/// The length of the random salt prefixed to every obfuscated datagram
+const SALT_LEN: usize = 8;
+
+/// The tokio runtime, with every UDP socket wrapped by [`ObfsUdpSocket`]
+///
+/// Sockets given to the endpoint later, e.g. when rebinding, are obfuscated as well.
+#[derive(Debug)]
+pub struct ObfsRuntime {
+    password: Arc<[u8]>,
+}
+
+impl ObfsRuntime {
+    pub fn new(password: Arc<[u8]>) -> Self {
+        Self { password }
+    }
+}
+
+impl Runtime for ObfsRuntime {
+    fn new_timer(&self, i: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        TokioRuntime.new_timer(i)
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        TokioRuntime.spawn(future)
+    }
+
+    fn wrap_udp_socket(&self, t: StdUdpSocket) -> IoResult<Box<dyn AsyncUdpSocket>> {
+        let socket = TokioRuntime.wrap_udp_socket(t)?;
+        Ok(Box::new(ObfsUdpSocket::new(socket, self.password.clone())))
+    }
+}
+
+/// A UDP socket obfuscating every datagram with a password shared with the server, so that no QUIC header is visible on the wire
+///
+/// Each datagram is prefixed with a random salt, and XORed with the SHA-256 digest of the password and the salt. This only defeats naive fingerprinting by middleboxes, as the QUIC packets are encrypted anyway.
+#[derive(Debug)]
+pub struct ObfsUdpSocket {
+    inner: Box<dyn AsyncUdpSocket>,
+    password: Arc<[u8]>,
+    rng: SystemRandom,
+}
+
+impl ObfsUdpSocket {
+    pub fn new(inner: Box<dyn AsyncUdpSocket>, password: Arc<[u8]>) -> Self {
+        Self {
+            inner,
+            password,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    fn key(&self, salt: &[u8]) -> digest::Digest {
+        let mut ctx = digest::Context::new(&SHA256);
+        ctx.update(&self.password);
+        ctx.update(salt);
+        ctx.finish()
+    }
+
+    fn obfuscate(&self, datagram: &[u8], buf: &mut BytesMut) {
+        let mut salt = [0; SALT_LEN];
+        let _ = self.rng.fill(&mut salt);
+        let key = self.key(&salt);
+
+        buf.put_slice(&salt);
+        buf.extend(
+            datagram
+                .iter()
+                .zip(key.as_ref().iter().cycle())
+                .map(|(b, k)| b ^ k),
+        );
+    }
+
+    /// Restores the datagram at `buf[start..end]` to `buf[dst..]`, returning its length
+    ///
+    /// `dst` must not be after `start`, so that datagrams received together can be packed in place.
+    fn deobfuscate(&self, buf: &mut [u8], start: usize, end: usize, dst: usize) -> Option<usize> {
+        if end - start <= SALT_LEN {
+            return None;
+        }
+
+        let key = self.key(&buf[start..start + SALT_LEN]);
+        let payload = start + SALT_LEN;
+
+        for (i, k) in (payload..end).zip(key.as_ref().iter().cycle()) {
+            buf[dst + i - payload] = buf[i] ^ k;
+        }
+
+        Some(end - payload)
+    }
+}
+
+impl AsyncUdpSocket for ObfsUdpSocket {
+    fn poll_send(
+        &self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<IoResult<usize>> {
+        let transmits = transmits
+            .iter()
+            .map(|transmit| {
+                let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+                let segments = transmit.contents.chunks(segment_size.max(1));
+                let mut buf =
+                    BytesMut::with_capacity(transmit.contents.len() + segments.len() * SALT_LEN);
+
+                for segment in segments {
+                    self.obfuscate(segment, &mut buf);
+                }
+
+                Transmit {
+                    destination: transmit.destination,
+                    ecn: transmit.ecn,
+                    contents: buf.freeze(),
+                    segment_size: transmit.segment_size.map(|size| size + SALT_LEN),
+                    src_ip: transmit.src_ip,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.inner.poll_send(state, cx, &transmits)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<IoResult<usize>> {
+        let count = ready!(self.inner.poll_recv(cx, bufs, meta))?;
+
+        for (buf, meta) in bufs.iter_mut().zip(meta.iter_mut()).take(count) {
+            let stride = meta.stride.max(1);
+            let mut len = 0;
+
+            // only the last one of datagrams received together can be shorter than the stride, so anything after an invalid one is dropped
+            for start in (0..meta.len).step_by(stride) {
+                let end = (start + stride).min(meta.len);
+
+                match self.deobfuscate(buf, start, end, len) {
+                    Some(n) => len += n,
+                    None => break,
+                }
+            }
+
+            meta.len = len;
+            meta.stride = stride.saturating_sub(SALT_LEN).max(1);
+        }
+
+        Poll::Ready(Ok(count))
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
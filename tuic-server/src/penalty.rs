@@ -0,0 +1,227 @@
+//! Slowing down and then banning the sources of malformed traffic, e.g. scanners probing the port
+//!
+//! Every command that fails to unmarshal or to reassemble, or is not allowed from the client is a strike against the IP address of its connection. The strikes of an address are forgotten once none was made for `window`. Past `delay_after` strikes, closing the connection on the error is delayed by `delay`, doubled for every further strike up to `max_delay`, so that probing gets slower. At `ban_after` strikes, the address is banned for `ban_duration`: its connection is closed at once, and every datagram from it is dropped by the endpoint before reaching QUIC, so no new connection can be made either.
+
+use crate::config::MalformedTraffic as MalformedTrafficConfig;
+use parking_lot::{Mutex, RwLock};
+use quinn::{
+    udp::{RecvMeta, Transmit, UdpState},
+    AsyncTimer, AsyncUdpSocket, Runtime,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{IoSliceMut, Result as IoResult},
+    net::{IpAddr, SocketAddr, UdpSocket as StdUdpSocket},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// The number of addresses tracked, beyond which new ones are not tracked until old ones are forgotten
+const MAX_SOURCES: usize = 65536;
+
+#[derive(Debug)]
+pub struct Penalties {
+    window: Duration,
+    delay_after: u32,
+    delay: Duration,
+    max_delay: Duration,
+    ban_after: u32,
+    ban_duration: Duration,
+    sources: Mutex<HashMap<IpAddr, Source>>,
+    bans: RwLock<HashMap<IpAddr, Instant>>,
+    /// Checked first by the endpoint on every datagram, so that no lock is taken while there is no ban
+    any_ban: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Source {
+    strikes: u32,
+    last: Instant,
+}
+
+/// What to do with the connection making a strike
+pub enum Penalty {
+    None,
+    /// Delay closing the connection
+    Delay(Duration),
+    /// Close the connection at once, as the address is banned
+    Ban(Duration),
+}
+
+impl Penalties {
+    pub fn new(cfg: MalformedTrafficConfig) -> Self {
+        Self {
+            window: cfg.window,
+            delay_after: cfg.delay_after,
+            delay: cfg.delay,
+            max_delay: cfg.max_delay,
+            ban_after: cfg.ban_after,
+            ban_duration: cfg.ban_duration,
+            sources: Mutex::new(HashMap::new()),
+            bans: RwLock::new(HashMap::new()),
+            any_ban: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts a strike against the address
+    pub fn strike(&self, ip: IpAddr) -> Penalty {
+        let now = Instant::now();
+
+        let strikes = {
+            let mut sources = self.sources.lock();
+
+            if sources.len() >= MAX_SOURCES {
+                sources.retain(|_, source| now.duration_since(source.last) < self.window);
+            }
+
+            if sources.len() >= MAX_SOURCES && !sources.contains_key(&ip) {
+                return Penalty::None;
+            }
+
+            let source = sources.entry(ip).or_insert(Source {
+                strikes: 0,
+                last: now,
+            });
+
+            if now.duration_since(source.last) >= self.window {
+                source.strikes = 0;
+            }
+
+            source.strikes += 1;
+            source.last = now;
+            source.strikes
+        };
+
+        if self.ban_after > 0 && strikes >= self.ban_after {
+            self.sources.lock().remove(&ip);
+
+            let mut bans = self.bans.write();
+            bans.retain(|_, until| *until > now);
+            bans.insert(ip, now + self.ban_duration);
+            self.any_ban.store(true, Ordering::Release);
+
+            return Penalty::Ban(self.ban_duration);
+        }
+
+        if strikes > self.delay_after {
+            let doublings = (strikes - self.delay_after - 1).min(31);
+            let delay = self.delay.saturating_mul(1 << doublings);
+            return Penalty::Delay(delay.min(self.max_delay));
+        }
+
+        Penalty::None
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        if !self.any_ban.load(Ordering::Acquire) {
+            return false;
+        }
+
+        match self.bans.read().get(&ip) {
+            Some(until) if *until > Instant::now() => return true,
+            Some(_) => {}
+            None => return false,
+        }
+
+        let mut bans = self.bans.write();
+        let now = Instant::now();
+        bans.retain(|_, until| *until > now);
+        self.any_ban.store(!bans.is_empty(), Ordering::Release);
+        false
+    }
+}
+
+/// A runtime with every UDP socket wrapped by [`FilterUdpSocket`]
+#[derive(Debug)]
+pub struct FilterRuntime {
+    inner: Arc<dyn Runtime>,
+    penalties: Arc<Penalties>,
+}
+
+impl FilterRuntime {
+    pub fn new(inner: Arc<dyn Runtime>, penalties: Arc<Penalties>) -> Self {
+        Self { inner, penalties }
+    }
+}
+
+impl Runtime for FilterRuntime {
+    fn new_timer(&self, i: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        self.inner.new_timer(i)
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.inner.spawn(future)
+    }
+
+    fn wrap_udp_socket(&self, t: StdUdpSocket) -> IoResult<Box<dyn AsyncUdpSocket>> {
+        let socket = self.inner.wrap_udp_socket(t)?;
+        Ok(Box::new(FilterUdpSocket {
+            inner: socket,
+            penalties: self.penalties.clone(),
+        }))
+    }
+}
+
+/// A UDP socket dropping every datagram from the banned addresses
+#[derive(Debug)]
+pub struct FilterUdpSocket {
+    inner: Box<dyn AsyncUdpSocket>,
+    penalties: Arc<Penalties>,
+}
+
+impl AsyncUdpSocket for FilterUdpSocket {
+    fn poll_send(
+        &self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<IoResult<usize>> {
+        self.inner.poll_send(state, cx, transmits)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<IoResult<usize>> {
+        loop {
+            let count = ready!(self.inner.poll_recv(cx, bufs, meta))?;
+            let mut kept = 0;
+
+            // the datagrams kept are packed to the front
+            for idx in 0..count {
+                if self.penalties.is_banned(meta[idx].addr.ip()) {
+                    continue;
+                }
+
+                if kept != idx {
+                    let len = meta[idx].len;
+                    let (head, tail) = bufs.split_at_mut(idx);
+                    head[kept][..len].copy_from_slice(&tail[0][..len]);
+                    meta[kept] = meta[idx];
+                }
+
+                kept += 1;
+            }
+
+            if kept > 0 || count == 0 {
+                return Poll::Ready(Ok(kept));
+            }
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
@@ -0,0 +1,68 @@
+//! Switching stateless retries on while the server is flooded with new connections
+//!
+//! With a Retry, the server answers the first Initial of a connection with a token instead of starting the TLS handshake, and only proceeds once the client echoes the token from the same address. This costs every client a round trip, but spoofed sources never get past it, so it is only turned on once more than `auto_threshold` new connections arrive in a second, and off again after a quiet period.
+
+use quinn::ServerConfig;
+use std::time::{Duration, Instant};
+
+/// The period the new connections are counted over
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// How long the rate of new connections stays under the threshold before retries are turned off
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+pub struct AutoRetry {
+    cfg: ServerConfig,
+    threshold: u32,
+    window_start: Instant,
+    count: u32,
+    /// When the threshold was last exceeded, while retries are on
+    busy_at: Option<Instant>,
+}
+
+impl AutoRetry {
+    pub fn new(cfg: ServerConfig, threshold: u32) -> Self {
+        Self {
+            cfg,
+            threshold,
+            window_start: Instant::now(),
+            count: 0,
+            busy_at: None,
+        }
+    }
+
+    /// Counts a new connection, returning the server config to switch the endpoint to, if retries are to be turned on or off
+    pub fn count(&mut self) -> Option<ServerConfig> {
+        let now = Instant::now();
+
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.count = 0;
+        }
+
+        self.count += 1;
+
+        if self.count > self.threshold {
+            if self.busy_at.replace(now).is_none() {
+                log::warn!(
+                    "more than {} new connections per second, turning on stateless retry",
+                    self.threshold
+                );
+
+                self.cfg.use_retry(true);
+                return Some(self.cfg.clone());
+            }
+        } else if self
+            .busy_at
+            .map_or(false, |at| now.duration_since(at) >= COOLDOWN)
+        {
+            log::warn!("new connections calmed down, turning off stateless retry");
+
+            self.busy_at = None;
+            self.cfg.use_retry(false);
+            return Some(self.cfg.clone());
+        }
+
+        None
+    }
+}
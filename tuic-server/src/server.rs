@@ -1,47 +1,85 @@
 use crate::{
-    config::Config,
-    connection::{Connection, DEFAULT_CONCURRENT_STREAMS},
+    audit::AuditLog,
+    auth::{Auth, Tenants},
+    bridge::Bridge,
+    config::{Auth as AuthConfig, Config, OutboundTcp, Sni as SniConfig, Tokens as TokensConfig},
+    connect_limit::ConnectLimits,
+    connection::{Connection, Resumption, SocketPool},
     error::Error,
-    utils::{self, CongestionControl},
+    healthz::Healthz,
+    ipv6_source::Ipv6Source,
+    masque::Masque,
+    obfs::ObfsRuntime,
+    penalty::{FilterRuntime, Penalties},
+    retry::AutoRetry,
+    sni::CertResolver,
+    standby::Standby,
+    utils::{self, BadCommand, CongestionControl, RetryMode},
 };
+use parking_lot::Mutex;
 use quinn::{
     congestion::{BbrConfig, CubicConfig, NewRenoConfig},
-    Endpoint, EndpointConfig, IdleTimeout, ServerConfig, TokioRuntime, TransportConfig, VarInt,
+    Endpoint, EndpointConfig, IdleTimeout, Runtime, ServerConfig, TokioRuntime, TransportConfig,
+    VarInt,
 };
+use ring::hkdf;
 use rustls::{version, ServerConfig as RustlsServerConfig};
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 use std::{
     collections::HashMap,
+    fs,
     net::{SocketAddr, UdpSocket as StdUdpSocket},
-    sync::Arc,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
+use tokio::time;
+use tuic_quinn::{congestion::BrutalConfig, BadCommandPolicy, CloseCode, PreAuthPolicy};
 use uuid::Uuid;
 
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
 pub struct Server {
     ep: Endpoint,
-    users: Arc<HashMap<Uuid, Box<[u8]>>>,
+    users: Arc<Tenants>,
+    bandwidth: Option<u64>,
     udp_relay_ipv6: bool,
+    udp_relay_mode_switching: bool,
+    udp_compression: bool,
+    allow_bind: bool,
+    allow_bench: bool,
     zero_rtt_handshake: bool,
     auth_timeout: Duration,
     task_negotiation_timeout: Duration,
     max_external_pkt_size: usize,
+    max_pkt_size: u16,
+    max_concurrent_streams: u32,
+    pre_auth: PreAuthPolicy,
+    bad_command: BadCommandPolicy,
     gc_interval: Duration,
     gc_lifetime: Duration,
+    qlog_dir: Option<Arc<Path>>,
+    masque: Option<Arc<Masque>>,
+    socket_pool: Option<Arc<SocketPool>>,
+    connect_limits: Option<Arc<ConnectLimits>>,
+    outbound_tcp: OutboundTcp,
+    ipv6_source: Option<Arc<Ipv6Source>>,
+    audit_log: Option<Arc<AuditLog>>,
+    penalties: Option<Arc<Penalties>>,
+    auto_retry: Option<Mutex<AutoRetry>>,
+    bridge: Mutex<Option<Bridge>>,
+    healthz: Mutex<Option<Healthz>>,
+    serving: Arc<AtomicBool>,
+    resumption: Option<Arc<Resumption>>,
+    standby: Option<Arc<Standby>>,
 }
 
 impl Server {
     pub fn init(cfg: Config) -> Result<Self, Error> {
-        let certs = utils::load_certs(cfg.certificate)?;
-        let priv_key = utils::load_priv_key(cfg.private_key)?;
-
-        let mut crypto = RustlsServerConfig::builder()
-            .with_safe_default_cipher_suites()
-            .with_safe_default_kx_groups()
-            .with_protocol_versions(&[&version::TLS13])
-            .unwrap()
-            .with_no_client_auth()
-            .with_single_cert(certs, priv_key)?;
+        let mut crypto = Self::crypto(&cfg.certificate, &cfg.private_key, &cfg.sni)?;
 
         crypto.alpn_protocols = cfg.alpn;
         crypto.max_early_data_size = u32::MAX;
@@ -51,10 +89,12 @@ impl Server {
         let mut tp_cfg = TransportConfig::default();
 
         tp_cfg
-            .max_concurrent_bidi_streams(VarInt::from(DEFAULT_CONCURRENT_STREAMS))
-            .max_concurrent_uni_streams(VarInt::from(DEFAULT_CONCURRENT_STREAMS))
+            .max_concurrent_bidi_streams(VarInt::from(cfg.max_concurrent_streams))
+            .max_concurrent_uni_streams(VarInt::from(cfg.max_concurrent_streams))
             .send_window(cfg.send_window)
             .stream_receive_window(VarInt::from_u32(cfg.receive_window))
+            .initial_rtt(cfg.initial_rtt)
+            .datagram_receive_buffer_size(Some(cfg.datagram_receive_buffer_size))
             .max_idle_timeout(Some(
                 IdleTimeout::try_from(cfg.max_idle_time).map_err(|_| Error::InvalidMaxIdleTime)?,
             ));
@@ -69,10 +109,58 @@ impl Server {
             CongestionControl::Bbr => {
                 tp_cfg.congestion_controller_factory(Arc::new(BbrConfig::default()))
             }
+            CongestionControl::Brutal => {
+                let Some(bandwidth) = cfg.bandwidth.filter(|bw| *bw > 0) else {
+                    return Err(Error::InvalidCongestionControl(
+                        "`bandwidth` must be set to use `brutal`",
+                    ));
+                };
+
+                tp_cfg.congestion_controller_factory(BrutalConfig::new(bandwidth))
+            }
         };
 
+        // the window is already checked to be a valid `VarInt` when parsing the config
+        if let Some(window) = cfg.connection_receive_window {
+            tp_cfg.receive_window(VarInt::from_u64(window).unwrap());
+        }
+
         config.transport_config(Arc::new(tp_cfg));
 
+        let validation = cfg.address_validation;
+
+        if validation.token_lifetime.is_zero() {
+            return Err(Error::InvalidTransport(
+                "`address_validation.token_lifetime` cannot be zero",
+            ));
+        }
+
+        if validation.max_connections == 0 {
+            return Err(Error::InvalidTransport(
+                "`address_validation.max_connections` cannot be zero",
+            ));
+        }
+
+        config
+            .use_retry(matches!(validation.retry, RetryMode::Always))
+            .retry_token_lifetime(validation.token_lifetime)
+            .concurrent_connections(validation.max_connections);
+
+        // servers sharing the secret accept the retry tokens issued by each other, e.g. behind a load balancer
+        if let Some(secret) = validation.token_secret {
+            let key = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(secret.as_bytes());
+            config.token_key(Arc::new(key));
+        }
+
+        let auto_retry = matches!(validation.retry, RetryMode::Auto)
+            .then(|| Mutex::new(AutoRetry::new(config.clone(), validation.auto_threshold)));
+
+        let mut ep_cfg = EndpointConfig::default();
+
+        ep_cfg
+            .max_udp_payload_size(cfg.max_udp_payload_size)
+            .map_err(|_| Error::InvalidTransport("`max_udp_payload_size` is out of bounds"))?;
+
         let socket = {
             let domain = match cfg.server {
                 SocketAddr::V4(_) => Domain::IPV4,
@@ -95,47 +183,247 @@ impl Server {
             StdUdpSocket::from(socket)
         };
 
-        let ep = Endpoint::new(
-            EndpointConfig::default(),
-            Some(config),
-            socket,
-            Arc::new(TokioRuntime),
-        )?;
+        if let Some(dir) = &cfg.qlog_dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut runtime: Arc<dyn Runtime> = match cfg.obfs_password {
+            Some(password) => Arc::new(ObfsRuntime::new(Arc::from(password.into_bytes()))),
+            None => Arc::new(TokioRuntime),
+        };
+
+        let penalties = cfg
+            .malformed_traffic
+            .map(|cfg| Arc::new(Penalties::new(cfg)));
+
+        // the banned sources are dropped before reaching the endpoint
+        if let Some(penalties) = &penalties {
+            runtime = Arc::new(FilterRuntime::new(runtime, penalties.clone()));
+        }
+
+        let ep = Endpoint::new(ep_cfg, Some(config), socket, runtime)?;
+
+        let bridge = cfg
+            .bridge
+            .map(|bridge| Bridge::new(bridge, ep.local_addr()?))
+            .transpose()?;
+
+        let serving = Arc::new(AtomicBool::new(true));
+
+        let (resumption, standby) = match cfg.udp_resumption {
+            Some(cfg) => {
+                let standby = cfg
+                    .standby
+                    .map(|standby| Standby::new(standby, cfg.lifetime))
+                    .transpose()?
+                    .map(Arc::new);
+
+                let resumption = Resumption::new(cfg.lifetime, standby.clone());
+                (Some(Arc::new(resumption)), standby)
+            }
+            None => (None, None),
+        };
+
+        let ipv6_source = cfg.ipv6_source.map(Ipv6Source::new).transpose()?;
+
+        if let Some(ipv6_source) = &ipv6_source {
+            ipv6_source.check_addresses()?;
+        }
+
+        let healthz = cfg
+            .healthz
+            .map(|listen| Healthz::new(listen, serving.clone()))
+            .transpose()?;
 
         Ok(Self {
             ep,
-            users: Arc::new(cfg.users),
+            users: Arc::new(Self::tenants(cfg.users, cfg.tokens, cfg.auth, cfg.sni)?),
+            bandwidth: cfg.bandwidth,
             udp_relay_ipv6: cfg.udp_relay_ipv6,
+            udp_relay_mode_switching: cfg.udp_relay_mode_switching,
+            udp_compression: cfg.udp_compression,
+            allow_bind: cfg.allow_bind,
+            allow_bench: cfg.allow_bench,
             zero_rtt_handshake: cfg.zero_rtt_handshake,
             auth_timeout: cfg.auth_timeout,
             task_negotiation_timeout: cfg.task_negotiation_timeout,
             max_external_pkt_size: cfg.max_external_packet_size,
+            max_pkt_size: cfg.max_packet_size,
+            max_concurrent_streams: cfg.max_concurrent_streams,
+            pre_auth: PreAuthPolicy {
+                max_bytes: cfg.pre_auth.max_bytes.unwrap_or(usize::MAX),
+                reject_tasks: cfg.pre_auth.reject_tasks,
+            },
+            bad_command: match cfg.bad_command {
+                BadCommand::Close => BadCommandPolicy::Return,
+                BadCommand::Discard => BadCommandPolicy::Discard,
+                BadCommand::Tarpit => BadCommandPolicy::Tarpit,
+            },
             gc_interval: cfg.gc_interval,
             gc_lifetime: cfg.gc_lifetime,
+            qlog_dir: cfg.qlog_dir.map(Arc::from),
+            masque: cfg.masque.map(Masque::new).transpose()?.map(Arc::new),
+            socket_pool: cfg
+                .udp_socket_pool
+                .map(|pool| SocketPool::new(pool, cfg.udp_relay_ipv6, cfg.max_external_packet_size))
+                .transpose()?
+                .map(Arc::new),
+            connect_limits: cfg
+                .connect_limits
+                .map(ConnectLimits::new)
+                .transpose()?
+                .map(Arc::new),
+            outbound_tcp: cfg.outbound_tcp,
+            ipv6_source: ipv6_source.map(Arc::new),
+            audit_log: cfg.audit_log.map(AuditLog::new).transpose()?.map(Arc::new),
+            penalties,
+            auto_retry,
+            bridge: Mutex::new(bridge),
+            healthz: Mutex::new(healthz),
+            serving,
+            resumption,
+            standby,
         })
     }
 
+    /// Checks that the files the config refers to can be loaded, without binding any socket
+    pub fn check(cfg: Config) -> Result<(), Error> {
+        Self::crypto(&cfg.certificate, &cfg.private_key, &cfg.sni)?;
+        Self::tenants(cfg.users, cfg.tokens, cfg.auth, cfg.sni)?;
+        cfg.ipv6_source.map(Ipv6Source::new).transpose()?;
+        cfg.audit_log.as_ref().map(AuditLog::check).transpose()?;
+        cfg.masque.map(Masque::new).transpose()?;
+        cfg.udp_socket_pool
+            .as_ref()
+            .map(SocketPool::check)
+            .transpose()?;
+        cfg.connect_limits.map(ConnectLimits::new).transpose()?;
+
+        Ok(())
+    }
+
+    fn crypto(
+        certificate: &Path,
+        private_key: &Path,
+        sni: &HashMap<String, SniConfig>,
+    ) -> Result<RustlsServerConfig, Error> {
+        let builder = RustlsServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&version::TLS13])
+            .unwrap()
+            .with_no_client_auth();
+
+        let mut resolver = None;
+
+        for (name, sni) in sni {
+            match (&sni.certificate, &sni.private_key) {
+                (Some(cert), Some(key)) => resolver
+                    .get_or_insert(CertResolver::new(certificate, private_key)?)
+                    .add(name, cert, key)?,
+                (None, None) => {}
+                _ => {
+                    return Err(Error::InvalidSniConfig(
+                        name.clone(),
+                        "`certificate` and `private_key` must be set together",
+                    ))
+                }
+            }
+        }
+
+        let crypto = match resolver {
+            Some(resolver) => builder.with_cert_resolver(Arc::new(resolver)),
+            None => builder.with_single_cert(
+                utils::load_certs(certificate.to_path_buf())?,
+                utils::load_priv_key(private_key.to_path_buf())?,
+            )?,
+        };
+
+        Ok(crypto)
+    }
+
+    fn tenants(
+        users: HashMap<Uuid, Box<[u8]>>,
+        tokens: Option<TokensConfig>,
+        auth: Option<AuthConfig>,
+        sni: HashMap<String, SniConfig>,
+    ) -> Result<Tenants, Error> {
+        let by_name = sni
+            .into_iter()
+            .filter_map(|(name, sni)| Some((name, sni.users?)))
+            .collect();
+
+        Tenants::new(Auth::new(users, tokens, auth)?, by_name)
+    }
+
+    /// Closes all connections, telling the clients that the server is shutting down, and waits for the closing to be delivered
+    pub async fn shutdown(&self) {
+        log::warn!("server shutting down");
+        self.serving.store(false, Ordering::Relaxed);
+
+        let code = CloseCode::ShuttingDown;
+        self.ep.close(code.into(), code.to_string().as_bytes());
+
+        let _ = time::timeout(SHUTDOWN_TIMEOUT, self.ep.wait_idle()).await;
+    }
+
     pub async fn start(&self) {
         log::warn!(
             "server started, listening on {}",
             self.ep.local_addr().unwrap()
         );
 
+        if let Some(bridge) = self.bridge.lock().take() {
+            tokio::spawn(bridge.start());
+        }
+
+        if let Some(healthz) = self.healthz.lock().take() {
+            tokio::spawn(healthz.start());
+        }
+
+        if let Some(standby) = self.standby.clone() {
+            tokio::spawn(async move { standby.start().await });
+        }
+
         loop {
             let Some(conn) = self.ep.accept().await else {
                 return;
             };
 
+            if let Some(auto_retry) = &self.auto_retry {
+                if let Some(cfg) = auto_retry.lock().count() {
+                    self.ep.set_server_config(Some(cfg));
+                }
+            }
+
             tokio::spawn(Connection::handle(
                 conn,
                 self.users.clone(),
+                self.bandwidth,
                 self.udp_relay_ipv6,
+                self.udp_relay_mode_switching,
+                self.udp_compression,
+                self.allow_bind,
+                self.allow_bench,
                 self.zero_rtt_handshake,
                 self.auth_timeout,
                 self.task_negotiation_timeout,
                 self.max_external_pkt_size,
+                self.max_pkt_size,
+                self.max_concurrent_streams,
+                self.pre_auth,
+                self.bad_command.clone(),
                 self.gc_interval,
                 self.gc_lifetime,
+                self.qlog_dir.clone(),
+                self.masque.clone(),
+                self.socket_pool.clone(),
+                self.connect_limits.clone(),
+                self.outbound_tcp,
+                self.ipv6_source.clone(),
+                self.audit_log.clone(),
+                self.penalties.clone(),
+                self.resumption.clone(),
             ));
         }
     }
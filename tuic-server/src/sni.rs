@@ -0,0 +1,70 @@
+//! Hosting several endpoints on one server, told apart by the SNI of the clients
+//!
+//! Each server name in `sni` may have a certificate of its own, presented to the clients connecting with that name instead of the default one, and users of its own, replacing the users of the config for those clients. A name `*.example.com` matches any single label in place of the `*`, while exact names take precedence. Clients with other names, or without SNI, get the default certificate and users.
+
+use crate::{error::Error, utils};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Error as RustlsError,
+};
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+/// Looks up a server name in a map keyed by lowercase names, trying the wildcard of the parent domain if there is no exact match
+pub fn lookup<'a, T>(map: &'a HashMap<String, T>, name: &str) -> Option<&'a T> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+    map.get(&name).or_else(|| {
+        let (_, parent) = name.split_once('.')?;
+        map.get(&format!("*.{parent}"))
+    })
+}
+
+/// Picks the certificate by the SNI of the client
+pub struct CertResolver {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn new(certificate: &Path, private_key: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            default: Arc::new(load(certificate, private_key)?),
+            by_name: HashMap::new(),
+        })
+    }
+
+    pub fn add(&mut self, name: &str, certificate: &Path, private_key: &Path) -> Result<(), Error> {
+        let key = load(certificate, private_key)?;
+        self.by_name
+            .insert(name.to_ascii_lowercase(), Arc::new(key));
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let key = hello
+            .server_name()
+            .and_then(|name| lookup(&self.by_name, name))
+            .unwrap_or(&self.default);
+
+        Some(key.clone())
+    }
+}
+
+fn load(certificate: &Path, private_key: &Path) -> Result<CertifiedKey, Error> {
+    let certs = utils::load_certs(certificate.to_path_buf())?;
+    let priv_key = utils::load_priv_key(private_key.to_path_buf())?;
+
+    let key = sign::any_supported_type(&priv_key)
+        .map_err(|_| RustlsError::General(String::from("invalid private key")))?;
+
+    let key = CertifiedKey::new(certs, key);
+
+    // an empty chain would only fail the handshakes
+    key.end_entity_cert()
+        .map_err(|_| RustlsError::General(String::from("no certificate")))?;
+
+    Ok(key)
+}
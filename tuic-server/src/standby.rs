@@ -0,0 +1,259 @@
+//! Experimental replication of the UDP resumption state between a pair of servers, for hot-standby failover
+//!
+//! Each server of the pair sends the resumption tokens it issues, with the user and the association IDs of the UDP sessions behind them, to the other over a TCP side channel, and keeps those received from the other as replicas. A client failing over to the other server, e.g. behind a floating IP, resumes with its token there, and gets its association IDs back on fresh relay sockets instead of associating again. The relay sockets themselves cannot be moved, so the addresses seen by the targets change.
+//!
+//! The side channel is authenticated by a shared secret, but not encrypted, so it should be on a private network. Messages are JSON, one per line.
+
+use crate::{config::Standby as StandbyConfig, error::Error};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError, Sender},
+    time,
+};
+use uuid::Uuid;
+
+/// How long to wait before connecting to the peer again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the peer has to authenticate after connecting
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of updates buffered for the peer. The whole state is sent again when the link falls behind
+const UPDATE_CAPACITY: usize = 1024;
+
+pub struct Standby {
+    listener: Mutex<Option<TcpListener>>,
+    peer: SocketAddr,
+    secret: String,
+    /// How long the replicas are kept after the link from the peer is lost, the same as the lifetime of parked sessions
+    lifetime: Duration,
+    /// The tokens issued by this server, sent to the peer in full on connecting
+    local: Mutex<HashMap<Uuid, Session>>,
+    replicas: Mutex<HashMap<Uuid, Replica>>,
+    updates: Sender<Message>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Session {
+    user: Uuid,
+    assoc_ids: Vec<u16>,
+}
+
+struct Replica {
+    session: Session,
+    /// Set once the link from the peer is lost
+    expires: Option<Instant>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Hello {
+        secret: String,
+    },
+    Session {
+        token: Uuid,
+        #[serde(flatten)]
+        session: Session,
+    },
+    Drop {
+        token: Uuid,
+    },
+}
+
+impl Standby {
+    pub fn new(cfg: StandbyConfig, lifetime: Duration) -> Result<Self, Error> {
+        let listener = StdTcpListener::bind(cfg.listen)
+            .map_err(|err| Error::Socket("failed to bind standby listener", err))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| Error::Socket("failed to bind standby listener", err))?;
+
+        Ok(Self {
+            listener: Mutex::new(Some(TcpListener::from_std(listener)?)),
+            peer: cfg.peer,
+            secret: cfg.secret,
+            lifetime,
+            local: Mutex::new(HashMap::new()),
+            replicas: Mutex::new(HashMap::new()),
+            updates: broadcast::channel(UPDATE_CAPACITY).0,
+        })
+    }
+
+    /// Records the UDP sessions behind a token issued by this server, replacing those recorded before
+    pub fn track(&self, token: [u8; 16], user: Uuid, assoc_ids: Vec<u16>) {
+        let token = Uuid::from_bytes(token);
+        let session = Session { user, assoc_ids };
+
+        self.local.lock().insert(token, session.clone());
+        let _ = self.updates.send(Message::Session { token, session });
+    }
+
+    /// Forgets a token issued by this server, once it is resumed or expires
+    pub fn untrack(&self, token: [u8; 16]) {
+        let token = Uuid::from_bytes(token);
+
+        if self.local.lock().remove(&token).is_some() {
+            let _ = self.updates.send(Message::Drop { token });
+        }
+    }
+
+    /// Takes the association IDs behind a token issued by the peer, if it belongs to the user
+    pub fn take(&self, token: [u8; 16], user: Uuid) -> Option<Vec<u16>> {
+        let token = Uuid::from_bytes(token);
+        let mut replicas = self.replicas.lock();
+        let replica = replicas.get(&token)?;
+
+        if replica.session.user != user
+            || replica.expires.map_or(false, |exp| exp <= Instant::now())
+        {
+            return None;
+        }
+
+        replicas
+            .remove(&token)
+            .map(|replica| replica.session.assoc_ids)
+    }
+
+    pub async fn start(&self) {
+        let Some(listener) = self.listener.lock().take() else {
+            return;
+        };
+
+        log::warn!(
+            "[standby] listening on {}, replicating to {}",
+            listener.local_addr().unwrap(),
+            self.peer
+        );
+
+        tokio::join!(self.accept(listener), self.replicate());
+    }
+
+    /// Receives the tokens of the peer
+    async fn accept(&self, listener: TcpListener) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    log::warn!("[standby] failed accepting connection: {err}");
+                    continue;
+                }
+            };
+
+            match self.receive(stream).await {
+                Ok(()) => log::warn!("[standby] [{addr}] link from peer closed"),
+                Err(err) => log::warn!("[standby] [{addr}] link from peer lost: {err}"),
+            }
+
+            // the replicas outlive the link for as long as parked sessions, in case the peer failed
+            let expires = Instant::now() + self.lifetime;
+            let mut replicas = self.replicas.lock();
+            replicas.retain(|_, replica| replica.expires.map_or(true, |exp| exp > Instant::now()));
+
+            for replica in replicas.values_mut() {
+                replica.expires.get_or_insert(expires);
+            }
+        }
+    }
+
+    async fn receive(&self, stream: TcpStream) -> Result<(), Error> {
+        let mut lines = BufReader::new(stream).lines();
+
+        let hello = time::timeout(HELLO_TIMEOUT, lines.next_line())
+            .await
+            .map_err(|_| Error::StandbyAuthFailed)??;
+
+        match hello.map(|line| serde_json::from_str(&line)) {
+            Some(Ok(Message::Hello { secret })) if secret == self.secret => {}
+            _ => return Err(Error::StandbyAuthFailed),
+        }
+
+        log::warn!("[standby] link from peer established");
+
+        // the peer sends all its tokens again on connecting
+        self.replicas.lock().clear();
+
+        while let Some(line) = lines.next_line().await? {
+            match serde_json::from_str(&line) {
+                Ok(Message::Session { token, session }) => {
+                    let replica = Replica {
+                        session,
+                        expires: None,
+                    };
+                    self.replicas.lock().insert(token, replica);
+                }
+                Ok(Message::Drop { token }) => {
+                    self.replicas.lock().remove(&token);
+                }
+                Ok(Message::Hello { .. }) => {}
+                Err(err) => log::debug!("[standby] invalid message from peer: {err}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the tokens of this server to the peer, connecting again whenever the link is lost
+    async fn replicate(&self) {
+        loop {
+            match self.send().await {
+                Ok(()) => {}
+                Err(err) => log::debug!("[standby] link to peer {}: {err}", self.peer),
+            }
+
+            time::sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+
+    async fn send(&self) -> Result<(), Error> {
+        let mut stream = TcpStream::connect(self.peer).await?;
+        stream.set_nodelay(true)?;
+
+        // subscribed before taking the snapshot, so no update in between is missed
+        let mut updates = self.updates.subscribe();
+
+        let snapshot = self
+            .local
+            .lock()
+            .iter()
+            .map(|(token, session)| Message::Session {
+                token: *token,
+                session: session.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let hello = Message::Hello {
+            secret: self.secret.clone(),
+        };
+
+        for msg in [hello].into_iter().chain(snapshot) {
+            write(&mut stream, &msg).await?;
+        }
+
+        log::warn!("[standby] link to peer {} established", self.peer);
+
+        loop {
+            match updates.recv().await {
+                Ok(msg) => write(&mut stream, &msg).await?,
+                // start over with the whole state
+                Err(RecvError::Lagged(_)) => return Ok(()),
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
+
+async fn write(stream: &mut TcpStream, msg: &Message) -> Result<(), Error> {
+    let mut line = serde_json::to_vec(msg).unwrap();
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    Ok(())
+}
@@ -4,6 +4,7 @@ use std::{
     fmt::{Display, Formatter, Result as FmtResult},
     fs::{self, File},
     io::{BufReader, Error as IoError},
+    net::IpAddr,
     path::PathBuf,
     str::FromStr,
 };
@@ -41,7 +42,7 @@ pub fn load_priv_key(path: PathBuf) -> Result<PrivateKey, IoError> {
         .map(PrivateKey)
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum UdpRelayMode {
     Native,
     Quic,
@@ -56,10 +57,186 @@ impl Display for UdpRelayMode {
     }
 }
 
+/// What to do with a command received where it is not expected
+pub enum BadCommand {
+    Close,
+    Discard,
+    Tarpit,
+}
+
+impl FromStr for BadCommand {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("close") {
+            Ok(Self::Close)
+        } else if s.eq_ignore_ascii_case("discard") {
+            Ok(Self::Discard)
+        } else if s.eq_ignore_ascii_case("tarpit") {
+            Ok(Self::Tarpit)
+        } else {
+            Err("invalid bad command policy")
+        }
+    }
+}
+
+/// When new connections are asked to validate their addresses with a Retry before the handshake
+#[derive(Clone, Copy)]
+pub enum RetryMode {
+    Never,
+    Always,
+    /// Only while new connections arrive faster than `address_validation.auto_threshold`
+    Auto,
+}
+
+impl FromStr for RetryMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("never") {
+            Ok(Self::Never)
+        } else if s.eq_ignore_ascii_case("always") {
+            Ok(Self::Always)
+        } else if s.eq_ignore_ascii_case("auto") {
+            Ok(Self::Auto)
+        } else {
+            Err("invalid retry mode")
+        }
+    }
+}
+
+/// How the source address of outbound relays to IPv6 targets is picked among `ipv6_source.addresses`
+#[derive(Clone, Copy)]
+pub enum Ipv6SourcePolicy {
+    /// The same address for every relay of a user
+    PerUser,
+    /// The next address for every relay
+    Rotate,
+}
+
+impl FromStr for Ipv6SourcePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("per_user") {
+            Ok(Self::PerUser)
+        } else if s.eq_ignore_ascii_case("rotate") {
+            Ok(Self::Rotate)
+        } else {
+            Err("invalid IPv6 source policy")
+        }
+    }
+}
+
+/// An IPv4 or IPv6 network. A bare address is parsed as a single-address network
+#[derive(Clone, Copy)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(addr)) => addr
+                .to_ipv4_mapped()
+                .map_or(false, |addr| self.contains(IpAddr::V4(addr))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| "invalid IP CIDR address")?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or("invalid IP CIDR prefix length")?,
+            None => max_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// The destination of a relayed connection that a limit applies to: a network, or a domain with its subdomains
+pub enum Destination {
+    Cidr(IpCidr),
+    Domain(String),
+}
+
+impl Destination {
+    /// Whether the destination covers a target, given by the domain it was requested with, if any, and the address connected to
+    pub fn matches(&self, domain: Option<&str>, ip: IpAddr) -> bool {
+        match self {
+            Self::Cidr(cidr) => cidr.contains(ip),
+            Self::Domain(suffix) => domain.map_or(false, |domain| {
+                let domain = domain.trim_end_matches('.').as_bytes();
+
+                match domain.len().checked_sub(suffix.len()) {
+                    Some(0) => domain.eq_ignore_ascii_case(suffix.as_bytes()),
+                    Some(start) => {
+                        domain[start - 1] == b'.'
+                            && domain[start..].eq_ignore_ascii_case(suffix.as_bytes())
+                    }
+                    None => false,
+                }
+            }),
+        }
+    }
+}
+
+impl FromStr for Destination {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(cidr) = s.parse() {
+            return Ok(Self::Cidr(cidr));
+        }
+
+        if s.contains(['/', ':']) {
+            return Err("invalid destination network");
+        }
+
+        let domain = s.trim_end_matches('.');
+
+        if domain.is_empty() {
+            Err("empty destination domain")
+        } else {
+            Ok(Self::Domain(domain.to_ascii_lowercase()))
+        }
+    }
+}
+
 pub enum CongestionControl {
     Cubic,
     NewReno,
     Bbr,
+    Brutal,
 }
 
 impl FromStr for CongestionControl {
@@ -72,6 +249,8 @@ impl FromStr for CongestionControl {
             Ok(Self::NewReno)
         } else if s.eq_ignore_ascii_case("bbr") {
             Ok(Self::Bbr)
+        } else if s.eq_ignore_ascii_case("brutal") {
+            Ok(Self::Brutal)
         } else {
             Err("invalid congestion control")
         }
@@ -0,0 +1,143 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::{io::Cursor, net::SocketAddr};
+use tuic::{model::Connection, Address, Connect, Header, Packet};
+
+const MAX_PKT_SIZE: usize = 1200;
+const PAYLOAD_SIZES: [usize; 3] = [512, 8 * 1024, 60 * 1024];
+
+fn headers() -> [(&'static str, Header); 3] {
+    [
+        (
+            "connect_domain",
+            Header::Connect(Connect::new(Address::DomainAddress(
+                String::from("www.example.com"),
+                443,
+            ))),
+        ),
+        (
+            "packet_ipv4",
+            Header::Packet(Packet::new(
+                1,
+                2,
+                3,
+                0,
+                1024,
+                Address::SocketAddress(SocketAddr::from(([8, 8, 8, 8], 53))),
+            )),
+        ),
+        (
+            "packet_none",
+            Header::Packet(Packet::new(1, 2, 3, 1, 1024, Address::None)),
+        ),
+    ]
+}
+
+fn marshal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("marshal");
+
+    for (name, header) in headers() {
+        group.bench_function(name, |b| {
+            let mut buf = BytesMut::with_capacity(header.len());
+
+            b.iter(|| {
+                buf.clear();
+                header.write(&mut buf);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn unmarshal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unmarshal");
+
+    for (name, header) in headers() {
+        let mut buf = Vec::new();
+        header.marshal(&mut buf).unwrap();
+
+        group.bench_function(name, |b| {
+            b.iter(|| Header::unmarshal(&mut Cursor::new(&buf)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn fragment(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fragment");
+    let conn = Connection::<Bytes>::new();
+    let addr = Address::SocketAddress(SocketAddr::from(([8, 8, 8, 8], 53)));
+
+    for size in PAYLOAD_SIZES {
+        let payload = vec![0; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            let mut buf = BytesMut::with_capacity(MAX_PKT_SIZE);
+
+            b.iter(|| {
+                let pkt = conn.send_packet(0, addr.clone(), MAX_PKT_SIZE);
+
+                for (header, frag) in pkt.into_fragments(payload) {
+                    buf.clear();
+                    header.write(&mut buf);
+                    buf.extend_from_slice(frag);
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn reassemble(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reassemble");
+    let addr = Address::SocketAddress(SocketAddr::from(([8, 8, 8, 8], 53)));
+
+    for size in PAYLOAD_SIZES {
+        let payload = vec![0; size];
+
+        let frags = Connection::<Bytes>::new()
+            .send_packet(0, addr.clone(), MAX_PKT_SIZE)
+            .into_fragments(&payload)
+            .map(|(header, frag)| {
+                let Header::Packet(header) = header else {
+                    unreachable!()
+                };
+
+                (header, Bytes::copy_from_slice(frag))
+            })
+            .collect::<Vec<_>>();
+
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &frags, |b, frags| {
+            let conn = Connection::<Bytes>::new();
+
+            b.iter_batched(
+                || frags.clone(),
+                |frags| {
+                    let mut buf = Vec::with_capacity(size);
+
+                    for (header, frag) in frags {
+                        let pkt = conn.recv_packet_unrestricted(header);
+
+                        if let Some(pkt) = pkt.assemble(frag).unwrap() {
+                            pkt.assemble(&mut buf);
+                        }
+                    }
+
+                    buf
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, marshal, unmarshal, fragment, reassemble);
+criterion_main!(benches);
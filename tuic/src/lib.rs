@@ -3,7 +3,8 @@
 mod protocol;
 
 pub use self::protocol::{
-    Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION,
+    Address, AddressError, Authenticate, Bandwidth, Bind, BindUdp, CongestionHint, Connect,
+    Dissociate, DissociateAck, Echo, Header, Heartbeat, Packet, Parity, Resume, VERSION,
 };
 
 #[cfg(any(feature = "async_marshal", feature = "marshal"))]
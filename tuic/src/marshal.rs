@@ -1,5 +1,9 @@
-use crate::{Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION};
+use crate::{
+    Address, Authenticate, Bandwidth, Bind, BindUdp, Connect, Dissociate, DissociateAck, Echo,
+    Header, Heartbeat, Packet, Parity, Resume, VERSION,
+};
 use bytes::{BufMut, BytesMut};
+#[cfg(feature = "async_marshal")]
 use futures_util::{AsyncWrite, AsyncWriteExt};
 use std::{
     io::{Error as IoError, Write},
@@ -34,6 +38,12 @@ impl Header {
             Self::Packet(packet) => packet.write(buf),
             Self::Dissociate(dissociate) => dissociate.write(buf),
             Self::Heartbeat(heartbeat) => heartbeat.write(buf),
+            Self::BindUdp(bind) => bind.write(buf),
+            Self::DissociateAck(ack) => ack.write(buf),
+            Self::Resume(resume) => resume.write(buf),
+            Self::Bind(bind) => bind.write(buf),
+            Self::Echo(echo) => echo.write(buf),
+            Self::Parity(parity) => parity.write(buf),
         }
     }
 }
@@ -67,11 +77,33 @@ impl Authenticate {
     fn write(&self, buf: &mut impl BufMut) {
         buf.put_slice(self.uuid().as_ref());
         buf.put_slice(&self.token());
+
+        if let Some(bandwidth) = self.bandwidth() {
+            buf.put_u16(Bandwidth::EXTENSION_TYPE);
+            buf.put_u16(Bandwidth::len() as u16);
+            buf.put_u64(bandwidth.up());
+            buf.put_u64(bandwidth.down());
+        }
+
+        if self.fec() {
+            buf.put_u16(Self::FEC_EXTENSION_TYPE);
+            buf.put_u16(0);
+        }
+
+        if let Some(min_size) = self.compression() {
+            buf.put_u16(Self::COMPRESSION_EXTENSION_TYPE);
+            buf.put_u16(2);
+            buf.put_u16(min_size);
+        }
     }
 }
 
 impl Connect {
     fn write(&self, buf: &mut impl BufMut) {
+        if let Some(hint) = self.hint() {
+            buf.put_u8(hint.code());
+        }
+
         self.addr().write(buf);
     }
 }
@@ -87,6 +119,17 @@ impl Packet {
     }
 }
 
+impl Parity {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.assoc_id());
+        buf.put_u16(self.pkt_id());
+        buf.put_u8(self.frag_total());
+        buf.put_u16(self.pkt_size());
+        buf.put_u16(self.size());
+        self.addr().write(buf);
+    }
+}
+
 impl Dissociate {
     fn write(&self, buf: &mut impl BufMut) {
         buf.put_u16(self.assoc_id());
@@ -96,3 +139,41 @@ impl Dissociate {
 impl Heartbeat {
     fn write(&self, _buf: &mut impl BufMut) {}
 }
+
+impl BindUdp {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.assoc_id());
+        self.addr().write(buf);
+    }
+}
+
+impl Bind {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.bind_id());
+        self.addr().write(buf);
+    }
+}
+
+impl DissociateAck {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.assoc_id());
+    }
+}
+
+impl Resume {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_slice(&self.token());
+        buf.put_u16(self.assoc_ids().len() as u16);
+
+        for assoc_id in self.assoc_ids() {
+            buf.put_u16(*assoc_id);
+        }
+    }
+}
+
+impl Echo {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.payload().len() as u16);
+        buf.put_slice(self.payload());
+    }
+}
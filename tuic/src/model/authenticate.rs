@@ -1,5 +1,5 @@
 use super::side::{self, Side};
-use crate::{Authenticate as AuthenticateHeader, Header};
+use crate::{Authenticate as AuthenticateHeader, Bandwidth, Header};
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 use uuid::Uuid;
 
@@ -18,13 +18,36 @@ impl Authenticate<side::Tx> {
         uuid: Uuid,
         password: impl AsRef<[u8]>,
         exporter: &impl KeyingMaterialExporter,
-    ) -> Self {
+        bandwidth: Option<Bandwidth>,
+        fec: bool,
+    ) -> Option<Self> {
+        let token = exporter.export_keying_material(uuid.as_ref(), password.as_ref())?;
+
+        let mut header = match bandwidth {
+            Some(bandwidth) => AuthenticateHeader::with_bandwidth(uuid, token, bandwidth),
+            None => AuthenticateHeader::new(uuid, token),
+        };
+
+        if fec {
+            header = header.with_fec();
+        }
+
+        Some(Self {
+            inner: Side::Tx(Tx {
+                header: Header::Authenticate(header),
+            }),
+            _marker: side::Tx,
+        })
+    }
+
+    /// Declares that the client accepts compressed UDP packets, and compresses its own of at least `min_size` bytes once the server acknowledges compression
+    pub fn with_compression(self, min_size: u16) -> Self {
+        let Side::Tx(tx) = self.inner else { unreachable!() };
+        let Header::Authenticate(header) = tx.header else { unreachable!() };
+
         Self {
             inner: Side::Tx(Tx {
-                header: Header::Authenticate(AuthenticateHeader::new(
-                    uuid,
-                    exporter.export_keying_material(uuid.as_ref(), password.as_ref()),
-                )),
+                header: Header::Authenticate(header.with_compression(min_size)),
             }),
             _marker: side::Tx,
         }
@@ -49,12 +72,27 @@ impl Debug for Authenticate<side::Tx> {
 struct Rx {
     uuid: Uuid,
     token: [u8; 32],
+    bandwidth: Option<Bandwidth>,
+    fec: bool,
+    compression: Option<u16>,
 }
 
 impl Authenticate<side::Rx> {
-    pub(super) fn new(uuid: Uuid, token: [u8; 32]) -> Self {
+    pub(super) fn new(
+        uuid: Uuid,
+        token: [u8; 32],
+        bandwidth: Option<Bandwidth>,
+        fec: bool,
+        compression: Option<u16>,
+    ) -> Self {
         Self {
-            inner: Side::Rx(Rx { uuid, token }),
+            inner: Side::Rx(Rx {
+                uuid,
+                token,
+                bandwidth,
+                fec,
+                compression,
+            }),
             _marker: side::Rx,
         }
     }
@@ -71,14 +109,36 @@ impl Authenticate<side::Rx> {
         rx.token
     }
 
+    /// Returns the bandwidth declared by the peer
+    pub fn bandwidth(&self) -> Option<Bandwidth> {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.bandwidth
+    }
+
+    /// Returns whether the peer accepts `Parity` commands
+    pub fn fec(&self) -> bool {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.fec
+    }
+
+    /// Returns the minimum size of UDP packets to compress, if the peer declared compression
+    pub fn compression(&self) -> Option<u16> {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.compression
+    }
+
     /// Returns whether the token is valid
+    ///
+    /// The token is never valid if the keying material can not be exported.
     pub fn is_valid(
         &self,
         password: impl AsRef<[u8]>,
         exporter: &impl KeyingMaterialExporter,
     ) -> bool {
         let Side::Rx(rx) = &self.inner else { unreachable!() };
-        rx.token == exporter.export_keying_material(rx.uuid.as_ref(), password.as_ref())
+        exporter
+            .export_keying_material(rx.uuid.as_ref(), password.as_ref())
+            .map_or(false, |token| token == rx.token)
     }
 }
 
@@ -88,12 +148,15 @@ impl Debug for Authenticate<side::Rx> {
         f.debug_struct("Authenticate")
             .field("uuid", &rx.uuid)
             .field("token", &rx.token)
+            .field("bandwidth", &rx.bandwidth)
+            .field("fec", &rx.fec)
+            .field("compression", &rx.compression)
             .finish()
     }
 }
 
 /// The trait for exporting keying material
 pub trait KeyingMaterialExporter {
-    /// Exports keying material
-    fn export_keying_material(&self, label: &[u8], context: &[u8]) -> [u8; 32];
+    /// Exports keying material, or `None` if it can not be exported
+    fn export_keying_material(&self, label: &[u8], context: &[u8]) -> Option<[u8; 32]>;
 }
@@ -0,0 +1,75 @@
+use super::side::{self, Side};
+use crate::{Address, Bind as BindHeader, Header};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `Bind` command
+pub struct Bind<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl Bind<side::Tx> {
+    pub(super) fn new(bind_id: u16, addr: Address) -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::Bind(BindHeader::new(bind_id, addr)),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `Bind` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for Bind<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("Bind")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx {
+    bind_id: u16,
+    addr: Address,
+}
+
+impl Bind<side::Rx> {
+    pub(super) fn new(bind_id: u16, addr: Address) -> Self {
+        Self {
+            inner: Side::Rx(Rx { bind_id, addr }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Returns the TCP binding ID
+    pub fn bind_id(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.bind_id
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        &rx.addr
+    }
+}
+
+impl Debug for Bind<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("Bind")
+            .field("bind_id", &rx.bind_id)
+            .field("addr", &rx.addr)
+            .finish()
+    }
+}
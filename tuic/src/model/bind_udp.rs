@@ -0,0 +1,75 @@
+use super::side::{self, Side};
+use crate::{Address, BindUdp as BindUdpHeader, Header};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `BindUdp` command
+pub struct BindUdp<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl BindUdp<side::Tx> {
+    pub(super) fn new(assoc_id: u16, addr: Address) -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::BindUdp(BindUdpHeader::new(assoc_id, addr)),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `BindUdp` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for BindUdp<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("BindUdp")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx {
+    assoc_id: u16,
+    addr: Address,
+}
+
+impl BindUdp<side::Rx> {
+    pub(super) fn new(assoc_id: u16, addr: Address) -> Self {
+        Self {
+            inner: Side::Rx(Rx { assoc_id, addr }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.assoc_id
+    }
+
+    /// Returns the address to bind, or `None` for any
+    pub fn addr(&self) -> &Address {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        &rx.addr
+    }
+}
+
+impl Debug for BindUdp<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("BindUdp")
+            .field("assoc_id", &rx.assoc_id)
+            .field("addr", &rx.addr)
+            .finish()
+    }
+}
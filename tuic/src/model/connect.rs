@@ -1,6 +1,8 @@
-use super::side::{self, Side};
-use crate::{Address, Connect as ConnectHeader, Header};
-use register_count::Register;
+use super::{
+    metrics::TaskRegister,
+    side::{self, Side},
+};
+use crate::{Address, CongestionHint, Connect as ConnectHeader, Header};
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 
 /// The model of the `Connect` command
@@ -11,14 +13,23 @@ pub struct Connect<M> {
 
 struct Tx {
     header: Header,
-    _task_reg: Register,
+    _task_reg: TaskRegister,
 }
 
 impl Connect<side::Tx> {
-    pub(super) fn new(task_reg: Register, addr: Address) -> Self {
+    pub(super) fn new(
+        task_reg: TaskRegister,
+        addr: Address,
+        hint: Option<CongestionHint>,
+    ) -> Self {
+        let header = match hint {
+            Some(hint) => ConnectHeader::with_hint(addr, hint),
+            None => ConnectHeader::new(addr),
+        };
+
         Self {
             inner: Side::Tx(Tx {
-                header: Header::Connect(ConnectHeader::new(addr)),
+                header: Header::Connect(header),
                 _task_reg: task_reg,
             }),
             _marker: side::Tx,
@@ -43,14 +54,20 @@ impl Debug for Connect<side::Tx> {
 
 struct Rx {
     addr: Address,
-    _task_reg: Register,
+    hint: Option<CongestionHint>,
+    _task_reg: TaskRegister,
 }
 
 impl Connect<side::Rx> {
-    pub(super) fn new(task_reg: Register, addr: Address) -> Self {
+    pub(super) fn new(
+        task_reg: TaskRegister,
+        addr: Address,
+        hint: Option<CongestionHint>,
+    ) -> Self {
         Self {
             inner: Side::Rx(Rx {
                 addr,
+                hint,
                 _task_reg: task_reg,
             }),
             _marker: side::Rx,
@@ -62,11 +79,20 @@ impl Connect<side::Rx> {
         let Side::Rx(rx) = &self.inner else { unreachable!() };
         &rx.addr
     }
+
+    /// Returns the congestion hint
+    pub fn hint(&self) -> Option<CongestionHint> {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.hint
+    }
 }
 
 impl Debug for Connect<side::Rx> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         let Side::Rx(rx) = &self.inner else { unreachable!() };
-        f.debug_struct("Connect").field("addr", &rx.addr).finish()
+        f.debug_struct("Connect")
+            .field("addr", &rx.addr)
+            .field("hint", &rx.hint)
+            .finish()
     }
 }
@@ -27,6 +27,13 @@ impl Dissociate<side::Tx> {
         let Side::Tx(tx) = &self.inner else { unreachable!() };
         &tx.header
     }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        let Header::Dissociate(dissoc) = &tx.header else { unreachable!() };
+        dissoc.assoc_id()
+    }
 }
 
 impl Debug for Dissociate<side::Tx> {
@@ -0,0 +1,67 @@
+use super::side::{self, Side};
+use crate::{DissociateAck as DissociateAckHeader, Header};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `DissociateAck` command
+pub struct DissociateAck<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl DissociateAck<side::Tx> {
+    pub(super) fn new(assoc_id: u16) -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::DissociateAck(DissociateAckHeader::new(assoc_id)),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `DissociateAck` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for DissociateAck<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("DissociateAck")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx {
+    assoc_id: u16,
+}
+
+impl DissociateAck<side::Rx> {
+    pub(super) fn new(assoc_id: u16) -> Self {
+        Self {
+            inner: Side::Rx(Rx { assoc_id }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.assoc_id
+    }
+}
+
+impl Debug for DissociateAck<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("DissociateAck")
+            .field("assoc_id", &rx.assoc_id)
+            .finish()
+    }
+}
@@ -0,0 +1,71 @@
+use super::side::{self, Side};
+use crate::{Echo as EchoHeader, Header};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `Echo` command
+pub struct Echo<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl Echo<side::Tx> {
+    pub(super) fn new(payload: Vec<u8>) -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::Echo(EchoHeader::new(payload)),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `Echo` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for Echo<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("Echo").field("header", &tx.header).finish()
+    }
+}
+
+struct Rx {
+    payload: Vec<u8>,
+}
+
+impl Echo<side::Rx> {
+    pub(super) fn new(payload: Vec<u8>) -> Self {
+        Self {
+            inner: Side::Rx(Rx { payload }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Returns the payload
+    pub fn payload(&self) -> &[u8] {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        &rx.payload
+    }
+
+    /// Consumes the model, returning the payload
+    pub fn into_payload(self) -> Vec<u8> {
+        let Side::Rx(rx) = self.inner else { unreachable!() };
+        rx.payload
+    }
+}
+
+impl Debug for Echo<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("Echo")
+            .field("payload", &rx.payload.len())
+            .finish()
+    }
+}
@@ -0,0 +1,280 @@
+use super::{Assemblable, Instant};
+use parking_lot::Mutex;
+use register_count::{Counter, Register};
+use std::{collections::VecDeque, sync::Arc};
+
+/// The number of one-second slots the creation and teardown rates are measured over
+const SLOTS: usize = 60;
+
+/// The kind of a task counted by a [`Connection`](super::Connection)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskKind {
+    /// A `Connect` task, relaying a TCP stream
+    Connect,
+    /// A UDP session
+    Associate,
+}
+
+/// A task being created or torn down, recorded in the task log of a [`Connection`](super::Connection)
+#[derive(Clone, Copy, Debug)]
+pub struct TaskEvent {
+    pub at: Instant,
+    pub kind: TaskKind,
+    /// Whether the task is created, or torn down otherwise
+    pub created: bool,
+    /// The number of tasks of the kind right after the event
+    pub count: usize,
+}
+
+/// Counters of a kind of tasks
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskCounts {
+    /// The number of tasks alive
+    pub current: usize,
+    /// The most tasks ever alive at the same time
+    pub high_water: usize,
+    /// The number of tasks ever created
+    pub created: u64,
+    /// The number of tasks ever torn down
+    pub closed: u64,
+    /// Tasks created per second, over about the last minute
+    pub creation_rate: f64,
+    /// Tasks torn down per second, over about the last minute
+    pub teardown_rate: f64,
+}
+
+/// Counters of the tasks of a [`Connection`](super::Connection)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskMetrics {
+    pub connect: TaskCounts,
+    pub associate: TaskCounts,
+}
+
+/// Counters of the reassembly of received packets, by a UDP session or by a [`Connection`](super::Connection) in total
+///
+/// Fragments lost on the network leave their packets pending until they time out, while rejected fragments point to a misbehaving peer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AssemblyCounts {
+    /// The number of fragments received, including those of unfragmented packets
+    pub fragments: u64,
+    /// The number of packets fully assembled
+    pub assembled: u64,
+    /// The number of packets assembled with a lost fragment recovered from the `Parity` of the packet, included in `assembled`
+    pub recovered: u64,
+    /// The number of incomplete packets dropped after the reassembly timeout
+    pub timed_out: u64,
+    /// The number of fragments dropped for being invalid or duplicated
+    pub rejected: u64,
+    /// The number of incomplete packets waiting for their fragments
+    pub pending: usize,
+}
+
+impl AssemblyCounts {
+    /// Records the result of inserting a fragment
+    pub(super) fn record<B, E>(&mut self, res: &Result<Option<Assemblable<B>>, E>) {
+        self.fragments += 1;
+        self.record_result(res);
+    }
+
+    /// Records the result of inserting a `Parity`, which is not counted as a fragment
+    pub(super) fn record_parity<B, E>(&mut self, res: &Result<Option<Assemblable<B>>, E>) {
+        self.record_result(res);
+    }
+
+    fn record_result<B, E>(&mut self, res: &Result<Option<Assemblable<B>>, E>) {
+        match res {
+            Ok(Some(pkt)) => {
+                self.assembled += 1;
+
+                if pkt.is_recovered() {
+                    self.recovered += 1;
+                }
+            }
+            Ok(None) => {}
+            Err(_) => self.rejected += 1,
+        }
+    }
+}
+
+/// Counts the tasks of a kind, recording their creation and teardown in the metrics shared by the connection
+#[derive(Clone)]
+pub(super) struct TaskCounter {
+    kind: TaskKind,
+    counter: Counter,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl TaskCounter {
+    pub(super) fn new(kind: TaskKind, metrics: Arc<Mutex<Metrics>>) -> Self {
+        Self {
+            kind,
+            counter: Counter::new(),
+            metrics,
+        }
+    }
+
+    pub(super) fn reg(&self) -> TaskRegister {
+        let reg = self.counter.reg();
+        self.metrics
+            .lock()
+            .record(self.kind, true, self.counter.count());
+
+        TaskRegister {
+            _reg: reg,
+            counter: self.clone(),
+        }
+    }
+
+    pub(super) fn count(&self) -> usize {
+        self.counter.count()
+    }
+}
+
+/// Keeps a task counted while alive
+pub(super) struct TaskRegister {
+    _reg: Register,
+    counter: TaskCounter,
+}
+
+impl Drop for TaskRegister {
+    fn drop(&mut self) {
+        // the register is dropped after this, so the task is still counted
+        let count = self.counter.count().saturating_sub(1);
+        self.counter
+            .metrics
+            .lock()
+            .record(self.counter.kind, false, count);
+    }
+}
+
+pub(super) struct Metrics {
+    start: Instant,
+    connect: KindMetrics,
+    associate: KindMetrics,
+    log: VecDeque<TaskEvent>,
+    log_capacity: usize,
+}
+
+struct KindMetrics {
+    high_water: usize,
+    created: u64,
+    closed: u64,
+    slots: [Slot; SLOTS],
+}
+
+/// The tasks created and torn down in a second, since the metrics started
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    second: u64,
+    created: u32,
+    closed: u32,
+}
+
+impl Metrics {
+    pub(super) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            connect: KindMetrics::new(),
+            associate: KindMetrics::new(),
+            log: VecDeque::new(),
+            log_capacity: 0,
+        }
+    }
+
+    fn record(&mut self, kind: TaskKind, created: bool, count: usize) {
+        let now = Instant::now();
+        let second = now.duration_since(self.start).as_secs();
+
+        let metrics = match kind {
+            TaskKind::Connect => &mut self.connect,
+            TaskKind::Associate => &mut self.associate,
+        };
+
+        let slot = &mut metrics.slots[second as usize % SLOTS];
+
+        if slot.second != second {
+            *slot = Slot {
+                second,
+                created: 0,
+                closed: 0,
+            };
+        }
+
+        if created {
+            metrics.created += 1;
+            metrics.high_water = metrics.high_water.max(count);
+            slot.created = slot.created.saturating_add(1);
+        } else {
+            metrics.closed += 1;
+            slot.closed = slot.closed.saturating_add(1);
+        }
+
+        if self.log_capacity > 0 {
+            if self.log.len() == self.log_capacity {
+                self.log.pop_front();
+            }
+
+            self.log.push_back(TaskEvent {
+                at: now,
+                kind,
+                created,
+                count,
+            });
+        }
+    }
+
+    pub(super) fn snapshot(&self, connect: usize, associate: usize) -> TaskMetrics {
+        let second = Instant::now().duration_since(self.start).as_secs();
+
+        TaskMetrics {
+            connect: self.connect.counts(connect, second),
+            associate: self.associate.counts(associate, second),
+        }
+    }
+
+    /// Keeps the last `capacity` task events, or none with 0
+    pub(super) fn set_log_capacity(&mut self, capacity: usize) {
+        self.log_capacity = capacity;
+
+        while self.log.len() > capacity {
+            self.log.pop_front();
+        }
+    }
+
+    pub(super) fn events(&self) -> Vec<TaskEvent> {
+        self.log.iter().copied().collect()
+    }
+}
+
+impl KindMetrics {
+    fn new() -> Self {
+        Self {
+            high_water: 0,
+            created: 0,
+            closed: 0,
+            slots: [Slot::default(); SLOTS],
+        }
+    }
+
+    fn counts(&self, current: usize, second: u64) -> TaskCounts {
+        // the slot of the current second is still filling, so the rates are over the full seconds before it
+        let (created, closed) = self
+            .slots
+            .iter()
+            .filter(|slot| slot.second < second && second - slot.second < SLOTS as u64)
+            .fold((0, 0), |(created, closed), slot| {
+                (created + slot.created as u64, closed + slot.closed as u64)
+            });
+
+        let secs = second.clamp(1, SLOTS as u64 - 1) as f64;
+
+        TaskCounts {
+            current,
+            high_water: self.high_water,
+            created: self.created,
+            closed: self.closed,
+            creation_rate: created as f64 / secs,
+            teardown_rate: closed as f64 / secs,
+        }
+    }
+}
@@ -1,44 +1,68 @@
 //! An abstraction of a TUIC connection, with packet fragmentation management and task counters. No I/O operation is involved internally
 
+use self::metrics::{Metrics, TaskCounter, TaskRegister};
 use crate::{
-    Address, Authenticate as AuthenticateHeader, Connect as ConnectHeader,
-    Dissociate as DissociateHeader, Heartbeat as HeartbeatHeader, Packet as PacketHeader,
+    Address, Authenticate as AuthenticateHeader, Bandwidth, Bind as BindHeader,
+    BindUdp as BindUdpHeader, CongestionHint, Connect as ConnectHeader,
+    Dissociate as DissociateHeader, DissociateAck as DissociateAckHeader, Echo as EchoHeader,
+    Heartbeat as HeartbeatHeader, Packet as PacketHeader, Parity as ParityHeader,
+    Resume as ResumeHeader,
 };
 use parking_lot::Mutex;
-use register_count::{Counter, Register};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt::{Debug, Formatter, Result as FmtResult},
     mem,
     sync::{
         atomic::{AtomicU16, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
+    time::Duration,
 };
 use thiserror::Error;
 use uuid::Uuid;
 
+#[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+use std::time::Instant;
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+use web_time::Instant;
+
 mod authenticate;
+mod bind;
+mod bind_udp;
 mod connect;
 mod dissociate;
+mod dissociate_ack;
+mod echo;
 mod heartbeat;
+mod metrics;
 mod packet;
+mod parity;
+mod resume;
 
 pub use self::{
     authenticate::{Authenticate, KeyingMaterialExporter},
+    bind::Bind,
+    bind_udp::BindUdp,
     connect::Connect,
     dissociate::Dissociate,
+    dissociate_ack::DissociateAck,
+    echo::Echo,
     heartbeat::Heartbeat,
+    metrics::{AssemblyCounts, TaskCounts, TaskEvent, TaskKind, TaskMetrics},
     packet::{Fragments, Packet},
+    parity::Parity,
+    resume::Resume,
 };
 
 /// An abstraction of a TUIC connection, with packet fragmentation management and task counters. No I/O operation is involved internally
 #[derive(Clone)]
 pub struct Connection<B> {
     udp_sessions: Arc<Mutex<UdpSessions<B>>>,
-    task_connect_count: Counter,
-    task_associate_count: Counter,
+    task_connect_count: TaskCounter,
+    task_associate_count: TaskCounter,
+    task_metrics: Arc<Mutex<Metrics>>,
 }
 
 impl<B> Connection<B>
@@ -48,12 +72,14 @@ where
     /// Creates a new `Connection`
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        let task_associate_count = Counter::new();
+        let task_metrics = Arc::new(Mutex::new(Metrics::new()));
+        let task_associate_count = TaskCounter::new(TaskKind::Associate, task_metrics.clone());
 
         Self {
             udp_sessions: Arc::new(Mutex::new(UdpSessions::new(task_associate_count.clone()))),
-            task_connect_count: Counter::new(),
+            task_connect_count: TaskCounter::new(TaskKind::Connect, task_metrics.clone()),
             task_associate_count,
+            task_metrics,
         }
     }
 
@@ -63,25 +89,54 @@ where
         uuid: Uuid,
         password: impl AsRef<[u8]>,
         exporter: &impl KeyingMaterialExporter,
-    ) -> Authenticate<side::Tx> {
-        Authenticate::<side::Tx>::new(uuid, password, exporter)
+    ) -> Option<Authenticate<side::Tx>> {
+        Authenticate::<side::Tx>::new(uuid, password, exporter, None, false)
+    }
+
+    /// Sends an `Authenticate` declaring the bandwidth of the client
+    pub fn send_authenticate_with_bandwidth(
+        &self,
+        uuid: Uuid,
+        password: impl AsRef<[u8]>,
+        exporter: &impl KeyingMaterialExporter,
+        bandwidth: Bandwidth,
+    ) -> Option<Authenticate<side::Tx>> {
+        Authenticate::<side::Tx>::new(uuid, password, exporter, Some(bandwidth), false)
+    }
+
+    /// Sends an `Authenticate` declaring that the client accepts `Parity` commands, optionally with the bandwidth of the client
+    pub fn send_authenticate_with_fec(
+        &self,
+        uuid: Uuid,
+        password: impl AsRef<[u8]>,
+        exporter: &impl KeyingMaterialExporter,
+        bandwidth: Option<Bandwidth>,
+    ) -> Option<Authenticate<side::Tx>> {
+        Authenticate::<side::Tx>::new(uuid, password, exporter, bandwidth, true)
     }
 
     /// Receives an `Authenticate`
     pub fn recv_authenticate(&self, header: AuthenticateHeader) -> Authenticate<side::Rx> {
-        let (uuid, token) = header.into();
-        Authenticate::<side::Rx>::new(uuid, token)
+        let fec = header.fec();
+        let compression = header.compression();
+        let (uuid, token, bandwidth) = header.into();
+        Authenticate::<side::Rx>::new(uuid, token, bandwidth, fec, compression)
     }
 
     /// Sends a `Connect`
     pub fn send_connect(&self, addr: Address) -> Connect<side::Tx> {
-        Connect::<side::Tx>::new(self.task_connect_count.reg(), addr)
+        Connect::<side::Tx>::new(self.task_connect_count.reg(), addr, None)
+    }
+
+    /// Sends a `Connect` with a congestion hint
+    pub fn send_connect_with_hint(&self, addr: Address, hint: CongestionHint) -> Connect<side::Tx> {
+        Connect::<side::Tx>::new(self.task_connect_count.reg(), addr, Some(hint))
     }
 
     /// Receives a `Connect`
     pub fn recv_connect(&self, header: ConnectHeader) -> Connect<side::Rx> {
-        let (addr,) = header.into();
-        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr)
+        let (addr, hint) = header.into();
+        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr, hint)
     }
 
     /// Sends a `Packet`
@@ -93,11 +148,37 @@ where
     ) -> Packet<side::Tx, B> {
         self.udp_sessions
             .lock()
-            .send_packet(assoc_id, addr, max_pkt_size)
+            .send_packet(assoc_id, addr, max_pkt_size, false)
+    }
+
+    /// Sends a `Packet` with a `Parity`, for the receiver to recover a single lost fragment
+    ///
+    /// The fragments are made smaller by the length of the address for the `Parity` to fit in `max_pkt_size`. If that leaves no room for the payload, the packet is sent without parity.
+    pub fn send_packet_with_parity(
+        &self,
+        assoc_id: u16,
+        addr: Address,
+        max_pkt_size: usize,
+    ) -> Packet<side::Tx, B> {
+        // the header of the first fragment takes 10 bytes besides the address
+        match max_pkt_size
+            .checked_sub(addr.len())
+            .filter(|size| *size > 10 + addr.len())
+        {
+            Some(size) => self
+                .udp_sessions
+                .lock()
+                .send_packet(assoc_id, addr, size, true),
+            None => self
+                .udp_sessions
+                .lock()
+                .send_packet(assoc_id, addr, max_pkt_size, false),
+        }
     }
 
     /// Receives a `Packet`. If the association ID is not found, returns `None`
     pub fn recv_packet(&self, header: PacketHeader) -> Option<Packet<side::Rx, B>> {
+        let compressed = header.is_compressed();
         let (assoc_id, pkt_id, frag_total, frag_id, size, addr) = header.into();
         self.udp_sessions.lock().recv_packet(
             self.udp_sessions.clone(),
@@ -107,11 +188,13 @@ where
             frag_id,
             size,
             addr,
+            compressed,
         )
     }
 
     /// Receives a `Packet` without checking the association ID
     pub fn recv_packet_unrestricted(&self, header: PacketHeader) -> Packet<side::Rx, B> {
+        let compressed = header.is_compressed();
         let (assoc_id, pkt_id, frag_total, frag_id, size, addr) = header.into();
         self.udp_sessions.lock().recv_packet_unrestricted(
             self.udp_sessions.clone(),
@@ -121,6 +204,44 @@ where
             frag_id,
             size,
             addr,
+            compressed,
+        )
+    }
+
+    /// Receives a `Parity`. If the association ID is not found, returns `None`
+    pub fn recv_parity(&self, header: ParityHeader) -> Option<Parity<side::Rx, B>> {
+        let (assoc_id, pkt_id, frag_total, pkt_size, size, addr) = header.into();
+
+        self.udp_sessions
+            .lock()
+            .sessions
+            .contains_key(&assoc_id)
+            .then(|| {
+                Parity::<side::Rx, B>::new(
+                    self.udp_sessions.clone(),
+                    assoc_id,
+                    pkt_id,
+                    frag_total,
+                    pkt_size,
+                    size,
+                    addr,
+                )
+            })
+    }
+
+    /// Receives a `Parity` without checking the association ID
+    pub fn recv_parity_unrestricted(&self, header: ParityHeader) -> Parity<side::Rx, B> {
+        let (assoc_id, pkt_id, frag_total, pkt_size, size, addr) = header.into();
+        self.udp_sessions.lock().bind(assoc_id);
+
+        Parity::<side::Rx, B>::new(
+            self.udp_sessions.clone(),
+            assoc_id,
+            pkt_id,
+            frag_total,
+            pkt_size,
+            size,
+            addr,
         )
     }
 
@@ -129,12 +250,80 @@ where
         self.udp_sessions.lock().send_dissociate(assoc_id)
     }
 
+    /// Sends a `Dissociate` for every UDP session, tearing them all down
+    pub fn send_dissociate_all(&self) -> Vec<Dissociate<side::Tx>> {
+        self.udp_sessions.lock().send_dissociate_all()
+    }
+
     /// Receives a `Dissociate`
     pub fn recv_dissociate(&self, header: DissociateHeader) -> Dissociate<side::Rx> {
         let (assoc_id,) = header.into();
         self.udp_sessions.lock().recv_dissociate(assoc_id)
     }
 
+    /// Sends a `DissociateAck`, confirming that the UDP session is torn down
+    pub fn send_dissociate_ack(&self, assoc_id: u16) -> DissociateAck<side::Tx> {
+        DissociateAck::<side::Tx>::new(assoc_id)
+    }
+
+    /// Receives a `DissociateAck`
+    pub fn recv_dissociate_ack(&self, header: DissociateAckHeader) -> DissociateAck<side::Rx> {
+        let (assoc_id,) = header.into();
+        DissociateAck::<side::Rx>::new(assoc_id)
+    }
+
+    /// Sends a `Resume`
+    pub fn send_resume(&self, token: [u8; 16], assoc_ids: Vec<u16>) -> Resume<side::Tx> {
+        Resume::<side::Tx>::new(token, assoc_ids)
+    }
+
+    /// Receives a `Resume`. The UDP sessions resumed are created, so packets of them can be received before sending any
+    pub fn recv_resume(&self, header: ResumeHeader) -> Resume<side::Rx> {
+        let (token, assoc_ids) = header.into();
+        let mut sessions = self.udp_sessions.lock();
+
+        for assoc_id in &assoc_ids {
+            sessions.bind(*assoc_id);
+        }
+
+        Resume::<side::Rx>::new(token, assoc_ids)
+    }
+
+    /// Sends a `BindUdp`. The UDP session is created, so packets from the server can be received before sending any
+    pub fn send_bind_udp(&self, assoc_id: u16, addr: Address) -> BindUdp<side::Tx> {
+        self.udp_sessions.lock().bind(assoc_id);
+        BindUdp::<side::Tx>::new(assoc_id, addr)
+    }
+
+    /// Receives a `BindUdp`
+    pub fn recv_bind_udp(&self, header: BindUdpHeader) -> BindUdp<side::Rx> {
+        let (assoc_id, addr) = header.into();
+        self.udp_sessions.lock().bind(assoc_id);
+        BindUdp::<side::Rx>::new(assoc_id, addr)
+    }
+
+    /// Sends a `Bind`
+    pub fn send_bind(&self, bind_id: u16, addr: Address) -> Bind<side::Tx> {
+        Bind::<side::Tx>::new(bind_id, addr)
+    }
+
+    /// Receives a `Bind`
+    pub fn recv_bind(&self, header: BindHeader) -> Bind<side::Rx> {
+        let (bind_id, addr) = header.into();
+        Bind::<side::Rx>::new(bind_id, addr)
+    }
+
+    /// Sends an `Echo`
+    pub fn send_echo(&self, payload: Vec<u8>) -> Echo<side::Tx> {
+        Echo::<side::Tx>::new(payload)
+    }
+
+    /// Receives an `Echo`
+    pub fn recv_echo(&self, header: EchoHeader) -> Echo<side::Rx> {
+        let (payload,) = header.into();
+        Echo::<side::Rx>::new(payload)
+    }
+
     /// Sends a `Heartbeat`
     pub fn send_heartbeat(&self) -> Heartbeat<side::Tx> {
         Heartbeat::<side::Tx>::new()
@@ -156,6 +345,42 @@ where
         self.task_associate_count.count()
     }
 
+    /// Returns the counters of `Connect` tasks and UDP sessions, with their high-water marks and creation and teardown rates
+    pub fn task_metrics(&self) -> TaskMetrics {
+        self.task_metrics
+            .lock()
+            .snapshot(self.task_connect_count(), self.task_associate_count())
+    }
+
+    /// Keeps a log of the last `capacity` task creations and teardowns, or stops logging with 0
+    pub fn set_task_log(&self, capacity: usize) {
+        self.task_metrics.lock().set_log_capacity(capacity);
+    }
+
+    /// Returns the logged task events, oldest first
+    pub fn task_events(&self) -> Vec<TaskEvent> {
+        self.task_metrics.lock().events()
+    }
+
+    /// Returns the reassembly counters of all UDP sessions, including the removed ones
+    pub fn assembly_counts(&self) -> AssemblyCounts {
+        self.udp_sessions.lock().assembly_counts()
+    }
+
+    /// Returns the reassembly counters of each existing UDP session, ordered by the association ID
+    pub fn session_assembly_counts(&self) -> Vec<(u16, AssemblyCounts)> {
+        let mut counts = self
+            .udp_sessions
+            .lock()
+            .sessions
+            .iter()
+            .map(|(assoc_id, session)| (*assoc_id, session.assembly_counts()))
+            .collect::<Vec<_>>();
+
+        counts.sort_by_key(|(assoc_id, _)| *assoc_id);
+        counts
+    }
+
     /// Removes fragments that can not be reassembled within the specified timeout
     pub fn collect_garbage(&self, timeout: Duration) {
         self.udp_sessions.lock().collect_garbage(timeout);
@@ -190,17 +415,19 @@ pub mod side {
 
 struct UdpSessions<B> {
     sessions: HashMap<u16, UdpSession<B>>,
-    task_associate_count: Counter,
+    task_associate_count: TaskCounter,
+    assembly: AssemblyCounts,
 }
 
 impl<B> UdpSessions<B>
 where
     B: AsRef<[u8]>,
 {
-    fn new(task_associate_count: Counter) -> Self {
+    fn new(task_associate_count: TaskCounter) -> Self {
         Self {
             sessions: HashMap::new(),
             task_associate_count,
+            assembly: AssemblyCounts::default(),
         }
     }
 
@@ -209,11 +436,12 @@ where
         assoc_id: u16,
         addr: Address,
         max_pkt_size: usize,
+        parity: bool,
     ) -> Packet<side::Tx, B> {
         self.sessions
             .entry(assoc_id)
             .or_insert_with(|| UdpSession::new(self.task_associate_count.reg()))
-            .send_packet(assoc_id, addr, max_pkt_size)
+            .send_packet(assoc_id, addr, max_pkt_size, parity)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -226,9 +454,12 @@ where
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
     ) -> Option<Packet<side::Rx, B>> {
         self.sessions.get_mut(&assoc_id).map(|session| {
-            session.recv_packet(sessions, assoc_id, pkt_id, frag_total, frag_id, size, addr)
+            session.recv_packet(
+                sessions, assoc_id, pkt_id, frag_total, frag_id, size, addr, compressed,
+            )
         })
     }
 
@@ -242,11 +473,20 @@ where
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
     ) -> Packet<side::Rx, B> {
         self.sessions
             .entry(assoc_id)
             .or_insert_with(|| UdpSession::new(self.task_associate_count.reg()))
-            .recv_packet(sessions, assoc_id, pkt_id, frag_total, frag_id, size, addr)
+            .recv_packet(
+                sessions, assoc_id, pkt_id, frag_total, frag_id, size, addr, compressed,
+            )
+    }
+
+    fn bind(&mut self, assoc_id: u16) {
+        self.sessions
+            .entry(assoc_id)
+            .or_insert_with(|| UdpSession::new(self.task_associate_count.reg()));
     }
 
     fn send_dissociate(&mut self, assoc_id: u16) -> Dissociate<side::Tx> {
@@ -254,6 +494,13 @@ where
         Dissociate::<side::Tx>::new(assoc_id)
     }
 
+    fn send_dissociate_all(&mut self) -> Vec<Dissociate<side::Tx>> {
+        self.sessions
+            .drain()
+            .map(|(assoc_id, _)| Dissociate::<side::Tx>::new(assoc_id))
+            .collect()
+    }
+
     fn recv_dissociate(&mut self, assoc_id: u16) -> Dissociate<side::Rx> {
         self.sessions.remove(&assoc_id);
         Dissociate::<side::Rx>::new(assoc_id)
@@ -268,17 +515,60 @@ where
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
         data: B,
-    ) -> Result<Option<Assemblable<B>>, AssembleError> {
-        self.sessions
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        let session = self
+            .sessions
             .entry(assoc_id)
-            .or_insert_with(|| UdpSession::new(self.task_associate_count.reg()))
-            .insert(assoc_id, pkt_id, frag_total, frag_id, size, addr, data)
+            .or_insert_with(|| UdpSession::new(self.task_associate_count.reg()));
+
+        let res = session.insert(
+            assoc_id, pkt_id, frag_total, frag_id, size, addr, compressed, data,
+        );
+        session.assembly.record(&res);
+        self.assembly.record(&res);
+        res
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert_parity(
+        &mut self,
+        assoc_id: u16,
+        pkt_id: u16,
+        frag_total: u8,
+        pkt_size: u16,
+        size: u16,
+        addr: Address,
+        parity: B,
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        let session = self
+            .sessions
+            .entry(assoc_id)
+            .or_insert_with(|| UdpSession::new(self.task_associate_count.reg()));
+
+        let res =
+            session.insert_parity(assoc_id, pkt_id, frag_total, pkt_size, size, addr, parity);
+        session.assembly.record_parity(&res);
+        self.assembly.record_parity(&res);
+        res
+    }
+
+    fn assembly_counts(&self) -> AssemblyCounts {
+        let mut counts = self.assembly;
+        counts.pending = self.sessions.values().map(|s| s.pkt_buf.len()).sum();
+        counts
     }
 
     fn collect_garbage(&mut self, timeout: Duration) {
         for (_, session) in self.sessions.iter_mut() {
-            session.collect_garbage(timeout);
+            self.assembly.timed_out += session.collect_garbage(timeout);
         }
     }
 }
@@ -294,19 +584,31 @@ where
     }
 }
 
+/// The number of recently assembled packets remembered per UDP session for rejecting replayed fragments
+///
+/// Far below the packet ID space, so that the IDs of the sender wrap around long before a remembered one is reused.
+const ASSEMBLED_HISTORY_LEN: usize = 4096;
+
 struct UdpSession<B> {
     pkt_buf: HashMap<u16, PacketBuffer<B>>,
+    /// The recently assembled packets, with the fragment recovered from the parity, if any
+    assembled: HashMap<u16, Option<u8>>,
+    assembled_order: VecDeque<u16>,
+    assembly: AssemblyCounts,
     next_pkt_id: AtomicU16,
-    _task_reg: Register,
+    _task_reg: TaskRegister,
 }
 
 impl<B> UdpSession<B>
 where
     B: AsRef<[u8]>,
 {
-    fn new(task_reg: Register) -> Self {
+    fn new(task_reg: TaskRegister) -> Self {
         Self {
             pkt_buf: HashMap::new(),
+            assembled: HashMap::new(),
+            assembled_order: VecDeque::new(),
+            assembly: AssemblyCounts::default(),
             next_pkt_id: AtomicU16::new(0),
             _task_reg: task_reg,
         }
@@ -317,12 +619,14 @@ where
         assoc_id: u16,
         addr: Address,
         max_pkt_size: usize,
+        parity: bool,
     ) -> Packet<side::Tx, B> {
         Packet::<side::Tx, B>::new(
             assoc_id,
             self.next_pkt_id.fetch_add(1, Ordering::AcqRel),
             addr,
             max_pkt_size,
+            parity,
         )
     }
 
@@ -336,8 +640,11 @@ where
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
     ) -> Packet<side::Rx, B> {
-        Packet::<side::Rx, B>::new(sessions, assoc_id, pkt_id, frag_total, frag_id, size, addr)
+        Packet::<side::Rx, B>::new(
+            sessions, assoc_id, pkt_id, frag_total, frag_id, size, addr, compressed,
+        )
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -349,23 +656,102 @@ where
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
         data: B,
-    ) -> Result<Option<Assemblable<B>>, AssembleError> {
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        match self.assembled.get(&pkt_id) {
+            // the fragment recovered from the parity was only delayed, e.g. behind the parity
+            Some(Some(recovered)) if *recovered == frag_id => return Ok(None),
+            // a fragment of an already assembled packet is a retransmission or a replay
+            Some(_) => return Err(AssembleError::DuplicateFragment(pkt_id, frag_id)),
+            None => {}
+        }
+
         let res = self
             .pkt_buf
             .entry(pkt_id)
             .or_insert_with(|| PacketBuffer::new(frag_total))
-            .insert(assoc_id, frag_total, frag_id, size, addr, data)?;
+            .insert(
+                assoc_id, pkt_id, frag_total, frag_id, size, addr, compressed, data,
+            )?;
 
         if res.is_some() {
-            self.pkt_buf.remove(&pkt_id);
+            self.finish_assembly(pkt_id);
         }
 
         Ok(res)
     }
 
-    fn collect_garbage(&mut self, timeout: Duration) {
+    #[allow(clippy::too_many_arguments)]
+    fn insert_parity(
+        &mut self,
+        assoc_id: u16,
+        pkt_id: u16,
+        frag_total: u8,
+        pkt_size: u16,
+        size: u16,
+        addr: Address,
+        parity: B,
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        // the parity of a packet assembled without it is just not needed
+        if self.assembled.contains_key(&pkt_id) {
+            return Ok(None);
+        }
+
+        if frag_total < 2 {
+            return Err(AssembleError::InvalidParity(pkt_id));
+        }
+
+        let res = self
+            .pkt_buf
+            .entry(pkt_id)
+            .or_insert_with(|| PacketBuffer::new(frag_total))
+            .insert_parity(assoc_id, pkt_id, frag_total, pkt_size, size, addr, parity)?;
+
+        if res.is_some() {
+            self.finish_assembly(pkt_id);
+        }
+
+        Ok(res)
+    }
+
+    fn finish_assembly(&mut self, pkt_id: u16) {
+        let recovered = self
+            .pkt_buf
+            .remove(&pkt_id)
+            .and_then(|pkt_buf| pkt_buf.recovered);
+
+        if self.assembled_order.len() == ASSEMBLED_HISTORY_LEN {
+            if let Some(oldest) = self.assembled_order.pop_front() {
+                self.assembled.remove(&oldest);
+            }
+        }
+
+        self.assembled.insert(pkt_id, recovered);
+        self.assembled_order.push_back(pkt_id);
+    }
+
+    fn assembly_counts(&self) -> AssemblyCounts {
+        AssemblyCounts {
+            pending: self.pkt_buf.len(),
+            ..self.assembly
+        }
+    }
+
+    /// Removes the packets not reassembled within the timeout, returning the number of them
+    fn collect_garbage(&mut self, timeout: Duration) -> u64 {
+        let pending = self.pkt_buf.len();
         self.pkt_buf.retain(|_, buf| buf.c_time.elapsed() < timeout);
+
+        let timed_out = (pending - self.pkt_buf.len()) as u64;
+        self.assembly.timed_out += timed_out;
+        timed_out
     }
 }
 
@@ -376,6 +762,7 @@ where
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         f.debug_struct("UdpSession")
             .field("pkt_buf", &self.pkt_buf)
+            .field("assembled", &self.assembled.len())
             .field("next_pkt_id", &self.next_pkt_id)
             .finish()
     }
@@ -387,6 +774,11 @@ struct PacketBuffer<B> {
     frag_total: u8,
     frag_received: u8,
     addr: Address,
+    /// Whether the packet is compressed, known once a fragment is received
+    compressed: Option<bool>,
+    /// The parity with the length and the address of the packet, kept until a single fragment is missing
+    parity: Option<(B, u16, Address)>,
+    recovered: Option<u8>,
     c_time: Instant,
 }
 
@@ -403,21 +795,35 @@ where
             frag_total,
             frag_received: 0,
             addr: Address::None,
+            compressed: None,
+            parity: None,
+            recovered: None,
             c_time: Instant::now(),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn insert(
         &mut self,
         assoc_id: u16,
+        pkt_id: u16,
         frag_total: u8,
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
         data: B,
-    ) -> Result<Option<Assemblable<B>>, AssembleError> {
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
         assert_eq!(data.as_ref().len(), size as usize);
 
+        // fragments of the same packet must agree on the total, which sizes the buffer
+        if frag_total != self.frag_total {
+            return Err(AssembleError::FragmentTotalMismatch(self.frag_total, frag_total));
+        }
+
         if frag_id >= frag_total {
             return Err(AssembleError::InvalidFragmentId(frag_total, frag_id));
         }
@@ -435,10 +841,15 @@ where
         }
 
         if self.buf[frag_id as usize].is_some() {
-            return Err(AssembleError::DuplicatedFragment(frag_id));
+            return Err(AssembleError::DuplicateFragment(pkt_id, frag_id));
+        }
+
+        if self.compressed.map_or(false, |c| c != compressed) {
+            return Err(AssembleError::CompressionMismatch(pkt_id));
         }
 
         self.buf[frag_id as usize] = Some(data);
+        self.compressed = Some(compressed);
         self.frag_received += 1;
 
         if frag_id == 0 {
@@ -450,11 +861,104 @@ where
                 mem::take(&mut self.buf),
                 self.addr.take(),
                 assoc_id,
+                compressed,
+                false,
             )))
         } else {
-            Ok(None)
+            self.recover(assoc_id, pkt_id)
         }
     }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert_parity(
+        &mut self,
+        assoc_id: u16,
+        pkt_id: u16,
+        frag_total: u8,
+        pkt_size: u16,
+        size: u16,
+        addr: Address,
+        parity: B,
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        assert_eq!(parity.as_ref().len(), size as usize);
+
+        if frag_total != self.frag_total {
+            return Err(AssembleError::FragmentTotalMismatch(self.frag_total, frag_total));
+        }
+
+        if self.parity.is_some() {
+            return Err(AssembleError::DuplicateFragment(pkt_id, frag_total));
+        }
+
+        self.parity = Some((parity, pkt_size, addr));
+        self.recover(assoc_id, pkt_id)
+    }
+
+    /// Recovers the missing fragment from the parity, once it is the only one missing
+    fn recover(
+        &mut self,
+        assoc_id: u16,
+        pkt_id: u16,
+    ) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        if self.frag_received + 1 != self.frag_total {
+            return Ok(None);
+        }
+
+        let Some((parity, pkt_size, addr)) = self.parity.take() else {
+            return Ok(None);
+        };
+
+        let missing = self.buf.iter().position(Option::is_none).unwrap();
+
+        let received = self
+            .buf
+            .iter()
+            .flatten()
+            .map(|frag| frag.as_ref().len())
+            .sum::<usize>();
+
+        // the missing fragment takes the rest of the packet, which the parity must cover
+        let Some(len) = (pkt_size as usize)
+            .checked_sub(received)
+            .filter(|len| *len <= parity.as_ref().len())
+        else {
+            return Err(AssembleError::InvalidParity(pkt_id));
+        };
+
+        if missing == 0 {
+            if addr.is_none() {
+                return Err(AssembleError::InvalidAddress("no address in parity"));
+            }
+
+            self.addr = addr;
+        }
+
+        let mut data = parity.as_ref()[..len].to_vec();
+
+        for frag in self.buf.iter().flatten() {
+            for (d, b) in data.iter_mut().zip(frag.as_ref()) {
+                *d ^= b;
+            }
+        }
+
+        self.buf[missing] = Some(B::from(data));
+        self.frag_received += 1;
+        self.recovered = Some(missing as u8);
+
+        Ok(Some(Assemblable::new(
+            mem::take(&mut self.buf),
+            self.addr.take(),
+            assoc_id,
+            self.compressed.unwrap_or(false),
+            true,
+        )))
+    }
 }
 
 /// A complete packet that can be assembled
@@ -463,17 +967,39 @@ pub struct Assemblable<B> {
     buf: Vec<Option<B>>,
     addr: Address,
     assoc_id: u16,
+    compressed: bool,
+    recovered: bool,
+}
+
+impl<B> Assemblable<B> {
+    /// Returns whether the packet is compressed, i.e. its fragments were received in type `0x0c`
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Returns whether a lost fragment of the packet was recovered from its `Parity`
+    pub fn is_recovered(&self) -> bool {
+        self.recovered
+    }
 }
 
 impl<B> Assemblable<B>
 where
     B: AsRef<[u8]>,
 {
-    fn new(buf: Vec<Option<B>>, addr: Address, assoc_id: u16) -> Self {
+    fn new(
+        buf: Vec<Option<B>>,
+        addr: Address,
+        assoc_id: u16,
+        compressed: bool,
+        recovered: bool,
+    ) -> Self {
         Self {
             buf,
             addr,
             assoc_id,
+            compressed,
+            recovered,
         }
     }
 
@@ -512,8 +1038,248 @@ where
 pub enum AssembleError {
     #[error("invalid fragment id {1} in total {0} fragments")]
     InvalidFragmentId(u8, u8),
+    #[error("fragment total {1} mismatches the {0} fragments of the packet")]
+    FragmentTotalMismatch(u8, u8),
     #[error("{0}")]
     InvalidAddress(&'static str),
-    #[error("duplicated fragment: {0}")]
-    DuplicatedFragment(u8),
+    #[error("duplicate fragment {1} of packet {0}")]
+    DuplicateFragment(u16, u8),
+    #[error("invalid parity of packet {0}")]
+    InvalidParity(u16),
+    #[error("fragments of packet {0} disagree on compression")]
+    CompressionMismatch(u16),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Header;
+    use std::net::SocketAddr;
+
+    const ASSOC_ID: u16 = 1;
+
+    fn addr() -> Address {
+        Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 5353)))
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..50).collect()
+    }
+
+    /// Fragments the payload into 16, 22 and 12 bytes, returning the fragments and the parity
+    fn fragment(payload: &[u8]) -> (Vec<(PacketHeader, Vec<u8>)>, ParityHeader, Vec<u8>) {
+        let conn = Connection::<Vec<u8>>::new();
+        let pkt = conn.send_packet_with_parity(ASSOC_ID, addr(), 40);
+        let parity = pkt.parity(payload).unwrap();
+
+        let frags = pkt
+            .into_fragments(payload)
+            .map(|(header, frag)| {
+                let Header::Packet(header) = header else { unreachable!() };
+                (header, frag.to_vec())
+            })
+            .collect::<Vec<_>>();
+
+        let Header::Parity(header) = parity.header().clone() else { unreachable!() };
+        (frags, header, parity.parity().to_vec())
+    }
+
+    fn recv_frag(
+        conn: &Connection<Vec<u8>>,
+        (header, frag): &(PacketHeader, Vec<u8>),
+    ) -> Result<Option<Assemblable<Vec<u8>>>, AssembleError> {
+        conn.recv_packet_unrestricted(header.clone())
+            .assemble(frag.clone())
+    }
+
+    fn recv_parity(
+        conn: &Connection<Vec<u8>>,
+        header: &ParityHeader,
+        parity: &[u8],
+    ) -> Result<Option<Assemblable<Vec<u8>>>, AssembleError> {
+        conn.recv_parity_unrestricted(header.clone())
+            .recover(parity.to_vec())
+    }
+
+    fn assemble(pkt: Assemblable<Vec<u8>>) -> (Vec<u8>, Address) {
+        assert!(pkt.is_recovered());
+        let mut buf = Vec::new();
+        let (addr, assoc_id) = pkt.assemble(&mut buf);
+        assert_eq!(assoc_id, ASSOC_ID);
+        (buf, addr)
+    }
+
+    #[test]
+    fn fragments_and_parity() {
+        let (frags, parity_header, parity) = fragment(&payload());
+        let sizes = frags.iter().map(|(_, frag)| frag.len()).collect::<Vec<_>>();
+
+        assert_eq!(sizes, [16, 22, 12]);
+        assert_eq!(parity.len(), 22);
+        assert_eq!(parity_header.frag_total(), 3);
+        assert_eq!(parity_header.pkt_size(), 50);
+    }
+
+    #[test]
+    fn recover_first_fragment() {
+        let (frags, parity_header, parity) = fragment(&payload());
+        let conn = Connection::new();
+
+        assert!(matches!(recv_frag(&conn, &frags[1]), Ok(None)));
+        assert!(matches!(recv_frag(&conn, &frags[2]), Ok(None)));
+
+        // the address is only in the first fragment, so it is taken from the parity
+        let pkt = recv_parity(&conn, &parity_header, &parity)
+            .unwrap()
+            .unwrap();
+        assert_eq!(assemble(pkt), (payload(), addr()));
+    }
+
+    #[test]
+    fn recover_middle_and_last_fragments() {
+        for missing in [1, 2] {
+            let (frags, parity_header, parity) = fragment(&payload());
+            let conn = Connection::new();
+
+            for (frag_id, frag) in frags.iter().enumerate() {
+                if frag_id != missing {
+                    assert!(matches!(recv_frag(&conn, frag), Ok(None)));
+                }
+            }
+
+            let pkt = recv_parity(&conn, &parity_header, &parity)
+                .unwrap()
+                .unwrap();
+            assert_eq!(assemble(pkt), (payload(), addr()));
+        }
+    }
+
+    #[test]
+    fn recover_with_parity_first() {
+        let (frags, parity_header, parity) = fragment(&payload());
+        let conn = Connection::new();
+
+        assert!(matches!(
+            recv_parity(&conn, &parity_header, &parity),
+            Ok(None)
+        ));
+        assert!(matches!(recv_frag(&conn, &frags[2]), Ok(None)));
+
+        let pkt = recv_frag(&conn, &frags[0]).unwrap().unwrap();
+        assert_eq!(assemble(pkt), (payload(), addr()));
+    }
+
+    #[test]
+    fn late_recovered_fragment() {
+        let (frags, parity_header, parity) = fragment(&payload());
+        let conn = Connection::new();
+
+        assert!(matches!(recv_frag(&conn, &frags[0]), Ok(None)));
+        assert!(matches!(recv_frag(&conn, &frags[2]), Ok(None)));
+        assert!(recv_parity(&conn, &parity_header, &parity)
+            .unwrap()
+            .is_some());
+
+        // the recovered fragment arriving late is ignored, while others are duplicates
+        assert!(matches!(recv_frag(&conn, &frags[1]), Ok(None)));
+        assert!(matches!(
+            recv_frag(&conn, &frags[0]),
+            Err(AssembleError::DuplicateFragment(_, 0))
+        ));
+        assert_eq!(conn.assembly_counts().pending, 0);
+    }
+
+    #[test]
+    fn parity_with_short_packet_size() {
+        let (frags, parity_header, parity) = fragment(&payload());
+        let conn = Connection::new();
+
+        let (assoc_id, pkt_id, frag_total, _, size, addr) = parity_header.into();
+        let parity_header = ParityHeader::new(assoc_id, pkt_id, frag_total, 20, size, addr);
+
+        assert!(matches!(recv_frag(&conn, &frags[0]), Ok(None)));
+        assert!(matches!(recv_frag(&conn, &frags[1]), Ok(None)));
+        assert!(matches!(
+            recv_parity(&conn, &parity_header, &parity),
+            Err(AssembleError::InvalidParity(id)) if id == pkt_id
+        ));
+    }
+
+    #[test]
+    fn duplicate_parity() {
+        let (frags, parity_header, parity) = fragment(&payload());
+        let conn = Connection::new();
+
+        assert!(matches!(recv_frag(&conn, &frags[0]), Ok(None)));
+        assert!(matches!(
+            recv_parity(&conn, &parity_header, &parity),
+            Ok(None)
+        ));
+        assert!(matches!(
+            recv_parity(&conn, &parity_header, &parity),
+            Err(AssembleError::DuplicateFragment(_, 3))
+        ));
+    }
+
+    #[test]
+    fn fragment_total_mismatch() {
+        let (frags, _, _) = fragment(&payload());
+        let conn = Connection::new();
+
+        let (header, frag) = &frags[1];
+        let (assoc_id, pkt_id, _, frag_id, size, addr) = header.clone().into();
+        let header = PacketHeader::new(assoc_id, pkt_id, 4, frag_id, size, addr);
+
+        assert!(matches!(recv_frag(&conn, &frags[0]), Ok(None)));
+        assert!(matches!(
+            recv_frag(&conn, &(header, frag.clone())),
+            Err(AssembleError::FragmentTotalMismatch(3, 4))
+        ));
+    }
+    #[test]
+    fn recover_compressed_packet() {
+        let conn = Connection::<Vec<u8>>::new();
+        let pkt = conn
+            .send_packet_with_parity(ASSOC_ID, addr(), 40)
+            .compressed();
+        let parity = pkt.parity(payload()).unwrap();
+        let Header::Parity(parity_header) = parity.header().clone() else { unreachable!() };
+        let parity = parity.parity().to_vec();
+
+        let frags = pkt
+            .into_fragments(payload())
+            .map(|(header, frag)| {
+                let Header::Packet(header) = header else { unreachable!() };
+                assert!(header.is_compressed());
+                (header, frag.to_vec())
+            })
+            .collect::<Vec<_>>();
+
+        let conn = Connection::new();
+        assert!(matches!(recv_frag(&conn, &frags[1]), Ok(None)));
+        assert!(matches!(recv_frag(&conn, &frags[2]), Ok(None)));
+
+        // the parity carries no compression flag, which is taken from the received fragments
+        let pkt = recv_parity(&conn, &parity_header, &parity)
+            .unwrap()
+            .unwrap();
+        assert!(pkt.is_compressed());
+        assert_eq!(assemble(pkt), (payload(), addr()));
+    }
+
+    #[test]
+    fn compression_mismatch() {
+        let (frags, _, _) = fragment(&payload());
+        let conn = Connection::new();
+
+        let (header, frag) = &frags[1];
+        let pkt_id = header.pkt_id();
+        let header = header.clone().compressed();
+
+        assert!(matches!(recv_frag(&conn, &frags[0]), Ok(None)));
+        assert!(matches!(
+            recv_frag(&conn, &(header, frag.clone())),
+            Err(AssembleError::CompressionMismatch(id)) if id == pkt_id
+        ));
+    }
 }
@@ -1,6 +1,6 @@
 use super::{
     side::{self, Side},
-    Assemblable, AssembleError, UdpSessions,
+    Assemblable, AssembleError, Parity, UdpSessions,
 };
 use crate::{Address, Header, Packet as PacketHeader};
 use parking_lot::Mutex;
@@ -21,28 +21,83 @@ struct Tx {
     pkt_id: u16,
     addr: Address,
     max_pkt_size: usize,
+    parity: bool,
+    compressed: bool,
 }
 
 impl<B> Packet<side::Tx, B> {
-    pub(super) fn new(assoc_id: u16, pkt_id: u16, addr: Address, max_pkt_size: usize) -> Self {
+    pub(super) fn new(
+        assoc_id: u16,
+        pkt_id: u16,
+        addr: Address,
+        max_pkt_size: usize,
+        parity: bool,
+    ) -> Self {
         Self {
             inner: Side::Tx(Tx {
                 assoc_id,
                 pkt_id,
                 addr,
                 max_pkt_size,
+                parity,
+                compressed: false,
             }),
             _marker: side::Tx,
         }
     }
 
+    /// Marks the payload as compressed, sending its fragments in type `0x0c`
+    pub fn compressed(mut self) -> Self {
+        let Side::Tx(tx) = &mut self.inner else { unreachable!() };
+        tx.compressed = true;
+        self
+    }
+
+    /// Computes the `Parity` of the fragments of the payload, if the packet is sent with parity and fragmented
+    pub fn parity(&self, payload: impl AsRef<[u8]>) -> Option<Parity<side::Tx, B>> {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+
+        if !tx.parity {
+            return None;
+        }
+
+        let payload = payload.as_ref();
+        let frags = Fragments::new(
+            tx.assoc_id,
+            tx.pkt_id,
+            tx.addr.clone(),
+            tx.max_pkt_size,
+            tx.compressed,
+            payload,
+        );
+
+        if frags.len() < 2 {
+            return None;
+        }
+
+        Some(Parity::<side::Tx, B>::new(
+            tx.assoc_id,
+            tx.pkt_id,
+            payload.len() as u16,
+            tx.addr.clone(),
+            frags.map(|(_, frag)| frag),
+        ))
+    }
+
     /// Fragment the payload into multiple packets
     pub fn into_fragments<'a, P>(self, payload: P) -> Fragments<'a, P>
     where
         P: AsRef<[u8]> + 'a,
     {
         let Side::Tx(tx) = self.inner else { unreachable!() };
-        Fragments::new(tx.assoc_id, tx.pkt_id, tx.addr, tx.max_pkt_size, payload)
+        Fragments::new(
+            tx.assoc_id,
+            tx.pkt_id,
+            tx.addr,
+            tx.max_pkt_size,
+            tx.compressed,
+            payload,
+        )
     }
 
     /// Returns the UDP session ID
@@ -72,6 +127,8 @@ impl Debug for Packet<side::Tx, ()> {
             .field("pkt_id", &tx.pkt_id)
             .field("addr", &tx.addr)
             .field("max_pkt_size", &tx.max_pkt_size)
+            .field("parity", &tx.parity)
+            .field("compressed", &tx.compressed)
             .finish()
     }
 }
@@ -84,12 +141,14 @@ struct Rx<B> {
     frag_id: u8,
     size: u16,
     addr: Address,
+    compressed: bool,
 }
 
 impl<B> Packet<side::Rx, B>
 where
     B: AsRef<[u8]>,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         sessions: Arc<Mutex<UdpSessions<B>>>,
         assoc_id: u16,
@@ -98,6 +157,7 @@ where
         frag_id: u8,
         size: u16,
         addr: Address,
+        compressed: bool,
     ) -> Self {
         Self {
             inner: Side::Rx(Rx {
@@ -108,13 +168,19 @@ where
                 frag_id,
                 size,
                 addr,
+                compressed,
             }),
             _marker: side::Rx,
         }
     }
 
     /// Reassembles the packet. If the packet is not complete yet, `None` is returned.
-    pub fn assemble(self, data: B) -> Result<Option<Assemblable<B>>, AssembleError> {
+    ///
+    /// A single missing fragment is recovered once the `Parity` of the packet is received.
+    pub fn assemble(self, data: B) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
         let Side::Rx(rx) = self.inner else { unreachable!() };
         let mut sessions = rx.sessions.lock();
 
@@ -125,6 +191,7 @@ where
             rx.frag_id,
             rx.size,
             rx.addr,
+            rx.compressed,
             data,
         )
     }
@@ -164,6 +231,12 @@ where
         let Side::Rx(rx) = &self.inner else { unreachable!() };
         rx.size
     }
+
+    /// Returns whether the packet is compressed
+    pub fn is_compressed(&self) -> bool {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.compressed
+    }
 }
 
 impl<B> Debug for Packet<side::Rx, B> {
@@ -176,6 +249,7 @@ impl<B> Debug for Packet<side::Rx, B> {
             .field("frag_id", &rx.frag_id)
             .field("size", &rx.size)
             .field("addr", &rx.addr)
+            .field("compressed", &rx.compressed)
             .finish()
     }
 }
@@ -187,6 +261,7 @@ pub struct Fragments<'a, P> {
     pkt_id: u16,
     addr: Address,
     max_pkt_size: usize,
+    compressed: bool,
     frag_total: u8,
     next_frag_id: u8,
     next_frag_start: usize,
@@ -198,7 +273,14 @@ impl<'a, P> Fragments<'a, P>
 where
     P: AsRef<[u8]> + 'a,
 {
-    fn new(assoc_id: u16, pkt_id: u16, addr: Address, max_pkt_size: usize, payload: P) -> Self {
+    fn new(
+        assoc_id: u16,
+        pkt_id: u16,
+        addr: Address,
+        max_pkt_size: usize,
+        compressed: bool,
+        payload: P,
+    ) -> Self {
         let header_addr_ref = Header::Packet(PacketHeader::new(0, 0, 0, 0, 0, addr));
         let header_addr_none_ref = Header::Packet(PacketHeader::new(0, 0, 0, 0, 0, Address::None));
 
@@ -219,6 +301,7 @@ where
             pkt_id,
             addr,
             max_pkt_size,
+            compressed,
             frag_total,
             next_frag_id: 0,
             next_frag_start: 0,
@@ -245,14 +328,20 @@ where
             let Header::Packet(pkt) = header_ref else { unreachable!() };
             let (_, _, _, _, _, addr) = pkt.into();
 
-            let header = Header::Packet(PacketHeader::new(
+            let header = PacketHeader::new(
                 self.assoc_id,
                 self.pkt_id,
                 self.frag_total,
                 self.next_frag_id,
                 (next_frag_end - self.next_frag_start) as u16,
                 addr,
-            ));
+            );
+
+            let header = if self.compressed {
+                Header::Packet(header.compressed())
+            } else {
+                Header::Packet(header)
+            };
 
             let payload_ptr = &(self.payload.as_ref()[self.next_frag_start]) as *const u8;
             let payload =
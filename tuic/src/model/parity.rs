@@ -0,0 +1,188 @@
+use super::{
+    side::{self, Side},
+    Assemblable, AssembleError, UdpSessions,
+};
+use crate::{Address, Header, Parity as ParityHeader};
+use parking_lot::Mutex;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    sync::Arc,
+};
+
+/// The model of the `Parity` command
+pub struct Parity<M, B> {
+    inner: Side<Tx, Rx<B>>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+    parity: Vec<u8>,
+}
+
+impl<B> Parity<side::Tx, B> {
+    /// Computes the parity of the fragments
+    pub(super) fn new<'a>(
+        assoc_id: u16,
+        pkt_id: u16,
+        pkt_size: u16,
+        addr: Address,
+        frags: impl ExactSizeIterator<Item = &'a [u8]>,
+    ) -> Self {
+        let frag_total = frags.len() as u8;
+        let mut parity = Vec::new();
+
+        for frag in frags {
+            if parity.len() < frag.len() {
+                parity.resize(frag.len(), 0);
+            }
+
+            for (p, b) in parity.iter_mut().zip(frag) {
+                *p ^= b;
+            }
+        }
+
+        let header = Header::Parity(ParityHeader::new(
+            assoc_id,
+            pkt_id,
+            frag_total,
+            pkt_size,
+            parity.len() as u16,
+            addr,
+        ));
+
+        Self {
+            inner: Side::Tx(Tx { header, parity }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `Parity` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+
+    /// Returns the parity
+    pub fn parity(&self) -> &[u8] {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.parity
+    }
+}
+
+impl<B> Debug for Parity<side::Tx, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("Parity")
+            .field("header", &tx.header)
+            .field("parity", &tx.parity.len())
+            .finish()
+    }
+}
+
+struct Rx<B> {
+    sessions: Arc<Mutex<UdpSessions<B>>>,
+    assoc_id: u16,
+    pkt_id: u16,
+    frag_total: u8,
+    pkt_size: u16,
+    size: u16,
+    addr: Address,
+}
+
+impl<B> Parity<side::Rx, B>
+where
+    B: AsRef<[u8]>,
+{
+    pub(super) fn new(
+        sessions: Arc<Mutex<UdpSessions<B>>>,
+        assoc_id: u16,
+        pkt_id: u16,
+        frag_total: u8,
+        pkt_size: u16,
+        size: u16,
+        addr: Address,
+    ) -> Self {
+        Self {
+            inner: Side::Rx(Rx {
+                sessions,
+                assoc_id,
+                pkt_id,
+                frag_total,
+                pkt_size,
+                size,
+                addr,
+            }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Recovers the missing fragment of the packet with the parity, if all the others are received. If the packet is not complete yet, or is already assembled, `None` is returned.
+    pub fn recover(self, parity: B) -> Result<Option<Assemblable<B>>, AssembleError>
+    where
+        B: From<Vec<u8>>,
+    {
+        let Side::Rx(rx) = self.inner else { unreachable!() };
+        let mut sessions = rx.sessions.lock();
+
+        sessions.insert_parity(
+            rx.assoc_id,
+            rx.pkt_id,
+            rx.frag_total,
+            rx.pkt_size,
+            rx.size,
+            rx.addr,
+            parity,
+        )
+    }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.assoc_id
+    }
+
+    /// Returns the packet ID
+    pub fn pkt_id(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.pkt_id
+    }
+
+    /// Returns the total number of fragments
+    pub fn frag_total(&self) -> u8 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.frag_total
+    }
+
+    /// Returns the length of the whole packet
+    pub fn pkt_size(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.pkt_size
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        &rx.addr
+    }
+
+    /// Returns the size of the parity
+    pub fn size(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.size
+    }
+}
+
+impl<B> Debug for Parity<side::Rx, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("Parity")
+            .field("assoc_id", &rx.assoc_id)
+            .field("pkt_id", &rx.pkt_id)
+            .field("frag_total", &rx.frag_total)
+            .field("pkt_size", &rx.pkt_size)
+            .field("size", &rx.size)
+            .field("addr", &rx.addr)
+            .finish()
+    }
+}
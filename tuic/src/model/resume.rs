@@ -0,0 +1,75 @@
+use super::side::{self, Side};
+use crate::{Header, Resume as ResumeHeader};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `Resume` command
+pub struct Resume<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl Resume<side::Tx> {
+    pub(super) fn new(token: [u8; 16], assoc_ids: Vec<u16>) -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::Resume(ResumeHeader::new(token, assoc_ids)),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `Resume` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for Resume<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("Resume")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx {
+    token: [u8; 16],
+    assoc_ids: Vec<u16>,
+}
+
+impl Resume<side::Rx> {
+    pub(super) fn new(token: [u8; 16], assoc_ids: Vec<u16>) -> Self {
+        Self {
+            inner: Side::Rx(Rx { token, assoc_ids }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Returns the resumption token
+    pub fn token(&self) -> [u8; 16] {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.token
+    }
+
+    /// Returns the IDs of the UDP sessions resumed
+    pub fn assoc_ids(&self) -> &[u16] {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        &rx.assoc_ids
+    }
+}
+
+impl Debug for Resume<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("Resume")
+            .field("token", &rx.token)
+            .field("assoc_ids", &rx.assoc_ids)
+            .finish()
+    }
+}
@@ -2,29 +2,75 @@ use uuid::Uuid;
 
 /// Command `Authenticate`
 /// ```plain
-/// +------+-------+
-/// | UUID | TOKEN |
-/// +------+-------+
-/// |  16  |  32   |
-/// +------+-------+
+/// +------+-------+------------+
+/// | UUID | TOKEN | EXTENSIONS |
+/// +------+-------+------------+
+/// |  16  |  32   |  Variable  |
+/// +------+-------+------------+
 /// ```
 ///
 /// where:
 ///
 /// - `UUID` - client UUID
 /// - `TOKEN` - client token. The client raw password is hashed into a 256-bit long token using [TLS Keying Material Exporter](https://www.rfc-editor.org/rfc/rfc5705) on current TLS session. While exporting, the `label` should be the client UUID and the `context` should be the raw password.
+/// - `EXTENSIONS` - optional extensions until the end of the stream, each in the form of `TYPE (u16) | LEN (u16) | VALUE (LEN bytes)`. Unknown extensions are skipped by the receiver
+///
+/// Extension types:
+///
+/// - `0x0001` - `Bandwidth` - see [`Bandwidth`]
+/// - `0x0002` - `Fec` - with an empty value, declaring that the client accepts [`Parity`](super::Parity) commands for the UDP packets relayed in mode `native`
+/// - `0x0003` - `Compression` - with the minimum size of UDP packets to compress as a `u16`, declaring that the client accepts compressed UDP packets, sent as `Packet` in type `0x0c`. The client compresses its own once it receives a compressed packet, with which the server acknowledges compression
 #[derive(Clone, Debug)]
 pub struct Authenticate {
     uuid: Uuid,
     token: [u8; 32],
+    bandwidth: Option<Bandwidth>,
+    fec: bool,
+    compression: Option<u16>,
 }
 
 impl Authenticate {
     const TYPE_CODE: u8 = 0x00;
 
+    /// The maximum length of the extensions accepted by the receiver
+    pub const MAX_EXTENSIONS_LEN: usize = 1024;
+
+    pub(crate) const FEC_EXTENSION_TYPE: u16 = 0x0002;
+    pub(crate) const COMPRESSION_EXTENSION_TYPE: u16 = 0x0003;
+
     /// Creates a new `Authenticate` command
     pub const fn new(uuid: Uuid, token: [u8; 32]) -> Self {
-        Self { uuid, token }
+        Self {
+            uuid,
+            token,
+            bandwidth: None,
+            fec: false,
+            compression: None,
+        }
+    }
+
+    /// Creates a new `Authenticate` command declaring the bandwidth of the client
+    pub const fn with_bandwidth(uuid: Uuid, token: [u8; 32], bandwidth: Bandwidth) -> Self {
+        Self {
+            uuid,
+            token,
+            bandwidth: Some(bandwidth),
+            fec: false,
+            compression: None,
+        }
+    }
+
+    /// Declares that the client accepts `Parity` commands
+    pub const fn with_fec(self) -> Self {
+        Self { fec: true, ..self }
+    }
+
+    /// Declares that the client accepts compressed UDP packets, and compresses its own of at least `min_size` bytes once the server acknowledges compression
+    pub const fn with_compression(self, min_size: u16) -> Self {
+        Self {
+            compression: Some(min_size),
+            ..self
+        }
     }
 
     /// Returns the UUID
@@ -37,6 +83,21 @@ impl Authenticate {
         self.token
     }
 
+    /// Returns the bandwidth declared by the client
+    pub const fn bandwidth(&self) -> Option<Bandwidth> {
+        self.bandwidth
+    }
+
+    /// Returns whether the client accepts `Parity` commands
+    pub const fn fec(&self) -> bool {
+        self.fec
+    }
+
+    /// Returns the minimum size of UDP packets to compress, if the client declared compression
+    pub const fn compression(&self) -> Option<u16> {
+        self.compression
+    }
+
     /// Returns the command type code
     pub const fn type_code() -> u8 {
         Self::TYPE_CODE
@@ -46,11 +107,58 @@ impl Authenticate {
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         16 + 32
+            + self.bandwidth.map_or(0, |_| 4 + Bandwidth::len())
+            + if self.fec { 4 } else { 0 }
+            + self.compression.map_or(0, |_| 4 + 2)
     }
 }
 
-impl From<Authenticate> for (Uuid, [u8; 32]) {
+impl From<Authenticate> for (Uuid, [u8; 32], Option<Bandwidth>) {
     fn from(auth: Authenticate) -> Self {
-        (auth.uuid, auth.token)
+        (auth.uuid, auth.token, auth.bandwidth)
+    }
+}
+
+/// Extension `Bandwidth` of the `Authenticate` command
+/// ```plain
+/// +------+------+
+/// |  UP  | DOWN |
+/// +------+------+
+/// |  8   |  8   |
+/// +------+------+
+/// ```
+///
+/// where:
+///
+/// - `UP` - the upload bandwidth of the client, in bytes per second
+/// - `DOWN` - the download bandwidth of the client, in bytes per second. The server may send at this rate instead of probing for it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bandwidth {
+    up: u64,
+    down: u64,
+}
+
+impl Bandwidth {
+    pub(crate) const EXTENSION_TYPE: u16 = 0x0001;
+
+    /// Creates a new `Bandwidth` extension
+    pub const fn new(up: u64, down: u64) -> Self {
+        Self { up, down }
+    }
+
+    /// Returns the upload bandwidth of the client, in bytes per second
+    pub const fn up(&self) -> u64 {
+        self.up
+    }
+
+    /// Returns the download bandwidth of the client, in bytes per second
+    pub const fn down(&self) -> u64 {
+        self.down
+    }
+
+    /// Returns the serialized length of the extension value
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len() -> usize {
+        8 + 8
     }
 }
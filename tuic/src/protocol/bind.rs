@@ -0,0 +1,57 @@
+use super::Address;
+
+/// Command `Bind`
+///
+/// ```plain
+/// +---------+----------+
+/// | BIND_ID |   ADDR   |
+/// +---------+----------+
+/// |    2    | Variable |
+/// +---------+----------+
+/// ```
+///
+/// where:
+///
+/// - `BIND_ID` - TCP binding ID
+/// - `ADDR` - the address to listen on the server (from client), or the bound address (server replying on the binding stream), or the address of the inbound connection (server opening a stream for it)
+#[derive(Clone, Debug)]
+pub struct Bind {
+    bind_id: u16,
+    addr: Address,
+}
+
+impl Bind {
+    const TYPE_CODE: u8 = 0x09;
+
+    /// Creates a new `Bind` command
+    pub const fn new(bind_id: u16, addr: Address) -> Self {
+        Self { bind_id, addr }
+    }
+
+    /// Returns the TCP binding ID
+    pub fn bind_id(&self) -> u16 {
+        self.bind_id
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + self.addr.len()
+    }
+}
+
+impl From<Bind> for (u16, Address) {
+    fn from(bind: Bind) -> Self {
+        (bind.bind_id, bind.addr)
+    }
+}
@@ -0,0 +1,57 @@
+use super::Address;
+
+/// Command `BindUdp`
+///
+/// ```plain
+/// +----------+----------+
+/// | ASSOC_ID |   ADDR   |
+/// +----------+----------+
+/// |    2     | Variable |
+/// +----------+----------+
+/// ```
+///
+/// where:
+///
+/// - `ASSOC_ID` - UDP relay session ID
+/// - `ADDR` - the address to bind on the server (from client), `None` for any, or the bound address (from server)
+#[derive(Clone, Debug)]
+pub struct BindUdp {
+    assoc_id: u16,
+    addr: Address,
+}
+
+impl BindUdp {
+    const TYPE_CODE: u8 = 0x05;
+
+    /// Creates a new `BindUdp` command
+    pub const fn new(assoc_id: u16, addr: Address) -> Self {
+        Self { assoc_id, addr }
+    }
+
+    /// Returns the UDP relay session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.assoc_id
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + self.addr.len()
+    }
+}
+
+impl From<BindUdp> for (u16, Address) {
+    fn from(bind: BindUdp) -> Self {
+        (bind.assoc_id, bind.addr)
+    }
+}
@@ -1,4 +1,5 @@
 use super::Address;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 
 /// Command `Connect`
 /// ```plain
@@ -12,17 +13,41 @@ use super::Address;
 /// where:
 ///
 /// - `ADDR` - target address
+///
+/// With a congestion hint, the command is sent in type `0x06` instead, with the hint prepended:
+///
+/// ```plain
+/// +------+----------+
+/// | HINT |   ADDR   |
+/// +------+----------+
+/// |  1   | Variable |
+/// +------+----------+
+/// ```
+///
+/// where:
+///
+/// - `HINT` - the congestion hint. See [`CongestionHint`]
 #[derive(Clone, Debug)]
 pub struct Connect {
     addr: Address,
+    hint: Option<CongestionHint>,
 }
 
 impl Connect {
     const TYPE_CODE: u8 = 0x01;
+    const TYPE_CODE_HINTED: u8 = 0x06;
 
     /// Creates a new `Connect` command
     pub const fn new(addr: Address) -> Self {
-        Self { addr }
+        Self { addr, hint: None }
+    }
+
+    /// Creates a new `Connect` command with a congestion hint
+    pub const fn with_hint(addr: Address, hint: CongestionHint) -> Self {
+        Self {
+            addr,
+            hint: Some(hint),
+        }
     }
 
     /// Returns the address
@@ -30,20 +55,73 @@ impl Connect {
         &self.addr
     }
 
+    /// Returns the congestion hint
+    pub const fn hint(&self) -> Option<CongestionHint> {
+        self.hint
+    }
+
     /// Returns the command type code
     pub const fn type_code() -> u8 {
         Self::TYPE_CODE
     }
 
+    /// Returns the command type code of the command with a congestion hint
+    pub const fn type_code_hinted() -> u8 {
+        Self::TYPE_CODE_HINTED
+    }
+
     /// Returns the serialized length of the command
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
-        self.addr.len()
+        self.hint.map_or(0, |_| 1) + self.addr.len()
     }
 }
 
-impl From<Connect> for (Address,) {
+impl From<Connect> for (Address, Option<CongestionHint>) {
     fn from(conn: Connect) -> Self {
-        (conn.addr,)
+        (conn.addr, conn.hint)
+    }
+}
+
+/// The traffic class of a `Connect` task, which the server may map to stream priorities and buffer sizes
+///
+/// - `0x00` - `Interactive` - latency-sensitive traffic, e.g. SSH sessions
+/// - `0x01` - `Bulk` - throughput-oriented traffic, e.g. downloads
+///
+/// Unknown hints are ignored by the receiver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CongestionHint {
+    Interactive,
+    Bulk,
+}
+
+impl CongestionHint {
+    pub const CODE_INTERACTIVE: u8 = 0x00;
+    pub const CODE_BULK: u8 = 0x01;
+
+    /// Returns the hint code
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::Interactive => Self::CODE_INTERACTIVE,
+            Self::Bulk => Self::CODE_BULK,
+        }
+    }
+
+    /// Returns the hint of the code, or `None` if it is unknown
+    pub const fn from_code(code: u8) -> Option<Self> {
+        match code {
+            Self::CODE_INTERACTIVE => Some(Self::Interactive),
+            Self::CODE_BULK => Some(Self::Bulk),
+            _ => None,
+        }
+    }
+}
+
+impl Display for CongestionHint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Interactive => write!(f, "interactive"),
+            Self::Bulk => write!(f, "bulk"),
+        }
     }
 }
@@ -0,0 +1,48 @@
+/// Command `DissociateAck`
+///
+/// ```plain
+/// +----------+
+/// | ASSOC_ID |
+/// +----------+
+/// |    2     |
+/// +----------+
+/// ```
+///
+/// where:
+///
+/// - `ASSOC_ID` - UDP relay session ID
+#[derive(Clone, Debug)]
+pub struct DissociateAck {
+    assoc_id: u16,
+}
+
+impl DissociateAck {
+    const TYPE_CODE: u8 = 0x07;
+
+    /// Creates a new `DissociateAck` command
+    pub const fn new(assoc_id: u16) -> Self {
+        Self { assoc_id }
+    }
+
+    /// Returns the UDP relay session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.assoc_id
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2
+    }
+}
+
+impl From<DissociateAck> for (u16,) {
+    fn from(ack: DissociateAck) -> Self {
+        (ack.assoc_id,)
+    }
+}
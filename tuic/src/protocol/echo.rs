@@ -0,0 +1,49 @@
+/// Command `Echo`
+///
+/// ```plain
+/// +-----+---------+
+/// | LEN | PAYLOAD |
+/// +-----+---------+
+/// |  2  |   LEN   |
+/// +-----+---------+
+/// ```
+///
+/// where:
+///
+/// - `LEN` - length of the payload
+/// - `PAYLOAD` - arbitrary bytes, reflected by the server unchanged
+#[derive(Clone, Debug)]
+pub struct Echo {
+    payload: Vec<u8>,
+}
+
+impl Echo {
+    const TYPE_CODE: u8 = 0x0a;
+
+    /// Creates a new `Echo` command. The payload cannot exceed 65535 bytes
+    pub const fn new(payload: Vec<u8>) -> Self {
+        Self { payload }
+    }
+
+    /// Returns the payload
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + self.payload.len()
+    }
+}
+
+impl From<Echo> for (Vec<u8>,) {
+    fn from(echo: Echo) -> Self {
+        (echo.payload,)
+    }
+}
@@ -1,23 +1,46 @@
 use std::{
+    error::Error,
     fmt::{Display, Formatter, Result as FmtResult},
     mem,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr, SocketAddrV6},
+    str::FromStr,
 };
 
 mod authenticate;
+mod bind;
+mod bind_udp;
 mod connect;
 mod dissociate;
+mod dissociate_ack;
+mod echo;
 mod heartbeat;
 mod packet;
+mod parity;
+mod resume;
 
 pub use self::{
-    authenticate::Authenticate, connect::Connect, dissociate::Dissociate, heartbeat::Heartbeat,
+    authenticate::{Authenticate, Bandwidth},
+    bind::Bind,
+    bind_udp::BindUdp,
+    connect::{CongestionHint, Connect},
+    dissociate::Dissociate,
+    dissociate_ack::DissociateAck,
+    echo::Echo,
+    heartbeat::Heartbeat,
     packet::Packet,
+    parity::Parity,
+    resume::Resume,
 };
 
 /// The TUIC protocol version
 pub const VERSION: u8 = 0x05;
 
+/// The maximum length of a domain in its ASCII form, without the trailing dot
+const MAX_DOMAIN_LEN: usize = 253;
+
+/// The maximum length of a label of a domain
+const MAX_LABEL_LEN: usize = 63;
+
 /// The command header for negotiating tasks
 /// ```plain
 /// +-----+------+----------+
@@ -35,15 +58,23 @@ pub const VERSION: u8 = 0x05;
 ///
 /// ## Command Types
 ///
-/// There are five types of command:
+/// There are thirteen types of command:
 ///
 /// - `0x00` - `Authenticate` - for authenticating the multiplexed stream
 /// - `0x01` - `Connect` - for establishing a TCP relay
 /// - `0x02` - `Packet` - for relaying (fragmented part of) a UDP packet
 /// - `0x03` - `Dissociate` - for terminating a UDP relaying session
 /// - `0x04` - `Heartbeat` - for keeping the QUIC connection alive
+/// - `0x05` - `BindUdp` - for binding a UDP relay session that receives packets from any source before sending any
+/// - `0x06` - `Connect` with a congestion hint
+/// - `0x07` - `DissociateAck` - for confirming that a UDP relaying session is terminated
+/// - `0x08` - `Resume` - for resuming the UDP relaying sessions of a previous connection
+/// - `0x09` - `Bind` - for listening on a TCP port of the server and relaying inbound connections back to the client
+/// - `0x0a` - `Echo` - for having a payload reflected by the server, verifying the protocol end to end
+/// - `0x0b` - `Parity` - for recovering a lost fragment of a UDP packet
+/// - `0x0c` - `Packet` carrying a compressed UDP packet
 ///
-/// Command `Connect` and `Packet` carry payload (stream / packet fragment)
+/// Command `Connect`, `Packet` and `Parity` carry payload (stream / packet fragment / parity)
 #[non_exhaustive]
 #[derive(Clone, Debug)]
 pub enum Header {
@@ -52,23 +83,45 @@ pub enum Header {
     Packet(Packet),
     Dissociate(Dissociate),
     Heartbeat(Heartbeat),
+    BindUdp(BindUdp),
+    DissociateAck(DissociateAck),
+    Resume(Resume),
+    Bind(Bind),
+    Echo(Echo),
+    Parity(Parity),
 }
 
 impl Header {
     pub const TYPE_CODE_AUTHENTICATE: u8 = Authenticate::type_code();
     pub const TYPE_CODE_CONNECT: u8 = Connect::type_code();
+    pub const TYPE_CODE_CONNECT_HINTED: u8 = Connect::type_code_hinted();
     pub const TYPE_CODE_PACKET: u8 = Packet::type_code();
+    pub const TYPE_CODE_PACKET_COMPRESSED: u8 = Packet::type_code_compressed();
     pub const TYPE_CODE_DISSOCIATE: u8 = Dissociate::type_code();
     pub const TYPE_CODE_HEARTBEAT: u8 = Heartbeat::type_code();
+    pub const TYPE_CODE_BIND_UDP: u8 = BindUdp::type_code();
+    pub const TYPE_CODE_DISSOCIATE_ACK: u8 = DissociateAck::type_code();
+    pub const TYPE_CODE_RESUME: u8 = Resume::type_code();
+    pub const TYPE_CODE_BIND: u8 = Bind::type_code();
+    pub const TYPE_CODE_ECHO: u8 = Echo::type_code();
+    pub const TYPE_CODE_PARITY: u8 = Parity::type_code();
 
     /// Returns the command type code
     pub const fn type_code(&self) -> u8 {
         match self {
             Self::Authenticate(_) => Authenticate::type_code(),
+            Self::Connect(conn) if conn.hint().is_some() => Connect::type_code_hinted(),
             Self::Connect(_) => Connect::type_code(),
+            Self::Packet(pkt) if pkt.is_compressed() => Packet::type_code_compressed(),
             Self::Packet(_) => Packet::type_code(),
             Self::Dissociate(_) => Dissociate::type_code(),
             Self::Heartbeat(_) => Heartbeat::type_code(),
+            Self::BindUdp(_) => BindUdp::type_code(),
+            Self::DissociateAck(_) => DissociateAck::type_code(),
+            Self::Resume(_) => Resume::type_code(),
+            Self::Bind(_) => Bind::type_code(),
+            Self::Echo(_) => Echo::type_code(),
+            Self::Parity(_) => Parity::type_code(),
         }
     }
 
@@ -81,6 +134,12 @@ impl Header {
             Self::Packet(packet) => packet.len(),
             Self::Dissociate(dissociate) => dissociate.len(),
             Self::Heartbeat(heartbeat) => heartbeat.len(),
+            Self::BindUdp(bind) => bind.len(),
+            Self::DissociateAck(ack) => ack.len(),
+            Self::Resume(resume) => resume.len(),
+            Self::Bind(bind) => bind.len(),
+            Self::Echo(echo) => echo.len(),
+            Self::Parity(parity) => parity.len(),
         }
     }
 }
@@ -111,8 +170,11 @@ impl Header {
 /// Address type `None` is used in `Packet` commands that is not the first fragment of a UDP packet.
 ///
 /// The port number is encoded in 2 bytes after the Domain name / IP address.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+///
+/// Domain addresses should be created with [`Address::domain`], which canonicalizes the domain, so that the same domain compares equal and matches the same rules on both sides however it is written.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Address {
+    #[default]
     None,
     DomainAddress(String, u16),
     SocketAddress(SocketAddr),
@@ -124,6 +186,108 @@ impl Address {
     pub const TYPE_CODE_IPV4: u8 = 0x01;
     pub const TYPE_CODE_IPV6: u8 = 0x02;
 
+    /// Creates a domain address, canonicalizing the domain
+    ///
+    /// The domain is lowercased with its trailing dot removed, and internationalized domain names are converted to their ASCII form (punycode). Domains longer than 253 bytes in their ASCII form, or with a label empty or longer than 63 bytes, are rejected.
+    pub fn domain(domain: &str, port: u16) -> Result<Self, AddressError> {
+        let stripped = domain.strip_suffix('.').unwrap_or(domain);
+
+        let ascii = if stripped.is_ascii() {
+            stripped.to_ascii_lowercase()
+        } else {
+            idna::domain_to_ascii(stripped)
+                .map_err(|_| AddressError::InvalidIdn(domain.to_owned()))?
+        };
+
+        if ascii.is_empty() {
+            return Err(AddressError::EmptyDomain);
+        }
+
+        if ascii.len() > MAX_DOMAIN_LEN {
+            return Err(AddressError::DomainTooLong(ascii.len()));
+        }
+
+        if let Some(label) = ascii
+            .split('.')
+            .find(|label| label.is_empty() || label.len() > MAX_LABEL_LEN)
+        {
+            return Err(AddressError::InvalidLabel(label.to_owned()));
+        }
+
+        Ok(Self::DomainAddress(ascii, port))
+    }
+
+    /// Parses the address in the form of `HOST:PORT`, or `HOST` alone with the default port
+    ///
+    /// See the [`FromStr`] implementation for the accepted forms of `HOST`.
+    pub fn parse_with_default_port(s: &str, default_port: u16) -> Result<Self, AddressError> {
+        Self::parse(s, Some(default_port))
+    }
+
+    /// Formats the address like its [`Display`] implementation, leaving the port out if it is the default one
+    ///
+    /// The result is parsed back with [`Address::parse_with_default_port`] with the same default port.
+    pub fn display_with_default_port(&self, default_port: u16) -> impl Display + '_ {
+        DisplayWithDefaultPort(self, default_port)
+    }
+
+    fn parse(s: &str, default_port: Option<u16>) -> Result<Self, AddressError> {
+        if s == "none" {
+            return Ok(Self::None);
+        }
+
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::SocketAddress(addr));
+        }
+
+        // a bare IPv6 address has no port, as its last group can not be told apart from one
+        let (host, port) = if s.starts_with('[') || s.parse::<IpAddr>().is_ok() {
+            match s.find(']').map(|idx| s.split_at(idx + 1)) {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => match port.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(AddressError::InvalidHost(s.to_owned())),
+                },
+                None => (s, None),
+            }
+        } else {
+            match s.rsplit_once(':') {
+                // an IPv6 address with a port must be in brackets
+                Some((host, _)) if host.contains(':') => {
+                    return Err(AddressError::InvalidHost(s.to_owned()))
+                }
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            }
+        };
+
+        let port = match (port, default_port) {
+            (Some(port), _) => port
+                .parse()
+                .map_err(|_| AddressError::InvalidPort(port.to_owned()))?,
+            (None, Some(port)) => port,
+            (None, None) => return Err(AddressError::MissingPort),
+        };
+
+        if let Some(ip) = host.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')) {
+            // parsed as a socket address for the scope ID
+            return format!("[{ip}]:{port}")
+                .parse::<SocketAddrV6>()
+                .map(|addr| Self::SocketAddress(SocketAddr::V6(addr)))
+                .map_err(|_| AddressError::InvalidHost(host.to_owned()));
+        }
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(Self::SocketAddress(SocketAddr::new(ip, port)));
+        }
+
+        if host.contains([':', '[', ']']) {
+            return Err(AddressError::InvalidHost(host.to_owned()));
+        }
+
+        Self::domain(host, port)
+    }
+
     /// Returns the address type code
     pub const fn type_code(&self) -> u8 {
         match self {
@@ -183,8 +347,166 @@ impl Display for Address {
     }
 }
 
-impl Default for Address {
-    fn default() -> Self {
-        Self::None
+/// Parses the address in the form of `HOST:PORT`, as formatted by its [`Display`] implementation
+///
+/// `HOST` can be a domain, which is canonicalized as in [`Address::domain`], an IPv4 address, or an IPv6 address in brackets, optionally with a scope ID. `none` is parsed as [`Address::None`].
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, None)
+    }
+}
+
+struct DisplayWithDefaultPort<'a>(&'a Address, u16);
+
+impl Display for DisplayWithDefaultPort<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Self(addr, default_port) = self;
+
+        match addr {
+            Address::DomainAddress(domain, port) if port == default_port => write!(f, "{domain}"),
+            Address::SocketAddress(SocketAddr::V4(addr)) if addr.port() == *default_port => {
+                write!(f, "{}", addr.ip())
+            }
+            Address::SocketAddress(SocketAddr::V6(addr)) if addr.port() == *default_port => {
+                match addr.scope_id() {
+                    0 => write!(f, "[{}]", addr.ip()),
+                    scope_id => write!(f, "[{}%{scope_id}]", addr.ip()),
+                }
+            }
+            _ => write!(f, "{addr}"),
+        }
+    }
+}
+
+/// Errors of parsing an address, or of canonicalizing the domain of a domain address
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AddressError {
+    /// The address has no port, with no default one
+    MissingPort,
+    /// The port is not a number from 0 to 65535
+    InvalidPort(String),
+    /// The host is neither an IP address nor a domain, e.g. an IPv6 address with a port but no brackets
+    InvalidHost(String),
+    /// The domain is empty
+    EmptyDomain,
+    /// The domain is longer than 253 bytes in its ASCII form
+    DomainTooLong(usize),
+    /// A label of the domain is empty or longer than 63 bytes
+    InvalidLabel(String),
+    /// The internationalized domain name can not be converted to its ASCII form
+    InvalidIdn(String),
+}
+
+impl Display for AddressError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::MissingPort => write!(f, "missing port, expecting `HOST:PORT`"),
+            Self::InvalidPort(port) => write!(f, "invalid port: {port}"),
+            Self::InvalidHost(host) => write!(f, "invalid host: {host}"),
+            Self::EmptyDomain => write!(f, "empty domain"),
+            Self::DomainTooLong(len) => write!(f, "domain too long: {len} bytes"),
+            Self::InvalidLabel(label) => write!(f, "invalid domain label: {label:?}"),
+            Self::InvalidIdn(domain) => {
+                write!(f, "invalid internationalized domain name: {domain}")
+            }
+        }
+    }
+}
+
+impl Error for AddressError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn round_trip(addr: Address, default_port: u16, expected: &str) {
+        let s = addr.display_with_default_port(default_port).to_string();
+        assert_eq!(s, expected);
+        assert_eq!(
+            Address::parse_with_default_port(&s, default_port),
+            Ok(addr.clone())
+        );
+        assert_eq!(addr.to_string().parse::<Address>(), Ok(addr));
+    }
+
+    fn ipv6(port: u16, scope_id: u32) -> Address {
+        let ip = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        Address::SocketAddress(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+    }
+
+    #[test]
+    fn bracketed_ipv6() {
+        round_trip(ipv6(443, 0), 443, "[fe80::1]");
+        round_trip(ipv6(8443, 0), 443, "[fe80::1]:8443");
+        round_trip(ipv6(443, 2), 443, "[fe80::1%2]");
+        round_trip(ipv6(8443, 2), 443, "[fe80::1%2]:8443");
+    }
+
+    #[test]
+    fn bare_ipv6() {
+        assert_eq!(
+            Address::parse_with_default_port("fe80::1", 443),
+            Ok(ipv6(443, 0))
+        );
+        assert_eq!("fe80::1".parse::<Address>(), Err(AddressError::MissingPort));
+    }
+
+    #[test]
+    fn ipv6_with_port_without_brackets() {
+        assert_eq!(
+            Address::parse_with_default_port("::ffff:1.2.3.4:443", 443),
+            Err(AddressError::InvalidHost("::ffff:1.2.3.4:443".to_owned()))
+        );
+        assert_eq!(
+            "fe80::1%2:443".parse::<Address>(),
+            Err(AddressError::InvalidHost("fe80::1%2:443".to_owned()))
+        );
+    }
+
+    #[test]
+    fn domain() {
+        let addr = Address::DomainAddress("example.com".to_owned(), 443);
+        round_trip(addr.clone(), 443, "example.com");
+        round_trip(addr, 80, "example.com:443");
+
+        assert_eq!(
+            Address::parse_with_default_port("Example.COM.", 443),
+            Ok(Address::DomainAddress("example.com".to_owned(), 443))
+        );
+        assert_eq!(
+            "example.com".parse::<Address>(),
+            Err(AddressError::MissingPort)
+        );
+    }
+
+    #[test]
+    fn ipv4() {
+        let addr = Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 443)));
+        round_trip(addr.clone(), 443, "127.0.0.1");
+        round_trip(addr, 80, "127.0.0.1:443");
+    }
+
+    #[test]
+    fn none() {
+        round_trip(Address::None, 443, "none");
+    }
+
+    #[test]
+    fn invalid_port() {
+        for (s, port) in [
+            ("example.com:", ""),
+            ("example.com:http", "http"),
+            ("127.0.0.1:65536", "65536"),
+            ("[fe80::1]:", ""),
+            ("[fe80::1]:http", "http"),
+        ] {
+            assert_eq!(
+                Address::parse_with_default_port(s, 443),
+                Err(AddressError::InvalidPort(port.to_owned()))
+            );
+        }
     }
 }
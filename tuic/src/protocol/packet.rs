@@ -17,6 +17,8 @@ use super::Address;
 /// - `FRAG_ID` - fragment ID of the UDP packet
 /// - `SIZE` - length of the (fragmented) UDP packet
 /// - `ADDR` - target (from client) or source (from server) address
+///
+/// A fragment of a compressed UDP packet is sent in type `0x0c` instead, with the same layout. The fragments then carry the compressed packet, and `SIZE` is the length of the (fragmented) compressed packet
 #[derive(Clone, Debug)]
 pub struct Packet {
     assoc_id: u16,
@@ -25,10 +27,12 @@ pub struct Packet {
     frag_id: u8,
     size: u16,
     addr: Address,
+    compressed: bool,
 }
 
 impl Packet {
     const TYPE_CODE: u8 = 0x02;
+    const TYPE_CODE_COMPRESSED: u8 = 0x0c;
 
     /// Creates a new `Packet` command
     pub const fn new(
@@ -46,9 +50,16 @@ impl Packet {
             frag_id,
             size,
             addr,
+            compressed: false,
         }
     }
 
+    /// Marks the command as a fragment of a compressed UDP packet
+    pub const fn compressed(mut self) -> Self {
+        self.compressed = true;
+        self
+    }
+
     /// Returns the UDP relay session ID
     pub fn assoc_id(&self) -> u16 {
         self.assoc_id
@@ -79,11 +90,21 @@ impl Packet {
         &self.addr
     }
 
+    /// Returns whether the command carries a fragment of a compressed UDP packet
+    pub const fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
     /// Returns the command type code
     pub const fn type_code() -> u8 {
         Self::TYPE_CODE
     }
 
+    /// Returns the command type code of a fragment of a compressed UDP packet
+    pub const fn type_code_compressed() -> u8 {
+        Self::TYPE_CODE_COMPRESSED
+    }
+
     /// Returns the serialized length of the command
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
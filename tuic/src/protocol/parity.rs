@@ -0,0 +1,107 @@
+use super::Address;
+
+/// Command `Parity`
+/// ```plain
+/// +----------+--------+------------+----------+------+----------+
+/// | ASSOC_ID | PKT_ID | FRAG_TOTAL | PKT_SIZE | SIZE |   ADDR   |
+/// +----------+--------+------------+----------+------+----------+
+/// |    2     |   2    |     1      |    2     |  2   | Variable |
+/// +----------+--------+------------+----------+------+----------+
+/// ```
+///
+/// where:
+///
+/// - `ASSOC_ID` - UDP relay session ID
+/// - `PKT_ID` - UDP packet ID
+/// - `FRAG_TOTAL` - total number of fragments of the UDP packet
+/// - `PKT_SIZE` - length of the whole UDP packet
+/// - `SIZE` - length of the parity, which is the XOR of the payloads of all fragments, each zero-padded to the length of the longest
+/// - `ADDR` - target (from client) or source (from server) address of the UDP packet
+///
+/// A single lost fragment of the UDP packet can be recovered from the parity and the other fragments.
+#[derive(Clone, Debug)]
+pub struct Parity {
+    assoc_id: u16,
+    pkt_id: u16,
+    frag_total: u8,
+    pkt_size: u16,
+    size: u16,
+    addr: Address,
+}
+
+impl Parity {
+    const TYPE_CODE: u8 = 0x0b;
+
+    /// Creates a new `Parity` command
+    pub const fn new(
+        assoc_id: u16,
+        pkt_id: u16,
+        frag_total: u8,
+        pkt_size: u16,
+        size: u16,
+        addr: Address,
+    ) -> Self {
+        Self {
+            assoc_id,
+            pkt_id,
+            frag_total,
+            pkt_size,
+            size,
+            addr,
+        }
+    }
+
+    /// Returns the UDP relay session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.assoc_id
+    }
+
+    /// Returns the packet ID
+    pub fn pkt_id(&self) -> u16 {
+        self.pkt_id
+    }
+
+    /// Returns the total number of fragments of the UDP packet
+    pub fn frag_total(&self) -> u8 {
+        self.frag_total
+    }
+
+    /// Returns the length of the whole UDP packet
+    pub fn pkt_size(&self) -> u16 {
+        self.pkt_size
+    }
+
+    /// Returns the length of the parity
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the target (from client) or source (from server) address of the UDP packet
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + 2 + 1 + 2 + 2 + self.addr.len()
+    }
+}
+
+impl From<Parity> for (u16, u16, u8, u16, u16, Address) {
+    fn from(parity: Parity) -> Self {
+        (
+            parity.assoc_id,
+            parity.pkt_id,
+            parity.frag_total,
+            parity.pkt_size,
+            parity.size,
+            parity.addr,
+        )
+    }
+}
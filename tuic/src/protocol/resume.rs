@@ -0,0 +1,56 @@
+/// Command `Resume`
+///
+/// ```plain
+/// +-------+-----+----------+
+/// | TOKEN | NUM | ASSOC_ID |
+/// +-------+-----+----------+
+/// |  16   |  2  | 2 * NUM  |
+/// +-------+-----+----------+
+/// ```
+///
+/// where:
+///
+/// - `TOKEN` - the resumption token issued by the server for the previous connection, or all zeros for none (from client), or the token issued for the current connection (from server)
+/// - `NUM` - number of the UDP relay sessions resumed, `0` from client
+/// - `ASSOC_ID` - the IDs of the UDP relay sessions resumed
+#[derive(Clone, Debug)]
+pub struct Resume {
+    token: [u8; 16],
+    assoc_ids: Vec<u16>,
+}
+
+impl Resume {
+    const TYPE_CODE: u8 = 0x08;
+
+    /// Creates a new `Resume` command
+    pub const fn new(token: [u8; 16], assoc_ids: Vec<u16>) -> Self {
+        Self { token, assoc_ids }
+    }
+
+    /// Returns the resumption token
+    pub fn token(&self) -> [u8; 16] {
+        self.token
+    }
+
+    /// Returns the IDs of the UDP relay sessions resumed
+    pub fn assoc_ids(&self) -> &[u16] {
+        &self.assoc_ids
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        16 + 2 + self.assoc_ids.len() * 2
+    }
+}
+
+impl From<Resume> for ([u8; 16], Vec<u16>) {
+    fn from(resume: Resume) -> Self {
+        (resume.token, resume.assoc_ids)
+    }
+}
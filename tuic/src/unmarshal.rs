@@ -1,4 +1,8 @@
-use crate::{Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION};
+use crate::{
+    Address, AddressError, Authenticate, Bandwidth, Bind, BindUdp, CongestionHint, Connect,
+    Dissociate, DissociateAck, Echo, Header, Heartbeat, Packet, Parity, Resume, VERSION,
+};
+#[cfg(feature = "async_marshal")]
 use futures_util::{AsyncRead, AsyncReadExt};
 use std::{
     io::{Error as IoError, Read},
@@ -29,9 +33,23 @@ impl Header {
                 Authenticate::async_read(s).await.map(Self::Authenticate)
             }
             Header::TYPE_CODE_CONNECT => Connect::async_read(s).await.map(Self::Connect),
+            Header::TYPE_CODE_CONNECT_HINTED => {
+                Connect::async_read_hinted(s).await.map(Self::Connect)
+            }
             Header::TYPE_CODE_PACKET => Packet::async_read(s).await.map(Self::Packet),
+            Header::TYPE_CODE_PACKET_COMPRESSED => Packet::async_read(s)
+                .await
+                .map(|pkt| Self::Packet(pkt.compressed())),
             Header::TYPE_CODE_DISSOCIATE => Dissociate::async_read(s).await.map(Self::Dissociate),
             Header::TYPE_CODE_HEARTBEAT => Heartbeat::async_read(s).await.map(Self::Heartbeat),
+            Header::TYPE_CODE_BIND_UDP => BindUdp::async_read(s).await.map(Self::BindUdp),
+            Header::TYPE_CODE_DISSOCIATE_ACK => {
+                DissociateAck::async_read(s).await.map(Self::DissociateAck)
+            }
+            Header::TYPE_CODE_RESUME => Resume::async_read(s).await.map(Self::Resume),
+            Header::TYPE_CODE_BIND => Bind::async_read(s).await.map(Self::Bind),
+            Header::TYPE_CODE_ECHO => Echo::async_read(s).await.map(Self::Echo),
+            Header::TYPE_CODE_PARITY => Parity::async_read(s).await.map(Self::Parity),
             _ => Err(UnmarshalError::InvalidCommand(cmd)),
         }
     }
@@ -54,9 +72,19 @@ impl Header {
         match cmd {
             Header::TYPE_CODE_AUTHENTICATE => Authenticate::read(s).map(Self::Authenticate),
             Header::TYPE_CODE_CONNECT => Connect::read(s).map(Self::Connect),
+            Header::TYPE_CODE_CONNECT_HINTED => Connect::read_hinted(s).map(Self::Connect),
             Header::TYPE_CODE_PACKET => Packet::read(s).map(Self::Packet),
+            Header::TYPE_CODE_PACKET_COMPRESSED => {
+                Packet::read(s).map(|pkt| Self::Packet(pkt.compressed()))
+            }
             Header::TYPE_CODE_DISSOCIATE => Dissociate::read(s).map(Self::Dissociate),
             Header::TYPE_CODE_HEARTBEAT => Heartbeat::read(s).map(Self::Heartbeat),
+            Header::TYPE_CODE_BIND_UDP => BindUdp::read(s).map(Self::BindUdp),
+            Header::TYPE_CODE_DISSOCIATE_ACK => DissociateAck::read(s).map(Self::DissociateAck),
+            Header::TYPE_CODE_RESUME => Resume::read(s).map(Self::Resume),
+            Header::TYPE_CODE_BIND => Bind::read(s).map(Self::Bind),
+            Header::TYPE_CODE_ECHO => Echo::read(s).map(Self::Echo),
+            Header::TYPE_CODE_PARITY => Parity::read(s).map(Self::Parity),
             _ => Err(UnmarshalError::InvalidCommand(cmd)),
         }
     }
@@ -82,7 +110,7 @@ impl Address {
                 buf.truncate(len);
                 let domain = String::from_utf8(buf)?;
 
-                Ok(Self::DomainAddress(domain, port))
+                Ok(Self::domain(&domain, port)?)
             }
             Address::TYPE_CODE_IPV4 => {
                 let mut buf = [0; 6];
@@ -131,7 +159,7 @@ impl Address {
                 buf.truncate(len);
                 let domain = String::from_utf8(buf)?;
 
-                Ok(Self::DomainAddress(domain, port))
+                Ok(Self::domain(&domain, port)?)
             }
             Address::TYPE_CODE_IPV4 => {
                 let mut buf = [0; 6];
@@ -169,7 +197,13 @@ impl Authenticate {
         s.read_exact(&mut buf).await?;
         let uuid = Uuid::from_slice(&buf[..16])?;
         let token = TryFrom::try_from(&buf[16..]).unwrap();
-        Ok(Self::new(uuid, token))
+
+        let mut exts = Vec::new();
+        s.take(Self::MAX_EXTENSIONS_LEN as u64)
+            .read_to_end(&mut exts)
+            .await?;
+
+        Ok(Self::with_extensions(uuid, token, &exts))
     }
 
     #[cfg(feature = "marshal")]
@@ -178,7 +212,55 @@ impl Authenticate {
         s.read_exact(&mut buf)?;
         let uuid = Uuid::from_slice(&buf[..16])?;
         let token = TryFrom::try_from(&buf[16..]).unwrap();
-        Ok(Self::new(uuid, token))
+
+        let mut exts = Vec::new();
+        s.take(Self::MAX_EXTENSIONS_LEN as u64)
+            .read_to_end(&mut exts)?;
+
+        Ok(Self::with_extensions(uuid, token, &exts))
+    }
+
+    /// Parses the extensions, skipping unknown ones. Malformed extensions end the parsing, as they only carry optional information
+    fn with_extensions(uuid: Uuid, token: [u8; 32], mut exts: &[u8]) -> Self {
+        let mut bandwidth = None;
+        let mut fec = false;
+        let mut compression = None;
+
+        while exts.len() >= 4 {
+            let ext_type = u16::from_be_bytes([exts[0], exts[1]]);
+            let len = u16::from_be_bytes([exts[2], exts[3]]) as usize;
+
+            let Some(value) = exts.get(4..4 + len) else {
+                break;
+            };
+
+            if ext_type == Bandwidth::EXTENSION_TYPE && len == Bandwidth::len() {
+                let up = u64::from_be_bytes(value[..8].try_into().unwrap());
+                let down = u64::from_be_bytes(value[8..].try_into().unwrap());
+                bandwidth = Some(Bandwidth::new(up, down));
+            } else if ext_type == Self::FEC_EXTENSION_TYPE {
+                fec = true;
+            } else if ext_type == Self::COMPRESSION_EXTENSION_TYPE && len == 2 {
+                compression = Some(u16::from_be_bytes([value[0], value[1]]));
+            }
+
+            exts = &exts[4 + len..];
+        }
+
+        let mut auth = match bandwidth {
+            Some(bandwidth) => Self::with_bandwidth(uuid, token, bandwidth),
+            None => Self::new(uuid, token),
+        };
+
+        if fec {
+            auth = auth.with_fec();
+        }
+
+        if let Some(min_size) = compression {
+            auth = auth.with_compression(min_size);
+        }
+
+        auth
     }
 }
 
@@ -192,6 +274,30 @@ impl Connect {
     fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
         Ok(Self::new(Address::read(s)?))
     }
+
+    #[cfg(feature = "async_marshal")]
+    async fn async_read_hinted(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 1];
+        s.read_exact(&mut buf).await?;
+        let addr = Address::async_read(s).await?;
+
+        Ok(match CongestionHint::from_code(buf[0]) {
+            Some(hint) => Self::with_hint(addr, hint),
+            None => Self::new(addr),
+        })
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read_hinted(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 1];
+        s.read_exact(&mut buf)?;
+        let addr = Address::read(s)?;
+
+        Ok(match CongestionHint::from_code(buf[0]) {
+            Some(hint) => Self::with_hint(addr, hint),
+            None => Self::new(addr),
+        })
+    }
 }
 
 impl Packet {
@@ -226,6 +332,42 @@ impl Packet {
     }
 }
 
+impl Parity {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 9];
+        s.read_exact(&mut buf).await?;
+
+        let assoc_id = u16::from_be_bytes([buf[0], buf[1]]);
+        let pkt_id = u16::from_be_bytes([buf[2], buf[3]]);
+        let frag_total = buf[4];
+        let pkt_size = u16::from_be_bytes([buf[5], buf[6]]);
+        let size = u16::from_be_bytes([buf[7], buf[8]]);
+        let addr = Address::async_read(s).await?;
+
+        Ok(Self::new(
+            assoc_id, pkt_id, frag_total, pkt_size, size, addr,
+        ))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 9];
+        s.read_exact(&mut buf)?;
+
+        let assoc_id = u16::from_be_bytes([buf[0], buf[1]]);
+        let pkt_id = u16::from_be_bytes([buf[2], buf[3]]);
+        let frag_total = buf[4];
+        let pkt_size = u16::from_be_bytes([buf[5], buf[6]]);
+        let size = u16::from_be_bytes([buf[7], buf[8]]);
+        let addr = Address::read(s)?;
+
+        Ok(Self::new(
+            assoc_id, pkt_id, frag_total, pkt_size, size, addr,
+        ))
+    }
+}
+
 impl Dissociate {
     #[cfg(feature = "async_marshal")]
     async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
@@ -244,6 +386,60 @@ impl Dissociate {
     }
 }
 
+impl DissociateAck {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf).await?;
+        let assoc_id = u16::from_be_bytes(buf);
+        Ok(Self::new(assoc_id))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf)?;
+        let assoc_id = u16::from_be_bytes(buf);
+        Ok(Self::new(assoc_id))
+    }
+}
+
+impl Resume {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 18];
+        s.read_exact(&mut buf).await?;
+        let token = TryFrom::try_from(&buf[..16]).unwrap();
+        let num = u16::from_be_bytes([buf[16], buf[17]]);
+
+        let mut buf = vec![0; num as usize * 2];
+        s.read_exact(&mut buf).await?;
+        let assoc_ids = buf
+            .chunks_exact(2)
+            .map(|id| u16::from_be_bytes([id[0], id[1]]))
+            .collect();
+
+        Ok(Self::new(token, assoc_ids))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 18];
+        s.read_exact(&mut buf)?;
+        let token = TryFrom::try_from(&buf[..16]).unwrap();
+        let num = u16::from_be_bytes([buf[16], buf[17]]);
+
+        let mut buf = vec![0; num as usize * 2];
+        s.read_exact(&mut buf)?;
+        let assoc_ids = buf
+            .chunks_exact(2)
+            .map(|id| u16::from_be_bytes([id[0], id[1]]))
+            .collect();
+
+        Ok(Self::new(token, assoc_ids))
+    }
+}
+
 impl Heartbeat {
     #[cfg(feature = "async_marshal")]
     async fn async_read(_s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
@@ -256,6 +452,70 @@ impl Heartbeat {
     }
 }
 
+impl BindUdp {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf).await?;
+        let assoc_id = u16::from_be_bytes(buf);
+        let addr = Address::async_read(s).await?;
+        Ok(Self::new(assoc_id, addr))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf)?;
+        let assoc_id = u16::from_be_bytes(buf);
+        let addr = Address::read(s)?;
+        Ok(Self::new(assoc_id, addr))
+    }
+}
+
+impl Bind {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf).await?;
+        let bind_id = u16::from_be_bytes(buf);
+        let addr = Address::async_read(s).await?;
+        Ok(Self::new(bind_id, addr))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf)?;
+        let bind_id = u16::from_be_bytes(buf);
+        let addr = Address::read(s)?;
+        Ok(Self::new(bind_id, addr))
+    }
+}
+
+impl Echo {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf).await?;
+        let len = u16::from_be_bytes(buf);
+
+        let mut buf = vec![0; len as usize];
+        s.read_exact(&mut buf).await?;
+        Ok(Self::new(buf))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf)?;
+        let len = u16::from_be_bytes(buf);
+
+        let mut buf = vec![0; len as usize];
+        s.read_exact(&mut buf)?;
+        Ok(Self::new(buf))
+    }
+}
+
 /// Errors that can occur when unmarshalling a packet
 #[derive(Debug, Error)]
 pub enum UnmarshalError {
@@ -271,4 +531,6 @@ pub enum UnmarshalError {
     InvalidAddressType(u8),
     #[error("address parsing error: {0}")]
     AddressParse(#[from] FromUtf8Error),
+    #[error("invalid domain address: {0}")]
+    InvalidDomain(#[from] AddressError),
 }